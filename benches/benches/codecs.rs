@@ -0,0 +1,62 @@
+//! Encoding batches as producers do and decoding them as the broker and consumers do, for every
+//! compression codec.
+
+use benches::{bench_batch, encode_batch};
+use bytes::BytesMut;
+use common::codecs::decoder::{BatchDecoder, RecordBatchDecoder};
+use common::codecs::encoder::BatchEncoder;
+use common::models::CompressionCodec;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tokio_util::codec::{Decoder, Encoder};
+
+const RECORDS_PER_BATCH: usize = 100;
+const RECORD_SIZE: usize = 512;
+const CODECS: [CompressionCodec; 5] = [
+    CompressionCodec::None,
+    CompressionCodec::Gzip,
+    CompressionCodec::Lz4,
+    CompressionCodec::Zstd,
+    CompressionCodec::Snappy,
+];
+
+fn batch_codecs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Bytes((RECORDS_PER_BATCH * RECORD_SIZE) as u64));
+    for compression in CODECS {
+        let batch = bench_batch(RECORDS_PER_BATCH, RECORD_SIZE, compression);
+        let encoded_batch = encode_batch(batch.clone());
+        let codec_name = format!("{:?}", compression).to_lowercase();
+
+        group.bench_function(format!("encode/{}", codec_name), |b| {
+            let mut dst = BytesMut::new();
+            b.iter_batched(
+                || batch.clone(),
+                |batch| {
+                    dst.clear();
+                    BatchEncoder {}.encode(batch, &mut dst).unwrap();
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        // what the broker does with every write, the records stay compressed
+        group.bench_function(format!("decode_header/{}", codec_name), |b| {
+            b.iter_batched(
+                || BytesMut::from(&encoded_batch[..]),
+                |mut src| RecordBatchDecoder {}.decode(&mut src).unwrap().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        // what consumers do with every fetched batch
+        group.bench_function(format!("decode_records/{}", codec_name), |b| {
+            b.iter_batched(
+                || BytesMut::from(&encoded_batch[..]),
+                |mut src| BatchDecoder {}.decode(&mut src).unwrap().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batch_codecs);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+//! Writes and reads against a broker of their own: appending a batch to a partition, producing a
+//! batch and fetching it back, and a load generator writing from many connections at once,
+//! which prints its throughput and latencies after the criterion runs.
+
+use std::time::Duration;
+
+use benches::{
+    bench_batch, encode_batch, generate_load, BenchConnection, BrokerProcess, LoadSettings,
+};
+use common::models::{CompressionCodec, Topic, TopicPartition};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const TOPIC_NAME: &str = "bench";
+const PARTITIONS: u8 = 4;
+const RECORD_SIZE: usize = 512;
+/// Connections of the load generator and how long they write.
+const LOAD_CONNECTIONS: usize = 16;
+const LOAD_DURATION: Duration = Duration::from_secs(10);
+
+fn round_trips(c: &mut Criterion) {
+    let broker = BrokerProcess::start().expect("Could not start the broker");
+    let mut connection =
+        BenchConnection::connect(&broker.address).expect("Could not connect to the broker");
+    let topic = Topic::new(
+        TOPIC_NAME.to_string(),
+        Some(PARTITIONS),
+        Some(1),
+        None,
+        None,
+        None,
+    );
+    connection
+        .create_topic(topic)
+        .expect("Could not create the topic");
+    let topic_partition = TopicPartition::new(TOPIC_NAME.to_string(), 0);
+
+    let mut group = c.benchmark_group("partition");
+    for (records_per_batch, compression) in [
+        (1, CompressionCodec::None),
+        (100, CompressionCodec::None),
+        (100, CompressionCodec::Lz4),
+    ] {
+        let encoded_batch = encode_batch(bench_batch(records_per_batch, RECORD_SIZE, compression));
+        let codec_name = format!("{:?}", compression).to_lowercase();
+        group.throughput(Throughput::Bytes(encoded_batch.len() as u64));
+
+        group.bench_function(
+            format!("append/{}_records/{}", records_per_batch, codec_name),
+            |b| {
+                b.iter(|| {
+                    connection
+                        .produce(&topic_partition, encoded_batch.clone())
+                        .unwrap()
+                })
+            },
+        );
+        group.bench_function(
+            format!("produce_fetch/{}_records/{}", records_per_batch, codec_name),
+            |b| {
+                b.iter(|| {
+                    let base_offset = connection
+                        .produce(&topic_partition, encoded_batch.clone())
+                        .unwrap();
+                    let records = connection
+                        .fetch(&topic_partition, base_offset, records_per_batch as u32)
+                        .unwrap();
+                    assert_eq!(records.len(), records_per_batch);
+                })
+            },
+        );
+    }
+    group.finish();
+
+    let load_settings = LoadSettings {
+        connections: LOAD_CONNECTIONS,
+        topic_name: TOPIC_NAME.to_string(),
+        partitions: PARTITIONS,
+        duration: LOAD_DURATION,
+        batch: bench_batch(100, RECORD_SIZE, CompressionCodec::Lz4),
+    };
+    let report = generate_load(&broker.address, &load_settings).expect("Load generator failed");
+    println!(
+        "load/{}_connections/{}_partitions: {}",
+        LOAD_CONNECTIONS, PARTITIONS, report
+    );
+}
+
+criterion_group! {
+    name = benches;
+    // every sample is a round trip to the broker, fewer of them keep a run short
+    config = Criterion::default().sample_size(50);
+    targets = round_trips
+}
+criterion_main!(benches);
@@ -0,0 +1,398 @@
+//! Helpers of the benchmarks in `benches/`: batches like producers send them, a broker started
+//! for the benchmarks with a blocking connection to it, and a load generator writing to it from
+//! many connections at once.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use common::codecs::encoder::BatchEncoder;
+use common::codecs::protocol::{next_correlation_id, Request, ResponseCodec};
+use common::models::{
+    Acks, Batch, BrokerResponse, CompressionCodec, FetchRequest, FetchedBatch, Message,
+    OffsetResetPolicy, Topic, TopicCommand, TopicPartition,
+};
+use tempdir::TempDir;
+use tokio_util::codec::{Decoder, Encoder};
+
+const CLIENT_ID: &str = "walrs-bench";
+/// Time the broker gets to listen and to become the leader of its metadata quorum.
+const BROKER_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const BROKER_STARTUP_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Batch of `record_count` keyed records with payloads of `record_size` bytes. Payloads differ
+/// from record to record, so compressing them is work without shrinking them to nothing.
+pub fn bench_batch(
+    record_count: usize,
+    record_size: usize,
+    compression: CompressionCodec,
+) -> Batch {
+    let records = (0..record_count)
+        .map(|index| {
+            let payload: Vec<u8> = (0..record_size)
+                .map(|position| b'A' + ((index * 31 + position * 7) % 26) as u8)
+                .collect();
+            Message {
+                payload: Some(payload.into()),
+                key: Some(format!("key-{}", index % 16).into()),
+                timestamp: Some(1_700_000_000_000 + index as u128),
+                headers: vec![],
+            }
+        })
+        .collect();
+    Batch {
+        records,
+        producer: None,
+        compression,
+    }
+}
+
+/// The batch encoded as producers send it.
+pub fn encode_batch(batch: Batch) -> Bytes {
+    let mut encoded_batch = BytesMut::new();
+    BatchEncoder {}
+        .encode(batch, &mut encoded_batch)
+        .expect("Could not encode batch");
+    encoded_batch.freeze()
+}
+
+/// A broker of its own running the release build of the `core` package, or the binary
+/// `WALRS_BROKER_BIN` names. It is killed and its log directory removed when this is dropped.
+/// Writes are not lingered unless `WALRS_PARTITION_LINGER_MS` is set, so latencies measure the
+/// writer rather than the linger.
+pub struct BrokerProcess {
+    pub address: String,
+    process: Child,
+    _log_dir: TempDir,
+}
+
+impl BrokerProcess {
+    pub fn start() -> io::Result<Self> {
+        let broker_bin = std::env::var("WALRS_BROKER_BIN")
+            .unwrap_or_else(|_| format!("{}/../target/release/core", env!("CARGO_MANIFEST_DIR")));
+        let log_dir = TempDir::new("walrs_bench_")?;
+        // the port is released for the broker to listen on
+        let address = TcpListener::bind("127.0.0.1:0")?.local_addr()?.to_string();
+        let mut command = Command::new(&broker_bin);
+        command
+            .env("WALRS_LISTEN_ADDRESS", &address)
+            .env("WALRS_LOG_DIR", log_dir.path())
+            .env(
+                "RUST_LOG",
+                std::env::var("RUST_LOG").unwrap_or_else(|_| "warn".to_string()),
+            )
+            .stdout(Stdio::null());
+        if std::env::var_os("WALRS_PARTITION_LINGER_MS").is_none() {
+            command.env("WALRS_PARTITION_LINGER_MS", "0");
+        }
+        let process = command.spawn().map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "could not start {}, build it with `cargo build --release --package core`: {}",
+                    broker_bin, e
+                ),
+            )
+        })?;
+        let mut broker = BrokerProcess {
+            address,
+            process,
+            _log_dir: log_dir,
+        };
+        broker.wait_until_listening()?;
+        Ok(broker)
+    }
+
+    fn wait_until_listening(&mut self) -> io::Result<()> {
+        let started_at = Instant::now();
+        loop {
+            let pong = BenchConnection::connect(&self.address)
+                .and_then(|mut connection| connection.request(TopicCommand::Ping, Bytes::new()));
+            if let Ok(BrokerResponse::Pong { .. }) = pong {
+                return Ok(());
+            }
+            if let Some(status) = self.process.try_wait()? {
+                return Err(io::Error::other(format!("broker exited with {}", status)));
+            }
+            if started_at.elapsed() > BROKER_STARTUP_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "broker did not answer pings",
+                ));
+            }
+            thread::sleep(BROKER_STARTUP_BACKOFF);
+        }
+    }
+}
+
+impl Drop for BrokerProcess {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// A blocking connection sending one request at a time.
+pub struct BenchConnection {
+    stream: TcpStream,
+    response_codec: ResponseCodec,
+    received: BytesMut,
+}
+
+impl BenchConnection {
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(BenchConnection {
+            stream,
+            response_codec: ResponseCodec::default(),
+            received: BytesMut::with_capacity(64 * 1024),
+        })
+    }
+
+    pub fn request(&mut self, command: TopicCommand, body: Bytes) -> io::Result<BrokerResponse> {
+        let correlation_id = next_correlation_id();
+        let mut encoded_request = BytesMut::new();
+        self.response_codec.encode(
+            Request::new(correlation_id, CLIENT_ID, command, body),
+            &mut encoded_request,
+        )?;
+        self.stream.write_all(&encoded_request)?;
+        let mut read_buffer = [0; 64 * 1024];
+        loop {
+            if let Some(response) = self.response_codec.decode(&mut self.received)? {
+                if response.correlation_id != correlation_id {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "expected the response to request {} but got one to {}",
+                            correlation_id, response.correlation_id
+                        ),
+                    ));
+                }
+                return Ok(response.response);
+            }
+            let read = self.stream.read(&mut read_buffer)?;
+            if read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed without an answer",
+                ));
+            }
+            self.received.extend_from_slice(&read_buffer[..read]);
+        }
+    }
+
+    /// Creates the topic, retrying while the broker is still electing itself the leader of its
+    /// metadata quorum.
+    pub fn create_topic(&mut self, topic: Topic) -> io::Result<()> {
+        let started_at = Instant::now();
+        loop {
+            let command = TopicCommand::CreateTopic {
+                topic: topic.clone(),
+            };
+            match self.request(command, Bytes::new())? {
+                BrokerResponse::TopicCreated { .. } | BrokerResponse::TopicAlreadyExists { .. } => {
+                    return Ok(())
+                }
+                response if started_at.elapsed() > BROKER_STARTUP_TIMEOUT => {
+                    return Err(unexpected_response(response))
+                }
+                _ => thread::sleep(BROKER_STARTUP_BACKOFF),
+            }
+        }
+    }
+
+    /// Appends an encoded batch to the partition and returns the offset of its first record
+    /// once the leader wrote it.
+    pub fn produce(
+        &mut self,
+        topic_partition: &TopicPartition,
+        encoded_batch: Bytes,
+    ) -> io::Result<u64> {
+        let command = TopicCommand::WriteToTopic {
+            topic_name: topic_partition.topic_name.clone(),
+            partition_index: topic_partition.partition_index,
+            acks: Acks::Leader,
+            leader_epoch: None,
+        };
+        match self.request(command, encoded_batch)? {
+            BrokerResponse::MessageBatchAppended(produced) => Ok(produced.base_offset),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    /// Fetches up to `max_records` records of the partition starting at `offset` and returns
+    /// them decompressed, as consumers read them.
+    pub fn fetch(
+        &mut self,
+        topic_partition: &TopicPartition,
+        offset: u64,
+        max_records: u32,
+    ) -> io::Result<Vec<Message>> {
+        let fetch_request = FetchRequest {
+            topic_partition: topic_partition.clone(),
+            offset: Some(offset),
+            group_id: None,
+            member_id: None,
+            auto_offset_reset: OffsetResetPolicy::Earliest,
+            max_records,
+            replica_id: None,
+            leader_epoch: None,
+            rack_id: None,
+        };
+        match self.request(TopicCommand::Fetch(fetch_request), Bytes::new())? {
+            BrokerResponse::Records { batches, .. } => {
+                FetchedBatch::records(batches, offset, max_records as usize)
+            }
+            response => Err(unexpected_response(response)),
+        }
+    }
+}
+
+fn unexpected_response(response: BrokerResponse) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected response: {:?}", response),
+    )
+}
+
+/// Load `generate_load` puts on a broker.
+#[derive(Debug, Clone)]
+pub struct LoadSettings {
+    /// Connections writing at once, each one to its own partition as long as there are enough
+    pub connections: usize,
+    pub topic_name: String,
+    pub partitions: u8,
+    pub duration: Duration,
+    /// Batch every connection writes over and over
+    pub batch: Batch,
+}
+
+/// Writes of a load generator run with the latencies from sending a batch until it was
+/// acknowledged, in microseconds.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub batches: u64,
+    pub records: u64,
+    pub bytes: u64,
+    pub errors: u64,
+    pub elapsed: Duration,
+    latencies_micros: Vec<u64>,
+}
+
+impl LoadReport {
+    /// Latency below which `percentile` percent of the writes are, in milliseconds.
+    pub fn percentile_millis(&self, percentile: f64) -> f64 {
+        let mut latencies = self.latencies_micros.clone();
+        latencies.sort_unstable();
+        if latencies.is_empty() {
+            return 0.0;
+        }
+        let index = ((percentile / 100.0) * latencies.len() as f64).ceil() as usize;
+        latencies[index.clamp(1, latencies.len()) - 1] as f64 / 1000.0
+    }
+
+    fn merge(&mut self, other: LoadReport) {
+        self.batches += other.batches;
+        self.records += other.records;
+        self.bytes += other.bytes;
+        self.errors += other.errors;
+        self.latencies_micros.extend(other.latencies_micros);
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        write!(
+            f,
+            "{} records in {} batches, {:.1} records/sec ({:.2} MB/sec), {} errors, \
+             {:.2} ms 50th, {:.2} ms 99th, {:.2} ms 99.9th produce latency",
+            self.records,
+            self.batches,
+            self.records as f64 / seconds,
+            self.bytes as f64 / seconds / (1024.0 * 1024.0),
+            self.errors,
+            self.percentile_millis(50.0),
+            self.percentile_millis(99.0),
+            self.percentile_millis(99.9),
+        )
+    }
+}
+
+/// Writes the settings' batch from every connection until the duration passed and reports
+/// the writes of all of them.
+pub fn generate_load(address: &str, settings: &LoadSettings) -> io::Result<LoadReport> {
+    let record_count = settings.batch.records.len() as u64;
+    let encoded_batch = encode_batch(settings.batch.clone());
+    let started_at = Instant::now();
+    let writers: Vec<_> = (0..settings.connections)
+        .map(|connection_index| {
+            let address = address.to_string();
+            let encoded_batch = encoded_batch.clone();
+            let topic_partition = TopicPartition::new(
+                settings.topic_name.clone(),
+                (connection_index % settings.partitions.max(1) as usize) as u8,
+            );
+            let duration = settings.duration;
+            thread::spawn(move || -> io::Result<LoadReport> {
+                let mut connection = BenchConnection::connect(&address)?;
+                let mut report = LoadReport::default();
+                while started_at.elapsed() < duration {
+                    let sent_at = Instant::now();
+                    match connection.produce(&topic_partition, encoded_batch.clone()) {
+                        Ok(_) => {
+                            report.batches += 1;
+                            report.records += record_count;
+                            report.bytes += encoded_batch.len() as u64;
+                            report
+                                .latencies_micros
+                                .push(sent_at.elapsed().as_micros() as u64);
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::InvalidData => report.errors += 1,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(report)
+            })
+        })
+        .collect();
+    let mut report = LoadReport::default();
+    for writer in writers {
+        report.merge(writer.join().expect("load generator thread panicked")?);
+    }
+    report.elapsed = started_at.elapsed();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::codecs::decoder::BatchDecoder;
+
+    use super::*;
+
+    #[test]
+    fn test_bench_batch_should_encode_as_producers_send_it() {
+        let batch = bench_batch(10, 100, CompressionCodec::Lz4);
+        assert!(batch
+            .records
+            .iter()
+            .all(|record| record.payload_len() == 100));
+
+        let mut encoded_batch = BytesMut::from(&encode_batch(batch.clone())[..]);
+        let decoded_batch = BatchDecoder {}.decode(&mut encoded_batch).unwrap().unwrap();
+        assert_eq!(decoded_batch, batch);
+
+        let report = LoadReport {
+            latencies_micros: vec![4000, 1000, 3000, 2000],
+            ..LoadReport::default()
+        };
+        assert_eq!(report.percentile_millis(50.0), 2.0);
+        assert_eq!(report.percentile_millis(99.0), 4.0);
+    }
+}
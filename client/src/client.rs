@@ -0,0 +1,1116 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use bytes::{Bytes, BytesMut};
+use common::{
+    codecs::encoder::BatchEncoder,
+    errors::{ProduceError, WalrsError},
+    models::{
+        Acks, Batch, BrokerResponse, FetchRequest, FetchedBatch, Message, OffsetResetPolicy,
+        ProduceResponse, SessionFetchRequest, Topic, TopicCommand, TopicMetadata, TopicPartition,
+    },
+};
+use tokio::runtime::Runtime;
+use tokio_util::codec::Encoder;
+
+use crate::config::{ConfigError, ConnectionConfig};
+use crate::connection_pool::ConnectionPool;
+
+/// Records fetched from a partition.
+#[derive(Debug)]
+pub struct FetchedRecords {
+    /// Offset of the first record.
+    pub base_offset: u64,
+    pub records: Vec<Message>,
+    /// Offset the partition's next record will get.
+    pub log_end_offset: u64,
+}
+
+/// Fetch sessions of a consumer with the brokers it fetches its partitions from, see
+/// `WalrsClient::fetch_partitions`.
+#[derive(Debug, Default)]
+pub struct FetchSessions {
+    sessions: HashMap<String, FetchSession>,
+}
+
+/// A fetch session with one broker, as far as the broker confirmed it.
+#[derive(Debug, Default)]
+struct FetchSession {
+    /// `None` until the broker opened the session.
+    session_id: Option<u32>,
+    epoch: u32,
+    /// Offsets the broker continues the partitions of the session from.
+    positions: BTreeMap<TopicPartition, Option<u64>>,
+}
+
+/// Async client of a walrs cluster. Every request to a broker shares one persistent connection,
+/// requests are written as they come and the broker's responses are matched to them by
+/// correlation ID, so a slow request does not hold up the others. Produce and fetch requests go to the leader of
+/// their partition, learned from the cluster's metadata.
+pub struct WalrsClient {
+    /// Brokers asked for the cluster's metadata, any of them will do.
+    bootstrap_addresses: Vec<String>,
+    /// Index of the bootstrap broker which answered last, tried first by the next request.
+    bootstrap_index: AtomicUsize,
+    client_id: String,
+    connections: ConnectionPool,
+    /// Addresses of the partitions' leaders, a partition is looked up again once its leader
+    /// seems to have moved.
+    leaders: Mutex<HashMap<TopicPartition, String>>,
+    /// Addresses of the brokers of the last metadata.
+    broker_addresses: Mutex<HashMap<u32, String>>,
+    client_rack: Option<String>,
+    /// Addresses of the in-sync replicas in the client's rack the leaders redirected fetches of
+    /// their partitions to, used until fetching from them fails.
+    read_replicas: Mutex<HashMap<TopicPartition, String>>,
+}
+
+impl WalrsClient {
+    /// Expects a validated `config`.
+    pub fn new(config: ConnectionConfig) -> Self {
+        assert!(
+            !config.bootstrap_addresses.is_empty(),
+            "at least one bootstrap address is required"
+        );
+        WalrsClient {
+            bootstrap_addresses: config.bootstrap_addresses.clone(),
+            bootstrap_index: AtomicUsize::new(0),
+            client_id: config.client_id.clone(),
+            client_rack: config.client_rack.clone(),
+            connections: ConnectionPool::new(config),
+            leaders: Mutex::new(HashMap::new()),
+            broker_addresses: Mutex::new(HashMap::new()),
+            read_replicas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sends `command` to a bootstrap broker and waits for its response. Brokers which can't be
+    /// reached are skipped for the next bootstrap address.
+    pub async fn request(&self, command: TopicCommand) -> Result<BrokerResponse, WalrsError> {
+        let first_index = self.bootstrap_index.load(Ordering::Relaxed);
+        let mut unavailable = WalrsError::BrokerUnavailable("no bootstrap addresses".to_string());
+        for attempt in 0..self.bootstrap_addresses.len() {
+            let index = (first_index + attempt) % self.bootstrap_addresses.len();
+            let broker_address = &self.bootstrap_addresses[index];
+            match self
+                .request_to(broker_address, command.clone(), Bytes::new())
+                .await
+            {
+                Err(e @ (WalrsError::BrokerUnavailable(_) | WalrsError::TimedOut(_))) => {
+                    tracing::warn!("Bootstrap broker {} is unavailable: {}", broker_address, e);
+                    unavailable = e;
+                }
+                response => {
+                    self.bootstrap_index.store(index, Ordering::Relaxed);
+                    return response;
+                }
+            }
+        }
+        Err(unavailable)
+    }
+
+    /// Sends `command` followed by `body` to the broker at `broker_address` and waits for its
+    /// response. Answers refusing the client's credentials or the request's version are errors.
+    pub async fn request_to(
+        &self,
+        broker_address: &str,
+        command: TopicCommand,
+        body: Bytes,
+    ) -> Result<BrokerResponse, WalrsError> {
+        let connection = self.connections.get(broker_address).await?;
+        let response = connection.request(command, body).await?;
+        if response.throttle_time_ms > 0 {
+            tracing::info!(
+                "Broker {} throttled the request for {} ms, {} exceeds a quota",
+                broker_address,
+                response.throttle_time_ms,
+                self.client_id
+            );
+        }
+        match response.response {
+            BrokerResponse::SaslAuthenticationFailed { error } => {
+                Err(WalrsError::AuthenticationFailed(error))
+            }
+            BrokerResponse::UnsupportedVersion {
+                api_key,
+                api_version,
+            } => Err(WalrsError::UnsupportedVersion(format!(
+                "version {} of requests with API key {}",
+                api_version, api_key
+            ))),
+            response => Ok(response),
+        }
+    }
+
+    /// Creates the topic, returns it with the broker's defaults filled in.
+    pub async fn create_topic(&self, topic: Topic) -> Result<Topic, WalrsError> {
+        match self.request(TopicCommand::CreateTopic { topic }).await? {
+            BrokerResponse::TopicDescription { topic } => Ok(topic),
+            response => Err(error_response(response)),
+        }
+    }
+
+    /// Describes the topics named in `topic_names`, every topic for `None`. Unknown topics are
+    /// left out.
+    pub async fn metadata(
+        &self,
+        topic_names: Option<Vec<String>>,
+    ) -> Result<Vec<TopicMetadata>, WalrsError> {
+        let response = self.request(TopicCommand::Metadata { topic_names }).await?;
+        let BrokerResponse::Metadata {
+            brokers, topics, ..
+        } = response
+        else {
+            return Err(error_response(response));
+        };
+        let broker_addresses: HashMap<u32, String> = brokers
+            .into_iter()
+            .map(|broker| (broker.broker_id, broker.address))
+            .collect();
+        let mut leaders = self.leaders.lock().unwrap();
+        *self.broker_addresses.lock().unwrap() = broker_addresses.clone();
+        for topic_metadata in &topics {
+            for partition in &topic_metadata.partitions {
+                let topic_partition = TopicPartition::new(
+                    topic_metadata.topic.name.clone(),
+                    partition.partition_index,
+                );
+                match broker_addresses.get(&partition.leader_id) {
+                    Some(address) => leaders.insert(topic_partition, address.clone()),
+                    None => leaders.remove(&topic_partition),
+                };
+            }
+        }
+        Ok(topics)
+    }
+
+    pub async fn delete_topic(&self, topic_name: String) -> Result<(), WalrsError> {
+        match self
+            .request(TopicCommand::DeleteTopic { topic_name })
+            .await?
+        {
+            BrokerResponse::TopicDeleted { .. } => Ok(()),
+            response => Err(error_response(response)),
+        }
+    }
+
+    /// Asks broker `broker_id` to let the followers of its partitions catch up and then hand
+    /// their leadership to them, e.g. before it restarts. Returns the partitions it still leads
+    /// as no other replica could take them over.
+    pub async fn controlled_shutdown(
+        &self,
+        broker_id: u32,
+    ) -> Result<Vec<TopicPartition>, WalrsError> {
+        // only the broker itself knows whether its followers caught up
+        self.metadata(Some(vec![])).await?;
+        let broker_address = self
+            .broker_addresses
+            .lock()
+            .unwrap()
+            .get(&broker_id)
+            .cloned()
+            .ok_or_else(|| {
+                WalrsError::BrokerUnavailable(format!("broker {} is not registered", broker_id))
+            })?;
+        match self
+            .request_to(
+                &broker_address,
+                TopicCommand::ControlledShutdown { broker_id },
+                Bytes::new(),
+            )
+            .await?
+        {
+            BrokerResponse::ControlledShutdownCompleted {
+                remaining_partitions,
+            } => Ok(remaining_partitions),
+            response => Err(error_response(response)),
+        }
+    }
+
+    /// Appends the batch to the partition on its leader. Returns where and when the leader
+    /// appended it, which is unknown with `Acks::None` since the leader does not answer.
+    pub async fn produce(
+        &self,
+        topic_partition: TopicPartition,
+        batch: Batch,
+        acks: Acks,
+    ) -> Result<Option<ProduceResponse>, WalrsError> {
+        let leader_address = self.leader_address(&topic_partition).await?;
+        let command = TopicCommand::WriteToTopic {
+            topic_name: topic_partition.topic_name.clone(),
+            partition_index: topic_partition.partition_index,
+            acks,
+            leader_epoch: None,
+        };
+        let mut encoded_batch = BytesMut::with_capacity(256);
+        BatchEncoder {}
+            .encode(batch, &mut encoded_batch)
+            .map_err(|e| ProduceError::InvalidBatch(e.to_string()))?;
+        let produced = if acks == Acks::None {
+            self.send_to(&leader_address, command, encoded_batch.freeze())
+                .await
+                .map(|()| None)
+        } else {
+            match self
+                .request_to(&leader_address, command, encoded_batch.freeze())
+                .await
+            {
+                Ok(BrokerResponse::MessageBatchAppended(produced)) => Ok(Some(produced)),
+                Ok(response) => Err(error_response(response)),
+                Err(e) => Err(e),
+            }
+        };
+        if let Err(e) = &produced {
+            self.check_leader(&topic_partition, e).await;
+        }
+        produced
+    }
+
+    /// Fetches records of the partition from its leader, up to the request's `max_records`.
+    /// Clients with a rack fetch from the in-sync replica in their rack the leader redirects
+    /// them to, and from the leader again once that replica fails them.
+    pub async fn fetch(
+        &self,
+        mut fetch_request: FetchRequest,
+    ) -> Result<FetchedRecords, WalrsError> {
+        let topic_partition = fetch_request.topic_partition.clone();
+        let max_records = fetch_request.max_records as usize;
+        if fetch_request.rack_id.is_none() {
+            fetch_request.rack_id = self.client_rack.clone();
+        }
+        let read_replica_address = self
+            .read_replicas
+            .lock()
+            .unwrap()
+            .get(&topic_partition)
+            .cloned();
+        let response = match read_replica_address {
+            Some(read_replica_address) => {
+                self.fetch_from_read_replica(&read_replica_address, fetch_request.clone())
+                    .await
+            }
+            None => None,
+        };
+        let response = match response {
+            Some(response) => response,
+            None => {
+                let leader_address = self.leader_address(&topic_partition).await?;
+                self.fetch_from_leader(&leader_address, fetch_request).await
+            }
+        };
+        let (base_offset, batches, log_end_offset) = match response {
+            Ok(BrokerResponse::Records {
+                base_offset,
+                batches,
+                log_end_offset,
+                ..
+            }) => (base_offset, batches, log_end_offset),
+            Ok(response) => {
+                let error = error_response(response);
+                self.check_leader(&topic_partition, &error).await;
+                return Err(error);
+            }
+            Err(e) => {
+                self.check_leader(&topic_partition, &e).await;
+                return Err(e);
+            }
+        };
+        let records = FetchedBatch::records(batches, base_offset, max_records)
+            .map_err(|e| WalrsError::UnexpectedResponse(format!("undecodable records: {}", e)))?;
+        Ok(FetchedRecords {
+            base_offset,
+            records,
+            log_end_offset,
+        })
+    }
+
+    /// Fetches the partitions from their leaders, or from the read replicas in the client's rack
+    /// the leaders redirect them to, with one fetch session per broker. Only the partitions
+    /// whose position moved since the last fetch are sent, and only the partitions with new
+    /// records or an error are answered.
+    pub async fn fetch_partitions(
+        &self,
+        fetch_sessions: &mut FetchSessions,
+        positions: &BTreeMap<TopicPartition, Option<u64>>,
+        auto_offset_reset: OffsetResetPolicy,
+        max_records: u32,
+    ) -> Vec<(TopicPartition, Result<FetchedRecords, WalrsError>)> {
+        let mut fetched = vec![];
+        let mut broker_positions: HashMap<String, BTreeMap<TopicPartition, Option<u64>>> =
+            HashMap::new();
+        for (topic_partition, position) in positions {
+            let read_replica_address = self
+                .read_replicas
+                .lock()
+                .unwrap()
+                .get(topic_partition)
+                .cloned();
+            let broker_address = match read_replica_address {
+                Some(read_replica_address) => read_replica_address,
+                None => match self.leader_address(topic_partition).await {
+                    Ok(leader_address) => leader_address,
+                    Err(e) => {
+                        fetched.push((topic_partition.clone(), Err(e)));
+                        continue;
+                    }
+                },
+            };
+            broker_positions
+                .entry(broker_address)
+                .or_default()
+                .insert(topic_partition.clone(), *position);
+        }
+        // the brokers evict sessions which are not used any more
+        fetch_sessions
+            .sessions
+            .retain(|broker_address, _| broker_positions.contains_key(broker_address));
+        for (broker_address, positions) in broker_positions {
+            let session = fetch_sessions
+                .sessions
+                .entry(broker_address.clone())
+                .or_default();
+            let request = SessionFetchRequest {
+                session_id: session.session_id,
+                session_epoch: session.epoch,
+                partitions: positions
+                    .iter()
+                    .filter(|(topic_partition, position)| {
+                        session.positions.get(*topic_partition) != Some(*position)
+                    })
+                    .map(|(topic_partition, position)| (topic_partition.clone(), *position))
+                    .collect(),
+                forgotten: session
+                    .positions
+                    .keys()
+                    .filter(|topic_partition| !positions.contains_key(*topic_partition))
+                    .cloned()
+                    .collect(),
+                auto_offset_reset,
+                max_records,
+                rack_id: self.client_rack.clone(),
+            };
+            let response = self
+                .request_to(
+                    &broker_address,
+                    TopicCommand::SessionFetch(request),
+                    Bytes::new(),
+                )
+                .await;
+            let responses = match response {
+                Ok(BrokerResponse::SessionRecords {
+                    session_id,
+                    responses,
+                }) => {
+                    session.session_id = Some(session_id);
+                    session.epoch += 1;
+                    session.positions = positions;
+                    responses
+                }
+                Ok(BrokerResponse::InvalidFetchSession { session_id }) => {
+                    tracing::info!(
+                        "Broker {} dropped fetch session {}, opening a new one",
+                        broker_address,
+                        session_id
+                    );
+                    *session = FetchSession::default();
+                    continue;
+                }
+                response => {
+                    *session = FetchSession::default();
+                    let error = response.map_or_else(|e| e, error_response);
+                    for topic_partition in positions.into_keys() {
+                        self.check_fetch_error(&broker_address, &topic_partition, &error)
+                            .await;
+                        fetched.push((topic_partition, Err(error.clone())));
+                    }
+                    continue;
+                }
+            };
+            for (topic_partition, response) in responses {
+                let records = match response {
+                    BrokerResponse::Records {
+                        base_offset,
+                        batches,
+                        log_end_offset,
+                        ..
+                    } => FetchedBatch::records(batches, base_offset, max_records as usize)
+                        .map(|records| FetchedRecords {
+                            base_offset,
+                            records,
+                            log_end_offset,
+                        })
+                        .map_err(|e| {
+                            WalrsError::UnexpectedResponse(format!("undecodable records: {}", e))
+                        }),
+                    // the broker left the partition out of the session
+                    BrokerResponse::PreferredReadReplica { broker_id, .. } => {
+                        session.positions.remove(&topic_partition);
+                        self.use_read_replica(topic_partition, broker_id).await;
+                        continue;
+                    }
+                    response => {
+                        let error = error_response(response);
+                        self.check_fetch_error(&broker_address, &topic_partition, &error)
+                            .await;
+                        Err(error)
+                    }
+                };
+                if let Ok(records) = &records {
+                    let next_offset = records.base_offset + records.records.len() as u64;
+                    session
+                        .positions
+                        .insert(topic_partition.clone(), Some(next_offset));
+                }
+                fetched.push((topic_partition, records));
+            }
+        }
+        fetched
+    }
+
+    /// Fetches the partition from the read replica `broker_id` from now on, as long as the
+    /// client knows its address.
+    async fn use_read_replica(&self, topic_partition: TopicPartition, broker_id: u32) {
+        if !self
+            .broker_addresses
+            .lock()
+            .unwrap()
+            .contains_key(&broker_id)
+        {
+            let topic_names = Some(vec![topic_partition.topic_name.clone()]);
+            if let Err(e) = self.metadata(topic_names).await {
+                tracing::warn!("Could not look up broker {}: {}", broker_id, e);
+            }
+        }
+        let read_replica_address = self
+            .broker_addresses
+            .lock()
+            .unwrap()
+            .get(&broker_id)
+            .cloned();
+        match read_replica_address {
+            Some(read_replica_address) => {
+                tracing::info!(
+                    "Fetching {:?} from broker {} in rack {:?}",
+                    topic_partition,
+                    broker_id,
+                    self.client_rack
+                );
+                self.read_replicas
+                    .lock()
+                    .unwrap()
+                    .insert(topic_partition, read_replica_address);
+            }
+            None => tracing::warn!("Broker {} has no known address", broker_id),
+        }
+    }
+
+    /// Fetches the partition from its leader again once its read replica failed a fetch, or
+    /// looks up its leader again after errors hinting that it moved.
+    async fn check_fetch_error(
+        &self,
+        broker_address: &str,
+        topic_partition: &TopicPartition,
+        error: &WalrsError,
+    ) {
+        let read_replica_failed = {
+            let mut read_replicas = self.read_replicas.lock().unwrap();
+            let read_replica =
+                read_replicas.get(topic_partition).map(String::as_str) == Some(broker_address);
+            if read_replica {
+                read_replicas.remove(topic_partition);
+            }
+            read_replica
+        };
+        if read_replica_failed {
+            tracing::warn!(
+                "Could not fetch {:?} from {}, fetching from its leader: {}",
+                topic_partition,
+                broker_address,
+                error
+            );
+        } else {
+            self.check_leader(topic_partition, error).await;
+        }
+    }
+
+    /// Fetches from the leader, or from the read replica in the client's rack it redirects the
+    /// fetch to.
+    async fn fetch_from_leader(
+        &self,
+        leader_address: &str,
+        mut fetch_request: FetchRequest,
+    ) -> Result<BrokerResponse, WalrsError> {
+        let topic_partition = fetch_request.topic_partition.clone();
+        let response = self
+            .request_to(
+                leader_address,
+                TopicCommand::Fetch(fetch_request.clone()),
+                Bytes::new(),
+            )
+            .await?;
+        let BrokerResponse::PreferredReadReplica { broker_id, .. } = response else {
+            return Ok(response);
+        };
+        let read_replica_address = self
+            .broker_addresses
+            .lock()
+            .unwrap()
+            .get(&broker_id)
+            .cloned();
+        if let Some(read_replica_address) = read_replica_address {
+            tracing::info!(
+                "Fetching {:?} from broker {} in rack {:?}",
+                topic_partition,
+                broker_id,
+                fetch_request.rack_id
+            );
+            self.read_replicas
+                .lock()
+                .unwrap()
+                .insert(topic_partition, read_replica_address.clone());
+            let response = self
+                .fetch_from_read_replica(&read_replica_address, fetch_request.clone())
+                .await;
+            if let Some(response) = response {
+                return response;
+            }
+        }
+        // the leader serves fetches without a rack itself
+        fetch_request.rack_id = None;
+        self.request_to(
+            leader_address,
+            TopicCommand::Fetch(fetch_request),
+            Bytes::new(),
+        )
+        .await
+    }
+
+    /// The records of the fetch from the read replica at `read_replica_address`, `None` once it
+    /// failed the fetch and the partition's fetches go to its leader again.
+    async fn fetch_from_read_replica(
+        &self,
+        read_replica_address: &str,
+        fetch_request: FetchRequest,
+    ) -> Option<Result<BrokerResponse, WalrsError>> {
+        let topic_partition = fetch_request.topic_partition.clone();
+        let response = self
+            .request_to(
+                read_replica_address,
+                TopicCommand::Fetch(fetch_request),
+                Bytes::new(),
+            )
+            .await;
+        match response {
+            Ok(response @ BrokerResponse::Records { .. }) => Some(Ok(response)),
+            // e.g. a replica behind the offset the leader served up to
+            response => {
+                tracing::warn!(
+                    "Could not fetch {:?} from {}, fetching from its leader: {:?}",
+                    topic_partition,
+                    read_replica_address,
+                    response
+                );
+                self.read_replicas.lock().unwrap().remove(&topic_partition);
+                None
+            }
+        }
+    }
+
+    /// Sends `command` to a broker which does not answer it.
+    async fn send_to(
+        &self,
+        broker_address: &str,
+        command: TopicCommand,
+        body: Bytes,
+    ) -> Result<(), WalrsError> {
+        let connection = self.connections.get(broker_address).await?;
+        connection.send(command, body).await
+    }
+
+    /// Address of the partition's leader, a bootstrap broker while the leader did not register
+    /// its address.
+    async fn leader_address(&self, topic_partition: &TopicPartition) -> Result<String, WalrsError> {
+        let leader_address = self.leaders.lock().unwrap().get(topic_partition).cloned();
+        if let Some(leader_address) = leader_address {
+            return Ok(leader_address);
+        }
+        self.metadata(Some(vec![topic_partition.topic_name.clone()]))
+            .await?;
+        let leader_address = self.leaders.lock().unwrap().get(topic_partition).cloned();
+        Ok(leader_address.unwrap_or_else(|| {
+            let index = self.bootstrap_index.load(Ordering::Relaxed);
+            self.bootstrap_addresses[index].clone()
+        }))
+    }
+
+    /// Looks up the leader of the partition again after errors hinting that it moved, so the
+    /// retry goes to the new leader.
+    async fn check_leader(&self, topic_partition: &TopicPartition, error: &WalrsError) {
+        if matches!(
+            error,
+            WalrsError::BrokerUnavailable(_)
+                | WalrsError::TimedOut(_)
+                | WalrsError::UnknownPartition(_)
+                | WalrsError::Produce(
+                    ProduceError::NotLeader(_) | ProduceError::FencedLeaderEpoch { .. }
+                )
+        ) {
+            self.leaders.lock().unwrap().remove(topic_partition);
+            let topic_names = Some(vec![topic_partition.topic_name.clone()]);
+            if let Err(e) = self.metadata(topic_names).await {
+                tracing::warn!(
+                    "Could not look up the leader of {:?}: {}",
+                    topic_partition,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// The error of a response refusing a request.
+fn error_response(response: BrokerResponse) -> WalrsError {
+    match response {
+        BrokerResponse::TopicNotFound { topic_name } => WalrsError::UnknownTopic(topic_name),
+        BrokerResponse::UnknownTopicPartition { topic_partition } => {
+            WalrsError::UnknownPartition(topic_partition)
+        }
+        BrokerResponse::ProduceFailed { error } => WalrsError::Produce(error),
+        BrokerResponse::MessageBatchWriteFailure { error } => {
+            WalrsError::Produce(ProduceError::InvalidBatch(error))
+        }
+        BrokerResponse::OffsetOutOfRange {
+            topic_partition,
+            offset,
+            ..
+        } => WalrsError::OffsetOutOfRange {
+            topic_partition,
+            offset,
+        },
+        response => WalrsError::UnexpectedResponse(format!("{:?}", response)),
+    }
+}
+
+/// Builds the blocking client the CLI's admin commands send their requests with.
+pub struct AdminClientBuilder {
+    connection: ConnectionConfig,
+}
+
+impl AdminClientBuilder {
+    pub fn new(connection: ConnectionConfig) -> Self {
+        AdminClientBuilder { connection }
+    }
+
+    /// Fails if the connection settings can't work.
+    pub fn build(self) -> Result<BlockingClient, ConfigError> {
+        self.connection.validate()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Could not start tokio runtime");
+        Ok(BlockingClient {
+            runtime,
+            client: WalrsClient::new(self.connection),
+        })
+    }
+}
+
+/// Blocking wrapper of `WalrsClient` for scripts and the CLI, running the client on a runtime of
+/// its own.
+pub struct BlockingClient {
+    runtime: Runtime,
+    client: WalrsClient,
+}
+
+impl BlockingClient {
+    pub fn request(&self, command: TopicCommand) -> Result<BrokerResponse, WalrsError> {
+        self.runtime.block_on(self.client.request(command))
+    }
+
+    pub fn create_topic(&self, topic: Topic) -> Result<Topic, WalrsError> {
+        self.runtime.block_on(self.client.create_topic(topic))
+    }
+
+    pub fn metadata(
+        &self,
+        topic_names: Option<Vec<String>>,
+    ) -> Result<Vec<TopicMetadata>, WalrsError> {
+        self.runtime.block_on(self.client.metadata(topic_names))
+    }
+
+    pub fn delete_topic(&self, topic_name: String) -> Result<(), WalrsError> {
+        self.runtime.block_on(self.client.delete_topic(topic_name))
+    }
+
+    pub fn fetch(&self, fetch_request: FetchRequest) -> Result<FetchedRecords, WalrsError> {
+        self.runtime.block_on(self.client.fetch(fetch_request))
+    }
+
+    pub fn controlled_shutdown(&self, broker_id: u32) -> Result<Vec<TopicPartition>, WalrsError> {
+        self.runtime
+            .block_on(self.client.controlled_shutdown(broker_id))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use common::codecs::protocol::{api_versions, RequestCodec, Response};
+    use common::models::{BrokerRegistration, PartitionMetadata, RecordBatch};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Decoder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_client_should_match_responses_to_requests_on_one_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let broker_address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            // a second connection would never be answered
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request_codec = RequestCodec::default();
+            let mut received = BytesMut::new();
+            let mut requests = vec![];
+            while requests.len() < 2 {
+                match request_codec.decode(&mut received).unwrap() {
+                    Some(request) => requests.push(request),
+                    None => {
+                        stream.read_buf(&mut received).await.unwrap();
+                    }
+                }
+            }
+            let mut encoded_responses = BytesMut::new();
+            for request in requests.into_iter().rev() {
+                let response = match request.command {
+                    TopicCommand::ApiVersions => api_versions(),
+                    TopicCommand::DeleteTopic { topic_name } => {
+                        BrokerResponse::TopicNotFound { topic_name }
+                    }
+                    _ => unreachable!(),
+                };
+                RequestCodec::default()
+                    .encode(
+                        Response {
+                            correlation_id: request.header.correlation_id,
+                            throttle_time_ms: 0,
+                            response,
+                        },
+                        &mut encoded_responses,
+                    )
+                    .unwrap();
+            }
+            stream.write_all(&encoded_responses).await.unwrap();
+            // keeps the connection open until the client is gone
+            let _ = stream.read_buf(&mut received).await;
+        });
+        let client = WalrsClient::new(ConnectionConfig::new(&broker_address));
+
+        let (versions, deleted) = tokio::join!(
+            client.request(TopicCommand::ApiVersions),
+            client.delete_topic("t1".to_string())
+        );
+        assert!(matches!(versions, Ok(BrokerResponse::ApiVersions { .. })));
+        assert_eq!(deleted, Err(WalrsError::UnknownTopic("t1".to_string())));
+    }
+
+    /// Answers every request with `answer`, on any number of connections.
+    pub(crate) async fn start_fake_broker(
+        answer: impl Fn(TopicCommand) -> BrokerResponse + Send + Sync + 'static,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let broker_address = listener.local_addr().unwrap().to_string();
+        let answer = Arc::new(answer);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let answer = answer.clone();
+                tokio::spawn(async move {
+                    let mut request_codec = RequestCodec::default();
+                    let mut received = BytesMut::new();
+                    loop {
+                        let Some(request) = request_codec.decode(&mut received).unwrap() else {
+                            if stream.read_buf(&mut received).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            continue;
+                        };
+                        let mut encoded_response = BytesMut::new();
+                        RequestCodec::default()
+                            .encode(
+                                Response {
+                                    correlation_id: request.header.correlation_id,
+                                    throttle_time_ms: 0,
+                                    response: answer(request.command),
+                                },
+                                &mut encoded_response,
+                            )
+                            .unwrap();
+                        stream.write_all(&encoded_response).await.unwrap();
+                    }
+                });
+            }
+        });
+        broker_address
+    }
+
+    #[tokio::test]
+    async fn test_client_should_follow_partition_leaders_from_any_bootstrap_broker() {
+        let topic_partition = TopicPartition::new("t1".to_string(), 0);
+        let old_leader_partition = topic_partition.clone();
+        let old_leader_address = start_fake_broker(move |_| BrokerResponse::ProduceFailed {
+            error: ProduceError::NotLeader(old_leader_partition.clone()),
+        })
+        .await;
+        let new_leader_address = start_fake_broker(|command| match command {
+            TopicCommand::WriteToTopic {
+                topic_name,
+                partition_index,
+                acks,
+                ..
+            } => BrokerResponse::MessageBatchAppended(ProduceResponse {
+                topic_partition: TopicPartition::new(topic_name, partition_index),
+                base_offset: 0,
+                log_append_time_millis: Some(1_000),
+                acks,
+            }),
+            _ => unreachable!(),
+        })
+        .await;
+        let metadata_requests = Arc::new(AtomicUsize::new(0));
+        let counted_metadata_requests = metadata_requests.clone();
+        let bootstrap_address = start_fake_broker(move |_| {
+            // the partition's leader moves to broker 2 after the first lookup
+            let leader_id = match counted_metadata_requests.fetch_add(1, Ordering::SeqCst) {
+                0 => 1,
+                _ => 2,
+            };
+            BrokerResponse::Metadata {
+                brokers: vec![
+                    BrokerRegistration {
+                        broker_id: 1,
+                        address: old_leader_address.clone(),
+                        rack: None,
+                    },
+                    BrokerRegistration {
+                        broker_id: 2,
+                        address: new_leader_address.clone(),
+                        rack: None,
+                    },
+                ],
+                controller_id: Some(1),
+                topics: vec![TopicMetadata {
+                    topic: Topic::new("t1".to_string(), Some(1), None, None, None, None),
+                    partitions: vec![PartitionMetadata {
+                        partition_index: 0,
+                        leader_id,
+                        leader_epoch: leader_id,
+                        replicas: vec![1, 2],
+                        isr: vec![1, 2],
+                    }],
+                }],
+            }
+        })
+        .await;
+        // nothing listens on the first bootstrap address
+        let client = WalrsClient::new(ConnectionConfig::new(&format!(
+            "127.0.0.1:1,{}",
+            bootstrap_address
+        )));
+        let batch = || Batch {
+            records: vec![Message::new(Bytes::from_static(b"v1"), None, None)],
+            ..Batch::default()
+        };
+
+        let produced = client
+            .produce(topic_partition.clone(), batch(), Acks::Leader)
+            .await;
+        assert_eq!(
+            produced,
+            Err(WalrsError::Produce(ProduceError::NotLeader(
+                topic_partition.clone()
+            )))
+        );
+        let produced = client
+            .produce(topic_partition.clone(), batch(), Acks::Leader)
+            .await
+            .unwrap();
+        assert_eq!(produced.unwrap().base_offset, 0);
+        // the NotLeader error refreshed the leader, the retry did not look it up again
+        assert_eq!(metadata_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_should_fetch_from_the_read_replica_the_leader_prefers() {
+        let records = |base_offset| BrokerResponse::Records {
+            topic_partition: TopicPartition::new("t1".to_string(), 0),
+            base_offset,
+            batches: vec![],
+            log_end_offset: base_offset,
+            high_watermark: base_offset,
+            leader_epoch: 1,
+        };
+        let read_replica_fetches = Arc::new(AtomicUsize::new(0));
+        let counted_fetches = read_replica_fetches.clone();
+        let read_replica_address = start_fake_broker(move |command| match command {
+            TopicCommand::Fetch(fetch_request) => {
+                counted_fetches.fetch_add(1, Ordering::SeqCst);
+                records(fetch_request.offset.unwrap())
+            }
+            _ => unreachable!(),
+        })
+        .await;
+        let leader_address = start_fake_broker(move |command| match command {
+            TopicCommand::Metadata { .. } => BrokerResponse::Metadata {
+                brokers: vec![BrokerRegistration {
+                    broker_id: 2,
+                    address: read_replica_address.clone(),
+                    rack: Some("b".to_string()),
+                }],
+                controller_id: Some(1),
+                topics: vec![TopicMetadata {
+                    topic: Topic::new("t1".to_string(), Some(1), None, None, None, None),
+                    partitions: vec![PartitionMetadata {
+                        partition_index: 0,
+                        leader_id: 1,
+                        leader_epoch: 1,
+                        replicas: vec![1, 2],
+                        isr: vec![1, 2],
+                    }],
+                }],
+            },
+            TopicCommand::Fetch(fetch_request) => match fetch_request.rack_id {
+                Some(_) => BrokerResponse::PreferredReadReplica {
+                    topic_partition: fetch_request.topic_partition,
+                    broker_id: 2,
+                },
+                None => records(fetch_request.offset.unwrap()),
+            },
+            _ => unreachable!(),
+        })
+        .await;
+        let fetch_request = |offset| FetchRequest {
+            topic_partition: TopicPartition::new("t1".to_string(), 0),
+            offset: Some(offset),
+            group_id: None,
+            member_id: None,
+            auto_offset_reset: OffsetResetPolicy::None,
+            max_records: 10,
+            replica_id: None,
+            leader_epoch: None,
+            rack_id: None,
+        };
+
+        let client = WalrsClient::new(ConnectionConfig::new(&leader_address));
+        assert_eq!(client.fetch(fetch_request(3)).await.unwrap().base_offset, 3);
+        assert_eq!(read_replica_fetches.load(Ordering::SeqCst), 0);
+
+        let client = WalrsClient::new(ConnectionConfig {
+            client_rack: Some("b".to_string()),
+            ..ConnectionConfig::new(&leader_address)
+        });
+        assert_eq!(client.fetch(fetch_request(3)).await.unwrap().base_offset, 3);
+        // later fetches go to the read replica right away
+        assert_eq!(client.fetch(fetch_request(4)).await.unwrap().base_offset, 4);
+        assert_eq!(read_replica_fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_should_only_send_moved_partitions_in_fetch_sessions() {
+        let session_fetches = Arc::new(Mutex::new(vec![]));
+        let recorded_fetches = session_fetches.clone();
+        let broker_address = start_fake_broker(move |command| match command {
+            // the broker leads every partition
+            TopicCommand::Metadata { .. } => BrokerResponse::Metadata {
+                brokers: vec![],
+                controller_id: None,
+                topics: vec![],
+            },
+            TopicCommand::SessionFetch(request) => {
+                recorded_fetches.lock().unwrap().push(request.clone());
+                // only the first partition has new records
+                let topic_partition = TopicPartition::new("t1".to_string(), 0);
+                let records = BrokerResponse::Records {
+                    topic_partition: topic_partition.clone(),
+                    base_offset: 0,
+                    batches: vec![FetchedBatch {
+                        base_offset: 0,
+                        batch: RecordBatch::new(Batch {
+                            records: vec![Message::new(Bytes::from_static(b"v0"), None, None)],
+                            ..Batch::default()
+                        })
+                        .unwrap(),
+                    }],
+                    log_end_offset: 1,
+                    high_watermark: 1,
+                    leader_epoch: 1,
+                };
+                let responses = match request.session_id {
+                    None => vec![(topic_partition, records)],
+                    Some(_) => vec![],
+                };
+                BrokerResponse::SessionRecords {
+                    session_id: 7,
+                    responses,
+                }
+            }
+            _ => unreachable!(),
+        })
+        .await;
+        let t0 = TopicPartition::new("t1".to_string(), 0);
+        let t1 = TopicPartition::new("t1".to_string(), 1);
+        let client = WalrsClient::new(ConnectionConfig::new(&broker_address));
+        let mut fetch_sessions = FetchSessions::default();
+        let mut positions = BTreeMap::from([(t0.clone(), Some(0)), (t1.clone(), None)]);
+
+        let fetched = client
+            .fetch_partitions(
+                &mut fetch_sessions,
+                &positions,
+                OffsetResetPolicy::Earliest,
+                10,
+            )
+            .await;
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].0, t0);
+        positions.insert(t0.clone(), Some(1));
+        assert!(client
+            .fetch_partitions(
+                &mut fetch_sessions,
+                &positions,
+                OffsetResetPolicy::Earliest,
+                10
+            )
+            .await
+            .is_empty());
+        positions.remove(&t1);
+        assert!(client
+            .fetch_partitions(
+                &mut fetch_sessions,
+                &positions,
+                OffsetResetPolicy::Earliest,
+                10
+            )
+            .await
+            .is_empty());
+
+        let session_fetches = session_fetches.lock().unwrap();
+        assert_eq!(
+            session_fetches[0].partitions,
+            vec![(t0.clone(), Some(0)), (t1.clone(), None)]
+        );
+        // the position the records moved the partition to is the one the broker continues from
+        assert_eq!(session_fetches[1].session_id, Some(7));
+        assert_eq!(session_fetches[1].session_epoch, 1);
+        assert!(session_fetches[1].partitions.is_empty());
+        assert_eq!(session_fetches[2].forgotten, vec![t1]);
+        assert!(session_fetches[2].partitions.is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+use std::thread;
+use std::time::Duration;
+
+use common::models::{ApiKey, BrokerResponse, PartitionReassignment, TopicCommand, TopicPartition};
+
+use super::{admin_client, join_broker_ids, send_request, topics::print_partitions};
+use crate::config::ConnectionConfig;
+
+/// Time between two checks of a decommission's progress.
+const DECOMMISSION_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Prints the brokers of the cluster and the partitions of the topics, of every topic with
+/// `None`.
+pub fn describe_cluster(topic_names: Option<Vec<String>>, connection: ConnectionConfig) {
+    match send_request(connection, TopicCommand::Metadata { topic_names }) {
+        Ok(BrokerResponse::Metadata {
+            brokers,
+            controller_id,
+            topics,
+        }) => {
+            println!(
+                "{:<9} {:<30} {:<15} CONTROLLER",
+                "BROKER", "ADDRESS", "RACK"
+            );
+            for broker in brokers {
+                println!(
+                    "{:<9} {:<30} {:<15} {}",
+                    broker.broker_id,
+                    broker.address,
+                    broker.rack.unwrap_or_else(|| "-".to_string()),
+                    if controller_id == Some(broker.broker_id) {
+                        "*"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            println!();
+            print_partitions(topics);
+        }
+        Ok(response) => tracing::error!("Could not describe the cluster: {:?}", response),
+        Err(e) => tracing::error!("Could not describe the cluster: {}", e),
+    }
+}
+
+/// Prints what SREs page on: under-replicated and offline partitions, unreachable brokers and
+/// how often ISRs changed lately.
+pub fn describe_cluster_health(connection: ConnectionConfig) {
+    match send_request(connection, TopicCommand::DescribeCluster) {
+        Ok(BrokerResponse::ClusterHealth(health)) => {
+            let unreachable_brokers = match health.unreachable_brokers.as_slice() {
+                [] => "-".to_string(),
+                broker_ids => join_broker_ids(broker_ids),
+            };
+            println!("{:<32} {}", "Unreachable brokers", unreachable_brokers);
+            println!(
+                "{:<32} {}",
+                "ISR changes in the last minute", health.isr_changes_last_minute
+            );
+            for (state, topic_partitions) in [
+                ("OFFLINE", health.offline_partitions),
+                ("UNDER-REPLICATED", health.under_replicated_partitions),
+            ] {
+                for topic_partition in topic_partitions {
+                    println!(
+                        "{:<16} {}-{}",
+                        state, topic_partition.topic_name, topic_partition.partition_index
+                    );
+                }
+            }
+        }
+        Ok(response) => tracing::error!("Could not describe the cluster's health: {:?}", response),
+        Err(e) => tracing::error!("Could not describe the cluster's health: {}", e),
+    }
+}
+
+pub fn describe_metrics(connection: ConnectionConfig) {
+    match send_request(connection, TopicCommand::DescribeMetrics) {
+        Ok(BrokerResponse::Metrics { metrics }) => {
+            for (name, value) in metrics {
+                println!("{:<40} {}", name, value);
+            }
+        }
+        Ok(response) => tracing::error!("Could not describe metrics: {:?}", response),
+        Err(e) => tracing::error!("Could not describe metrics: {}", e),
+    }
+}
+
+pub fn describe_api_versions(connection: ConnectionConfig) {
+    match send_request(connection, TopicCommand::ApiVersions) {
+        Ok(BrokerResponse::ApiVersions {
+            api_versions,
+            batch_format_versions,
+        }) => {
+            for (api_key, versions) in api_versions {
+                let name = match ApiKey::try_from(api_key) {
+                    Ok(api_key) => format!("{:?}", api_key),
+                    Err(api_key) => format!("Unknown({})", api_key),
+                };
+                println!(
+                    "{:<30} {}-{}",
+                    name, versions.min_version, versions.max_version
+                );
+            }
+            println!(
+                "{:<30} {}-{}",
+                "BatchFormat", batch_format_versions.min_version, batch_format_versions.max_version
+            );
+        }
+        Ok(response) => tracing::error!("Could not describe API versions: {:?}", response),
+        Err(e) => tracing::error!("Could not describe API versions: {}", e),
+    }
+}
+
+pub fn alter_log_filter(filter: String, connection: ConnectionConfig) {
+    let command = TopicCommand::AlterLogFilter {
+        filter: filter.clone(),
+    };
+    match send_request(connection, command) {
+        Ok(BrokerResponse::LogFilterAltered { previous_filter }) => tracing::info!(
+            "Broker logs with filter {} instead of {}",
+            filter,
+            previous_filter
+        ),
+        Ok(response) => tracing::error!("Could not change the log filter: {:?}", response),
+        Err(e) => tracing::error!("Could not change the log filter: {}", e),
+    }
+}
+
+pub fn reassign_partition(
+    topic_partition: TopicPartition,
+    replicas: Vec<u32>,
+    throttle_rate: Option<u64>,
+    connection: ConnectionConfig,
+) {
+    let command = TopicCommand::ReassignPartitions {
+        reassignments: vec![PartitionReassignment {
+            topic_partition,
+            replicas,
+        }],
+        throttle_rate,
+    };
+    match send_request(connection, command) {
+        Ok(BrokerResponse::ReassignmentsStarted { topic_partitions }) => {
+            for topic_partition in topic_partitions {
+                println!(
+                    "Reassigning {}-{}",
+                    topic_partition.topic_name, topic_partition.partition_index
+                );
+            }
+        }
+        Ok(BrokerResponse::ReassignmentFailed {
+            topic_partition,
+            error,
+        }) => tracing::error!(
+            "Could not reassign {}-{}: {}",
+            topic_partition.topic_name,
+            topic_partition.partition_index,
+            error
+        ),
+        Ok(response) => tracing::error!("Could not reassign partition: {:?}", response),
+        Err(e) => tracing::error!("Could not reassign partition: {}", e),
+    }
+}
+
+pub fn describe_reassignments(connection: ConnectionConfig) {
+    match send_request(connection, TopicCommand::DescribeReassignments) {
+        Ok(BrokerResponse::Reassignments { reassignments }) => {
+            println!(
+                "{:<30} {:>9} {:<12} {:<12} {:<12} ISR",
+                "TOPIC", "PARTITION", "REPLICAS", "ADDING", "REMOVING"
+            );
+            for reassignment in reassignments {
+                println!(
+                    "{:<30} {:>9} {:<12} {:<12} {:<12} {}",
+                    reassignment.topic_partition.topic_name,
+                    reassignment.topic_partition.partition_index,
+                    join_broker_ids(&reassignment.replicas),
+                    join_broker_ids(&reassignment.adding_replicas),
+                    join_broker_ids(&reassignment.removing_replicas),
+                    join_broker_ids(&reassignment.isr),
+                );
+            }
+        }
+        Ok(response) => tracing::error!("Could not describe reassignments: {:?}", response),
+        Err(e) => tracing::error!("Could not describe reassignments: {}", e),
+    }
+}
+
+pub fn elect_preferred_leaders(
+    topic_partitions: Option<Vec<TopicPartition>>,
+    connection: ConnectionConfig,
+) {
+    match send_request(
+        connection,
+        TopicCommand::ElectPreferredLeaders { topic_partitions },
+    ) {
+        Ok(BrokerResponse::PreferredLeadersElected {
+            elected,
+            not_elected,
+        }) => {
+            for topic_partition in elected {
+                println!(
+                    "Elected the preferred leader of {}-{}",
+                    topic_partition.topic_name, topic_partition.partition_index
+                );
+            }
+            for (topic_partition, error) in not_elected {
+                println!(
+                    "Could not elect the preferred leader of {}-{}: {}",
+                    topic_partition.topic_name, topic_partition.partition_index, error
+                );
+            }
+        }
+        Ok(response) => tracing::error!("Could not elect preferred leaders: {:?}", response),
+        Err(e) => tracing::error!("Could not elect preferred leaders: {}", e),
+    }
+}
+
+/// Moves the leadership of the partitions of broker `broker_id` to their followers once they
+/// caught up, so it can restart without losing records.
+pub fn controlled_shutdown(broker_id: u32, connection: ConnectionConfig) {
+    match admin_client(connection).controlled_shutdown(broker_id) {
+        Ok(remaining_partitions) if remaining_partitions.is_empty() => {
+            println!("Other brokers lead the partitions of broker {}", broker_id)
+        }
+        Ok(remaining_partitions) => {
+            for topic_partition in remaining_partitions {
+                println!(
+                    "Broker {} still leads {}-{}, no other replica is in sync",
+                    broker_id, topic_partition.topic_name, topic_partition.partition_index
+                );
+            }
+        }
+        Err(e) => tracing::error!("Could not shut down broker {}: {}", broker_id, e),
+    }
+}
+
+/// Moves the replicas of broker `broker_id` to the other brokers and prints the partitions still
+/// moving until they moved and the broker left the cluster.
+pub fn decommission_broker(broker_id: u32, connection: ConnectionConfig) {
+    let client = admin_client(connection);
+    loop {
+        match client.request(TopicCommand::DecommissionBroker { broker_id }) {
+            Ok(BrokerResponse::DecommissionProgress {
+                failed_partitions, ..
+            }) if !failed_partitions.is_empty() => {
+                for (topic_partition, error) in failed_partitions {
+                    println!(
+                        "Could not move {}-{} off broker {}: {}",
+                        topic_partition.topic_name,
+                        topic_partition.partition_index,
+                        broker_id,
+                        error
+                    );
+                }
+                return;
+            }
+            Ok(BrokerResponse::DecommissionProgress {
+                unregistered: true, ..
+            }) => {
+                println!(
+                    "Broker {} holds no replicas and left the cluster",
+                    broker_id
+                );
+                return;
+            }
+            Ok(BrokerResponse::DecommissionProgress {
+                moving_partitions, ..
+            }) => println!(
+                "Moving {} partitions off broker {}",
+                moving_partitions.len(),
+                broker_id
+            ),
+            Ok(response) => {
+                tracing::error!(
+                    "Could not decommission broker {}: {:?}",
+                    broker_id,
+                    response
+                );
+                return;
+            }
+            Err(e) => {
+                tracing::error!("Could not decommission broker {}: {}", broker_id, e);
+                return;
+            }
+        }
+        thread::sleep(DECOMMISSION_CHECK_INTERVAL);
+    }
+}
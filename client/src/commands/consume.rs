@@ -0,0 +1,568 @@
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::Duration;
+
+use common::clock::now_millis;
+use common::errors::ProduceError;
+use common::models::{
+    BrokerResponse, FetchRequest, FetchedBatch, JoinGroupRequest, Message, OffsetResetPolicy,
+    PartitionOffset, Subscription, TopicCommand, TopicPartition,
+};
+use common::trace_context;
+use futures::StreamExt;
+
+use crate::{
+    config::ConnectionConfig,
+    connection::BrokerConnection,
+    consumer::{ConsumeError, ConsumerBuilder},
+    dead_letter::{DeadLetterPolicy, DeadLetterQueue},
+    producer::ProducerBuilder,
+    schema_registry::JsonSchemaSerde,
+    serialization::{SerializationError, TypedRecord, ValueFormat},
+};
+
+/// Pause before fetching again after no partition had new records.
+const EMPTY_FETCH_BACKOFF: Duration = Duration::from_millis(500);
+/// Records fetched from a partition at once.
+const FETCH_MAX_RECORDS: u32 = 500;
+
+/// Which fields of a record `consume` prints, in the order of kafka-console-consumer with the
+/// value last.
+#[derive(Debug, Clone)]
+pub struct RecordFormat {
+    pub print_timestamp: bool,
+    pub print_partition: bool,
+    pub print_offset: bool,
+    pub print_headers: bool,
+    pub print_key: bool,
+    /// Printed between the fields of a record.
+    pub separator: String,
+    pub value_format: ValueFormat,
+    /// Values are JSON framed with their schema's ID instead of `value_format`.
+    pub schema_registry: Option<JsonSchemaSerde>,
+}
+
+impl Default for RecordFormat {
+    fn default() -> Self {
+        RecordFormat {
+            print_timestamp: false,
+            print_partition: false,
+            print_offset: false,
+            print_headers: false,
+            print_key: false,
+            separator: "\t".to_string(),
+            value_format: ValueFormat::default(),
+            schema_registry: None,
+        }
+    }
+}
+
+impl RecordFormat {
+    fn format(&self, partition_index: u8, offset: u64, record: TypedRecord<String>) -> String {
+        let mut fields = Vec::new();
+        if self.print_timestamp {
+            fields.push(
+                record
+                    .timestamp
+                    .map_or_else(|| "-".to_string(), |timestamp| timestamp.to_string()),
+            );
+        }
+        if self.print_partition {
+            fields.push(partition_index.to_string());
+        }
+        if self.print_offset {
+            fields.push(offset.to_string());
+        }
+        if self.print_headers {
+            let headers: Vec<String> = record
+                .headers
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, String::from_utf8_lossy(value)))
+                .collect();
+            fields.push(headers.join(","));
+        }
+        if self.print_key {
+            fields.push(record.key.map_or_else(
+                || "null".to_string(),
+                |key| String::from_utf8_lossy(&key).into_owned(),
+            ));
+        }
+        fields.push(record.value.unwrap_or_else(|| "null".to_string()));
+        fields.join(&self.separator)
+    }
+}
+
+/// Where `consume` starts reading and when it stops.
+#[derive(Debug, Clone, Default)]
+pub struct ConsumeOptions {
+    /// Reads only this partition instead of every partition of the topic.
+    pub partition_index: Option<u8>,
+    /// Offset of the first record of `partition_index`.
+    pub offset: Option<u64>,
+    /// Starts at the first record instead of the log end when there is no offset to start from.
+    pub from_beginning: bool,
+    /// Joins this group, reads the partitions assigned to it and commits its offsets.
+    pub group_id: Option<String>,
+    /// Stops after printing this many records instead of waiting for new ones.
+    pub max_messages: Option<usize>,
+    /// Writes records which can't be read to the topic's dead letter topic instead of skipping
+    /// them.
+    pub dead_letter_policy: Option<DeadLetterPolicy>,
+}
+
+/// A member of the group `consume` reads with.
+struct Membership {
+    group_id: String,
+    member_id: String,
+    generation_id: u32,
+}
+
+/// Prints the records of a topic to stdout as they are written, like kafka-console-consumer.
+pub fn consume(
+    topic_name: String,
+    options: ConsumeOptions,
+    record_format: RecordFormat,
+    connection_config: ConnectionConfig,
+) {
+    let auto_offset_reset = if options.from_beginning {
+        OffsetResetPolicy::Earliest
+    } else {
+        OffsetResetPolicy::Latest
+    };
+    let Some(group_id) = options.group_id.clone() else {
+        consume_without_group(
+            topic_name,
+            options,
+            record_format,
+            connection_config,
+            auto_offset_reset,
+        );
+        return;
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the consumer's runtime");
+    let dead_letter_queue = options.dead_letter_policy.clone().map(|policy| {
+        let _runtime = runtime.enter();
+        dead_letter_queue(connection_config.clone(), policy)
+    });
+    let mut connection = match BrokerConnection::connect(connection_config) {
+        Ok(connection) => connection,
+        Err(e) => {
+            tracing::error!("Could not connect to broker: {}", e);
+            return;
+        }
+    };
+    let Some((mut member, assignment)) = join_group(&mut connection, &group_id, &topic_name) else {
+        return;
+    };
+    // next offset of every partition read, `None` until the first fetch, which then starts at the
+    // group's committed offset or the reset policy
+    let mut positions: BTreeMap<TopicPartition, Option<u64>> =
+        assignment.into_iter().map(|tp| (tp, None)).collect();
+
+    let mut printed = 0;
+    'consuming: loop {
+        let mut fetched_any = false;
+        for (topic_partition, position) in positions.iter_mut() {
+            if options.max_messages == Some(printed) {
+                break 'consuming;
+            }
+            let remaining = options
+                .max_messages
+                .map_or(FETCH_MAX_RECORDS, |max_messages| {
+                    (max_messages - printed).min(FETCH_MAX_RECORDS as usize) as u32
+                });
+            let fetch_request = FetchRequest {
+                topic_partition: topic_partition.clone(),
+                offset: *position,
+                group_id: Some(member.group_id.clone()),
+                member_id: Some(member.member_id.clone()),
+                auto_offset_reset,
+                max_records: remaining,
+                replica_id: None,
+                leader_epoch: None,
+                rack_id: None,
+            };
+            let (base_offset, batches) =
+                match connection.request(TopicCommand::Fetch(fetch_request)) {
+                    Ok(BrokerResponse::Records {
+                        base_offset,
+                        batches,
+                        ..
+                    }) => (base_offset, batches),
+                    Ok(BrokerResponse::OffsetOutOfRange {
+                        offset,
+                        log_start_offset,
+                        log_end_offset,
+                        ..
+                    }) => {
+                        tracing::warn!(
+                            "Offset {} of {}-{} is not between {} and {}, resetting it",
+                            offset,
+                            topic_partition.topic_name,
+                            topic_partition.partition_index,
+                            log_start_offset,
+                            log_end_offset
+                        );
+                        *position = None;
+                        continue;
+                    }
+                    Ok(response) => {
+                        tracing::error!("Could not fetch records: {:?}", response);
+                        break 'consuming;
+                    }
+                    Err(e) => {
+                        tracing::error!("Could not fetch records: {}", e);
+                        break 'consuming;
+                    }
+                };
+            let records = match FetchedBatch::records(batches, base_offset, remaining as usize) {
+                Ok(records) => records,
+                Err(e) => {
+                    tracing::error!("Could not decompress records: {}", e);
+                    break 'consuming;
+                }
+            };
+            *position = Some(base_offset + records.len() as u64);
+            fetched_any |= !records.is_empty();
+            let now = now_millis();
+            for (offset, record) in (base_offset..).zip(records) {
+                if record.is_expired(now) {
+                    tracing::debug!(
+                        "Skipping expired offset {} of {:?}",
+                        offset,
+                        topic_partition
+                    );
+                    continue;
+                }
+                let consumed = runtime.block_on(consume_record(
+                    &record_format,
+                    dead_letter_queue.as_ref(),
+                    topic_partition,
+                    offset,
+                    record,
+                ));
+                if let Err(e) = consumed {
+                    tracing::error!(
+                        "Could not write offset {} of {:?} to its dead letter topic: {}",
+                        offset,
+                        topic_partition,
+                        e
+                    );
+                    // the group reads the record again
+                    *position = Some(offset);
+                    break 'consuming;
+                }
+                printed += 1;
+            }
+        }
+        commit_positions(&mut connection, &member.group_id, &positions);
+        match rejoin_if_rebalanced(&mut connection, &mut member, &topic_name) {
+            Some(Some(assignment)) => {
+                positions = assignment.into_iter().map(|tp| (tp, None)).collect()
+            }
+            Some(None) => {}
+            None => return,
+        }
+        if !fetched_any {
+            thread::sleep(EMPTY_FETCH_BACKOFF);
+        }
+    }
+    commit_positions(&mut connection, &member.group_id, &positions);
+    let command = TopicCommand::LeaveGroup {
+        group_id: member.group_id,
+        member_id: member.member_id,
+    };
+    if let Err(e) = connection.request(command) {
+        tracing::error!("Could not leave group: {}", e);
+    }
+}
+
+/// Reads the partitions without a group, from `options.offset` or the reset policy, and commits
+/// nothing.
+fn consume_without_group(
+    topic_name: String,
+    options: ConsumeOptions,
+    record_format: RecordFormat,
+    connection_config: ConnectionConfig,
+    auto_offset_reset: OffsetResetPolicy,
+) {
+    let dead_letter_connection_config = connection_config.clone();
+    let consumer_builder = ConsumerBuilder::new(connection_config)
+        .auto_offset_reset(auto_offset_reset)
+        .max_poll_records(FETCH_MAX_RECORDS)
+        .fetch_backoff(EMPTY_FETCH_BACKOFF);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the consumer's runtime");
+    runtime.block_on(async {
+        let dead_letter_queue = options
+            .dead_letter_policy
+            .map(|policy| dead_letter_queue(dead_letter_connection_config, policy));
+        let mut consumer = match options.partition_index {
+            Some(partition_index) => consumer_builder.assign(BTreeMap::from([(
+                TopicPartition::new(topic_name.clone(), partition_index),
+                options.offset,
+            )])),
+            None => consumer_builder.subscribe(topic_name.clone()),
+        }
+        .expect("Invalid consumer configuration");
+        let mut printed = 0;
+        while options.max_messages != Some(printed) {
+            match consumer.next().await {
+                Some(Ok(record)) => {
+                    let consumed = consume_record(
+                        &record_format,
+                        dead_letter_queue.as_ref(),
+                        &record.topic_partition,
+                        record.offset,
+                        record.message,
+                    )
+                    .await;
+                    if let Err(e) = consumed {
+                        tracing::error!(
+                            "Could not write offset {} of {:?} to its dead letter topic: {}",
+                            record.offset,
+                            record.topic_partition,
+                            e
+                        );
+                        return;
+                    }
+                    printed += 1;
+                }
+                Some(Err(e @ ConsumeError::OffsetOutOfRange { .. })) => tracing::warn!("{}", e),
+                Some(Err(e)) => {
+                    tracing::error!("{}", e);
+                    return;
+                }
+                None => return,
+            }
+        }
+    });
+}
+
+fn dead_letter_queue(
+    connection_config: ConnectionConfig,
+    policy: DeadLetterPolicy,
+) -> DeadLetterQueue {
+    let producer = ProducerBuilder::new(connection_config)
+        .build()
+        .expect("Invalid producer configuration");
+    DeadLetterQueue::new(producer, policy)
+}
+
+/// Prints the record, or writes it to the dead letter topic once it could not be read.
+async fn consume_record(
+    record_format: &RecordFormat,
+    dead_letter_queue: Option<&DeadLetterQueue>,
+    topic_partition: &TopicPartition,
+    offset: u64,
+    record: Message,
+) -> Result<(), ProduceError> {
+    let print = |record| {
+        std::future::ready(print_record(
+            record_format,
+            &topic_partition.topic_name,
+            topic_partition.partition_index,
+            offset,
+            record,
+        ))
+    };
+    match dead_letter_queue {
+        // retried since the schema registry may not have answered
+        Some(dead_letter_queue) => {
+            dead_letter_queue
+                .process(topic_partition, offset, record, print)
+                .await
+        }
+        None => {
+            if let Err(e) = print(record).await {
+                tracing::error!(
+                    "Could not read record at offset {} of partition {}: {}",
+                    offset,
+                    topic_partition.partition_index,
+                    e
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+fn print_record(
+    record_format: &RecordFormat,
+    topic_name: &str,
+    partition_index: u8,
+    offset: u64,
+    record: Message,
+) -> Result<(), SerializationError> {
+    let span = tracing::info_span!("consume", topic = %topic_name, offset);
+    trace_context::follow_record(&span, &record.headers);
+    let _entered = span.enter();
+    let record = match &record_format.schema_registry {
+        Some(schema_registry) => schema_registry.to_record(topic_name, record),
+        None => record_format.value_format.to_record(topic_name, record),
+    };
+    println!("{}", record_format.format(partition_index, offset, record?));
+    Ok(())
+}
+
+fn join_group(
+    connection: &mut BrokerConnection,
+    group_id: &str,
+    topic_name: &str,
+) -> Option<(Membership, Vec<TopicPartition>)> {
+    let request = JoinGroupRequest::new(
+        group_id.to_string(),
+        Subscription::Topics(vec![topic_name.to_string()]),
+    );
+    match connection.request(TopicCommand::JoinGroup(request)) {
+        Ok(BrokerResponse::GroupJoined {
+            member_id,
+            generation_id,
+            assignment,
+            ..
+        }) => {
+            tracing::info!(
+                "Joined group {} as {} in generation {}",
+                group_id,
+                member_id,
+                generation_id
+            );
+            let membership = Membership {
+                group_id: group_id.to_string(),
+                member_id,
+                generation_id,
+            };
+            Some((membership, assignment))
+        }
+        Ok(response) => {
+            tracing::error!("Could not join group {}: {:?}", group_id, response);
+            None
+        }
+        Err(e) => {
+            tracing::error!("Could not join group {}: {}", group_id, e);
+            None
+        }
+    }
+}
+
+/// Sends a heartbeat and returns the new assignment if the group rebalanced, `None` if the
+/// member could not stay in the group.
+fn rejoin_if_rebalanced(
+    connection: &mut BrokerConnection,
+    member: &mut Membership,
+    topic_name: &str,
+) -> Option<Option<Vec<TopicPartition>>> {
+    let command = TopicCommand::Heartbeat {
+        group_id: member.group_id.clone(),
+        member_id: member.member_id.clone(),
+    };
+    match connection.request(command) {
+        Ok(BrokerResponse::HeartbeatAccepted { generation_id })
+            if generation_id == member.generation_id =>
+        {
+            Some(None)
+        }
+        Ok(BrokerResponse::HeartbeatAccepted { .. }) => {
+            let command = TopicCommand::GetAssignment {
+                group_id: member.group_id.clone(),
+                member_id: member.member_id.clone(),
+            };
+            match connection.request(command) {
+                Ok(BrokerResponse::MemberAssignment {
+                    generation_id,
+                    assignment,
+                    ..
+                }) => {
+                    tracing::info!("Group rebalanced in generation {}", generation_id);
+                    member.generation_id = generation_id;
+                    Some(Some(assignment))
+                }
+                response => {
+                    tracing::error!("Could not get the new assignment: {:?}", response);
+                    None
+                }
+            }
+        }
+        // evicted, e.g. after a long pause, join again as a new member
+        Ok(BrokerResponse::UnknownGroupMember { .. }) => {
+            let (joined, assignment) = join_group(connection, &member.group_id, topic_name)?;
+            *member = joined;
+            Some(Some(assignment))
+        }
+        response => {
+            tracing::error!("Could not send heartbeat: {:?}", response);
+            None
+        }
+    }
+}
+
+fn commit_positions(
+    connection: &mut BrokerConnection,
+    group_id: &str,
+    positions: &BTreeMap<TopicPartition, Option<u64>>,
+) {
+    let offsets: Vec<PartitionOffset> = positions
+        .iter()
+        .filter_map(|(topic_partition, position)| {
+            position.map(|offset| PartitionOffset {
+                topic_partition: topic_partition.clone(),
+                offset,
+            })
+        })
+        .collect();
+    if offsets.is_empty() {
+        return;
+    }
+    let command = TopicCommand::CommitOffsets {
+        group_id: group_id.to_string(),
+        offsets,
+    };
+    match connection.request(command) {
+        Ok(BrokerResponse::OffsetsCommitted) => {}
+        response => tracing::error!("Could not commit offsets: {:?}", response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn test_record_format_should_print_selected_fields_before_the_value() {
+        let record = TypedRecord {
+            key: Some(Bytes::from("user-1")),
+            value: Some("clicked".to_string()),
+            timestamp: Some(1_700_000_000_000),
+            headers: vec![
+                ("trace".to_string(), Bytes::from_static(b"1")),
+                ("tenant".to_string(), Bytes::from_static(b"a")),
+            ],
+        };
+
+        assert_eq!(
+            RecordFormat::default().format(2, 42, record.clone()),
+            "clicked"
+        );
+
+        let record_format = RecordFormat {
+            print_timestamp: true,
+            print_partition: true,
+            print_offset: true,
+            print_headers: true,
+            print_key: true,
+            separator: " | ".to_string(),
+            ..RecordFormat::default()
+        };
+        assert_eq!(
+            record_format.format(2, 42, record),
+            "1700000000000 | 2 | 42 | trace=1,tenant=a | user-1 | clicked"
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs;
+
+use bytes::BytesMut;
+use common::codecs::decoder::RecordBatchDecoder;
+use common::models::RecordBatch;
+use tokio_util::codec::Decoder;
+
+/// What `dump_log` found in a segment file.
+#[derive(Debug, Default, PartialEq)]
+pub struct SegmentSummary {
+    pub batches: usize,
+    pub records: u64,
+    /// Batches whose CRC does not match or whose records cannot be read.
+    pub corrupt_batches: usize,
+    /// Bytes after the last complete batch, e.g. of a write cut off by a crash.
+    pub trailing_bytes: usize,
+}
+
+impl SegmentSummary {
+    pub fn is_valid(&self) -> bool {
+        self.corrupt_batches == 0 && self.trailing_bytes == 0
+    }
+}
+
+/// Prints every batch of a segment file with its offset, format, compression, CRC status and
+/// size, followed by the timestamp, key and payload size of its records unless `verify` only
+/// checks them. Exits with an error status when the segment is corrupt.
+pub fn dump_log(segment_file_path: String, verify: bool) {
+    let segment = match fs::read(&segment_file_path) {
+        Ok(segment) => segment,
+        Err(e) => {
+            tracing::error!("Could not read {}: {}", segment_file_path, e);
+            std::process::exit(1);
+        }
+    };
+    let mut lines = Vec::new();
+    let summary = inspect_segment(&segment, verify, &mut lines);
+    for line in lines {
+        println!("{}", line);
+    }
+    println!(
+        "{}: {} batches, {} records, {} corrupt batches, {} trailing bytes",
+        segment_file_path,
+        summary.batches,
+        summary.records,
+        summary.corrupt_batches,
+        summary.trailing_bytes
+    );
+    if !summary.is_valid() {
+        std::process::exit(1);
+    }
+}
+
+/// Decodes the batches of `segment`, appending the lines to print to `lines`. Offsets count the
+/// records from the start of the segment, as the partition writer assigns them.
+fn inspect_segment(segment: &[u8], verify: bool, lines: &mut Vec<String>) -> SegmentSummary {
+    let mut summary = SegmentSummary::default();
+    let mut src = BytesMut::from(segment);
+    let mut batch_decoder = RecordBatchDecoder {};
+    loop {
+        let position = segment.len() - src.len();
+        let remaining = src.len();
+        let batch = match batch_decoder.decode(&mut src) {
+            Ok(Some(batch)) => batch,
+            // the decoder may have consumed the length of a cut off batch already
+            Ok(None) => {
+                summary.trailing_bytes = remaining;
+                break;
+            }
+            // the frame was consumed, the next batch follows it
+            Err(e) if src.len() < remaining => {
+                summary.batches += 1;
+                summary.corrupt_batches += 1;
+                lines.push(format!(
+                    "position: {} size: {} error: {}",
+                    position,
+                    remaining - src.len(),
+                    e
+                ));
+                continue;
+            }
+            Err(e) => {
+                lines.push(format!("position: {} error: {}", position, e));
+                summary.trailing_bytes = remaining;
+                break;
+            }
+        };
+        let base_offset = summary.records;
+        summary.batches += 1;
+        summary.records += batch.record_count as u64;
+        let (crc, records) = match batch.crc_matches() {
+            Some(false) => ("invalid", None),
+            crc_matches => {
+                let crc = if crc_matches.is_some() {
+                    "valid"
+                } else {
+                    "none"
+                };
+                (crc, Some(batch.records()))
+            }
+        };
+        lines.push(format_batch(
+            &batch,
+            base_offset,
+            position,
+            remaining - src.len(),
+            crc,
+        ));
+        match records {
+            Some(Ok(records)) if records.len() == batch.record_count as usize => {
+                if verify {
+                    continue;
+                }
+                for (offset, record) in (base_offset..).zip(records) {
+                    lines.push(format!(
+                        "| offset: {} timestamp: {} key: {} headers: {} payload_size: {}",
+                        offset,
+                        record
+                            .timestamp
+                            .map_or_else(|| "-".to_string(), |timestamp| timestamp.to_string()),
+                        record.key.as_ref().map_or_else(
+                            || "-".to_string(),
+                            |key| String::from_utf8_lossy(key).into_owned()
+                        ),
+                        record.headers.len(),
+                        record.payload_len()
+                    ));
+                }
+            }
+            Some(Ok(records)) => {
+                summary.corrupt_batches += 1;
+                lines.push(format!(
+                    "| error: {} records instead of {}",
+                    records.len(),
+                    batch.record_count
+                ));
+            }
+            Some(Err(e)) => {
+                summary.corrupt_batches += 1;
+                lines.push(format!("| error: {}", e));
+            }
+            None => summary.corrupt_batches += 1,
+        }
+    }
+    summary
+}
+
+fn format_batch(
+    batch: &RecordBatch,
+    base_offset: u64,
+    position: usize,
+    size: usize,
+    crc: &str,
+) -> String {
+    format!(
+        "baseOffset: {} lastOffset: {} count: {} position: {} size: {} format: {} compression: {:?} producer: {} timestampType: {:?} maxTimestamp: {} crc: {}",
+        base_offset,
+        (base_offset + batch.record_count as u64).saturating_sub(1),
+        batch.record_count,
+        position,
+        size,
+        batch.format_version,
+        batch.compression,
+        batch.producer.map_or_else(
+            || "-".to_string(),
+            |producer| format!("{}:{}", producer.producer_id, producer.base_sequence)
+        ),
+        batch.timestamp_type,
+        batch
+            .max_timestamp
+            .map_or_else(|| "-".to_string(), |max_timestamp| max_timestamp.to_string()),
+        crc
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use common::codecs::encoder::BatchEncoder;
+    use common::models::{Batch, CompressionCodec, Message};
+    use tokio_util::codec::Encoder;
+
+    use super::*;
+
+    fn encoded_batch(payloads: &[&'static [u8]]) -> BytesMut {
+        let batch = Batch {
+            records: payloads
+                .iter()
+                .map(|payload| Message {
+                    payload: Some(Bytes::from_static(payload)),
+                    key: Some(Bytes::from_static(b"key")),
+                    timestamp: Some(1_700_000_000_000),
+                    headers: vec![],
+                })
+                .collect(),
+            producer: None,
+            compression: CompressionCodec::None,
+        };
+        let mut encoded_batch = BytesMut::new();
+        BatchEncoder {}.encode(batch, &mut encoded_batch).unwrap();
+        encoded_batch
+    }
+
+    #[test]
+    fn test_inspect_segment_should_report_corrupt_batches_and_trailing_bytes() {
+        let mut segment = encoded_batch(&[b"first", b"second"]);
+        let mut corrupt_batch = encoded_batch(&[b"third"]);
+        let last_byte = corrupt_batch.len() - 1;
+        corrupt_batch[last_byte] ^= 0xff;
+        segment.extend_from_slice(&corrupt_batch);
+        segment.extend_from_slice(&encoded_batch(&[b"fourth"]));
+
+        let mut lines = Vec::new();
+        let summary = inspect_segment(&segment, false, &mut lines);
+        assert_eq!(
+            summary,
+            SegmentSummary {
+                batches: 3,
+                records: 4,
+                corrupt_batches: 1,
+                trailing_bytes: 0,
+            }
+        );
+        assert!(lines[0].starts_with("baseOffset: 0 lastOffset: 1 count: 2"));
+        assert!(lines[0].ends_with("crc: valid"));
+        assert!(lines[1].starts_with("| offset: 0 timestamp: 1700000000000 key: key"));
+        assert!(lines[3].starts_with("baseOffset: 2 lastOffset: 2"));
+        assert!(lines[3].ends_with("crc: invalid"));
+        assert!(lines[4].starts_with("baseOffset: 3"));
+
+        let mut cut_off_segment = encoded_batch(&[b"first"]);
+        let cut_off_batch = encoded_batch(&[b"second"]);
+        cut_off_segment.extend_from_slice(&cut_off_batch[..cut_off_batch.len() / 2]);
+        let mut lines = Vec::new();
+        let summary = inspect_segment(&cut_off_segment, true, &mut lines);
+        assert_eq!(summary.batches, 1);
+        assert_eq!(summary.trailing_bytes, cut_off_batch.len() / 2);
+        assert!(!summary.is_valid());
+        assert_eq!(lines.len(), 1);
+    }
+}
@@ -0,0 +1,68 @@
+use common::models::{BrokerResponse, OffsetResetTarget, TopicCommand};
+
+use super::send_request;
+use crate::config::ConnectionConfig;
+
+pub fn describe_group(group_id: String, connection: ConnectionConfig) {
+    match send_request(connection, TopicCommand::DescribeGroup { group_id }) {
+        Ok(BrokerResponse::GroupDescription {
+            group_id,
+            generation_id,
+            partitions,
+        }) => {
+            println!("Group {} (generation {})", group_id, generation_id);
+            println!(
+                "{:<30} {:>9} {:>16} {:>14} {:>10} MEMBER",
+                "TOPIC", "PARTITION", "COMMITTED-OFFSET", "LOG-END-OFFSET", "LAG"
+            );
+            let unknown = || "-".to_string();
+            for partition in partitions {
+                println!(
+                    "{:<30} {:>9} {:>16} {:>14} {:>10} {}",
+                    partition.topic_partition.topic_name,
+                    partition.topic_partition.partition_index,
+                    partition
+                        .committed_offset
+                        .map_or_else(unknown, |offset| offset.to_string()),
+                    partition.log_end_offset,
+                    partition.lag.map_or_else(unknown, |lag| lag.to_string()),
+                    partition.member_id.unwrap_or_else(unknown),
+                );
+            }
+        }
+        Ok(response) => tracing::error!("Could not describe group: {:?}", response),
+        Err(e) => tracing::error!("Could not describe group: {}", e),
+    }
+}
+
+pub fn reset_offsets(
+    group_id: String,
+    topic_name: String,
+    to: OffsetResetTarget,
+    connection: ConnectionConfig,
+) {
+    let command = TopicCommand::ResetOffsets {
+        group_id,
+        topic_name,
+        to,
+    };
+    match send_request(connection, command) {
+        Ok(BrokerResponse::OffsetsReset { group_id, offsets }) => {
+            println!("Reset offsets of group {}", group_id);
+            for partition_offset in offsets {
+                println!(
+                    "{}-{}\t{}",
+                    partition_offset.topic_partition.topic_name,
+                    partition_offset.topic_partition.partition_index,
+                    partition_offset.offset
+                );
+            }
+        }
+        Ok(BrokerResponse::GroupNotEmpty { group_id }) => tracing::error!(
+            "Group {} still has members, stop its consumers before resetting offsets",
+            group_id
+        ),
+        Ok(response) => tracing::error!("Could not reset offsets: {:?}", response),
+        Err(e) => tracing::error!("Could not reset offsets: {}", e),
+    }
+}
@@ -0,0 +1,40 @@
+//! Subcommands of the `walrs` CLI, grouped like their command line.
+
+use common::errors::WalrsError;
+use common::models::{BrokerResponse, TopicCommand};
+
+use crate::client::{AdminClientBuilder, BlockingClient};
+use crate::config::ConnectionConfig;
+
+pub mod cluster;
+pub mod consume;
+pub mod dump_log;
+pub mod groups;
+pub mod perf;
+pub mod produce;
+pub mod topics;
+
+/// Client of the admin commands, exits if the connection settings can't work.
+fn admin_client(connection: ConnectionConfig) -> BlockingClient {
+    AdminClientBuilder::new(connection)
+        .build()
+        .expect("Invalid connection configuration")
+}
+
+/// Sends `command` to the broker and waits for its response, failing with `BrokerUnavailable`
+/// when the broker can't be reached.
+fn send_request(
+    connection: ConnectionConfig,
+    command: TopicCommand,
+) -> Result<BrokerResponse, WalrsError> {
+    admin_client(connection).request(command)
+}
+
+/// Broker IDs separated by commas.
+fn join_broker_ids(broker_ids: &[u32]) -> String {
+    broker_ids
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use bytes::Bytes;
+use common::models::{FetchRequest, Message, OffsetResetPolicy, TopicPartition};
+use tokio::sync::mpsc;
+
+use crate::{
+    client::FetchedRecords,
+    config::ConnectionConfig,
+    producer::{DeliveryFuture, ProducerBuilder},
+};
+
+/// Records sent but not acknowledged yet, sending pauses once this many are waiting.
+const DELIVERIES_IN_FLIGHT: usize = 10_000;
+/// Time between the progress reports of a running test.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Pause before fetching again after no partition had new records.
+const EMPTY_FETCH_BACKOFF: Duration = Duration::from_millis(100);
+
+/// When a performance test stops, at whichever limit is reached first.
+#[derive(Debug, Clone, Copy)]
+pub struct PerfLimits {
+    pub num_records: Option<u64>,
+    pub duration: Option<Duration>,
+}
+
+impl PerfLimits {
+    fn reached(&self, records: u64, started_at: Instant) -> bool {
+        self.num_records
+            .is_some_and(|num_records| records >= num_records)
+            || self
+                .duration
+                .is_some_and(|duration| started_at.elapsed() >= duration)
+    }
+}
+
+/// Latencies of a test in microseconds, with the records and bytes they cover.
+#[derive(Debug, Default)]
+struct PerfStats {
+    records: u64,
+    bytes: u64,
+    errors: u64,
+    latencies_micros: Vec<u64>,
+}
+
+impl PerfStats {
+    fn record(&mut self, records: u64, bytes: u64, latency: Duration) {
+        self.records += records;
+        self.bytes += bytes;
+        self.latencies_micros.push(latency.as_micros() as u64);
+    }
+
+    /// Latency below which `percentile` percent of the samples are, in milliseconds.
+    fn percentile_millis(sorted_latencies: &[u64], percentile: f64) -> f64 {
+        if sorted_latencies.is_empty() {
+            return 0.0;
+        }
+        let index = ((percentile / 100.0) * sorted_latencies.len() as f64).ceil() as usize;
+        sorted_latencies[index.clamp(1, sorted_latencies.len()) - 1] as f64 / 1000.0
+    }
+
+    /// One line like kafka-producer-perf-test prints, `latency_name` says what was timed.
+    fn summary(&self, elapsed: Duration, latency_name: &str) -> String {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut latencies = self.latencies_micros.clone();
+        latencies.sort_unstable();
+        let average = if latencies.is_empty() {
+            0.0
+        } else {
+            latencies.iter().sum::<u64>() as f64 / latencies.len() as f64 / 1000.0
+        };
+        format!(
+            "{} records, {:.1} records/sec ({:.2} MB/sec), {} errors, {:.2} ms avg {}, \
+             {:.2} ms max, {:.2} ms 50th, {:.2} ms 95th, {:.2} ms 99th, {:.2} ms 99.9th",
+            self.records,
+            self.records as f64 / seconds,
+            self.bytes as f64 / seconds / (1024.0 * 1024.0),
+            self.errors,
+            average,
+            latency_name,
+            latencies.last().map_or(0.0, |max| *max as f64 / 1000.0),
+            Self::percentile_millis(&latencies, 50.0),
+            Self::percentile_millis(&latencies, 95.0),
+            Self::percentile_millis(&latencies, 99.0),
+            Self::percentile_millis(&latencies, 99.9),
+        )
+    }
+}
+
+/// Payload of `record_size` random uppercase letters, so compression does not shrink it to
+/// nothing.
+fn random_payload(record_size: usize) -> Bytes {
+    let mut state = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+        | 1;
+    (0..record_size)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            b'A' + (state % 26) as u8
+        })
+        .collect::<Vec<u8>>()
+        .into()
+}
+
+/// Writes records of `record_size` bytes to the topic, at most `throughput` per second, and
+/// prints the throughput and the latencies from sending a record until it was acknowledged.
+pub fn perf_produce(
+    topic_name: String,
+    record_size: usize,
+    throughput: Option<u64>,
+    limits: PerfLimits,
+    producer_builder: ProducerBuilder,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Could not start tokio runtime");
+    runtime.block_on(async {
+        let producer = producer_builder
+            .build()
+            .expect("Invalid producer configuration");
+        let payload = random_payload(record_size);
+        let (deliveries_tx, deliveries_rx) = mpsc::channel(DELIVERIES_IN_FLIGHT);
+        let started_at = Instant::now();
+        let collecting = tokio::spawn(collect_deliveries(
+            deliveries_rx,
+            record_size as u64,
+            started_at,
+        ));
+        let mut sent = 0;
+        while !limits.reached(sent, started_at) {
+            if let Some(throughput) = throughput {
+                // sent records are ahead of the target, wait until they are due
+                let due_at = started_at + Duration::from_secs_f64(sent as f64 / throughput as f64);
+                tokio::time::sleep_until(due_at.into()).await;
+            }
+            let message = Message::new(payload.clone(), None, None);
+            let sent_at = Instant::now();
+            match producer.send(topic_name.clone(), message).await {
+                Ok(delivery) => deliveries_tx.send((sent_at, delivery)).await.unwrap(),
+                Err(e) => {
+                    tracing::error!("Could not send record: {}", e);
+                    break;
+                }
+            }
+            sent += 1;
+        }
+        producer.close().await;
+        drop(deliveries_tx);
+        let stats = collecting.await.unwrap();
+        println!("{}", stats.summary(started_at.elapsed(), "produce latency"));
+    });
+}
+
+async fn collect_deliveries(
+    mut deliveries_rx: mpsc::Receiver<(Instant, DeliveryFuture)>,
+    record_size: u64,
+    started_at: Instant,
+) -> PerfStats {
+    let mut stats = PerfStats::default();
+    let mut window = PerfStats::default();
+    let mut window_started_at = started_at;
+    while let Some((sent_at, delivery)) = deliveries_rx.recv().await {
+        match delivery.await {
+            Ok(_) => {
+                let latency = sent_at.elapsed();
+                stats.record(1, record_size, latency);
+                window.record(1, record_size, latency);
+            }
+            Err(e) => {
+                tracing::error!("Could not write record: {}", e);
+                stats.errors += 1;
+                window.errors += 1;
+            }
+        }
+        if window_started_at.elapsed() >= REPORT_INTERVAL {
+            println!(
+                "{}",
+                window.summary(window_started_at.elapsed(), "produce latency")
+            );
+            window = PerfStats::default();
+            window_started_at = Instant::now();
+        }
+    }
+    stats
+}
+
+/// Reads every partition of the topic from its first record and prints the throughput and the
+/// latencies of the fetches.
+pub fn perf_consume(
+    topic_name: String,
+    max_records_per_fetch: u32,
+    limits: PerfLimits,
+    connection: ConnectionConfig,
+) {
+    let client = super::admin_client(connection);
+    let partition_count = match client.metadata(Some(vec![topic_name.clone()])) {
+        Ok(topics) if !topics.is_empty() => topics[0].partitions.len() as u8,
+        Ok(_) => {
+            tracing::error!("Topic {} does not exist", topic_name);
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Could not find the partitions of the topic: {}", e);
+            return;
+        }
+    };
+    let mut positions: BTreeMap<TopicPartition, Option<u64>> = (0..partition_count)
+        .map(|partition_index| {
+            (
+                TopicPartition::new(topic_name.clone(), partition_index),
+                None,
+            )
+        })
+        .collect();
+
+    let started_at = Instant::now();
+    let mut stats = PerfStats::default();
+    let mut window = PerfStats::default();
+    let mut window_started_at = started_at;
+    'consuming: while !limits.reached(stats.records, started_at) {
+        let mut fetched_any = false;
+        for (topic_partition, position) in positions.iter_mut() {
+            let fetch_request = FetchRequest {
+                topic_partition: topic_partition.clone(),
+                offset: *position,
+                group_id: None,
+                member_id: None,
+                auto_offset_reset: OffsetResetPolicy::Earliest,
+                max_records: max_records_per_fetch,
+                replica_id: None,
+                leader_epoch: None,
+                rack_id: None,
+            };
+            let fetched_at = Instant::now();
+            let FetchedRecords {
+                base_offset,
+                records,
+                ..
+            } = match client.fetch(fetch_request) {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    tracing::error!("Could not fetch records: {}", e);
+                    stats.errors += 1;
+                    break 'consuming;
+                }
+            };
+            let latency = fetched_at.elapsed();
+            let bytes = records
+                .iter()
+                .map(|record| record.payload_len() as u64)
+                .sum();
+            *position = Some(base_offset + records.len() as u64);
+            fetched_any |= !records.is_empty();
+            stats.record(records.len() as u64, bytes, latency);
+            window.record(records.len() as u64, bytes, latency);
+        }
+        if window_started_at.elapsed() >= REPORT_INTERVAL {
+            println!(
+                "{}",
+                window.summary(window_started_at.elapsed(), "fetch latency")
+            );
+            window = PerfStats::default();
+            window_started_at = Instant::now();
+        }
+        if !fetched_any {
+            std::thread::sleep(EMPTY_FETCH_BACKOFF);
+        }
+    }
+    println!("{}", stats.summary(started_at.elapsed(), "fetch latency"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perf_stats_should_report_throughput_and_latency_percentiles() {
+        let mut stats = PerfStats::default();
+        for latency_millis in 1..=100 {
+            stats.record(10, 1024 * 1024, Duration::from_millis(latency_millis));
+        }
+
+        assert_eq!(
+            stats.summary(Duration::from_secs(10), "produce latency"),
+            "1000 records, 100.0 records/sec (10.00 MB/sec), 0 errors, 50.50 ms avg produce \
+             latency, 100.00 ms max, 50.00 ms 50th, 95.00 ms 95th, 99.00 ms 99th, 100.00 ms 99.9th"
+        );
+        let limits = PerfLimits {
+            num_records: Some(1000),
+            duration: None,
+        };
+        assert!(limits.reached(stats.records, Instant::now()));
+        assert!(!limits.reached(999, Instant::now()));
+        assert_eq!(random_payload(100).len(), 100);
+    }
+}
@@ -0,0 +1,230 @@
+use bytes::Bytes;
+use common::models::Message;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use crate::{
+    producer::{DeliveryFuture, Producer, ProducerBuilder, RecordMetadata},
+    schema_registry::JsonSchemaSerde,
+    serialization::{TypedRecord, ValueFormat},
+};
+
+/// Records sent but not acknowledged yet, reading stdin pauses once this many are waiting.
+const DELIVERIES_IN_FLIGHT: usize = 1000;
+
+/// How `produce` turns a line into a record.
+#[derive(Debug, Clone, Default)]
+pub struct LineFormat {
+    /// Lines are `<key><separator><value>`, lines without the separator are rejected.
+    pub key_separator: Option<String>,
+    /// Lines start with their headers, `name=value` pairs separated by commas, followed by a tab.
+    pub parse_headers: bool,
+    pub value_format: ValueFormat,
+    /// Key of records whose line has none.
+    pub key: Option<String>,
+    /// Headers of every record, before the headers of its line.
+    pub headers: Vec<(String, Bytes)>,
+    /// Value written as a tombstone, which marks its key as deleted.
+    pub null_marker: Option<String>,
+    /// Values are JSON framed with their schema's ID instead of `value_format`.
+    pub schema_registry: Option<JsonSchemaSerde>,
+}
+
+impl LineFormat {
+    fn to_message(&self, topic_name: &str, line: &str) -> Result<Message, String> {
+        let mut headers = self.headers.clone();
+        let mut line = line;
+        if self.parse_headers {
+            let (line_headers, rest) = line
+                .split_once('\t')
+                .ok_or_else(|| "no tab after the headers".to_string())?;
+            for header in line_headers.split(',').filter(|header| !header.is_empty()) {
+                headers.push(parse_header(header)?);
+            }
+            line = rest;
+        }
+        let (key, value) = match &self.key_separator {
+            Some(key_separator) => {
+                let (key, value) = line
+                    .split_once(key_separator.as_str())
+                    .ok_or_else(|| format!("no key separator {}", key_separator))?;
+                (Some(key.to_string()), value)
+            }
+            None => (self.key.clone(), line),
+        };
+        let record = TypedRecord {
+            key: key.map(Bytes::from),
+            value: (self.null_marker.as_deref() != Some(value)).then(|| value.to_string()),
+            timestamp: None,
+            headers,
+        };
+        match &self.schema_registry {
+            Some(schema_registry) => schema_registry.to_message(topic_name, record),
+            None => self.value_format.to_message(topic_name, record),
+        }
+        .map_err(|e| e.to_string())
+    }
+}
+
+pub fn parse_header(value: &str) -> Result<(String, Bytes), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() => {
+            Ok((name.to_string(), Bytes::copy_from_slice(value.as_bytes())))
+        }
+        _ => Err(format!("Invalid header {}, expected name=value", value)),
+    }
+}
+
+/// Writes `message`, or every line read from stdin until it ends, as a record and prints the
+/// partition and offset of each record in the order of the lines, like kafka-console-producer.
+pub fn produce(
+    topic_name: String,
+    message: Option<String>,
+    line_format: LineFormat,
+    producer_builder: ProducerBuilder,
+) {
+    tracing::info!("Writing to topic: {}", topic_name);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Could not start tokio runtime");
+    runtime.block_on(async {
+        let producer = producer_builder
+            .build()
+            .expect("Invalid producer configuration");
+        let (deliveries_tx, deliveries_rx) = mpsc::channel(DELIVERIES_IN_FLIGHT);
+        let printing = tokio::spawn(print_deliveries(deliveries_rx));
+        match message {
+            Some(message) => {
+                send_line(
+                    &producer,
+                    &topic_name,
+                    &line_format,
+                    1,
+                    &message,
+                    &deliveries_tx,
+                )
+                .await
+            }
+            None => {
+                let mut lines = BufReader::new(tokio::io::stdin()).lines();
+                let mut line_number = 0;
+                loop {
+                    let line = match lines.next_line().await {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break,
+                        Err(e) => {
+                            tracing::error!("Could not read stdin: {}", e);
+                            break;
+                        }
+                    };
+                    line_number += 1;
+                    send_line(
+                        &producer,
+                        &topic_name,
+                        &line_format,
+                        line_number,
+                        &line,
+                        &deliveries_tx,
+                    )
+                    .await;
+                }
+            }
+        }
+        producer.close().await;
+        drop(deliveries_tx);
+        printing.await.unwrap();
+    });
+}
+
+async fn send_line(
+    producer: &Producer,
+    topic_name: &str,
+    line_format: &LineFormat,
+    line_number: usize,
+    line: &str,
+    deliveries_tx: &mpsc::Sender<(usize, DeliveryFuture)>,
+) {
+    let message = match line_format.to_message(topic_name, line) {
+        Ok(message) => message,
+        Err(e) => {
+            tracing::error!("Skipping invalid line {}: {}", line_number, e);
+            return;
+        }
+    };
+    // every record gets its own trace, which its consumers continue
+    let span = tracing::info_span!("produce", topic = %topic_name, line = line_number);
+    match producer
+        .send(topic_name.to_string(), message)
+        .instrument(span)
+        .await
+    {
+        Ok(delivery) => deliveries_tx.send((line_number, delivery)).await.unwrap(),
+        Err(e) => tracing::error!("Could not send line {}: {}", line_number, e),
+    }
+}
+
+/// Prints where every record was written once it was, in the order they were sent.
+async fn print_deliveries(mut deliveries_rx: mpsc::Receiver<(usize, DeliveryFuture)>) {
+    while let Some((line_number, delivery)) = deliveries_rx.recv().await {
+        match delivery.await {
+            Ok(RecordMetadata {
+                topic_partition,
+                offset,
+                ..
+            }) => println!(
+                "{}-{}\t{}",
+                topic_partition.topic_name,
+                topic_partition.partition_index,
+                // the broker does not answer writes with acks 0
+                offset.map_or_else(|| "-".to_string(), |offset| offset.to_string())
+            ),
+            Err(e) => tracing::error!("Could not write line {}: {}", line_number, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_format_should_split_lines_into_headers_key_and_value() {
+        let line_format = LineFormat {
+            key_separator: Some(":".to_string()),
+            parse_headers: true,
+            headers: vec![("source".to_string(), Bytes::from_static(b"cli"))],
+            ..LineFormat::default()
+        };
+
+        let message = line_format
+            .to_message("t1", "trace=1,tenant=a\tuser-1:{\"clicks\": 3}")
+            .unwrap();
+
+        assert_eq!(message.key, Some(Bytes::from("user-1")));
+        assert_eq!(
+            message.payload,
+            Some(Bytes::from_static(b"{\"clicks\": 3}"))
+        );
+        assert_eq!(
+            message.headers,
+            vec![
+                ("source".to_string(), Bytes::from_static(b"cli")),
+                ("trace".to_string(), Bytes::from_static(b"1")),
+                ("tenant".to_string(), Bytes::from_static(b"a")),
+            ]
+        );
+        assert!(line_format.to_message("t1", "user-1:no headers").is_err());
+        assert!(line_format.to_message("t1", "\tno key").is_err());
+
+        let line_format = LineFormat {
+            key: Some("default".to_string()),
+            value_format: ValueFormat::Json,
+            ..LineFormat::default()
+        };
+        let message = line_format.to_message("t1", "{\"clicks\": 3}").unwrap();
+        assert_eq!(message.key, Some(Bytes::from("default")));
+        assert!(line_format.to_message("t1", "not json").is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use common::errors::WalrsError;
+use common::models::{Topic, TopicMetadata};
+
+use super::{admin_client, join_broker_ids};
+use crate::config::ConnectionConfig;
+
+pub fn create_topic(topic: Topic, connection: ConnectionConfig) {
+    tracing::info!(
+        "Creating topic: {:?} on brokers: {:?}",
+        topic,
+        connection.bootstrap_addresses
+    );
+    match admin_client(connection).create_topic(topic) {
+        Ok(topic) => tracing::info!("Created topic {:?}", topic),
+        Err(e) => tracing::error!("Could not create topic: {}", e),
+    }
+}
+
+pub fn list_topics(connection: ConnectionConfig) {
+    match admin_client(connection).metadata(None) {
+        Ok(mut topics) => {
+            topics.sort_by(|a, b| a.topic.name.cmp(&b.topic.name));
+            println!(
+                "{:<30} {:>10} {:>18} ORDERING",
+                "TOPIC", "PARTITIONS", "REPLICATION-FACTOR"
+            );
+            for topic_metadata in topics {
+                let topic = topic_metadata.topic;
+                println!(
+                    "{:<30} {:>10} {:>18} {}",
+                    topic.name,
+                    topic_metadata.partitions.len(),
+                    optional(topic.replication_factor),
+                    topic
+                        .ordering_mode
+                        .map_or_else(|| "-".to_string(), |mode| format!("{:?}", mode)),
+                );
+            }
+        }
+        Err(e) => tracing::error!("Could not list topics: {}", e),
+    }
+}
+
+pub fn describe_topic(topic_name: String, connection: ConnectionConfig) {
+    match admin_client(connection).metadata(Some(vec![topic_name.clone()])) {
+        Ok(topics) if topics.is_empty() => tracing::error!("Topic {} does not exist", topic_name),
+        Ok(topics) => {
+            for topic_metadata in &topics {
+                let topic = &topic_metadata.topic;
+                println!(
+                    "Topic {}: partitions {}, replication factor {}, batch size {}, \
+                     retention period {}, ordering {:?}, queue size {}, overflow policy {:?}, \
+                     max message bytes {}, timestamp type {:?}, scheduled delivery {}",
+                    topic.name,
+                    optional(topic.num_partitions),
+                    optional(topic.replication_factor),
+                    optional(topic.batch_size),
+                    optional(topic.retention_period),
+                    topic.ordering_mode.unwrap_or_default(),
+                    optional(topic.queue_size),
+                    topic.overflow_policy.unwrap_or_default(),
+                    optional(topic.max_message_bytes),
+                    topic.timestamp_type.unwrap_or_default(),
+                    topic.scheduled_delivery.unwrap_or_default(),
+                );
+            }
+            println!();
+            print_partitions(topics);
+        }
+        Err(e) => tracing::error!("Could not describe topic: {}", e),
+    }
+}
+
+pub fn delete_topic(topic_name: String, connection: ConnectionConfig) {
+    match admin_client(connection).delete_topic(topic_name.clone()) {
+        Ok(()) => tracing::info!("Deleted topic {}", topic_name),
+        Err(WalrsError::UnknownTopic(topic_name)) => {
+            tracing::error!("Topic {} does not exist", topic_name)
+        }
+        Err(e) => tracing::error!("Could not delete topic: {}", e),
+    }
+}
+
+/// Prints the leader, leader epoch, replicas and ISR of every partition of the topics.
+pub(super) fn print_partitions(topics: Vec<TopicMetadata>) {
+    println!(
+        "{:<30} {:>9} {:>6} {:>12} {:<15} ISR",
+        "TOPIC", "PARTITION", "LEADER", "LEADER-EPOCH", "REPLICAS"
+    );
+    for topic in topics {
+        for partition in topic.partitions {
+            println!(
+                "{:<30} {:>9} {:>6} {:>12} {:<15} {}",
+                topic.topic.name,
+                partition.partition_index,
+                partition.leader_id,
+                partition.leader_epoch,
+                join_broker_ids(&partition.replicas),
+                join_broker_ids(&partition.isr)
+            );
+        }
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
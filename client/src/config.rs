@@ -0,0 +1,113 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use common::sasl::SaslCredentials;
+use rustls::ClientConfig;
+
+use crate::connection::{DEFAULT_CLIENT_ID, DEFAULT_KEEPALIVE_INTERVAL};
+
+/// A client configuration which can't work, reported when the client is built instead of by its
+/// first request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid client configuration: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// How every client connects to the cluster, shared by the producer, consumer and admin client
+/// builders.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// `bootstrap.servers`, the brokers asked for the cluster's metadata, any of them will do
+    pub bootstrap_addresses: Vec<String>,
+    /// `client.id`, names the client in the broker's logs
+    pub client_id: String,
+    /// Connections idle for this long are pinged to check the broker is still there.
+    pub keepalive_interval: Duration,
+    /// `request.timeout.ms`, how long a request waits for the broker's answer
+    pub request_timeout: Duration,
+    /// TLS of the connections to `tls://` addresses.
+    pub tls: Option<Arc<ClientConfig>>,
+    /// SASL credentials every new connection authenticates with.
+    pub credentials: Option<SaslCredentials>,
+    /// `client.rack`, fetches go to an in-sync replica in this rack when the leader is in
+    /// another one
+    pub client_rack: Option<String>,
+}
+
+impl ConnectionConfig {
+    /// `bootstrap_servers` are separated by commas.
+    pub fn new(bootstrap_servers: &str) -> Self {
+        ConnectionConfig {
+            bootstrap_addresses: bootstrap_addresses(bootstrap_servers),
+            client_id: DEFAULT_CLIENT_ID.to_string(),
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            request_timeout: Duration::from_secs(30),
+            tls: None,
+            credentials: None,
+            client_rack: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.bootstrap_addresses.is_empty() {
+            return Err(ConfigError("no bootstrap broker given".to_string()));
+        }
+        if self.client_id.is_empty() {
+            return Err(ConfigError("client ID is empty".to_string()));
+        }
+        if self.request_timeout.is_zero() {
+            return Err(ConfigError("request timeout is zero".to_string()));
+        }
+        let tls_address = self
+            .bootstrap_addresses
+            .iter()
+            .find(|address| common::tls::parse_address(address).1);
+        if let (Some(address), None) = (tls_address, &self.tls) {
+            return Err(ConfigError(format!(
+                "{} needs TLS but no CA certificate was given",
+                address
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Brokers separated by commas, as in `--broker-address`.
+fn bootstrap_addresses(addresses: &str) -> Vec<String> {
+    addresses
+        .split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_config_should_reject_unusable_settings() {
+        let config = ConnectionConfig::new("localhost:30002, localhost:30003,");
+        assert_eq!(
+            config.bootstrap_addresses,
+            vec!["localhost:30002", "localhost:30003"]
+        );
+        assert_eq!(config.validate(), Ok(()));
+
+        assert!(ConnectionConfig::new(" , ").validate().is_err());
+        assert!(ConnectionConfig::new("tls://localhost:30002")
+            .validate()
+            .is_err());
+        let config = ConnectionConfig {
+            request_timeout: Duration::ZERO,
+            ..ConnectionConfig::new("localhost:30002")
+        };
+        assert!(config.validate().is_err());
+    }
+}
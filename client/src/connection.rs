@@ -0,0 +1,393 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use bytes::{Bytes, BytesMut};
+use common::{
+    clock::{estimate_skew_millis, is_skew_significant, now_millis},
+    codecs::protocol::{next_correlation_id, Request, Response, ResponseCodec},
+    models::{BrokerResponse, TopicCommand},
+    sasl::{SaslClient, SaslCredentials},
+};
+use rustls::{ClientConnection, StreamOwned};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::config::ConnectionConfig;
+
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Client ID sent in the header of every request, like Kafka's `client.id`.
+pub const DEFAULT_CLIENT_ID: &str = "walrs-client";
+/// A broker which does not answer a ping within this time is treated as gone.
+pub const KEEPALIVE_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A blocking connection to a broker, encrypted for `tls://` addresses.
+enum BrokerStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl BrokerStream {
+    /// Connects to the broker at `address` and authenticates when credentials are set.
+    fn connect(address: &str, config: &ConnectionConfig) -> io::Result<Self> {
+        let mut stream = BrokerStream::open(address, config)?;
+        if let Some(credentials) = &config.credentials {
+            authenticate(&mut stream, &config.client_id, credentials)?;
+        }
+        Ok(stream)
+    }
+
+    fn open(address: &str, config: &ConnectionConfig) -> io::Result<Self> {
+        let (host_port, use_tls) = common::tls::parse_address(address);
+        let stream = connect_within(host_port, config.request_timeout)?;
+        stream.set_write_timeout(Some(config.request_timeout))?;
+        if !use_tls {
+            return Ok(BrokerStream::Plain(stream));
+        }
+        let tls = config.tls.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no TLS configured to connect to {}", address),
+            )
+        })?;
+        let connection = ClientConnection::new(tls.clone(), common::tls::server_name(host_port)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(BrokerStream::Tls(Box::new(StreamOwned::new(
+            connection, stream,
+        ))))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            BrokerStream::Plain(stream) => stream.set_read_timeout(timeout),
+            BrokerStream::Tls(stream) => stream.get_ref().set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for BrokerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BrokerStream::Plain(stream) => stream.read(buf),
+            BrokerStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for BrokerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BrokerStream::Plain(stream) => stream.write(buf),
+            BrokerStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BrokerStream::Plain(stream) => stream.flush(),
+            BrokerStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A connection to a broker which is checked with a keepalive ping once it has been idle for
+/// `keepalive_interval`. Connections silently dropped by a NAT or firewall are detected by the
+/// failing ping and re-established before the next request is sent on them.
+pub struct BrokerConnection {
+    config: ConnectionConfig,
+    /// Address of the bootstrap broker connected to.
+    broker_address: String,
+    stream: Option<BrokerStream>,
+    last_activity: Instant,
+}
+
+impl BrokerConnection {
+    /// Connects to the first of the bootstrap brokers which can be reached.
+    pub fn connect(config: ConnectionConfig) -> io::Result<Self> {
+        let mut connection = BrokerConnection {
+            config,
+            broker_address: String::new(),
+            stream: None,
+            last_activity: Instant::now(),
+        };
+        connection.reconnect()?;
+        Ok(connection)
+    }
+
+    /// Pings the broker if the connection has been idle for longer than the keepalive interval
+    /// and reconnects if the ping does not get an answer.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        if self.stream.is_some() && self.last_activity.elapsed() < self.config.keepalive_interval {
+            return Ok(());
+        }
+        if let Err(e) = self.ping() {
+            tracing::warn!(
+                "Keepalive to {} failed with {:?}, reconnecting",
+                self.broker_address,
+                e
+            );
+            self.reconnect()?;
+        }
+        Ok(())
+    }
+
+    /// Sends a request and waits for its response. The connection is kept for the next request
+    /// unless the exchange failed, then the next request reconnects.
+    pub fn request(&mut self, command: TopicCommand) -> io::Result<BrokerResponse> {
+        self.keep_alive()?;
+        if self.stream.is_none() {
+            self.reconnect()?;
+        }
+        let stream = self.stream.as_mut().unwrap();
+        stream.set_read_timeout(Some(self.config.request_timeout))?;
+        let response = exchange(stream, &self.config.client_id, command).map_err(|e| {
+            match e.kind() {
+                // read timeouts surface as `WouldBlock` on unix
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "no answer within {:?}, the request may still be handled",
+                        self.config.request_timeout
+                    ),
+                ),
+                _ => e,
+            }
+        });
+        match response {
+            Ok(_) => self.last_activity = Instant::now(),
+            Err(_) => self.stream = None,
+        }
+        response
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, "not connected")),
+        };
+        stream.set_read_timeout(Some(KEEPALIVE_RESPONSE_TIMEOUT))?;
+        let sent_at_millis = now_millis();
+        match exchange(stream, &self.config.client_id, TopicCommand::Ping)? {
+            BrokerResponse::Pong { broker_time_millis } => {
+                let skew_millis =
+                    estimate_skew_millis(sent_at_millis, now_millis(), broker_time_millis);
+                if is_skew_significant(skew_millis) {
+                    tracing::warn!(
+                        "Clock of broker {} is {} ms away from the local clock, record timestamps will be off",
+                        self.broker_address,
+                        skew_millis
+                    );
+                }
+                self.last_activity = Instant::now();
+                Ok(())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected response to keepalive",
+            )),
+        }
+    }
+
+    /// Connects to the first of the bootstrap brokers which can be reached.
+    fn reconnect(&mut self) -> io::Result<()> {
+        let mut error = io::Error::new(io::ErrorKind::InvalidInput, "no broker address");
+        for broker_address in &self.config.bootstrap_addresses {
+            match BrokerStream::connect(broker_address, &self.config) {
+                Ok(stream) => {
+                    self.broker_address = broker_address.clone();
+                    self.stream = Some(stream);
+                    self.last_activity = Instant::now();
+                    return Ok(());
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Blocking counterpart of `common::sasl::authenticate`.
+fn authenticate(
+    stream: &mut BrokerStream,
+    client_id: &str,
+    credentials: &SaslCredentials,
+) -> io::Result<()> {
+    let permission_denied = |error: String| io::Error::new(io::ErrorKind::PermissionDenied, error);
+    let mechanism = credentials.mechanism.name().to_string();
+    match exchange(stream, client_id, TopicCommand::SaslHandshake { mechanism })? {
+        BrokerResponse::SaslHandshake { enabled_mechanisms }
+            if enabled_mechanisms.contains(&credentials.mechanism.name().to_string()) => {}
+        BrokerResponse::SaslHandshake { enabled_mechanisms } => {
+            return Err(permission_denied(format!(
+                "broker does not enable {}, only {:?}",
+                credentials.mechanism, enabled_mechanisms
+            )))
+        }
+        response => return Err(unexpected_response(response)),
+    }
+    let mut client = SaslClient::new(credentials.clone());
+    let mut auth_bytes = client.initial_response();
+    loop {
+        let command = TopicCommand::SaslAuthenticate { auth_bytes };
+        match exchange(stream, client_id, command)? {
+            BrokerResponse::SaslAuthenticated {
+                auth_bytes: challenge,
+            } => match client.respond(&challenge).map_err(permission_denied)? {
+                Some(response) => auth_bytes = response,
+                None => return Ok(()),
+            },
+            BrokerResponse::SaslAuthenticationFailed { error } => {
+                return Err(permission_denied(error))
+            }
+            response => return Err(unexpected_response(response)),
+        }
+    }
+}
+
+/// Connects to the first of the resolved addresses which accepts within `timeout`.
+fn connect_within(host_port: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} did not resolve to an address", host_port),
+    );
+    for address in host_port.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+fn unexpected_response(response: BrokerResponse) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected response to authentication: {:?}", response),
+    )
+}
+
+/// Reads one response frame from the broker, `None` when it closed the connection first.
+fn read_response(stream: &mut impl Read) -> io::Result<Option<Response>> {
+    let mut response_codec = ResponseCodec::default();
+    let mut received = BytesMut::new();
+    let mut read_buffer = [0; 4096];
+    loop {
+        if let Some(response) = response_codec.decode(&mut received)? {
+            return Ok(Some(response));
+        }
+        let read = stream.read(&mut read_buffer)?;
+        if read == 0 && received.is_empty() {
+            return Ok(None);
+        }
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed within a response",
+            ));
+        }
+        received.extend_from_slice(&read_buffer[..read]);
+    }
+}
+
+fn write_request(stream: &mut impl Write, request: Request) -> io::Result<()> {
+    let mut encoded_request = BytesMut::new();
+    ResponseCodec::default().encode(request, &mut encoded_request)?;
+    stream.write_all(&encoded_request)?;
+    stream.flush()
+}
+
+/// Sends a request and reads the response with its correlation ID.
+fn exchange(
+    stream: &mut BrokerStream,
+    client_id: &str,
+    command: TopicCommand,
+) -> io::Result<BrokerResponse> {
+    let correlation_id = next_correlation_id();
+    write_request(
+        stream,
+        Request::new(correlation_id, client_id, command, Bytes::new()),
+    )?;
+    match read_response(stream)? {
+        Some(response) if response.correlation_id == correlation_id => {
+            if response.throttle_time_ms > 0 {
+                tracing::info!(
+                    "Broker throttled the request for {} ms, the client exceeds a quota",
+                    response.throttle_time_ms
+                );
+            }
+            Ok(response.response)
+        }
+        Some(response) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "expected the response to request {} but got one to {}",
+                correlation_id, response.correlation_id
+            ),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed without an answer",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use common::codecs::protocol::RequestCodec;
+
+    use super::*;
+
+    #[test]
+    fn test_keep_alive_should_reconnect_when_broker_dropped_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broker_address = listener.local_addr().unwrap().to_string();
+
+        let broker = thread::spawn(move || {
+            // the first connection is dropped without answering, like an expired NAT mapping
+            let (first_connection, _) = listener.accept().unwrap();
+            drop(first_connection);
+
+            let (mut second_connection, _) = listener.accept().unwrap();
+            let mut request_codec = RequestCodec::default();
+            let mut received = BytesMut::new();
+            let ping = loop {
+                if let Some(request) = request_codec.decode(&mut received).unwrap() {
+                    break request;
+                }
+                let mut read_buffer = [0; 1024];
+                let read = second_connection.read(&mut read_buffer).unwrap();
+                received.extend_from_slice(&read_buffer[..read]);
+            };
+            assert_eq!(ping.command, TopicCommand::Ping);
+            assert_eq!(ping.header.client_id, DEFAULT_CLIENT_ID);
+            let mut pong = BytesMut::new();
+            RequestCodec::default()
+                .encode(
+                    Response {
+                        correlation_id: ping.header.correlation_id,
+                        throttle_time_ms: 0,
+                        response: BrokerResponse::Pong {
+                            broker_time_millis: now_millis(),
+                        },
+                    },
+                    &mut pong,
+                )
+                .unwrap();
+            second_connection.write_all(&pong).unwrap();
+        });
+
+        let mut connection = BrokerConnection::connect(ConnectionConfig {
+            keepalive_interval: Duration::from_millis(0),
+            ..ConnectionConfig::new(&broker_address)
+        })
+        .unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        connection.keep_alive().unwrap();
+        connection.keep_alive().unwrap();
+        broker.join().unwrap();
+    }
+}
@@ -0,0 +1,461 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use common::{
+    codecs::protocol::{next_correlation_id, Request, Response, ResponseCodec},
+    errors::{ProduceError, WalrsError},
+    models::{BrokerResponse, TopicCommand},
+    tls::BrokerStream,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    sync::oneshot,
+    time::{self, Instant},
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::config::ConnectionConfig;
+use crate::connection::KEEPALIVE_RESPONSE_TIMEOUT;
+
+/// Wait before connecting again to a broker which could not be reached, like Kafka's
+/// `reconnect.backoff.ms`, doubled for every further failed attempt.
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(50);
+/// `reconnect.backoff.max.ms`
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// Requests waiting for their response by correlation ID, `None` once the connection broke.
+type InFlightRequests = Arc<Mutex<Option<HashMap<u32, oneshot::Sender<Response>>>>>;
+
+/// Persistent connections of a client, one per broker. A connection which broke or failed its
+/// health check is replaced by the next request to its broker. After a failed connection attempt
+/// the broker is not connected to again until a backoff passed, which doubles with every failed
+/// attempt; requests in the meantime fail right away with the retriable `BrokerUnavailable`.
+pub struct ConnectionPool {
+    config: ConnectionConfig,
+    brokers: Mutex<HashMap<String, Arc<tokio::sync::Mutex<BrokerSlot>>>>,
+}
+
+#[derive(Default)]
+struct BrokerSlot {
+    connection: Option<Arc<MultiplexedConnection>>,
+    failed_attempts: u32,
+    reconnect_at: Option<Instant>,
+}
+
+impl ConnectionPool {
+    pub fn new(config: ConnectionConfig) -> Self {
+        ConnectionPool {
+            config,
+            brokers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The connection to the broker, connects unless there is a healthy one. Requests to other
+    /// brokers do not wait for the connection attempt.
+    pub async fn get(
+        &self,
+        broker_address: &str,
+    ) -> Result<Arc<MultiplexedConnection>, WalrsError> {
+        let slot = self
+            .brokers
+            .lock()
+            .unwrap()
+            .entry(broker_address.to_string())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some(connection) = &slot.connection {
+            if !connection.is_closed() {
+                return Ok(connection.clone());
+            }
+        }
+        if let Some(reconnect_at) = slot.reconnect_at {
+            let now = Instant::now();
+            if now < reconnect_at {
+                return Err(WalrsError::BrokerUnavailable(format!(
+                    "{}: reconnecting in {:?}",
+                    broker_address,
+                    reconnect_at - now
+                )));
+            }
+        }
+        let connected = MultiplexedConnection::connect(broker_address, &self.config).await;
+        match connected {
+            Ok(connection) => {
+                slot.failed_attempts = 0;
+                slot.reconnect_at = None;
+                slot.connection = Some(connection.clone());
+                Ok(connection)
+            }
+            Err(e) => {
+                let backoff = reconnect_backoff(slot.failed_attempts);
+                tracing::warn!(
+                    "Could not connect to {}, retrying in {:?}: {}",
+                    broker_address,
+                    backoff,
+                    e
+                );
+                slot.failed_attempts += 1;
+                slot.reconnect_at = Some(Instant::now() + backoff);
+                slot.connection = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Backoff after `failed_attempts` earlier failed attempts to connect.
+fn reconnect_backoff(failed_attempts: u32) -> Duration {
+    RECONNECT_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failed_attempts))
+        .min(RECONNECT_BACKOFF_MAX)
+}
+
+/// A connection to a broker shared by every request to it. Requests are written as they come
+/// and a task hands the broker's responses to the requests waiting for them, matched by
+/// correlation ID, until the connection breaks or is dropped.
+pub struct MultiplexedConnection {
+    broker_address: String,
+    client_id: String,
+    /// How long a request waits for its response.
+    request_timeout: Duration,
+    writer: tokio::sync::Mutex<WriteHalf<BrokerStream>>,
+    in_flight: InFlightRequests,
+    /// When the broker last answered, connections idle for the keepalive interval are pinged.
+    last_received: Arc<Mutex<Instant>>,
+    /// Stops the reading task once the connection is dropped.
+    _dropped_tx: oneshot::Sender<()>,
+}
+
+impl MultiplexedConnection {
+    /// Connects to the broker and authenticates when credentials are set. Connections idle for
+    /// the keepalive interval are pinged to check the broker is still there.
+    async fn connect(
+        broker_address: &str,
+        config: &ConnectionConfig,
+    ) -> Result<Arc<Self>, WalrsError> {
+        let unavailable =
+            |e: io::Error| WalrsError::BrokerUnavailable(format!("{}: {}", broker_address, e));
+        let connecting = async {
+            let mut stream = common::tls::connect(broker_address, config.tls.as_ref())
+                .await
+                .map_err(unavailable)?;
+            if let Some(credentials) = &config.credentials {
+                common::sasl::authenticate(&mut stream, &config.client_id, credentials)
+                    .await
+                    .map_err(|e| match e.kind() {
+                        io::ErrorKind::PermissionDenied => {
+                            WalrsError::AuthenticationFailed(e.to_string())
+                        }
+                        _ => unavailable(e),
+                    })?;
+            }
+            Ok::<_, WalrsError>(stream)
+        };
+        let stream = time::timeout(config.request_timeout, connecting)
+            .await
+            .map_err(|_| {
+                unavailable(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connecting timed out",
+                ))
+            })??;
+        let (reader, writer) = tokio::io::split(stream);
+        let (dropped_tx, dropped_rx) = oneshot::channel();
+        let connection = Arc::new(MultiplexedConnection {
+            broker_address: broker_address.to_string(),
+            client_id: config.client_id.clone(),
+            request_timeout: config.request_timeout,
+            writer: tokio::sync::Mutex::new(writer),
+            in_flight: Arc::new(Mutex::new(Some(HashMap::new()))),
+            last_received: Arc::new(Mutex::new(Instant::now())),
+            _dropped_tx: dropped_tx,
+        });
+        tokio::spawn(read_responses(
+            reader,
+            connection.in_flight.clone(),
+            connection.last_received.clone(),
+            broker_address.to_string(),
+            dropped_rx,
+        ));
+        tokio::spawn(check_health(
+            Arc::downgrade(&connection),
+            config.keepalive_interval,
+        ));
+        Ok(connection)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.in_flight.lock().unwrap().is_none()
+    }
+
+    /// Fails the requests waiting for a response, the next request connects again.
+    fn close(&self) {
+        self.in_flight.lock().unwrap().take();
+    }
+
+    /// Sends `command` followed by `body` and waits for the broker's response, failing with the
+    /// retriable `TimedOut` when it does not come within the request timeout. Callers may give
+    /// up on a request earlier by dropping it, e.g. with `tokio::time::timeout`, the response is
+    /// then dropped when it comes.
+    pub async fn request(
+        &self,
+        command: TopicCommand,
+        body: Bytes,
+    ) -> Result<Response, WalrsError> {
+        let correlation_id = next_correlation_id();
+        // registered before the request is sent so a quick response is not missed
+        let (response_tx, response_rx) = oneshot::channel();
+        match self.in_flight.lock().unwrap().as_mut() {
+            Some(in_flight) => in_flight.insert(correlation_id, response_tx),
+            None => return Err(self.unavailable("connection closed")),
+        };
+        let _pending = PendingResponse {
+            in_flight: &self.in_flight,
+            correlation_id,
+        };
+        let exchange = async {
+            self.write(Request::new(correlation_id, &self.client_id, command, body))
+                .await?;
+            response_rx
+                .await
+                .map_err(|_| self.unavailable("connection closed without an answer"))
+        };
+        match time::timeout(self.request_timeout, exchange).await {
+            Ok(response) => response,
+            Err(_) => Err(WalrsError::TimedOut(format!(
+                "{}: no answer within {:?}",
+                self.broker_address, self.request_timeout
+            ))),
+        }
+    }
+
+    /// Sends `command` followed by `body` for requests the broker does not answer.
+    pub async fn send(&self, command: TopicCommand, body: Bytes) -> Result<(), WalrsError> {
+        let request = Request::new(next_correlation_id(), &self.client_id, command, body);
+        self.write(request).await
+    }
+
+    async fn write(&self, request: Request) -> Result<(), WalrsError> {
+        let mut encoded_request = BytesMut::new();
+        ResponseCodec::default()
+            .encode(request, &mut encoded_request)
+            .map_err(|e| ProduceError::InvalidBatch(e.to_string()))?;
+        let mut writer = self.writer.lock().await;
+        let mut write_guard = WriteGuard {
+            connection: self,
+            written: false,
+        };
+        writer
+            .write_all(&encoded_request)
+            .await
+            .map_err(|e| self.unavailable(&e.to_string()))?;
+        write_guard.written = true;
+        Ok(())
+    }
+
+    fn unavailable(&self, reason: &str) -> WalrsError {
+        WalrsError::BrokerUnavailable(format!("{}: {}", self.broker_address, reason))
+    }
+}
+
+/// Forgets a request once its caller stopped waiting for the response, e.g. as it timed out.
+struct PendingResponse<'a> {
+    in_flight: &'a InFlightRequests,
+    correlation_id: u32,
+}
+
+impl Drop for PendingResponse<'_> {
+    fn drop(&mut self) {
+        if let Some(in_flight) = self.in_flight.lock().unwrap().as_mut() {
+            in_flight.remove(&self.correlation_id);
+        }
+    }
+}
+
+/// Closes the connection unless the request was written completely, as a request cut off by an
+/// error or a cancelled write would garble the ones after it.
+struct WriteGuard<'a> {
+    connection: &'a MultiplexedConnection,
+    written: bool,
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if !self.written {
+            self.connection.close();
+        }
+    }
+}
+
+async fn read_responses(
+    mut reader: ReadHalf<BrokerStream>,
+    in_flight: InFlightRequests,
+    last_received: Arc<Mutex<Instant>>,
+    broker_address: String,
+    mut dropped_rx: oneshot::Receiver<()>,
+) {
+    let mut response_codec = ResponseCodec::default();
+    let mut received = BytesMut::new();
+    loop {
+        match response_codec.decode(&mut received) {
+            Ok(Some(response)) => {
+                *last_received.lock().unwrap() = Instant::now();
+                let response_tx = in_flight
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .and_then(|in_flight| in_flight.remove(&response.correlation_id));
+                match response_tx {
+                    Some(response_tx) => {
+                        let _ = response_tx.send(response);
+                    }
+                    None => tracing::warn!(
+                        "Dropping response to unknown request {} from {}",
+                        response.correlation_id,
+                        broker_address
+                    ),
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!("Could not decode response from {}: {}", broker_address, e);
+                break;
+            }
+        }
+        tokio::select! {
+            read = reader.read_buf(&mut received) => match read {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Connection to {} broke: {}", broker_address, e);
+                    break;
+                }
+            },
+            _ = &mut dropped_rx => break,
+        }
+    }
+    // dropping the senders fails the requests still waiting
+    in_flight.lock().unwrap().take();
+}
+
+/// Pings the broker whenever the connection was idle for `keepalive_interval` and closes the
+/// connection if the broker does not answer in time, so requests go to a new connection instead
+/// of waiting on a broker which is gone.
+async fn check_health(weak_connection: Weak<MultiplexedConnection>, keepalive_interval: Duration) {
+    loop {
+        let idle_at = match weak_connection.upgrade() {
+            Some(connection) if !connection.is_closed() => {
+                *connection.last_received.lock().unwrap() + keepalive_interval
+            }
+            _ => return,
+        };
+        time::sleep_until(idle_at).await;
+        let Some(connection) = weak_connection.upgrade() else {
+            return;
+        };
+        if connection.last_received.lock().unwrap().elapsed() < keepalive_interval {
+            continue;
+        }
+        let ping = connection.request(TopicCommand::Ping, Bytes::new());
+        match time::timeout(KEEPALIVE_RESPONSE_TIMEOUT, ping).await {
+            Ok(Ok(Response {
+                response: BrokerResponse::Pong { .. },
+                ..
+            })) => {}
+            Ok(Ok(response)) => {
+                tracing::warn!(
+                    "Broker {} answered a ping with {:?}, closing the connection",
+                    connection.broker_address,
+                    response.response
+                );
+                connection.close();
+            }
+            Ok(Err(_)) => {}
+            Err(_) => {
+                tracing::warn!(
+                    "Broker {} did not answer a ping in {:?}, closing the connection",
+                    connection.broker_address,
+                    KEEPALIVE_RESPONSE_TIMEOUT
+                );
+                connection.close();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pool_should_back_off_from_unreachable_brokers() {
+        let pool = ConnectionPool::new(ConnectionConfig::new("127.0.0.1:1"));
+
+        // nothing listens on the address
+        let error = pool.get("127.0.0.1:1").await.err().unwrap();
+        assert!(error.is_retriable());
+        let Some(WalrsError::BrokerUnavailable(reason)) = pool.get("127.0.0.1:1").await.err()
+        else {
+            panic!("the retry within the backoff should fail as unavailable");
+        };
+        assert!(reason.contains("reconnecting in"));
+
+        assert_eq!(reconnect_backoff(0), RECONNECT_BACKOFF);
+        assert_eq!(reconnect_backoff(1), RECONNECT_BACKOFF * 2);
+        assert_eq!(reconnect_backoff(30), RECONNECT_BACKOFF_MAX);
+    }
+
+    #[tokio::test]
+    async fn test_request_should_time_out_and_forget_the_request() {
+        // accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let broker_address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut streams = vec![];
+            loop {
+                streams.push(listener.accept().await.unwrap().0);
+            }
+        });
+        let pool = ConnectionPool::new(ConnectionConfig {
+            request_timeout: Duration::from_millis(50),
+            ..ConnectionConfig::new(&broker_address)
+        });
+        let connection = pool.get(&broker_address).await.unwrap();
+
+        let error = connection
+            .request(TopicCommand::Ping, Bytes::new())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(error, WalrsError::TimedOut(_)));
+        assert!(error.is_retriable());
+        assert!(connection
+            .in_flight
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .is_empty());
+
+        // callers giving up earlier leave nothing behind either
+        let ping = connection.request(TopicCommand::Ping, Bytes::new());
+        assert!(time::timeout(Duration::from_millis(10), ping)
+            .await
+            .is_err());
+        assert!(connection
+            .in_flight
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .is_empty());
+        assert!(!connection.is_closed());
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use common::{
+    clock::now_millis,
+    errors::WalrsError,
+    models::{Message, OffsetResetPolicy, TopicPartition},
+};
+use futures::Stream;
+use tokio::sync::mpsc;
+
+use crate::{
+    client::{FetchSessions, FetchedRecords, WalrsClient},
+    config::{ConfigError, ConnectionConfig},
+};
+
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    pub connection: ConnectionConfig,
+    /// `auto.offset.reset`, where partitions without an offset to start from are read
+    pub auto_offset_reset: OffsetResetPolicy,
+    /// `max.poll.records`, records fetched from a partition at once, also the number of records
+    /// buffered ahead of the stream
+    pub max_poll_records: u32,
+    /// Pause before fetching again after no partition had new records or a fetch failed with a
+    /// retriable error.
+    pub fetch_backoff: Duration,
+}
+
+impl ConsumerConfig {
+    pub fn new(connection: ConnectionConfig) -> Self {
+        ConsumerConfig {
+            connection,
+            auto_offset_reset: OffsetResetPolicy::Latest,
+            max_poll_records: 500,
+            fetch_backoff: Duration::from_millis(500),
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.connection.validate()?;
+        if self.max_poll_records == 0 {
+            return Err(ConfigError("max poll records is zero".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Builds a consumer, checking its settings work before it starts.
+pub struct ConsumerBuilder {
+    config: ConsumerConfig,
+}
+
+impl ConsumerBuilder {
+    pub fn new(connection: ConnectionConfig) -> Self {
+        ConsumerBuilder {
+            config: ConsumerConfig::new(connection),
+        }
+    }
+
+    pub fn auto_offset_reset(mut self, auto_offset_reset: OffsetResetPolicy) -> Self {
+        self.config.auto_offset_reset = auto_offset_reset;
+        self
+    }
+
+    pub fn max_poll_records(mut self, max_poll_records: u32) -> Self {
+        self.config.max_poll_records = max_poll_records;
+        self
+    }
+
+    pub fn fetch_backoff(mut self, fetch_backoff: Duration) -> Self {
+        self.config.fetch_backoff = fetch_backoff;
+        self
+    }
+
+    /// Reads every partition of the topic. Must be called within a tokio runtime.
+    pub fn subscribe(self, topic_name: String) -> Result<Consumer, ConfigError> {
+        self.config.validate()?;
+        Ok(Consumer::start(self.config, Assignment::Topic(topic_name)))
+    }
+
+    /// Reads the partitions from the given offsets. Must be called within a tokio runtime.
+    pub fn assign(
+        self,
+        positions: BTreeMap<TopicPartition, Option<u64>>,
+    ) -> Result<Consumer, ConfigError> {
+        self.config.validate()?;
+        Ok(Consumer::start(
+            self.config,
+            Assignment::Partitions(positions),
+        ))
+    }
+}
+
+/// A record read by a `Consumer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub topic_partition: TopicPartition,
+    pub offset: u64,
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsumeError {
+    /// The partition's offset was not in its log any more or not yet. The consumer goes on from
+    /// where `auto_offset_reset` points, records in between may have been skipped.
+    OffsetOutOfRange {
+        topic_partition: TopicPartition,
+        offset: u64,
+    },
+    /// Fetching failed for good, the stream ends after this error.
+    Fetch(WalrsError),
+}
+
+impl fmt::Display for ConsumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsumeError::OffsetOutOfRange {
+                topic_partition,
+                offset,
+            } => write!(
+                f,
+                "offset {} of {:?} is out of range, resetting it",
+                offset, topic_partition
+            ),
+            ConsumeError::Fetch(error) => write!(f, "could not fetch records: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ConsumeError {}
+
+/// What a consumer reads.
+enum Assignment {
+    /// Every partition of the topic, starting from `auto_offset_reset`.
+    Topic(String),
+    /// The partitions starting at their offset, `None` starts from `auto_offset_reset`.
+    Partitions(BTreeMap<TopicPartition, Option<u64>>),
+}
+
+/// Reads partitions without a group as a stream of records, in offset order within every
+/// partition. A task fetches the partitions through fetch sessions with their leaders and
+/// buffers the records until the stream is polled, it retries retriable errors and stops once
+/// the consumer is dropped.
+pub struct Consumer {
+    records_rx: mpsc::Receiver<Result<Record, ConsumeError>>,
+}
+
+impl Consumer {
+    fn start(config: ConsumerConfig, assignment: Assignment) -> Self {
+        let (records_tx, records_rx) = mpsc::channel(config.max_poll_records.max(1) as usize);
+        tokio::spawn(fetch_records(config, assignment, records_tx));
+        Consumer { records_rx }
+    }
+}
+
+impl Stream for Consumer {
+    type Item = Result<Record, ConsumeError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.records_rx.poll_recv(cx)
+    }
+}
+
+async fn fetch_records(
+    config: ConsumerConfig,
+    assignment: Assignment,
+    records_tx: mpsc::Sender<Result<Record, ConsumeError>>,
+) {
+    let client = WalrsClient::new(config.connection.clone());
+    let mut positions = match assignment {
+        Assignment::Partitions(positions) => positions,
+        Assignment::Topic(topic_name) => match partitions(&client, &topic_name).await {
+            Ok(positions) => positions,
+            Err(e) => {
+                let _ = records_tx.send(Err(ConsumeError::Fetch(e))).await;
+                return;
+            }
+        },
+    };
+    let mut fetch_sessions = FetchSessions::default();
+    loop {
+        let mut fetched_any = false;
+        let fetched = client
+            .fetch_partitions(
+                &mut fetch_sessions,
+                &positions,
+                config.auto_offset_reset,
+                config.max_poll_records,
+            )
+            .await;
+        for (topic_partition, fetched) in fetched {
+            let Some(position) = positions.get_mut(&topic_partition) else {
+                continue;
+            };
+            let FetchedRecords {
+                base_offset,
+                records,
+                ..
+            } = match fetched {
+                Ok(fetched) => fetched,
+                Err(WalrsError::OffsetOutOfRange {
+                    topic_partition,
+                    offset,
+                }) => {
+                    *position = None;
+                    let error = ConsumeError::OffsetOutOfRange {
+                        topic_partition,
+                        offset,
+                    };
+                    if records_tx.send(Err(error)).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(e) if e.is_retriable() => {
+                    tracing::warn!("Could not fetch {:?}, retrying: {}", topic_partition, e);
+                    continue;
+                }
+                Err(e) => {
+                    let _ = records_tx.send(Err(ConsumeError::Fetch(e))).await;
+                    return;
+                }
+            };
+            *position = Some(base_offset + records.len() as u64);
+            fetched_any |= !records.is_empty();
+            let now = now_millis();
+            for (offset, message) in (base_offset..).zip(records) {
+                // expired records are skipped, the consumer moves past them all the same
+                if message.is_expired(now) {
+                    tracing::debug!(
+                        "Skipping expired offset {} of {:?}",
+                        offset,
+                        topic_partition
+                    );
+                    continue;
+                }
+                let record = Record {
+                    topic_partition: topic_partition.clone(),
+                    offset,
+                    message,
+                };
+                // the consumer was dropped
+                if records_tx.send(Ok(record)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        if !fetched_any {
+            tokio::select! {
+                _ = tokio::time::sleep(config.fetch_backoff) => {}
+                _ = records_tx.closed() => return,
+            }
+        }
+    }
+}
+
+/// Every partition of the topic, without an offset to start from.
+pub async fn partitions(
+    client: &WalrsClient,
+    topic_name: &str,
+) -> Result<BTreeMap<TopicPartition, Option<u64>>, WalrsError> {
+    let topics = client.metadata(Some(vec![topic_name.to_string()])).await?;
+    let topic_metadata = topics
+        .first()
+        .ok_or_else(|| WalrsError::UnknownTopic(topic_name.to_string()))?;
+    Ok(topic_metadata
+        .partitions
+        .iter()
+        .map(|partition| {
+            (
+                TopicPartition::new(topic_name.to_string(), partition.partition_index),
+                None,
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+    use common::models::{
+        Batch, BrokerResponse, FetchedBatch, PartitionMetadata, RecordBatch, Topic, TopicCommand,
+        TopicMetadata, TTL_HEADER,
+    };
+    use futures::StreamExt;
+
+    use crate::client::tests::start_fake_broker;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_consumer_should_stream_records_and_reset_out_of_range_offsets() {
+        let fetched_offsets = Arc::new(Mutex::new(vec![]));
+        let recorded_offsets = fetched_offsets.clone();
+        let broker_address = start_fake_broker(move |command| match command {
+            TopicCommand::Metadata { .. } => BrokerResponse::Metadata {
+                brokers: vec![],
+                controller_id: None,
+                topics: vec![TopicMetadata {
+                    topic: Topic::new("t1".to_string(), Some(1), None, None, None, None),
+                    partitions: vec![PartitionMetadata {
+                        partition_index: 0,
+                        leader_id: 1,
+                        leader_epoch: 1,
+                        replicas: vec![1],
+                        isr: vec![1],
+                    }],
+                }],
+            },
+            TopicCommand::SessionFetch(request) => {
+                // the session continues partitions whose offset the consumer did not move
+                let responses = request
+                    .partitions
+                    .into_iter()
+                    .map(|(topic_partition, offset)| {
+                        recorded_offsets.lock().unwrap().push(offset);
+                        let response = match offset {
+                            // the log starts at offset 5
+                            None => BrokerResponse::Records {
+                                topic_partition: topic_partition.clone(),
+                                base_offset: 5,
+                                batches: vec![FetchedBatch {
+                                    base_offset: 5,
+                                    batch: RecordBatch::new(Batch {
+                                        records: vec![
+                                            Message::new(Bytes::from_static(b"v5"), None, None),
+                                            Message::new(Bytes::from_static(b"v6"), None, None),
+                                        ],
+                                        ..Batch::default()
+                                    })
+                                    .unwrap(),
+                                }],
+                                log_end_offset: 7,
+                                high_watermark: 7,
+                                leader_epoch: 1,
+                            },
+                            Some(offset) => BrokerResponse::OffsetOutOfRange {
+                                topic_partition: topic_partition.clone(),
+                                offset,
+                                log_start_offset: 5,
+                                log_end_offset: 7,
+                            },
+                        };
+                        (topic_partition, response)
+                    })
+                    .collect();
+                BrokerResponse::SessionRecords {
+                    session_id: 1,
+                    responses,
+                }
+            }
+            _ => unreachable!(),
+        })
+        .await;
+        let topic_partition = TopicPartition::new("t1".to_string(), 0);
+        let consumer = ConsumerBuilder::new(ConnectionConfig::new(&broker_address))
+            .assign(BTreeMap::from([(topic_partition.clone(), Some(2))]))
+            .unwrap();
+
+        let consumed: Vec<_> = consumer.take(3).collect().await;
+        assert_eq!(
+            consumed[0],
+            Err(ConsumeError::OffsetOutOfRange {
+                topic_partition: topic_partition.clone(),
+                offset: 2,
+            })
+        );
+        let offsets: Vec<u64> = consumed[1..]
+            .iter()
+            .map(|record| record.as_ref().unwrap().offset)
+            .collect();
+        assert_eq!(offsets, vec![5, 6]);
+        assert_eq!(
+            consumed[2].as_ref().unwrap().message.payload,
+            Some(Bytes::from_static(b"v6"))
+        );
+        assert_eq!(fetched_offsets.lock().unwrap()[..2], [Some(2), None]);
+    }
+
+    #[tokio::test]
+    async fn test_consumer_should_skip_expired_records() {
+        let mut expired = Message::new(Bytes::from_static(b"v0"), None, Some(1_000));
+        expired.headers = vec![(TTL_HEADER.to_string(), Bytes::from_static(b"60000"))];
+        let mut live = Message::new(Bytes::from_static(b"v1"), None, None);
+        live.headers = vec![(TTL_HEADER.to_string(), Bytes::from_static(b"60000"))];
+        let batch = RecordBatch::new(Batch {
+            records: vec![expired, live],
+            ..Batch::default()
+        })
+        .unwrap();
+        let broker_address = start_fake_broker(move |command| match command {
+            // the broker leads every partition
+            TopicCommand::Metadata { .. } => BrokerResponse::Metadata {
+                brokers: vec![],
+                controller_id: None,
+                topics: vec![],
+            },
+            TopicCommand::SessionFetch(request) => BrokerResponse::SessionRecords {
+                session_id: 1,
+                responses: request
+                    .partitions
+                    .into_iter()
+                    .map(|(topic_partition, _)| {
+                        let records = BrokerResponse::Records {
+                            topic_partition: topic_partition.clone(),
+                            base_offset: 0,
+                            batches: vec![FetchedBatch {
+                                base_offset: 0,
+                                batch: batch.clone(),
+                            }],
+                            log_end_offset: 2,
+                            high_watermark: 2,
+                            leader_epoch: 1,
+                        };
+                        (topic_partition, records)
+                    })
+                    .collect(),
+            },
+            _ => unreachable!(),
+        })
+        .await;
+        let topic_partition = TopicPartition::new("t1".to_string(), 0);
+        let mut consumer = ConsumerBuilder::new(ConnectionConfig::new(&broker_address))
+            .assign(BTreeMap::from([(topic_partition, Some(0))]))
+            .unwrap();
+
+        let record = consumer.next().await.unwrap().unwrap();
+        assert_eq!(record.offset, 1);
+        assert_eq!(record.message.payload, Some(Bytes::from_static(b"v1")));
+    }
+}
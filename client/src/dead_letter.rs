@@ -0,0 +1,232 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::errors::ProduceError;
+use common::models::{Message, TopicPartition};
+
+use crate::producer::Producer;
+
+/// Records of a topic which could not be processed are written to the topic named like it with
+/// this suffix.
+const DEAD_LETTER_TOPIC_SUFFIX: &str = ".dlq";
+/// Headers of dead letters telling where they were read and why they could not be processed.
+const SOURCE_TOPIC_HEADER: &str = "dlq-source-topic";
+const SOURCE_PARTITION_HEADER: &str = "dlq-source-partition";
+const SOURCE_OFFSET_HEADER: &str = "dlq-source-offset";
+const ATTEMPTS_HEADER: &str = "dlq-attempts";
+const ERROR_HEADER: &str = "dlq-error";
+
+/// How often a record is processed before it is given up on.
+#[derive(Debug, Clone)]
+pub struct DeadLetterPolicy {
+    /// Attempts at processing a record, at least 1.
+    pub max_attempts: u32,
+    /// Pause before processing a record again after it failed.
+    pub retry_backoff: Duration,
+}
+
+impl DeadLetterPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        DeadLetterPolicy {
+            max_attempts,
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Writes the records a consumer could not process to `<topic>.dlq`, so it moves past them
+/// instead of blocking their partition. The dead letter topic has to exist.
+pub struct DeadLetterQueue {
+    producer: Producer,
+    policy: DeadLetterPolicy,
+}
+
+impl DeadLetterQueue {
+    pub fn new(producer: Producer, policy: DeadLetterPolicy) -> Self {
+        DeadLetterQueue { producer, policy }
+    }
+
+    /// Processes the record read at `offset` of the partition until `process` succeeds, up to the
+    /// policy's `max_attempts` times, and writes it to the dead letter topic once it never did.
+    /// Returns once the record was processed or its dead letter was written, the consumer
+    /// must not move past the record when writing the dead letter failed.
+    pub async fn process<F, Fut, E>(
+        &self,
+        topic_partition: &TopicPartition,
+        offset: u64,
+        message: Message,
+        mut process: F,
+    ) -> Result<(), ProduceError>
+    where
+        F: FnMut(Message) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            match process(message.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempts < self.policy.max_attempts => {
+                    tracing::warn!(
+                        "Could not process offset {} of {:?}, retrying: {}",
+                        offset,
+                        topic_partition,
+                        e
+                    );
+                    tokio::time::sleep(self.policy.retry_backoff).await;
+                }
+                Err(e) => break e.to_string(),
+            }
+        };
+        let dead_letter_topic =
+            format!("{}{}", topic_partition.topic_name, DEAD_LETTER_TOPIC_SUFFIX);
+        let message = dead_letter(topic_partition, offset, message, attempts, &error);
+        let delivery = self
+            .producer
+            .send(dead_letter_topic.clone(), message)
+            .await?;
+        self.producer.flush().await;
+        delivery.await?;
+        tracing::warn!(
+            "Wrote offset {} of {:?} to {} after {} failed attempts: {}",
+            offset,
+            topic_partition,
+            dead_letter_topic,
+            attempts,
+            error
+        );
+        Ok(())
+    }
+}
+
+/// `message` with headers telling where it was read and why it could not be processed.
+fn dead_letter(
+    topic_partition: &TopicPartition,
+    offset: u64,
+    mut message: Message,
+    attempts: u32,
+    error: &str,
+) -> Message {
+    message.headers.extend([
+        (
+            SOURCE_TOPIC_HEADER.to_string(),
+            Bytes::from(topic_partition.topic_name.clone()),
+        ),
+        (
+            SOURCE_PARTITION_HEADER.to_string(),
+            Bytes::from(topic_partition.partition_index.to_string()),
+        ),
+        (
+            SOURCE_OFFSET_HEADER.to_string(),
+            Bytes::from(offset.to_string()),
+        ),
+        (
+            ATTEMPTS_HEADER.to_string(),
+            Bytes::from(attempts.to_string()),
+        ),
+        (ERROR_HEADER.to_string(), Bytes::from(error.to_string())),
+    ]);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use common::codecs::protocol::api_versions;
+    use common::models::{
+        BrokerResponse, PartitionMetadata, ProduceResponse, Topic, TopicCommand, TopicMetadata,
+    };
+
+    use crate::client::tests::start_fake_broker;
+    use crate::config::ConnectionConfig;
+    use crate::producer::ProducerBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dead_letter_queue_should_write_records_failing_every_attempt() {
+        let written_topics = Arc::new(Mutex::new(vec![]));
+        let recorded_topics = written_topics.clone();
+        let broker_address = start_fake_broker(move |command| match command {
+            TopicCommand::Metadata { topic_names } => BrokerResponse::Metadata {
+                brokers: vec![],
+                controller_id: None,
+                topics: topic_names
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|topic_name| TopicMetadata {
+                        topic: Topic::new(topic_name, Some(1), None, None, None, None),
+                        partitions: vec![PartitionMetadata {
+                            partition_index: 0,
+                            leader_id: 1,
+                            leader_epoch: 1,
+                            replicas: vec![1],
+                            isr: vec![1],
+                        }],
+                    })
+                    .collect(),
+            },
+            TopicCommand::WriteToTopic {
+                topic_name,
+                partition_index,
+                acks,
+                ..
+            } => {
+                recorded_topics.lock().unwrap().push(topic_name.clone());
+                BrokerResponse::MessageBatchAppended(ProduceResponse {
+                    topic_partition: TopicPartition::new(topic_name, partition_index),
+                    base_offset: 0,
+                    log_append_time_millis: None,
+                    acks,
+                })
+            }
+            TopicCommand::InitProducerId => BrokerResponse::ProducerIdAllocated { producer_id: 7 },
+            TopicCommand::ApiVersions => api_versions(),
+            _ => unreachable!(),
+        })
+        .await;
+        let producer = ProducerBuilder::new(ConnectionConfig::new(&broker_address))
+            .build()
+            .unwrap();
+        let policy = DeadLetterPolicy {
+            max_attempts: 2,
+            retry_backoff: Duration::ZERO,
+        };
+        let dead_letter_queue = DeadLetterQueue::new(producer, policy);
+        let topic_partition = TopicPartition::new("orders".to_string(), 0);
+        let message = Message::new(Bytes::from_static(b"v1"), None, None);
+
+        let mut attempts = 0;
+        dead_letter_queue
+            .process(&topic_partition, 7, message.clone(), |_| {
+                attempts += 1;
+                async { Err("invalid order") }
+            })
+            .await
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(*written_topics.lock().unwrap(), vec!["orders.dlq"]);
+
+        dead_letter_queue
+            .process(&topic_partition, 8, message.clone(), |_| async {
+                Ok::<(), String>(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(written_topics.lock().unwrap().len(), 1);
+
+        let dead_letter = dead_letter(&topic_partition, 7, message, 2, "invalid order");
+        assert_eq!(
+            dead_letter.headers[2..],
+            [
+                (SOURCE_OFFSET_HEADER.to_string(), Bytes::from("7")),
+                (ATTEMPTS_HEADER.to_string(), Bytes::from("2")),
+                (ERROR_HEADER.to_string(), Bytes::from("invalid order")),
+            ]
+        );
+    }
+}
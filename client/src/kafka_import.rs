@@ -0,0 +1,179 @@
+use std::ops::Range;
+
+use bytes::Bytes;
+use common::models::Message;
+use futures::{stream, SinkExt, StreamExt};
+use rskafka::client::{
+    partition::{OffsetAt, UnknownTopicHandling},
+    ClientBuilder,
+};
+use rskafka::record::Record;
+
+use crate::config::ConnectionConfig;
+use crate::producer::{ProducerBuilder, ProducerRecord};
+
+const FETCH_BYTES: Range<i32> = 1..1_000_000;
+const FETCH_MAX_WAIT_MS: i32 = 500;
+
+/// Copies every record of `kafka_topic` which exists when the import starts into the walrs topic
+/// `topic_name`, partition by partition and from the earliest retained offset.
+pub fn import_from_kafka(
+    kafka_brokers: Vec<String>,
+    kafka_topic: String,
+    topic_name: String,
+    connection: ConnectionConfig,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Could not start tokio runtime");
+    let imported_records = runtime.block_on(import_topic(
+        kafka_brokers,
+        kafka_topic.clone(),
+        topic_name.clone(),
+        connection,
+    ));
+    tracing::info!(
+        "Imported {} records from Kafka topic {} into {}",
+        imported_records,
+        kafka_topic,
+        topic_name
+    );
+}
+
+async fn import_topic(
+    kafka_brokers: Vec<String>,
+    kafka_topic: String,
+    topic_name: String,
+    connection: ConnectionConfig,
+) -> usize {
+    let kafka_client = ClientBuilder::new(kafka_brokers)
+        .build()
+        .await
+        .expect("Could not connect to Kafka");
+    let kafka_partitions = kafka_client
+        .list_topics()
+        .await
+        .expect("Could not list Kafka topics")
+        .into_iter()
+        .find(|topic| topic.name == kafka_topic)
+        .map(|topic| topic.partitions);
+    let kafka_partitions = match kafka_partitions {
+        Some(kafka_partitions) => kafka_partitions,
+        None => {
+            tracing::error!("Kafka topic {} does not exist", kafka_topic);
+            return 0;
+        }
+    };
+
+    let mut sink = ProducerBuilder::new(connection)
+        .build()
+        .expect("Invalid producer configuration")
+        .into_sink();
+    let mut imported_records = 0;
+    for kafka_partition in kafka_partitions {
+        let partition_client = kafka_client
+            .partition_client(
+                kafka_topic.clone(),
+                kafka_partition,
+                UnknownTopicHandling::Error,
+            )
+            .await
+            .expect("Could not connect to Kafka partition");
+        let mut offset = partition_client
+            .get_offset(OffsetAt::Earliest)
+            .await
+            .expect("Could not get earliest Kafka offset");
+        // records produced while the import runs are not part of this one-shot copy
+        let end_offset = partition_client
+            .get_offset(OffsetAt::Latest)
+            .await
+            .expect("Could not get latest Kafka offset");
+        tracing::info!(
+            "Importing offsets {}..{} of Kafka partition {}",
+            offset,
+            end_offset,
+            kafka_partition
+        );
+
+        while offset < end_offset {
+            let (records, _high_watermark) = partition_client
+                .fetch_records(offset, FETCH_BYTES, FETCH_MAX_WAIT_MS)
+                .await
+                .expect("Could not fetch records from Kafka");
+            let mut messages = vec![];
+            for record_and_offset in records {
+                if record_and_offset.offset >= end_offset {
+                    break;
+                }
+                offset = record_and_offset.offset + 1;
+                messages.push(to_message(record_and_offset.record));
+            }
+            if messages.is_empty() {
+                tracing::warn!(
+                    "No more records returned for Kafka partition {} at offset {}",
+                    kafka_partition,
+                    offset
+                );
+                break;
+            }
+
+            let fetched_records = messages.len();
+            let mut records = stream::iter(messages).map(|message| {
+                Ok(ProducerRecord {
+                    topic_name: topic_name.clone(),
+                    message,
+                })
+            });
+            // completes once every record of the fetch was written
+            if let Err(e) = sink.send_all(&mut records).await {
+                tracing::error!("Stopping import, could not write records: {}", e);
+                return imported_records;
+            }
+            imported_records += fetched_records;
+        }
+    }
+    imported_records
+}
+
+fn to_message(record: Record) -> Message {
+    Message {
+        payload: record.value.map(Bytes::from),
+        key: record.key.map(Bytes::from),
+        timestamp: Some(record.timestamp.timestamp_millis().max(0) as u128),
+        headers: record
+            .headers
+            .into_iter()
+            .map(|(name, value)| (name, Bytes::from(value)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rskafka::chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    #[test]
+    fn test_to_message_should_keep_key_payload_timestamp_and_headers() {
+        let record = Record {
+            key: Some("user-1".as_bytes().to_vec()),
+            value: Some(vec![1, 2, 3]),
+            headers: BTreeMap::from([("trace-id".to_string(), b"abc".to_vec())]),
+            timestamp: Utc.timestamp_millis_opt(1_700_000_000_123).unwrap(),
+        };
+
+        let message = to_message(record);
+
+        assert_eq!(message.key, Some(Bytes::from("user-1")));
+        assert_eq!(message.payload, Some(Bytes::from(vec![1, 2, 3])));
+        assert_eq!(message.timestamp, Some(1_700_000_000_123));
+        assert_eq!(
+            message.headers,
+            vec![("trace-id".to_string(), Bytes::from("abc"))]
+        );
+    }
+}
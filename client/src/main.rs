@@ -1,62 +1,829 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
 use clap::{Parser, Subcommand};
-use commands::{create_topic, write_message};
-use common::models::Topic;
+use commands::consume::{ConsumeOptions, RecordFormat};
+use commands::perf::PerfLimits;
+use commands::produce::{parse_header, LineFormat};
+use commands::{cluster, consume, dump_log, groups, perf, produce, topics};
+use common::models::{
+    Acks, CompressionCodec, OffsetResetTarget, OrderingMode, OverflowPolicy, TimestampType, Topic,
+    TopicPartition,
+};
+use common::sasl::{SaslCredentials, SaslMechanism};
+use config::ConnectionConfig;
+use dead_letter::DeadLetterPolicy;
+use kafka_import::import_from_kafka;
+use mirror::{mirror_topics, MirrorSettings, TopicRename};
+use mqtt_bridge::{bridge_from_mqtt, parse_qos, MqttSettings, TopicMapping};
+use parquet_export::{export_to_parquet, ColumnMapping};
+use partitioner::{KeyHashAlgorithm, PartitionerKind};
+use producer::ProducerBuilder;
+use schema_registry::{JsonSchemaSerde, SchemaRegistryClient};
+use serialization::ValueFormat;
 
+mod client;
 mod commands;
+mod config;
+mod connection;
+mod connection_pool;
+mod consumer;
+mod dead_letter;
+mod kafka_import;
+mod mirror;
+mod mqtt_bridge;
+mod parquet_export;
+mod partitioner;
+mod producer;
+mod schema_registry;
+mod serialization;
 
 fn main() {
-    common::enable_tracing();
+    let _tracing_guard = common::enable_tracing("walrs-client");
     let args = Arguments::parse();
+    let mut connection = ConnectionConfig {
+        client_id: args.client_id,
+        request_timeout: Duration::from_millis(args.request_timeout_ms),
+        client_rack: args.client_rack,
+        ..ConnectionConfig::new(&args.broker_address)
+    };
+    if let Some(ca_path) = &args.tls_ca_cert {
+        let client_certificate = match (&args.tls_cert, &args.tls_key) {
+            (Some(certificate_path), Some(private_key_path)) => {
+                Some((certificate_path.as_str(), private_key_path.as_str()))
+            }
+            (None, None) => None,
+            _ => panic!("--tls-cert and --tls-key are required together"),
+        };
+        let tls = common::tls::client_config(ca_path, client_certificate)
+            .expect("Failed to load the TLS certificates");
+        connection.tls = Some(tls);
+    }
+    if let Some(username) = &args.sasl_username {
+        connection.credentials = Some(SaslCredentials {
+            mechanism: args.sasl_mechanism,
+            username: username.clone(),
+            password: args
+                .sasl_password
+                .clone()
+                .expect("--sasl-password is required with --sasl-username"),
+        });
+    }
     match args.command {
-        Some(Commands::CreateTopic {
+        Commands::Topics { command } => run_topics_command(command, connection),
+        Commands::Produce {
+            topic_name,
+            message,
+            key,
+            key_separator,
+            headers,
+            parse_headers,
+            acks,
+            partitioner,
+            key_hash,
+            compression,
+            value_format,
+            null_marker,
+            schema_registry_url,
+            value_schema,
+        } => {
+            let line_format = LineFormat {
+                key_separator,
+                parse_headers,
+                value_format,
+                key,
+                headers,
+                null_marker,
+                schema_registry: json_schema_serde(schema_registry_url, value_schema),
+            };
+            let producer_builder = ProducerBuilder::new(connection)
+                .acks(acks)
+                .compression(compression)
+                .partitioner(partitioner.build(key_hash));
+            produce::produce(topic_name, message, line_format, producer_builder)
+        }
+        Commands::Consume {
+            topic_name,
+            partition_index,
+            offset,
+            from_beginning,
+            group_id,
+            max_messages,
+            print_timestamp,
+            print_partition,
+            print_offset,
+            print_headers,
+            print_key,
+            separator,
+            value_format,
+            schema_registry_url,
+            value_schema,
+            dead_letter_after,
+        } => {
+            let options = ConsumeOptions {
+                partition_index,
+                offset,
+                from_beginning,
+                group_id,
+                max_messages,
+                dead_letter_policy: dead_letter_after.map(DeadLetterPolicy::new),
+            };
+            let record_format = RecordFormat {
+                print_timestamp,
+                print_partition,
+                print_offset,
+                print_headers,
+                print_key,
+                separator,
+                value_format,
+                schema_registry: json_schema_serde(schema_registry_url, value_schema),
+            };
+            consume::consume(topic_name, options, record_format, connection)
+        }
+        Commands::Groups { command } => run_groups_command(command, connection),
+        Commands::Cluster { command } => run_cluster_command(command, connection),
+        Commands::Perf { command } => run_perf_command(command, connection),
+        Commands::ImportFromKafka {
+            topic_name,
+            kafka_brokers,
+            kafka_topic,
+        } => import_from_kafka(kafka_brokers, kafka_topic, topic_name, connection),
+        Commands::Mirror {
+            source_broker_address,
+            topic_names,
+            renames,
+            checkpoint_path,
+        } => {
+            // the source cluster is reached with the same client ID, TLS and SASL settings
+            let source = ConnectionConfig {
+                bootstrap_addresses: ConnectionConfig::new(&source_broker_address)
+                    .bootstrap_addresses,
+                ..connection.clone()
+            };
+            let mirror_settings = MirrorSettings {
+                source,
+                topic_names,
+                renames,
+                checkpoint_path,
+            };
+            mirror_topics(mirror_settings, connection)
+        }
+        Commands::BridgeFromMqtt {
+            mqtt_host,
+            mqtt_port,
+            mqtt_client_id,
+            mqtt_username,
+            mqtt_password,
+            qos,
+            mappings,
+        } => {
+            let credentials = mqtt_username.map(|username| {
+                let password =
+                    mqtt_password.expect("--mqtt-password is required with --mqtt-username");
+                (username, password)
+            });
+            let mqtt_settings = MqttSettings {
+                host: mqtt_host,
+                port: mqtt_port,
+                client_id: mqtt_client_id,
+                credentials,
+                qos,
+            };
+            bridge_from_mqtt(mqtt_settings, mappings, connection)
+        }
+        Commands::ExportToParquet {
+            topic_name,
+            log_dir,
+            output_dir,
+            columns,
+        } => {
+            let columns = if columns.is_empty() {
+                ColumnMapping::defaults()
+            } else {
+                columns
+            };
+            export_to_parquet(log_dir, topic_name, output_dir, columns)
+        }
+        Commands::DumpLog {
+            segment_file_path,
+            verify,
+        } => dump_log::dump_log(segment_file_path, verify),
+    }
+}
+
+fn json_schema_serde(
+    schema_registry_url: Option<String>,
+    value_schema_path: Option<String>,
+) -> Option<JsonSchemaSerde> {
+    let value_schema = value_schema_path
+        .map(|path| std::fs::read_to_string(path).expect("Could not read the value schema"));
+    schema_registry_url
+        .map(|url| JsonSchemaSerde::new(Arc::new(SchemaRegistryClient::new(url)), value_schema))
+}
+
+fn run_topics_command(command: TopicsCommands, connection: ConnectionConfig) {
+    match command {
+        TopicsCommands::Create {
+            topic_name,
             partition_count,
             batch_size,
             replication_factor,
-        }) => {
+            ordering_mode,
+            queue_size,
+            overflow_policy,
+            max_message_bytes,
+            timestamp_type,
+            scheduled_delivery,
+        } => {
             let topic_to_create = Topic {
-                name: args.topic_name,
+                name: topic_name,
                 num_partitions: partition_count,
-                replication_factor: replication_factor,
+                replication_factor,
                 retention_period: Some(1),
-                batch_size: batch_size,
+                batch_size,
+                ordering_mode,
+                queue_size,
+                overflow_policy,
+                max_message_bytes,
+                timestamp_type,
+                scheduled_delivery: scheduled_delivery.then_some(true),
             };
-            create_topic(topic_to_create, args.broker_address);
+            topics::create_topic(topic_to_create, connection);
+        }
+        TopicsCommands::List => topics::list_topics(connection),
+        TopicsCommands::Describe { topic_name } => topics::describe_topic(topic_name, connection),
+        TopicsCommands::Delete { topic_name } => topics::delete_topic(topic_name, connection),
+    }
+}
+
+fn run_groups_command(command: GroupsCommands, connection: ConnectionConfig) {
+    match command {
+        GroupsCommands::Describe { group_id } => groups::describe_group(group_id, connection),
+        GroupsCommands::ResetOffsets {
+            group_id,
+            topic_name,
+            to,
+        } => groups::reset_offsets(group_id, topic_name, to, connection),
+    }
+}
+
+fn run_perf_command(command: PerfCommands, connection: ConnectionConfig) {
+    match command {
+        PerfCommands::Produce {
+            topic_name,
+            record_size,
+            limits,
+            throughput,
+            acks,
+            compression,
+            batch_size,
+            linger_ms,
+        } => {
+            let producer_builder = ProducerBuilder::new(connection)
+                .acks(acks)
+                .compression(compression)
+                .batch_size(batch_size)
+                .linger(Duration::from_millis(linger_ms));
+            perf::perf_produce(
+                topic_name,
+                record_size,
+                throughput,
+                limits.into(),
+                producer_builder,
+            )
         }
-        Some(Commands::WriteToTopic { message }) => {
-            write_message(message, args.topic_name, args.broker_address)
+        PerfCommands::Consume {
+            topic_name,
+            limits,
+            max_records_per_fetch,
+        } => perf::perf_consume(topic_name, max_records_per_fetch, limits.into(), connection),
+    }
+}
+
+fn run_cluster_command(command: ClusterCommands, connection: ConnectionConfig) {
+    match command {
+        ClusterCommands::Describe { topic_names } => {
+            cluster::describe_cluster((!topic_names.is_empty()).then_some(topic_names), connection)
         }
-        None => {
-            tracing::info!("ERROR: No command provided");
+        ClusterCommands::Health => cluster::describe_cluster_health(connection),
+        ClusterCommands::Metrics => cluster::describe_metrics(connection),
+        ClusterCommands::ApiVersions => cluster::describe_api_versions(connection),
+        ClusterCommands::SetLogFilter { filter } => cluster::alter_log_filter(filter, connection),
+        ClusterCommands::ReassignPartition {
+            topic_name,
+            partition_index,
+            replicas,
+            throttle_rate,
+        } => cluster::reassign_partition(
+            TopicPartition::new(topic_name, partition_index),
+            replicas,
+            throttle_rate,
+            connection,
+        ),
+        ClusterCommands::Reassignments => cluster::describe_reassignments(connection),
+        ClusterCommands::ElectPreferredLeaders {
+            topic_name,
+            partition_index,
+        } => cluster::elect_preferred_leaders(
+            topic_name
+                .zip(partition_index)
+                .map(|(topic_name, partition_index)| {
+                    vec![TopicPartition::new(topic_name, partition_index)]
+                }),
+            connection,
+        ),
+        ClusterCommands::ControlledShutdown { broker_id } => {
+            cluster::controlled_shutdown(broker_id, connection)
+        }
+        ClusterCommands::Decommission { broker_id } => {
+            cluster::decommission_broker(broker_id, connection)
         }
     }
 }
 
-#[derive(Debug, Parser, Default)]
+/// Command line client of walrs, administers a cluster and writes and reads its topics
+#[derive(Debug, Parser)]
+#[clap(name = "walrs")]
 struct Arguments {
     #[clap(subcommand)]
-    command: Option<Commands>,
+    command: Commands,
 
+    /// Brokers to bootstrap from, separated by commas, the first reachable one is used
     #[clap(short = 'a', long = "broker-address")]
     broker_address: String,
 
-    #[clap(short = 't', long = "topic-name")]
-    topic_name: String,
+    /// Names the client in the brokers' logs and quotas
+    #[clap(long = "client-id", default_value = "walrs-client")]
+    client_id: String,
+
+    /// How long a request waits for the broker's answer
+    #[clap(long = "request-timeout-ms", default_value_t = 30_000)]
+    request_timeout_ms: u64,
+
+    /// Rack of the client, records are fetched from an in-sync replica in this rack when the
+    /// leader is in another one
+    #[clap(long = "client-rack")]
+    client_rack: Option<String>,
+
+    /// CA certificates of brokers reached with `tls://` addresses, PEM encoded
+    #[clap(long = "tls-ca-cert")]
+    tls_ca_cert: Option<String>,
+
+    /// Certificate presented to brokers which verify their clients, PEM encoded
+    #[clap(long = "tls-cert")]
+    tls_cert: Option<String>,
+
+    /// Private key of `--tls-cert`, PEM encoded
+    #[clap(long = "tls-key")]
+    tls_key: Option<String>,
+
+    /// Authenticates with SASL as this user, to brokers with SASL
+    #[clap(long = "sasl-username")]
+    sasl_username: Option<String>,
+
+    #[clap(long = "sasl-password")]
+    sasl_password: Option<String>,
+
+    /// PLAIN sends the password, over TLS only, SCRAM-SHA-256 proves it knows it
+    #[clap(long = "sasl-mechanism", default_value = "SCRAM-SHA-256")]
+    sasl_mechanism: SaslMechanism,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
-    CreateTopic {
-        #[clap(short = 'p')]
+    /// Creates, lists, describes and deletes topics
+    Topics {
+        #[clap(subcommand)]
+        command: TopicsCommands,
+    },
+    /// Writes every line read from stdin as a record and prints the partition and offset of each
+    Produce {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        /// writes this message instead of reading stdin
+        #[clap(short = 'm')]
+        message: Option<String>,
+
+        /// key of every record, unless --key-separator reads it from the line
+        #[clap(short = 'k', long = "key")]
+        key: Option<String>,
+
+        /// lines are <key><separator><value>, lines without it are skipped
+        #[clap(long = "key-separator")]
+        key_separator: Option<String>,
+
+        /// header of every record as name=value, may be repeated
+        #[clap(long = "header", value_parser = parse_header)]
+        headers: Vec<(String, Bytes)>,
+
+        /// lines start with their headers, name=value pairs separated by commas, followed by a
+        /// tab
+        #[clap(long = "headers")]
+        parse_headers: bool,
+
+        /// 0 does not wait for the broker, 1 waits for the leader and all for every in-sync replica
+        #[clap(long = "acks", default_value = "1")]
+        acks: Acks,
+
+        /// default hashes keys of strict topics and sticks to a partition of relaxed topics,
+        /// key-hash, round-robin and sticky do so regardless of the ordering mode
+        #[clap(long = "partitioner", default_value = "default")]
+        partitioner: PartitionerKind,
+
+        /// murmur2 maps keys like Kafka clients do, std is Rust's DefaultHasher
+        #[clap(long = "key-hash", default_value = "murmur2")]
+        key_hash: KeyHashAlgorithm,
+
+        /// none, gzip, lz4, zstd or snappy, the broker stores the batch compressed
+        #[clap(long = "compression", default_value = "none")]
+        compression: CompressionCodec,
+
+        /// raw writes values as they are, json checks each is a valid JSON document and bincode
+        /// encodes them as bincode strings
+        #[clap(long = "value-format", default_value = "raw")]
+        value_format: ValueFormat,
+
+        /// lines whose value is this are written as tombstones of their key
+        #[clap(long = "null-marker")]
+        null_marker: Option<String>,
+
+        /// write JSON values framed with the ID of --value-schema in this schema registry
+        #[clap(long = "schema-registry-url", requires = "value_schema")]
+        schema_registry_url: Option<String>,
+
+        /// file with the JSON schema of the values, registered under <TOPIC>-value
+        #[clap(long = "value-schema", requires = "schema_registry_url")]
+        value_schema: Option<String>,
+    },
+    /// Prints the records of a topic as they are written
+    Consume {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        /// read only this partition, defaults to every partition of the topic
+        #[clap(short = 'p', long = "partition", conflicts_with = "group_id")]
+        partition_index: Option<u8>,
+
+        /// offset of the first record of --partition
+        #[clap(short = 'f', long = "offset", requires = "partition_index")]
+        offset: Option<u64>,
+
+        /// start at the first record instead of the end when there is no committed offset
+        #[clap(long = "from-beginning")]
+        from_beginning: bool,
+
+        /// read the partitions assigned to this group and commit its offsets
+        #[clap(short = 'g', long = "group")]
+        group_id: Option<String>,
+
+        /// exit after this many records instead of waiting for new ones
+        #[clap(short = 'n', long = "max-messages")]
+        max_messages: Option<usize>,
+
+        #[clap(long = "print-timestamp")]
+        print_timestamp: bool,
+
+        #[clap(long = "print-partition")]
+        print_partition: bool,
+
+        #[clap(long = "print-offset")]
+        print_offset: bool,
+
+        #[clap(long = "print-headers")]
+        print_headers: bool,
+
+        #[clap(long = "print-key")]
+        print_key: bool,
+
+        /// printed between the fields of a record
+        #[clap(long = "separator", default_value = "\t")]
+        separator: String,
+
+        /// raw, json or bincode, how values are printed
+        #[clap(long = "value-format", default_value = "raw")]
+        value_format: ValueFormat,
+
+        /// read JSON values framed with a schema ID of this schema registry
+        #[clap(long = "schema-registry-url")]
+        schema_registry_url: Option<String>,
+
+        /// file with the JSON schema values must have been written with
+        #[clap(long = "value-schema", requires = "schema_registry_url")]
+        value_schema: Option<String>,
+
+        /// write records which could not be read this many times to <TOPIC>.dlq and go on
+        #[clap(long = "dead-letter-after", value_parser = clap::value_parser!(u32).range(1..))]
+        dead_letter_after: Option<u32>,
+    },
+    /// Shows consumer groups and moves their committed offsets
+    Groups {
+        #[clap(subcommand)]
+        command: GroupsCommands,
+    },
+    /// Shows the brokers, metrics and partition leaders of the cluster and moves partitions
+    Cluster {
+        #[clap(subcommand)]
+        command: ClusterCommands,
+    },
+    /// Measures the throughput and latency of producing to and consuming from a topic
+    Perf {
+        #[clap(subcommand)]
+        command: PerfCommands,
+    },
+    /// Copies all records of a Kafka topic into a topic
+    ImportFromKafka {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        #[clap(short = 'k', long = "kafka-broker", required = true)]
+        kafka_brokers: Vec<String>,
+
+        #[clap(short = 's', long = "kafka-topic")]
+        kafka_topic: String,
+    },
+    /// Copies the records of topics of another cluster into this one as they are written, for
+    /// disaster recovery and migrations
+    Mirror {
+        /// brokers of the cluster to copy from, separated by commas
+        #[clap(short = 's', long = "source-broker-address")]
+        source_broker_address: String,
+
+        /// topic of the source cluster to copy, may be repeated
+        #[clap(short = 't', long = "topic", required = true)]
+        topic_names: Vec<String>,
+
+        /// <source topic>=<target topic>, may be repeated, other topics keep their name
+        #[clap(short = 'r', long = "rename")]
+        renames: Vec<TopicRename>,
+
+        /// JSON file of the last copied offset of every partition, the mirror resumes from it
+        #[clap(
+            long = "checkpoint-file",
+            default_value = "walrs-mirror-checkpoints.json"
+        )]
+        checkpoint_path: String,
+    },
+    /// Subscribes to an MQTT broker and writes the messages it receives to walrs topics
+    BridgeFromMqtt {
+        #[clap(long = "mqtt-host")]
+        mqtt_host: String,
+
+        #[clap(long = "mqtt-port", default_value_t = 1883)]
+        mqtt_port: u16,
+
+        /// the MQTT broker keeps the subscriptions of this client ID while the bridge is away
+        #[clap(long = "mqtt-client-id", default_value = "walrs-bridge")]
+        mqtt_client_id: String,
+
+        #[clap(long = "mqtt-username")]
+        mqtt_username: Option<String>,
+
+        #[clap(long = "mqtt-password")]
+        mqtt_password: Option<String>,
+
+        /// 0 may lose messages, 1 and 2 acknowledge messages once walrs stored them
+        #[clap(long = "qos", default_value = "1", value_parser = parse_qos)]
+        qos: rumqttc::QoS,
+
+        /// <MQTT topic filter>=<walrs topic>, may be repeated, the first matching mapping is used
+        #[clap(short = 'm', long = "mapping", required = true)]
+        mappings: Vec<TopicMapping>,
+    },
+    /// Writes the records of a topic to one Parquet file per partition
+    ExportToParquet {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        /// log directory of the broker holding the topic
+        #[clap(short = 'l', long = "log-dir")]
+        log_dir: String,
+
+        #[clap(short = 'o', long = "output-dir")]
+        output_dir: String,
+
+        /// <field>[=<column name>] with field one of offset, key, payload, timestamp.
+        /// All fields under their own name are written when no column is given.
+        #[clap(short = 'c', long = "column")]
+        columns: Vec<ColumnMapping>,
+    },
+    /// Prints the batches and records of a segment file of a broker's log directory
+    DumpLog {
+        #[clap(value_name = "SEGMENT FILE")]
+        segment_file_path: String,
+
+        /// only check the CRC and records of every batch, printing the batches
+        #[clap(long = "verify")]
+        verify: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum TopicsCommands {
+    Create {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        #[clap(short = 'p', long = "partitions")]
         partition_count: Option<u8>,
 
-        #[clap(short = 'b')]
+        #[clap(short = 'b', long = "batch-size")]
         batch_size: Option<u8>,
 
-        #[clap(short = 'r')]
+        #[clap(short = 'r', long = "replication-factor")]
         replication_factor: Option<u8>,
+
+        /// strict pins keys to partitions, relaxed spreads records for throughput
+        #[clap(short = 'o', long = "ordering-mode")]
+        ordering_mode: Option<OrderingMode>,
+
+        /// writes queued per partition, the broker's partition channel size by default
+        #[clap(long = "queue-size")]
+        queue_size: Option<u32>,
+
+        /// block[:<ms>], reject or shed-oldest, what writes to a full partition queue do
+        #[clap(long = "overflow-policy")]
+        overflow_policy: Option<OverflowPolicy>,
+
+        /// largest batch the topic accepts, the broker's message max bytes by default
+        #[clap(long = "max-message-bytes")]
+        max_message_bytes: Option<u32>,
+
+        /// create-time keeps the producers' timestamps, log-append-time stamps records on append
+        #[clap(long = "timestamp-type")]
+        timestamp_type: Option<TimestampType>,
+
+        /// consumers read records with a deliver-at header, in milliseconds since the epoch,
+        /// only from that time on, records after them wait as well
+        #[clap(long = "scheduled-delivery")]
+        scheduled_delivery: bool,
     },
-    WriteToTopic {
-        #[clap(short = 'm')]
-        message: String,
+    /// Shows the topics of the cluster with their partition count
+    List,
+    /// Shows the settings of a topic and the leader and replicas of its partitions
+    Describe {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+    },
+    /// Deletes a topic and its records on every broker
+    Delete {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum GroupsCommands {
+    /// Shows committed offset, log end offset and lag of every partition consumed by a group
+    Describe {
+        #[clap(value_name = "GROUP")]
+        group_id: String,
     },
+    /// Moves the committed offsets of a group without members for a topic
+    ResetOffsets {
+        #[clap(value_name = "GROUP")]
+        group_id: String,
+
+        #[clap(short = 't', long = "topic")]
+        topic_name: String,
+
+        /// earliest, latest, offset:<offset> or timestamp:<millis since epoch>
+        #[clap(long = "to")]
+        to: OffsetResetTarget,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ClusterCommands {
+    /// Shows the brokers and the leader and replicas of every partition, of the given topics or
+    /// of every topic
+    Describe {
+        #[clap(short = 't', long = "topic")]
+        topic_names: Vec<String>,
+    },
+    /// Shows the under-replicated and offline partitions, unreachable brokers and ISR churn
+    Health,
+    /// Shows the counters and gauges of the broker
+    Metrics,
+    /// Shows the request versions and batch format versions the broker supports
+    ApiVersions,
+    /// Replaces what the broker logs until it restarts, e.g. `info,walrs_broker::managers=debug`
+    SetLogFilter {
+        #[clap(short = 'f', long = "filter")]
+        filter: String,
+    },
+    /// Moves the replicas of a partition to other brokers
+    ReassignPartition {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        #[clap(short = 'p', long = "partition", default_value_t = 0)]
+        partition_index: u8,
+
+        /// IDs of the new replicas separated by commas, the first one is the preferred leader
+        #[clap(short = 'r', long = "replicas", value_delimiter = ',', required = true)]
+        replicas: Vec<u32>,
+
+        /// Bytes per second the new replicas copy the partition with, unlimited by default
+        #[clap(long = "throttle")]
+        throttle_rate: Option<u64>,
+    },
+    /// Shows the partitions whose replicas are being moved
+    Reassignments,
+    /// Moves the leadership of partitions back to their preferred replicas, of a partition of
+    /// --topic or of every partition
+    ElectPreferredLeaders {
+        #[clap(short = 't', long = "topic", requires = "partition_index")]
+        topic_name: Option<String>,
+
+        #[clap(short = 'p', long = "partition", requires = "topic_name")]
+        partition_index: Option<u8>,
+    },
+    /// Moves the leadership of the partitions of a broker to their followers once they caught
+    /// up, before the broker restarts
+    ControlledShutdown {
+        #[clap(value_name = "BROKER_ID")]
+        broker_id: u32,
+    },
+    /// Moves the replicas of a broker to the other brokers and removes it from the cluster once
+    /// they caught up, so it can be stopped for good
+    Decommission {
+        #[clap(value_name = "BROKER_ID")]
+        broker_id: u32,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PerfCommands {
+    /// Writes records as fast as the broker takes them or at --throughput, like
+    /// kafka-producer-perf-test
+    Produce {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        /// bytes of every record's payload
+        #[clap(short = 's', long = "record-size", default_value_t = 100)]
+        record_size: usize,
+
+        #[clap(flatten)]
+        limits: PerfLimitArgs,
+
+        /// records per second at most, unlimited by default
+        #[clap(short = 't', long = "throughput")]
+        throughput: Option<u64>,
+
+        /// 0 does not wait for the broker, 1 waits for the leader and all for every in-sync replica
+        #[clap(long = "acks", default_value = "1")]
+        acks: Acks,
+
+        /// none, gzip, lz4, zstd or snappy
+        #[clap(long = "compression", default_value = "none")]
+        compression: CompressionCodec,
+
+        /// records sent to a partition at once
+        #[clap(long = "batch-size", default_value_t = 100)]
+        batch_size: usize,
+
+        /// milliseconds records wait for their batch to fill up
+        #[clap(long = "linger-ms", default_value_t = 5)]
+        linger_ms: u64,
+    },
+    /// Reads every partition of a topic from its first record, like kafka-consumer-perf-test
+    Consume {
+        #[clap(value_name = "TOPIC")]
+        topic_name: String,
+
+        #[clap(flatten)]
+        limits: PerfLimitArgs,
+
+        /// records fetched from a partition at once
+        #[clap(long = "max-records-per-fetch", default_value_t = 500)]
+        max_records_per_fetch: u32,
+    },
+}
+
+/// A test stops at whichever limit it reaches first.
+#[derive(Debug, clap::Args)]
+struct PerfLimitArgs {
+    /// stop after this many records
+    #[clap(
+        short = 'n',
+        long = "num-records",
+        required_unless_present = "duration_secs"
+    )]
+    num_records: Option<u64>,
+
+    /// stop after this many seconds
+    #[clap(short = 'd', long = "duration")]
+    duration_secs: Option<u64>,
+}
+
+impl From<PerfLimitArgs> for PerfLimits {
+    fn from(args: PerfLimitArgs) -> Self {
+        PerfLimits {
+            num_records: args.num_records,
+            duration: args.duration_secs.map(Duration::from_secs),
+        }
+    }
 }
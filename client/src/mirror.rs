@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{fs, io};
+
+use bytes::Bytes;
+use common::errors::WalrsError;
+use common::models::{Acks, FetchRequest, Message, OffsetResetPolicy, Topic, TopicPartition};
+use serde::{Deserialize, Serialize};
+
+use crate::client::{FetchedRecords, WalrsClient};
+use crate::config::ConnectionConfig;
+use crate::consumer::partitions;
+use crate::producer::ProducerBuilder;
+
+/// Records fetched from a source partition at once.
+const MIRROR_MAX_RECORDS: u32 = 500;
+/// Pause before fetching again after no source partition had new records.
+const MIRROR_FETCH_BACKOFF: Duration = Duration::from_millis(500);
+/// How often the checkpoints are saved and the lag is logged.
+const MIRROR_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// Headers of the mirrored records naming the record they were copied from, so any offset of
+/// the source cluster can be translated by looking for it in the target partition.
+const SOURCE_HEADER_PREFIX: &str = "mirror-source-";
+const SOURCE_TOPIC_HEADER: &str = "mirror-source-topic";
+const SOURCE_PARTITION_HEADER: &str = "mirror-source-partition";
+const SOURCE_OFFSET_HEADER: &str = "mirror-source-offset";
+
+/// `<source topic>=<target topic>`, records of the source topic are written to the target topic.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TopicRename {
+    pub source: String,
+    pub target: String,
+}
+
+impl FromStr for TopicRename {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((source, target)) if !source.is_empty() && !target.is_empty() => Ok(TopicRename {
+                source: source.to_string(),
+                target: target.to_string(),
+            }),
+            _ => Err(format!(
+                "Invalid rename {}, expected <source topic>=<target topic>",
+                value
+            )),
+        }
+    }
+}
+
+pub struct MirrorSettings {
+    /// Cluster the records are copied from.
+    pub source: ConnectionConfig,
+    pub topic_names: Vec<String>,
+    /// Topics without a rename keep their name in the target cluster.
+    pub renames: Vec<TopicRename>,
+    /// JSON file of the checkpoints, the mirror resumes after them once it is started again.
+    pub checkpoint_path: String,
+}
+
+/// The last record of a source partition which was mirrored and where it was written, offsets
+/// of consumers of the source partition up to `source_offset` are mirrored up to `target_offset`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Checkpoint {
+    source: TopicPartition,
+    source_offset: u64,
+    target: TopicPartition,
+    target_offset: Option<u64>,
+}
+
+/// Copies the records of the source cluster's topics into the cluster of `target` as they are
+/// written, until the process is stopped or a record can't be copied. Records keep their
+/// partition index, key, timestamp and headers. Records copied after the last saved checkpoint
+/// are copied again once the mirror is started again.
+pub fn mirror_topics(settings: MirrorSettings, target: ConnectionConfig) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Could not start tokio runtime");
+    runtime.block_on(run_mirror(settings, target));
+}
+
+async fn run_mirror(settings: MirrorSettings, target: ConnectionConfig) {
+    let source_client = WalrsClient::new(settings.source);
+    let producer = ProducerBuilder::new(target)
+        .acks(Acks::All)
+        .partitioner(Box::new(source_partition))
+        .build()
+        .expect("Invalid producer configuration");
+    let mut checkpoints = load_checkpoints(&settings.checkpoint_path);
+    let mut positions = BTreeMap::new();
+    for topic_name in &settings.topic_names {
+        let topic_positions = partitions(&source_client, topic_name)
+            .await
+            .expect("Could not look up the partitions of the source topic");
+        positions.extend(topic_positions);
+    }
+    for (source, position) in positions.iter_mut() {
+        *position = checkpoints
+            .get(source)
+            .map(|checkpoint| checkpoint.source_offset + 1);
+    }
+    let mut lags = BTreeMap::new();
+    let mut last_report = Instant::now();
+    'mirroring: loop {
+        let mut fetched_any = false;
+        for (source, position) in positions.iter_mut() {
+            let fetch_request = FetchRequest {
+                topic_partition: source.clone(),
+                offset: *position,
+                group_id: None,
+                member_id: None,
+                auto_offset_reset: OffsetResetPolicy::Earliest,
+                max_records: MIRROR_MAX_RECORDS,
+                replica_id: None,
+                leader_epoch: None,
+                rack_id: None,
+            };
+            let FetchedRecords {
+                base_offset,
+                records,
+                log_end_offset,
+            } = match source_client.fetch(fetch_request).await {
+                Ok(fetched) => fetched,
+                Err(WalrsError::OffsetOutOfRange { offset, .. }) => {
+                    tracing::warn!(
+                        "Offset {} of {:?} is out of range, mirroring from its first record",
+                        offset,
+                        source
+                    );
+                    *position = None;
+                    continue;
+                }
+                Err(e) if e.is_retriable() => {
+                    tracing::warn!("Could not fetch {:?}, retrying: {}", source, e);
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("Stopping the mirror, could not fetch {:?}: {}", source, e);
+                    break 'mirroring;
+                }
+            };
+            let next_offset = base_offset + records.len() as u64;
+            *position = Some(next_offset);
+            lags.insert(source.clone(), log_end_offset.saturating_sub(next_offset));
+            fetched_any |= !records.is_empty();
+
+            let target_topic = target_topic(&settings.renames, &source.topic_name);
+            let mut deliveries = Vec::with_capacity(records.len());
+            for (offset, message) in (base_offset..).zip(records) {
+                let message = mirrored_message(source, offset, message);
+                match producer.send(target_topic.to_string(), message).await {
+                    Ok(delivery) => deliveries.push((offset, delivery)),
+                    Err(e) => {
+                        tracing::error!("Stopping the mirror, could not send records: {}", e);
+                        break 'mirroring;
+                    }
+                }
+            }
+            // the checkpoint only moves past records which were written
+            producer.flush().await;
+            for (offset, delivery) in deliveries {
+                match delivery.await {
+                    Ok(record_metadata) => {
+                        let checkpoint = Checkpoint {
+                            source: source.clone(),
+                            source_offset: offset,
+                            target: record_metadata.topic_partition,
+                            target_offset: record_metadata.offset,
+                        };
+                        checkpoints.insert(source.clone(), checkpoint);
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            "Stopping the mirror, could not write record {} of {:?}: {}",
+                            offset,
+                            source,
+                            e
+                        );
+                        break 'mirroring;
+                    }
+                }
+            }
+        }
+        if last_report.elapsed() >= MIRROR_REPORT_INTERVAL {
+            report(&settings.checkpoint_path, &checkpoints, &lags);
+            last_report = Instant::now();
+        }
+        if !fetched_any {
+            tokio::time::sleep(MIRROR_FETCH_BACKOFF).await;
+        }
+    }
+    report(&settings.checkpoint_path, &checkpoints, &lags);
+}
+
+fn target_topic<'a>(renames: &'a [TopicRename], source_topic: &'a str) -> &'a str {
+    renames
+        .iter()
+        .find(|rename| rename.source == source_topic)
+        .map_or(source_topic, |rename| rename.target.as_str())
+}
+
+/// `message` with headers naming the record it was copied from, replacing those of a cluster
+/// it was mirrored from before.
+fn mirrored_message(source: &TopicPartition, offset: u64, mut message: Message) -> Message {
+    message
+        .headers
+        .retain(|(name, _)| !name.starts_with(SOURCE_HEADER_PREFIX));
+    message.headers.extend([
+        (
+            SOURCE_TOPIC_HEADER.to_string(),
+            Bytes::from(source.topic_name.clone()),
+        ),
+        (
+            SOURCE_PARTITION_HEADER.to_string(),
+            Bytes::from(source.partition_index.to_string()),
+        ),
+        (
+            SOURCE_OFFSET_HEADER.to_string(),
+            Bytes::from(offset.to_string()),
+        ),
+    ]);
+    message
+}
+
+/// Writes mirrored records to the partition with the index of their source partition, wrapped
+/// around when the target topic has fewer partitions.
+fn source_partition(topic: &Topic, message: &Message) -> u8 {
+    let num_partitions = topic.num_partitions.unwrap_or(1).max(1);
+    message
+        .headers
+        .iter()
+        .find(|(name, _)| name == SOURCE_PARTITION_HEADER)
+        .and_then(|(_, value)| std::str::from_utf8(value).ok()?.parse::<u8>().ok())
+        .map_or(0, |partition_index| partition_index % num_partitions)
+}
+
+fn load_checkpoints(checkpoint_path: &str) -> BTreeMap<TopicPartition, Checkpoint> {
+    let checkpoints: Vec<Checkpoint> = match fs::read(checkpoint_path) {
+        Ok(checkpoints) => {
+            serde_json::from_slice(&checkpoints).expect("Could not parse the checkpoint file")
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => panic!("Could not read the checkpoint file: {}", e),
+    };
+    checkpoints
+        .into_iter()
+        .map(|checkpoint| (checkpoint.source.clone(), checkpoint))
+        .collect()
+}
+
+/// Replaces the checkpoint file at once, so a mirror stopped while saving keeps the previous one.
+fn save_checkpoints(
+    checkpoint_path: &str,
+    checkpoints: &BTreeMap<TopicPartition, Checkpoint>,
+) -> io::Result<()> {
+    let checkpoints: Vec<&Checkpoint> = checkpoints.values().collect();
+    let temporary_path = format!("{}.tmp", checkpoint_path);
+    fs::write(&temporary_path, serde_json::to_vec_pretty(&checkpoints)?)?;
+    fs::rename(temporary_path, checkpoint_path)
+}
+
+fn report(
+    checkpoint_path: &str,
+    checkpoints: &BTreeMap<TopicPartition, Checkpoint>,
+    lags: &BTreeMap<TopicPartition, u64>,
+) {
+    if let Err(e) = save_checkpoints(checkpoint_path, checkpoints) {
+        tracing::error!(
+            "Could not save the checkpoints to {}: {}",
+            checkpoint_path,
+            e
+        );
+    }
+    for (source, lag) in lags {
+        tracing::info!("Mirror of {:?} is {} records behind", source, lag);
+    }
+    tracing::info!(
+        "Mirror is {} records behind in total",
+        lags.values().sum::<u64>()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_mirrored_records_should_keep_their_source_partition_and_checkpoint() {
+        let renames = vec!["orders=dr.orders".parse::<TopicRename>().unwrap()];
+        assert_eq!(target_topic(&renames, "orders"), "dr.orders");
+        assert_eq!(target_topic(&renames, "payments"), "payments");
+        assert!("orders=".parse::<TopicRename>().is_err());
+
+        let source = TopicPartition::new("orders".to_string(), 5);
+        let mut message = Message::new(Bytes::from_static(b"v1"), None, None);
+        message.headers = vec![(SOURCE_OFFSET_HEADER.to_string(), Bytes::from("3"))];
+        let message = mirrored_message(&source, 42, message);
+        assert_eq!(
+            message.headers,
+            vec![
+                (SOURCE_TOPIC_HEADER.to_string(), Bytes::from("orders")),
+                (SOURCE_PARTITION_HEADER.to_string(), Bytes::from("5")),
+                (SOURCE_OFFSET_HEADER.to_string(), Bytes::from("42")),
+            ]
+        );
+        let topic = Topic::new("dr.orders".to_string(), Some(4), None, None, None, None);
+        assert_eq!(source_partition(&topic, &message), 1);
+
+        let dir = TempDir::new("mirror").unwrap();
+        let checkpoint_path = dir.path().join("checkpoints.json");
+        let checkpoint_path = checkpoint_path.to_str().unwrap();
+        assert!(load_checkpoints(checkpoint_path).is_empty());
+        let checkpoints = BTreeMap::from([(
+            source.clone(),
+            Checkpoint {
+                source: source.clone(),
+                source_offset: 42,
+                target: TopicPartition::new("dr.orders".to_string(), 1),
+                target_offset: Some(7),
+            },
+        )]);
+        save_checkpoints(checkpoint_path, &checkpoints).unwrap();
+        assert_eq!(load_checkpoints(checkpoint_path), checkpoints);
+    }
+}
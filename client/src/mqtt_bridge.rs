@@ -0,0 +1,204 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use common::models::Message;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS};
+
+use crate::config::ConnectionConfig;
+use crate::producer::ProducerBuilder;
+
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+const MQTT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Requests of the bridge, such as acknowledgements, waiting to be sent to the MQTT broker.
+const MQTT_REQUEST_CAPACITY: usize = 100;
+/// Header of the bridged records holding the MQTT topic they were published to.
+const MQTT_TOPIC_HEADER: &str = "mqtt-topic";
+
+/// `<MQTT topic filter>=<walrs topic>`, messages published to a topic matching the filter are
+/// written to the walrs topic.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TopicMapping {
+    pub filter: String,
+    pub topic_name: String,
+}
+
+impl FromStr for TopicMapping {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((filter, topic_name))
+                if rumqttc::valid_filter(filter) && !topic_name.is_empty() =>
+            {
+                Ok(TopicMapping {
+                    filter: filter.to_string(),
+                    topic_name: topic_name.to_string(),
+                })
+            }
+            _ => Err(format!(
+                "Invalid mapping {}, expected <MQTT topic filter>=<walrs topic>",
+                value
+            )),
+        }
+    }
+}
+
+/// Connection to the MQTT broker the bridge subscribes to.
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub credentials: Option<(String, String)>,
+    /// QoS of the subscriptions. Messages received with QoS 1 or 2 are acknowledged once walrs
+    /// stored them, so the MQTT broker sends them again when the bridge stopped before.
+    pub qos: QoS,
+}
+
+pub fn parse_qos(value: &str) -> Result<QoS, String> {
+    value
+        .parse::<u8>()
+        .ok()
+        .and_then(|qos| rumqttc::qos(qos).ok())
+        .ok_or_else(|| format!("Invalid QoS {}, expected 0, 1 or 2", value))
+}
+
+/// Subscribes to the topic filters of `mappings` and writes every message received to the
+/// walrs topic of the first mapping matching its MQTT topic, until the process is stopped.
+/// Records are keyed by the MQTT topic so the messages of a device keep their order.
+pub fn bridge_from_mqtt(
+    mqtt_settings: MqttSettings,
+    mappings: Vec<TopicMapping>,
+    connection: ConnectionConfig,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Could not start tokio runtime");
+    runtime.block_on(run_bridge(mqtt_settings, mappings, connection));
+}
+
+async fn run_bridge(
+    mqtt_settings: MqttSettings,
+    mappings: Vec<TopicMapping>,
+    connection: ConnectionConfig,
+) {
+    let mut options = MqttOptions::new(
+        mqtt_settings.client_id,
+        mqtt_settings.host,
+        mqtt_settings.port,
+    );
+    options
+        .set_keep_alive(MQTT_KEEP_ALIVE)
+        .set_manual_acks(true)
+        // the MQTT broker keeps the subscriptions and queues messages while the bridge is away
+        .set_clean_session(mqtt_settings.qos == QoS::AtMostOnce);
+    if let Some((username, password)) = mqtt_settings.credentials {
+        options.set_credentials(username, password);
+    }
+    let (mqtt_client, mut event_loop) = AsyncClient::new(options, MQTT_REQUEST_CAPACITY);
+    for mapping in &mappings {
+        mqtt_client
+            .subscribe(mapping.filter.clone(), mqtt_settings.qos)
+            .await
+            .expect("Could not subscribe to the MQTT topic filter");
+    }
+
+    let producer = ProducerBuilder::new(connection)
+        .build()
+        .expect("Invalid producer configuration");
+    loop {
+        let publish = match next_publish(&mut event_loop).await {
+            Some(publish) => publish,
+            None => continue,
+        };
+        let Some(topic_name) = walrs_topic(&mappings, &publish.topic) else {
+            tracing::warn!("No mapping for MQTT topic {}, dropping it", publish.topic);
+            ack(&mqtt_client, &publish).await;
+            continue;
+        };
+        let mut message = Message::new(
+            publish.payload.clone(),
+            Some(publish.topic.clone().into()),
+            None,
+        );
+        message.headers = vec![(MQTT_TOPIC_HEADER.to_string(), publish.topic.clone().into())];
+        let delivery = match producer.send(topic_name.to_string(), message).await {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                tracing::error!("Could not send message of {}: {}", publish.topic, e);
+                continue;
+            }
+        };
+        let mqtt_client = mqtt_client.clone();
+        // deliveries are awaited aside so the event loop keeps receiving while batches linger
+        tokio::spawn(async move {
+            match delivery.await {
+                Ok(_) => ack(&mqtt_client, &publish).await,
+                Err(e) => tracing::error!(
+                    "Could not write message of {}, it is sent again once the bridge reconnects: {}",
+                    publish.topic,
+                    e
+                ),
+            }
+        });
+    }
+}
+
+/// Polls the MQTT event loop until the next message arrives, reconnecting after errors.
+async fn next_publish(event_loop: &mut EventLoop) -> Option<Publish> {
+    match event_loop.poll().await {
+        Ok(Event::Incoming(Packet::Publish(publish))) => Some(publish),
+        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+            tracing::info!("Connected to the MQTT broker");
+            None
+        }
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!("MQTT connection failed, reconnecting: {}", e);
+            tokio::time::sleep(MQTT_RECONNECT_DELAY).await;
+            None
+        }
+    }
+}
+
+async fn ack(mqtt_client: &AsyncClient, publish: &Publish) {
+    if let Err(e) = mqtt_client.ack(publish).await {
+        tracing::error!("Could not acknowledge message of {}: {}", publish.topic, e);
+    }
+}
+
+fn walrs_topic<'a>(mappings: &'a [TopicMapping], mqtt_topic: &str) -> Option<&'a str> {
+    mappings
+        .iter()
+        .find(|mapping| rumqttc::matches(mqtt_topic, &mapping.filter))
+        .map(|mapping| mapping.topic_name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mappings_should_map_mqtt_topics_to_the_first_matching_walrs_topic() {
+        let mappings = ["sensors/+/temperature=temperatures", "sensors/#=sensors"]
+            .iter()
+            .map(|mapping| mapping.parse())
+            .collect::<Result<Vec<TopicMapping>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            walrs_topic(&mappings, "sensors/device-1/temperature"),
+            Some("temperatures")
+        );
+        assert_eq!(
+            walrs_topic(&mappings, "sensors/device-1/humidity"),
+            Some("sensors")
+        );
+        assert_eq!(walrs_topic(&mappings, "actuators/device-1"), None);
+
+        assert!("sensors/#/temperature=t1".parse::<TopicMapping>().is_err());
+        assert!("sensors/#".parse::<TopicMapping>().is_err());
+        assert_eq!(parse_qos("1"), Ok(QoS::AtLeastOnce));
+        assert!(parse_qos("3").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{fs, str::FromStr, sync::Arc};
+
+use bytes::BytesMut;
+use common::{codecs::decoder::BatchDecoder, models::Message};
+use parquet::{
+    basic::{ConvertedType, Repetition, Type as PhysicalType},
+    data_type::{ByteArray, ByteArrayType, Int64Type},
+    errors::Result,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+use tokio_util::codec::Decoder;
+
+/// A record field of a walrs message and the Parquet column it is written to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMapping {
+    field: RecordField,
+    column_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RecordField {
+    Offset,
+    Key,
+    Payload,
+    Timestamp,
+}
+
+impl ColumnMapping {
+    /// Every record field written to a column of the same name.
+    pub fn defaults() -> Vec<ColumnMapping> {
+        ["offset", "key", "payload", "timestamp"]
+            .iter()
+            .map(|field| field.parse().unwrap())
+            .collect()
+    }
+
+    fn parquet_type(&self) -> Result<Type> {
+        let (physical_type, repetition, converted_type) = match self.field {
+            RecordField::Offset => (PhysicalType::INT64, Repetition::REQUIRED, None),
+            RecordField::Key => (
+                PhysicalType::BYTE_ARRAY,
+                Repetition::OPTIONAL,
+                Some(ConvertedType::UTF8),
+            ),
+            // tombstones have no payload
+            RecordField::Payload => (PhysicalType::BYTE_ARRAY, Repetition::OPTIONAL, None),
+            RecordField::Timestamp => (
+                PhysicalType::INT64,
+                Repetition::OPTIONAL,
+                Some(ConvertedType::TIMESTAMP_MILLIS),
+            ),
+        };
+        Type::primitive_type_builder(&self.column_name, physical_type)
+            .with_repetition(repetition)
+            .with_converted_type(converted_type.unwrap_or(ConvertedType::NONE))
+            .build()
+    }
+}
+
+/// Parses `<field>` or `<field>=<column name>`, e.g. `payload=body`.
+impl FromStr for ColumnMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (field, column_name) = s.split_once('=').unwrap_or((s, s));
+        let field = match field {
+            "offset" => RecordField::Offset,
+            "key" => RecordField::Key,
+            "payload" => RecordField::Payload,
+            "timestamp" => RecordField::Timestamp,
+            _ => {
+                return Err(format!(
+                    "Unknown field {}, expected one of offset, key, payload, timestamp",
+                    field
+                ))
+            }
+        };
+        if column_name.is_empty()
+            || !column_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!("Invalid column name {}", column_name));
+        }
+        Ok(ColumnMapping {
+            field,
+            column_name: column_name.to_string(),
+        })
+    }
+}
+
+/// Writes the records of every partition of `topic_name` found under the broker's `log_dir` to
+/// `<output_dir>/<topic_name>/partition=<index>/part-00000.parquet`, so the files can be loaded
+/// as a partitioned table. Offsets are the position of a record within its partition.
+pub fn export_to_parquet(
+    log_dir: String,
+    topic_name: String,
+    output_dir: String,
+    columns: Vec<ColumnMapping>,
+) {
+    let topic_dir = format!("{}/{}", log_dir, topic_name);
+    let mut partition_indexes: Vec<u8> = fs::read_dir(&topic_dir)
+        .expect("Could not read topic directory")
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    partition_indexes.sort();
+
+    let mut exported_records = 0;
+    for partition_index in partition_indexes {
+        let records = read_partition(&format!("{}/{}/segment_0.log", topic_dir, partition_index));
+        let partition_output_dir = format!(
+            "{}/{}/partition={}",
+            output_dir, topic_name, partition_index
+        );
+        fs::create_dir_all(&partition_output_dir).expect("Could not create output directory");
+        let file_path = format!("{}/part-00000.parquet", partition_output_dir);
+        match write_parquet_file(&file_path, &records, &columns) {
+            Ok(_) => {
+                tracing::info!("Exported {} records to {}", records.len(), file_path);
+                exported_records += records.len();
+            }
+            Err(e) => {
+                tracing::error!("Could not write {} with error: {:?}", file_path, e);
+                return;
+            }
+        }
+    }
+    tracing::info!(
+        "Exported {} records of topic {} to {}",
+        exported_records,
+        topic_name,
+        output_dir
+    );
+}
+
+fn read_partition(segment_file_path: &str) -> Vec<Message> {
+    let segment = match fs::read(segment_file_path) {
+        Ok(segment) => segment,
+        Err(e) => {
+            tracing::warn!("Could not read {} with error: {:?}", segment_file_path, e);
+            return vec![];
+        }
+    };
+    let mut src = BytesMut::from(segment.as_slice());
+    let mut batch_decoder = BatchDecoder {};
+    let mut records = vec![];
+    while let Some(batch) = batch_decoder
+        .decode(&mut src)
+        .expect("Could not decode segment file")
+    {
+        records.extend(batch.records);
+    }
+    records
+}
+
+fn write_parquet_file(
+    file_path: &str,
+    records: &[Message],
+    columns: &[ColumnMapping],
+) -> Result<()> {
+    let fields = columns
+        .iter()
+        .map(|column| column.parquet_type().map(Arc::new))
+        .collect::<Result<Vec<_>>>()?;
+    let schema = Type::group_type_builder("walrs_record")
+        .with_fields(fields)
+        .build()?;
+    let file = fs::File::create(file_path)?;
+    let mut writer = SerializedFileWriter::new(
+        file,
+        Arc::new(schema),
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+
+    let mut row_group_writer = writer.next_row_group()?;
+    for column in columns {
+        let mut column_writer = row_group_writer
+            .next_column()?
+            .expect("Schema has a column per mapping");
+        match column.field {
+            RecordField::Offset => {
+                let offsets: Vec<i64> = (0..records.len() as i64).collect();
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&offsets, None, None)?;
+            }
+            RecordField::Key => {
+                let keys: Vec<ByteArray> = records
+                    .iter()
+                    .filter_map(|record| {
+                        record.key.as_ref().map(|key| ByteArray::from(key.to_vec()))
+                    })
+                    .collect();
+                let definition_levels = definition_levels(records, |record| record.key.is_some());
+                column_writer.typed::<ByteArrayType>().write_batch(
+                    &keys,
+                    Some(&definition_levels),
+                    None,
+                )?;
+            }
+            RecordField::Payload => {
+                let payloads: Vec<ByteArray> = records
+                    .iter()
+                    .filter_map(|record| {
+                        record
+                            .payload
+                            .as_ref()
+                            .map(|payload| ByteArray::from(payload.to_vec()))
+                    })
+                    .collect();
+                let definition_levels =
+                    definition_levels(records, |record| record.payload.is_some());
+                column_writer.typed::<ByteArrayType>().write_batch(
+                    &payloads,
+                    Some(&definition_levels),
+                    None,
+                )?;
+            }
+            RecordField::Timestamp => {
+                let timestamps: Vec<i64> = records
+                    .iter()
+                    .filter_map(|record| record.timestamp.map(|timestamp| timestamp as i64))
+                    .collect();
+                let definition_levels =
+                    definition_levels(records, |record| record.timestamp.is_some());
+                column_writer.typed::<Int64Type>().write_batch(
+                    &timestamps,
+                    Some(&definition_levels),
+                    None,
+                )?;
+            }
+        }
+        column_writer.close()?;
+    }
+    row_group_writer.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Definition level 1 marks a present value of an optional column, 0 a null.
+fn definition_levels(records: &[Message], is_present: impl Fn(&Message) -> bool) -> Vec<i16> {
+    records
+        .iter()
+        .map(|record| is_present(record) as i16)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use common::{
+        codecs::encoder::BatchEncoder,
+        models::{Batch, CompressionCodec},
+    };
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use tokio_util::codec::Encoder;
+
+    use super::*;
+
+    #[test]
+    fn test_column_mapping_should_parse_renamed_and_plain_fields() {
+        assert_eq!(
+            "payload=body".parse::<ColumnMapping>().unwrap(),
+            ColumnMapping {
+                field: RecordField::Payload,
+                column_name: "body".to_string()
+            }
+        );
+        assert_eq!(
+            "key".parse::<ColumnMapping>().unwrap().column_name,
+            "key".to_string()
+        );
+        assert!("headers".parse::<ColumnMapping>().is_err());
+        assert!("key=user id".parse::<ColumnMapping>().is_err());
+    }
+
+    #[test]
+    fn test_export_to_parquet_should_write_file_per_partition() {
+        let log_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let output_dir = tempdir::TempDir::new("output_dir_").unwrap();
+        let partition_path = log_dir.path().join("test_topic").join("0");
+        fs::create_dir_all(&partition_path).unwrap();
+
+        let batch = Batch {
+            records: vec![
+                Message {
+                    payload: Some(Bytes::from("first")),
+                    key: Some(Bytes::from("user-1")),
+                    timestamp: Some(1_700_000_000_000),
+                    headers: vec![],
+                },
+                Message {
+                    payload: Some(Bytes::from("second")),
+                    key: None,
+                    timestamp: None,
+                    headers: vec![],
+                },
+            ],
+            producer: None,
+            // segments keep batches compressed as producers sent them
+            compression: CompressionCodec::Lz4,
+        };
+        let mut encoded_batch = BytesMut::new();
+        BatchEncoder {}.encode(batch, &mut encoded_batch).unwrap();
+        fs::write(partition_path.join("segment_0.log"), &encoded_batch).unwrap();
+
+        export_to_parquet(
+            log_dir.path().to_str().unwrap().to_string(),
+            "test_topic".to_string(),
+            output_dir.path().to_str().unwrap().to_string(),
+            vec!["payload=body".parse().unwrap(), "key".parse().unwrap()],
+        );
+
+        let parquet_file = fs::File::open(
+            output_dir
+                .path()
+                .join("test_topic/partition=0/part-00000.parquet"),
+        )
+        .unwrap();
+        let reader = SerializedFileReader::new(parquet_file).unwrap();
+        let file_metadata = reader.metadata().file_metadata();
+        assert_eq!(file_metadata.num_rows(), 2);
+        let column_names: Vec<&str> = file_metadata
+            .schema()
+            .get_fields()
+            .iter()
+            .map(|field| field.name())
+            .collect();
+        assert_eq!(column_names, vec!["body", "key"]);
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::Hasher,
+    str::FromStr,
+};
+
+use common::models::{Message, OrderingMode, Topic};
+
+/// Picks the partition a record is written to. The broker appends every batch to the partition
+/// the producer chose, so records which must stay in order have to go to the same partition.
+pub trait Partitioner: Send {
+    /// Returns a partition index below the topic's number of partitions.
+    fn partition(&mut self, topic: &Topic, message: &Message) -> u8;
+
+    /// Called after a batch of `topic_name` was sent.
+    fn on_new_batch(&mut self, _topic_name: &str) {}
+}
+
+/// Closures work as custom partitioners.
+impl<F> Partitioner for F
+where
+    F: FnMut(&Topic, &Message) -> u8 + Send,
+{
+    fn partition(&mut self, topic: &Topic, message: &Message) -> u8 {
+        self(topic, message)
+    }
+}
+
+fn num_partitions(topic: &Topic) -> u8 {
+    topic.num_partitions.unwrap_or(1).max(1)
+}
+
+/// How keys are hashed to partitions.
+/// `Murmur2` maps keys exactly like Kafka's default partitioner and never changes between
+/// releases. `Std` uses Rust's `DefaultHasher` which may map keys differently after a Rust
+/// upgrade, it is only kept for topics written by older clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyHashAlgorithm {
+    #[default]
+    Murmur2,
+    Std,
+}
+
+impl KeyHashAlgorithm {
+    fn hash(self, key: &[u8]) -> u64 {
+        match self {
+            // same as Kafka's `Utils.toPositive(Utils.murmur2(key))`
+            KeyHashAlgorithm::Murmur2 => (murmur2(key) & 0x7fffffff) as u64,
+            KeyHashAlgorithm::Std => {
+                let mut hasher = DefaultHasher::new();
+                hasher.write(key);
+                hasher.finish()
+            }
+        }
+    }
+}
+
+impl FromStr for KeyHashAlgorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "murmur2" => Ok(KeyHashAlgorithm::Murmur2),
+            "std" => Ok(KeyHashAlgorithm::Std),
+            _ => Err(format!(
+                "Unknown key hash algorithm {}, expected murmur2 or std",
+                value
+            )),
+        }
+    }
+}
+
+/// 32 bit murmur2 with Kafka's seed, ported from Kafka's `Utils.murmur2`.
+fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747b28c;
+    const M: u32 = 0x5bd1e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    let tail = chunks.remainder();
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h
+}
+
+/// Records with the same key always go to the same partition, records without a key go to
+/// partition 0.
+#[derive(Debug, Default)]
+pub struct KeyHashPartitioner {
+    algorithm: KeyHashAlgorithm,
+}
+
+impl KeyHashPartitioner {
+    pub fn new(algorithm: KeyHashAlgorithm) -> Self {
+        KeyHashPartitioner { algorithm }
+    }
+}
+
+impl Partitioner for KeyHashPartitioner {
+    fn partition(&mut self, topic: &Topic, message: &Message) -> u8 {
+        message
+            .key
+            .as_ref()
+            .map(|key| (self.algorithm.hash(key) % num_partitions(topic) as u64) as u8)
+            .unwrap_or(0)
+    }
+}
+
+/// Spreads records evenly, every record goes to the next partition of its topic.
+#[derive(Debug, Default)]
+pub struct RoundRobinPartitioner {
+    next_partitions: HashMap<String, u8>,
+}
+
+impl Partitioner for RoundRobinPartitioner {
+    fn partition(&mut self, topic: &Topic, _message: &Message) -> u8 {
+        let next_partition = self.next_partitions.entry(topic.name.clone()).or_insert(0);
+        let partition_index = *next_partition % num_partitions(topic);
+        *next_partition = (partition_index + 1) % num_partitions(topic);
+        partition_index
+    }
+}
+
+/// Sends records to one partition of a topic until a batch of the topic was sent, then moves on
+/// to the next partition. Fills batches faster than round robin while still spreading the load.
+#[derive(Debug, Default)]
+pub struct StickyPartitioner {
+    sticky_partitions: HashMap<String, u8>,
+}
+
+impl Partitioner for StickyPartitioner {
+    fn partition(&mut self, topic: &Topic, _message: &Message) -> u8 {
+        let sticky_partition = self
+            .sticky_partitions
+            .entry(topic.name.clone())
+            .or_insert(0);
+        *sticky_partition %= num_partitions(topic);
+        *sticky_partition
+    }
+
+    fn on_new_batch(&mut self, topic_name: &str) {
+        if let Some(sticky_partition) = self.sticky_partitions.get_mut(topic_name) {
+            *sticky_partition = sticky_partition.wrapping_add(1);
+        }
+    }
+}
+
+/// Hashes keys of strict topics so a key's records stay in order and sticks to partitions of
+/// relaxed topics.
+#[derive(Debug, Default)]
+pub struct DefaultPartitioner {
+    key_hash: KeyHashPartitioner,
+    sticky: StickyPartitioner,
+}
+
+impl DefaultPartitioner {
+    pub fn new(algorithm: KeyHashAlgorithm) -> Self {
+        DefaultPartitioner {
+            key_hash: KeyHashPartitioner::new(algorithm),
+            sticky: StickyPartitioner::default(),
+        }
+    }
+}
+
+impl Partitioner for DefaultPartitioner {
+    fn partition(&mut self, topic: &Topic, message: &Message) -> u8 {
+        match topic.ordering_mode.unwrap_or_default() {
+            OrderingMode::Strict => self.key_hash.partition(topic, message),
+            OrderingMode::Relaxed => self.sticky.partition(topic, message),
+        }
+    }
+
+    fn on_new_batch(&mut self, topic_name: &str) {
+        self.sticky.on_new_batch(topic_name);
+    }
+}
+
+/// Built-in partitioners which can be picked on the command line.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum PartitionerKind {
+    #[default]
+    Default,
+    KeyHash,
+    RoundRobin,
+    Sticky,
+}
+
+impl PartitionerKind {
+    pub fn build(self, algorithm: KeyHashAlgorithm) -> Box<dyn Partitioner> {
+        match self {
+            PartitionerKind::Default => Box::new(DefaultPartitioner::new(algorithm)),
+            PartitionerKind::KeyHash => Box::new(KeyHashPartitioner::new(algorithm)),
+            PartitionerKind::RoundRobin => Box::new(RoundRobinPartitioner::default()),
+            PartitionerKind::Sticky => Box::new(StickyPartitioner::default()),
+        }
+    }
+}
+
+impl FromStr for PartitionerKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "default" => Ok(PartitionerKind::Default),
+            "key-hash" => Ok(PartitionerKind::KeyHash),
+            "round-robin" => Ok(PartitionerKind::RoundRobin),
+            "sticky" => Ok(PartitionerKind::Sticky),
+            _ => Err(format!(
+                "Unknown partitioner {}, expected default, key-hash, round-robin or sticky",
+                value
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn topic(ordering_mode: OrderingMode) -> Topic {
+        Topic::new(
+            "t1".to_string(),
+            Some(3),
+            Some(1),
+            Some(1),
+            Some(10),
+            Some(ordering_mode),
+        )
+    }
+
+    fn message(key: Option<&str>) -> Message {
+        Message {
+            payload: Some(Bytes::from_static(b"payload")),
+            key: key.map(|key| Bytes::copy_from_slice(key.as_bytes())),
+            timestamp: None,
+            headers: vec![],
+        }
+    }
+
+    #[test]
+    fn test_default_partitioner_should_pin_keys_only_for_strict_topics() {
+        let mut partitioner = DefaultPartitioner::default();
+        let strict = topic(OrderingMode::Strict);
+        let first = partitioner.partition(&strict, &message(Some("key")));
+        for _ in 0..5 {
+            assert_eq!(partitioner.partition(&strict, &message(Some("key"))), first);
+        }
+        assert_eq!(partitioner.partition(&strict, &message(None)), 0);
+
+        let relaxed = topic(OrderingMode::Relaxed);
+        let partitions: Vec<u8> = (0..2)
+            .map(|_| partitioner.partition(&relaxed, &message(Some("key"))))
+            .collect();
+        assert_eq!(partitions, vec![0, 0]);
+        partitioner.on_new_batch("t1");
+        assert_eq!(partitioner.partition(&relaxed, &message(Some("key"))), 1);
+    }
+
+    #[test]
+    fn test_murmur2_should_match_kafka() {
+        // expected values are Kafka's `Utils.murmur2` results, which are signed ints
+        let cases: [(&str, i32); 6] = [
+            ("21", -973932308),
+            ("foobar", -790332482),
+            ("a-little-bit-long-string", -985981536),
+            ("a-little-bit-longer-string", -1486304829),
+            (
+                "lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8",
+                -58897971,
+            ),
+            ("abc", 479470107),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(murmur2(key.as_bytes()) as i32, expected, "key {}", key);
+        }
+    }
+
+    #[test]
+    fn test_key_hash_partitioner_should_match_kafka_partitions() {
+        let mut partitioner = KeyHashPartitioner::new(KeyHashAlgorithm::Murmur2);
+        let topic = topic(OrderingMode::Strict);
+        // (-790332482 & 0x7fffffff) % 3
+        assert_eq!(partitioner.partition(&topic, &message(Some("foobar"))), 0);
+        // (-985981536 & 0x7fffffff) % 3
+        assert_eq!(
+            partitioner.partition(&topic, &message(Some("a-little-bit-long-string"))),
+            2
+        );
+    }
+
+    #[test]
+    fn test_round_robin_partitioner_should_cycle_through_partitions() {
+        let mut partitioner = RoundRobinPartitioner::default();
+        let topic = topic(OrderingMode::Strict);
+        let partitions: Vec<u8> = (0..4)
+            .map(|_| partitioner.partition(&topic, &message(Some("key"))))
+            .collect();
+        assert_eq!(partitions, vec![0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn test_sticky_partitioner_should_move_on_after_each_batch() {
+        let mut partitioner = StickyPartitioner::default();
+        let topic = topic(OrderingMode::Relaxed);
+        let mut partitions = vec![];
+        for _ in 0..4 {
+            partitions.push(partitioner.partition(&topic, &message(None)));
+            partitions.push(partitioner.partition(&topic, &message(None)));
+            partitioner.on_new_batch("t1");
+        }
+        assert_eq!(partitions, vec![0, 0, 1, 1, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_closures_should_work_as_partitioners() {
+        let mut partitioner = |topic: &Topic, message: &Message| {
+            message.payload_len() as u8 % topic.num_partitions.unwrap()
+        };
+        assert_eq!(
+            partitioner.partition(&topic(OrderingMode::Strict), &message(None)),
+            1
+        );
+    }
+}
@@ -0,0 +1,1068 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use common::{
+    codecs::protocol::API_VERSION,
+    errors::{ProduceError, WalrsError},
+    models::{
+        Acks, ApiKey, Batch, BrokerResponse, CompressionCodec, Message, ProduceResponse,
+        ProducerSequence, TopicCommand, TopicMetadata, TopicPartition, VersionRange,
+        BATCH_FORMAT_VERSION,
+    },
+    trace_context,
+};
+use futures::{stream::FuturesUnordered, Sink, StreamExt};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{self, Instant},
+};
+use tokio_util::sync::PollSender;
+
+use crate::client::WalrsClient;
+use crate::config::{ConfigError, ConnectionConfig};
+use crate::partitioner::{DefaultPartitioner, Partitioner};
+
+const PRODUCER_CHANNEL_SIZE: usize = 1000;
+/// Requests a producer sends, the broker must handle `API_VERSION` of each.
+const PRODUCER_API_KEYS: [ApiKey; 3] = [
+    ApiKey::Metadata,
+    ApiKey::InitProducerId,
+    ApiKey::WriteToTopic,
+];
+
+#[derive(Debug, Clone)]
+pub struct ProducerConfig {
+    pub connection: ConnectionConfig,
+    /// `batch.size`, records accumulated for a partition before they are sent
+    pub batch_size: usize,
+    /// `linger.ms`, how long records wait for their batch to fill up before it is sent anyway
+    pub linger: Duration,
+    pub acks: Acks,
+    /// `enable.idempotence`, numbers the records of every partition so the broker can drop
+    /// batches it already appended when they are sent again
+    pub enable_idempotence: bool,
+    /// `retry.backoff.ms`, wait before the first retry of a batch which failed with a retriable
+    /// error, doubled for every further retry
+    pub retry_backoff: Duration,
+    /// `retry.backoff.max.ms`
+    pub retry_backoff_max: Duration,
+    /// `delivery.timeout.ms`, how long after a record was sent its batch is retried at most
+    pub delivery_timeout: Duration,
+    /// `compression.type`, codec the records of every batch are compressed with
+    pub compression: CompressionCodec,
+}
+
+impl ProducerConfig {
+    pub fn new(connection: ConnectionConfig) -> Self {
+        ProducerConfig {
+            connection,
+            batch_size: 100,
+            linger: Duration::from_millis(5),
+            acks: Acks::default(),
+            enable_idempotence: true,
+            retry_backoff: Duration::from_millis(100),
+            retry_backoff_max: Duration::from_secs(1),
+            delivery_timeout: Duration::from_secs(120),
+            compression: CompressionCodec::None,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.connection.validate()?;
+        if self.batch_size == 0 {
+            return Err(ConfigError("batch size is zero".to_string()));
+        }
+        if self.retry_backoff > self.retry_backoff_max {
+            return Err(ConfigError(format!(
+                "retry backoff of {:?} is longer than its maximum of {:?}",
+                self.retry_backoff, self.retry_backoff_max
+            )));
+        }
+        // like Kafka, a record must get the chance to linger and be sent once
+        let shortest_delivery_timeout = self.linger + self.connection.request_timeout;
+        if self.delivery_timeout < shortest_delivery_timeout {
+            return Err(ConfigError(format!(
+                "delivery timeout of {:?} is shorter than linger plus the request timeout, {:?}",
+                self.delivery_timeout, shortest_delivery_timeout
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Builds a producer, checking its settings work together before it starts.
+pub struct ProducerBuilder {
+    config: ProducerConfig,
+    partitioner: Box<dyn Partitioner>,
+}
+
+impl ProducerBuilder {
+    pub fn new(connection: ConnectionConfig) -> Self {
+        ProducerBuilder {
+            config: ProducerConfig::new(connection),
+            partitioner: Box::new(DefaultPartitioner::default()),
+        }
+    }
+
+    pub fn acks(mut self, acks: Acks) -> Self {
+        self.config.acks = acks;
+        self
+    }
+
+    pub fn compression(mut self, compression: CompressionCodec) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.config.batch_size = batch_size;
+        self
+    }
+
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.config.linger = linger;
+        self
+    }
+
+    /// Picks the partition of every record instead of the `DefaultPartitioner`.
+    pub fn partitioner(mut self, partitioner: Box<dyn Partitioner>) -> Self {
+        self.partitioner = partitioner;
+        self
+    }
+
+    /// Must be called within a tokio runtime.
+    pub fn build(self) -> Result<Producer, ConfigError> {
+        self.config.validate()?;
+        Ok(Producer::start(self.config, self.partitioner))
+    }
+}
+
+/// Where a record was written and which guarantee the broker gave for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordMetadata {
+    pub topic_partition: TopicPartition,
+    /// Unknown with `Acks::None` since the broker does not answer.
+    pub offset: Option<u64>,
+    /// When the broker stamped the record, in milliseconds since the epoch. Unknown with
+    /// `Acks::None` too, and for topics keeping the producers' create time.
+    pub log_append_time_millis: Option<u128>,
+    pub acks: Acks,
+}
+
+type DeliveryResult = Result<RecordMetadata, ProduceError>;
+
+/// Resolves once the batch of a sent record was handled as the producer's `acks` requires.
+pub struct DeliveryFuture {
+    offset_rx: oneshot::Receiver<DeliveryResult>,
+}
+
+impl Future for DeliveryFuture {
+    type Output = DeliveryResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.offset_rx)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(ProduceError::Closed)))
+    }
+}
+
+enum ProducerCommands {
+    Send {
+        topic_name: String,
+        message: Message,
+        offset_tx: oneshot::Sender<DeliveryResult>,
+    },
+    Flush {
+        reply_tx: oneshot::Sender<()>,
+    },
+}
+
+/// Sends records asynchronously. Records are accumulated per partition by a background task and
+/// a partition's batch is sent when it holds `batch_size` records or its oldest record waited for
+/// `linger`, whichever comes first.
+pub struct Producer {
+    commands_tx: mpsc::Sender<ProducerCommands>,
+}
+
+impl Producer {
+    fn start(config: ProducerConfig, partitioner: Box<dyn Partitioner>) -> Self {
+        let (commands_tx, commands_rx) = mpsc::channel(PRODUCER_CHANNEL_SIZE);
+        tokio::spawn(RecordAccumulator::new(config, partitioner).run(commands_rx));
+        Producer { commands_tx }
+    }
+
+    /// Queues `message` for `topic_name`. Waits only while the producer's queue is full, the
+    /// returned future resolves once the record was written. Records sent within a span carry
+    /// its trace context in their headers.
+    pub async fn send(
+        &self,
+        topic_name: String,
+        mut message: Message,
+    ) -> Result<DeliveryFuture, ProduceError> {
+        trace_context::inject_current_span(&mut message.headers);
+        let (offset_tx, offset_rx) = oneshot::channel();
+        self.commands_tx
+            .send(ProducerCommands::Send {
+                topic_name,
+                message,
+                offset_tx,
+            })
+            .await
+            .map_err(|_| ProduceError::Closed)?;
+        Ok(DeliveryFuture { offset_rx })
+    }
+
+    /// Sends every accumulated record without waiting for linger and returns once they were
+    /// written.
+    pub async fn flush(&self) {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self
+            .commands_tx
+            .send(ProducerCommands::Flush { reply_tx })
+            .await
+            .is_ok()
+        {
+            let _ = reply_rx.await;
+        }
+    }
+
+    pub async fn close(self) {
+        self.flush().await;
+    }
+
+    /// The producer as a `Sink` of records.
+    pub fn into_sink(self) -> ProducerSink {
+        ProducerSink {
+            commands_tx: PollSender::new(self.commands_tx),
+            deliveries: FuturesUnordered::new(),
+            flush_requested: false,
+        }
+    }
+}
+
+/// A record for `ProducerSink`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProducerRecord {
+    pub topic_name: String,
+    pub message: Message,
+}
+
+/// Producer taking records as a `Sink`, so a stream of records can be forwarded into walrs.
+/// The sink is ready while the producer's queue of `PRODUCER_CHANNEL_SIZE` records has room, so a
+/// fast stream waits for the records before it to be accumulated. Flushing sends the accumulated
+/// batches without waiting for linger and completes once every record was written. The first
+/// record which could not be written fails the sink.
+pub struct ProducerSink {
+    commands_tx: PollSender<ProducerCommands>,
+    deliveries: FuturesUnordered<DeliveryFuture>,
+    /// Whether the records sent since the last flush were flushed already.
+    flush_requested: bool,
+}
+
+impl ProducerSink {
+    /// Ready once every record sent was written, fails with the first one which was not.
+    fn poll_deliveries(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ProduceError>> {
+        while let Poll::Ready(Some(delivery)) = self.deliveries.poll_next_unpin(cx) {
+            delivery?;
+        }
+        if self.deliveries.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Sink<ProducerRecord> for ProducerSink {
+    type Error = ProduceError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // fails without waiting for the other deliveries
+        if let Poll::Ready(Err(e)) = self.poll_deliveries(cx) {
+            return Poll::Ready(Err(e));
+        }
+        self.commands_tx
+            .poll_reserve(cx)
+            .map_err(|_| ProduceError::Closed)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, record: ProducerRecord) -> Result<(), Self::Error> {
+        let mut message = record.message;
+        trace_context::inject_current_span(&mut message.headers);
+        let (offset_tx, offset_rx) = oneshot::channel();
+        self.commands_tx
+            .send_item(ProducerCommands::Send {
+                topic_name: record.topic_name,
+                message,
+                offset_tx,
+            })
+            .map_err(|_| ProduceError::Closed)?;
+        self.deliveries.push(DeliveryFuture { offset_rx });
+        self.flush_requested = false;
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !self.deliveries.is_empty() && !self.flush_requested {
+            ready!(self.commands_tx.poll_reserve(cx)).map_err(|_| ProduceError::Closed)?;
+            // the deliveries tell when the batches were written
+            let (reply_tx, _) = oneshot::channel();
+            self.commands_tx
+                .send_item(ProducerCommands::Flush { reply_tx })
+                .map_err(|_| ProduceError::Closed)?;
+            self.flush_requested = true;
+        }
+        self.poll_deliveries(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.commands_tx.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct PendingBatch {
+    records: Vec<Message>,
+    offset_txs: Vec<oneshot::Sender<DeliveryResult>>,
+    created_at: Instant,
+    deadline: Instant,
+}
+
+struct RecordAccumulator {
+    config: ProducerConfig,
+    /// Sends the producer's requests to the partitions' leaders.
+    client: WalrsClient,
+    /// Partitions of the topics records were sent to, the client follows their leaders.
+    topics: HashMap<String, TopicMetadata>,
+    partitioner: Box<dyn Partitioner>,
+    pending_batches: HashMap<TopicPartition, PendingBatch>,
+    /// Allocated by the broker before the first batch of an idempotent producer is sent.
+    producer_id: Option<u64>,
+    next_sequences: HashMap<TopicPartition, u32>,
+    /// Whether the broker was asked for its versions, once before the first batch is sent.
+    api_versions_checked: bool,
+}
+
+impl RecordAccumulator {
+    fn new(config: ProducerConfig, partitioner: Box<dyn Partitioner>) -> Self {
+        RecordAccumulator {
+            client: WalrsClient::new(config.connection.clone()),
+            config,
+            topics: HashMap::new(),
+            partitioner,
+            pending_batches: HashMap::new(),
+            producer_id: None,
+            next_sequences: HashMap::new(),
+            api_versions_checked: false,
+        }
+    }
+
+    async fn run(mut self, mut commands_rx: mpsc::Receiver<ProducerCommands>) {
+        loop {
+            let next_deadline = self
+                .pending_batches
+                .values()
+                .map(|batch| batch.deadline)
+                .min();
+            tokio::select! {
+                command = commands_rx.recv() => match command {
+                    Some(ProducerCommands::Send { topic_name, message, offset_tx }) => {
+                        self.append(topic_name, message, offset_tx).await;
+                    }
+                    Some(ProducerCommands::Flush { reply_tx }) => {
+                        self.send_batches(|_| true).await;
+                        let _ = reply_tx.send(());
+                    }
+                    None => {
+                        self.send_batches(|_| true).await;
+                        break;
+                    }
+                },
+                _ = time::sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                    let now = Instant::now();
+                    self.send_batches(|batch| batch.deadline <= now).await;
+                }
+            }
+        }
+    }
+
+    async fn append(
+        &mut self,
+        topic_name: String,
+        message: Message,
+        offset_tx: oneshot::Sender<DeliveryResult>,
+    ) {
+        let partition_index = match self.select_partition(&topic_name, &message).await {
+            Ok(partition_index) => partition_index,
+            Err(e) => {
+                let _ = offset_tx.send(Err(e));
+                return;
+            }
+        };
+        let topic_partition = TopicPartition::new(topic_name, partition_index);
+        let linger = self.config.linger;
+        let batch = self
+            .pending_batches
+            .entry(topic_partition.clone())
+            .or_insert_with(|| PendingBatch {
+                records: vec![],
+                offset_txs: vec![],
+                created_at: Instant::now(),
+                deadline: Instant::now() + linger,
+            });
+        batch.records.push(message);
+        batch.offset_txs.push(offset_tx);
+        if batch.records.len() >= self.config.batch_size {
+            let batch = self.pending_batches.remove(&topic_partition).unwrap();
+            self.send_batch(topic_partition, batch).await;
+        }
+    }
+
+    async fn select_partition(
+        &mut self,
+        topic_name: &str,
+        message: &Message,
+    ) -> Result<u8, ProduceError> {
+        self.load_topic_metadata(topic_name).await?;
+        let topic = &self.topics[topic_name].topic;
+        let partition_index = self.partitioner.partition(topic, message);
+        let num_partitions = topic.num_partitions.unwrap_or(1);
+        if partition_index >= num_partitions {
+            return Err(ProduceError::InvalidBatch(format!(
+                "partitioner picked partition {} but {} has {} partitions",
+                partition_index, topic_name, num_partitions
+            )));
+        }
+        Ok(partition_index)
+    }
+
+    /// Asks a bootstrap broker for the partitions of the topic unless they are known.
+    async fn load_topic_metadata(&mut self, topic_name: &str) -> Result<(), ProduceError> {
+        if !self.topics.contains_key(topic_name) {
+            let topics = self
+                .client
+                .metadata(Some(vec![topic_name.to_string()]))
+                .await
+                .map_err(produce_error)?;
+            let Some(topic) = topics.into_iter().next() else {
+                return Err(ProduceError::UnknownTopic(topic_name.to_string()));
+            };
+            self.topics.insert(topic_name.to_string(), topic);
+        }
+        Ok(())
+    }
+
+    async fn send_batches(&mut self, is_ready: impl Fn(&PendingBatch) -> bool) {
+        let ready: Vec<TopicPartition> = self
+            .pending_batches
+            .iter()
+            .filter(|(_, batch)| is_ready(batch))
+            .map(|(topic_partition, _)| topic_partition.clone())
+            .collect();
+        for topic_partition in ready {
+            let batch = self.pending_batches.remove(&topic_partition).unwrap();
+            self.send_batch(topic_partition, batch).await;
+        }
+    }
+
+    /// Batches are sent one at a time so records of a partition are appended in send order.
+    /// Retries keep the batch's sequence so the broker appends a batch only once even if an
+    /// earlier attempt was appended without the producer learning about it.
+    async fn send_batch(&mut self, topic_partition: TopicPartition, batch: PendingBatch) {
+        tracing::debug!(
+            "Sending {} records to {:?}",
+            batch.records.len(),
+            topic_partition
+        );
+        self.partitioner.on_new_batch(&topic_partition.topic_name);
+        let mut producer = None;
+        let mut retries = 0;
+        let result = loop {
+            // an attempt never outlasts the delivery timeout, even while requests wait for
+            // their own timeout
+            let remaining = self
+                .config
+                .delivery_timeout
+                .saturating_sub(batch.created_at.elapsed());
+            let attempt = self.try_send_batch(&topic_partition, &batch.records, &mut producer);
+            let error = match time::timeout(remaining, attempt)
+                .await
+                .unwrap_or(Err(ProduceError::TimedOut))
+            {
+                Ok(appended) => break Ok(appended),
+                Err(error) if error.is_retriable() => error,
+                Err(error) => break Err(error),
+            };
+            let mut backoff = retry_backoff(
+                self.config.retry_backoff,
+                self.config.retry_backoff_max,
+                retries,
+            );
+            if let ProduceError::Throttled { throttle_time_ms } = error {
+                backoff = backoff.max(Duration::from_millis(throttle_time_ms));
+            }
+            if batch.created_at.elapsed() + backoff >= self.config.delivery_timeout {
+                break Err(error);
+            }
+            tracing::warn!(
+                "Retrying batch for {:?} in {:?} after {}",
+                topic_partition,
+                backoff,
+                error
+            );
+            time::sleep(backoff).await;
+            retries += 1;
+        };
+        match result {
+            Err(ProduceError::UnknownTopic(_)) => {
+                // the topic may have been recreated with fewer partitions
+                self.topics.remove(&topic_partition.topic_name);
+            }
+            Err(ProduceError::OutOfOrderSequence { .. }) => {
+                // the broker rejects every later batch of this producer ID, so start a new sequence
+                self.producer_id = None;
+                self.next_sequences.clear();
+            }
+            _ => {}
+        }
+        for (offset, offset_tx) in (0..).zip(batch.offset_txs) {
+            let _ = offset_tx.send(result.clone().map(|produced| {
+                RecordMetadata {
+                    topic_partition: topic_partition.clone(),
+                    offset: produced
+                        .as_ref()
+                        .map(|produced| produced.base_offset + offset),
+                    log_append_time_millis: produced
+                        .as_ref()
+                        .and_then(|produced| produced.log_append_time_millis),
+                    acks: produced.map_or(Acks::None, |produced| produced.acks),
+                }
+            }));
+        }
+    }
+
+    async fn try_send_batch(
+        &mut self,
+        topic_partition: &TopicPartition,
+        records: &[Message],
+        producer: &mut Option<ProducerSequence>,
+    ) -> Result<Option<ProduceResponse>, ProduceError> {
+        if !self.api_versions_checked {
+            check_api_versions(&self.client).await?;
+            self.api_versions_checked = true;
+        }
+        if producer.is_none() {
+            *producer = self
+                .producer_sequence(topic_partition, records.len() as u32)
+                .await?;
+        }
+        let batch = Batch {
+            records: records.to_vec(),
+            producer: *producer,
+            compression: self.config.compression,
+        };
+        self.client
+            .produce(topic_partition.clone(), batch, self.config.acks)
+            .await
+            .map_err(produce_error)
+    }
+
+    /// Sequence of the next batch for the partition, `None` unless the producer is idempotent.
+    async fn producer_sequence(
+        &mut self,
+        topic_partition: &TopicPartition,
+        record_count: u32,
+    ) -> Result<Option<ProducerSequence>, ProduceError> {
+        if !self.config.enable_idempotence {
+            return Ok(None);
+        }
+        let producer_id = match self.producer_id {
+            Some(producer_id) => producer_id,
+            None => {
+                let producer_id = init_producer_id(&self.client).await?;
+                self.producer_id = Some(producer_id);
+                producer_id
+            }
+        };
+        let next_sequence = self
+            .next_sequences
+            .entry(topic_partition.clone())
+            .or_insert(0);
+        let base_sequence = *next_sequence;
+        *next_sequence += record_count;
+        Ok(Some(ProducerSequence {
+            producer_id,
+            base_sequence,
+        }))
+    }
+}
+
+/// Exponential backoff before the retry after `retries` retries, varied by up to 20% so
+/// producers which failed at the same time do not all retry at the same time.
+fn retry_backoff(retry_backoff: Duration, retry_backoff_max: Duration, retries: u32) -> Duration {
+    let backoff = retry_backoff
+        .saturating_mul(2u32.saturating_pow(retries))
+        .min(retry_backoff_max);
+    let jitter_percent = RandomState::new().build_hasher().finish() % 41;
+    backoff.mul_f64((80 + jitter_percent) as f64 / 100.0)
+}
+
+/// Fails with `ProduceError::UnsupportedVersion` if the broker cannot handle the requests or
+/// read the batches of this producer.
+async fn check_api_versions(client: &WalrsClient) -> Result<(), ProduceError> {
+    let command = TopicCommand::ApiVersions;
+    match client.request(command).await.map_err(produce_error)? {
+        BrokerResponse::ApiVersions {
+            api_versions,
+            batch_format_versions,
+        } => match unsupported_version(&api_versions, batch_format_versions) {
+            Some(reason) => Err(ProduceError::UnsupportedVersion(reason)),
+            None => Ok(()),
+        },
+        response => Err(ProduceError::UnexpectedResponse(format!("{:?}", response))),
+    }
+}
+
+/// What the producer needs but the broker does not support, if anything.
+fn unsupported_version(
+    api_versions: &[(u16, VersionRange)],
+    batch_format_versions: VersionRange,
+) -> Option<String> {
+    for api_key in PRODUCER_API_KEYS {
+        let supported = api_versions
+            .iter()
+            .any(|(key, versions)| *key == api_key as u16 && versions.contains(API_VERSION));
+        if !supported {
+            return Some(format!("version {} of {:?} requests", API_VERSION, api_key));
+        }
+    }
+    if !batch_format_versions.contains(BATCH_FORMAT_VERSION as u16) {
+        return Some(format!("batch format version {}", BATCH_FORMAT_VERSION));
+    }
+    None
+}
+
+async fn init_producer_id(client: &WalrsClient) -> Result<u64, ProduceError> {
+    let command = TopicCommand::InitProducerId;
+    match client.request(command).await.map_err(produce_error)? {
+        BrokerResponse::ProducerIdAllocated { producer_id } => Ok(producer_id),
+        response => Err(ProduceError::UnexpectedResponse(format!("{:?}", response))),
+    }
+}
+
+/// The producer's view of a failed request.
+fn produce_error(error: WalrsError) -> ProduceError {
+    match error {
+        WalrsError::Produce(error) => error,
+        WalrsError::UnknownTopic(topic_name) => ProduceError::UnknownTopic(topic_name),
+        WalrsError::BrokerUnavailable(error) => ProduceError::BrokerUnavailable(error),
+        WalrsError::TimedOut(_) => ProduceError::TimedOut,
+        WalrsError::AuthenticationFailed(error) => ProduceError::AuthenticationFailed(error),
+        WalrsError::UnsupportedVersion(reason) => ProduceError::UnsupportedVersion(reason),
+        error => ProduceError::UnexpectedResponse(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bytes::{Bytes, BytesMut};
+    use common::{
+        codecs::{
+            decoder::BatchDecoder,
+            protocol::{api_versions, RequestCodec, Response},
+        },
+        models::{BrokerRegistration, OrderingMode, PartitionMetadata, Topic},
+    };
+    use futures::{stream, SinkExt};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+
+    /// Answers version requests, metadata requests for a single partition topic led by
+    /// `leader_address`, by itself with `None`, producer ID requests and appends, passing on every
+    /// batch it receives. The first `timeouts` appends time out.
+    async fn start_fake_broker(
+        timeouts: usize,
+        leader_address: Option<String>,
+    ) -> (String, mpsc::UnboundedReceiver<Batch>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let broker_address = listener.local_addr().unwrap().to_string();
+        let leader_address = leader_address.unwrap_or_else(|| broker_address.clone());
+        let (batches_tx, batches_rx) = mpsc::unbounded_channel();
+        // the appends which still time out and the log end offset
+        let partition = Arc::new(Mutex::new((timeouts, 0)));
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let leader_address = leader_address.clone();
+                let batches_tx = batches_tx.clone();
+                let partition = partition.clone();
+                tokio::spawn(async move {
+                    let mut request_codec = RequestCodec::default();
+                    let mut received = BytesMut::new();
+                    loop {
+                        let request = match request_codec.decode(&mut received).unwrap() {
+                            Some(request) => request,
+                            None => {
+                                // the producer is gone
+                                if stream.read_buf(&mut received).await.unwrap_or(0) == 0 {
+                                    return;
+                                }
+                                continue;
+                            }
+                        };
+                        let response = match request.command.clone() {
+                            TopicCommand::Metadata { topic_names } => BrokerResponse::Metadata {
+                                brokers: vec![BrokerRegistration {
+                                    broker_id: 1,
+                                    address: leader_address.clone(),
+                                    rack: None,
+                                }],
+                                controller_id: Some(1),
+                                topics: topic_names
+                                    .unwrap()
+                                    .into_iter()
+                                    .map(|topic_name| TopicMetadata {
+                                        topic: Topic::new(
+                                            topic_name,
+                                            Some(1),
+                                            None,
+                                            None,
+                                            None,
+                                            Some(OrderingMode::Strict),
+                                        ),
+                                        partitions: vec![PartitionMetadata {
+                                            partition_index: 0,
+                                            leader_id: 1,
+                                            leader_epoch: 0,
+                                            replicas: vec![1],
+                                            isr: vec![1],
+                                        }],
+                                    })
+                                    .collect(),
+                            },
+                            TopicCommand::WriteToTopic {
+                                topic_name,
+                                partition_index,
+                                acks,
+                                ..
+                            } => {
+                                let mut body = BytesMut::from(&request.body[..]);
+                                let batch = BatchDecoder {}.decode(&mut body).unwrap().unwrap();
+                                batches_tx.send(batch.clone()).unwrap();
+                                let mut partition = partition.lock().unwrap();
+                                let (timeouts, log_end_offset) = &mut *partition;
+                                if *timeouts > 0 {
+                                    *timeouts -= 1;
+                                    BrokerResponse::ProduceFailed {
+                                        error: ProduceError::TimedOut,
+                                    }
+                                } else {
+                                    let base_offset = *log_end_offset;
+                                    *log_end_offset += batch.records.len() as u64;
+                                    if acks == Acks::None {
+                                        continue;
+                                    }
+                                    BrokerResponse::MessageBatchAppended(ProduceResponse {
+                                        topic_partition: TopicPartition::new(
+                                            topic_name,
+                                            partition_index,
+                                        ),
+                                        base_offset,
+                                        log_append_time_millis: Some(1_000),
+                                        acks,
+                                    })
+                                }
+                            }
+                            TopicCommand::InitProducerId => {
+                                BrokerResponse::ProducerIdAllocated { producer_id: 7 }
+                            }
+                            TopicCommand::ApiVersions => api_versions(),
+                            _ => unreachable!(),
+                        };
+                        let mut encoded_response = BytesMut::new();
+                        RequestCodec::default()
+                            .encode(
+                                Response {
+                                    correlation_id: request.header.correlation_id,
+                                    throttle_time_ms: 0,
+                                    response,
+                                },
+                                &mut encoded_response,
+                            )
+                            .unwrap();
+                        stream.write_all(&encoded_response).await.unwrap();
+                    }
+                });
+            }
+        });
+        (broker_address, batches_rx)
+    }
+
+    /// Starts a producer without checking `config`, so tests can shorten its timeouts.
+    fn producer(config: ProducerConfig) -> Producer {
+        Producer::start(config, Box::new(DefaultPartitioner::default()))
+    }
+
+    fn message(payload: &'static str) -> Message {
+        Message {
+            payload: Some(Bytes::from(payload)),
+            key: None,
+            timestamp: None,
+            headers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_producer_should_send_full_batches_and_lingering_records() {
+        let (broker_address, mut batches_rx) = start_fake_broker(0, None).await;
+        let producer = producer(ProducerConfig {
+            batch_size: 2,
+            linger: Duration::from_millis(100),
+            acks: Acks::All,
+            ..ProducerConfig::new(ConnectionConfig::new(&broker_address))
+        });
+        let offset = |delivery: DeliveryResult| delivery.unwrap().offset;
+
+        let first = producer
+            .send("test_topic".to_string(), message("first"))
+            .await
+            .unwrap();
+        let second = producer
+            .send("test_topic".to_string(), message("second"))
+            .await
+            .unwrap();
+        let first = first.await.unwrap();
+        assert_eq!(first.offset, Some(0));
+        assert_eq!(first.log_append_time_millis, Some(1_000));
+        assert_eq!(first.acks, Acks::All);
+        assert_eq!(offset(second.await), Some(1));
+        let batch = batches_rx.recv().await.unwrap();
+        assert_eq!(batch.records.len(), 2);
+        assert_eq!(
+            batch.producer,
+            Some(ProducerSequence {
+                producer_id: 7,
+                base_sequence: 0
+            })
+        );
+
+        let sent_at = Instant::now();
+        let third = producer
+            .send("test_topic".to_string(), message("third"))
+            .await
+            .unwrap();
+        assert_eq!(offset(third.await), Some(2));
+        assert!(sent_at.elapsed() >= Duration::from_millis(100));
+        let batch = batches_rx.recv().await.unwrap();
+        assert_eq!(batch.records.len(), 1);
+        assert_eq!(batch.producer.unwrap().base_sequence, 2);
+
+        producer.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_producer_without_acks_should_not_wait_for_offsets() {
+        let (broker_address, mut batches_rx) = start_fake_broker(0, None).await;
+        let producer = producer(ProducerConfig {
+            batch_size: 1,
+            acks: Acks::None,
+            enable_idempotence: false,
+            ..ProducerConfig::new(ConnectionConfig::new(&broker_address))
+        });
+
+        let delivery = producer
+            .send("test_topic".to_string(), message("first"))
+            .await
+            .unwrap();
+        let metadata = delivery.await.unwrap();
+        assert_eq!(metadata.offset, None);
+        assert_eq!(metadata.log_append_time_millis, None);
+        assert_eq!(metadata.acks, Acks::None);
+        assert_eq!(batches_rx.recv().await.unwrap().producer, None);
+        producer.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_producer_sink_should_write_a_stream_of_records_on_flush() {
+        let (broker_address, mut batches_rx) = start_fake_broker(0, None).await;
+        let mut sink = producer(ProducerConfig {
+            // only the flush at the end of the stream sends the batch
+            linger: Duration::from_secs(60),
+            ..ProducerConfig::new(ConnectionConfig::new(&broker_address))
+        })
+        .into_sink();
+        let mut records = stream::iter(["first", "second", "third"]).map(|payload| {
+            Ok(ProducerRecord {
+                topic_name: "test_topic".to_string(),
+                message: message(payload),
+            })
+        });
+
+        sink.send_all(&mut records).await.unwrap();
+        let batch = batches_rx.recv().await.unwrap();
+        assert_eq!(batch.records.len(), 3);
+        sink.close().await.unwrap();
+        assert_eq!(
+            sink.send(ProducerRecord {
+                topic_name: "test_topic".to_string(),
+                message: message("fourth"),
+            })
+            .await,
+            Err(ProduceError::Closed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_producer_should_retry_retriable_errors_with_same_sequence() {
+        let (broker_address, mut batches_rx) = start_fake_broker(2, None).await;
+        let producer = producer(ProducerConfig {
+            batch_size: 1,
+            retry_backoff: Duration::from_millis(10),
+            ..ProducerConfig::new(ConnectionConfig::new(&broker_address))
+        });
+
+        let delivery = producer
+            .send("test_topic".to_string(), message("first"))
+            .await
+            .unwrap();
+        assert_eq!(delivery.await.unwrap().offset, Some(0));
+        for _ in 0..3 {
+            let batch = batches_rx.recv().await.unwrap();
+            assert_eq!(batch.producer.unwrap().base_sequence, 0);
+        }
+        producer.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_producer_should_send_batches_to_partition_leaders() {
+        let (leader_address, mut leader_batches_rx) = start_fake_broker(0, None).await;
+        let (bootstrap_address, mut bootstrap_batches_rx) =
+            start_fake_broker(0, Some(leader_address)).await;
+        let producer = producer(ProducerConfig {
+            batch_size: 1,
+            ..ProducerConfig::new(ConnectionConfig::new(&bootstrap_address))
+        });
+
+        let delivery = producer
+            .send("test_topic".to_string(), message("first"))
+            .await
+            .unwrap();
+        assert_eq!(delivery.await.unwrap().offset, Some(0));
+        assert_eq!(leader_batches_rx.recv().await.unwrap().records.len(), 1);
+        producer.close().await;
+        assert!(bootstrap_batches_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_producer_should_give_up_after_delivery_timeout() {
+        let (broker_address, _batches_rx) = start_fake_broker(usize::MAX, None).await;
+        let producer = producer(ProducerConfig {
+            batch_size: 1,
+            retry_backoff: Duration::from_millis(10),
+            delivery_timeout: Duration::from_millis(200),
+            ..ProducerConfig::new(ConnectionConfig::new(&broker_address))
+        });
+
+        let sent_at = Instant::now();
+        let delivery = producer
+            .send("test_topic".to_string(), message("first"))
+            .await
+            .unwrap();
+        assert_eq!(delivery.await, Err(ProduceError::TimedOut));
+        assert!(sent_at.elapsed() < Duration::from_millis(200));
+        producer.close().await;
+    }
+
+    #[test]
+    fn test_unsupported_version_should_name_what_the_broker_lacks() {
+        let BrokerResponse::ApiVersions {
+            mut api_versions,
+            batch_format_versions,
+        } = api_versions()
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            unsupported_version(&api_versions, batch_format_versions),
+            None
+        );
+        let old_batch_formats = VersionRange {
+            min_version: 1,
+            max_version: 1,
+        };
+        assert_eq!(
+            unsupported_version(&api_versions, old_batch_formats),
+            Some(format!("batch format version {}", BATCH_FORMAT_VERSION))
+        );
+
+        api_versions.retain(|(api_key, _)| *api_key != ApiKey::InitProducerId as u16);
+        assert_eq!(
+            unsupported_version(&api_versions, batch_format_versions),
+            Some("version 0 of InitProducerId requests".to_string())
+        );
+        // a broker which dropped the versions this producer sends
+        let newer_versions = VersionRange {
+            min_version: API_VERSION + 1,
+            max_version: API_VERSION + 1,
+        };
+        let newer_api_versions: Vec<_> = ApiKey::ALL
+            .iter()
+            .map(|api_key| (*api_key as u16, newer_versions))
+            .collect();
+        assert_eq!(
+            unsupported_version(&newer_api_versions, batch_format_versions),
+            Some("version 0 of Metadata requests".to_string())
+        );
+    }
+
+    #[test]
+    fn test_producer_builder_should_reject_settings_which_do_not_work_together() {
+        let connection = ConnectionConfig::new("localhost:30002");
+        let config = ProducerConfig::new(connection.clone());
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = ProducerConfig {
+            batch_size: 0,
+            ..ProducerConfig::new(connection.clone())
+        };
+        assert!(config.validate().is_err());
+        let config = ProducerConfig {
+            retry_backoff: Duration::from_secs(2),
+            ..ProducerConfig::new(connection.clone())
+        };
+        assert!(config.validate().is_err());
+        let config = ProducerConfig {
+            delivery_timeout: Duration::from_secs(10),
+            ..ProducerConfig::new(connection)
+        };
+        assert!(config.validate().is_err());
+        assert!(ProducerBuilder::new(ConnectionConfig::new(""))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_retry_backoff_should_grow_exponentially_up_to_max() {
+        let base = Duration::from_millis(100);
+        let max = Duration::from_secs(1);
+        let first = retry_backoff(base, max, 0);
+        assert!(first >= Duration::from_millis(80) && first <= Duration::from_millis(120));
+        let third = retry_backoff(base, max, 2);
+        assert!(third >= Duration::from_millis(320) && third <= Duration::from_millis(480));
+        assert!(retry_backoff(base, max, 20) <= Duration::from_millis(1200));
+    }
+}
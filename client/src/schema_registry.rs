@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use common::models::Message;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::serialization::{Deserializer, SerializationError, Serializer, TypedRecord};
+
+/// First byte of payloads framed with a schema ID, as Confluent serializers write them.
+const MAGIC_BYTE: u8 = 0;
+/// Magic byte and the big endian schema ID.
+const HEADER_LENGTH: usize = 5;
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+/// Client of a Confluent compatible schema registry. Schemas and their IDs are cached, so only
+/// the first record of a schema waits for the registry.
+#[derive(Debug)]
+pub struct SchemaRegistryClient {
+    base_url: String,
+    agent: ureq::Agent,
+    schema_ids: Mutex<HashMap<(String, String), u32>>,
+    schemas: Mutex<HashMap<u32, String>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RegisterSchemaRequest<'a> {
+    schema_type: &'a str,
+    schema: &'a str,
+}
+
+#[derive(Deserialize)]
+struct RegisterSchemaResponse {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+}
+
+impl SchemaRegistryClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        SchemaRegistryClient {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            agent: ureq::Agent::new(),
+            schema_ids: Mutex::new(HashMap::new()),
+            schemas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// ID of the JSON schema under `subject`, which the registry registers unless the subject
+    /// has it already. The registry refuses schemas breaking the subject's compatibility rules.
+    pub fn register(&self, subject: &str, schema: &str) -> Result<u32, SerializationError> {
+        let cache_key = (subject.to_string(), schema.to_string());
+        if let Some(schema_id) = self.schema_ids.lock().unwrap().get(&cache_key) {
+            return Ok(*schema_id);
+        }
+        let response: RegisterSchemaResponse = self
+            .agent
+            .post(&format!("{}/subjects/{}/versions", self.base_url, subject))
+            .set("Content-Type", CONTENT_TYPE)
+            .send_json(RegisterSchemaRequest {
+                schema_type: "JSON",
+                schema,
+            })
+            .map_err(|e| registry_error("register the schema", e))?
+            .into_json()
+            .map_err(|e| registry_error("read the schema ID", e))?;
+        self.schema_ids
+            .lock()
+            .unwrap()
+            .insert(cache_key, response.id);
+        Ok(response.id)
+    }
+
+    /// The schema registered with `schema_id`.
+    pub fn schema(&self, schema_id: u32) -> Result<String, SerializationError> {
+        if let Some(schema) = self.schemas.lock().unwrap().get(&schema_id) {
+            return Ok(schema.clone());
+        }
+        let response: SchemaResponse = self
+            .agent
+            .get(&format!("{}/schemas/ids/{}", self.base_url, schema_id))
+            .set("Accept", CONTENT_TYPE)
+            .call()
+            .map_err(|e| registry_error("look up the schema", e))?
+            .into_json()
+            .map_err(|e| registry_error("read the schema", e))?;
+        self.schemas
+            .lock()
+            .unwrap()
+            .insert(schema_id, response.schema.clone());
+        Ok(response.schema)
+    }
+}
+
+fn registry_error(action: &str, error: impl ToString) -> SerializationError {
+    SerializationError(format!(
+        "could not {} with the schema registry: {}",
+        action,
+        error.to_string()
+    ))
+}
+
+/// Values as JSON framed with the ID of their schema in a schema registry, readable by Confluent
+/// JSON Schema deserializers. Producers register `schema` under the `<topic>-value` subject.
+/// Consumers look up the schema of every payload and, given the schema they expect, reject
+/// records written with another one instead of misreading them.
+#[derive(Debug, Clone)]
+pub struct JsonSchemaSerde {
+    registry: Arc<SchemaRegistryClient>,
+    schema: Option<String>,
+}
+
+impl JsonSchemaSerde {
+    pub fn new(registry: Arc<SchemaRegistryClient>, schema: Option<String>) -> Self {
+        JsonSchemaSerde { registry, schema }
+    }
+
+    /// Message for a JSON value given on the command line.
+    pub fn to_message(
+        &self,
+        topic_name: &str,
+        record: TypedRecord<String>,
+    ) -> Result<Message, SerializationError> {
+        let value: Option<serde_json::Value> = record
+            .value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| SerializationError(e.to_string()))?;
+        TypedRecord {
+            key: record.key,
+            value,
+            timestamp: record.timestamp,
+            headers: record.headers,
+        }
+        .into_message(topic_name, self)
+    }
+
+    /// Fetched record with its value as a JSON string.
+    pub fn to_record(
+        &self,
+        topic_name: &str,
+        message: Message,
+    ) -> Result<TypedRecord<String>, SerializationError> {
+        let record: TypedRecord<serde_json::Value> =
+            TypedRecord::from_message(topic_name, message, self)?;
+        Ok(TypedRecord {
+            key: record.key,
+            value: record.value.map(|value| value.to_string()),
+            timestamp: record.timestamp,
+            headers: record.headers,
+        })
+    }
+}
+
+/// Schemas are compared as JSON documents, so formatting does not count as a change.
+fn same_schema(schema: &str, other_schema: &str) -> bool {
+    match (
+        serde_json::from_str::<serde_json::Value>(schema),
+        serde_json::from_str::<serde_json::Value>(other_schema),
+    ) {
+        (Ok(schema), Ok(other_schema)) => schema == other_schema,
+        _ => schema == other_schema,
+    }
+}
+
+impl<T: Serialize> Serializer<T> for JsonSchemaSerde {
+    fn serialize(&self, topic_name: &str, value: &T) -> Result<Bytes, SerializationError> {
+        let schema = self
+            .schema
+            .as_deref()
+            .ok_or_else(|| SerializationError("no schema to register".to_string()))?;
+        let schema_id = self
+            .registry
+            .register(&format!("{}-value", topic_name), schema)?;
+        let mut payload = BytesMut::new();
+        payload.put_u8(MAGIC_BYTE);
+        payload.put_u32(schema_id);
+        serde_json::to_writer((&mut payload).writer(), value)
+            .map_err(|e| SerializationError(e.to_string()))?;
+        Ok(payload.freeze())
+    }
+}
+
+impl<T: DeserializeOwned> Deserializer<T> for JsonSchemaSerde {
+    fn deserialize(&self, _topic_name: &str, payload: &[u8]) -> Result<T, SerializationError> {
+        if payload.len() < HEADER_LENGTH || payload[0] != MAGIC_BYTE {
+            return Err(SerializationError(
+                "payload does not start with a schema ID".to_string(),
+            ));
+        }
+        let schema_id = u32::from_be_bytes(payload[1..HEADER_LENGTH].try_into().unwrap());
+        let writer_schema = self.registry.schema(schema_id)?;
+        if let Some(schema) = &self.schema {
+            if !same_schema(schema, &writer_schema) {
+                return Err(SerializationError(format!(
+                    "value was written with schema {}, not the expected one",
+                    schema_id
+                )));
+            }
+        }
+        serde_json::from_slice(&payload[HEADER_LENGTH..])
+            .map_err(|e| SerializationError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    const ORDER_SCHEMA: &str = r#"{"type": "object", "properties": {"id": {"type": "integer"}}}"#;
+
+    /// Answers registrations with schema ID 7 and lookups of it with `ORDER_SCHEMA`, counting
+    /// the requests.
+    fn fake_registry() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted_requests = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                counted_requests.fetch_add(1, Ordering::SeqCst);
+                let response_body = if request_line.starts_with("POST /subjects/orders-value/") {
+                    r#"{"id": 7}"#.to_string()
+                } else if request_line.starts_with("GET /schemas/ids/7 ") {
+                    serde_json::json!({ "schema": ORDER_SCHEMA }).to_string()
+                } else {
+                    r#"{"error_code": 40403, "message": "Schema not found"}"#.to_string()
+                };
+                let status = if response_body.contains("error_code") {
+                    "404 Not Found"
+                } else {
+                    "200 OK"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    CONTENT_TYPE,
+                    response_body.len(),
+                    response_body
+                )
+                .unwrap();
+            }
+        });
+        (format!("http://{}", address), requests)
+    }
+
+    #[test]
+    fn test_json_schema_serde_should_frame_values_with_their_schema_id() {
+        let (registry_url, requests) = fake_registry();
+        let registry = Arc::new(SchemaRegistryClient::new(registry_url));
+        let serde = JsonSchemaSerde::new(registry.clone(), Some(ORDER_SCHEMA.to_string()));
+        let value = serde_json::json!({"id": 3});
+
+        let payload = serde.serialize("orders", &value).unwrap();
+        assert_eq!(&payload[..HEADER_LENGTH], &[MAGIC_BYTE, 0, 0, 0, 7]);
+        assert_eq!(&payload[HEADER_LENGTH..], br#"{"id":3}"#);
+        // the schema ID is cached
+        serde.serialize("orders", &value).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let read_value: serde_json::Value = serde.deserialize("orders", &payload).unwrap();
+        assert_eq!(read_value, value);
+
+        let drifted_serde =
+            JsonSchemaSerde::new(registry, Some(r#"{"type": "string"}"#.to_string()));
+        assert!(
+            Deserializer::<serde_json::Value>::deserialize(&drifted_serde, "orders", &payload)
+                .is_err()
+        );
+        assert!(
+            Deserializer::<serde_json::Value>::deserialize(&serde, "orders", br#"{"id":3}"#)
+                .is_err()
+        );
+    }
+}
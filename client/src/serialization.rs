@@ -0,0 +1,279 @@
+use std::{fmt, str::FromStr};
+
+use bytes::Bytes;
+use common::models::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Turns typed values into record payloads. `topic_name` lets implementations pick a schema per
+/// topic, e.g. Avro or Protobuf serializers backed by a schema registry.
+pub trait Serializer<T>: Send + Sync {
+    fn serialize(&self, topic_name: &str, value: &T) -> Result<Bytes, SerializationError>;
+}
+
+/// Turns record payloads back into typed values.
+pub trait Deserializer<T>: Send + Sync {
+    fn deserialize(&self, topic_name: &str, payload: &[u8]) -> Result<T, SerializationError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerializationError(pub String);
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not (de)serialize value: {}", self.0)
+    }
+}
+
+impl std::error::Error for SerializationError {}
+
+/// Values as JSON, readable by consumers in any language.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonSerde;
+
+impl<T: Serialize> Serializer<T> for JsonSerde {
+    fn serialize(&self, _topic_name: &str, value: &T) -> Result<Bytes, SerializationError> {
+        serde_json::to_vec(value)
+            .map(Bytes::from)
+            .map_err(|e| SerializationError(e.to_string()))
+    }
+}
+
+impl<T: DeserializeOwned> Deserializer<T> for JsonSerde {
+    fn deserialize(&self, _topic_name: &str, payload: &[u8]) -> Result<T, SerializationError> {
+        serde_json::from_slice(payload).map_err(|e| SerializationError(e.to_string()))
+    }
+}
+
+/// Values as bincode, compact but only readable by Rust consumers sharing the type.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BincodeSerde;
+
+impl<T: Serialize> Serializer<T> for BincodeSerde {
+    fn serialize(&self, _topic_name: &str, value: &T) -> Result<Bytes, SerializationError> {
+        bincode::serialize(value)
+            .map(Bytes::from)
+            .map_err(|e| SerializationError(e.to_string()))
+    }
+}
+
+impl<T: DeserializeOwned> Deserializer<T> for BincodeSerde {
+    fn deserialize(&self, _topic_name: &str, payload: &[u8]) -> Result<T, SerializationError> {
+        bincode::deserialize(payload).map_err(|e| SerializationError(e.to_string()))
+    }
+}
+
+/// A record whose payload is a typed value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedRecord<T> {
+    pub key: Option<Bytes>,
+    /// `None` for tombstones.
+    pub value: Option<T>,
+    pub timestamp: Option<u128>,
+    pub headers: Vec<(String, Bytes)>,
+}
+
+impl<T> TypedRecord<T> {
+    pub fn into_message(
+        self,
+        topic_name: &str,
+        serializer: &dyn Serializer<T>,
+    ) -> Result<Message, SerializationError> {
+        Ok(Message {
+            payload: self
+                .value
+                .map(|value| serializer.serialize(topic_name, &value))
+                .transpose()?,
+            key: self.key,
+            timestamp: self.timestamp,
+            headers: self.headers,
+        })
+    }
+
+    pub fn from_message(
+        topic_name: &str,
+        message: Message,
+        deserializer: &dyn Deserializer<T>,
+    ) -> Result<Self, SerializationError> {
+        Ok(TypedRecord {
+            value: message
+                .payload
+                .map(|payload| deserializer.deserialize(topic_name, &payload))
+                .transpose()?,
+            key: message.key,
+            timestamp: message.timestamp,
+            headers: message.headers,
+        })
+    }
+}
+
+/// How the command line client reads and prints message values.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ValueFormat {
+    /// Payloads are written and printed as they are.
+    #[default]
+    Raw,
+    /// Values must be valid JSON.
+    Json,
+    /// Values are bincode encoded strings.
+    Bincode,
+}
+
+impl ValueFormat {
+    /// Message for a value given on the command line.
+    pub fn to_message(
+        self,
+        topic_name: &str,
+        record: TypedRecord<String>,
+    ) -> Result<Message, SerializationError> {
+        match self {
+            ValueFormat::Raw => record.into_message(topic_name, &RawSerde),
+            ValueFormat::Json => {
+                let value: Option<serde_json::Value> = record
+                    .value
+                    .map(|value| serde_json::from_str(&value))
+                    .transpose()
+                    .map_err(|e| SerializationError(e.to_string()))?;
+                TypedRecord {
+                    key: record.key,
+                    value,
+                    timestamp: record.timestamp,
+                    headers: record.headers,
+                }
+                .into_message(topic_name, &JsonSerde)
+            }
+            ValueFormat::Bincode => record.into_message(topic_name, &BincodeSerde),
+        }
+    }
+
+    /// Fetched record with its value in printable form.
+    pub fn to_record(
+        self,
+        topic_name: &str,
+        message: Message,
+    ) -> Result<TypedRecord<String>, SerializationError> {
+        match self {
+            ValueFormat::Raw => TypedRecord::from_message(topic_name, message, &RawSerde),
+            ValueFormat::Json => {
+                let record: TypedRecord<serde_json::Value> =
+                    TypedRecord::from_message(topic_name, message, &JsonSerde)?;
+                Ok(TypedRecord {
+                    key: record.key,
+                    value: record.value.map(|value| value.to_string()),
+                    timestamp: record.timestamp,
+                    headers: record.headers,
+                })
+            }
+            ValueFormat::Bincode => TypedRecord::from_message(topic_name, message, &BincodeSerde),
+        }
+    }
+}
+
+impl FromStr for ValueFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "raw" => Ok(ValueFormat::Raw),
+            "json" => Ok(ValueFormat::Json),
+            "bincode" => Ok(ValueFormat::Bincode),
+            _ => Err(format!(
+                "Unknown value format {}, expected raw, json or bincode",
+                value
+            )),
+        }
+    }
+}
+
+/// Strings as their UTF-8 bytes, invalid UTF-8 is replaced when reading.
+struct RawSerde;
+
+impl Serializer<String> for RawSerde {
+    fn serialize(&self, _topic_name: &str, value: &String) -> Result<Bytes, SerializationError> {
+        Ok(Bytes::copy_from_slice(value.as_bytes()))
+    }
+}
+
+impl Deserializer<String> for RawSerde {
+    fn deserialize(&self, _topic_name: &str, payload: &[u8]) -> Result<String, SerializationError> {
+        Ok(String::from_utf8_lossy(payload).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        items: Vec<String>,
+    }
+
+    #[test]
+    fn test_typed_records_should_round_trip_with_json_and_bincode() {
+        let record = TypedRecord {
+            key: Some(Bytes::from("customer-1")),
+            value: Some(Order {
+                id: 7,
+                items: vec!["book".to_string()],
+            }),
+            timestamp: Some(1234567890),
+            headers: vec![("trace-id".to_string(), Bytes::from("abc"))],
+        };
+
+        let message = record.clone().into_message("orders", &JsonSerde).unwrap();
+        assert_eq!(
+            message.payload,
+            Some(Bytes::from(r#"{"id":7,"items":["book"]}"#))
+        );
+        assert_eq!(message.headers, record.headers);
+        assert_eq!(
+            TypedRecord::from_message("orders", message, &JsonSerde).unwrap(),
+            record
+        );
+
+        let message = record
+            .clone()
+            .into_message("orders", &BincodeSerde)
+            .unwrap();
+        assert_eq!(
+            TypedRecord::from_message("orders", message, &BincodeSerde).unwrap(),
+            record
+        );
+
+        let tombstone = TypedRecord {
+            value: None,
+            ..record
+        };
+        let message = tombstone
+            .clone()
+            .into_message("orders", &JsonSerde)
+            .unwrap();
+        assert!(message.is_tombstone());
+        assert_eq!(
+            TypedRecord::from_message("orders", message, &JsonSerde).unwrap(),
+            tombstone
+        );
+    }
+
+    #[test]
+    fn test_deserializers_should_reject_other_types() {
+        let message = Message {
+            payload: Some(Bytes::from(r#"{"id":"not a number"}"#)),
+            key: None,
+            timestamp: None,
+            headers: vec![],
+        };
+        assert!(TypedRecord::<Order>::from_message("orders", message, &JsonSerde).is_err());
+
+        let record = TypedRecord {
+            key: None,
+            value: Some("{not json".to_string()),
+            timestamp: None,
+            headers: vec![],
+        };
+        assert!(ValueFormat::Json.to_message("orders", record).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::{Duration, SystemTime};
+
+/// Clocks further apart than this are reported, time-based features would misbehave beyond it.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+/// Wall clock time in milliseconds since the epoch.
+pub fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// How far a remote clock is ahead of the local one, negative when it is behind.
+/// `remote_millis` was read by the remote side between `sent_at_millis` and `received_at_millis`
+/// of the local clock, so it is compared with the middle of the round trip.
+pub fn estimate_skew_millis(
+    sent_at_millis: u128,
+    received_at_millis: u128,
+    remote_millis: u128,
+) -> i128 {
+    let round_trip_midpoint = (sent_at_millis + received_at_millis) / 2;
+    remote_millis as i128 - round_trip_midpoint as i128
+}
+
+pub fn is_skew_significant(skew_millis: i128) -> bool {
+    skew_millis.unsigned_abs() > MAX_CLOCK_SKEW.as_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_skew_should_compare_with_round_trip_midpoint() {
+        assert_eq!(estimate_skew_millis(1_000, 1_200, 1_100), 0);
+        assert_eq!(estimate_skew_millis(1_000, 1_200, 61_100), 60_000);
+        assert_eq!(estimate_skew_millis(61_000, 61_200, 1_100), -60_000);
+        assert!(is_skew_significant(-60_000));
+        assert!(!is_skew_significant(100));
+    }
+}
@@ -1,6 +1,6 @@
 use tokio_util::codec::{self, Decoder};
 
-use crate::models::{Batch, Message};
+use crate::models::{Batch, Message, RecordBatch};
 
 pub struct MessageDecoder {}
 
@@ -22,6 +22,7 @@ impl Decoder for MessageDecoder {
     }
 }
 
+/// Decodes batches and decompresses their records.
 pub struct BatchDecoder {}
 
 impl Decoder for BatchDecoder {
@@ -30,12 +31,9 @@ impl Decoder for BatchDecoder {
     type Error = std::io::Error;
 
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let mut codec = codec::LengthDelimitedCodec::default();
-        match codec.decode(src) {
-            Ok(Some(encoded_data)) => {
-                let decoded_data: Batch = bincode::deserialize(&encoded_data).unwrap();
-                Ok(Some(decoded_data))
-            }
+        let mut record_batch_decoder = RecordBatchDecoder {};
+        match record_batch_decoder.decode(src) {
+            Ok(Some(record_batch)) => record_batch.into_batch().map(Some),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
@@ -48,30 +46,47 @@ impl Decoder for BatchDecoder {
                 if buf.is_empty() {
                     Ok(None)
                 } else {
-                    Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "bytes remaining on stream",
-                    ))
+                    Err(std::io::Error::other("bytes remaining on stream"))
                 }
             }
         }
     }
 }
 
+/// Decodes batches leaving their records compressed, for the broker which never reads them.
+pub struct RecordBatchDecoder {}
+
+impl Decoder for RecordBatchDecoder {
+    type Item = RecordBatch;
+
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let mut codec = codec::LengthDelimitedCodec::default();
+        match codec.decode(src) {
+            Ok(Some(encoded_data)) => RecordBatch::decode(&encoded_data).map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::SystemTime;
 
     use crate::codecs::encoder::{BatchEncoder, MessageEncoder};
+    use crate::models::{CompressionCodec, ProducerSequence, TimestampType, BATCH_FORMAT_VERSION};
 
     use super::*;
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
+    use serde::Serialize;
     use tokio_util::codec::Encoder;
 
     #[test]
     fn test_message_decoder() {
         let message = Message {
-            payload: vec![1, 2, 3].into(),
+            payload: Some(vec![1, 2, 3].into()),
             key: None,
             timestamp: Some(
                 SystemTime::now()
@@ -79,6 +94,7 @@ mod tests {
                     .unwrap()
                     .as_millis(),
             ),
+            headers: vec![],
         };
         let mut encoder = MessageEncoder {
             payload_max_bytes: 10,
@@ -97,7 +113,7 @@ mod tests {
         let batch = Batch {
             records: vec![
                 Message {
-                    payload: vec![1, 2, 3].into(),
+                    payload: Some(vec![1, 2, 3].into()),
                     key: None,
                     timestamp: Some(
                         SystemTime::now()
@@ -105,9 +121,10 @@ mod tests {
                             .unwrap()
                             .as_millis(),
                     ),
+                    headers: vec![],
                 },
                 Message {
-                    payload: vec![4, 5, 6].into(),
+                    payload: Some(vec![4, 5, 6].into()),
                     key: None,
                     timestamp: Some(
                         SystemTime::now()
@@ -115,8 +132,15 @@ mod tests {
                             .unwrap()
                             .as_millis(),
                     ),
+                    headers: vec![
+                        ("trace-id".to_string(), vec![7, 8].into()),
+                        ("trace-id".to_string(), vec![9].into()),
+                    ],
                 },
+                Message::tombstone(Bytes::from_static(b"deleted-key"), None),
             ],
+            producer: None,
+            compression: CompressionCodec::Zstd,
         };
         let mut batch_encoder = BatchEncoder {};
         let mut encoded_batch_buffer = BytesMut::new();
@@ -130,5 +154,185 @@ mod tests {
             .unwrap()
             .unwrap();
         assert_eq!(decoded_batch, batch);
+
+        let record_batch = RecordBatchDecoder {}
+            .decode(&mut encoded_batch_buffer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record_batch.format_version, BATCH_FORMAT_VERSION);
+        assert_eq!(record_batch.compression, CompressionCodec::Zstd);
+        assert_eq!(record_batch.record_count, 3);
+        assert_eq!(record_batch.records().unwrap(), batch.records);
+    }
+
+    #[test]
+    fn test_record_batch_should_carry_log_append_time() {
+        let batch = Batch {
+            records: vec![
+                Message::new(vec![1].into(), None, Some(1_000)),
+                Message::new(vec![2].into(), None, Some(3_000)),
+            ],
+            producer: None,
+            compression: CompressionCodec::Gzip,
+        };
+        let mut record_batch = RecordBatch::new(batch).unwrap();
+        assert_eq!(record_batch.max_timestamp, Some(3_000));
+
+        record_batch.set_log_append_time(5_000);
+        let mut encoded_batch_buffer = BytesMut::new();
+        BatchEncoder {}
+            .encode(record_batch, &mut encoded_batch_buffer)
+            .unwrap();
+        let record_batch = RecordBatchDecoder {}
+            .decode(&mut encoded_batch_buffer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(record_batch.timestamp_type, TimestampType::LogAppendTime);
+        assert_eq!(record_batch.crc_matches(), Some(true));
+        let timestamps: Vec<_> = record_batch
+            .records()
+            .unwrap()
+            .into_iter()
+            .map(|record| record.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![Some(5_000), Some(5_000)]);
+    }
+
+    #[test]
+    fn test_record_batch_decoder_should_read_format_version_1() {
+        #[derive(Serialize)]
+        struct MessageV1 {
+            payload: Bytes,
+            key: Option<String>,
+            timestamp: Option<u128>,
+        }
+        #[derive(Serialize)]
+        struct RecordBatchV1 {
+            producer: Option<ProducerSequence>,
+            compression: CompressionCodec,
+            record_count: u32,
+            records: Bytes,
+        }
+        let records = vec![MessageV1 {
+            payload: vec![1, 2, 3].into(),
+            key: Some("key".to_string()),
+            timestamp: Some(1234567890),
+        }];
+        let encoded_records = bincode::serialize(&records).unwrap();
+        let batch = RecordBatchV1 {
+            producer: Some(ProducerSequence {
+                producer_id: 7,
+                base_sequence: 0,
+            }),
+            compression: CompressionCodec::Gzip,
+            record_count: 1,
+            records: CompressionCodec::Gzip
+                .compress(&encoded_records)
+                .unwrap()
+                .into(),
+        };
+        let mut encoded_batch_buffer = BytesMut::new();
+        codec::LengthDelimitedCodec::default()
+            .encode(
+                bincode::serialize(&batch).unwrap().into(),
+                &mut encoded_batch_buffer,
+            )
+            .unwrap();
+        let written_batch = encoded_batch_buffer.clone();
+
+        let record_batch = RecordBatchDecoder {}
+            .decode(&mut encoded_batch_buffer)
+            .unwrap()
+            .unwrap();
+        // stored as received, the batch keeps the layout it was written in
+        let mut stored_batch = BytesMut::new();
+        BatchEncoder {}
+            .encode(record_batch.clone(), &mut stored_batch)
+            .unwrap();
+        assert_eq!(stored_batch, written_batch);
+        assert_eq!(record_batch.format_version, 1);
+        assert_eq!(record_batch.producer.unwrap().producer_id, 7);
+        assert_eq!(
+            record_batch.records().unwrap(),
+            vec![Message {
+                payload: Some(vec![1, 2, 3].into()),
+                key: Some(Bytes::from_static(b"key")),
+                timestamp: Some(1234567890),
+                headers: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_record_batch_decoder_should_read_format_version_3() {
+        #[derive(Serialize)]
+        struct MessageV2 {
+            payload: Bytes,
+            key: Option<String>,
+            timestamp: Option<u128>,
+            headers: Vec<(String, Bytes)>,
+        }
+        let records = vec![MessageV2 {
+            payload: vec![1, 2, 3].into(),
+            key: Some("key".to_string()),
+            timestamp: Some(1234567890),
+            headers: vec![("trace-id".to_string(), vec![7].into())],
+        }];
+        let encoded_records = Bytes::from(bincode::serialize(&records).unwrap());
+        let batch = RecordBatch {
+            format_version: 3,
+            producer: None,
+            compression: CompressionCodec::None,
+            record_count: 1,
+            crc: Some(crc32fast::hash(&encoded_records)),
+            timestamp_type: TimestampType::CreateTime,
+            max_timestamp: None,
+            records: encoded_records,
+        };
+        let mut encoded_batch_buffer = BytesMut::new();
+        BatchEncoder {}
+            .encode(batch, &mut encoded_batch_buffer)
+            .unwrap();
+
+        let decoded_batch = BatchDecoder {}
+            .decode(&mut encoded_batch_buffer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            decoded_batch.records,
+            vec![Message {
+                payload: Some(vec![1, 2, 3].into()),
+                key: Some(Bytes::from_static(b"key")),
+                timestamp: Some(1234567890),
+                headers: vec![("trace-id".to_string(), vec![7].into())],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_batch_decoder_should_reject_records_not_matching_the_crc() {
+        let batch = Batch {
+            records: vec![Message {
+                payload: Some(vec![1, 2, 3].into()),
+                key: None,
+                timestamp: Some(1234567890),
+                headers: vec![],
+            }],
+            producer: None,
+            compression: CompressionCodec::None,
+        };
+        let mut encoded_batch_buffer = BytesMut::new();
+        BatchEncoder {}
+            .encode(batch, &mut encoded_batch_buffer)
+            .unwrap();
+        let last_byte = encoded_batch_buffer.len() - 1;
+        encoded_batch_buffer[last_byte] ^= 0xff;
+
+        let record_batch = RecordBatchDecoder {}
+            .decode(&mut encoded_batch_buffer.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(record_batch.crc_matches(), Some(false));
+        assert!(BatchDecoder {}.decode(&mut encoded_batch_buffer).is_err());
     }
 }
@@ -1,5 +1,31 @@
-use crate::models::{Batch, Message};
-use tokio_util::codec::{Encoder, LengthDelimitedCodec};
+use crate::models::{Batch, Message, RecordBatch, RecordBatchLayout};
+use bytes::BufMut;
+use serde::Serialize;
+use tokio_util::codec::Encoder;
+
+/// Largest frame the decoders accept, the default of `LengthDelimitedCodec`.
+const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Serializes `item` into `dst` behind its length, as `LengthDelimitedCodec` frames it, without
+/// an intermediate buffer so encoding into a reused `dst` does not allocate.
+fn encode_length_delimited<T: Serialize>(
+    item: &T,
+    dst: &mut bytes::BytesMut,
+) -> Result<(), std::io::Error> {
+    let invalid_input = |err: bincode::Error| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string())
+    };
+    let length = bincode::serialized_size(item).map_err(invalid_input)? as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "frame size too big",
+        ));
+    }
+    dst.reserve(4 + length);
+    dst.put_u32(length as u32);
+    bincode::serialize_into(dst.writer(), item).map_err(invalid_input)
+}
 
 pub struct MessageEncoder {
     pub payload_max_bytes: usize,
@@ -9,7 +35,7 @@ impl Encoder<Message> for MessageEncoder {
     type Error = std::io::Error;
 
     fn encode(&mut self, message: Message, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
-        if message.payload.len() > self.payload_max_bytes {
+        if message.payload_len() > self.payload_max_bytes {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 format!(
@@ -18,23 +44,27 @@ impl Encoder<Message> for MessageEncoder {
                 ),
             ));
         }
-        let encoded_data = bincode::serialize(&message).map_err(|err| {
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string())
-        })?;
-        LengthDelimitedCodec::default().encode(encoded_data.into(), dst)
+        encode_length_delimited(&message, dst)
     }
 }
 
+/// Compresses the records of a `Batch` with its codec, a `RecordBatch` is encoded as it is in the
+/// layout of its format version.
 pub struct BatchEncoder {}
 
 impl Encoder<Batch> for BatchEncoder {
     type Error = std::io::Error;
 
     fn encode(&mut self, item: Batch, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
-        let encoded_data = bincode::serialize(&item).map_err(|err| {
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, err.to_string())
-        })?;
-        LengthDelimitedCodec::default().encode(encoded_data.into(), dst)
+        self.encode(RecordBatch::new(item)?, dst)
+    }
+}
+
+impl Encoder<RecordBatch> for BatchEncoder {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: RecordBatch, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
+        encode_length_delimited(&RecordBatchLayout::from(item), dst)
     }
 }
 
@@ -51,7 +81,7 @@ mod tests {
     #[test]
     fn test_encode() {
         let message = Message {
-            payload: vec![1, 2, 3].into(),
+            payload: Some(vec![1, 2, 3].into()),
             key: None,
             timestamp: Some(
                 SystemTime::now()
@@ -59,6 +89,7 @@ mod tests {
                     .unwrap()
                     .as_millis(),
             ),
+            headers: vec![],
         };
         let mut encoder = MessageEncoder {
             payload_max_bytes: 10,
@@ -75,7 +106,7 @@ mod tests {
     #[test]
     fn test_encode_payload_too_large() {
         let message = Message {
-            payload: vec![0, 99].into(),
+            payload: Some(vec![0, 99].into()),
             key: None,
             timestamp: Some(
                 SystemTime::now()
@@ -83,6 +114,7 @@ mod tests {
                     .unwrap()
                     .as_millis(),
             ),
+            headers: vec![],
         };
         let mut encoder = MessageEncoder {
             payload_max_bytes: 10,
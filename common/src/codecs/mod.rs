@@ -1,2 +1,4 @@
 pub mod decoder;
 pub mod encoder;
+pub mod protocol;
+pub mod records;
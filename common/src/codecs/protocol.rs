@@ -0,0 +1,357 @@
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+use crate::models::{ApiKey, BrokerResponse, TopicCommand, VersionRange, BATCH_FORMAT_VERSION};
+
+/// Largest request or response by default, like Kafka's `socket.request.max.bytes`.
+pub const MAX_FRAME_LENGTH: usize = 100 * 1024 * 1024;
+/// Version of the requests this build sends.
+pub const API_VERSION: u16 = 0;
+/// Versions of every request this build of the broker handles. A new request version raises
+/// `API_VERSION` and keeps the older ones here as long as the broker still handles them.
+pub const SUPPORTED_API_VERSIONS: VersionRange = VersionRange {
+    min_version: 0,
+    max_version: API_VERSION,
+};
+/// Batch format versions this build reads, see `BATCH_FORMAT_VERSION`.
+pub const SUPPORTED_BATCH_FORMAT_VERSIONS: VersionRange = VersionRange {
+    min_version: 1,
+    max_version: BATCH_FORMAT_VERSION as u16,
+};
+
+/// The answer of this build to `TopicCommand::ApiVersions`.
+pub fn api_versions() -> BrokerResponse {
+    BrokerResponse::ApiVersions {
+        api_versions: ApiKey::ALL
+            .iter()
+            .map(|api_key| (*api_key as u16, SUPPORTED_API_VERSIONS))
+            .collect(),
+        batch_format_versions: SUPPORTED_BATCH_FORMAT_VERSIONS,
+    }
+}
+
+static NEXT_CORRELATION_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Correlation ID of a new request, unique within the process so responses can be matched to
+/// their request on any connection.
+pub fn next_correlation_id() -> u32 {
+    NEXT_CORRELATION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Sent before every request, like Kafka's request header. Brokers echo the correlation ID in
+/// their response so clients can match responses to requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestHeader {
+    pub api_key: ApiKey,
+    pub api_version: u16,
+    pub correlation_id: u32,
+    /// Names the client in the broker's logs.
+    pub client_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub header: RequestHeader,
+    pub command: TopicCommand,
+    /// Data following the command, the encoded batch of a `TopicCommand::WriteToTopic`.
+    pub body: Bytes,
+}
+
+impl Request {
+    pub fn new(correlation_id: u32, client_id: &str, command: TopicCommand, body: Bytes) -> Self {
+        Request {
+            header: RequestHeader {
+                api_key: command.api_key(),
+                api_version: API_VERSION,
+                correlation_id,
+                client_id: client_id.to_string(),
+            },
+            command,
+            body,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub correlation_id: u32,
+    /// How long the broker delayed the response because the client exceeded one of its quotas,
+    /// like the `throttle_time_ms` of Kafka's responses.
+    pub throttle_time_ms: u32,
+    pub response: BrokerResponse,
+}
+
+/// Failure to decode a request on a broker connection.
+#[derive(Debug)]
+pub enum RequestError {
+    /// The connection failed or sent a malformed frame, it cannot be used anymore.
+    Io(io::Error),
+    /// The broker does not handle the version of this request. Its frame was skipped, so the
+    /// broker can answer with `BrokerResponse::UnsupportedVersion` and read the next request.
+    UnsupportedVersion(RequestHeader),
+}
+
+impl From<io::Error> for RequestError {
+    fn from(error: io::Error) -> Self {
+        RequestError::Io(error)
+    }
+}
+
+/// Frames on a broker connection, length prefixed. A request frame holds the API key, API
+/// version, correlation ID and the client ID as a length prefixed string, followed by the
+/// bincode encoded command and its body. A response frame holds the correlation ID and the
+/// throttle time followed by the bincode encoded response. Brokers decode requests and encode responses with it.
+#[derive(Debug)]
+pub struct RequestCodec {
+    frame_codec: LengthDelimitedCodec,
+}
+
+/// The client side of `RequestCodec`, encodes requests and decodes responses.
+#[derive(Debug)]
+pub struct ResponseCodec {
+    frame_codec: LengthDelimitedCodec,
+}
+
+fn frame_codec(max_frame_length: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(max_frame_length)
+        .new_codec()
+}
+
+fn invalid_data(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+impl RequestCodec {
+    /// Fails on frames announcing more than `max_frame_length` bytes as soon as their length is
+    /// read, so a client can't make the broker buffer a huge frame.
+    pub fn new(max_frame_length: usize) -> Self {
+        RequestCodec {
+            frame_codec: frame_codec(max_frame_length),
+        }
+    }
+}
+
+impl Default for RequestCodec {
+    fn default() -> Self {
+        RequestCodec::new(MAX_FRAME_LENGTH)
+    }
+}
+
+impl Default for ResponseCodec {
+    fn default() -> Self {
+        ResponseCodec {
+            frame_codec: frame_codec(MAX_FRAME_LENGTH),
+        }
+    }
+}
+
+impl Decoder for RequestCodec {
+    type Item = Request;
+
+    type Error = RequestError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(mut frame) = self.frame_codec.decode(src)? else {
+            return Ok(None);
+        };
+        if frame.len() < 10 {
+            return Err(invalid_data("request header is truncated").into());
+        }
+        let api_key = ApiKey::try_from(frame.get_u16())
+            .map_err(|api_key| invalid_data(format!("unknown API key {}", api_key)))?;
+        let api_version = frame.get_u16();
+        let correlation_id = frame.get_u32();
+        let client_id_length = frame.get_u16() as usize;
+        if frame.len() < client_id_length {
+            return Err(invalid_data("client ID is truncated").into());
+        }
+        let client_id =
+            String::from_utf8(frame.split_to(client_id_length).to_vec()).map_err(invalid_data)?;
+        let header = RequestHeader {
+            api_key,
+            api_version,
+            correlation_id,
+            client_id,
+        };
+        // commands of other versions may not decode as the ones of this build
+        if !SUPPORTED_API_VERSIONS.contains(api_version) {
+            return Err(RequestError::UnsupportedVersion(header));
+        }
+        let command: TopicCommand = bincode::deserialize(&frame).map_err(invalid_data)?;
+        if command.api_key() != api_key {
+            return Err(invalid_data(format!(
+                "{:?} request holds a {:?} command",
+                api_key,
+                command.api_key()
+            ))
+            .into());
+        }
+        let command_size = bincode::serialized_size(&command).map_err(invalid_data)? as usize;
+        frame.advance(command_size);
+        Ok(Some(Request {
+            header,
+            command,
+            body: frame.freeze(),
+        }))
+    }
+}
+
+impl Encoder<Response> for RequestCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let response = bincode::serialize(&item.response).map_err(invalid_data)?;
+        let mut frame = BytesMut::with_capacity(8 + response.len());
+        frame.put_u32(item.correlation_id);
+        frame.put_u32(item.throttle_time_ms);
+        frame.put_slice(&response);
+        self.frame_codec.encode(frame.freeze(), dst)
+    }
+}
+
+impl Encoder<Request> for ResponseCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let client_id = item.header.client_id.as_bytes();
+        let client_id_length = u16::try_from(client_id.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "client ID is too long"))?;
+        let command = bincode::serialize(&item.command).map_err(invalid_data)?;
+        let mut frame =
+            BytesMut::with_capacity(10 + client_id.len() + command.len() + item.body.len());
+        frame.put_u16(item.header.api_key as u16);
+        frame.put_u16(item.header.api_version);
+        frame.put_u32(item.header.correlation_id);
+        frame.put_u16(client_id_length);
+        frame.put_slice(client_id);
+        frame.put_slice(&command);
+        frame.put_slice(&item.body);
+        self.frame_codec.encode(frame.freeze(), dst)
+    }
+}
+
+impl Decoder for ResponseCodec {
+    type Item = Response;
+
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(mut frame) = self.frame_codec.decode(src)? else {
+            return Ok(None);
+        };
+        if frame.len() < 8 {
+            return Err(invalid_data("response header is truncated"));
+        }
+        let correlation_id = frame.get_u32();
+        let throttle_time_ms = frame.get_u32();
+        let response = bincode::deserialize(&frame).map_err(invalid_data)?;
+        Ok(Some(Response {
+            correlation_id,
+            throttle_time_ms,
+            response,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Acks, TopicPartition};
+
+    use super::*;
+
+    #[test]
+    fn test_request_codec_should_decode_requests_split_over_reads() {
+        let request = Request::new(
+            7,
+            "producer-1",
+            TopicCommand::WriteToTopic {
+                topic_name: "t1".to_string(),
+                partition_index: 0,
+                acks: Acks::All,
+                leader_epoch: Some(2),
+            },
+            Bytes::from_static(b"batch"),
+        );
+        let mut encoded = BytesMut::new();
+        ResponseCodec::default()
+            .encode(request.clone(), &mut encoded)
+            .unwrap();
+        ResponseCodec::default()
+            .encode(
+                Request::new(8, "", TopicCommand::Ping, Bytes::new()),
+                &mut encoded,
+            )
+            .unwrap();
+
+        let mut request_codec = RequestCodec::default();
+        let mut received = encoded.split_to(5);
+        assert_eq!(request_codec.decode(&mut received).unwrap(), None);
+        received.unsplit(encoded);
+        assert_eq!(request_codec.decode(&mut received).unwrap(), Some(request));
+        let ping = request_codec.decode(&mut received).unwrap().unwrap();
+        assert_eq!(ping.header.correlation_id, 8);
+        assert_eq!(ping.command, TopicCommand::Ping);
+        assert!(received.is_empty());
+
+        let response = || Response {
+            correlation_id: 7,
+            throttle_time_ms: 250,
+            response: BrokerResponse::UnknownTopicPartition {
+                topic_partition: TopicPartition::new("t1".to_string(), 0),
+            },
+        };
+        let mut encoded = BytesMut::new();
+        request_codec.encode(response(), &mut encoded).unwrap();
+        assert_eq!(
+            ResponseCodec::default().decode(&mut encoded).unwrap(),
+            Some(response())
+        );
+    }
+
+    #[test]
+    fn test_request_codec_should_reject_unknown_api_keys() {
+        let mut encoded = BytesMut::new();
+        ResponseCodec::default()
+            .encode(
+                Request::new(1, "", TopicCommand::DescribeMetrics, Bytes::new()),
+                &mut encoded,
+            )
+            .unwrap();
+        // the API key follows the 4 bytes of the frame length
+        encoded[4..6].copy_from_slice(&999u16.to_be_bytes());
+        assert!(matches!(
+            RequestCodec::default().decode(&mut encoded),
+            Err(RequestError::Io(error)) if error.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    fn test_request_codec_should_skip_requests_of_unsupported_versions() {
+        let mut unsupported = Request::new(3, "", TopicCommand::DescribeMetrics, Bytes::new());
+        unsupported.header.api_version = API_VERSION + 1;
+        let mut encoded = BytesMut::new();
+        let mut response_codec = ResponseCodec::default();
+        response_codec
+            .encode(unsupported.clone(), &mut encoded)
+            .unwrap();
+        response_codec
+            .encode(
+                Request::new(4, "", TopicCommand::ApiVersions, Bytes::new()),
+                &mut encoded,
+            )
+            .unwrap();
+
+        let mut request_codec = RequestCodec::default();
+        assert!(matches!(
+            request_codec.decode(&mut encoded),
+            Err(RequestError::UnsupportedVersion(header)) if header == unsupported.header
+        ));
+        let api_versions = request_codec.decode(&mut encoded).unwrap().unwrap();
+        assert_eq!(api_versions.command, TopicCommand::ApiVersions);
+        assert!(encoded.is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+/// Codec the records section of a stored batch is compressed with. Producers pick it, the broker
+/// stores and serves batches as they were received.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CompressionCodec {
+    #[default]
+    None,
+    Gzip,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+impl CompressionCodec {
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            CompressionCodec::None => Ok(data.to_vec()),
+            CompressionCodec::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            CompressionCodec::Lz4 => {
+                let mut encoder = lz4::EncoderBuilder::new().build(Vec::new())?;
+                encoder.write_all(data)?;
+                let (compressed, result) = encoder.finish();
+                result.map(|_| compressed)
+            }
+            CompressionCodec::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+            CompressionCodec::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(io::Error::other),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self {
+            CompressionCodec::None => decompressed.extend_from_slice(data),
+            CompressionCodec::Gzip => {
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+            CompressionCodec::Lz4 => {
+                lz4::Decoder::new(data)?.read_to_end(&mut decompressed)?;
+            }
+            CompressionCodec::Zstd => decompressed = zstd::decode_all(data)?,
+            CompressionCodec::Snappy => {
+                decompressed = snap::raw::Decoder::new()
+                    .decompress_vec(data)
+                    .map_err(io::Error::other)?
+            }
+        }
+        Ok(decompressed)
+    }
+}
+
+impl FromStr for CompressionCodec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(CompressionCodec::None),
+            "gzip" => Ok(CompressionCodec::Gzip),
+            "lz4" => Ok(CompressionCodec::Lz4),
+            "zstd" => Ok(CompressionCodec::Zstd),
+            "snappy" => Ok(CompressionCodec::Snappy),
+            _ => Err(format!(
+                "Unknown compression codec {}, expected none, gzip, lz4, zstd or snappy",
+                value
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_should_round_trip() {
+        let data = "walrs ".repeat(100).into_bytes();
+        for codec in [
+            CompressionCodec::None,
+            CompressionCodec::Gzip,
+            CompressionCodec::Lz4,
+            CompressionCodec::Zstd,
+            CompressionCodec::Snappy,
+        ] {
+            let compressed = codec.compress(&data).unwrap();
+            if codec != CompressionCodec::None {
+                assert!(
+                    compressed.len() < data.len(),
+                    "{:?} did not compress",
+                    codec
+                );
+            }
+            assert_eq!(codec.decompress(&compressed).unwrap(), data);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::TopicPartition;
+
+/// Why records could not be written, shared by the broker's answers and the producer.
+/// Retriable errors are transient and the same batch may succeed when it is sent again, fatal
+/// errors fail again for the same batch.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum ProduceError {
+    /// The partition's leader moved to another broker.
+    NotLeader(TopicPartition),
+    /// The broker did not append the batch in time, it may still be appended.
+    TimedOut,
+    /// The broker asks the client to slow down for `throttle_time_ms`.
+    Throttled {
+        throttle_time_ms: u64,
+    },
+    /// The broker could not be reached or the connection broke.
+    BrokerUnavailable(String),
+    UnknownTopic(String),
+    MessageTooLarge {
+        size: usize,
+        max_size: usize,
+    },
+    /// The broker is missing batches an idempotent producer sent before this one.
+    OutOfOrderSequence {
+        topic_partition: TopicPartition,
+        expected_sequence: u32,
+        sequence: u32,
+    },
+    /// The producer's leader epoch of the partition is not the one of the broker, the producer or
+    /// the broker missed a leader change.
+    FencedLeaderEpoch {
+        topic_partition: TopicPartition,
+        leader_epoch: u32,
+        current_leader_epoch: u32,
+    },
+    InvalidBatch(String),
+    UnexpectedResponse(String),
+    /// The broker does not support the requests or the batch format of this producer.
+    UnsupportedVersion(String),
+    /// The broker rejected the producer's SASL credentials.
+    AuthenticationFailed(String),
+    /// The producer was closed before the record was sent.
+    Closed,
+    /// The broker's log directory is short of free space, the write may succeed once retention
+    /// freed some.
+    StorageFull,
+}
+
+impl ProduceError {
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            ProduceError::NotLeader(_)
+            | ProduceError::TimedOut
+            | ProduceError::Throttled { .. }
+            | ProduceError::BrokerUnavailable(_)
+            | ProduceError::StorageFull => true,
+            ProduceError::UnknownTopic(_)
+            | ProduceError::MessageTooLarge { .. }
+            | ProduceError::OutOfOrderSequence { .. }
+            | ProduceError::FencedLeaderEpoch { .. }
+            | ProduceError::InvalidBatch(_)
+            | ProduceError::UnexpectedResponse(_)
+            | ProduceError::UnsupportedVersion(_)
+            | ProduceError::AuthenticationFailed(_)
+            | ProduceError::Closed => false,
+        }
+    }
+}
+
+impl fmt::Display for ProduceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProduceError::NotLeader(topic_partition) => {
+                write!(f, "broker is not the leader of {:?}", topic_partition)
+            }
+            ProduceError::TimedOut => write!(f, "broker did not append the batch in time"),
+            ProduceError::Throttled { throttle_time_ms } => {
+                write!(f, "throttled for {} ms", throttle_time_ms)
+            }
+            ProduceError::BrokerUnavailable(error) => {
+                write!(f, "could not reach broker: {}", error)
+            }
+            ProduceError::UnknownTopic(topic_name) => write!(f, "topic {} not found", topic_name),
+            ProduceError::MessageTooLarge { size, max_size } => write!(
+                f,
+                "message of {} bytes is larger than {} bytes",
+                size, max_size
+            ),
+            ProduceError::OutOfOrderSequence {
+                topic_partition,
+                expected_sequence,
+                sequence,
+            } => write!(
+                f,
+                "expected sequence {} for {:?} but got {}, earlier batches were lost",
+                expected_sequence, topic_partition, sequence
+            ),
+            ProduceError::FencedLeaderEpoch {
+                topic_partition,
+                leader_epoch,
+                current_leader_epoch,
+            } => write!(
+                f,
+                "leader epoch {} of {:?} does not match the broker's epoch {}",
+                leader_epoch, topic_partition, current_leader_epoch
+            ),
+            ProduceError::InvalidBatch(error) => write!(f, "broker rejected batch: {}", error),
+            ProduceError::UnexpectedResponse(response) => {
+                write!(f, "unexpected response from broker: {}", response)
+            }
+            ProduceError::UnsupportedVersion(reason) => {
+                write!(f, "unsupported by the broker: {}", reason)
+            }
+            ProduceError::AuthenticationFailed(error) => {
+                write!(f, "authentication failed: {}", error)
+            }
+            ProduceError::Closed => write!(f, "producer closed"),
+            ProduceError::StorageFull => write!(f, "broker is short of disk space"),
+        }
+    }
+}
+
+impl std::error::Error for ProduceError {}
+
+/// Why a request other than a write failed, shared by the broker's managers, which answer their
+/// commands with it instead of panicking, and the client.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum WalrsError {
+    UnknownTopic(String),
+    UnknownPartition(TopicPartition),
+    /// The broker could not be reached or the connection broke.
+    BrokerUnavailable(String),
+    /// The broker did not answer within the request timeout, the request may still be handled.
+    TimedOut(String),
+    /// The manager handling the request stopped, e.g. as the broker shuts down.
+    ManagerStopped(String),
+    /// Reading or writing local files failed.
+    Io(String),
+    Produce(ProduceError),
+    /// The broker refused the client's credentials.
+    AuthenticationFailed(String),
+    /// The broker does not handle the request's version.
+    UnsupportedVersion(String),
+    /// The fetched offset is not between the log start and end offsets of the partition.
+    OffsetOutOfRange {
+        topic_partition: TopicPartition,
+        offset: u64,
+    },
+    UnexpectedResponse(String),
+}
+
+impl WalrsError {
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            WalrsError::BrokerUnavailable(_)
+            | WalrsError::TimedOut(_)
+            | WalrsError::ManagerStopped(_) => true,
+            WalrsError::Produce(error) => error.is_retriable(),
+            WalrsError::UnknownTopic(_)
+            | WalrsError::UnknownPartition(_)
+            | WalrsError::Io(_)
+            | WalrsError::AuthenticationFailed(_)
+            | WalrsError::UnsupportedVersion(_)
+            | WalrsError::OffsetOutOfRange { .. }
+            | WalrsError::UnexpectedResponse(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for WalrsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalrsError::UnknownTopic(topic_name) => write!(f, "topic {} not found", topic_name),
+            WalrsError::UnknownPartition(topic_partition) => {
+                write!(f, "partition {:?} not found", topic_partition)
+            }
+            WalrsError::BrokerUnavailable(error) => write!(f, "could not reach broker: {}", error),
+            WalrsError::TimedOut(error) => write!(f, "request timed out: {}", error),
+            WalrsError::ManagerStopped(manager) => write!(f, "{} stopped", manager),
+            WalrsError::Io(error) => write!(f, "I/O failed: {}", error),
+            WalrsError::Produce(error) => error.fmt(f),
+            WalrsError::AuthenticationFailed(error) => {
+                write!(f, "authentication failed: {}", error)
+            }
+            WalrsError::UnsupportedVersion(reason) => {
+                write!(f, "unsupported by the broker: {}", reason)
+            }
+            WalrsError::OffsetOutOfRange {
+                topic_partition,
+                offset,
+            } => write!(
+                f,
+                "offset {} of {:?} is out of range",
+                offset, topic_partition
+            ),
+            WalrsError::UnexpectedResponse(response) => {
+                write!(f, "unexpected response from broker: {}", response)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WalrsError {}
+
+impl From<ProduceError> for WalrsError {
+    fn from(error: ProduceError) -> Self {
+        WalrsError::Produce(error)
+    }
+}
+
+impl From<std::io::Error> for WalrsError {
+    fn from(error: std::io::Error) -> Self {
+        WalrsError::Io(error.to_string())
+    }
+}
@@ -1,18 +1,91 @@
+pub mod clock;
 pub mod codecs;
+pub mod compression;
+pub mod errors;
 pub mod models;
+pub mod sasl;
+pub mod tls;
+pub mod trace_context;
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
 
 use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Filter used without `RUST_LOG`.
+const DEFAULT_LOG_FILTER: &str = "debug";
+
+/// Replaces the filter of the subscriber installed by `enable_tracing`.
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Exports the spans still buffered when it is dropped.
+pub struct TracingGuard {
+    tracer_provider: Option<SdkTracerProvider>,
+}
 
-pub fn enable_tracing() {
-    let subscriber = tracing_subscriber::fmt::Subscriber::builder()
-        .with_max_level(tracing::Level::DEBUG)
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(e) = tracer_provider.shutdown() {
+                eprintln!("Could not export the remaining spans: {}", e);
+            }
+        }
+    }
+}
+
+/// Logs to stdout what the filter of `RUST_LOG` lets through, e.g. `info,walrs_broker::managers=debug`,
+/// and, when `OTEL_EXPORTER_OTLP_ENDPOINT` is set, exports spans over OTLP/HTTP as
+/// `service_name` unless `OTEL_SERVICE_NAME` names the service. Must be called before any tokio
+/// runtime is started, the guard is dropped once they stopped.
+pub fn enable_tracing(service_name: &str) -> TracingGuard {
+    let tracer_provider = std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").map(|_| {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()
+            .expect("Could not create the OTLP span exporter");
+        let service_name =
+            std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| service_name.to_string());
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build()
+    });
+    let otel_layer = tracer_provider.as_ref().map(|tracer_provider| {
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("walrs"))
+    });
+    let fmt_layer = tracing_subscriber::fmt::layer()
         .compact()
         .with_file(true)
         .with_line_number(true)
         .with_target(false)
-        .with_span_events(FmtSpan::ENTER | FmtSpan::CLOSE)
-        .with_thread_ids(true)
-        .finish();
+        // entering is not logged as request spans are entered on every poll of their future
+        .with_span_events(FmtSpan::CLOSE)
+        .with_thread_ids(true);
+    let log_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let (log_filter, log_filter_handle) = reload::Layer::new(log_filter);
+    let _ = LOG_FILTER.set(log_filter_handle);
+    let subscriber = tracing_subscriber::registry()
+        .with(log_filter)
+        .with(fmt_layer)
+        .with(otel_layer);
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
     tracing::info!("Tracing enabled!");
+    TracingGuard { tracer_provider }
+}
+
+/// Replaces the filter of what is logged and exported, e.g. with `info,walrs_broker::managers=debug`, and
+/// returns the replaced filter.
+pub fn set_log_filter(filter: &str) -> Result<String, String> {
+    let handle = LOG_FILTER.get().ok_or("tracing is not enabled")?;
+    let filter = EnvFilter::try_new(filter).map_err(|e| e.to_string())?;
+    let previous_filter = handle
+        .with_current(ToString::to_string)
+        .map_err(|e| e.to_string())?;
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    Ok(previous_filter)
 }
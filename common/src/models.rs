@@ -1,17 +1,33 @@
-use std::time::SystemTime;
+use std::{io, str::FromStr, time::SystemTime};
 
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
+use crate::codecs::records::{decode_records, encode_records};
+pub use crate::compression::CompressionCodec;
+use crate::errors::ProduceError;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct Message {
-    pub payload: Bytes,
-    pub key: Option<String>,
+    /// `None` makes a keyed record a tombstone, which marks its key as deleted.
+    pub payload: Option<Bytes>,
+    /// Any bytes, e.g. a UUID or a protobuf message. Encoded like the strings keys were before,
+    /// so batches written with string keys read the same.
+    pub key: Option<Bytes>,
     pub timestamp: Option<u128>,
+    /// Metadata such as trace context, kept in order and possibly with repeated names.
+    pub headers: Vec<(String, Bytes)>,
 }
 
+/// Header of records which consumers skip once this many milliseconds passed since their
+/// timestamp, written as a decimal number.
+pub const TTL_HEADER: &str = "ttl-ms";
+/// Header of records which consumers of topics with scheduled delivery read only from this time
+/// on, in milliseconds since the epoch written as a decimal number.
+pub const DELIVER_AT_HEADER: &str = "deliver-at";
+
 impl Message {
-    pub fn new(payload: Bytes, key: Option<String>, timestamp: Option<u128>) -> Self {
+    pub fn new(payload: Bytes, key: Option<Bytes>, timestamp: Option<u128>) -> Self {
         let message_timestamp = timestamp.unwrap_or_else(|| {
             SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
@@ -19,27 +35,616 @@ impl Message {
                 .as_millis()
         });
         Message {
-            payload,
+            payload: Some(payload),
             key,
             timestamp: Some(message_timestamp),
+            headers: vec![],
+        }
+    }
+
+    /// Record without a payload marking `key` as deleted.
+    pub fn tombstone(key: Bytes, timestamp: Option<u128>) -> Self {
+        Message {
+            payload: None,
+            ..Message::new(Bytes::new(), Some(key), timestamp)
         }
     }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.payload.is_none()
+    }
+
+    /// Whether the record's `TTL_HEADER` ran out at `now`, in milliseconds since the epoch.
+    /// Records without a timestamp or a valid TTL never expire.
+    pub fn is_expired(&self, now: u128) -> bool {
+        let ttl = self
+            .headers
+            .iter()
+            .find(|(name, _)| name == TTL_HEADER)
+            .and_then(|(_, value)| std::str::from_utf8(value).ok()?.parse::<u128>().ok());
+        matches!((self.timestamp, ttl), (Some(timestamp), Some(ttl)) if timestamp.saturating_add(ttl) <= now)
+    }
+
+    /// Size of the payload, 0 for tombstones.
+    pub fn payload_len(&self) -> usize {
+        self.payload.as_ref().map_or(0, Bytes::len)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
 pub struct Batch {
     pub records: Vec<Message>,
+    /// Set by idempotent producers so the broker can recognise retried batches.
+    pub producer: Option<ProducerSequence>,
+    /// Codec the records are compressed with when the batch is encoded.
+    pub compression: CompressionCodec,
+}
+
+/// Format of batches written by this version, encoded as the first byte of every batch.
+/// 1: batches without a format byte, whose records have no headers.
+/// 2: records carry headers.
+/// 3: batches carry the CRC-32 of their records.
+/// 4: record payloads are optional, tombstones have none.
+/// 5: batches carry their timestamp type and the largest timestamp of their records.
+/// 6: records use the compact layout of `codecs::records`, with varint lengths and offset and
+/// timestamp deltas.
+pub const BATCH_FORMAT_VERSION: u8 = 6;
+
+/// A batch as producers send it, segments store it and consumers fetch it. Only the records are
+/// compressed, so the broker appends and serves batches using the header alone.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct RecordBatch {
+    /// Layout of `records`, batches are served in the format they were written with.
+    pub format_version: u8,
+    pub producer: Option<ProducerSequence>,
+    pub compression: CompressionCodec,
+    pub record_count: u32,
+    /// CRC-32 of `records` as written by the producer, `None` in batches of format versions
+    /// before 3.
+    pub crc: Option<u32>,
+    /// Whether the records carry their create time or the broker's log append time. Not
+    /// covered by the CRC, so the broker stamps batches without touching their records.
+    pub timestamp_type: TimestampType,
+    /// Largest create time of the records, or their log append time, `None` in batches of
+    /// format versions before 5.
+    pub max_timestamp: Option<u128>,
+    /// Records encoded as the format version says, compressed with `compression`.
+    pub records: Bytes,
+}
+
+impl RecordBatch {
+    pub fn new(batch: Batch) -> io::Result<Self> {
+        let encoded_records = encode_records(&batch.records);
+        let records = batch.compression.compress(&encoded_records)?;
+        Ok(RecordBatch {
+            format_version: BATCH_FORMAT_VERSION,
+            producer: batch.producer,
+            compression: batch.compression,
+            record_count: batch.records.len() as u32,
+            crc: Some(crc32fast::hash(&records)),
+            timestamp_type: TimestampType::CreateTime,
+            max_timestamp: batch
+                .records
+                .iter()
+                .filter_map(|record| record.timestamp)
+                .max(),
+            records: records.into(),
+        })
+    }
+
+    /// Decodes a batch of any format version, in the layout its first byte names.
+    pub fn decode(encoded_batch: &[u8]) -> io::Result<Self> {
+        let layout = match encoded_batch.first() {
+            // version 1 batches start with the `Option` tag of their producer
+            Some(0) | Some(1) => RecordBatchLayout::V1(deserialize(encoded_batch)?),
+            Some(2) => RecordBatchLayout::V2(deserialize(encoded_batch)?),
+            // version 4 only changed the layout of the records
+            Some(3) | Some(4) => RecordBatchLayout::V3(deserialize(encoded_batch)?),
+            // version 6 only changed the layout of the records
+            Some(5) | Some(&BATCH_FORMAT_VERSION) => {
+                RecordBatchLayout::Current(deserialize(encoded_batch)?)
+            }
+            format_version => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown batch format version {:?}", format_version),
+                ))
+            }
+        };
+        Ok(layout.into())
+    }
+
+    /// Makes `timestamp` the timestamp of every record of the batch, for topics using log append
+    /// time. Batches of format versions before 5 have no room for it and keep their create times.
+    pub fn set_log_append_time(&mut self, timestamp: u128) {
+        if self.format_version >= 5 {
+            self.timestamp_type = TimestampType::LogAppendTime;
+            self.max_timestamp = Some(timestamp);
+        }
+    }
+
+    /// Whether the records are still the bytes the producer wrote, `None` when the batch's format
+    /// has no CRC.
+    pub fn crc_matches(&self) -> Option<bool> {
+        self.crc.map(|crc| crc == crc32fast::hash(&self.records))
+    }
+
+    pub fn records(&self) -> io::Result<Vec<Message>> {
+        if self.crc_matches() == Some(false) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Batch CRC does not match its records",
+            ));
+        }
+        let encoded_records = self.compression.decompress(&self.records)?;
+        let mut records: Vec<Message> = match self.format_version {
+            1 => {
+                let records: Vec<MessageV1> = deserialize(&encoded_records)?;
+                Ok(records
+                    .into_iter()
+                    .map(|record| Message {
+                        payload: Some(record.payload),
+                        key: record.key,
+                        timestamp: record.timestamp,
+                        headers: vec![],
+                    })
+                    .collect())
+            }
+            2 | 3 => {
+                let records: Vec<MessageV2> = deserialize(&encoded_records)?;
+                Ok(records
+                    .into_iter()
+                    .map(|record| Message {
+                        payload: Some(record.payload),
+                        key: record.key,
+                        timestamp: record.timestamp,
+                        headers: record.headers,
+                    })
+                    .collect())
+            }
+            4 | 5 => deserialize(&encoded_records),
+            _ => decode_records(&encoded_records),
+        }?;
+        if self.timestamp_type == TimestampType::LogAppendTime {
+            for record in &mut records {
+                record.timestamp = self.max_timestamp;
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn into_batch(self) -> io::Result<Batch> {
+        Ok(Batch {
+            records: self.records()?,
+            producer: self.producer,
+            compression: self.compression,
+        })
+    }
+}
+
+fn deserialize<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> io::Result<T> {
+    bincode::deserialize(bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+/// A batch in the layout of its format version, so batches stored as received are encoded again
+/// as their producer wrote them and old segments stay readable.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(crate) enum RecordBatchLayout {
+    V1(RecordBatchV1),
+    V2(RecordBatchV2),
+    V3(RecordBatchV3),
+    Current(RecordBatch),
+}
+
+impl From<RecordBatch> for RecordBatchLayout {
+    fn from(batch: RecordBatch) -> Self {
+        match batch.format_version {
+            1 => RecordBatchLayout::V1(RecordBatchV1 {
+                producer: batch.producer,
+                compression: batch.compression,
+                record_count: batch.record_count,
+                records: batch.records,
+            }),
+            2 => RecordBatchLayout::V2(RecordBatchV2 {
+                format_version: batch.format_version,
+                producer: batch.producer,
+                compression: batch.compression,
+                record_count: batch.record_count,
+                records: batch.records,
+            }),
+            3 | 4 => RecordBatchLayout::V3(RecordBatchV3 {
+                format_version: batch.format_version,
+                producer: batch.producer,
+                compression: batch.compression,
+                record_count: batch.record_count,
+                crc: batch.crc,
+                records: batch.records,
+            }),
+            _ => RecordBatchLayout::Current(batch),
+        }
+    }
+}
+
+impl From<RecordBatchLayout> for RecordBatch {
+    fn from(layout: RecordBatchLayout) -> Self {
+        let (format_version, producer, compression, record_count, crc, records) = match layout {
+            RecordBatchLayout::V1(batch) => (
+                1,
+                batch.producer,
+                batch.compression,
+                batch.record_count,
+                None,
+                batch.records,
+            ),
+            RecordBatchLayout::V2(batch) => (
+                batch.format_version,
+                batch.producer,
+                batch.compression,
+                batch.record_count,
+                None,
+                batch.records,
+            ),
+            RecordBatchLayout::V3(batch) => (
+                batch.format_version,
+                batch.producer,
+                batch.compression,
+                batch.record_count,
+                batch.crc,
+                batch.records,
+            ),
+            RecordBatchLayout::Current(batch) => return batch,
+        };
+        RecordBatch {
+            format_version,
+            producer,
+            compression,
+            record_count,
+            crc,
+            timestamp_type: TimestampType::CreateTime,
+            max_timestamp: None,
+            records,
+        }
+    }
+}
+
+/// Batch layout of format version 1.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RecordBatchV1 {
+    producer: Option<ProducerSequence>,
+    compression: CompressionCodec,
+    record_count: u32,
+    records: Bytes,
+}
+
+/// Batch layout of format version 2.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RecordBatchV2 {
+    format_version: u8,
+    producer: Option<ProducerSequence>,
+    compression: CompressionCodec,
+    record_count: u32,
+    records: Bytes,
+}
+
+/// Batch layout of format versions 3 and 4.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RecordBatchV3 {
+    format_version: u8,
+    producer: Option<ProducerSequence>,
+    compression: CompressionCodec,
+    record_count: u32,
+    crc: Option<u32>,
+    records: Bytes,
+}
+
+/// Record layout of format version 1.
+#[derive(Deserialize)]
+struct MessageV1 {
+    payload: Bytes,
+    key: Option<Bytes>,
+    timestamp: Option<u128>,
+}
+
+/// Record layout of format versions 2 and 3.
+#[derive(Deserialize)]
+struct MessageV2 {
+    payload: Bytes,
+    key: Option<Bytes>,
+    timestamp: Option<u128>,
+    headers: Vec<(String, Bytes)>,
+}
+
+/// Sequence numbers count the records an idempotent producer sent to a partition, starting at 0.
+/// `base_sequence` is the sequence of the first record of the batch.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct ProducerSequence {
+    pub producer_id: u64,
+    pub base_sequence: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum TopicCommand {
-    CreateTopic { topic: Topic },
-    WriteToTopic { topic_name: String },
+    CreateTopic {
+        topic: Topic,
+    },
+    /// Followed by an encoded batch which is appended to the partition. Clients pick the
+    /// partition, see the client's `Partitioner`.
+    WriteToTopic {
+        topic_name: String,
+        partition_index: u8,
+        acks: Acks,
+        /// Leader epoch of the partition the producer knows, writes to a broker with another
+        /// epoch fail with `ProduceError::FencedLeaderEpoch`. `None` skips the check.
+        leader_epoch: Option<u32>,
+    },
+    DescribeTopic {
+        topic_name: String,
+    },
+    JoinGroup(JoinGroupRequest),
+    /// Keeps a member in its group, members which stop sending heartbeats are evicted.
+    Heartbeat {
+        group_id: String,
+        member_id: String,
+    },
+    LeaveGroup {
+        group_id: String,
+        member_id: String,
+    },
+    GetAssignment {
+        group_id: String,
+        member_id: String,
+    },
+    /// Sent by a member once it stopped consuming the partitions revoked from it.
+    RevocationCompleted {
+        group_id: String,
+        member_id: String,
+    },
+    CommitOffsets {
+        group_id: String,
+        offsets: Vec<PartitionOffset>,
+    },
+    /// Reports committed offset, log end offset and lag for every partition the group consumes.
+    DescribeGroup {
+        group_id: String,
+    },
+    /// Moves the committed offsets of a group for every partition of a topic.
+    /// Only allowed while the group has no members, so no consumer overwrites them right away.
+    ResetOffsets {
+        group_id: String,
+        topic_name: String,
+        to: OffsetResetTarget,
+    },
+    Fetch(FetchRequest),
+    /// Fetches the partitions of a fetch session, only partitions with new records or an error
+    /// are answered.
+    SessionFetch(SessionFetchRequest),
+    /// Raft vote request of a broker which wants to lead the cluster's metadata quorum.
+    RequestVote(VoteRequest),
+    /// Sent by the metadata quorum's leader to replicate its log, also as its heartbeat.
+    AppendEntries(AppendEntriesRequest),
+    /// Sent by brokers to the metadata quorum's leader, which appends the record to its log.
+    ProposeMetadata {
+        record: MetadataRecord,
+    },
+    /// Moves the replicas of partitions to other brokers. New replicas copy the partition from
+    /// its leader and the old replicas are removed once the new ones joined the ISR.
+    ReassignPartitions {
+        reassignments: Vec<PartitionReassignment>,
+        /// Bytes per second the new replicas copy each partition with, unlimited without a value.
+        throttle_rate: Option<u64>,
+    },
+    /// Lists the partitions whose replicas are being moved.
+    DescribeReassignments,
+    /// Moves the leadership of partitions back to their preferred replica, the first of their
+    /// replicas, when it is in sync. `None` elects the preferred leaders of every partition.
+    ElectPreferredLeaders {
+        topic_partitions: Option<Vec<TopicPartition>>,
+    },
+    /// Sent by every broker to the controller, brokers which stop sending heartbeats are
+    /// considered dead.
+    BrokerHeartbeat {
+        broker_id: u32,
+    },
+    /// Keepalive sent by clients on idle connections, answered with `BrokerResponse::Pong`.
+    Ping,
+    /// Allocates the ID of an idempotent producer.
+    InitProducerId,
+    /// Reads the counters and gauges of the broker.
+    DescribeMetrics,
+    /// Lists the request versions and batch formats the broker supports, so clients can use
+    /// versions both sides understand.
+    ApiVersions,
+    /// Brokers of the cluster and the leader and replicas of every partition of the topics, of
+    /// every topic with `None`. Any broker answers it, so clients bootstrap from one broker and
+    /// send writes to the leaders of the partitions.
+    Metadata {
+        topic_names: Option<Vec<String>>,
+    },
+    /// Picks the SASL mechanism of a new connection, answered with the mechanisms the broker
+    /// enables. Brokers with SASL only handle this, `SaslAuthenticate` and `ApiVersions` until
+    /// the connection is authenticated.
+    SaslHandshake {
+        mechanism: String,
+    },
+    /// Message of the SASL mechanism picked with `SaslHandshake`, answered with the broker's
+    /// challenge until the authentication completed.
+    SaslAuthenticate {
+        auth_bytes: Vec<u8>,
+    },
+    /// Sent by a broker which shuts down to the controller, which moves the leadership of its
+    /// partitions to other replicas of their ISR and removes it from the ISRs, like Kafka's
+    /// controlled shutdown. The controller does not elect it again until it stopped.
+    ControlledShutdown {
+        broker_id: u32,
+    },
+    /// Replaces the log filter of the broker until it restarts, e.g. `info,walrs_broker::managers=debug`
+    /// to debug the managers of a live broker.
+    AlterLogFilter {
+        filter: String,
+    },
+    /// Deletes a topic through the metadata log, every broker stops its partitions and removes
+    /// their files. Answered with `BrokerResponse::TopicDeleted` once this broker deleted it.
+    DeleteTopic {
+        topic_name: String,
+    },
+    /// Drains a broker before it leaves the cluster: the controller reassigns its replicas to
+    /// the other brokers and unregisters it from the metadata log once it holds none. Answered
+    /// with `BrokerResponse::DecommissionProgress`, clients send it again until it completed.
+    DecommissionBroker {
+        broker_id: u32,
+    },
+    /// Health of the cluster as the active controller sees it, answered with
+    /// `BrokerResponse::ClusterHealth`.
+    DescribeCluster,
+    /// Sent by a follower to the partition's leader after a leader change, answered with
+    /// `BrokerResponse::EpochEndOffset`. The follower truncates its log where it diverges from
+    /// the leader's before it fetches again.
+    OffsetForLeaderEpoch {
+        topic_partition: TopicPartition,
+        leader_epoch: u32,
+    },
+}
+
+impl TopicCommand {
+    pub fn api_key(&self) -> ApiKey {
+        match self {
+            TopicCommand::CreateTopic { .. } => ApiKey::CreateTopic,
+            TopicCommand::WriteToTopic { .. } => ApiKey::WriteToTopic,
+            TopicCommand::DescribeTopic { .. } => ApiKey::DescribeTopic,
+            TopicCommand::JoinGroup(_) => ApiKey::JoinGroup,
+            TopicCommand::Heartbeat { .. } => ApiKey::Heartbeat,
+            TopicCommand::LeaveGroup { .. } => ApiKey::LeaveGroup,
+            TopicCommand::GetAssignment { .. } => ApiKey::GetAssignment,
+            TopicCommand::RevocationCompleted { .. } => ApiKey::RevocationCompleted,
+            TopicCommand::CommitOffsets { .. } => ApiKey::CommitOffsets,
+            TopicCommand::DescribeGroup { .. } => ApiKey::DescribeGroup,
+            TopicCommand::ResetOffsets { .. } => ApiKey::ResetOffsets,
+            TopicCommand::Fetch(_) => ApiKey::Fetch,
+            TopicCommand::SessionFetch(_) => ApiKey::SessionFetch,
+            TopicCommand::RequestVote(_) => ApiKey::RequestVote,
+            TopicCommand::AppendEntries(_) => ApiKey::AppendEntries,
+            TopicCommand::ProposeMetadata { .. } => ApiKey::ProposeMetadata,
+            TopicCommand::ReassignPartitions { .. } => ApiKey::ReassignPartitions,
+            TopicCommand::DescribeReassignments => ApiKey::DescribeReassignments,
+            TopicCommand::ElectPreferredLeaders { .. } => ApiKey::ElectPreferredLeaders,
+            TopicCommand::BrokerHeartbeat { .. } => ApiKey::BrokerHeartbeat,
+            TopicCommand::Ping => ApiKey::Ping,
+            TopicCommand::InitProducerId => ApiKey::InitProducerId,
+            TopicCommand::DescribeMetrics => ApiKey::DescribeMetrics,
+            TopicCommand::ApiVersions => ApiKey::ApiVersions,
+            TopicCommand::Metadata { .. } => ApiKey::Metadata,
+            TopicCommand::SaslHandshake { .. } => ApiKey::SaslHandshake,
+            TopicCommand::SaslAuthenticate { .. } => ApiKey::SaslAuthenticate,
+            TopicCommand::ControlledShutdown { .. } => ApiKey::ControlledShutdown,
+            TopicCommand::AlterLogFilter { .. } => ApiKey::AlterLogFilter,
+            TopicCommand::DeleteTopic { .. } => ApiKey::DeleteTopic,
+            TopicCommand::DecommissionBroker { .. } => ApiKey::DecommissionBroker,
+            TopicCommand::DescribeCluster => ApiKey::DescribeCluster,
+            TopicCommand::OffsetForLeaderEpoch { .. } => ApiKey::OffsetForLeaderEpoch,
+        }
+    }
+}
+
+/// Identifies the type of a request in its header, like Kafka's API keys. The numbers are part
+/// of the wire protocol, new requests get the next free one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ApiKey {
+    CreateTopic = 0,
+    WriteToTopic = 1,
+    DescribeTopic = 2,
+    JoinGroup = 3,
+    Heartbeat = 4,
+    LeaveGroup = 5,
+    GetAssignment = 6,
+    RevocationCompleted = 7,
+    CommitOffsets = 8,
+    DescribeGroup = 9,
+    ResetOffsets = 10,
+    Fetch = 11,
+    RequestVote = 12,
+    AppendEntries = 13,
+    ProposeMetadata = 14,
+    ReassignPartitions = 15,
+    DescribeReassignments = 16,
+    ElectPreferredLeaders = 17,
+    BrokerHeartbeat = 18,
+    Ping = 19,
+    InitProducerId = 20,
+    DescribeMetrics = 21,
+    ApiVersions = 22,
+    Metadata = 23,
+    SaslHandshake = 24,
+    SaslAuthenticate = 25,
+    ControlledShutdown = 26,
+    AlterLogFilter = 27,
+    DeleteTopic = 28,
+    SessionFetch = 29,
+    DecommissionBroker = 30,
+    DescribeCluster = 31,
+    OffsetForLeaderEpoch = 32,
+}
+
+impl ApiKey {
+    pub const ALL: [ApiKey; 33] = [
+        ApiKey::CreateTopic,
+        ApiKey::WriteToTopic,
+        ApiKey::DescribeTopic,
+        ApiKey::JoinGroup,
+        ApiKey::Heartbeat,
+        ApiKey::LeaveGroup,
+        ApiKey::GetAssignment,
+        ApiKey::RevocationCompleted,
+        ApiKey::CommitOffsets,
+        ApiKey::DescribeGroup,
+        ApiKey::ResetOffsets,
+        ApiKey::Fetch,
+        ApiKey::RequestVote,
+        ApiKey::AppendEntries,
+        ApiKey::ProposeMetadata,
+        ApiKey::ReassignPartitions,
+        ApiKey::DescribeReassignments,
+        ApiKey::ElectPreferredLeaders,
+        ApiKey::BrokerHeartbeat,
+        ApiKey::Ping,
+        ApiKey::InitProducerId,
+        ApiKey::DescribeMetrics,
+        ApiKey::ApiVersions,
+        ApiKey::Metadata,
+        ApiKey::SaslHandshake,
+        ApiKey::SaslAuthenticate,
+        ApiKey::ControlledShutdown,
+        ApiKey::AlterLogFilter,
+        ApiKey::DeleteTopic,
+        ApiKey::SessionFetch,
+        ApiKey::DecommissionBroker,
+        ApiKey::DescribeCluster,
+        ApiKey::OffsetForLeaderEpoch,
+    ];
+}
+
+/// Oldest and newest version of a request or of the batch format a broker supports.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub struct VersionRange {
+    pub min_version: u16,
+    pub max_version: u16,
+}
+
+impl VersionRange {
+    pub fn contains(&self, version: u16) -> bool {
+        (self.min_version..=self.max_version).contains(&version)
+    }
 }
 
-impl From<Vec<u8>> for TopicCommand {
-    fn from(bytes: Vec<u8>) -> Self {
-        bincode::deserialize(&bytes).unwrap()
+impl TryFrom<u16> for ApiKey {
+    type Error = u16;
+
+    fn try_from(api_key: u16) -> Result<Self, Self::Error> {
+        ApiKey::ALL
+            .into_iter()
+            .find(|known_api_key| *known_api_key as u16 == api_key)
+            .ok_or(api_key)
     }
 }
 
@@ -50,6 +655,20 @@ pub struct Topic {
     pub replication_factor: Option<u8>,
     pub retention_period: Option<u8>,
     pub batch_size: Option<u8>,
+    pub ordering_mode: Option<OrderingMode>,
+    /// Writes waiting for a partition writer, the broker's `WALRS_PARTITION_CHANNEL_SIZE` when
+    /// `None`.
+    pub queue_size: Option<u32>,
+    /// What writes to a partition whose queue is full do, `OverflowPolicy::default()` when `None`.
+    pub overflow_policy: Option<OverflowPolicy>,
+    /// Largest batch the topic accepts, like Kafka's `max.message.bytes`, the broker's
+    /// `WALRS_MESSAGE_MAX_BYTES` when `None`.
+    pub max_message_bytes: Option<u32>,
+    /// `TimestampType::default()` when `None`.
+    pub timestamp_type: Option<TimestampType>,
+    /// Whether consumers read records with a `DELIVER_AT_HEADER` only once its time came,
+    /// `false` when `None`.
+    pub scheduled_delivery: Option<bool>,
 }
 
 impl Topic {
@@ -59,30 +678,789 @@ impl Topic {
         replication_factor: Option<u8>,
         retention_period: Option<u8>,
         batch_size: Option<u8>,
+        ordering_mode: Option<OrderingMode>,
     ) -> Self {
         let num_partitions = num_partitions.unwrap_or(3);
         let replication_factor = replication_factor.unwrap_or(2);
         let retention_period = retention_period.unwrap_or(24 * 7);
         let batch_size = batch_size.unwrap_or(10);
+        let ordering_mode = ordering_mode.unwrap_or_default();
         Topic {
             name,
             num_partitions: Some(num_partitions),
             replication_factor: Some(replication_factor),
             retention_period: Some(retention_period),
             batch_size: Some(batch_size),
+            ordering_mode: Some(ordering_mode),
+            queue_size: None,
+            overflow_policy: None,
+            max_message_bytes: None,
+            timestamp_type: None,
+            scheduled_delivery: None,
+        }
+    }
+}
+
+/// What the timestamps of a topic's records mean, like Kafka's `message.timestamp.type`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TimestampType {
+    /// The time the producer created the record.
+    #[default]
+    CreateTime,
+    /// The time the leader appended the record, so timestamps grow with offsets.
+    LogAppendTime,
+}
+
+impl FromStr for TimestampType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "create-time" => Ok(TimestampType::CreateTime),
+            "log-append-time" => Ok(TimestampType::LogAppendTime),
+            _ => Err(format!(
+                "Unknown timestamp type {}, expected create-time or log-append-time",
+                value
+            )),
+        }
+    }
+}
+
+/// Trade-off between ordering and throughput chosen per topic.
+/// `Strict` pins every key to one partition and producers keep a single batch in flight,
+/// so records with the same key are stored in the order they were sent.
+/// `Relaxed` lets producers spread records over partitions regardless of their key and
+/// producers pipeline batches, so records with the same key may be stored out of order.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OrderingMode {
+    #[default]
+    Strict,
+    Relaxed,
+}
+
+impl FromStr for OrderingMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "strict" => Ok(OrderingMode::Strict),
+            "relaxed" => Ok(OrderingMode::Relaxed),
+            _ => Err(format!(
+                "Unknown ordering mode {}, expected strict or relaxed",
+                value
+            )),
+        }
+    }
+}
+
+/// What a write to a partition does while the queue of its writer is full. Rejected and shed
+/// writes fail with `ProduceError::Throttled`, so producers retry them later.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum OverflowPolicy {
+    /// Waits up to `timeout_ms` for room in the queue, then rejects the write.
+    Block { timeout_ms: u32 },
+    /// Rejects writes right away while the queue is full.
+    Reject,
+    /// Fails the oldest waiting write to make room, favouring fresh records.
+    ShedOldest,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::Block { timeout_ms: 30_000 }
+    }
+}
+
+/// Parses `block`, `block:<timeout ms>`, `reject` or `shed-oldest`.
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().split_once(':') {
+            Some(("block", timeout_ms)) => timeout_ms
+                .parse()
+                .map(|timeout_ms| OverflowPolicy::Block { timeout_ms })
+                .map_err(|_| format!("Invalid timeout {}", timeout_ms)),
+            Some(_) => Err(format!("Unknown overflow policy {}", value)),
+            None => match value.to_lowercase().as_str() {
+                "block" => Ok(OverflowPolicy::default()),
+                "reject" => Ok(OverflowPolicy::Reject),
+                "shed-oldest" => Ok(OverflowPolicy::ShedOldest),
+                _ => Err(format!(
+                    "Unknown overflow policy {}, expected block, block:<timeout ms>, reject or \
+                     shed-oldest",
+                    value
+                )),
+            },
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct JoinGroupRequest {
+    pub group_id: String,
+    pub member_id: Option<String>,
+    /// Stable id of a static member, see `GroupCoordinator`.
+    pub group_instance_id: Option<String>,
+    pub subscription: Subscription,
+    pub strategy: AssignmentStrategy,
+    pub protocol: RebalanceProtocol,
+    /// The member is evicted when no heartbeat arrives within this time.
+    pub session_timeout_ms: u32,
+    /// The member is evicted when it does not fetch within this time, even if it still sends
+    /// heartbeats, as it is most likely stuck processing records.
+    pub max_poll_interval_ms: u32,
+}
+
+impl JoinGroupRequest {
+    pub const DEFAULT_SESSION_TIMEOUT_MS: u32 = 45_000;
+    pub const DEFAULT_MAX_POLL_INTERVAL_MS: u32 = 300_000;
+
+    pub fn new(group_id: String, subscription: Subscription) -> Self {
+        JoinGroupRequest {
+            group_id,
+            member_id: None,
+            group_instance_id: None,
+            subscription,
+            strategy: AssignmentStrategy::default(),
+            protocol: RebalanceProtocol::default(),
+            session_timeout_ms: JoinGroupRequest::DEFAULT_SESSION_TIMEOUT_MS,
+            max_poll_interval_ms: JoinGroupRequest::DEFAULT_MAX_POLL_INTERVAL_MS,
+        }
+    }
+}
+
+/// Topics a group member consumes. A pattern has to match the whole topic name and is
+/// re-evaluated by the group coordinator whenever a topic is created.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum Subscription {
+    Topics(Vec<String>),
+    Pattern(String),
+}
+
+/// Strategy used by the group coordinator to spread partitions over the members of a group.
+/// The strategy is fixed by the first member which joins the group.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum AssignmentStrategy {
+    #[default]
+    Range,
+    RoundRobin,
+    Sticky,
+}
+
+/// How a group moves partitions between members during a rebalance.
+/// `Eager` revokes every partition from every member and hands out the new assignment at once.
+/// `Cooperative` only revokes the partitions which change owner and assigns them to the new owner
+/// in a follow-up generation, so the rest of the group keeps consuming during the rebalance.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum RebalanceProtocol {
+    #[default]
+    Eager,
+    Cooperative,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Hash, PartialOrd, Ord)]
+pub struct TopicPartition {
+    pub topic_name: String,
+    pub partition_index: u8,
+}
+
+impl TopicPartition {
+    pub fn new(topic_name: String, partition_index: u8) -> Self {
+        TopicPartition {
+            topic_name,
+            partition_index,
+        }
+    }
+}
+
+/// Leader and in-sync replicas of a partition in one leader epoch.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct LeaderAndIsr {
+    pub leader_id: u32,
+    pub leader_epoch: u32,
+    pub isr: Vec<u32>,
+}
+
+/// Change to the cluster's metadata, every broker applies the records of the metadata log in
+/// the same order and so ends up with the same topics and partition leaders.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum MetadataRecord {
+    /// Appended by every new leader of the metadata quorum, Raft only commits records of
+    /// earlier leaders together with one of the current leader.
+    Noop,
+    /// `replicas` holds the brokers of every partition, indexed by partition, their first
+    /// broker leads the partition in epoch 0.
+    TopicCreated {
+        topic: Topic,
+        replicas: Vec<Vec<u32>>,
+    },
+    /// Appended when a broker starts, a broker registered again replaces its registration.
+    BrokerRegistered(BrokerRegistration),
+    /// Appended once a decommissioned broker holds no replicas, it is left out of new topics
+    /// and of the metadata clients get.
+    BrokerUnregistered { broker_id: u32 },
+    /// A new leader elected by the controller, or a new ISR reported by the partition's leader.
+    LeaderAndIsrChanged {
+        topic_partition: TopicPartition,
+        leader_and_isr: LeaderAndIsr,
+    },
+    /// Replicas of a partition changed by a reassignment, in a new leader epoch. While the
+    /// reassignment is ongoing `replicas` holds the old and the new replicas.
+    ReplicasChanged {
+        topic_partition: TopicPartition,
+        replicas: Vec<u32>,
+        adding_replicas: Vec<u32>,
+        removing_replicas: Vec<u32>,
+        /// Bytes per second the adding replicas copy the partition with.
+        throttle_rate: Option<u64>,
+        leader_and_isr: LeaderAndIsr,
+    },
+    /// Every broker stops the topic's partitions and removes their files, the topic can be
+    /// created again afterwards.
+    TopicDeleted { topic_name: String },
+}
+
+/// New replicas of a partition, the first one is the preferred leader.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct PartitionReassignment {
+    pub topic_partition: TopicPartition,
+    pub replicas: Vec<u32>,
+}
+
+/// Progress of a partition's reassignment, it completes once every adding replica is in the ISR.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ReassignmentStatus {
+    pub topic_partition: TopicPartition,
+    /// Old and new replicas.
+    pub replicas: Vec<u32>,
+    pub adding_replicas: Vec<u32>,
+    pub removing_replicas: Vec<u32>,
+    pub isr: Vec<u32>,
+}
+
+/// Largest leader epoch at or before a requested one and the offset following its last record in
+/// the leader's log, like Kafka's `OffsetsForLeaderEpoch` answer.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct EpochEndOffset {
+    pub leader_epoch: u32,
+    pub end_offset: u64,
+}
+
+/// Signals of a cluster in trouble, from the active controller.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct ClusterHealth {
+    /// Partitions with a live leader but replicas out of the ISR, new replicas of reassignments
+    /// left aside.
+    pub under_replicated_partitions: Vec<TopicPartition>,
+    /// Partitions without a live replica in their ISR to lead them.
+    pub offline_partitions: Vec<TopicPartition>,
+    /// Brokers without a heartbeat within the session timeout.
+    pub unreachable_brokers: Vec<u32>,
+    /// ISR shrinks and expansions within the last minute, a flapping follower keeps it high.
+    pub isr_changes_last_minute: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct BrokerRegistration {
+    pub broker_id: u32,
+    /// `host:port` clients and other brokers connect to.
+    pub address: String,
+    pub rack: Option<String>,
+}
+
+/// A topic and its partitions as the answering broker knows them.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TopicMetadata {
+    pub topic: Topic,
+    /// Ordered by partition index.
+    pub partitions: Vec<PartitionMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct PartitionMetadata {
+    pub partition_index: u8,
+    pub leader_id: u32,
+    pub leader_epoch: u32,
+    /// The first replica is the preferred leader.
+    pub replicas: Vec<u32>,
+    pub isr: Vec<u32>,
+}
+
+/// Record of the metadata log with the quorum leader's term it was appended in.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct MetadataEntry {
+    pub term: u64,
+    pub record: MetadataRecord,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate_id: u32,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+}
+
+/// `entries` follow the entry at `prev_log_index`, which the receiver must have with
+/// `prev_log_term`. Heartbeats have no entries.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AppendEntriesRequest {
+    pub term: u64,
+    pub leader_id: u32,
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: Vec<MetadataEntry>,
+    pub leader_commit: u64,
+}
+
+/// Reads up to `max_records` records of a partition starting at `offset`, or at the offset
+/// committed by `group_id` when no offset is given. `auto_offset_reset` decides where to start
+/// when neither is known or the offset is out of range, e.g. because its records expired.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct FetchRequest {
+    pub topic_partition: TopicPartition,
+    pub offset: Option<u64>,
+    pub group_id: Option<String>,
+    /// Fetches of a group member count as polls for its `max_poll_interval_ms`.
+    pub member_id: Option<String>,
+    pub auto_offset_reset: OffsetResetPolicy,
+    pub max_records: u32,
+    /// Set by followers copying the partition from its leader.
+    pub replica_id: Option<u32>,
+    /// Leader epoch of the partition the requester knows, fetches from a broker with another
+    /// epoch fail with `BrokerResponse::FencedLeaderEpoch`. `None` skips the check.
+    pub leader_epoch: Option<u32>,
+    /// Rack of the consumer, like Kafka's `client.rack`. Leaders in another rack answer with
+    /// `BrokerResponse::PreferredReadReplica` when an in-sync follower is in the consumer's rack.
+    pub rack_id: Option<String>,
+}
+
+/// Fetch of a consumer polling many partitions from one broker, like Kafka's incremental fetch
+/// sessions. The broker keeps the partitions of the session and the offsets it continues them
+/// from, so only partitions which were added, moved by the consumer or forgotten are sent.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SessionFetchRequest {
+    /// `None` opens a new session with `partitions`.
+    pub session_id: Option<u32>,
+    /// Number of the fetch within its session, starting at 0, so the broker notices fetches
+    /// the consumer did not get the answer of.
+    pub session_epoch: u32,
+    /// Partitions added to the session or whose offset the consumer moved, `None` starts from
+    /// `auto_offset_reset`.
+    pub partitions: Vec<(TopicPartition, Option<u64>)>,
+    pub forgotten: Vec<TopicPartition>,
+    pub auto_offset_reset: OffsetResetPolicy,
+    /// Largest number of records fetched from every partition.
+    pub max_records: u32,
+    /// Rack of the consumer, partitions with an in-sync follower in it are answered with
+    /// `BrokerResponse::PreferredReadReplica` and leave the session.
+    pub rack_id: Option<String>,
+}
+
+/// How many replicas must have appended a batch before a write is answered, like Kafka's `acks`.
+/// With `None` the broker does not answer at all.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum Acks {
+    None,
+    /// The leader appended the batch to its log.
+    #[default]
+    Leader,
+    /// Every in-sync replica appended the batch. Leaders track which followers are in sync but
+    /// do not wait for them yet, so this is the same as `Leader` for now.
+    All,
+}
+
+impl FromStr for Acks {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "0" => Ok(Acks::None),
+            "1" => Ok(Acks::Leader),
+            "all" | "-1" => Ok(Acks::All),
+            _ => Err(format!("Unknown acks {}, expected 0, 1 or all", value)),
+        }
+    }
+}
+
+/// Where a consumer starts reading when it has no valid offset, like Kafka's `auto.offset.reset`.
+/// `None` makes the broker answer with an error instead of picking an offset.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OffsetResetPolicy {
+    Earliest,
+    #[default]
+    Latest,
+    None,
+}
+
+impl OffsetResetPolicy {
+    pub fn reset_offset(&self, log_start_offset: u64, log_end_offset: u64) -> Option<u64> {
+        match self {
+            OffsetResetPolicy::Earliest => Some(log_start_offset),
+            OffsetResetPolicy::Latest => Some(log_end_offset),
+            OffsetResetPolicy::None => None,
+        }
+    }
+}
+
+impl FromStr for OffsetResetPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "earliest" => Ok(OffsetResetPolicy::Earliest),
+            "latest" => Ok(OffsetResetPolicy::Latest),
+            "none" => Ok(OffsetResetPolicy::None),
+            _ => Err(format!(
+                "Unknown offset reset policy {}, expected earliest, latest or none",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum OffsetResetTarget {
+    Earliest,
+    Latest,
+    /// Clamped to the offsets stored in each partition.
+    Offset(u64),
+    /// First record with a timestamp, in milliseconds since the epoch, at or after this one.
+    Timestamp(u128),
+}
+
+/// Parses `earliest`, `latest`, `offset:<offset>` or `timestamp:<millis>`.
+impl FromStr for OffsetResetTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Unknown reset target {}, expected earliest, latest, offset:<offset> or timestamp:<millis>",
+                value
+            )
+        };
+        match value.to_lowercase().split_once(':') {
+            None if value.eq_ignore_ascii_case("earliest") => Ok(OffsetResetTarget::Earliest),
+            None if value.eq_ignore_ascii_case("latest") => Ok(OffsetResetTarget::Latest),
+            Some(("offset", offset)) => offset
+                .parse()
+                .map(OffsetResetTarget::Offset)
+                .map_err(|_| invalid()),
+            Some(("timestamp", timestamp)) => timestamp
+                .parse()
+                .map(OffsetResetTarget::Timestamp)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A stored batch of a fetch response, served as it was written. A fetch can start or end in the
+/// middle of a batch, consumers skip the records outside of the fetched range.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct FetchedBatch {
+    pub base_offset: u64,
+    pub batch: RecordBatch,
+}
+
+impl FetchedBatch {
+    /// Decompresses `batches` and returns up to `max_records` records starting at `offset`.
+    pub fn records(
+        batches: Vec<FetchedBatch>,
+        offset: u64,
+        max_records: usize,
+    ) -> io::Result<Vec<Message>> {
+        let mut records = vec![];
+        for fetched_batch in batches {
+            let skip = offset.saturating_sub(fetched_batch.base_offset) as usize;
+            let take = max_records - records.len();
+            records.extend(
+                fetched_batch
+                    .batch
+                    .records()?
+                    .into_iter()
+                    .skip(skip)
+                    .take(take),
+            );
+        }
+        Ok(records)
+    }
+}
+
+/// Offset of the next record a group will consume from a partition.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct PartitionOffset {
+    pub topic_partition: TopicPartition,
+    pub offset: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct PartitionLag {
+    pub topic_partition: TopicPartition,
+    pub member_id: Option<String>,
+    pub committed_offset: Option<u64>,
+    pub log_end_offset: u64,
+    /// Records written to the partition but not consumed yet, unknown until the group commits.
+    pub lag: Option<u64>,
+}
+
+/// Where and when the broker appended a produced batch, so producers can deduplicate by offset
+/// and keep an audit trail of their writes.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ProduceResponse {
+    pub topic_partition: TopicPartition,
+    /// Offset of the first record of the batch, the others follow consecutively.
+    pub base_offset: u64,
+    /// When the broker stamped the batch, in milliseconds since the epoch. `None` for topics
+    /// keeping the producers' create time, where Kafka answers -1.
+    pub log_append_time_millis: Option<u128>,
+    /// Guarantee the broker gave for the batch.
+    pub acks: Acks,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum BrokerResponse {
-    TopicCreated { topic: Topic },
-    TopicAlreadyExists { topic: Topic },
-    TopicNotFound { topic_name: String },
-    TopicDeleted { topic_name: String },
-    TopicNotDeleted { topic_name: String },
+    TopicCreated {
+        topic: Topic,
+    },
+    TopicAlreadyExists {
+        topic: Topic,
+    },
+    /// The topic was not created in time, e.g. as no majority of the brokers is reachable.
+    TopicNotCreated {
+        topic_name: String,
+    },
+    TopicNotFound {
+        topic_name: String,
+    },
+    TopicDeleted {
+        topic_name: String,
+    },
+    TopicNotDeleted {
+        topic_name: String,
+    },
     TopicList(Vec<Topic>),
-    MessageBatchWriteSuccess,
-    MessageBatchWriteFailure { error: String },
+    MessageBatchAppended(ProduceResponse),
+    TopicDescription {
+        topic: Topic,
+    },
+    MessageBatchWriteFailure {
+        error: String,
+    },
     SendMessageBatch,
+    GroupJoined {
+        member_id: String,
+        generation_id: u32,
+        assignment: Vec<TopicPartition>,
+        revoked_partitions: Vec<TopicPartition>,
+    },
+    GroupLeft {
+        member_id: String,
+    },
+    HeartbeatAccepted {
+        /// Lets members notice a rebalance and fetch their new assignment.
+        generation_id: u32,
+    },
+    MemberAssignment {
+        generation_id: u32,
+        assignment: Vec<TopicPartition>,
+        revoked_partitions: Vec<TopicPartition>,
+    },
+    UnknownGroupMember {
+        group_id: String,
+        member_id: String,
+    },
+    InconsistentGroupProtocol {
+        group_id: String,
+        strategy: AssignmentStrategy,
+        protocol: RebalanceProtocol,
+    },
+    Pong {
+        /// Lets clients detect clock skew to the broker.
+        broker_time_millis: u128,
+    },
+    ProducerIdAllocated {
+        producer_id: u64,
+    },
+    VoteResult {
+        term: u64,
+        vote_granted: bool,
+    },
+    /// `match_index` is the last entry the follower has in common with the leader on success,
+    /// else the follower's last entry.
+    EntriesAppended {
+        term: u64,
+        success: bool,
+        match_index: u64,
+    },
+    /// The record was appended to the metadata log at `index`.
+    MetadataProposed {
+        index: u64,
+    },
+    /// Answer of brokers which do not lead the metadata quorum to requests for its leader, e.g.
+    /// heartbeats for the controller.
+    NotQuorumLeader {
+        leader_id: Option<u32>,
+    },
+    BrokerHeartbeatAcknowledged,
+    ReassignmentsStarted {
+        topic_partitions: Vec<TopicPartition>,
+    },
+    /// No reassignment of the request was started.
+    ReassignmentFailed {
+        topic_partition: TopicPartition,
+        error: String,
+    },
+    Reassignments {
+        reassignments: Vec<ReassignmentStatus>,
+    },
+    /// `elected` partitions are led by their preferred replica now, `not_elected` ones could not
+    /// be with the reason. Partitions already led by their preferred replica are in neither.
+    PreferredLeadersElected {
+        elected: Vec<TopicPartition>,
+        not_elected: Vec<(TopicPartition, String)>,
+    },
+    /// Versions of every request the broker handles by API key, and the batch format versions it
+    /// appends.
+    ApiVersions {
+        api_versions: Vec<(u16, VersionRange)>,
+        batch_format_versions: VersionRange,
+    },
+    /// The broker does not handle this version of the request, see `TopicCommand::ApiVersions`.
+    UnsupportedVersion {
+        api_key: u16,
+        api_version: u16,
+    },
+    /// Requested topics which do not exist are left out of `topics`.
+    Metadata {
+        brokers: Vec<BrokerRegistration>,
+        /// Broker leading the metadata quorum, `None` while it is elected.
+        controller_id: Option<u32>,
+        topics: Vec<TopicMetadata>,
+    },
+    /// SASL mechanisms the broker enables, none when it does not authenticate clients.
+    SaslHandshake {
+        enabled_mechanisms: Vec<String>,
+    },
+    /// The broker's challenge, the client answers it with the next `SaslAuthenticate` until its
+    /// mechanism completed.
+    SaslAuthenticated {
+        auth_bytes: Vec<u8>,
+    },
+    /// The credentials were rejected, or the request was sent before the connection was
+    /// authenticated. The broker closes the connection after it.
+    SaslAuthenticationFailed {
+        error: String,
+    },
+    /// Name and value of every metric of the broker.
+    Metrics {
+        metrics: Vec<(String, u64)>,
+    },
+    /// Answer to a write which was not appended.
+    ProduceFailed {
+        error: ProduceError,
+    },
+    OffsetsCommitted,
+    GroupDescription {
+        group_id: String,
+        generation_id: u32,
+        partitions: Vec<PartitionLag>,
+    },
+    UnknownGroup {
+        group_id: String,
+    },
+    Records {
+        topic_partition: TopicPartition,
+        /// Offset of the first fetched record, `batches` may start before it.
+        base_offset: u64,
+        /// Stored batches holding the fetched records, in offset order.
+        batches: Vec<FetchedBatch>,
+        log_end_offset: u64,
+        /// Offset up to which every in-sync replica copied the partition. Consumers are only
+        /// served the batches before it, followers learn it from the leader.
+        high_watermark: u64,
+        /// Leader epoch of the partition on the answering broker.
+        leader_epoch: u32,
+    },
+    /// Answer of a leader to a consumer in another rack, which fetches from this in-sync
+    /// follower in its own rack instead.
+    PreferredReadReplica {
+        topic_partition: TopicPartition,
+        broker_id: u32,
+    },
+    /// Answer of a `TopicCommand::SessionFetch`, the responses of the partitions with new records
+    /// or an error, partitions without either are left out.
+    SessionRecords {
+        session_id: u32,
+        responses: Vec<(TopicPartition, BrokerResponse)>,
+    },
+    /// The fetch session is unknown, e.g. evicted or opened on a broker which restarted since, or
+    /// the fetch's epoch is not the next of the session. The consumer opens a new session.
+    InvalidFetchSession {
+        session_id: u32,
+    },
+    OffsetOutOfRange {
+        topic_partition: TopicPartition,
+        offset: u64,
+        log_start_offset: u64,
+        log_end_offset: u64,
+    },
+    NoCommittedOffset {
+        group_id: Option<String>,
+        topic_partition: TopicPartition,
+    },
+    UnknownTopicPartition {
+        topic_partition: TopicPartition,
+    },
+    /// The fetch's leader epoch is not the partition's epoch on the broker. Either the requester
+    /// missed a leader change or the broker did, e.g. an old leader cut off from the cluster.
+    FencedLeaderEpoch {
+        topic_partition: TopicPartition,
+        leader_epoch: u32,
+        current_leader_epoch: u32,
+    },
+    /// Answer to `TopicCommand::OffsetForLeaderEpoch`, `None` when the leader knows no epoch
+    /// at or before the requested one.
+    EpochEndOffset {
+        topic_partition: TopicPartition,
+        end_offset: Option<EpochEndOffset>,
+        current_leader_epoch: u32,
+    },
+    OffsetsReset {
+        group_id: String,
+        offsets: Vec<PartitionOffset>,
+    },
+    InvalidTopicPattern {
+        pattern: String,
+        error: String,
+    },
+    /// The request needs a group without members.
+    GroupNotEmpty {
+        group_id: String,
+    },
+    /// Answer to `TopicCommand::ControlledShutdown`, `remaining_partitions` are still led by the
+    /// broker as no other replica of their ISR is alive.
+    ControlledShutdownCompleted {
+        remaining_partitions: Vec<TopicPartition>,
+    },
+    /// Answer to `TopicCommand::DecommissionBroker`. `moving_partitions` still have a replica on
+    /// the broker, `failed_partitions` can't be moved with the reason, e.g. as every other
+    /// broker holds a replica already. `unregistered` once the broker left the cluster.
+    DecommissionProgress {
+        moving_partitions: Vec<TopicPartition>,
+        failed_partitions: Vec<(TopicPartition, String)>,
+        unregistered: bool,
+    },
+    ClusterHealth(ClusterHealth),
+    /// Answer to `TopicCommand::AlterLogFilter` with the filter it replaced.
+    LogFilterAltered {
+        previous_filter: String,
+    },
+    /// The filter of `TopicCommand::AlterLogFilter` is not a valid filter, the broker keeps its
+    /// filter.
+    InvalidLogFilter {
+        error: String,
+    },
 }
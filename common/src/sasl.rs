@@ -0,0 +1,541 @@
+use std::fmt;
+use std::io;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::codecs::protocol::{next_correlation_id, Request, ResponseCodec};
+use crate::models::{BrokerResponse, TopicCommand};
+
+/// Iterations of the salted passwords of new SCRAM credentials, the minimum of RFC 7677.
+pub const SCRAM_ITERATIONS: u32 = 4096;
+/// GS2 header of SCRAM messages of clients without channel binding and authorization identity.
+const GS2_HEADER: &str = "n,,";
+const NONCE_LENGTH: usize = 18;
+const SALT_LENGTH: usize = 16;
+
+/// SASL mechanisms clients authenticate with, named like in Kafka's `sasl.mechanism`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SaslMechanism {
+    /// Username and password in the clear, to be used over TLS only.
+    Plain,
+    /// Salted challenge response, the password is never sent, RFC 5802 and RFC 7677.
+    #[default]
+    ScramSha256,
+}
+
+impl SaslMechanism {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+        }
+    }
+}
+
+impl fmt::Display for SaslMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SaslMechanism {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "PLAIN" => Ok(SaslMechanism::Plain),
+            "SCRAM-SHA-256" => Ok(SaslMechanism::ScramSha256),
+            _ => Err(format!("unknown SASL mechanism {}", s)),
+        }
+    }
+}
+
+/// What a client authenticates with. The password is left out of `Debug`.
+#[derive(Clone, PartialEq)]
+pub struct SaslCredentials {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for SaslCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaslCredentials")
+            .field("mechanism", &self.mechanism)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a broker stores of a password to verify SCRAM and PLAIN authentications, the password
+/// itself cannot be derived from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScramCredential {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+impl ScramCredential {
+    pub fn new(password: &str, salt: &[u8], iterations: u32) -> Self {
+        let salted_password = salted_password(password, salt, iterations);
+        ScramCredential {
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: stored_key(&client_key(&salted_password)),
+            server_key: server_key(&salted_password),
+        }
+    }
+
+    /// Credential of `password` with a random salt.
+    pub fn generate(password: &str) -> Self {
+        ScramCredential::new(password, &random_bytes(SALT_LENGTH), SCRAM_ITERATIONS)
+    }
+
+    /// Whether `password` is the one this credential was created from, for PLAIN.
+    pub fn verify_password(&self, password: &str) -> bool {
+        let salted_password = salted_password(password, &self.salt, self.iterations);
+        constant_time_eq(&stored_key(&client_key(&salted_password)), &self.stored_key)
+    }
+}
+
+/// Authentication bytes of PLAIN, RFC 4616, without an authorization identity.
+pub fn plain_auth_bytes(username: &str, password: &str) -> Vec<u8> {
+    format!("\0{}\0{}", username, password).into_bytes()
+}
+
+/// Username and password of PLAIN authentication bytes.
+pub fn parse_plain_auth_bytes(auth_bytes: &[u8]) -> Result<(String, String), String> {
+    let auth = std::str::from_utf8(auth_bytes).map_err(|e| e.to_string())?;
+    match auth.split('\0').collect::<Vec<_>>()[..] {
+        [_, username, password] if !username.is_empty() => {
+            Ok((username.to_string(), password.to_string()))
+        }
+        _ => Err("malformed PLAIN authentication".to_string()),
+    }
+}
+
+/// The client side of an authentication. The client sends `initial_response`, then answers every
+/// challenge of the broker with `respond` until it returns `None`.
+pub struct SaslClient {
+    credentials: SaslCredentials,
+    state: ScramClientState,
+}
+
+enum ScramClientState {
+    Initial,
+    ClientFirstSent {
+        client_nonce: String,
+        client_first_bare: String,
+    },
+    ClientFinalSent {
+        server_signature: Vec<u8>,
+    },
+    Done,
+}
+
+impl SaslClient {
+    pub fn new(credentials: SaslCredentials) -> Self {
+        SaslClient {
+            credentials,
+            state: ScramClientState::Initial,
+        }
+    }
+
+    pub fn initial_response(&mut self) -> Vec<u8> {
+        let SaslCredentials {
+            mechanism,
+            username,
+            password,
+        } = &self.credentials;
+        match mechanism {
+            SaslMechanism::Plain => {
+                self.state = ScramClientState::Done;
+                plain_auth_bytes(username, password)
+            }
+            SaslMechanism::ScramSha256 => {
+                let client_nonce = BASE64.encode(random_bytes(NONCE_LENGTH));
+                let client_first_bare =
+                    format!("n={},r={}", escape_username(username), client_nonce);
+                let client_first = format!("{}{}", GS2_HEADER, client_first_bare);
+                self.state = ScramClientState::ClientFirstSent {
+                    client_nonce,
+                    client_first_bare,
+                };
+                client_first.into_bytes()
+            }
+        }
+    }
+
+    /// The answer to a challenge of the broker, `None` once the authentication is complete.
+    pub fn respond(&mut self, challenge: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        match std::mem::replace(&mut self.state, ScramClientState::Done) {
+            ScramClientState::Initial => Err("no initial response was sent".to_string()),
+            ScramClientState::ClientFirstSent {
+                client_nonce,
+                client_first_bare,
+            } => {
+                let server_first = std::str::from_utf8(challenge).map_err(|e| e.to_string())?;
+                let attributes = parse_attributes(server_first)?;
+                let nonce = attribute(&attributes, 'r')?;
+                if !nonce.starts_with(&client_nonce) {
+                    return Err("broker changed the client nonce".to_string());
+                }
+                let salt = BASE64
+                    .decode(attribute(&attributes, 's')?)
+                    .map_err(|e| e.to_string())?;
+                let iterations: u32 = attribute(&attributes, 'i')?
+                    .parse()
+                    .map_err(|_| "invalid iteration count".to_string())?;
+                let salted_password =
+                    salted_password(&self.credentials.password, &salt, iterations);
+                let client_final_without_proof =
+                    format!("c={},r={}", BASE64.encode(GS2_HEADER), nonce);
+                let auth_message = format!(
+                    "{},{},{}",
+                    client_first_bare, server_first, client_final_without_proof
+                );
+                let client_key = client_key(&salted_password);
+                let client_signature = sign(&stored_key(&client_key), &auth_message);
+                let client_proof: Vec<u8> = client_key
+                    .iter()
+                    .zip(client_signature)
+                    .map(|(key, signature)| key ^ signature)
+                    .collect();
+                self.state = ScramClientState::ClientFinalSent {
+                    server_signature: sign(&server_key(&salted_password), &auth_message),
+                };
+                Ok(Some(
+                    format!(
+                        "{},p={}",
+                        client_final_without_proof,
+                        BASE64.encode(client_proof)
+                    )
+                    .into_bytes(),
+                ))
+            }
+            ScramClientState::ClientFinalSent { server_signature } => {
+                let server_final = std::str::from_utf8(challenge).map_err(|e| e.to_string())?;
+                let attributes = parse_attributes(server_final)?;
+                let verifier = BASE64
+                    .decode(attribute(&attributes, 'v')?)
+                    .map_err(|e| e.to_string())?;
+                if !constant_time_eq(&verifier, &server_signature) {
+                    return Err("broker could not prove it knows the password".to_string());
+                }
+                Ok(None)
+            }
+            ScramClientState::Done => match self.credentials.mechanism {
+                SaslMechanism::Plain => Ok(None),
+                SaslMechanism::ScramSha256 => Err("authentication already completed".to_string()),
+            },
+        }
+    }
+}
+
+/// The broker side of a SCRAM authentication, created from the client's first message.
+pub struct ScramServer {
+    credential: ScramCredential,
+    client_first_bare: String,
+    server_first: String,
+    nonce: String,
+}
+
+impl ScramServer {
+    /// Username of the client's first message, whose credential the broker looks up.
+    pub fn username(client_first: &[u8]) -> Result<String, String> {
+        let (_, client_first_bare) = split_client_first(client_first)?;
+        unescape_username(attribute(&parse_attributes(client_first_bare)?, 'n')?)
+    }
+
+    /// Starts the authentication, returns the challenge sent to the client.
+    pub fn start(
+        client_first: &[u8],
+        credential: ScramCredential,
+    ) -> Result<(Self, Vec<u8>), String> {
+        let (gs2_header, client_first_bare) = split_client_first(client_first)?;
+        if gs2_header != GS2_HEADER {
+            return Err(
+                "channel binding and authorization identities are not supported".to_string(),
+            );
+        }
+        let client_nonce = attribute(&parse_attributes(client_first_bare)?, 'r')?;
+        let nonce = format!(
+            "{}{}",
+            client_nonce,
+            BASE64.encode(random_bytes(NONCE_LENGTH))
+        );
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            BASE64.encode(&credential.salt),
+            credential.iterations
+        );
+        let server = ScramServer {
+            credential,
+            client_first_bare: client_first_bare.to_string(),
+            server_first: server_first.clone(),
+            nonce,
+        };
+        Ok((server, server_first.into_bytes()))
+    }
+
+    /// Verifies the client's proof, returns the final message proving the broker knows the
+    /// password too.
+    pub fn finish(&self, client_final: &[u8]) -> Result<Vec<u8>, String> {
+        let client_final = std::str::from_utf8(client_final).map_err(|e| e.to_string())?;
+        let (client_final_without_proof, proof) = client_final
+            .rsplit_once(",p=")
+            .ok_or_else(|| "client proof is missing".to_string())?;
+        let attributes = parse_attributes(client_final_without_proof)?;
+        if attribute(&attributes, 'r')? != self.nonce {
+            return Err("client changed the nonce".to_string());
+        }
+        let proof = BASE64.decode(proof).map_err(|e| e.to_string())?;
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, self.server_first, client_final_without_proof
+        );
+        let client_signature = sign(&self.credential.stored_key, &auth_message);
+        let client_key: Vec<u8> = proof
+            .iter()
+            .zip(client_signature)
+            .map(|(proof, signature)| proof ^ signature)
+            .collect();
+        if proof.len() != self.credential.stored_key.len()
+            || !constant_time_eq(&stored_key(&client_key), &self.credential.stored_key)
+        {
+            return Err("invalid credentials".to_string());
+        }
+        let server_signature = sign(&self.credential.server_key, &auth_message);
+        Ok(format!("v={}", BASE64.encode(server_signature)).into_bytes())
+    }
+}
+
+/// Authenticates a new connection to a broker with `credentials`, before any other request is
+/// sent on it. Fails with `io::ErrorKind::PermissionDenied` when the broker rejects them.
+pub async fn authenticate(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    client_id: &str,
+    credentials: &SaslCredentials,
+) -> io::Result<()> {
+    let mechanism = credentials.mechanism.name().to_string();
+    match exchange(stream, client_id, TopicCommand::SaslHandshake { mechanism }).await? {
+        BrokerResponse::SaslHandshake { enabled_mechanisms }
+            if enabled_mechanisms
+                .iter()
+                .any(|m| m == credentials.mechanism.name()) => {}
+        BrokerResponse::SaslHandshake { enabled_mechanisms } => {
+            return Err(permission_denied(format!(
+                "broker does not enable {}, only {:?}",
+                credentials.mechanism, enabled_mechanisms
+            )))
+        }
+        response => return Err(unexpected_response(response)),
+    }
+    let mut client = SaslClient::new(credentials.clone());
+    let mut auth_bytes = client.initial_response();
+    loop {
+        let command = TopicCommand::SaslAuthenticate { auth_bytes };
+        match exchange(stream, client_id, command).await? {
+            BrokerResponse::SaslAuthenticated {
+                auth_bytes: challenge,
+            } => match client.respond(&challenge).map_err(permission_denied)? {
+                Some(response) => auth_bytes = response,
+                None => return Ok(()),
+            },
+            BrokerResponse::SaslAuthenticationFailed { error } => {
+                return Err(permission_denied(error))
+            }
+            response => return Err(unexpected_response(response)),
+        }
+    }
+}
+
+/// Sends a request and reads its response, on a connection without other requests in flight.
+async fn exchange(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    client_id: &str,
+    command: TopicCommand,
+) -> io::Result<BrokerResponse> {
+    let correlation_id = next_correlation_id();
+    let mut response_codec = ResponseCodec::default();
+    let mut buffer = BytesMut::new();
+    response_codec.encode(
+        Request::new(correlation_id, client_id, command, Bytes::new()),
+        &mut buffer,
+    )?;
+    stream.write_all(&buffer).await?;
+    stream.flush().await?;
+    buffer.clear();
+    loop {
+        if let Some(response) = response_codec.decode(&mut buffer)? {
+            if response.correlation_id != correlation_id {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "expected the response to request {} but got one to {}",
+                        correlation_id, response.correlation_id
+                    ),
+                ));
+            }
+            return Ok(response.response);
+        }
+        if stream.read_buf(&mut buffer).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed during authentication",
+            ));
+        }
+    }
+}
+
+fn permission_denied(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, error.to_string())
+}
+
+fn unexpected_response(response: BrokerResponse) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected response to authentication: {:?}", response),
+    )
+}
+
+fn salted_password(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut salted_password = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(iterations.max(1)).unwrap(),
+        salt,
+        password.as_bytes(),
+        &mut salted_password,
+    );
+    salted_password
+}
+
+fn sign(key: &[u8], message: &str) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), message.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+fn client_key(salted_password: &[u8]) -> Vec<u8> {
+    sign(salted_password, "Client Key")
+}
+
+fn server_key(salted_password: &[u8]) -> Vec<u8> {
+    sign(salted_password, "Server Key")
+}
+
+fn stored_key(client_key: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, client_key)
+        .as_ref()
+        .to_vec()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Could not generate random bytes");
+    bytes
+}
+
+/// Splits a client's first message into its GS2 header and the bare message.
+fn split_client_first(client_first: &[u8]) -> Result<(&str, &str), String> {
+    let client_first = std::str::from_utf8(client_first).map_err(|e| e.to_string())?;
+    let mut parts = client_first.splitn(3, ',');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(binding), Some(authzid), Some(bare)) => {
+            Ok((&client_first[..binding.len() + authzid.len() + 2], bare))
+        }
+        _ => Err("malformed SCRAM client message".to_string()),
+    }
+}
+
+fn parse_attributes(message: &str) -> Result<Vec<(char, &str)>, String> {
+    message
+        .split(',')
+        .map(|attribute| {
+            let mut chars = attribute.chars();
+            match (chars.next(), chars.next()) {
+                (Some(name), Some('=')) => Ok((name, &attribute[2..])),
+                _ => Err(format!("malformed SCRAM attribute {:?}", attribute)),
+            }
+        })
+        .collect()
+}
+
+fn attribute<'a>(attributes: &[(char, &'a str)], name: char) -> Result<&'a str, String> {
+    attributes
+        .iter()
+        .find(|(attribute_name, _)| *attribute_name == name)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| format!("SCRAM attribute {} is missing", name))
+}
+
+fn escape_username(username: &str) -> String {
+    username.replace('=', "=3D").replace(',', "=2C")
+}
+
+fn unescape_username(username: &str) -> Result<String, String> {
+    let unescaped = username.replace("=2C", ",").replace("=3D", "=");
+    if unescaped.len() + 2 * username.matches('=').count() != username.len() {
+        return Err("malformed SCRAM username".to_string());
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scram(password: &str, stored_password: &str) -> Result<(), String> {
+        let mut client = SaslClient::new(SaslCredentials {
+            mechanism: SaslMechanism::ScramSha256,
+            username: "alice,=admin".to_string(),
+            password: password.to_string(),
+        });
+        let client_first = client.initial_response();
+        assert_eq!(
+            ScramServer::username(&client_first).unwrap(),
+            "alice,=admin"
+        );
+        let (server, server_first) =
+            ScramServer::start(&client_first, ScramCredential::generate(stored_password))?;
+        let client_final = client.respond(&server_first)?.unwrap();
+        let server_final = server.finish(&client_final)?;
+        assert_eq!(client.respond(&server_final)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scram_should_authenticate_only_with_the_stored_password() {
+        assert_eq!(scram("secret", "secret"), Ok(()));
+        assert_eq!(
+            scram("guess", "secret"),
+            Err("invalid credentials".to_string())
+        );
+
+        let credential = ScramCredential::generate("secret");
+        assert!(credential.verify_password("secret"));
+        assert!(!credential.verify_password("guess"));
+        assert_eq!(
+            parse_plain_auth_bytes(&plain_auth_bytes("alice", "secret")),
+            Ok(("alice".to_string(), "secret".to_string()))
+        );
+    }
+}
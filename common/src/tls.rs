@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tokio_util::either::Either;
+
+/// Prefix of broker addresses which are connected to with TLS, e.g. `tls://broker-1:8443`.
+pub const TLS_SCHEME: &str = "tls://";
+
+/// A connection to a broker, encrypted for `tls://` addresses.
+pub type BrokerStream = Either<TcpStream, TlsStream<TcpStream>>;
+
+/// Splits an address into its `host:port` and whether it is connected to with TLS.
+pub fn parse_address(address: &str) -> (&str, bool) {
+    match address.strip_prefix(TLS_SCHEME) {
+        Some(host_port) => (host_port, true),
+        None => (address, false),
+    }
+}
+
+/// Name the certificate of the broker at `host:port` has to be issued for.
+pub fn server_name(host_port: &str) -> io::Result<ServerName<'static>> {
+    let host = match host_port.rsplit_once(':') {
+        Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => host_port,
+    };
+    ServerName::try_from(host.to_string()).map_err(invalid_input)
+}
+
+/// Connects to the broker at `address`, with TLS for `tls://` addresses. Fails for those
+/// without a TLS configuration.
+pub async fn connect(address: &str, tls: Option<&Arc<ClientConfig>>) -> io::Result<BrokerStream> {
+    let (host_port, use_tls) = parse_address(address);
+    let stream = TcpStream::connect(host_port).await?;
+    if !use_tls {
+        return Ok(Either::Left(stream));
+    }
+    let Some(tls) = tls else {
+        return Err(invalid_input(format!(
+            "no TLS configured to connect to {}",
+            address
+        )));
+    };
+    let stream = TlsConnector::from(tls.clone())
+        .connect(server_name(host_port)?, stream)
+        .await?;
+    Ok(Either::Right(stream))
+}
+
+pub fn load_certificates(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certificates = CertificateDer::pem_reader_iter(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid_input)?;
+    if certificates.is_empty() {
+        return Err(invalid_input(format!("no certificate in {}", path)));
+    }
+    Ok(certificates)
+}
+
+pub fn load_private_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_reader(BufReader::new(File::open(path)?)).map_err(invalid_input)
+}
+
+fn load_root_certificates(ca_path: &str) -> io::Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(ca_path)? {
+        roots.add(certificate).map_err(invalid_input)?;
+    }
+    Ok(Arc::new(roots))
+}
+
+/// TLS of a broker's listener. With `client_ca_path` clients have to present a certificate
+/// issued by one of its certificates, like Kafka's `ssl.client.auth=required`.
+pub fn server_config(
+    certificate_path: &str,
+    private_key_path: &str,
+    client_ca_path: Option<&str>,
+) -> io::Result<Arc<ServerConfig>> {
+    let builder = ServerConfig::builder();
+    let builder = match client_ca_path {
+        Some(client_ca_path) => {
+            let verifier = WebPkiClientVerifier::builder(load_root_certificates(client_ca_path)?)
+                .build()
+                .map_err(invalid_input)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(
+            load_certificates(certificate_path)?,
+            load_private_key(private_key_path)?,
+        )
+        .map_err(invalid_input)?;
+    Ok(Arc::new(config))
+}
+
+/// TLS of connections to brokers whose certificates were issued by one of the certificates in
+/// `ca_path`. `client_certificate` holds the paths of the certificate and private key presented
+/// to brokers which verify their clients.
+pub fn client_config(
+    ca_path: &str,
+    client_certificate: Option<(&str, &str)>,
+) -> io::Result<Arc<ClientConfig>> {
+    let builder = ClientConfig::builder().with_root_certificates(load_root_certificates(ca_path)?);
+    let config = match client_certificate {
+        Some((certificate_path, private_key_path)) => builder
+            .with_client_auth_cert(
+                load_certificates(certificate_path)?,
+                load_private_key(private_key_path)?,
+            )
+            .map_err(invalid_input)?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+fn invalid_input(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address_should_strip_tls_scheme() {
+        assert_eq!(
+            parse_address("tls://broker-1:8443"),
+            ("broker-1:8443", true)
+        );
+        assert_eq!(parse_address("broker-1:8080"), ("broker-1:8080", false));
+        assert_eq!(
+            server_name("broker-1:8443").unwrap(),
+            ServerName::try_from("broker-1").unwrap()
+        );
+        assert_eq!(
+            server_name("[::1]:8443").unwrap(),
+            ServerName::try_from("::1").unwrap()
+        );
+    }
+}
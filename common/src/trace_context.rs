@@ -0,0 +1,96 @@
+use bytes::Bytes;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Context;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Writes the W3C trace context of the current span, `traceparent` and `tracestate`, into the
+/// headers of a record, so the consumer of the record continues the producer's trace. Nothing is
+/// written outside of a span or when spans are not exported.
+pub fn inject_current_span(headers: &mut Vec<(String, Bytes)>) {
+    inject(&tracing::Span::current().context(), headers);
+}
+
+/// Makes `span` a child of the span which produced the record with `headers`, if it was produced
+/// within one.
+pub fn follow_record(span: &tracing::Span, headers: &[(String, Bytes)]) {
+    let context = extract(headers);
+    if context.has_active_span() {
+        span.set_parent(context);
+    }
+}
+
+fn inject(context: &Context, headers: &mut Vec<(String, Bytes)>) {
+    TraceContextPropagator::new().inject_context(context, &mut RecordHeaders(headers));
+}
+
+fn extract(headers: &[(String, Bytes)]) -> Context {
+    TraceContextPropagator::new().extract(&RecordHeaderValues(headers))
+}
+
+struct RecordHeaders<'a>(&'a mut Vec<(String, Bytes)>);
+
+impl Injector for RecordHeaders<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        // records sent again, by a bridge for instance, carry the context of their new producer
+        self.0.retain(|(name, _)| name != key);
+        self.0.push((key.to_string(), value.into()));
+    }
+}
+
+struct RecordHeaderValues<'a>(&'a [(String, Bytes)]);
+
+impl Extractor for RecordHeaderValues<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(name, _)| name == key)
+            .and_then(|(_, value)| std::str::from_utf8(value).ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+    use super::*;
+
+    #[test]
+    fn test_trace_context_should_round_trip_through_record_headers() {
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let mut headers = vec![
+            ("mqtt-topic".to_string(), Bytes::from_static(b"sensors/1")),
+            ("traceparent".to_string(), Bytes::from_static(b"stale")),
+        ];
+
+        inject(
+            &Context::new().with_remote_span_context(span_context.clone()),
+            &mut headers,
+        );
+
+        assert_eq!(
+            headers,
+            vec![
+                ("mqtt-topic".to_string(), Bytes::from_static(b"sensors/1")),
+                (
+                    "traceparent".to_string(),
+                    Bytes::from_static(b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+                ),
+                ("tracestate".to_string(), Bytes::new()),
+            ]
+        );
+        assert_eq!(extract(&headers).span().span_context(), &span_context);
+        assert!(!extract(&headers[..1]).has_active_span());
+    }
+}
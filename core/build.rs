@@ -0,0 +1,6 @@
+fn main() {
+    println!("cargo:rerun-if-changed=proto/walrs.proto");
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/walrs.proto")
+        .expect("Could not compile the protobuf definitions, is protoc installed?");
+}
@@ -0,0 +1,88 @@
+use std::collections::{BTreeMap, HashMap};
+
+use common::models::{AssignmentStrategy, TopicPartition};
+
+pub mod range;
+pub mod round_robin;
+pub mod sticky;
+
+/// Partitions assigned to each member of a group, keyed by member id.
+pub type Assignment = BTreeMap<String, Vec<TopicPartition>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberSubscription {
+    pub member_id: String,
+    pub topics: Vec<String>,
+    pub owned_partitions: Vec<TopicPartition>,
+}
+
+impl MemberSubscription {
+    pub fn new(member_id: String, topics: Vec<String>) -> Self {
+        MemberSubscription {
+            member_id,
+            topics,
+            owned_partitions: vec![],
+        }
+    }
+
+    pub fn is_subscribed_to(&self, topic_name: &str) -> bool {
+        self.topics.iter().any(|topic| topic == topic_name)
+    }
+}
+
+/// Decides which member of a consumer group reads which partition.
+/// Every member present in `subscriptions` must be present in the returned assignment,
+/// even if it did not get any partition.
+pub trait Assignor: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn assign(
+        &self,
+        subscriptions: &[MemberSubscription],
+        partitions_per_topic: &HashMap<String, u8>,
+    ) -> Assignment;
+}
+
+pub fn assignor_for(strategy: AssignmentStrategy) -> Box<dyn Assignor> {
+    match strategy {
+        AssignmentStrategy::Range => Box::new(range::RangeAssignor {}),
+        AssignmentStrategy::RoundRobin => Box::new(round_robin::RoundRobinAssignor {}),
+        AssignmentStrategy::Sticky => Box::new(sticky::StickyAssignor {}),
+    }
+}
+
+fn empty_assignment(subscriptions: &[MemberSubscription]) -> Assignment {
+    subscriptions
+        .iter()
+        .map(|subscription| (subscription.member_id.clone(), vec![]))
+        .collect()
+}
+
+/// All partitions of the topics which at least one member is subscribed to, sorted by topic and index.
+fn subscribed_partitions(
+    subscriptions: &[MemberSubscription],
+    partitions_per_topic: &HashMap<String, u8>,
+) -> Vec<TopicPartition> {
+    let mut topic_names: Vec<&String> = subscriptions
+        .iter()
+        .flat_map(|subscription| subscription.topics.iter())
+        .filter(|topic_name| partitions_per_topic.contains_key(*topic_name))
+        .collect();
+    topic_names.sort();
+    topic_names.dedup();
+    topic_names
+        .into_iter()
+        .flat_map(|topic_name| {
+            let num_partitions = partitions_per_topic[topic_name];
+            (0..num_partitions).map(move |partition_index| {
+                TopicPartition::new(topic_name.clone(), partition_index)
+            })
+        })
+        .collect()
+}
+
+fn sorted_by_member_id(subscriptions: &[MemberSubscription]) -> Vec<&MemberSubscription> {
+    let mut sorted: Vec<&MemberSubscription> = subscriptions.iter().collect();
+    sorted.sort_by(|left, right| left.member_id.cmp(&right.member_id));
+    sorted
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use common::models::TopicPartition;
+
+use super::{empty_assignment, sorted_by_member_id, Assignment, Assignor, MemberSubscription};
+
+/// Assigns each topic independently: partitions of a topic are split into contiguous ranges
+/// and the first members (ordered by member id) get one extra partition when the split is uneven.
+pub struct RangeAssignor {}
+
+impl Assignor for RangeAssignor {
+    fn name(&self) -> &'static str {
+        "range"
+    }
+
+    fn assign(
+        &self,
+        subscriptions: &[MemberSubscription],
+        partitions_per_topic: &HashMap<String, u8>,
+    ) -> Assignment {
+        let mut assignment = empty_assignment(subscriptions);
+        let members = sorted_by_member_id(subscriptions);
+
+        let mut topic_names: Vec<&String> = partitions_per_topic.keys().collect();
+        topic_names.sort();
+        for topic_name in topic_names {
+            let consumers: Vec<&&MemberSubscription> = members
+                .iter()
+                .filter(|member| member.is_subscribed_to(topic_name))
+                .collect();
+            if consumers.is_empty() {
+                continue;
+            }
+            let num_partitions = partitions_per_topic[topic_name] as usize;
+            let partitions_per_consumer = num_partitions / consumers.len();
+            let consumers_with_extra_partition = num_partitions % consumers.len();
+
+            let mut next_partition = 0;
+            for (consumer_index, consumer) in consumers.iter().enumerate() {
+                let mut range_size = partitions_per_consumer;
+                if consumer_index < consumers_with_extra_partition {
+                    range_size += 1;
+                }
+                let member_partitions = assignment.get_mut(&consumer.member_id).unwrap();
+                for partition_index in next_partition..next_partition + range_size {
+                    member_partitions.push(TopicPartition::new(
+                        topic_name.clone(),
+                        partition_index as u8,
+                    ));
+                }
+                next_partition += range_size;
+            }
+        }
+        assignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_assignor_should_give_extra_partitions_to_first_members() {
+        let subscriptions = vec![
+            MemberSubscription::new("consumer-b".to_string(), vec!["orders".to_string()]),
+            MemberSubscription::new("consumer-a".to_string(), vec!["orders".to_string()]),
+        ];
+        let partitions_per_topic = HashMap::from([("orders".to_string(), 3)]);
+
+        let assignment = RangeAssignor {}.assign(&subscriptions, &partitions_per_topic);
+
+        assert_eq!(
+            assignment["consumer-a"],
+            vec![
+                TopicPartition::new("orders".to_string(), 0),
+                TopicPartition::new("orders".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            assignment["consumer-b"],
+            vec![TopicPartition::new("orders".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_range_assignor_should_only_assign_subscribed_topics() {
+        let subscriptions = vec![
+            MemberSubscription::new(
+                "consumer-a".to_string(),
+                vec!["orders".to_string(), "payments".to_string()],
+            ),
+            MemberSubscription::new("consumer-b".to_string(), vec!["orders".to_string()]),
+        ];
+        let partitions_per_topic =
+            HashMap::from([("orders".to_string(), 2), ("payments".to_string(), 2)]);
+
+        let assignment = RangeAssignor {}.assign(&subscriptions, &partitions_per_topic);
+
+        assert_eq!(assignment["consumer-a"].len(), 3);
+        assert_eq!(
+            assignment["consumer-b"],
+            vec![TopicPartition::new("orders".to_string(), 1)]
+        );
+    }
+}
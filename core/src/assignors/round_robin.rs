@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use super::{
+    empty_assignment, sorted_by_member_id, subscribed_partitions, Assignment, Assignor,
+    MemberSubscription,
+};
+
+/// Lays out the partitions of all subscribed topics in one list and deals them out to the
+/// members one by one, skipping members which are not subscribed to the partition's topic.
+pub struct RoundRobinAssignor {}
+
+impl Assignor for RoundRobinAssignor {
+    fn name(&self) -> &'static str {
+        "round_robin"
+    }
+
+    fn assign(
+        &self,
+        subscriptions: &[MemberSubscription],
+        partitions_per_topic: &HashMap<String, u8>,
+    ) -> Assignment {
+        let mut assignment = empty_assignment(subscriptions);
+        let members = sorted_by_member_id(subscriptions);
+        if members.is_empty() {
+            return assignment;
+        }
+
+        let mut next_member = 0;
+        for topic_partition in subscribed_partitions(subscriptions, partitions_per_topic) {
+            for _ in 0..members.len() {
+                let member = members[next_member];
+                next_member = (next_member + 1) % members.len();
+                if member.is_subscribed_to(&topic_partition.topic_name) {
+                    assignment
+                        .get_mut(&member.member_id)
+                        .unwrap()
+                        .push(topic_partition);
+                    break;
+                }
+            }
+        }
+        assignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::models::TopicPartition;
+
+    use super::*;
+
+    #[test]
+    fn test_round_robin_assignor_should_interleave_partitions_across_topics() {
+        let topics = vec!["orders".to_string(), "payments".to_string()];
+        let subscriptions = vec![
+            MemberSubscription::new("consumer-a".to_string(), topics.clone()),
+            MemberSubscription::new("consumer-b".to_string(), topics),
+        ];
+        let partitions_per_topic =
+            HashMap::from([("orders".to_string(), 3), ("payments".to_string(), 1)]);
+
+        let assignment = RoundRobinAssignor {}.assign(&subscriptions, &partitions_per_topic);
+
+        assert_eq!(
+            assignment["consumer-a"],
+            vec![
+                TopicPartition::new("orders".to_string(), 0),
+                TopicPartition::new("orders".to_string(), 2)
+            ]
+        );
+        assert_eq!(
+            assignment["consumer-b"],
+            vec![
+                TopicPartition::new("orders".to_string(), 1),
+                TopicPartition::new("payments".to_string(), 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::{HashMap, HashSet};
+
+use common::models::TopicPartition;
+
+use super::{
+    empty_assignment, sorted_by_member_id, subscribed_partitions, Assignment, Assignor,
+    MemberSubscription,
+};
+
+/// Keeps partitions with the members which already own them as long as the group stays balanced,
+/// and hands the remaining partitions to the least loaded members.
+/// This keeps the number of partitions moving between members during a rebalance to a minimum.
+pub struct StickyAssignor {}
+
+impl Assignor for StickyAssignor {
+    fn name(&self) -> &'static str {
+        "sticky"
+    }
+
+    fn assign(
+        &self,
+        subscriptions: &[MemberSubscription],
+        partitions_per_topic: &HashMap<String, u8>,
+    ) -> Assignment {
+        let mut assignment = empty_assignment(subscriptions);
+        if subscriptions.is_empty() {
+            return assignment;
+        }
+        let all_partitions = subscribed_partitions(subscriptions, partitions_per_topic);
+        let valid_partitions: HashSet<&TopicPartition> = all_partitions.iter().collect();
+
+        let min_quota = all_partitions.len() / subscriptions.len();
+        let mut members_allowed_extra = all_partitions.len() % subscriptions.len();
+
+        // members owning the most partitions are the ones which keep the extra partition
+        let mut members = sorted_by_member_id(subscriptions);
+        members.sort_by_key(|member| std::cmp::Reverse(member.owned_partitions.len()));
+
+        let mut claimed: HashSet<TopicPartition> = HashSet::new();
+        for member in members {
+            let still_owned: Vec<TopicPartition> = member
+                .owned_partitions
+                .iter()
+                .filter(|partition| {
+                    valid_partitions.contains(partition)
+                        && member.is_subscribed_to(&partition.topic_name)
+                        && !claimed.contains(*partition)
+                })
+                .cloned()
+                .collect();
+            let mut quota = min_quota;
+            if still_owned.len() > min_quota && members_allowed_extra > 0 {
+                quota += 1;
+                members_allowed_extra -= 1;
+            }
+            let member_partitions = assignment.get_mut(&member.member_id).unwrap();
+            for partition in still_owned.into_iter().take(quota) {
+                claimed.insert(partition.clone());
+                member_partitions.push(partition);
+            }
+        }
+
+        for partition in all_partitions {
+            if claimed.contains(&partition) {
+                continue;
+            }
+            let least_loaded_member = sorted_by_member_id(subscriptions)
+                .into_iter()
+                .filter(|member| member.is_subscribed_to(&partition.topic_name))
+                .min_by_key(|member| assignment[&member.member_id].len());
+            if let Some(member) = least_loaded_member {
+                assignment
+                    .get_mut(&member.member_id)
+                    .unwrap()
+                    .push(partition);
+            }
+        }
+
+        for member_partitions in assignment.values_mut() {
+            member_partitions.sort();
+        }
+        assignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orders_partition(partition_index: u8) -> TopicPartition {
+        TopicPartition::new("orders".to_string(), partition_index)
+    }
+
+    #[test]
+    fn test_sticky_assignor_should_keep_owned_partitions_when_member_joins() {
+        let topics = vec!["orders".to_string()];
+        let mut consumer_a = MemberSubscription::new("consumer-a".to_string(), topics.clone());
+        consumer_a.owned_partitions = vec![orders_partition(0), orders_partition(2)];
+        let mut consumer_b = MemberSubscription::new("consumer-b".to_string(), topics.clone());
+        consumer_b.owned_partitions = vec![orders_partition(1), orders_partition(3)];
+        let consumer_c = MemberSubscription::new("consumer-c".to_string(), topics);
+        let partitions_per_topic = HashMap::from([("orders".to_string(), 4)]);
+
+        let assignment =
+            StickyAssignor {}.assign(&[consumer_a, consumer_b, consumer_c], &partitions_per_topic);
+
+        assert_eq!(
+            assignment["consumer-a"],
+            vec![orders_partition(0), orders_partition(2)]
+        );
+        assert_eq!(assignment["consumer-b"], vec![orders_partition(1)]);
+        assert_eq!(assignment["consumer-c"], vec![orders_partition(3)]);
+    }
+
+    #[test]
+    fn test_sticky_assignor_should_reassign_partitions_of_departed_member() {
+        let topics = vec!["orders".to_string()];
+        let mut consumer_a = MemberSubscription::new("consumer-a".to_string(), topics);
+        consumer_a.owned_partitions = vec![orders_partition(0)];
+        let partitions_per_topic = HashMap::from([("orders".to_string(), 3)]);
+
+        let assignment = StickyAssignor {}.assign(&[consumer_a], &partitions_per_topic);
+
+        assert_eq!(
+            assignment["consumer-a"],
+            vec![
+                orders_partition(0),
+                orders_partition(1),
+                orders_partition(2)
+            ]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Audit log of the administrative and security events of a broker, appended as JSON lines to
+//! the file of `WALRS_AUDIT_LOG_PATH` so security teams can review who changed the cluster.
+
+use std::net::IpAddr;
+
+use common::clock::now_millis;
+use common::models::{
+    BrokerResponse, OffsetResetTarget, PartitionReassignment, Topic, TopicCommand, TopicPartition,
+};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+/// Who did what from where, and how the broker answered.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct AuditEvent {
+    /// Milliseconds since the epoch.
+    pub timestamp: u128,
+    /// The SASL user of the connection, `None` on brokers without SASL.
+    pub principal: Option<String>,
+    pub client_id: String,
+    pub source_ip: IpAddr,
+    pub action: AuditAction,
+    /// Name of the response, e.g. `TopicCreated` or `NotController`.
+    pub outcome: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditAction {
+    CreateTopic {
+        topic: Topic,
+    },
+    DeleteTopic {
+        topic_name: String,
+    },
+    AlterLogFilter {
+        filter: String,
+    },
+    ReassignPartitions {
+        reassignments: Vec<PartitionReassignment>,
+    },
+    ElectPreferredLeaders {
+        topic_partitions: Option<Vec<TopicPartition>>,
+    },
+    ResetOffsets {
+        group_id: String,
+        topic_name: String,
+        to: OffsetResetTarget,
+    },
+    ControlledShutdown {
+        broker_id: u32,
+    },
+    DecommissionBroker {
+        broker_id: u32,
+    },
+    /// A connection failed to authenticate and was closed.
+    AuthenticationFailed {
+        error: String,
+    },
+}
+
+impl AuditAction {
+    /// The action of requests changing the cluster, `None` for the others.
+    pub fn of(command: &TopicCommand) -> Option<Self> {
+        let action = match command {
+            TopicCommand::CreateTopic { topic } => AuditAction::CreateTopic {
+                topic: topic.clone(),
+            },
+            TopicCommand::DeleteTopic { topic_name } => AuditAction::DeleteTopic {
+                topic_name: topic_name.clone(),
+            },
+            TopicCommand::AlterLogFilter { filter } => AuditAction::AlterLogFilter {
+                filter: filter.clone(),
+            },
+            TopicCommand::ReassignPartitions { reassignments, .. } => {
+                AuditAction::ReassignPartitions {
+                    reassignments: reassignments.clone(),
+                }
+            }
+            TopicCommand::ElectPreferredLeaders { topic_partitions } => {
+                AuditAction::ElectPreferredLeaders {
+                    topic_partitions: topic_partitions.clone(),
+                }
+            }
+            TopicCommand::ResetOffsets {
+                group_id,
+                topic_name,
+                to,
+            } => AuditAction::ResetOffsets {
+                group_id: group_id.clone(),
+                topic_name: topic_name.clone(),
+                to: *to,
+            },
+            TopicCommand::ControlledShutdown { broker_id } => AuditAction::ControlledShutdown {
+                broker_id: *broker_id,
+            },
+            TopicCommand::DecommissionBroker { broker_id } => AuditAction::DecommissionBroker {
+                broker_id: *broker_id,
+            },
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+/// Where a connection's requests come from, stamped on its audit events.
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    pub principal: Option<String>,
+    pub client_id: String,
+    pub source_ip: IpAddr,
+}
+
+/// Sends the events to the task appending them to the audit log file, events are dropped on
+/// brokers without one.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    events_tx: Option<mpsc::UnboundedSender<AuditEvent>>,
+}
+
+impl AuditLog {
+    /// Appends the events to the file at `path` until every `AuditLog` is dropped.
+    pub fn start(path: String) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_events(path, events_rx));
+        AuditLog {
+            events_tx: Some(events_tx),
+        }
+    }
+
+    pub fn record(&self, context: AuditContext, action: AuditAction, response: &BrokerResponse) {
+        let Some(events_tx) = &self.events_tx else {
+            return;
+        };
+        let event = AuditEvent {
+            timestamp: now_millis(),
+            principal: context.principal,
+            client_id: context.client_id,
+            source_ip: context.source_ip,
+            action,
+            outcome: response_name(response),
+        };
+        if events_tx.send(event).is_err() {
+            tracing::error!("Audit log is closed, dropping an audit event");
+        }
+    }
+}
+
+/// Name of the response's variant, without its fields.
+fn response_name(response: &BrokerResponse) -> String {
+    let response = format!("{:?}", response);
+    response
+        .split([' ', '{', '('])
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+async fn write_events(path: String, mut events_rx: mpsc::UnboundedReceiver<AuditEvent>) {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .expect("Could not open the audit log");
+    tracing::info!("Writing the audit log to {}", path);
+    while let Some(event) = events_rx.recv().await {
+        let mut line = serde_json::to_vec(&event).expect("Audit events serialize to JSON");
+        line.push(b'\n');
+        if let Err(e) = file.write_all(&line).await {
+            tracing::error!("Could not write to the audit log {}: {:?}", path, e);
+            continue;
+        }
+        if let Err(e) = file.flush().await {
+            tracing::error!("Could not flush the audit log {}: {:?}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_log_should_append_events_of_administrative_requests() {
+        let dir = TempDir::new("audit").unwrap();
+        let path = dir.path().join("audit.log");
+        let audit_log = AuditLog::start(path.to_str().unwrap().to_string());
+        let context = AuditContext {
+            principal: Some("alice".to_string()),
+            client_id: "admin".to_string(),
+            source_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+        let delete_topic = TopicCommand::DeleteTopic {
+            topic_name: "orders".to_string(),
+        };
+        assert_eq!(AuditAction::of(&TopicCommand::Ping), None);
+        let action = AuditAction::of(&delete_topic).unwrap();
+        let response = BrokerResponse::TopicDeleted {
+            topic_name: "orders".to_string(),
+        };
+        audit_log.record(context, action, &response);
+        drop(audit_log);
+
+        let mut events = String::new();
+        for _ in 0..50 {
+            events = std::fs::read_to_string(&path).unwrap_or_default();
+            if !events.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let event: serde_json::Value = serde_json::from_str(events.trim_end()).unwrap();
+        assert_eq!(event["principal"], "alice");
+        assert_eq!(event["source_ip"], "127.0.0.1");
+        assert_eq!(event["action"]["type"], "delete_topic");
+        assert_eq!(event["action"]["topic_name"], "orders");
+        assert_eq!(event["outcome"], "TopicDeleted");
+    }
+}
@@ -0,0 +1,165 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+
+use crate::metrics::Metrics;
+
+/// Buffers which grew beyond this many times the pool's buffer capacity are freed instead of
+/// pooled, so one large batch does not keep its memory for good.
+const MAX_POOLED_CAPACITY_FACTOR: usize = 4;
+
+/// Reusable `BytesMut` buffers, so reading requests, encoding responses and encoding batches do
+/// not allocate a buffer each time. Buffers go back to the pool when their `PooledBuffer` is
+/// dropped. Hits, misses and idle buffers are published as `buffer_pool_hits_total`,
+/// `buffer_pool_misses_total` and `buffer_pool_idle_buffers` with the pool's name.
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    idle_buffers: Mutex<Vec<BytesMut>>,
+    buffer_capacity: usize,
+    max_idle_buffers: usize,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    idle_buffers_count: Arc<AtomicU64>,
+}
+
+impl BufferPool {
+    /// Pool handing out buffers of at least `buffer_capacity` bytes, which keeps up to
+    /// `max_idle_buffers` of the returned ones.
+    pub fn new(
+        name: &str,
+        buffer_capacity: usize,
+        max_idle_buffers: usize,
+        metrics: &Metrics,
+    ) -> Self {
+        let metric =
+            |metric_name: &str| metrics.register(&format!("{}{{pool=\"{}\"}}", metric_name, name));
+        BufferPool {
+            inner: Arc::new(PoolInner {
+                idle_buffers: Mutex::new(Vec::with_capacity(max_idle_buffers)),
+                buffer_capacity,
+                max_idle_buffers,
+                hits: metric("buffer_pool_hits_total"),
+                misses: metric("buffer_pool_misses_total"),
+                idle_buffers_count: metric("buffer_pool_idle_buffers"),
+            }),
+        }
+    }
+
+    pub fn buffer_capacity(&self) -> usize {
+        self.inner.buffer_capacity
+    }
+
+    /// An empty buffer, a pooled one when there is one.
+    pub fn acquire(&self) -> PooledBuffer {
+        let inner = &self.inner;
+        let pooled_buffer = {
+            let mut idle_buffers = inner.idle_buffers.lock().unwrap();
+            let pooled_buffer = idle_buffers.pop();
+            inner
+                .idle_buffers_count
+                .store(idle_buffers.len() as u64, Ordering::Relaxed);
+            pooled_buffer
+        };
+        let buffer = match pooled_buffer {
+            Some(mut buffer) => {
+                inner.hits.fetch_add(1, Ordering::Relaxed);
+                // buffers split by decoders may have handed out part of their capacity
+                buffer.reserve(inner.buffer_capacity);
+                buffer
+            }
+            None => {
+                inner.misses.fetch_add(1, Ordering::Relaxed);
+                BytesMut::with_capacity(inner.buffer_capacity)
+            }
+        };
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    fn release(&self, mut buffer: BytesMut) {
+        let inner = &self.inner;
+        if buffer.capacity() > inner.buffer_capacity * MAX_POOLED_CAPACITY_FACTOR {
+            return;
+        }
+        buffer.clear();
+        let mut idle_buffers = inner.idle_buffers.lock().unwrap();
+        if idle_buffers.len() < inner.max_idle_buffers {
+            idle_buffers.push(buffer);
+            inner
+                .idle_buffers_count
+                .store(idle_buffers.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Buffer of a `BufferPool`, returned to it when dropped.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buffer: BytesMut,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+
+    use super::*;
+
+    #[test]
+    fn test_buffer_pool_should_reuse_released_buffers() {
+        let metrics = Metrics::new();
+        let pool = BufferPool::new("test", 1024, 1, &metrics);
+
+        let mut buffer = pool.acquire();
+        buffer.put_slice(b"request");
+        let other_buffer = pool.acquire();
+        drop(buffer);
+        // only one idle buffer is kept
+        drop(other_buffer);
+        let buffer = pool.acquire();
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 1024);
+
+        let mut oversized_buffer = pool.acquire();
+        oversized_buffer.reserve(1024 * MAX_POOLED_CAPACITY_FACTOR + 1);
+        drop(oversized_buffer);
+
+        assert_eq!(
+            metrics.snapshot(),
+            vec![
+                (r#"buffer_pool_hits_total{pool="test"}"#.to_string(), 1),
+                (r#"buffer_pool_idle_buffers{pool="test"}"#.to_string(), 0),
+                (r#"buffer_pool_misses_total{pool="test"}"#.to_string(), 3),
+            ]
+        );
+    }
+}
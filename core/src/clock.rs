@@ -0,0 +1,168 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use common::clock::{is_skew_significant, now_millis};
+use tokio_util::sync::CancellationToken;
+
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time of the broker's time-based features, e.g. the linger of partition writers and the
+/// session timeouts of group members and brokers. Tests use a `MockClock` which they move
+/// forward instead of sleeping.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for timeouts and timers.
+    fn now(&self) -> Instant;
+
+    /// Milliseconds since the epoch, never going backwards.
+    fn now_millis(&self) -> u128;
+
+    /// Completes once `now` reached `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// Time source for time-based features of the broker. It reads the wall clock once at startup and
+/// advances it with the monotonic clock, so a wall clock stepped by a misconfigured NTP host can't
+/// make fresh data look old or old data look fresh.
+#[derive(Debug, Clone, Copy)]
+pub struct BrokerClock {
+    wall_clock_at_start_millis: u128,
+    started: Instant,
+}
+
+impl BrokerClock {
+    pub fn new() -> Self {
+        BrokerClock {
+            wall_clock_at_start_millis: now_millis(),
+            started: Instant::now(),
+        }
+    }
+
+    /// How far the wall clock has moved away from this clock since startup.
+    pub fn wall_clock_drift_millis(&self) -> i128 {
+        now_millis() as i128 - self.now_millis() as i128
+    }
+}
+
+impl Clock for BrokerClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_millis(&self) -> u128 {
+        self.wall_clock_at_start_millis + self.started.elapsed().as_millis()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// Clock of tests which only moves when it is advanced, waking the timers which expired.
+#[cfg(test)]
+pub struct MockClock {
+    now_tx: tokio::sync::watch::Sender<Instant>,
+    started: Instant,
+    wall_clock_at_start_millis: u128,
+    /// Timers polled but not expired yet
+    sleeping_tx: std::sync::Arc<tokio::sync::watch::Sender<usize>>,
+}
+
+/// Counts a pending timer of a `MockClock` until it expires or is dropped.
+#[cfg(test)]
+struct Sleeping(std::sync::Arc<tokio::sync::watch::Sender<usize>>);
+
+#[cfg(test)]
+impl Drop for Sleeping {
+    fn drop(&mut self) {
+        self.0.send_modify(|sleeping| *sleeping -= 1);
+    }
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        let started = Instant::now();
+        MockClock {
+            now_tx: tokio::sync::watch::channel(started).0,
+            started,
+            wall_clock_at_start_millis: now_millis(),
+            sleeping_tx: std::sync::Arc::new(tokio::sync::watch::channel(0).0),
+        }
+    }
+
+    /// Moves the clock forward and lets the tasks of the timers which expired run.
+    pub async fn advance(&self, duration: Duration) {
+        self.now_tx.send_modify(|now| *now += duration);
+        tokio::task::yield_now().await;
+    }
+
+    /// Completes once a task waits for a timer, e.g. a partition writer for its linger.
+    pub async fn timer_started(&self) {
+        let mut sleeping_rx = self.sleeping_tx.subscribe();
+        let _ = sleeping_rx.wait_for(|sleeping| *sleeping > 0).await;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now_tx.borrow()
+    }
+
+    fn now_millis(&self) -> u128 {
+        self.wall_clock_at_start_millis + (self.now() - self.started).as_millis()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let mut now_rx = self.now_tx.subscribe();
+        let sleeping_tx = self.sleeping_tx.clone();
+        // counted from the first poll on, as `select!` creates the futures of disabled branches
+        Box::pin(async move {
+            if *now_rx.borrow() >= deadline {
+                return;
+            }
+            sleeping_tx.send_modify(|sleeping| *sleeping += 1);
+            let _sleeping = Sleeping(sleeping_tx);
+            let _ = now_rx.wait_for(|now| *now >= deadline).await;
+        })
+    }
+}
+
+/// Periodically warns when the wall clock of the host drifts away from the broker clock.
+pub async fn start_clock_monitor(clock: BrokerClock, cancellation_token: CancellationToken) {
+    let mut interval = tokio::time::interval(CLOCK_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let drift_millis = clock.wall_clock_drift_millis();
+                if is_skew_significant(drift_millis) {
+                    tracing::warn!(
+                        "Wall clock drifted {} ms away from the broker clock, check NTP on this host",
+                        drift_millis
+                    );
+                }
+            }
+            _ = cancellation_token.cancelled() => {
+                tracing::info!("Stopping clock monitor");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broker_clock_should_follow_wall_clock_without_going_backwards() {
+        let clock = BrokerClock::new();
+        let first = clock.now_millis();
+        std::thread::sleep(Duration::from_millis(20));
+        let second = clock.now_millis();
+
+        assert!(second >= first + 20);
+        assert!(!is_skew_significant(clock.wall_clock_drift_millis()));
+    }
+}
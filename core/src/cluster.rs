@@ -0,0 +1,595 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use std::{fmt, io};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use common::codecs::protocol::{next_correlation_id, Request, ResponseCodec};
+use common::models::{BrokerRegistration, BrokerResponse, TopicCommand};
+use common::sasl::{SaslCredentials, SaslMechanism};
+use common::tls::TLS_SCHEME;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::rustls::ClientConfig;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::config::{ConfigError, ConfigSource};
+use crate::encryption::EncryptionSettings;
+use crate::metrics::MetricsGranularity;
+
+pub type BrokerId = u32;
+
+/// Client ID of the requests brokers send to each other.
+const BROKER_CLIENT_ID: &str = "walrs-broker";
+
+/// TLS of the connections to other brokers, set at startup by brokers with a TLS CA.
+static BROKER_TLS: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+/// SASL credentials of the connections to other brokers, set at startup by brokers with SASL.
+static BROKER_CREDENTIALS: OnceLock<SaslCredentials> = OnceLock::new();
+
+/// Where the broker listens and stores its logs, and the other brokers of its cluster.
+/// A broker without peers leads every partition, like before brokers formed clusters.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClusterSettings {
+    /// `WALRS_BROKER_ID`
+    pub broker_id: BrokerId,
+    /// `WALRS_LISTEN_ADDRESS`
+    pub listen_address: String,
+    /// `WALRS_ADVERTISED_ADDRESS`, the address the broker registers with for other brokers and
+    /// clients, the listen address by default, prefixed with `tls://` when the broker uses TLS
+    pub advertised_address: String,
+    /// `WALRS_HTTP_LISTEN_ADDRESS`, where the HTTP proxy listens, no proxy is started without it
+    pub http_listen_address: Option<String>,
+    /// `WALRS_GRPC_LISTEN_ADDRESS`, where the gRPC server of brokers built with the `grpc`
+    /// feature listens, no server is started without it
+    pub grpc_listen_address: Option<String>,
+    /// `WALRS_RACK`, e.g. the availability zone of the broker
+    pub rack: Option<String>,
+    /// `WALRS_LOG_DIR`
+    pub log_dir_path: String,
+    /// `WALRS_LOG_DIR_MIN_FREE_PERCENT`, share of the log directory's file system which must
+    /// stay free, produces are rejected with `ProduceError::StorageFull` below it, 0 never
+    /// rejects them
+    pub log_dir_min_free_percent: u8,
+    /// `WALRS_PEERS`, the other brokers as comma separated `<broker id>=<host:port>` pairs,
+    /// e.g. `1=broker-1:8080,2=broker-2:8080`
+    pub peers: BTreeMap<BrokerId, String>,
+    /// `WALRS_REPLICA_LAG_TIME_MAX_MS`, followers which did not catch up with their leader
+    /// within this time are removed from the ISR, like Kafka's `replica.lag.time.max.ms`
+    pub replica_lag_time_max: Duration,
+    /// `WALRS_HIGH_WATERMARK_CHECKPOINT_INTERVAL_MS`, time between two checkpoints of the high
+    /// watermarks of the broker's partitions, like Kafka's
+    /// `replica.high.watermark.checkpoint.interval.ms`
+    pub high_watermark_checkpoint_interval: Duration,
+    /// `WALRS_LEADER_REPLICATION_THROTTLED_RATE`, bytes per second the broker serves to the
+    /// followers of its partitions, unlimited without a value, like Kafka's
+    /// `leader.replication.throttled.rate`
+    pub leader_replication_throttled_rate: Option<u64>,
+    /// `WALRS_FOLLOWER_REPLICATION_THROTTLED_RATE`, bytes per second the broker copies from the
+    /// leaders of the partitions it follows, unlimited without a value
+    pub follower_replication_throttled_rate: Option<u64>,
+    /// `WALRS_BROKER_SESSION_TIMEOUT_MS`, brokers which did not send the controller a heartbeat
+    /// within this time are considered dead and their partitions get new leaders, like Kafka's
+    /// `broker.session.timeout.ms`
+    pub broker_session_timeout: Duration,
+    /// `WALRS_AUTO_LEADER_REBALANCE_ENABLE`, whether the controller moves the leadership of
+    /// partitions back to their preferred replicas, like Kafka's `auto.leader.rebalance.enable`
+    pub auto_leader_rebalance_enable: bool,
+    /// `WALRS_LEADER_IMBALANCE_CHECK_INTERVAL_MS`, like Kafka's
+    /// `leader.imbalance.check.interval.seconds`
+    pub leader_imbalance_check_interval: Duration,
+    /// `WALRS_LEADER_IMBALANCE_PER_BROKER_PERCENTAGE`, share of the partitions a broker is the
+    /// preferred replica of which may be led by other brokers before the controller rebalances
+    /// them, like Kafka's `leader.imbalance.per.broker.percentage`
+    pub leader_imbalance_per_broker_percentage: u32,
+    /// `WALRS_UNCLEAN_LEADER_ELECTION_ENABLE`, whether the controller elects a replica which is
+    /// not in sync when no replica of the ISR is alive, losing the records it misses, instead of
+    /// keeping the partition offline, like Kafka's `unclean.leader.election.enable`
+    pub unclean_leader_election_enable: bool,
+    /// `WALRS_CONTROLLED_SHUTDOWN_ENABLE`, whether a broker which shuts down asks the controller
+    /// to move the leadership of its partitions to other replicas first, like Kafka's
+    /// `controlled.shutdown.enable`
+    pub controlled_shutdown_enable: bool,
+    /// `WALRS_SHUTDOWN_TIMEOUT_MS`, how long a broker which shuts down waits for its followers
+    /// to catch up, for the controller to move its partitions, and then for its connections to
+    /// answer the requests in flight
+    pub shutdown_timeout: Duration,
+    /// `WALRS_PARTITION_LINGER_MS`, how long uncompressed records wait in a partition writer for
+    /// more records to fill their topic's batch size before they are written anyway
+    pub partition_linger: Duration,
+    /// `WALRS_MESSAGE_MAX_BYTES`, largest batch the broker appends to topics without their own
+    /// `max_message_bytes`, like Kafka's `message.max.bytes`
+    pub message_max_bytes: usize,
+    /// `WALRS_METRICS_GRANULARITY`, `broker`, `topic` or `partition`, whether the broker keeps
+    /// metrics of every topic or every partition too
+    pub metrics_granularity: MetricsGranularity,
+    /// TLS of the listener, which only accepts TLS connections with it
+    pub tls: Option<TlsSettings>,
+    /// SASL authentication of the connections, which have to authenticate with it
+    pub sasl: Option<SaslSettings>,
+    /// Encryption at rest of the segments, which are written in plaintext without it
+    pub encryption: Option<EncryptionSettings>,
+    /// `WALRS_AUDIT_LOG_PATH`, file the administrative requests and failed authentications are
+    /// appended to as JSON lines, no audit log is written without it
+    pub audit_log_path: Option<String>,
+}
+
+/// PEM files of a broker using TLS.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TlsSettings {
+    /// `WALRS_TLS_CERT_PATH`, certificate chain the broker presents to clients and other brokers
+    pub certificate_path: String,
+    /// `WALRS_TLS_KEY_PATH`
+    pub private_key_path: String,
+    /// `WALRS_TLS_CA_PATH`, certificates which issued the certificates of the other brokers, and
+    /// of the clients with client authentication. Required to connect to `tls://` peers.
+    pub ca_path: Option<String>,
+    /// `WALRS_TLS_CLIENT_AUTH`, whether clients and other brokers have to present a certificate
+    /// issued by the CA, like Kafka's `ssl.client.auth=required`
+    pub client_auth: bool,
+}
+
+/// Users of a broker authenticating its connections with SASL.
+#[derive(PartialEq, Clone)]
+pub struct SaslSettings {
+    /// `WALRS_SASL_ENABLED_MECHANISMS`, comma separated, `SCRAM-SHA-256` by default, like Kafka's
+    /// `sasl.enabled.mechanisms`. Brokers authenticate to each other with the first one.
+    pub enabled_mechanisms: Vec<SaslMechanism>,
+    /// `WALRS_SASL_USERS`, comma separated `<username>:<password>` pairs, e.g.
+    /// `alice:secret,broker:secret`
+    pub users: BTreeMap<String, String>,
+    /// `WALRS_SASL_INTER_BROKER_USER`, the user of `users` the broker authenticates to other
+    /// brokers as
+    pub inter_broker_user: Option<String>,
+}
+
+impl fmt::Debug for SaslSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaslSettings")
+            .field("enabled_mechanisms", &self.enabled_mechanisms)
+            .field("users", &self.users.keys().collect::<Vec<_>>())
+            .field("inter_broker_user", &self.inter_broker_user)
+            .finish()
+    }
+}
+
+impl SaslSettings {
+    /// Credentials of `inter_broker_user`, `None` without one or when it is not in `users`.
+    pub fn inter_broker_credentials(&self) -> Option<SaslCredentials> {
+        let username = self.inter_broker_user.as_ref()?;
+        Some(SaslCredentials {
+            mechanism: *self.enabled_mechanisms.first()?,
+            username: username.clone(),
+            password: self.users.get(username)?.clone(),
+        })
+    }
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        ClusterSettings {
+            broker_id: 0,
+            listen_address: "0.0.0.0:8080".to_string(),
+            advertised_address: "0.0.0.0:8080".to_string(),
+            http_listen_address: None,
+            grpc_listen_address: None,
+            rack: None,
+            log_dir_path: "./logs/".to_string(),
+            log_dir_min_free_percent: 5,
+            peers: BTreeMap::new(),
+            replica_lag_time_max: Duration::from_secs(30),
+            high_watermark_checkpoint_interval: Duration::from_secs(5),
+            leader_replication_throttled_rate: None,
+            follower_replication_throttled_rate: None,
+            broker_session_timeout: Duration::from_secs(9),
+            auto_leader_rebalance_enable: true,
+            leader_imbalance_check_interval: Duration::from_secs(300),
+            leader_imbalance_per_broker_percentage: 10,
+            unclean_leader_election_enable: false,
+            controlled_shutdown_enable: true,
+            shutdown_timeout: Duration::from_secs(30),
+            partition_linger: Duration::from_millis(5),
+            message_max_bytes: 1024 * 1024,
+            metrics_granularity: MetricsGranularity::Broker,
+            tls: None,
+            sasl: None,
+            encryption: None,
+            audit_log_path: None,
+        }
+    }
+}
+
+impl ClusterSettings {
+    pub fn from_config(config: &ConfigSource) -> Result<Self, ConfigError> {
+        let defaults = ClusterSettings::default();
+        let listen_address: String = config
+            .value("WALRS_LISTEN_ADDRESS")
+            .unwrap_or(defaults.listen_address);
+        let ca_path = config.value("WALRS_TLS_CA_PATH");
+        let client_auth = config.parse("WALRS_TLS_CLIENT_AUTH")?.unwrap_or(false);
+        let tls = match (
+            config.value("WALRS_TLS_CERT_PATH"),
+            config.value("WALRS_TLS_KEY_PATH"),
+        ) {
+            (Some(certificate_path), Some(private_key_path)) => Some(TlsSettings {
+                certificate_path,
+                private_key_path,
+                ca_path,
+                client_auth,
+            }),
+            _ => None,
+        };
+        let enabled_mechanisms = config
+            .value("WALRS_SASL_ENABLED_MECHANISMS")
+            .map(|mechanisms| parse_mechanisms(&mechanisms))
+            .unwrap_or_else(|| vec![SaslMechanism::ScramSha256]);
+        let inter_broker_user = config.value("WALRS_SASL_INTER_BROKER_USER");
+        let sasl = config.value("WALRS_SASL_USERS").map(|users| SaslSettings {
+            enabled_mechanisms,
+            users: parse_users(&users),
+            inter_broker_user,
+        });
+        let encryption_keys = config
+            .value("WALRS_ENCRYPTION_KEYS")
+            .map(|keys| parse_encryption_keys(&keys))
+            .filter(|keys| !keys.is_empty());
+        let active_key_id: Option<u8> = config.parse("WALRS_ENCRYPTION_ACTIVE_KEY_ID")?;
+        let encryption = encryption_keys.map(|keys| EncryptionSettings {
+            active_key_id: active_key_id
+                .or_else(|| keys.keys().next_back().copied())
+                .unwrap_or_default(),
+            keys,
+        });
+        let scheme = if tls.is_some() { TLS_SCHEME } else { "" };
+        Ok(ClusterSettings {
+            broker_id: config
+                .parse("WALRS_BROKER_ID")?
+                .unwrap_or(defaults.broker_id),
+            advertised_address: config
+                .value("WALRS_ADVERTISED_ADDRESS")
+                .unwrap_or_else(|| format!("{}{}", scheme, listen_address)),
+            listen_address,
+            http_listen_address: config.value("WALRS_HTTP_LISTEN_ADDRESS"),
+            grpc_listen_address: config.value("WALRS_GRPC_LISTEN_ADDRESS"),
+            rack: config.value("WALRS_RACK").filter(|rack| !rack.is_empty()),
+            log_dir_path: config
+                .value("WALRS_LOG_DIR")
+                .unwrap_or(defaults.log_dir_path),
+            log_dir_min_free_percent: config
+                .parse("WALRS_LOG_DIR_MIN_FREE_PERCENT")?
+                .unwrap_or(defaults.log_dir_min_free_percent),
+            peers: config
+                .value("WALRS_PEERS")
+                .map(|peers| parse_peers(&peers))
+                .unwrap_or(defaults.peers),
+            replica_lag_time_max: config
+                .parse_millis("WALRS_REPLICA_LAG_TIME_MAX_MS")?
+                .unwrap_or(defaults.replica_lag_time_max),
+            high_watermark_checkpoint_interval: config
+                .parse_millis("WALRS_HIGH_WATERMARK_CHECKPOINT_INTERVAL_MS")?
+                .unwrap_or(defaults.high_watermark_checkpoint_interval),
+            leader_replication_throttled_rate: config
+                .parse("WALRS_LEADER_REPLICATION_THROTTLED_RATE")?,
+            follower_replication_throttled_rate: config
+                .parse("WALRS_FOLLOWER_REPLICATION_THROTTLED_RATE")?,
+            broker_session_timeout: config
+                .parse_millis("WALRS_BROKER_SESSION_TIMEOUT_MS")?
+                .unwrap_or(defaults.broker_session_timeout),
+            auto_leader_rebalance_enable: config
+                .parse("WALRS_AUTO_LEADER_REBALANCE_ENABLE")?
+                .unwrap_or(defaults.auto_leader_rebalance_enable),
+            leader_imbalance_check_interval: config
+                .parse_millis("WALRS_LEADER_IMBALANCE_CHECK_INTERVAL_MS")?
+                .unwrap_or(defaults.leader_imbalance_check_interval),
+            leader_imbalance_per_broker_percentage: config
+                .parse("WALRS_LEADER_IMBALANCE_PER_BROKER_PERCENTAGE")?
+                .unwrap_or(defaults.leader_imbalance_per_broker_percentage),
+            unclean_leader_election_enable: config
+                .parse("WALRS_UNCLEAN_LEADER_ELECTION_ENABLE")?
+                .unwrap_or(defaults.unclean_leader_election_enable),
+            controlled_shutdown_enable: config
+                .parse("WALRS_CONTROLLED_SHUTDOWN_ENABLE")?
+                .unwrap_or(defaults.controlled_shutdown_enable),
+            shutdown_timeout: config
+                .parse_millis("WALRS_SHUTDOWN_TIMEOUT_MS")?
+                .unwrap_or(defaults.shutdown_timeout),
+            partition_linger: config
+                .parse_millis("WALRS_PARTITION_LINGER_MS")?
+                .unwrap_or(defaults.partition_linger),
+            message_max_bytes: config
+                .parse("WALRS_MESSAGE_MAX_BYTES")?
+                .unwrap_or(defaults.message_max_bytes),
+            metrics_granularity: config
+                .parse("WALRS_METRICS_GRANULARITY")?
+                .unwrap_or(defaults.metrics_granularity),
+            tls,
+            sasl,
+            encryption,
+            audit_log_path: config.value("WALRS_AUDIT_LOG_PATH"),
+        })
+    }
+
+    /// Brokers holding a copy of the partition, its leader first. Brokers are ordered by ID and
+    /// alternate between racks, like Kafka's rack-aware assignment. Leaders are spread round
+    /// robin over that order, the brokers following the leader hold the other replicas, skipping
+    /// brokers in racks which already hold a replica until every rack holds one. So losing a rack
+    /// does not lose every replica of a partition. There are never more replicas than brokers.
+    /// Peers which did not register yet count as brokers without a rack.
+    pub fn replicas(
+        &self,
+        registered_brokers: &[BrokerRegistration],
+        partition_index: u8,
+        replication_factor: u8,
+    ) -> Vec<BrokerId> {
+        let mut broker_racks: BTreeMap<BrokerId, Option<String>> = self
+            .peers
+            .keys()
+            .map(|broker_id| (*broker_id, None))
+            .collect();
+        for registration in registered_brokers {
+            broker_racks.insert(registration.broker_id, registration.rack.clone());
+        }
+        broker_racks.insert(self.broker_id, self.rack.clone());
+        let broker_ids = rack_alternated_broker_ids(&broker_racks);
+        let replica_count = (replication_factor.max(1) as usize).min(broker_ids.len());
+        let leader_index = partition_index as usize % broker_ids.len();
+        let candidates: Vec<BrokerId> = (0..broker_ids.len())
+            .map(|offset| broker_ids[(leader_index + offset) % broker_ids.len()])
+            .collect();
+        let mut replicas: Vec<BrokerId> = vec![];
+        let mut racks_with_replicas = BTreeSet::new();
+        for broker_id in &candidates {
+            if replicas.len() < replica_count
+                && racks_with_replicas.insert(&broker_racks[broker_id])
+            {
+                replicas.push(*broker_id);
+            }
+        }
+        for broker_id in candidates {
+            if replicas.len() < replica_count && !replicas.contains(&broker_id) {
+                replicas.push(broker_id);
+            }
+        }
+        replicas
+    }
+
+    pub fn peer_address(&self, broker_id: BrokerId) -> Option<&str> {
+        self.peers.get(&broker_id).map(String::as_str)
+    }
+}
+
+fn parse_peers(value: &str) -> BTreeMap<BrokerId, String> {
+    let mut peers = BTreeMap::new();
+    for peer in value
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+    {
+        let parsed_peer = peer.split_once('=').and_then(|(broker_id, address)| {
+            Some((broker_id.trim().parse().ok()?, address.trim().to_string()))
+        });
+        match parsed_peer {
+            Some((broker_id, address)) => {
+                peers.insert(broker_id, address);
+            }
+            None => tracing::warn!("Ignoring invalid peer {} of WALRS_PEERS", peer),
+        }
+    }
+    peers
+}
+
+fn parse_users(value: &str) -> BTreeMap<String, String> {
+    let mut users = BTreeMap::new();
+    for user in value.split(',').filter(|user| !user.trim().is_empty()) {
+        match user.split_once(':') {
+            Some((username, password)) if !username.trim().is_empty() => {
+                users.insert(username.trim().to_string(), password.to_string());
+            }
+            _ => tracing::warn!("Ignoring invalid user of WALRS_SASL_USERS"),
+        }
+    }
+    users
+}
+
+fn parse_encryption_keys(value: &str) -> BTreeMap<u8, Vec<u8>> {
+    let mut keys = BTreeMap::new();
+    for key in value
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+    {
+        let parsed_key = key.split_once('=').and_then(|(key_id, key)| {
+            Some((key_id.trim().parse().ok()?, BASE64.decode(key.trim()).ok()?))
+        });
+        match parsed_key {
+            Some((key_id, key)) => {
+                keys.insert(key_id, key);
+            }
+            None => tracing::warn!("Ignoring invalid key of WALRS_ENCRYPTION_KEYS"),
+        }
+    }
+    keys
+}
+
+fn parse_mechanisms(value: &str) -> Vec<SaslMechanism> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|mechanism| !mechanism.is_empty())
+        .filter_map(|mechanism| match mechanism.parse() {
+            Ok(mechanism) => Some(mechanism),
+            Err(e) => {
+                tracing::warn!("Ignoring {} of WALRS_SASL_ENABLED_MECHANISMS", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Broker IDs taking turns between the racks, e.g. `a1, b1, c1, a2, b2, a3` for racks `a`, `b`
+/// and `c`. Brokers without a rack form one rack, so without racks the IDs stay ordered.
+fn rack_alternated_broker_ids(broker_racks: &BTreeMap<BrokerId, Option<String>>) -> Vec<BrokerId> {
+    let mut brokers_by_rack: BTreeMap<&Option<String>, Vec<BrokerId>> = BTreeMap::new();
+    for (broker_id, rack) in broker_racks {
+        brokers_by_rack.entry(rack).or_default().push(*broker_id);
+    }
+    let rack_size = brokers_by_rack.values().map(Vec::len).max().unwrap_or(0);
+    (0..rack_size)
+        .flat_map(|position| {
+            brokers_by_rack
+                .values()
+                .filter_map(move |broker_ids| broker_ids.get(position).copied())
+        })
+        .collect()
+}
+
+/// Makes `send_request` connect to `tls://` addresses with this configuration.
+pub fn set_broker_tls(tls: Arc<ClientConfig>) {
+    if BROKER_TLS.set(tls).is_err() {
+        tracing::warn!("TLS of the connections to other brokers was already set");
+    }
+}
+
+/// Makes `send_request` authenticate its connections with these credentials.
+pub fn set_broker_credentials(credentials: SaslCredentials) {
+    if BROKER_CREDENTIALS.set(credentials).is_err() {
+        tracing::warn!("SASL credentials of the connections to other brokers were already set");
+    }
+}
+
+/// Sends a request to another broker on a new connection and reads its response.
+pub async fn send_request(
+    broker_address: &str,
+    command: &TopicCommand,
+) -> io::Result<BrokerResponse> {
+    let mut stream = common::tls::connect(broker_address, BROKER_TLS.get()).await?;
+    if let Some(credentials) = BROKER_CREDENTIALS.get() {
+        common::sasl::authenticate(&mut stream, BROKER_CLIENT_ID, credentials).await?;
+    }
+    let correlation_id = next_correlation_id();
+    let request = Request::new(
+        correlation_id,
+        BROKER_CLIENT_ID,
+        command.clone(),
+        Bytes::new(),
+    );
+    let mut response_codec = ResponseCodec::default();
+    let mut buffer = BytesMut::new();
+    response_codec.encode(request, &mut buffer)?;
+    stream.write_all(&buffer).await?;
+    stream.flush().await?;
+    buffer.clear();
+    loop {
+        if let Some(response) = response_codec.decode(&mut buffer)? {
+            if response.correlation_id != correlation_id {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "expected the response to request {} but got one to {}",
+                        correlation_id, response.correlation_id
+                    ),
+                ));
+            }
+            return Ok(response.response);
+        }
+        if stream.read_buf(&mut buffer).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed without an answer",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_users_should_skip_users_without_password() {
+        let users = parse_users("alice:secret, bob,:nobody,carol:a:b");
+        assert_eq!(
+            users,
+            BTreeMap::from([
+                ("alice".to_string(), "secret".to_string()),
+                ("carol".to_string(), "a:b".to_string()),
+            ])
+        );
+        assert_eq!(
+            parse_mechanisms("plain, SCRAM-SHA-512,SCRAM-SHA-256"),
+            vec![SaslMechanism::Plain, SaslMechanism::ScramSha256]
+        );
+    }
+
+    #[test]
+    fn test_parse_peers_should_skip_invalid_peers() {
+        let peers =
+            parse_peers("1=broker-1:8080, 2 = broker-2:8080,broker-3:8080,x=broker-4:8080,");
+        assert_eq!(
+            peers,
+            BTreeMap::from([
+                (1, "broker-1:8080".to_string()),
+                (2, "broker-2:8080".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_replicas_should_spread_leaders_over_brokers() {
+        let cluster_settings = ClusterSettings {
+            broker_id: 1,
+            peers: BTreeMap::from([(0, "b0".to_string()), (2, "b2".to_string())]),
+            ..ClusterSettings::default()
+        };
+        assert_eq!(cluster_settings.replicas(&[], 0, 2), vec![0, 1]);
+        assert_eq!(cluster_settings.replicas(&[], 1, 2), vec![1, 2]);
+        assert_eq!(cluster_settings.replicas(&[], 2, 2), vec![2, 0]);
+        assert_eq!(cluster_settings.replicas(&[], 4, 5), vec![1, 2, 0]);
+
+        let single_broker = ClusterSettings::default();
+        assert_eq!(single_broker.replicas(&[], 3, 2), vec![0]);
+    }
+
+    #[test]
+    fn test_replicas_should_spread_replicas_over_racks() {
+        let cluster_settings = ClusterSettings {
+            broker_id: 0,
+            rack: Some("a".to_string()),
+            peers: BTreeMap::from([
+                (1, "b1".to_string()),
+                (2, "b2".to_string()),
+                (3, "b3".to_string()),
+            ]),
+            ..ClusterSettings::default()
+        };
+        let registration = |broker_id, rack: &str| BrokerRegistration {
+            broker_id,
+            address: format!("b{}", broker_id),
+            rack: Some(rack.to_string()),
+        };
+        let registered_brokers = [
+            registration(1, "a"),
+            registration(2, "a"),
+            registration(3, "b"),
+        ];
+
+        // brokers in rack order: 0, 3, 1, 2
+        assert_eq!(
+            cluster_settings.replicas(&registered_brokers, 0, 2),
+            vec![0, 3]
+        );
+        assert_eq!(
+            cluster_settings.replicas(&registered_brokers, 1, 2),
+            vec![3, 1]
+        );
+        assert_eq!(
+            cluster_settings.replicas(&registered_brokers, 2, 2),
+            vec![1, 3]
+        );
+        assert_eq!(
+            cluster_settings.replicas(&registered_brokers, 3, 3),
+            vec![2, 3, 0]
+        );
+    }
+}
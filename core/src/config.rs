@@ -0,0 +1,593 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fmt, fs, io};
+
+use crate::cluster::ClusterSettings;
+use crate::connections::ConnectionSettings;
+use crate::encryption::KEY_LEN;
+use crate::quotas::QuotaSettings;
+use crate::resources::{ResourceLimits, ResourceSettings};
+
+const ENV_PREFIX: &str = "WALRS_";
+
+/// Every setting of the broker, read at startup.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BrokerConfig {
+    pub resources: ResourceSettings,
+    pub cluster: ClusterSettings,
+    pub quotas: QuotaSettings,
+    pub connections: ConnectionSettings,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read { path: String, error: io::Error },
+    Syntax(String),
+    InvalidValue { name: String, value: String },
+    UnknownKeys(Vec<String>),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => write!(f, "could not read {}: {}", path, error),
+            ConfigError::Syntax(error) => write!(f, "config file is not valid TOML: {}", error),
+            ConfigError::InvalidValue { name, value } => write!(
+                f,
+                "invalid value {} of {} ({} in the config file)",
+                value,
+                name,
+                file_key(name)
+            ),
+            ConfigError::UnknownKeys(keys) => {
+                write!(f, "unknown keys in the config file: {}", keys.join(", "))
+            }
+            ConfigError::Invalid(problems) => write!(f, "{}", problems.join(", ")),
+        }
+    }
+}
+
+/// Values of the settings by the name of their environment variable. A variable of the
+/// environment overrides the key of the config file, which is its name in lowercase without the
+/// `WALRS_` prefix, e.g. `log_dir` for `WALRS_LOG_DIR`.
+pub struct ConfigSource {
+    file_values: BTreeMap<String, String>,
+    read_keys: RefCell<BTreeSet<String>>,
+}
+
+impl ConfigSource {
+    /// The settings of the environment, without a config file.
+    pub fn from_env() -> Self {
+        ConfigSource::new(BTreeMap::new())
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_string(),
+            error,
+        })?;
+        ConfigSource::from_toml(&content)
+    }
+
+    /// Values are strings, integers or booleans, arrays are joined with commas like the lists of
+    /// the environment variables, e.g. `peers = ["1=broker-1:8080", "2=broker-2:8080"]`.
+    fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        let table: toml::Table = content
+            .parse()
+            .map_err(|e: toml::de::Error| ConfigError::Syntax(e.to_string()))?;
+        let mut file_values = BTreeMap::new();
+        for (key, value) in table {
+            let value = match &value {
+                toml::Value::Array(values) => values
+                    .iter()
+                    .map(scalar)
+                    .collect::<Option<Vec<_>>>()
+                    .map(|values| values.join(",")),
+                value => scalar(value),
+            };
+            let Some(value) = value else {
+                return Err(ConfigError::InvalidValue {
+                    name: env_name(&key),
+                    value: "table".to_string(),
+                });
+            };
+            file_values.insert(key, value);
+        }
+        Ok(ConfigSource::new(file_values))
+    }
+
+    fn new(file_values: BTreeMap<String, String>) -> Self {
+        ConfigSource {
+            file_values,
+            read_keys: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    /// The value of the setting named `name`, `None` when it is not set.
+    pub fn value(&self, name: &str) -> Option<String> {
+        let key = file_key(name);
+        let file_value = self.file_values.get(&key).cloned();
+        self.read_keys.borrow_mut().insert(key);
+        env::var(name).ok().or(file_value)
+    }
+
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, ConfigError> {
+        self.value(name)
+            .map(|value| {
+                value.parse().map_err(|_| ConfigError::InvalidValue {
+                    name: name.to_string(),
+                    value,
+                })
+            })
+            .transpose()
+    }
+
+    pub fn parse_millis(&self, name: &str) -> Result<Option<Duration>, ConfigError> {
+        Ok(self.parse(name)?.map(Duration::from_millis))
+    }
+
+    /// Keys of the config file no setting was read from, mistyped ones for instance.
+    fn unknown_keys(&self) -> Vec<String> {
+        let read_keys = self.read_keys.borrow();
+        self.file_values
+            .keys()
+            .filter(|key| !read_keys.contains(*key))
+            .cloned()
+            .collect()
+    }
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn file_key(name: &str) -> String {
+    name.trim_start_matches(ENV_PREFIX).to_lowercase()
+}
+
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase())
+}
+
+impl BrokerConfig {
+    /// The default settings for the given resources, without reading the environment or a
+    /// config file, e.g. for brokers embedded in tests.
+    pub fn defaults(limits: ResourceLimits) -> Self {
+        BrokerConfig {
+            resources: ResourceSettings::from_limits(limits),
+            cluster: ClusterSettings::default(),
+            quotas: QuotaSettings::default(),
+            connections: ConnectionSettings::default(),
+        }
+    }
+
+    /// Reads the settings from the environment and the TOML config file at `path`, settings
+    /// which are in neither keep their defaults.
+    pub fn load(path: Option<&str>, limits: ResourceLimits) -> Result<Self, ConfigError> {
+        let source = match path {
+            Some(path) => ConfigSource::from_file(path)?,
+            None => ConfigSource::from_env(),
+        };
+        let config = BrokerConfig {
+            resources: ResourceSettings::from_config(&source, limits)?,
+            cluster: ClusterSettings::from_config(&source)?,
+            quotas: QuotaSettings::from_config(&source)?,
+            connections: ConnectionSettings::from_config(&source)?,
+        };
+        let unknown_keys = source.unknown_keys();
+        if !unknown_keys.is_empty() {
+            return Err(ConfigError::UnknownKeys(unknown_keys));
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Rejects settings the broker would fail with later, or which would stall it.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = vec![];
+        let mut check = |valid: bool, problem: &str| {
+            if !valid {
+                problems.push(problem.to_string());
+            }
+        };
+        let resources = &self.resources;
+        check(
+            resources.worker_threads > 0 && resources.disk_io_threads > 0,
+            "worker_threads and disk_io_threads must be positive",
+        );
+        check(
+            resources.partition_channel_size > 0 && resources.manager_channel_size > 0,
+            "channel sizes must be positive",
+        );
+        check(
+            resources.read_buffer_size > 0,
+            "read_buffer_size must be positive",
+        );
+
+        let cluster = &self.cluster;
+        let addresses = [&cluster.http_listen_address, &cluster.grpc_listen_address]
+            .into_iter()
+            .flatten()
+            .chain([&cluster.listen_address]);
+        for address in addresses {
+            check(
+                has_port(address),
+                &format!("{} is not a <host>:<port> address", address),
+            );
+        }
+        check(
+            !cluster.peers.contains_key(&cluster.broker_id),
+            "peers must not contain the broker itself",
+        );
+        check(
+            cluster.message_max_bytes > 0,
+            "message_max_bytes must be positive",
+        );
+        check(
+            cluster.leader_imbalance_per_broker_percentage <= 100,
+            "leader_imbalance_per_broker_percentage must be at most 100",
+        );
+        check(
+            cluster.log_dir_min_free_percent < 100,
+            "log_dir_min_free_percent must be below 100",
+        );
+        if let Some(tls) = &cluster.tls {
+            check(
+                !tls.client_auth || tls.ca_path.is_some(),
+                "tls_client_auth requires tls_ca_path",
+            );
+        }
+        if let Some(encryption) = &cluster.encryption {
+            check(
+                encryption.keys.contains_key(&encryption.active_key_id),
+                "encryption_active_key_id must be one of encryption_keys",
+            );
+            check(
+                encryption.keys.values().all(|key| key.len() == KEY_LEN),
+                "encryption_keys must be 256 bit keys",
+            );
+        }
+        if let Some(sasl) = &cluster.sasl {
+            check(
+                !sasl.enabled_mechanisms.is_empty(),
+                "sasl_enabled_mechanisms must name a mechanism",
+            );
+            check(
+                sasl.inter_broker_user
+                    .as_ref()
+                    .is_none_or(|user| sasl.users.contains_key(user)),
+                "sasl_inter_broker_user must be one of sasl_users",
+            );
+        }
+
+        let quotas = &self.quotas;
+        check(
+            [
+                quotas.producer_byte_rate,
+                quotas.consumer_byte_rate,
+                quotas.request_rate,
+            ]
+            .iter()
+            .all(|rate| *rate != Some(0)),
+            "quotas must be positive",
+        );
+        check(
+            cluster.leader_replication_throttled_rate != Some(0)
+                && cluster.follower_replication_throttled_rate != Some(0),
+            "replication throttled rates must be positive",
+        );
+        let connections = &self.connections;
+        check(
+            connections.max_connections != Some(0) && connections.max_connections_per_ip != Some(0),
+            "connection limits must be positive",
+        );
+        check(
+            connections.max_in_flight_requests > 0,
+            "max_in_flight_requests must be positive",
+        );
+        check(
+            connections.max_fetch_sessions > 0,
+            "max_fetch_sessions must be positive",
+        );
+        check(
+            connections.socket_request_max_bytes >= cluster.message_max_bytes,
+            "socket_request_max_bytes must be at least message_max_bytes",
+        );
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    /// The effective settings as a config file. Passwords of SASL users are left out.
+    pub fn to_toml(&self) -> String {
+        let mut table = toml::Table::new();
+        let mut set = |name: &str, value: toml::Value| {
+            table.insert(file_key(name), value);
+        };
+        let resources = &self.resources;
+        set("WALRS_WORKER_THREADS", integer(resources.worker_threads));
+        set("WALRS_DISK_IO_THREADS", integer(resources.disk_io_threads));
+        set(
+            "WALRS_PARTITION_CHANNEL_SIZE",
+            integer(resources.partition_channel_size),
+        );
+        set(
+            "WALRS_MANAGER_CHANNEL_SIZE",
+            integer(resources.manager_channel_size),
+        );
+        set(
+            "WALRS_READ_BUFFER_SIZE",
+            integer(resources.read_buffer_size),
+        );
+
+        let cluster = &self.cluster;
+        set("WALRS_BROKER_ID", integer(cluster.broker_id));
+        set(
+            "WALRS_LISTEN_ADDRESS",
+            cluster.listen_address.clone().into(),
+        );
+        set(
+            "WALRS_ADVERTISED_ADDRESS",
+            cluster.advertised_address.clone().into(),
+        );
+        if let Some(address) = &cluster.http_listen_address {
+            set("WALRS_HTTP_LISTEN_ADDRESS", address.clone().into());
+        }
+        if let Some(address) = &cluster.grpc_listen_address {
+            set("WALRS_GRPC_LISTEN_ADDRESS", address.clone().into());
+        }
+        if let Some(rack) = &cluster.rack {
+            set("WALRS_RACK", rack.clone().into());
+        }
+        set("WALRS_LOG_DIR", cluster.log_dir_path.clone().into());
+        set(
+            "WALRS_LOG_DIR_MIN_FREE_PERCENT",
+            integer(cluster.log_dir_min_free_percent),
+        );
+        set(
+            "WALRS_PEERS",
+            array(
+                cluster
+                    .peers
+                    .iter()
+                    .map(|(broker_id, address)| format!("{}={}", broker_id, address)),
+            ),
+        );
+        set(
+            "WALRS_REPLICA_LAG_TIME_MAX_MS",
+            millis(cluster.replica_lag_time_max),
+        );
+        set(
+            "WALRS_HIGH_WATERMARK_CHECKPOINT_INTERVAL_MS",
+            millis(cluster.high_watermark_checkpoint_interval),
+        );
+        for (name, rate) in [
+            (
+                "WALRS_LEADER_REPLICATION_THROTTLED_RATE",
+                cluster.leader_replication_throttled_rate,
+            ),
+            (
+                "WALRS_FOLLOWER_REPLICATION_THROTTLED_RATE",
+                cluster.follower_replication_throttled_rate,
+            ),
+        ] {
+            if let Some(rate) = rate {
+                set(name, integer(rate));
+            }
+        }
+        set(
+            "WALRS_BROKER_SESSION_TIMEOUT_MS",
+            millis(cluster.broker_session_timeout),
+        );
+        set(
+            "WALRS_AUTO_LEADER_REBALANCE_ENABLE",
+            cluster.auto_leader_rebalance_enable.into(),
+        );
+        set(
+            "WALRS_LEADER_IMBALANCE_CHECK_INTERVAL_MS",
+            millis(cluster.leader_imbalance_check_interval),
+        );
+        set(
+            "WALRS_LEADER_IMBALANCE_PER_BROKER_PERCENTAGE",
+            integer(cluster.leader_imbalance_per_broker_percentage),
+        );
+        set(
+            "WALRS_UNCLEAN_LEADER_ELECTION_ENABLE",
+            cluster.unclean_leader_election_enable.into(),
+        );
+        set(
+            "WALRS_CONTROLLED_SHUTDOWN_ENABLE",
+            cluster.controlled_shutdown_enable.into(),
+        );
+        set(
+            "WALRS_SHUTDOWN_TIMEOUT_MS",
+            millis(cluster.shutdown_timeout),
+        );
+        set(
+            "WALRS_PARTITION_LINGER_MS",
+            millis(cluster.partition_linger),
+        );
+        set(
+            "WALRS_MESSAGE_MAX_BYTES",
+            integer(cluster.message_max_bytes),
+        );
+        set(
+            "WALRS_METRICS_GRANULARITY",
+            cluster.metrics_granularity.to_string().into(),
+        );
+        if let Some(tls) = &cluster.tls {
+            set("WALRS_TLS_CERT_PATH", tls.certificate_path.clone().into());
+            set("WALRS_TLS_KEY_PATH", tls.private_key_path.clone().into());
+            if let Some(ca_path) = &tls.ca_path {
+                set("WALRS_TLS_CA_PATH", ca_path.clone().into());
+            }
+            set("WALRS_TLS_CLIENT_AUTH", tls.client_auth.into());
+        }
+        if let Some(sasl) = &cluster.sasl {
+            set(
+                "WALRS_SASL_ENABLED_MECHANISMS",
+                array(sasl.enabled_mechanisms.iter().map(ToString::to_string)),
+            );
+            set(
+                "WALRS_SASL_USERS",
+                array(
+                    sasl.users
+                        .keys()
+                        .map(|username| format!("{}:<redacted>", username)),
+                ),
+            );
+            if let Some(user) = &sasl.inter_broker_user {
+                set("WALRS_SASL_INTER_BROKER_USER", user.clone().into());
+            }
+        }
+        if let Some(path) = &cluster.audit_log_path {
+            set("WALRS_AUDIT_LOG_PATH", path.clone().into());
+        }
+        if let Some(encryption) = &cluster.encryption {
+            set(
+                "WALRS_ENCRYPTION_KEYS",
+                array(
+                    encryption
+                        .keys
+                        .keys()
+                        .map(|key_id| format!("{}=<redacted>", key_id)),
+                ),
+            );
+            set(
+                "WALRS_ENCRYPTION_ACTIVE_KEY_ID",
+                integer(encryption.active_key_id),
+            );
+        }
+
+        let quotas = &self.quotas;
+        for (name, rate) in [
+            ("WALRS_QUOTA_PRODUCER_BYTE_RATE", quotas.producer_byte_rate),
+            ("WALRS_QUOTA_CONSUMER_BYTE_RATE", quotas.consumer_byte_rate),
+            ("WALRS_QUOTA_REQUEST_RATE", quotas.request_rate),
+        ] {
+            if let Some(rate) = rate {
+                set(name, integer(rate));
+            }
+        }
+
+        let connections = &self.connections;
+        if let Some(max_connections) = connections.max_connections {
+            set("WALRS_MAX_CONNECTIONS", integer(max_connections));
+        }
+        if let Some(max_connections_per_ip) = connections.max_connections_per_ip {
+            set(
+                "WALRS_MAX_CONNECTIONS_PER_IP",
+                integer(max_connections_per_ip),
+            );
+        }
+        set(
+            "WALRS_MAX_IN_FLIGHT_REQUESTS",
+            integer(connections.max_in_flight_requests),
+        );
+        set(
+            "WALRS_CONNECTIONS_MAX_IDLE_MS",
+            millis(connections.idle_timeout),
+        );
+        set("WALRS_TCP_NODELAY", connections.tcp_nodelay.into());
+        set(
+            "WALRS_TCP_KEEPALIVE_SECS",
+            integer(connections.tcp_keepalive.map_or(0, |time| time.as_secs())),
+        );
+        set(
+            "WALRS_SOCKET_REQUEST_MAX_BYTES",
+            integer(connections.socket_request_max_bytes),
+        );
+        set(
+            "WALRS_MAX_FETCH_SESSIONS",
+            integer(connections.max_fetch_sessions),
+        );
+        table.to_string()
+    }
+}
+
+fn has_port(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+}
+
+fn integer(value: impl TryInto<i64>) -> toml::Value {
+    toml::Value::Integer(value.try_into().unwrap_or(i64::MAX))
+}
+
+fn millis(duration: Duration) -> toml::Value {
+    integer(duration.as_millis())
+}
+
+fn array(values: impl Iterator<Item = String>) -> toml::Value {
+    toml::Value::Array(values.map(toml::Value::String).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ResourceLimits = ResourceLimits {
+        memory_bytes: 1024 * 1024 * 1024,
+        cpus: 2,
+    };
+
+    fn load(content: &str) -> Result<BrokerConfig, ConfigError> {
+        let directory = tempdir::TempDir::new("broker_config_").unwrap();
+        let path = directory.path().join("broker.toml");
+        fs::write(&path, content).unwrap();
+        BrokerConfig::load(path.to_str(), LIMITS)
+    }
+
+    #[test]
+    fn test_config_file_should_override_defaults_and_be_validated() {
+        let config = load(
+            r#"
+            broker_id = 1
+            log_dir = "/var/lib/walrs"
+            peers = ["2=broker-2:8080", "3=broker-3:8080"]
+            manager_channel_size = 50
+            connections_max_idle_ms = 60000
+            tcp_keepalive_secs = 0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.cluster.broker_id, 1);
+        assert_eq!(config.cluster.log_dir_path, "/var/lib/walrs");
+        assert_eq!(config.cluster.peers.len(), 2);
+        assert_eq!(config.resources.manager_channel_size, 50);
+        assert_eq!(config.connections.idle_timeout, Duration::from_secs(60));
+        assert_eq!(config.connections.tcp_keepalive, None);
+        assert_eq!(
+            config.quotas,
+            QuotaSettings::default(),
+            "settings missing from the file keep their defaults"
+        );
+        assert_eq!(load(&config.to_toml()).unwrap(), config);
+
+        assert!(matches!(
+            load("log_directory = \"/tmp\""),
+            Err(ConfigError::UnknownKeys(keys)) if keys == ["log_directory"]
+        ));
+        assert!(matches!(
+            load("broker_id = \"one\""),
+            Err(ConfigError::InvalidValue { name, .. }) if name == "WALRS_BROKER_ID"
+        ));
+        assert!(matches!(
+            load("broker_id = 2\npeers = [\"2=broker-2:8080\"]\nmanager_channel_size = 0"),
+            Err(ConfigError::Invalid(problems)) if problems.len() == 2
+        ));
+        assert!(matches!(load("broker_id = "), Err(ConfigError::Syntax(_))));
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::codecs::protocol::MAX_FRAME_LENGTH;
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::task_tracker::TaskTrackerToken;
+use tokio_util::task::TaskTracker;
+
+use crate::config::{ConfigError, ConfigSource};
+use crate::metrics::Metrics;
+
+/// Limits and socket options of the client connections of a broker. Each one can be set in the
+/// config file or with its environment variable.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ConnectionSettings {
+    /// `WALRS_MAX_CONNECTIONS`, open connections of the broker, unlimited by default
+    pub max_connections: Option<usize>,
+    /// `WALRS_MAX_CONNECTIONS_PER_IP`, open connections from one IP address, unlimited by default
+    pub max_connections_per_ip: Option<usize>,
+    /// `WALRS_MAX_IN_FLIGHT_REQUESTS`, requests of a connection handled at the same time, further
+    /// requests are read once one of them was answered
+    pub max_in_flight_requests: usize,
+    /// `WALRS_CONNECTIONS_MAX_IDLE_MS`, connections on which no request, not even a keepalive
+    /// ping, arrives within this time are closed
+    pub idle_timeout: Duration,
+    /// `WALRS_TCP_NODELAY`, sends responses without waiting to fill a TCP segment
+    pub tcp_nodelay: bool,
+    /// `WALRS_TCP_KEEPALIVE_SECS`, idle time before the OS probes whether the peer is still
+    /// there, 0 turns TCP keepalive off
+    pub tcp_keepalive: Option<Duration>,
+    /// `WALRS_SOCKET_REQUEST_MAX_BYTES`, requests announcing a larger frame close their
+    /// connection before the frame is buffered, like Kafka's `socket.request.max.bytes`
+    pub socket_request_max_bytes: usize,
+    /// `WALRS_MAX_FETCH_SESSIONS`, fetch sessions the broker keeps for consumers, the least
+    /// recently used one is evicted for a new one, like Kafka's
+    /// `max.incremental.fetch.session.cache.slots`
+    pub max_fetch_sessions: usize,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        ConnectionSettings {
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_in_flight_requests: 100,
+            idle_timeout: Duration::from_secs(30),
+            tcp_nodelay: true,
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            socket_request_max_bytes: MAX_FRAME_LENGTH,
+            max_fetch_sessions: 1000,
+        }
+    }
+}
+
+impl ConnectionSettings {
+    pub fn from_config(config: &ConfigSource) -> Result<Self, ConfigError> {
+        let defaults = ConnectionSettings::default();
+        Ok(ConnectionSettings {
+            max_connections: config
+                .parse("WALRS_MAX_CONNECTIONS")?
+                .or(defaults.max_connections),
+            max_connections_per_ip: config
+                .parse("WALRS_MAX_CONNECTIONS_PER_IP")?
+                .or(defaults.max_connections_per_ip),
+            max_in_flight_requests: config
+                .parse("WALRS_MAX_IN_FLIGHT_REQUESTS")?
+                .unwrap_or(defaults.max_in_flight_requests),
+            idle_timeout: config
+                .parse_millis("WALRS_CONNECTIONS_MAX_IDLE_MS")?
+                .unwrap_or(defaults.idle_timeout),
+            tcp_nodelay: config
+                .parse("WALRS_TCP_NODELAY")?
+                .unwrap_or(defaults.tcp_nodelay),
+            tcp_keepalive: match config.parse::<u64>("WALRS_TCP_KEEPALIVE_SECS")? {
+                Some(0) => None,
+                Some(seconds) => Some(Duration::from_secs(seconds)),
+                None => defaults.tcp_keepalive,
+            },
+            socket_request_max_bytes: config
+                .parse("WALRS_SOCKET_REQUEST_MAX_BYTES")?
+                .unwrap_or(defaults.socket_request_max_bytes),
+            max_fetch_sessions: config
+                .parse("WALRS_MAX_FETCH_SESSIONS")?
+                .unwrap_or(defaults.max_fetch_sessions),
+        })
+    }
+}
+
+/// Counts the open connections of the broker by IP address, so new connections beyond the
+/// limits of the settings are rejected. Once `shutdown_token` is cancelled the connections stop
+/// reading requests and close after answering the requests in flight.
+pub struct ConnectionTracker {
+    settings: ConnectionSettings,
+    open_by_ip: Mutex<HashMap<IpAddr, usize>>,
+    /// Counts every connection as a task until its permit is dropped, so shutdowns wait for them.
+    connection_tasks: TaskTracker,
+    shutdown_token: CancellationToken,
+    open_connections: Arc<AtomicU64>,
+    rejected_connections: Arc<AtomicU64>,
+}
+
+impl ConnectionTracker {
+    pub fn new(
+        settings: ConnectionSettings,
+        metrics: &Metrics,
+        shutdown_token: CancellationToken,
+    ) -> Self {
+        ConnectionTracker {
+            settings,
+            open_by_ip: Mutex::new(HashMap::new()),
+            connection_tasks: TaskTracker::new(),
+            shutdown_token,
+            open_connections: metrics.register("open_connections_count"),
+            rejected_connections: metrics.register("rejected_connections_total"),
+        }
+    }
+
+    /// Counts a new connection from `ip` as open until the returned permit is dropped, `None`
+    /// when the broker or the IP address already has as many connections as allowed.
+    pub fn open(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionPermit> {
+        let mut open_by_ip = self.open_by_ip.lock().unwrap();
+        let open = open_by_ip.values().sum::<usize>();
+        let open_from_ip = open_by_ip.get(&ip).copied().unwrap_or(0);
+        let over_limit = self.settings.max_connections.is_some_and(|max| open >= max)
+            || self
+                .settings
+                .max_connections_per_ip
+                .is_some_and(|max| open_from_ip >= max);
+        if over_limit {
+            tracing::warn!(
+                "Rejecting a connection from {}, {} connections are open, {} from it",
+                ip,
+                open,
+                open_from_ip
+            );
+            self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        *open_by_ip.entry(ip).or_default() += 1;
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        Some(ConnectionPermit {
+            tracker: self.clone(),
+            ip,
+            _task: self.connection_tasks.token(),
+        })
+    }
+
+    /// Waits until every open connection closed, connections opened afterwards are not waited
+    /// for.
+    pub async fn closed(&self) {
+        self.connection_tasks.close();
+        self.connection_tasks.wait().await;
+    }
+
+    fn close(&self, ip: IpAddr) {
+        let mut open_by_ip = self.open_by_ip.lock().unwrap();
+        if let Some(open_from_ip) = open_by_ip.get_mut(&ip) {
+            *open_from_ip -= 1;
+            if *open_from_ip == 0 {
+                open_by_ip.remove(&ip);
+            }
+        }
+        self.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// An open connection, counted by its `ConnectionTracker` until it is dropped.
+pub struct ConnectionPermit {
+    tracker: Arc<ConnectionTracker>,
+    ip: IpAddr,
+    _task: TaskTrackerToken,
+}
+
+impl ConnectionPermit {
+    pub fn settings(&self) -> &ConnectionSettings {
+        &self.tracker.settings
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// Cancelled once the broker shuts down and the connection has to stop reading requests.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.tracker.shutdown_token.clone()
+    }
+
+    /// Applies the socket options of the settings to the connection.
+    pub fn configure(&self, socket: &TcpStream) -> io::Result<()> {
+        let settings = self.settings();
+        socket.set_nodelay(settings.tcp_nodelay)?;
+        if let Some(keepalive_time) = settings.tcp_keepalive {
+            SockRef::from(socket)
+                .set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive_time))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.tracker.close(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_connection_tracker_should_reject_connections_beyond_the_limits() {
+        let metrics = Metrics::new();
+        let tracker = Arc::new(ConnectionTracker::new(
+            ConnectionSettings {
+                max_connections: Some(3),
+                max_connections_per_ip: Some(2),
+                ..ConnectionSettings::default()
+            },
+            &metrics,
+            CancellationToken::new(),
+        ));
+        let first_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let second_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let third_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3));
+
+        let first = tracker.open(first_ip).unwrap();
+        let _second = tracker.open(first_ip).unwrap();
+        assert!(tracker.open(first_ip).is_none());
+        let _third = tracker.open(second_ip).unwrap();
+        assert!(tracker.open(third_ip).is_none());
+        drop(first);
+        let _fourth = tracker.open(third_ip).unwrap();
+
+        assert_eq!(
+            metrics.snapshot(),
+            vec![
+                ("open_connections_count".to_string(), 3),
+                ("rejected_connections_total".to_string(), 2),
+            ]
+        );
+    }
+}
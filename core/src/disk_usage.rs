@@ -0,0 +1,115 @@
+//! Free space of the log directory. Past the threshold the broker turns produces down with a
+//! retriable error instead of failing mid-append once the disk is full, while fetches,
+//! replication, deletes and retention, which frees space, go on.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics::Metrics;
+
+/// Time between two checks of the free space of the log directory.
+const DISK_USAGE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether the log directory is short of space, shared by the connections.
+#[derive(Debug, Clone, Default)]
+pub struct DiskUsage {
+    full: Arc<AtomicBool>,
+}
+
+impl DiskUsage {
+    /// Checks the free space of `log_dir_path` until the broker shuts down, it is full while
+    /// less than `min_free_percent` percent of its file system is free. 0 never fills it.
+    pub fn start(
+        log_dir_path: String,
+        min_free_percent: u8,
+        metrics: &Metrics,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        let disk_usage = DiskUsage::default();
+        let full = disk_usage.full.clone();
+        let free_bytes = metrics.register("log_dir_free_bytes");
+        tokio::spawn(async move {
+            let mut check_interval = tokio::time::interval(DISK_USAGE_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = check_interval.tick() => {}
+                    _ = cancellation_token.cancelled() => break,
+                }
+                check(
+                    Path::new(&log_dir_path),
+                    min_free_percent,
+                    &full,
+                    &free_bytes,
+                );
+            }
+        });
+        disk_usage
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.full.load(Ordering::Relaxed)
+    }
+}
+
+fn check(log_dir_path: &Path, min_free_percent: u8, full: &AtomicBool, free_bytes: &AtomicU64) {
+    let space = fs2::available_space(log_dir_path)
+        .and_then(|available| Ok((available, fs2::total_space(log_dir_path)?)));
+    let (available_bytes, total_bytes) = match space {
+        Ok(space) => space,
+        Err(e) => {
+            tracing::warn!(
+                "Could not read the free space of {}: {}",
+                log_dir_path.display(),
+                e
+            );
+            return;
+        }
+    };
+    free_bytes.store(available_bytes, Ordering::Relaxed);
+    let is_full = is_short_of_space(available_bytes, total_bytes, min_free_percent);
+    if full.swap(is_full, Ordering::Relaxed) != is_full {
+        if is_full {
+            tracing::error!(
+                "Only {} of {} bytes are free in {}, rejecting produces",
+                available_bytes,
+                total_bytes,
+                log_dir_path.display()
+            );
+        } else {
+            tracing::info!(
+                "{} bytes are free in {} again, accepting produces",
+                available_bytes,
+                log_dir_path.display()
+            );
+        }
+    }
+}
+
+fn is_short_of_space(available_bytes: u64, total_bytes: u64, min_free_percent: u8) -> bool {
+    (available_bytes as u128) * 100 < (total_bytes as u128) * min_free_percent as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_usage_should_be_full_below_the_free_space_threshold() {
+        assert!(!is_short_of_space(50, 1000, 5));
+        assert!(is_short_of_space(49, 1000, 5));
+        assert!(!is_short_of_space(0, 1000, 0));
+
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let full = AtomicBool::new(false);
+        let free_bytes = AtomicU64::new(0);
+        check(temp_dir.path(), 100, &full, &free_bytes);
+        assert!(full.load(Ordering::Relaxed));
+        check(temp_dir.path(), 0, &full, &free_bytes);
+        assert!(!full.load(Ordering::Relaxed));
+        assert!(free_bytes.load(Ordering::Relaxed) > 0);
+    }
+}
@@ -0,0 +1,267 @@
+//! Encryption at rest of the segment files. Brokers with encryption keys encrypt every batch they
+//! append with AES-256-GCM under their active key, and decrypt the batches of their segments with
+//! the key they were encrypted with. Segments may mix plaintext batches, written before the
+//! broker had keys, and batches encrypted under older keys, so keys are rotated by adding a new
+//! active key and keeping the old ones until no segment holds batches encrypted with them.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::{fmt, io};
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use bytes::{Bytes, BytesMut};
+use common::codecs::encoder::BatchEncoder;
+use common::models::RecordBatch;
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+/// First byte of the frames of encrypted batches, plaintext frames start with the format
+/// version of their batch.
+const ENCRYPTED_FRAME_MARKER: u8 = 0xE0;
+/// The marker and the key ID, authenticated along with the ciphertext.
+const ENVELOPE_HEADER_LEN: usize = 2;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Largest frame of a plaintext batch, the default of `LengthDelimitedCodec`.
+const MAX_BATCH_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+pub const KEY_LEN: usize = 32;
+
+/// Keys of the segments, set at startup by brokers with encryption keys.
+static SEGMENT_KEYS: OnceLock<SegmentKeys> = OnceLock::new();
+
+/// Keys of a broker encrypting its segments.
+#[derive(PartialEq, Clone)]
+pub struct EncryptionSettings {
+    /// `WALRS_ENCRYPTION_KEYS`, comma separated `<key id>=<base64 key>` pairs of 256 bit keys,
+    /// e.g. `1=<key>,2=<key>`
+    pub keys: BTreeMap<u8, Vec<u8>>,
+    /// `WALRS_ENCRYPTION_ACTIVE_KEY_ID`, the key new batches are encrypted with, the highest key
+    /// ID by default
+    pub active_key_id: u8,
+}
+
+impl fmt::Debug for EncryptionSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionSettings")
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .field("active_key_id", &self.active_key_id)
+            .finish()
+    }
+}
+
+pub struct SegmentKeys {
+    ciphers: BTreeMap<u8, Aes256Gcm>,
+    active_key_id: u8,
+}
+
+impl SegmentKeys {
+    pub fn new(settings: &EncryptionSettings) -> Result<Self, String> {
+        let mut ciphers = BTreeMap::new();
+        for (key_id, key) in &settings.keys {
+            let cipher = Aes256Gcm::new_from_slice(key)
+                .map_err(|_| format!("Encryption key {} is not {} bytes", key_id, KEY_LEN))?;
+            ciphers.insert(*key_id, cipher);
+        }
+        if !ciphers.contains_key(&settings.active_key_id) {
+            return Err(format!(
+                "No encryption key with the active key ID {}",
+                settings.active_key_id
+            ));
+        }
+        Ok(SegmentKeys {
+            ciphers,
+            active_key_id: settings.active_key_id,
+        })
+    }
+
+    /// `[marker, key ID, nonce, ciphertext and tag]` of an encoded batch.
+    fn encrypt(&self, encoded_batch: &[u8]) -> io::Result<Vec<u8>> {
+        let cipher = &self.ciphers[&self.active_key_id];
+        let header = [ENCRYPTED_FRAME_MARKER, self.active_key_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: encoded_batch,
+                    aad: &header,
+                },
+            )
+            .map_err(|_| io::Error::other("Could not encrypt the batch"))?;
+        let mut envelope = Vec::with_capacity(ENVELOPE_HEADER_LEN + NONCE_LEN + ciphertext.len());
+        envelope.extend_from_slice(&header);
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+        Ok(envelope)
+    }
+
+    /// The encoded batch of an envelope written by `encrypt`.
+    fn decrypt(&self, envelope: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid_data = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        if envelope.len() < ENVELOPE_HEADER_LEN + NONCE_LEN + TAG_LEN {
+            return Err(invalid_data("Encrypted batch is truncated".to_string()));
+        }
+        let (header, rest) = envelope.split_at(ENVELOPE_HEADER_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let key_id = header[1];
+        let cipher = self.ciphers.get(&key_id).ok_or_else(|| {
+            invalid_data(format!("No encryption key {} to decrypt the batch", key_id))
+        })?;
+        cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| invalid_data(format!("Could not decrypt the batch with key {}", key_id)))
+    }
+}
+
+/// Makes the partition writers encrypt the batches they append, and the segment readers decrypt
+/// them, with these keys.
+pub fn set_segment_keys(keys: SegmentKeys) {
+    if SEGMENT_KEYS.set(keys).is_err() {
+        tracing::warn!("Segment encryption keys were already set");
+    }
+}
+
+/// Frames batches as segments store them, encrypted when the broker has encryption keys.
+pub(crate) struct SegmentEncoder<'a> {
+    keys: Option<&'a SegmentKeys>,
+}
+
+impl SegmentEncoder<'static> {
+    pub(crate) fn new() -> Self {
+        SegmentEncoder {
+            keys: SEGMENT_KEYS.get(),
+        }
+    }
+}
+
+impl Encoder<RecordBatch> for SegmentEncoder<'_> {
+    type Error = io::Error;
+
+    fn encode(&mut self, batch: RecordBatch, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let Some(keys) = self.keys else {
+            return BatchEncoder {}.encode(batch, dst);
+        };
+        let mut frame = BytesMut::new();
+        BatchEncoder {}.encode(batch, &mut frame)?;
+        let envelope = keys.encrypt(&frame[4..])?;
+        segment_codec().encode(Bytes::from(envelope), dst)
+    }
+}
+
+/// Reads the batches of segments, decrypting those which were encrypted.
+pub(crate) struct SegmentDecoder<'a> {
+    keys: Option<&'a SegmentKeys>,
+}
+
+impl SegmentDecoder<'static> {
+    pub(crate) fn new() -> Self {
+        SegmentDecoder {
+            keys: SEGMENT_KEYS.get(),
+        }
+    }
+}
+
+impl Decoder for SegmentDecoder<'_> {
+    type Item = RecordBatch;
+
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(frame) = segment_codec().decode(src)? else {
+            return Ok(None);
+        };
+        if frame.first() != Some(&ENCRYPTED_FRAME_MARKER) {
+            return RecordBatch::decode(&frame).map(Some);
+        }
+        let encoded_batch = match self.keys {
+            Some(keys) => keys.decrypt(&frame),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Segment holds encrypted batches but the broker has no encryption keys",
+            )),
+        }
+        .inspect_err(|e| tracing::error!("Could not read an encrypted batch: {}", e))?;
+        RecordBatch::decode(&encoded_batch).map(Some)
+    }
+}
+
+/// Frames of segments, which hold plaintext batches of up to the default frame length and
+/// encrypted batches slightly larger.
+fn segment_codec() -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(MAX_BATCH_FRAME_LENGTH + ENVELOPE_HEADER_LEN + NONCE_LEN + TAG_LEN)
+        .new_codec()
+}
+
+#[cfg(test)]
+mod tests {
+    use common::models::{Batch, Message};
+
+    use super::*;
+
+    fn settings(keys: &[u8], active_key_id: u8) -> EncryptionSettings {
+        EncryptionSettings {
+            keys: keys
+                .iter()
+                .map(|key_id| (*key_id, vec![*key_id; KEY_LEN]))
+                .collect(),
+            active_key_id,
+        }
+    }
+
+    #[test]
+    fn test_segments_should_decrypt_batches_of_every_key_they_were_written_with() {
+        let batch = RecordBatch::new(Batch {
+            records: vec![Message::new(Bytes::from_static(b"card 4111"), None, None)],
+            ..Batch::default()
+        })
+        .unwrap();
+        let old_keys = SegmentKeys::new(&settings(&[1], 1)).unwrap();
+        let rotated_keys = SegmentKeys::new(&settings(&[1, 2], 2)).unwrap();
+
+        let mut segment = BytesMut::new();
+        let mut plaintext_encoder = SegmentEncoder { keys: None };
+        plaintext_encoder
+            .encode(batch.clone(), &mut segment)
+            .unwrap();
+        let mut old_encoder = SegmentEncoder {
+            keys: Some(&old_keys),
+        };
+        old_encoder.encode(batch.clone(), &mut segment).unwrap();
+        let mut rotated_encoder = SegmentEncoder {
+            keys: Some(&rotated_keys),
+        };
+        rotated_encoder.encode(batch.clone(), &mut segment).unwrap();
+        // only the batch written before the broker had keys is readable on disk
+        let plaintext_copies = segment
+            .windows(b"card 4111".len())
+            .filter(|window| *window == b"card 4111")
+            .count();
+        assert_eq!(plaintext_copies, 1);
+
+        let mut decoder = SegmentDecoder {
+            keys: Some(&rotated_keys),
+        };
+        let mut src = segment.clone();
+        for _ in 0..3 {
+            assert_eq!(decoder.decode(&mut src).unwrap(), Some(batch.clone()));
+        }
+        assert_eq!(decoder.decode(&mut src).unwrap(), None);
+
+        // batches of keys the broker dropped can't be read
+        let new_keys = SegmentKeys::new(&settings(&[2], 2)).unwrap();
+        let mut decoder = SegmentDecoder {
+            keys: Some(&new_keys),
+        };
+        let mut src = segment;
+        assert!(decoder.decode(&mut src).is_ok());
+        assert!(decoder.decode(&mut src).is_err());
+        assert!(SegmentKeys::new(&settings(&[1], 2)).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Faults chaos tests inject into the broker to check how it recovers, built with the
+//! `fault-injection` feature only. Faults are process-wide: every broker of the process, e.g. all
+//! embedded brokers of a test, suffers them until they are healed.
+
+use std::future::Future;
+use std::io;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+static FAULTS: Mutex<Vec<Fault>> = Mutex::new(Vec::new());
+/// Incremented every time the connections are severed.
+static SEVERED_CONNECTIONS: OnceLock<watch::Sender<u64>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Appends to segment files fail, the writes are not acknowledged.
+    FailSegmentWrites,
+    /// Segment files are synced this much later.
+    DelayFsyncs(Duration),
+    /// Followers' fetches from the leaders fail before they are sent, so the followers fall
+    /// behind and leave the ISR.
+    DropReplicaFetches,
+    /// Followers' fetches from the leaders are sent this much later.
+    DelayReplicaFetches(Duration),
+}
+
+/// Makes the brokers suffer `fault` until it is healed.
+pub fn inject(fault: Fault) {
+    let mut faults = FAULTS.lock().unwrap();
+    if !faults.contains(&fault) {
+        faults.push(fault);
+    }
+}
+
+/// Stops `fault`.
+pub fn heal(fault: Fault) {
+    FAULTS.lock().unwrap().retain(|injected| *injected != fault);
+}
+
+/// Stops every fault.
+pub fn heal_all() {
+    FAULTS.lock().unwrap().clear();
+}
+
+/// Closes the client and broker connections of the brokers without answering their requests in
+/// flight, the clients have to reconnect.
+pub fn sever_connections() {
+    severed_connections().send_modify(|generation| *generation += 1);
+}
+
+fn injected(fault: Fault) -> bool {
+    FAULTS.lock().unwrap().contains(&fault)
+}
+
+fn fsync_delay() -> Option<Duration> {
+    FAULTS.lock().unwrap().iter().find_map(|fault| match fault {
+        Fault::DelayFsyncs(delay) => Some(*delay),
+        _ => None,
+    })
+}
+
+fn replica_fetch_delay() -> Option<Duration> {
+    FAULTS.lock().unwrap().iter().find_map(|fault| match fault {
+        Fault::DelayReplicaFetches(delay) => Some(*delay),
+        _ => None,
+    })
+}
+
+fn severed_connections() -> &'static watch::Sender<u64> {
+    SEVERED_CONNECTIONS.get_or_init(|| watch::channel(0).0)
+}
+
+/// Fails with an error when segment writes fail.
+pub(crate) fn segment_write() -> io::Result<()> {
+    if injected(Fault::FailSegmentWrites) {
+        tracing::warn!("Failing the segment write");
+        return Err(io::Error::other("injected segment write failure"));
+    }
+    Ok(())
+}
+
+/// Waits before a segment file is synced.
+pub(crate) async fn fsync() {
+    if let Some(delay) = fsync_delay() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Waits before a replica fetch is sent, or fails with an error when it is dropped.
+pub(crate) async fn replica_fetch() -> io::Result<()> {
+    if let Some(delay) = replica_fetch_delay() {
+        tokio::time::sleep(delay).await;
+    }
+    if injected(Fault::DropReplicaFetches) {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "injected replica fetch drop",
+        ));
+    }
+    Ok(())
+}
+
+/// Completes once the connections are severed after this was called.
+pub(crate) fn connections_severed() -> impl Future<Output = ()> {
+    let mut severed_rx = severed_connections().subscribe();
+    async move {
+        let _ = severed_rx.changed().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults_should_last_until_healed() {
+        // a delayed fsync only slows down the shutdown of brokers of other tests
+        let delay = Duration::from_millis(10);
+        inject(Fault::DelayFsyncs(delay));
+        inject(Fault::DelayFsyncs(delay));
+        assert_eq!(fsync_delay(), Some(delay));
+        heal(Fault::DelayFsyncs(delay));
+        assert_eq!(fsync_delay(), None);
+    }
+}
@@ -0,0 +1,202 @@
+//! Fetch sessions of consumers polling many partitions from the broker, like Kafka's incremental
+//! fetch sessions. The broker keeps the partitions of every session and the offset it continues
+//! each one from, so consumers only send the partitions they add, move or forget.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use common::models::{BrokerResponse, SessionFetchRequest, TopicPartition};
+
+/// Partitions of a fetch session with the offsets they are fetched from.
+pub type SessionPartitions = Vec<(TopicPartition, Option<u64>)>;
+
+/// The broker's fetch sessions, shared by its connections so consumers keep their session when
+/// they reconnect.
+#[derive(Debug, Clone)]
+pub struct FetchSessions {
+    sessions: Arc<Mutex<SessionCache>>,
+}
+
+#[derive(Debug)]
+struct SessionCache {
+    sessions: HashMap<u32, FetchSession>,
+    next_session_id: u32,
+    max_sessions: usize,
+}
+
+#[derive(Debug)]
+struct FetchSession {
+    /// Epoch of the last fetch of the session.
+    epoch: u32,
+    /// Offsets the partitions are fetched from next, `None` until the first fetch of a partition
+    /// without an offset found where to start.
+    partitions: BTreeMap<TopicPartition, Option<u64>>,
+    last_used: Instant,
+}
+
+impl FetchSessions {
+    pub fn new(max_sessions: usize) -> Self {
+        FetchSessions {
+            sessions: Arc::new(Mutex::new(SessionCache {
+                sessions: HashMap::new(),
+                next_session_id: 1,
+                max_sessions,
+            })),
+        }
+    }
+
+    /// Applies the fetch to its session, or opens a new session with its partitions, and returns
+    /// the ID of the session and the offsets its partitions are fetched from.
+    pub fn update(
+        &self,
+        request: &SessionFetchRequest,
+    ) -> Result<(u32, SessionPartitions), BrokerResponse> {
+        let mut cache = self.sessions.lock().unwrap();
+        let session_id = match request.session_id {
+            Some(session_id) => session_id,
+            None => cache.open(),
+        };
+        let in_order = |session: &FetchSession| {
+            request.session_id.is_none() || request.session_epoch == session.epoch + 1
+        };
+        let session = match cache.sessions.get_mut(&session_id) {
+            Some(session) if in_order(session) => session,
+            // the consumer opens a new session, so this one is dropped right away
+            _ => {
+                cache.sessions.remove(&session_id);
+                return Err(BrokerResponse::InvalidFetchSession { session_id });
+            }
+        };
+        session.epoch = request.session_epoch;
+        session.last_used = Instant::now();
+        for topic_partition in &request.forgotten {
+            session.partitions.remove(topic_partition);
+        }
+        session
+            .partitions
+            .extend(request.partitions.iter().cloned());
+        let partitions = session
+            .partitions
+            .iter()
+            .map(|(topic_partition, offset)| (topic_partition.clone(), *offset))
+            .collect();
+        Ok((session_id, partitions))
+    }
+
+    /// Continues the partition of the session from `offset`, after the records fetched before it.
+    pub fn advance(&self, session_id: u32, topic_partition: &TopicPartition, offset: u64) {
+        let mut cache = self.sessions.lock().unwrap();
+        if let Some(session) = cache.sessions.get_mut(&session_id) {
+            if let Some(next_offset) = session.partitions.get_mut(topic_partition) {
+                *next_offset = Some(offset);
+            }
+        }
+    }
+
+    /// Removes the partition from the session, e.g. once the consumer fetches it from a read
+    /// replica.
+    pub fn forget(&self, session_id: u32, topic_partition: &TopicPartition) {
+        let mut cache = self.sessions.lock().unwrap();
+        if let Some(session) = cache.sessions.get_mut(&session_id) {
+            session.partitions.remove(topic_partition);
+        }
+    }
+}
+
+impl SessionCache {
+    /// Opens an empty session, evicting the least recently used session when the cache is full.
+    fn open(&mut self) -> u32 {
+        if self.sessions.len() >= self.max_sessions {
+            let least_recently_used = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(session_id, _)| *session_id);
+            if let Some(session_id) = least_recently_used {
+                tracing::info!("Evicting fetch session {}", session_id);
+                self.sessions.remove(&session_id);
+            }
+        }
+        let session_id = self.next_session_id;
+        self.next_session_id = self.next_session_id.wrapping_add(1).max(1);
+        self.sessions.insert(
+            session_id,
+            FetchSession {
+                epoch: 0,
+                partitions: BTreeMap::new(),
+                last_used: Instant::now(),
+            },
+        );
+        session_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::models::OffsetResetPolicy;
+
+    use super::*;
+
+    fn request(
+        session_id: Option<u32>,
+        session_epoch: u32,
+        partitions: Vec<(TopicPartition, Option<u64>)>,
+        forgotten: Vec<TopicPartition>,
+    ) -> SessionFetchRequest {
+        SessionFetchRequest {
+            session_id,
+            session_epoch,
+            partitions,
+            forgotten,
+            auto_offset_reset: OffsetResetPolicy::Earliest,
+            max_records: 10,
+            rack_id: None,
+        }
+    }
+
+    #[test]
+    fn test_fetch_sessions_should_keep_partitions_between_fetches() {
+        let fetch_sessions = FetchSessions::new(1);
+        let t0 = TopicPartition::new("t".to_string(), 0);
+        let t1 = TopicPartition::new("t".to_string(), 1);
+        let (session_id, partitions) = fetch_sessions
+            .update(&request(
+                None,
+                0,
+                vec![(t0.clone(), None), (t1.clone(), Some(4))],
+                vec![],
+            ))
+            .unwrap();
+        assert_eq!(partitions, vec![(t0.clone(), None), (t1.clone(), Some(4))]);
+        fetch_sessions.advance(session_id, &t0, 7);
+
+        // later fetches only name the partitions which changed
+        let (_, partitions) = fetch_sessions
+            .update(&request(Some(session_id), 1, vec![], vec![t1.clone()]))
+            .unwrap();
+        assert_eq!(partitions, vec![(t0.clone(), Some(7))]);
+
+        // a fetch whose answer got lost is noticed by its epoch
+        assert_eq!(
+            fetch_sessions.update(&request(Some(session_id), 3, vec![], vec![])),
+            Err(BrokerResponse::InvalidFetchSession { session_id })
+        );
+        // and the session is dropped for the new one the consumer opens
+        assert!(fetch_sessions
+            .update(&request(Some(session_id), 2, vec![], vec![]))
+            .is_err());
+
+        // a new session evicts the least recently used one from a full cache
+        let (old_session_id, _) = fetch_sessions
+            .update(&request(None, 0, vec![(t1.clone(), None)], vec![]))
+            .unwrap();
+        let (new_session_id, _) = fetch_sessions
+            .update(&request(None, 0, vec![(t1, None)], vec![]))
+            .unwrap();
+        assert_ne!(new_session_id, old_session_id);
+        assert!(fetch_sessions
+            .update(&request(Some(old_session_id), 1, vec![], vec![]))
+            .is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use common::codecs::encoder::BatchEncoder;
+use common::models::{Acks, Batch, BrokerResponse, FetchedBatch, Message, Topic, TopicPartition};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_util::codec::Encoder;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status};
+
+use crate::metrics::Metrics;
+use crate::ManagerChannels;
+
+pub mod proto {
+    tonic::include_proto!("walrs.v1");
+}
+
+use proto::walrs_server::{Walrs, WalrsServer};
+
+/// How long a streaming fetch which reached the end of the partition waits before it fetches
+/// again.
+const STREAM_FETCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Serves the `walrs.v1.Walrs` service until the broker shuts down.
+pub async fn start_grpc_server(
+    listen_address: String,
+    manager_channels: ManagerChannels,
+    metrics: Metrics,
+    cancellation_token: CancellationToken,
+) {
+    let listener = tokio::net::TcpListener::bind(&listen_address)
+        .await
+        .expect("Could not bind the gRPC server");
+    tracing::info!(
+        "gRPC server listening on: {}",
+        listener.local_addr().unwrap()
+    );
+    let service = WalrsService {
+        manager_channels,
+        metrics,
+    };
+    let server = tonic::transport::Server::builder()
+        .add_service(WalrsServer::new(service))
+        .serve_with_incoming_shutdown(
+            TcpListenerStream::new(listener),
+            cancellation_token.cancelled_owned(),
+        );
+    if let Err(e) = server.await {
+        tracing::error!("gRPC server failed: {:?}", e);
+    }
+}
+
+struct WalrsService {
+    manager_channels: ManagerChannels,
+    metrics: Metrics,
+}
+
+#[tonic::async_trait]
+impl Walrs for WalrsService {
+    async fn produce(
+        &self,
+        request: Request<proto::ProduceRequest>,
+    ) -> Result<Response<proto::ProduceResponse>, Status> {
+        let request = request.into_inner();
+        if request.records.is_empty() {
+            return Err(Status::invalid_argument("no records to produce"));
+        }
+        let topic_partition = topic_partition(request.topic, request.partition)?;
+        let mut body = BytesMut::new();
+        BatchEncoder {}
+            .encode(
+                Batch {
+                    records: request.records.into_iter().map(Message::from).collect(),
+                    ..Batch::default()
+                },
+                &mut body,
+            )
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let response = crate::handle_write_to_topic_request(
+            topic_partition,
+            Acks::Leader,
+            None,
+            &self.manager_channels,
+            Bytes::from(body),
+        )
+        .await
+        .await;
+        match response {
+            Some(BrokerResponse::MessageBatchAppended(produced)) => {
+                Ok(Response::new(proto::ProduceResponse {
+                    base_offset: produced.base_offset,
+                }))
+            }
+            Some(response) => Err(status(response)),
+            None => Err(Status::internal("the write was not answered")),
+        }
+    }
+
+    async fn fetch(
+        &self,
+        request: Request<proto::FetchRequest>,
+    ) -> Result<Response<proto::FetchResponse>, Status> {
+        let request = request.into_inner();
+        let topic_partition = topic_partition(request.topic, request.partition)?;
+        let (records, log_end_offset) = fetch_records(
+            topic_partition,
+            request.offset,
+            request.max_records,
+            self.manager_channels.clone(),
+        )
+        .await?;
+        Ok(Response::new(proto::FetchResponse {
+            records,
+            log_end_offset,
+        }))
+    }
+
+    type StreamFetchStream = ReceiverStream<Result<proto::Record, Status>>;
+
+    async fn stream_fetch(
+        &self,
+        request: Request<proto::FetchRequest>,
+    ) -> Result<Response<Self::StreamFetchStream>, Status> {
+        let request = request.into_inner();
+        let topic_partition = topic_partition(request.topic, request.partition)?;
+        let manager_channels = self.manager_channels.clone();
+        let (records_tx, records_rx) = mpsc::channel(crate::PROXY_DEFAULT_MAX_RECORDS as usize);
+        tokio::spawn(async move {
+            let mut offset = request.offset;
+            // the stream ends once the client is gone or the fetch failed
+            loop {
+                let fetched = fetch_records(
+                    topic_partition.clone(),
+                    offset,
+                    request.max_records,
+                    manager_channels.clone(),
+                )
+                .await;
+                let records = match fetched {
+                    Ok((records, _)) => records,
+                    Err(status) => {
+                        let _ = records_tx.send(Err(status)).await;
+                        return;
+                    }
+                };
+                if records.is_empty() {
+                    tokio::time::sleep(STREAM_FETCH_POLL_INTERVAL).await;
+                    if records_tx.is_closed() {
+                        return;
+                    }
+                    continue;
+                }
+                for record in records {
+                    offset = Some(record.offset + 1);
+                    if records_tx.send(Ok(record)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(records_rx)))
+    }
+
+    async fn create_topic(
+        &self,
+        request: Request<proto::CreateTopicRequest>,
+    ) -> Result<Response<proto::Topic>, Status> {
+        let request = request.into_inner();
+        let to_u8 = |value: Option<u32>, name: &str| {
+            value
+                .map(u8::try_from)
+                .transpose()
+                .map_err(|_| Status::invalid_argument(format!("{} is too large", name)))
+        };
+        let topic = Topic::new(
+            request.name,
+            to_u8(request.num_partitions, "num_partitions")?,
+            to_u8(request.replication_factor, "replication_factor")?,
+            None,
+            None,
+            None,
+        );
+        let ManagerChannels {
+            metadata_quorum_tx,
+            topic_manager_tx,
+            ..
+        } = self.manager_channels.clone();
+        match crate::handle_create_topic_request(topic, metadata_quorum_tx, topic_manager_tx).await
+        {
+            BrokerResponse::TopicDescription { topic } => Ok(Response::new(topic.into())),
+            response => Err(status(response)),
+        }
+    }
+
+    async fn describe_topic(
+        &self,
+        request: Request<proto::DescribeTopicRequest>,
+    ) -> Result<Response<proto::Topic>, Status> {
+        let topic_name = request.into_inner().name;
+        let topic_manager_tx = self.manager_channels.topic_manager_tx.clone();
+        match crate::handle_describe_topic_request(topic_name, topic_manager_tx).await {
+            BrokerResponse::TopicDescription { topic } => Ok(Response::new(topic.into())),
+            response => Err(status(response)),
+        }
+    }
+
+    async fn describe_metrics(
+        &self,
+        _request: Request<proto::DescribeMetricsRequest>,
+    ) -> Result<Response<proto::DescribeMetricsResponse>, Status> {
+        Ok(Response::new(proto::DescribeMetricsResponse {
+            metrics: self.metrics.snapshot().into_iter().collect(),
+        }))
+    }
+}
+
+/// Fetches the records of the partition from `offset` on, with the partition's log end offset.
+async fn fetch_records(
+    topic_partition: TopicPartition,
+    offset: Option<u64>,
+    max_records: Option<u32>,
+    manager_channels: ManagerChannels,
+) -> Result<(Vec<proto::Record>, u64), Status> {
+    let fetch_request = crate::proxy_fetch_request(topic_partition, offset, max_records);
+    let max_records = fetch_request.max_records as usize;
+    let response = crate::handle_fetch_request(
+        fetch_request,
+        manager_channels.topic_manager_tx,
+        manager_channels.group_coordinator_tx,
+    )
+    .await;
+    let BrokerResponse::Records {
+        base_offset,
+        batches,
+        log_end_offset,
+        ..
+    } = response
+    else {
+        return Err(status(response));
+    };
+    let messages = FetchedBatch::records(batches, base_offset, max_records)
+        .map_err(|e| Status::internal(e.to_string()))?;
+    let records = (base_offset..)
+        .zip(messages)
+        .map(|(offset, message)| proto::Record {
+            offset,
+            ..proto::Record::from(message)
+        })
+        .collect();
+    Ok((records, log_end_offset))
+}
+
+fn topic_partition(topic_name: String, partition: u32) -> Result<TopicPartition, Status> {
+    let partition_index = u8::try_from(partition)
+        .map_err(|_| Status::invalid_argument(format!("invalid partition {}", partition)))?;
+    Ok(TopicPartition::new(topic_name, partition_index))
+}
+
+/// The gRPC status of an error answer of the broker.
+fn status(response: BrokerResponse) -> Status {
+    match response {
+        BrokerResponse::UnknownTopicPartition { topic_partition } => {
+            Status::not_found(format!("unknown partition {:?}", topic_partition))
+        }
+        BrokerResponse::TopicNotFound { topic_name } => {
+            Status::not_found(format!("unknown topic {}", topic_name))
+        }
+        BrokerResponse::TopicNotCreated { topic_name } => {
+            Status::unavailable(format!("topic {} was not created", topic_name))
+        }
+        response @ BrokerResponse::OffsetOutOfRange { .. } => {
+            Status::out_of_range(format!("{:?}", response))
+        }
+        BrokerResponse::ProduceFailed { error } if error.is_retriable() => {
+            Status::unavailable(error.to_string())
+        }
+        BrokerResponse::ProduceFailed { error } => Status::invalid_argument(error.to_string()),
+        BrokerResponse::MessageBatchWriteFailure { error } => Status::invalid_argument(error),
+        response => Status::internal(format!("unexpected answer of the broker: {:?}", response)),
+    }
+}
+
+impl From<proto::Record> for Message {
+    fn from(record: proto::Record) -> Self {
+        let mut message = Message::new(
+            Bytes::new(),
+            record.key.map(Bytes::from),
+            record.timestamp.map(u128::from),
+        );
+        message.payload = record.value.map(Bytes::from);
+        message.headers = record
+            .headers
+            .into_iter()
+            .map(|header| (header.name, header.value.into()))
+            .collect();
+        message
+    }
+}
+
+impl From<Message> for proto::Record {
+    fn from(message: Message) -> Self {
+        proto::Record {
+            offset: 0,
+            key: message.key.map(|key| key.to_vec()),
+            value: message.payload.map(|payload| payload.to_vec()),
+            timestamp: message.timestamp.map(|timestamp| timestamp as u64),
+            headers: message
+                .headers
+                .into_iter()
+                .map(|(name, value)| proto::Header {
+                    name,
+                    value: value.to_vec(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Topic> for proto::Topic {
+    fn from(topic: Topic) -> Self {
+        proto::Topic {
+            name: topic.name,
+            num_partitions: topic.num_partitions.unwrap_or_default().into(),
+            replication_factor: topic.replication_factor.unwrap_or_default().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_should_convert_to_messages_and_back() {
+        let record = proto::Record {
+            offset: 0,
+            key: Some(b"k1".to_vec()),
+            value: Some(b"v1".to_vec()),
+            timestamp: Some(1_700_000_000_000),
+            headers: vec![proto::Header {
+                name: "trace".to_string(),
+                value: b"abc".to_vec(),
+            }],
+        };
+        let message = Message::from(record.clone());
+        assert_eq!(message.payload, Some(Bytes::from_static(b"v1")));
+        assert_eq!(
+            message.headers,
+            vec![("trace".to_string(), Bytes::from_static(b"abc"))]
+        );
+        assert_eq!(proto::Record::from(message), record);
+
+        assert!(Message::from(proto::Record::default()).timestamp.is_some());
+        assert!(topic_partition("t1".to_string(), 256).is_err());
+    }
+}
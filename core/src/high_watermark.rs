@@ -0,0 +1,140 @@
+//! High watermarks of the partitions, like Kafka's: the offset up to which every in-sync replica
+//! copied a partition. Consumers only read the records before it, so they never read records a
+//! new leader may not have.
+
+use std::fs;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use common::models::FetchedBatch;
+
+const HIGH_WATERMARK_FILE_NAME: &str = "high_watermark";
+
+/// High watermark of a partition, shared with the replica fetcher while this broker follows the
+/// partition. It is checkpointed next to the partition's segments and restored on startup, so a
+/// restarted broker neither serves records which were not committed nor falls back to the start
+/// of the log until its followers fetched again.
+#[derive(Debug)]
+pub struct HighWatermark {
+    offset: Arc<AtomicU64>,
+    checkpointed_offset: u64,
+    partition_path: String,
+}
+
+impl HighWatermark {
+    /// Restores the high watermark checkpointed in `partition_path`, new partitions start at 0.
+    pub fn load(partition_path: &str) -> Self {
+        let checkpointed_offset =
+            fs::read(format!("{}/{}", partition_path, HIGH_WATERMARK_FILE_NAME))
+                .ok()
+                .and_then(|offset| bincode::deserialize::<u64>(&offset).ok())
+                .unwrap_or_default();
+        HighWatermark {
+            offset: Arc::new(AtomicU64::new(checkpointed_offset)),
+            checkpointed_offset,
+            partition_path: partition_path.to_string(),
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    /// The shared offset, followers set it to the leader's high watermark.
+    pub fn shared_offset(&self) -> Arc<AtomicU64> {
+        self.offset.clone()
+    }
+
+    /// Moves the high watermark of a partition this broker leads up to `offset`, it never moves
+    /// back.
+    pub fn advance(&self, offset: u64) {
+        self.offset.fetch_max(offset, Ordering::SeqCst);
+    }
+
+    /// Writes the high watermark to a temporary file renamed over the checkpoint once it moved
+    /// since the last checkpoint.
+    pub fn checkpoint(&mut self) {
+        let offset = self.offset();
+        if offset == self.checkpointed_offset {
+            return;
+        }
+        match self.write_checkpoint(offset) {
+            Ok(()) => self.checkpointed_offset = offset,
+            Err(e) => tracing::error!(
+                "Could not checkpoint high watermark in {}: {:?}",
+                self.partition_path,
+                e
+            ),
+        }
+    }
+
+    /// The file and the rename are synced, so a crash leaves either the old or the new
+    /// checkpoint behind.
+    fn write_checkpoint(&self, offset: u64) -> io::Result<()> {
+        let checkpoint_path = format!("{}/{}", self.partition_path, HIGH_WATERMARK_FILE_NAME);
+        let temporary_path = format!("{}.tmp", checkpoint_path);
+        fs::create_dir_all(&self.partition_path)?;
+        let offset = bincode::serialize(&offset).map_err(io::Error::other)?;
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(&offset)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, &checkpoint_path)?;
+        fs::File::open(&self.partition_path)?.sync_all()
+    }
+}
+
+/// Fetched batches cut before the first batch holding records at or after `high_watermark`.
+/// Followers copy batches whole, so the high watermark falls between batches.
+pub fn committed_batches(batches: Vec<FetchedBatch>, high_watermark: u64) -> Vec<FetchedBatch> {
+    batches
+        .into_iter()
+        .take_while(|fetched_batch| {
+            fetched_batch.base_offset + fetched_batch.batch.record_count as u64 <= high_watermark
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use common::models::{Batch, Message, RecordBatch};
+
+    use super::*;
+
+    #[test]
+    fn test_high_watermark_should_be_restored_from_its_checkpoint() {
+        let temp_dir = tempdir::TempDir::new("partition_").unwrap();
+        let partition_path = temp_dir.path().join("t1/0");
+        let partition_path = partition_path.to_str().unwrap();
+
+        let mut high_watermark = HighWatermark::load(partition_path);
+        assert_eq!(high_watermark.offset(), 0);
+        high_watermark.advance(5);
+        high_watermark.advance(3);
+        assert_eq!(high_watermark.offset(), 5);
+        // not checkpointed yet
+        assert_eq!(HighWatermark::load(partition_path).offset(), 0);
+        high_watermark.checkpoint();
+        assert_eq!(HighWatermark::load(partition_path).offset(), 5);
+
+        let fetched_batch = |base_offset, payloads: &[&'static str]| FetchedBatch {
+            base_offset,
+            batch: RecordBatch::new(Batch {
+                records: payloads
+                    .iter()
+                    .map(|payload| Message::new(Bytes::from_static(payload.as_bytes()), None, None))
+                    .collect(),
+                ..Batch::default()
+            })
+            .unwrap(),
+        };
+        let batches = vec![fetched_batch(3, &["d", "e"]), fetched_batch(5, &["f", "g"])];
+        assert_eq!(committed_batches(batches.clone(), 4), vec![]);
+        assert_eq!(
+            committed_batches(batches.clone(), high_watermark.offset()),
+            vec![batches[0].clone()]
+        );
+        assert_eq!(committed_batches(batches.clone(), 7), batches);
+    }
+}
@@ -0,0 +1,325 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::{Bytes, BytesMut};
+use common::codecs::encoder::BatchEncoder;
+use common::models::{Acks, Batch, BrokerResponse, FetchedBatch, Message, TopicPartition};
+use serde::{Deserialize, Serialize};
+use tokio_util::codec::Encoder;
+use tokio_util::sync::CancellationToken;
+
+use crate::ManagerChannels;
+
+/// How record values are written in the JSON of the HTTP proxy.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum ValueFormat {
+    /// The value is the JSON document stored as the record's payload.
+    #[default]
+    Json,
+    /// The value is a base64 string of the payload, for payloads which are not JSON.
+    Base64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProduceRequest {
+    partition: u8,
+    #[serde(default)]
+    format: ValueFormat,
+    records: Vec<ProduceRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProduceRecord {
+    key: Option<String>,
+    value: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct ProduceResponse {
+    topic: String,
+    partition: u8,
+    base_offset: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsumeQuery {
+    /// Offset of the first record, the partition's first stored record without one.
+    offset: Option<u64>,
+    max_records: Option<u32>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Debug, Serialize)]
+struct ConsumeResponse {
+    records: Vec<ConsumedRecord>,
+    log_end_offset: u64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct ConsumedRecord {
+    offset: u64,
+    key: Option<String>,
+    value: serde_json::Value,
+    timestamp: Option<u128>,
+}
+
+/// An error answer of the proxy, its message is sent as the body.
+struct ProxyError(StatusCode, String);
+
+impl IntoResponse for ProxyError {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+impl ProduceRecord {
+    fn into_message(self, format: ValueFormat) -> Result<Message, String> {
+        let payload = match (format, self.value) {
+            // a null value makes the record a tombstone of its key
+            (_, serde_json::Value::Null) => None,
+            (ValueFormat::Json, value) => Some(
+                serde_json::to_vec(&value)
+                    .map_err(|e| e.to_string())?
+                    .into(),
+            ),
+            (ValueFormat::Base64, serde_json::Value::String(value)) => Some(
+                BASE64
+                    .decode(value)
+                    .map_err(|e| format!("invalid base64 value: {}", e))?
+                    .into(),
+            ),
+            (ValueFormat::Base64, _) => return Err("base64 values must be strings".to_string()),
+        };
+        let mut message = Message::new(Bytes::new(), self.key.map(Bytes::from), None);
+        message.payload = payload;
+        Ok(message)
+    }
+}
+
+impl ConsumedRecord {
+    fn new(offset: u64, message: Message, format: ValueFormat) -> Result<Self, String> {
+        let value = match (format, &message.payload) {
+            (_, None) => serde_json::Value::Null,
+            (ValueFormat::Json, Some(payload)) => {
+                serde_json::from_slice(payload).map_err(|_| {
+                    format!(
+                        "record at offset {} is not JSON, consume it with format=base64",
+                        offset
+                    )
+                })?
+            }
+            (ValueFormat::Base64, Some(payload)) => {
+                serde_json::Value::String(BASE64.encode(payload))
+            }
+        };
+        Ok(ConsumedRecord {
+            offset,
+            key: message
+                .key
+                .map(|key| String::from_utf8_lossy(&key).into_owned()),
+            value,
+            timestamp: message.timestamp,
+        })
+    }
+}
+
+/// Serves produce and consume requests over HTTP until the broker shuts down, for clients
+/// without a native client such as curl. Requests are handled by this broker, so they have to be
+/// sent to the leader of the partition. The proxy neither authenticates nor throttles clients.
+pub async fn start_http_proxy(
+    listen_address: String,
+    manager_channels: ManagerChannels,
+    cancellation_token: CancellationToken,
+) {
+    let listener = tokio::net::TcpListener::bind(&listen_address)
+        .await
+        .expect("Could not bind the HTTP proxy");
+    tracing::info!(
+        "HTTP proxy listening on: {}",
+        listener.local_addr().unwrap()
+    );
+    let server = axum::serve(listener, router(manager_channels))
+        .with_graceful_shutdown(cancellation_token.cancelled_owned());
+    if let Err(e) = server.await {
+        tracing::error!("HTTP proxy failed: {:?}", e);
+    }
+}
+
+fn router(manager_channels: ManagerChannels) -> Router {
+    Router::new()
+        .route("/topics/{topic_name}", post(produce))
+        .route(
+            "/topics/{topic_name}/partitions/{partition_index}/records",
+            get(consume),
+        )
+        .with_state(manager_channels)
+}
+
+/// `POST /topics/{topic_name}` with `{"partition": 0, "records": [{"key": "k", "value": {}}]}`,
+/// appends the records as one batch.
+async fn produce(
+    State(manager_channels): State<ManagerChannels>,
+    Path(topic_name): Path<String>,
+    Json(request): Json<ProduceRequest>,
+) -> Result<Json<ProduceResponse>, ProxyError> {
+    let records = request
+        .records
+        .into_iter()
+        .map(|record| record.into_message(request.format))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ProxyError(StatusCode::BAD_REQUEST, e))?;
+    if records.is_empty() {
+        return Err(ProxyError(
+            StatusCode::BAD_REQUEST,
+            "no records to produce".to_string(),
+        ));
+    }
+    let mut body = BytesMut::new();
+    BatchEncoder {}
+        .encode(
+            Batch {
+                records,
+                ..Batch::default()
+            },
+            &mut body,
+        )
+        .map_err(|e| ProxyError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let topic_partition = TopicPartition::new(topic_name, request.partition);
+    let response = crate::handle_write_to_topic_request(
+        topic_partition,
+        Acks::Leader,
+        None,
+        &manager_channels,
+        Bytes::from(body),
+    )
+    .await
+    .await;
+    match response {
+        Some(BrokerResponse::MessageBatchAppended(produced)) => Ok(Json(ProduceResponse {
+            topic: produced.topic_partition.topic_name,
+            partition: produced.topic_partition.partition_index,
+            base_offset: produced.base_offset,
+        })),
+        Some(BrokerResponse::ProduceFailed { error }) if error.is_retriable() => Err(ProxyError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            error.to_string(),
+        )),
+        Some(BrokerResponse::ProduceFailed { error }) => {
+            Err(ProxyError(StatusCode::BAD_REQUEST, error.to_string()))
+        }
+        response => Err(unexpected_response(response)),
+    }
+}
+
+/// `GET /topics/{topic_name}/partitions/{partition_index}/records?offset=0&max_records=10`,
+/// returns the records from the offset on.
+async fn consume(
+    State(manager_channels): State<ManagerChannels>,
+    Path((topic_name, partition_index)): Path<(String, u8)>,
+    Query(query): Query<ConsumeQuery>,
+) -> Result<Json<ConsumeResponse>, ProxyError> {
+    let fetch_request = crate::proxy_fetch_request(
+        TopicPartition::new(topic_name, partition_index),
+        query.offset,
+        query.max_records,
+    );
+    let max_records = fetch_request.max_records as usize;
+    let response = crate::handle_fetch_request(
+        fetch_request,
+        manager_channels.topic_manager_tx,
+        manager_channels.group_coordinator_tx,
+    )
+    .await;
+    match response {
+        BrokerResponse::Records {
+            base_offset,
+            batches,
+            log_end_offset,
+            ..
+        } => {
+            let messages = FetchedBatch::records(batches, base_offset, max_records)
+                .map_err(|e| ProxyError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let records = (base_offset..)
+                .zip(messages)
+                .map(|(offset, message)| ConsumedRecord::new(offset, message, query.format))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ProxyError(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+            Ok(Json(ConsumeResponse {
+                records,
+                log_end_offset,
+            }))
+        }
+        BrokerResponse::UnknownTopicPartition { topic_partition } => Err(ProxyError(
+            StatusCode::NOT_FOUND,
+            format!("unknown partition {:?}", topic_partition),
+        )),
+        response @ BrokerResponse::OffsetOutOfRange { .. } => Err(ProxyError(
+            StatusCode::BAD_REQUEST,
+            format!("{:?}", response),
+        )),
+        response => Err(unexpected_response(Some(response))),
+    }
+}
+
+fn unexpected_response(response: Option<BrokerResponse>) -> ProxyError {
+    ProxyError(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("unexpected answer of the broker: {:?}", response),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_record_values_should_round_trip_in_both_formats() {
+        let record = |value| ProduceRecord {
+            key: Some("k1".to_string()),
+            value,
+        };
+        let document = json!({"temperature": 21.5});
+        let message = record(document.clone())
+            .into_message(ValueFormat::Json)
+            .unwrap();
+        assert_eq!(message.key, Some(Bytes::from_static(b"k1")));
+        assert_eq!(
+            ConsumedRecord::new(3, message.clone(), ValueFormat::Json)
+                .unwrap()
+                .value,
+            document
+        );
+        assert_eq!(
+            ConsumedRecord::new(3, message.clone(), ValueFormat::Base64)
+                .unwrap()
+                .value,
+            json!(BASE64.encode(message.payload.as_ref().unwrap()))
+        );
+
+        let tombstone = record(json!(null))
+            .into_message(ValueFormat::Base64)
+            .unwrap();
+        assert!(tombstone.is_tombstone());
+        assert_eq!(
+            ConsumedRecord::new(5, tombstone, ValueFormat::Json)
+                .unwrap()
+                .value,
+            json!(null)
+        );
+
+        let binary = record(json!("AP8="))
+            .into_message(ValueFormat::Base64)
+            .unwrap();
+        assert_eq!(binary.payload, Some(Bytes::from_static(&[0, 255])));
+        assert!(ConsumedRecord::new(4, binary, ValueFormat::Json).is_err());
+        assert!(record(json!(1)).into_message(ValueFormat::Base64).is_err());
+    }
+}
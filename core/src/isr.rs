@@ -0,0 +1,250 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::cluster::BrokerId;
+
+const ISR_FILE_NAME: &str = "isr";
+
+/// What the leader knows about a follower from the follower's fetches.
+#[derive(Debug, Clone, Copy)]
+struct FollowerState {
+    /// Last time the follower had fetched every record the leader had.
+    last_caught_up: Instant,
+    last_fetch: Instant,
+    /// Offset the follower's last fetch started at, every record before it was copied.
+    last_fetch_offset: u64,
+    /// Leader's log end offset at the follower's last fetch.
+    last_fetch_leader_log_end_offset: u64,
+}
+
+/// In-sync replicas of a partition this broker leads, like Kafka's ISR. A follower is in sync
+/// while it caught up with the leader's log within `replica_lag_time_max`. Followers are dropped
+/// from the ISR when they fall further behind and re-added once they caught up again. The ISR is
+/// stored next to the partition's segments, so a restarted leader does not count followers as
+/// in sync which were not in sync before.
+#[derive(Debug)]
+pub struct PartitionIsr {
+    followers: HashMap<BrokerId, FollowerState>,
+    isr: BTreeSet<BrokerId>,
+    partition_path: String,
+}
+
+impl PartitionIsr {
+    /// Restores the ISR stored in `partition_path`, new partitions start with every replica in
+    /// sync. Followers get `replica_lag_time_max` from now to catch up.
+    pub fn load(
+        leader_id: BrokerId,
+        follower_ids: &[BrokerId],
+        partition_path: &str,
+        now: Instant,
+    ) -> Self {
+        let stored_isr = fs::read(format!("{}/{}", partition_path, ISR_FILE_NAME))
+            .ok()
+            .and_then(|isr| bincode::deserialize::<BTreeSet<BrokerId>>(&isr).ok());
+        let isr: Vec<BrokerId> = match stored_isr {
+            Some(isr) => isr.into_iter().collect(),
+            None => follower_ids.to_vec(),
+        };
+        PartitionIsr::new(leader_id, follower_ids, &isr, partition_path, now)
+    }
+
+    /// ISR of a partition this broker was elected to lead, `isr` is the ISR the controller
+    /// elected the leader from.
+    pub fn new(
+        leader_id: BrokerId,
+        follower_ids: &[BrokerId],
+        isr: &[BrokerId],
+        partition_path: &str,
+        now: Instant,
+    ) -> Self {
+        let followers: HashMap<BrokerId, FollowerState> = follower_ids
+            .iter()
+            .map(|follower_id| {
+                let state = FollowerState {
+                    last_caught_up: now,
+                    last_fetch: now,
+                    last_fetch_offset: 0,
+                    last_fetch_leader_log_end_offset: 0,
+                };
+                (*follower_id, state)
+            })
+            .collect();
+        let isr = isr
+            .iter()
+            .copied()
+            .filter(|replica_id| followers.contains_key(replica_id))
+            .chain([leader_id])
+            .collect();
+        let partition_isr = PartitionIsr {
+            followers,
+            isr,
+            partition_path: partition_path.to_string(),
+        };
+        partition_isr.persist();
+        partition_isr
+    }
+
+    /// Updates the follower's state from a fetch starting at `fetch_offset`, returns whether the
+    /// follower joined the ISR. A follower has caught up when it fetches from the leader's log
+    /// end offset, or from the log end offset the leader had at the follower's previous fetch.
+    pub fn record_fetch(
+        &mut self,
+        replica_id: BrokerId,
+        fetch_offset: u64,
+        leader_log_end_offset: u64,
+        now: Instant,
+    ) -> bool {
+        let Some(follower) = self.followers.get_mut(&replica_id) else {
+            tracing::warn!("Fetch from broker {} which is not a replica", replica_id);
+            return false;
+        };
+        if fetch_offset >= leader_log_end_offset {
+            follower.last_caught_up = follower.last_caught_up.max(now);
+        } else if fetch_offset >= follower.last_fetch_leader_log_end_offset {
+            follower.last_caught_up = follower.last_caught_up.max(follower.last_fetch);
+        }
+        follower.last_fetch = now;
+        follower.last_fetch_offset = fetch_offset;
+        follower.last_fetch_leader_log_end_offset = leader_log_end_offset;
+        if fetch_offset >= leader_log_end_offset && self.isr.insert(replica_id) {
+            self.persist();
+            return true;
+        }
+        false
+    }
+
+    /// Drops followers which did not catch up within `replica_lag_time_max` from the ISR,
+    /// returns whether the ISR shrank.
+    pub fn shrink(&mut self, replica_lag_time_max: Duration, now: Instant) -> bool {
+        let lagging: Vec<BrokerId> = self
+            .followers
+            .iter()
+            .filter(|(follower_id, follower)| {
+                self.isr.contains(follower_id)
+                    && now.duration_since(follower.last_caught_up) > replica_lag_time_max
+            })
+            .map(|(follower_id, _)| *follower_id)
+            .collect();
+        for follower_id in &lagging {
+            self.isr.remove(follower_id);
+        }
+        if !lagging.is_empty() {
+            self.persist();
+        }
+        !lagging.is_empty()
+    }
+
+    /// Drops the followers which are not in `isr`, e.g. when the controller removed dead brokers
+    /// from the ISR, returns whether the ISR shrank. They rejoin once they caught up again.
+    pub fn retain(&mut self, isr: &[BrokerId]) -> bool {
+        let followers_out_of_sync: Vec<BrokerId> = self
+            .followers
+            .keys()
+            .copied()
+            .filter(|follower_id| self.isr.contains(follower_id) && !isr.contains(follower_id))
+            .collect();
+        for follower_id in &followers_out_of_sync {
+            self.isr.remove(follower_id);
+        }
+        if !followers_out_of_sync.is_empty() {
+            self.persist();
+        }
+        !followers_out_of_sync.is_empty()
+    }
+
+    /// Whether every follower in the ISR copied the records up to `log_end_offset`, so any of
+    /// them can take over the leadership without losing records.
+    pub fn followers_caught_up(&self, log_end_offset: u64) -> bool {
+        self.followers.iter().all(|(follower_id, follower)| {
+            !self.isr.contains(follower_id) || follower.last_fetch_offset >= log_end_offset
+        })
+    }
+
+    /// Offset every replica in the ISR copied the records up to, at most `log_end_offset`.
+    pub fn high_watermark(&self, log_end_offset: u64) -> u64 {
+        self.followers
+            .iter()
+            .filter(|(follower_id, _)| self.isr.contains(follower_id))
+            .map(|(_, follower)| follower.last_fetch_offset)
+            .fold(log_end_offset, u64::min)
+    }
+
+    pub fn isr(&self) -> Vec<BrokerId> {
+        self.isr.iter().copied().collect()
+    }
+
+    /// Writes the ISR to the partition's directory, which the partition writer may not have
+    /// created yet.
+    fn persist(&self) {
+        let result = fs::create_dir_all(&self.partition_path)
+            .and_then(|_| bincode::serialize(&self.isr).map_err(std::io::Error::other))
+            .and_then(|isr| fs::write(format!("{}/{}", self.partition_path, ISR_FILE_NAME), isr));
+        if let Err(e) = result {
+            tracing::error!("Could not store ISR in {}: {:?}", self.partition_path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAG_TIME_MAX: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn test_isr_should_shrink_and_expand_with_follower_lag() {
+        let temp_dir = tempdir::TempDir::new("partition_").unwrap();
+        let partition_path = temp_dir.path().to_str().unwrap();
+        let start = Instant::now();
+        let mut partition_isr = PartitionIsr::load(0, &[1, 2], partition_path, start);
+        assert_eq!(partition_isr.isr(), vec![0, 1, 2]);
+
+        // broker 1 keeps up, broker 2 keeps fetching from an old offset
+        let later = start + Duration::from_secs(8);
+        assert!(!partition_isr.record_fetch(1, 100, 100, later));
+        assert!(!partition_isr.record_fetch(2, 10, 100, later));
+        assert!(!partition_isr.shrink(LAG_TIME_MAX, later));
+
+        let lagging = start + Duration::from_secs(11);
+        assert!(!partition_isr.record_fetch(2, 20, 120, lagging));
+        assert!(partition_isr.shrink(LAG_TIME_MAX, lagging));
+        assert_eq!(partition_isr.isr(), vec![0, 1]);
+
+        // a restarted leader does not put broker 2 back into the ISR
+        let mut partition_isr = PartitionIsr::load(0, &[1, 2], partition_path, lagging);
+        assert_eq!(partition_isr.isr(), vec![0, 1]);
+
+        assert!(partition_isr.record_fetch(2, 120, 120, lagging));
+        assert_eq!(partition_isr.isr(), vec![0, 1, 2]);
+        // broker 1 fetched from offset 20 last
+        assert!(!partition_isr.followers_caught_up(120));
+        assert!(partition_isr.high_watermark(120) < 120);
+        partition_isr.record_fetch(1, 120, 120, lagging);
+        assert!(partition_isr.followers_caught_up(120));
+        // the leader appended records its followers did not copy yet
+        assert_eq!(partition_isr.high_watermark(130), 120);
+
+        // the controller removed broker 1 after it died
+        assert!(partition_isr.retain(&[0, 2]));
+        assert!(!partition_isr.retain(&[0, 1, 2]));
+        assert_eq!(partition_isr.isr(), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_isr_should_keep_followers_fetching_the_previous_log_end_offset() {
+        let temp_dir = tempdir::TempDir::new("partition_").unwrap();
+        let start = Instant::now();
+        let mut partition_isr =
+            PartitionIsr::load(0, &[1], temp_dir.path().to_str().unwrap(), start);
+
+        // the leader appends records between every fetch, the follower never fetches the
+        // current log end offset but always the one of its previous fetch
+        for second in 1..30 {
+            let now = start + Duration::from_secs(second);
+            partition_isr.record_fetch(1, (second - 1) * 10, second * 10, now);
+            assert!(!partition_isr.shrink(LAG_TIME_MAX, now));
+        }
+        assert_eq!(partition_isr.isr(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,244 @@
+use std::fs;
+
+use common::models::EpochEndOffset;
+use serde::{Deserialize, Serialize};
+
+use crate::cluster::BrokerId;
+
+const LEADER_EPOCH_FILE_NAME: &str = "leader_epoch";
+const LEADER_EPOCH_CHECKPOINT_FILE_NAME: &str = "leader_epoch_checkpoint";
+
+/// Leader of a partition and the epoch of its leadership. The epoch grows with every change of
+/// the partition's leader, so requests carrying another epoch come from clients or followers which
+/// missed a change, or reach an old leader which missed it. Either way they are rejected instead
+/// of writing to or copying from the wrong log.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct PartitionLeader {
+    pub leader_id: BrokerId,
+    pub leader_epoch: u32,
+}
+
+impl PartitionLeader {
+    /// Restores the partition's leader stored in `partition_path`, which may have been elected
+    /// after the partition was created, even on a broker which is not one of `replicas` anymore
+    /// since a reassignment. New partitions are led by their first replica in epoch 0.
+    pub fn load(replicas: &[BrokerId], partition_path: &str) -> Self {
+        let stored_leader = fs::read(format!("{}/{}", partition_path, LEADER_EPOCH_FILE_NAME))
+            .ok()
+            .and_then(|leader| bincode::deserialize::<PartitionLeader>(&leader).ok());
+        if let Some(stored_leader) = stored_leader {
+            return stored_leader;
+        }
+        let partition_leader = PartitionLeader {
+            leader_id: replicas[0],
+            leader_epoch: 0,
+        };
+        partition_leader.store(partition_path);
+        partition_leader
+    }
+
+    pub fn store(&self, partition_path: &str) {
+        tracing::info!(
+            "Broker {} leads {} in epoch {}",
+            self.leader_id,
+            partition_path,
+            self.leader_epoch
+        );
+        let result = fs::create_dir_all(partition_path)
+            .and_then(|_| bincode::serialize(self).map_err(std::io::Error::other))
+            .and_then(|leader| {
+                fs::write(
+                    format!("{}/{}", partition_path, LEADER_EPOCH_FILE_NAME),
+                    leader,
+                )
+            });
+        if let Err(e) = result {
+            tracing::error!(
+                "Could not store leader epoch in {}: {:?}",
+                partition_path,
+                e
+            );
+        }
+    }
+
+    /// Whether a request with `leader_epoch` must be rejected, requests without an epoch are not
+    /// checked.
+    pub fn is_fenced(&self, leader_epoch: Option<u32>) -> bool {
+        leader_epoch.is_some_and(|leader_epoch| leader_epoch != self.leader_epoch)
+    }
+}
+
+/// Offset of the first record of every leader epoch of a partition's log, like Kafka's leader
+/// epoch cache. Followers find where their log diverges from a new leader's with it. Followers
+/// start an epoch at their log end offset once they truncated to the new leader's log. Every
+/// change is checkpointed next to the partition's segments, like Kafka's leader-epoch-checkpoint,
+/// so a restarted broker still finds where its log diverges after several leader changes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LeaderEpochCache {
+    /// Epochs and their start offsets, both increasing.
+    entries: Vec<(u32, u64)>,
+    partition_path: String,
+}
+
+impl LeaderEpochCache {
+    /// Restores the epochs checkpointed in `partition_path`. Logs without a checkpoint, e.g. of
+    /// new partitions, are taken to hold only records of `leader_epoch`.
+    pub fn load(leader_epoch: u32, partition_path: &str) -> Self {
+        let stored_entries = fs::read(format!(
+            "{}/{}",
+            partition_path, LEADER_EPOCH_CHECKPOINT_FILE_NAME
+        ))
+        .ok()
+        .and_then(|entries| bincode::deserialize::<Vec<(u32, u64)>>(&entries).ok());
+        let cache = LeaderEpochCache {
+            entries: stored_entries
+                .clone()
+                .unwrap_or_else(|| vec![(leader_epoch, 0)]),
+            partition_path: partition_path.to_string(),
+        };
+        if stored_entries.is_none() {
+            cache.checkpoint();
+        }
+        cache
+    }
+
+    pub fn latest_epoch(&self) -> Option<u32> {
+        self.entries.last().map(|(leader_epoch, _)| *leader_epoch)
+    }
+
+    /// Starts `leader_epoch` at `start_offset`, epochs older than the latest one are ignored.
+    /// Epochs starting at or after `start_offset` hold no records, so they are replaced.
+    pub fn assign(&mut self, leader_epoch: u32, start_offset: u64) {
+        if self
+            .latest_epoch()
+            .is_some_and(|latest_epoch| latest_epoch >= leader_epoch)
+        {
+            return;
+        }
+        self.entries
+            .retain(|(_, epoch_start_offset)| *epoch_start_offset < start_offset);
+        self.entries.push((leader_epoch, start_offset));
+        self.checkpoint();
+    }
+
+    /// Largest epoch at or before `leader_epoch` and the offset it ends at, which is
+    /// `log_end_offset` for the latest epoch. `None` when the log has no such epoch.
+    pub fn end_offset_for(&self, leader_epoch: u32, log_end_offset: u64) -> Option<EpochEndOffset> {
+        let index = self
+            .entries
+            .partition_point(|(epoch, _)| *epoch <= leader_epoch);
+        let (found_epoch, _) = *self.entries.get(index.checked_sub(1)?)?;
+        let end_offset = self
+            .entries
+            .get(index)
+            .map_or(log_end_offset, |(_, next_start_offset)| *next_start_offset);
+        Some(EpochEndOffset {
+            leader_epoch: found_epoch,
+            end_offset,
+        })
+    }
+
+    /// Forgets the epochs starting at or after `offset` once the log was truncated to it.
+    pub fn truncate_from_end(&mut self, offset: u64) {
+        self.entries
+            .retain(|(_, start_offset)| *start_offset < offset);
+        self.checkpoint();
+    }
+
+    /// Writes the epochs to a temporary file renamed over the checkpoint, so a crash while
+    /// writing keeps the previous checkpoint.
+    fn checkpoint(&self) {
+        let checkpoint_path = format!(
+            "{}/{}",
+            self.partition_path, LEADER_EPOCH_CHECKPOINT_FILE_NAME
+        );
+        let temporary_path = format!("{}.tmp", checkpoint_path);
+        let result = fs::create_dir_all(&self.partition_path)
+            .and_then(|_| bincode::serialize(&self.entries).map_err(std::io::Error::other))
+            .and_then(|entries| fs::write(&temporary_path, entries))
+            .and_then(|_| fs::rename(&temporary_path, &checkpoint_path));
+        if let Err(e) = result {
+            tracing::error!(
+                "Could not checkpoint leader epochs in {}: {:?}",
+                self.partition_path,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leader_epoch_should_grow_when_the_leader_changes() {
+        let temp_dir = tempdir::TempDir::new("partition_").unwrap();
+        let partition_path = temp_dir.path().join("t1/0");
+        let partition_path = partition_path.to_str().unwrap();
+
+        let partition_leader = PartitionLeader::load(&[0, 1], partition_path);
+        assert_eq!(partition_leader.leader_epoch, 0);
+        assert!(!partition_leader.is_fenced(None));
+        assert!(!partition_leader.is_fenced(Some(0)));
+
+        // a restart keeps an elected leader
+        let elected_leader = PartitionLeader {
+            leader_id: 1,
+            leader_epoch: 1,
+        };
+        elected_leader.store(partition_path);
+        assert_eq!(
+            PartitionLeader::load(&[0, 1], partition_path),
+            elected_leader
+        );
+        assert!(elected_leader.is_fenced(Some(0)));
+        assert!(elected_leader.is_fenced(Some(2)));
+
+        // the partition was reassigned to other replicas after it was created
+        assert_eq!(
+            PartitionLeader::load(&[2, 0], partition_path),
+            elected_leader
+        );
+    }
+
+    #[test]
+    fn test_leader_epoch_cache_should_find_where_epochs_end() {
+        let temp_dir = tempdir::TempDir::new("partition_").unwrap();
+        let partition_path = temp_dir.path().join("t1/0");
+        let partition_path = partition_path.to_str().unwrap();
+        let mut cache = LeaderEpochCache::load(0, partition_path);
+        cache.assign(2, 10);
+        // an older epoch is ignored and an empty epoch is replaced
+        cache.assign(1, 12);
+        cache.assign(3, 15);
+        cache.assign(4, 15);
+        assert_eq!(cache.latest_epoch(), Some(4));
+
+        let end_offset = |leader_epoch, end_offset| {
+            Some(EpochEndOffset {
+                leader_epoch,
+                end_offset,
+            })
+        };
+        assert_eq!(cache.end_offset_for(0, 20), end_offset(0, 10));
+        // an epoch the log has no records of ends where the next known one starts
+        assert_eq!(cache.end_offset_for(1, 20), end_offset(0, 10));
+        assert_eq!(cache.end_offset_for(2, 20), end_offset(2, 15));
+        assert_eq!(cache.end_offset_for(4, 20), end_offset(4, 20));
+        assert_eq!(cache.end_offset_for(7, 20), end_offset(4, 20));
+
+        cache.truncate_from_end(15);
+        assert_eq!(cache.latest_epoch(), Some(2));
+        assert_eq!(cache.end_offset_for(4, 12), end_offset(2, 12));
+
+        // a restart restores the checkpointed epochs instead of the current one
+        assert_eq!(LeaderEpochCache::load(5, partition_path), cache);
+        let new_partition_path = temp_dir.path().join("t1/1");
+        let new_partition_path = new_partition_path.to_str().unwrap();
+        assert_eq!(
+            LeaderEpochCache::load(3, new_partition_path).end_offset_for(2, 5),
+            None
+        );
+    }
+}
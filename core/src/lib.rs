@@ -0,0 +1,1724 @@
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_util::codec::{Decoder, Encoder};
+
+use bytes::{Bytes, BytesMut};
+use clock::{start_clock_monitor, BrokerClock, Clock};
+use cluster::{SaslSettings, TlsSettings};
+use common::clock::now_millis;
+use common::codecs::decoder::RecordBatchDecoder;
+use common::codecs::protocol::{api_versions, RequestCodec, RequestError, Response};
+use common::errors::ProduceError;
+use common::models::{
+    Acks, BrokerResponse, FetchRequest, FetchedBatch, MetadataRecord, OffsetResetPolicy,
+    ProduceResponse, RecordBatch, SessionFetchRequest, Topic, TopicCommand, TopicPartition,
+};
+use high_watermark::committed_batches;
+use managers::controller::{Controller, ControllerCommands};
+use managers::group_coordinator::{GroupCoordinator, GroupCoordinatorCommands};
+use managers::metadata_quorum::{MetadataQuorum, MetadataQuorumCommands};
+use managers::partition_manager::read_records;
+use managers::topics_manager::{TopicManagerCommands, TopicsManager};
+pub use resources::ResourceLimits;
+use scheduled_delivery::deliverable_batches;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::either::Either;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::Instrument;
+
+#[cfg(feature = "fault-injection")]
+use faults::connections_severed;
+
+/// A client connection, encrypted when the broker uses TLS.
+type ClientStream = Either<TcpStream, TlsStream<TcpStream>>;
+/// Topics which were not created within this time are answered with `None`, e.g. when no
+/// majority of the brokers is reachable.
+const CREATE_TOPIC_TIMEOUT: Duration = Duration::from_secs(10);
+/// Topics which were not deleted within this time are answered with `TopicNotDeleted`.
+const DELETE_TOPIC_TIMEOUT: Duration = Duration::from_secs(10);
+/// Controlled shutdowns whose followers did not catch up within this time leave the lagging
+/// partitions with the broker, answered as `remaining_partitions`.
+const FOLLOWER_CATCH_UP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Writes to an explicit partition which are not appended within this time are answered with
+/// `ProduceError::TimedOut`, e.g. when the partition writer is backed up.
+const PRODUCE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Records a fetch of the HTTP or gRPC proxy returns when the client does not limit them.
+const PROXY_DEFAULT_MAX_RECORDS: u32 = 100;
+/// Read and response buffers kept for new connections once theirs closed.
+const IDLE_CONNECTION_BUFFERS: usize = 256;
+
+mod assignors;
+mod audit;
+mod buffer_pool;
+mod clock;
+mod cluster;
+mod config;
+mod connections;
+mod disk_usage;
+mod encryption;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
+mod fetch_sessions;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod high_watermark;
+mod http_proxy;
+mod isr;
+mod leader_epoch;
+mod managers;
+mod membership;
+mod metrics;
+mod models;
+mod partition_queue;
+mod partition_routes;
+mod pipeline_monitor;
+mod quotas;
+mod raft;
+mod resources;
+mod sasl;
+mod scheduled_delivery;
+mod shutdown;
+
+use audit::{AuditAction, AuditContext, AuditLog};
+use buffer_pool::{BufferPool, PooledBuffer};
+pub use config::BrokerConfig;
+use connections::{ConnectionPermit, ConnectionTracker};
+use disk_usage::DiskUsage;
+use encryption::SegmentKeys;
+use fetch_sessions::FetchSessions;
+use metrics::Metrics;
+use models::{AppendedBatch, PartitionAppend, ProducerIdAllocator};
+use partition_queue::PartitionSender;
+use partition_routes::PartitionRoutes;
+use pipeline_monitor::PipelineMonitor;
+use quotas::{ClientQuotas, Quota, Replication, ReplicationThrottles};
+use sasl::{SaslAuthenticator, SaslSession, SaslStep, StaticCredentialStore};
+pub use shutdown::sigterm;
+use shutdown::BrokerShutdown;
+
+/// Serves clients on `listener` until `shutdown` completes, then shuts the broker down
+/// gracefully. Partition writers run on the `disk_io` runtime.
+pub async fn start_broker(
+    config: BrokerConfig,
+    listener: TcpListener,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    disk_io: Handle,
+) {
+    let BrokerConfig {
+        resources: resource_settings,
+        cluster: cluster_settings,
+        quotas: quota_settings,
+        connections: connection_settings,
+    } = config;
+    // the listeners and connections stop before the managers they pass requests to
+    let cancellation_token = CancellationToken::new();
+    let listener_token = cancellation_token.child_token();
+
+    let clock = BrokerClock::new();
+    tokio::spawn(start_clock_monitor(clock, cancellation_token.clone()));
+    let manager_clock: Arc<dyn Clock> = Arc::new(clock);
+    let producer_id_allocator = ProducerIdAllocator::new(clock.now_millis());
+    let metrics = Metrics::with_granularity(cluster_settings.metrics_granularity);
+    let client_quotas = Arc::new(ClientQuotas::new(quota_settings, &metrics));
+    let connection_tracker = Arc::new(ConnectionTracker::new(
+        connection_settings,
+        &metrics,
+        listener_token.clone(),
+    ));
+    let connection_buffers = BufferPool::new(
+        "connections",
+        resource_settings.read_buffer_size,
+        IDLE_CONNECTION_BUFFERS,
+        &metrics,
+    );
+
+    // segments are recovered with the keys once their partition writers start
+    if let Some(encryption) = &cluster_settings.encryption {
+        let keys = SegmentKeys::new(encryption).expect("Invalid segment encryption keys");
+        encryption::set_segment_keys(keys);
+    }
+    let mut topics_manager = TopicsManager::new(
+        cluster_settings.log_dir_path.clone(),
+        resource_settings.partition_channel_size,
+        cluster_settings.clone(),
+        &metrics,
+        disk_io,
+        manager_clock.clone(),
+        cancellation_token.clone(),
+    );
+    let topic_events_rx = topics_manager.subscribe_topic_events();
+    let quorum_topic_events_rx = topics_manager.subscribe_topic_events();
+    let partition_routes = topics_manager.partition_routes();
+    let partition_writer_tasks = topics_manager.partition_writer_tasks();
+    let replication_throttles = topics_manager.replication_throttles();
+    let (topic_manager_tx, topic_manager_rx) =
+        mpsc::channel::<TopicManagerCommands>(resource_settings.manager_channel_size);
+    let topics_manager_task = tokio::spawn(async move {
+        topics_manager.start_topics_manager(topic_manager_rx).await;
+    });
+
+    let metadata_quorum = MetadataQuorum::new(
+        cluster_settings.clone(),
+        topic_manager_tx.clone(),
+        cancellation_token.clone(),
+    );
+    let (metadata_quorum_tx, metadata_quorum_rx) =
+        mpsc::channel::<MetadataQuorumCommands>(resource_settings.manager_channel_size);
+    tokio::spawn(metadata_quorum.start_metadata_quorum(metadata_quorum_rx, quorum_topic_events_rx));
+
+    let controller = Controller::new(
+        cluster_settings.clone(),
+        topic_manager_tx.clone(),
+        metadata_quorum_tx.clone(),
+        &metrics,
+        manager_clock.clone(),
+        cancellation_token.clone(),
+    );
+    let (controller_tx, controller_rx) =
+        mpsc::channel::<ControllerCommands>(resource_settings.manager_channel_size);
+    tokio::spawn(controller.start_controller(controller_rx));
+    tokio::spawn(membership::start_broker_heartbeats(
+        cluster_settings.clone(),
+        metadata_quorum_tx.clone(),
+        controller_tx.clone(),
+        cancellation_token.clone(),
+    ));
+
+    let mut group_coordinator = GroupCoordinator::new(
+        topic_manager_tx.clone(),
+        manager_clock,
+        cancellation_token.clone(),
+    );
+    let (group_coordinator_tx, group_coordinator_rx) =
+        mpsc::channel::<GroupCoordinatorCommands>(resource_settings.manager_channel_size);
+    tokio::spawn(async move {
+        group_coordinator
+            .start_group_coordinator(group_coordinator_rx, topic_events_rx)
+            .await;
+    });
+
+    let manager_channels = ManagerChannels {
+        metadata_quorum_tx,
+        controller_tx,
+        topic_manager_tx,
+        group_coordinator_tx,
+        partition_routes,
+        fetch_sessions: FetchSessions::new(connection_settings.max_fetch_sessions),
+        disk_usage: DiskUsage::start(
+            cluster_settings.log_dir_path.clone(),
+            cluster_settings.log_dir_min_free_percent,
+            &metrics,
+            cancellation_token.clone(),
+        ),
+        replication_throttles,
+    };
+    let proxy_tasks = TaskTracker::new();
+    if let Some(http_listen_address) = cluster_settings.http_listen_address.clone() {
+        proxy_tasks.spawn(http_proxy::start_http_proxy(
+            http_listen_address,
+            manager_channels.clone(),
+            listener_token.clone(),
+        ));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_listen_address) = cluster_settings.grpc_listen_address.clone() {
+        proxy_tasks.spawn(grpc::start_grpc_server(
+            grpc_listen_address,
+            manager_channels.clone(),
+            metrics.clone(),
+            listener_token.clone(),
+        ));
+    }
+    #[cfg(not(feature = "grpc"))]
+    if cluster_settings.grpc_listen_address.is_some() {
+        tracing::warn!("Ignoring WALRS_GRPC_LISTEN_ADDRESS, the broker was built without gRPC");
+    }
+    let mut pipeline_monitor = PipelineMonitor::new(&metrics);
+    pipeline_monitor.watch_queue("metadata_quorum", &manager_channels.metadata_quorum_tx);
+    pipeline_monitor.watch_queue("controller", &manager_channels.controller_tx);
+    pipeline_monitor.watch_queue("topics_manager", &manager_channels.topic_manager_tx);
+    pipeline_monitor.watch_queue("group_coordinator", &manager_channels.group_coordinator_tx);
+    pipeline_monitor.watch_tasks("partition_writers", &partition_writer_tasks);
+    pipeline_monitor.watch_tasks("proxies", &proxy_tasks);
+    pipeline_monitor.start(cancellation_token.clone());
+
+    let connection_context = ConnectionContext {
+        security: ListenerSecurity {
+            tls_acceptor: cluster_settings.tls.as_ref().map(tls_acceptor),
+            sasl_authenticator: cluster_settings
+                .sasl
+                .as_ref()
+                .map(|sasl| sasl_authenticator(sasl, &metrics)),
+            audit_log: cluster_settings
+                .audit_log_path
+                .clone()
+                .map(AuditLog::start)
+                .unwrap_or_default(),
+        },
+        connection_buffers,
+        clock,
+        producer_id_allocator,
+        metrics,
+        client_quotas,
+    };
+    tracing::info!("Listening on: {}", listener.local_addr().unwrap());
+    let shutdown_task = tokio::spawn(
+        BrokerShutdown {
+            cluster_settings: cluster_settings.clone(),
+            controller_tx: manager_channels.controller_tx.clone(),
+            topic_manager_tx: manager_channels.topic_manager_tx.clone(),
+            listener_token: listener_token.clone(),
+            connection_tracker: connection_tracker.clone(),
+            proxy_tasks,
+            cancellation_token,
+            topics_manager_task,
+        }
+        .after(shutdown),
+    );
+
+    loop {
+        let (socket, peer_address) = tokio::select! {
+            accepted = listener.accept() => accepted.unwrap(),
+            _ = listener_token.cancelled() => break,
+        };
+        // dropping the socket of a rejected connection closes it
+        let Some(connection) = connection_tracker.open(peer_address.ip()) else {
+            continue;
+        };
+        handle_client_connection(
+            socket,
+            connection,
+            connection_context.clone(),
+            manager_channels.clone(),
+        )
+        .await;
+    }
+    // no new connections are accepted once the listener is dropped
+    drop(listener);
+    let _ = shutdown_task.await;
+}
+
+/// Loads the certificates of the listener, and of the connections to other brokers with a CA.
+fn tls_acceptor(tls: &TlsSettings) -> TlsAcceptor {
+    let client_ca_path = tls.client_auth.then(|| {
+        tls.ca_path
+            .as_deref()
+            .expect("WALRS_TLS_CLIENT_AUTH requires WALRS_TLS_CA_PATH")
+    });
+    let server_config =
+        common::tls::server_config(&tls.certificate_path, &tls.private_key_path, client_ca_path)
+            .expect("Could not load the TLS certificate of the broker");
+    if let Some(ca_path) = &tls.ca_path {
+        // brokers present their own certificate to brokers which authenticate their clients
+        let client_certificate = (tls.certificate_path.as_str(), tls.private_key_path.as_str());
+        let client_config = common::tls::client_config(ca_path, Some(client_certificate))
+            .expect("Could not load the TLS CA");
+        cluster::set_broker_tls(client_config);
+    }
+    TlsAcceptor::from(server_config)
+}
+
+/// Authenticates the connections with the users of the settings, and the connections to other
+/// brokers as the inter-broker user.
+fn sasl_authenticator(sasl: &SaslSettings, metrics: &Metrics) -> Arc<SaslAuthenticator> {
+    match sasl.inter_broker_credentials() {
+        Some(credentials) => cluster::set_broker_credentials(credentials),
+        None => tracing::warn!(
+            "No WALRS_SASL_INTER_BROKER_USER of WALRS_SASL_USERS, other brokers cannot be reached"
+        ),
+    }
+    Arc::new(SaslAuthenticator::new(
+        sasl.enabled_mechanisms.clone(),
+        Arc::new(StaticCredentialStore::new(&sasl.users)),
+        metrics,
+    ))
+}
+
+/// TLS and SASL of the listener, connections are accepted as is without them, and the audit log
+/// of its connections' administrative requests and failed authentications.
+#[derive(Clone, Default)]
+struct ListenerSecurity {
+    tls_acceptor: Option<TlsAcceptor>,
+    sasl_authenticator: Option<Arc<SaslAuthenticator>>,
+    audit_log: AuditLog,
+}
+
+/// Senders of the managers a connection passes requests to, the partition writers it routes
+/// writes to without going through the topics manager, the fetch sessions of the broker, whether
+/// its log directory has room for writes and the limits of its replication traffic.
+#[derive(Clone)]
+struct ManagerChannels {
+    metadata_quorum_tx: mpsc::Sender<MetadataQuorumCommands>,
+    controller_tx: mpsc::Sender<ControllerCommands>,
+    topic_manager_tx: mpsc::Sender<TopicManagerCommands>,
+    group_coordinator_tx: mpsc::Sender<GroupCoordinatorCommands>,
+    partition_routes: PartitionRoutes,
+    fetch_sessions: FetchSessions,
+    disk_usage: DiskUsage,
+    replication_throttles: Arc<ReplicationThrottles>,
+}
+
+/// What the connections of the listener share besides the managers' channels.
+#[derive(Clone)]
+struct ConnectionContext {
+    security: ListenerSecurity,
+    connection_buffers: BufferPool,
+    clock: BrokerClock,
+    producer_id_allocator: ProducerIdAllocator,
+    metrics: Metrics,
+    client_quotas: Arc<ClientQuotas>,
+}
+
+/// Handles the requests of the connection in its own task, which closes the connection once it
+/// is idle for longer than the idle timeout of the settings, or once the broker shuts down and
+/// the requests in flight are answered.
+async fn handle_client_connection(
+    socket: TcpStream,
+    connection: ConnectionPermit,
+    context: ConnectionContext,
+    manager_channels: ManagerChannels,
+) {
+    let ConnectionContext {
+        security,
+        connection_buffers,
+        clock,
+        producer_id_allocator,
+        metrics,
+        client_quotas,
+    } = context;
+    tracing::info!("Accepted a new connection");
+
+    if let Err(e) = connection.configure(&socket) {
+        tracing::warn!(
+            "Could not set the socket options of the connection: {:?}",
+            e
+        );
+    }
+    let idle_timeout = connection.settings().idle_timeout;
+    let max_in_flight_requests = connection.settings().max_in_flight_requests;
+    let socket_request_max_bytes = connection.settings().socket_request_max_bytes;
+    let shutdown_token = connection.shutdown_token();
+    let source_ip = connection.ip();
+
+    tokio::spawn(async move {
+        // the connection counts as open until the task ends
+        let _connection = connection;
+        let stream: ClientStream = match security.tls_acceptor {
+            Some(tls_acceptor) => {
+                let handshake = tls_acceptor.accept(socket);
+                match tokio::time::timeout(idle_timeout, handshake).await {
+                    Ok(Ok(stream)) => Either::Right(stream),
+                    Ok(Err(e)) => {
+                        tracing::warn!("TLS handshake failed: {:?}", e);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::warn!("TLS handshake timed out");
+                        return;
+                    }
+                }
+            }
+            None => Either::Left(socket),
+        };
+        let (mut read_half, write_half) = tokio::io::split(stream);
+        let (responses_tx, responses_rx) = mpsc::channel::<Response>(max_in_flight_requests);
+        let writer = tokio::spawn(write_responses(
+            write_half,
+            responses_rx,
+            connection_buffers.acquire(),
+        ));
+        let in_flight_requests = Arc::new(Semaphore::new(max_in_flight_requests));
+        let mut sasl_session: Option<SaslSession> = security
+            .sasl_authenticator
+            .as_ref()
+            .map(|authenticator| authenticator.session());
+        let mut request_codec = RequestCodec::new(socket_request_max_bytes);
+        let read_buffer_size = connection_buffers.buffer_capacity();
+        let mut message_buffer = connection_buffers.acquire();
+        let severed = connections_severed();
+        tokio::pin!(severed);
+        loop {
+            let request = match request_codec.decode(&mut message_buffer) {
+                Ok(Some(request)) => request,
+                Ok(None) => {
+                    message_buffer.reserve(read_buffer_size);
+                    let read = tokio::select! {
+                        read = tokio::time::timeout(
+                            idle_timeout,
+                            read_half.read_buf(&mut *message_buffer),
+                        ) => read,
+                        _ = shutdown_token.cancelled() => {
+                            tracing::info!("Broker is shutting down, closing the connection");
+                            break;
+                        }
+                        _ = &mut severed => {
+                            tracing::warn!("Severing the connection");
+                            writer.abort();
+                            break;
+                        }
+                    };
+                    let num_bytes_read = match read {
+                        Ok(Ok(num_bytes_read)) => num_bytes_read,
+                        Ok(Err(e)) => {
+                            tracing::info!("Could not read from the connection: {:?}", e);
+                            break;
+                        }
+                        Err(_) => {
+                            tracing::info!(
+                                "No request received in {:?}, closing idle connection",
+                                idle_timeout
+                            );
+                            break;
+                        }
+                    };
+                    if num_bytes_read == 0 {
+                        tracing::info!("Client closed the connection");
+                        break;
+                    }
+                    tracing::info!("Received {} bytes", num_bytes_read);
+                    continue;
+                }
+                Err(RequestError::UnsupportedVersion(header)) => {
+                    tracing::warn!(
+                        "Version {} of {:?} requests from {:?} is not supported",
+                        header.api_version,
+                        header.api_key,
+                        header.client_id
+                    );
+                    let response = BrokerResponse::UnsupportedVersion {
+                        api_key: header.api_key as u16,
+                        api_version: header.api_version,
+                    };
+                    let response = Response {
+                        correlation_id: header.correlation_id,
+                        throttle_time_ms: 0,
+                        response,
+                    };
+                    if responses_tx.send(response).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                Err(RequestError::Io(e)) => {
+                    tracing::error!("Could not decode request, closing connection: {:?}", e);
+                    break;
+                }
+            };
+            tracing::info!(
+                "Received {:?} request {} from {:?}",
+                request.header.api_key,
+                request.header.correlation_id,
+                request.header.client_id
+            );
+            let correlation_id = request.header.correlation_id;
+            // exported spans show how long each step of a request took
+            let span = tracing::info_span!(
+                "request",
+                api_key = ?request.header.api_key,
+                correlation_id,
+                client_id = %request.header.client_id
+            );
+            // authentication requests are answered in order, before the next request is read
+            let sasl_step = sasl_session
+                .as_mut()
+                .map_or(SaslStep::Pass, |session| session.handle(&request.command));
+            let audit_context = AuditContext {
+                principal: sasl_session
+                    .as_ref()
+                    .and_then(SaslSession::principal)
+                    .map(str::to_string),
+                client_id: request.header.client_id.clone(),
+                source_ip,
+            };
+            let (response, close) = match sasl_step {
+                SaslStep::Pass => (None, false),
+                SaslStep::Answer(response) => (Some(response), false),
+                SaslStep::Reject(response) => {
+                    if let BrokerResponse::SaslAuthenticationFailed { error } = &response {
+                        let action = AuditAction::AuthenticationFailed {
+                            error: error.clone(),
+                        };
+                        let audit_context = audit_context.clone();
+                        security.audit_log.record(audit_context, action, &response);
+                    }
+                    (Some(response), true)
+                }
+            };
+            if let Some(response) = response {
+                let response = Response {
+                    correlation_id,
+                    throttle_time_ms: 0,
+                    response,
+                };
+                if responses_tx.send(response).await.is_err() || close {
+                    break;
+                }
+                continue;
+            }
+            let client_id = request.header.client_id;
+            let request_throttle_time =
+                client_quotas.record(&client_id, Quota::Requests, 1, Instant::now());
+            let in_flight = in_flight_requests.clone().acquire_owned().await.unwrap();
+
+            match request.command {
+                TopicCommand::WriteToTopic {
+                    topic_name,
+                    partition_index,
+                    acks,
+                    leader_epoch,
+                } => {
+                    let produced_bytes = request.body.len() as u64;
+                    let throttle_time = request_throttle_time.max(client_quotas.record(
+                        &client_id,
+                        Quota::ProducedBytes,
+                        produced_bytes,
+                        Instant::now(),
+                    ));
+                    let response = handle_write_to_topic_request(
+                        TopicPartition::new(topic_name, partition_index),
+                        acks,
+                        leader_epoch,
+                        &manager_channels,
+                        request.body,
+                    )
+                    .instrument(span.clone())
+                    .await;
+                    tokio::spawn(
+                        respond(
+                            responses_tx.clone(),
+                            correlation_id,
+                            in_flight,
+                            response,
+                            move |_| throttle_time,
+                        )
+                        .instrument(span),
+                    );
+                }
+                command => {
+                    // followers fetching to replicate a partition do not use the client's quota but
+                    // the broker's replication throttles
+                    let (consumer_fetch, replica_id) = match &command {
+                        TopicCommand::Fetch(fetch_request) => {
+                            (fetch_request.replica_id.is_none(), fetch_request.replica_id)
+                        }
+                        TopicCommand::SessionFetch(_) => (true, None),
+                        _ => (false, None),
+                    };
+                    let replication_throttles = manager_channels.replication_throttles.clone();
+                    let audit_action = AuditAction::of(&command);
+                    let response = handle_request(
+                        command,
+                        clock,
+                        producer_id_allocator.clone(),
+                        metrics.clone(),
+                        manager_channels.clone(),
+                    );
+                    let audit_log = security.audit_log.clone();
+                    let response = async move {
+                        let response = response.await;
+                        if let Some(action) = audit_action {
+                            audit_log.record(audit_context, action, &response);
+                        }
+                        response
+                    };
+                    let client_quotas = client_quotas.clone();
+                    tokio::spawn(
+                        respond(
+                            responses_tx.clone(),
+                            correlation_id,
+                            in_flight,
+                            async move { Some(response.await) },
+                            move |response| match (response, replica_id) {
+                                (
+                                    BrokerResponse::Records { .. }
+                                    | BrokerResponse::SessionRecords { .. },
+                                    _,
+                                ) if consumer_fetch => {
+                                    request_throttle_time.max(client_quotas.record(
+                                        &client_id,
+                                        Quota::FetchedBytes,
+                                        fetched_bytes(response),
+                                        Instant::now(),
+                                    ))
+                                }
+                                (
+                                    BrokerResponse::Records {
+                                        topic_partition, ..
+                                    },
+                                    Some(replica_id),
+                                ) => request_throttle_time.max(replication_throttles.record(
+                                    Replication::Leader,
+                                    topic_partition,
+                                    replica_id,
+                                    fetched_bytes(response),
+                                    Instant::now(),
+                                )),
+                                _ => request_throttle_time,
+                            },
+                        )
+                        .instrument(span),
+                    );
+                }
+            }
+        }
+        // the writer closes the connection once the requests in flight are answered
+        drop(responses_tx);
+        let _ = writer.await;
+    });
+}
+
+/// Connections are only severed by fault injection.
+#[cfg(not(feature = "fault-injection"))]
+fn connections_severed() -> std::future::Pending<()> {
+    std::future::pending()
+}
+
+/// Answers every request except `TopicCommand::WriteToTopic`, which is handed to the partition
+/// writer before the next request of the connection is read.
+async fn handle_request(
+    command: TopicCommand,
+    clock: BrokerClock,
+    producer_id_allocator: ProducerIdAllocator,
+    metrics: Metrics,
+    manager_channels: ManagerChannels,
+) -> BrokerResponse {
+    let ManagerChannels {
+        metadata_quorum_tx,
+        controller_tx,
+        topic_manager_tx,
+        group_coordinator_tx,
+        fetch_sessions,
+        ..
+    } = manager_channels;
+    match command {
+        TopicCommand::Ping => BrokerResponse::Pong {
+            broker_time_millis: clock.now_millis(),
+        },
+        TopicCommand::ApiVersions => api_versions(),
+        TopicCommand::SaslHandshake { .. } => BrokerResponse::SaslHandshake {
+            enabled_mechanisms: vec![],
+        },
+        TopicCommand::SaslAuthenticate { .. } => BrokerResponse::SaslAuthenticationFailed {
+            error: "SASL is not enabled".to_string(),
+        },
+        TopicCommand::InitProducerId => BrokerResponse::ProducerIdAllocated {
+            producer_id: producer_id_allocator.allocate(),
+        },
+        TopicCommand::DescribeMetrics => BrokerResponse::Metrics {
+            metrics: metrics.snapshot(),
+        },
+        TopicCommand::AlterLogFilter { filter } => match common::set_log_filter(&filter) {
+            Ok(previous_filter) => {
+                tracing::info!("Log filter changed from {} to {}", previous_filter, filter);
+                BrokerResponse::LogFilterAltered { previous_filter }
+            }
+            Err(error) => BrokerResponse::InvalidLogFilter { error },
+        },
+        TopicCommand::CreateTopic { topic } => {
+            handle_create_topic_request(topic, metadata_quorum_tx, topic_manager_tx).await
+        }
+        TopicCommand::DeleteTopic { topic_name } => {
+            handle_delete_topic_request(topic_name, metadata_quorum_tx, topic_manager_tx).await
+        }
+        TopicCommand::RequestVote(request) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = MetadataQuorumCommands::RequestVote { request, reply_tx };
+            handle_quorum_request(command, reply_rx, metadata_quorum_tx).await
+        }
+        TopicCommand::AppendEntries(request) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = MetadataQuorumCommands::AppendEntries { request, reply_tx };
+            handle_quorum_request(command, reply_rx, metadata_quorum_tx).await
+        }
+        TopicCommand::ProposeMetadata { record } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = MetadataQuorumCommands::ProposeForwarded { record, reply_tx };
+            handle_quorum_request(command, reply_rx, metadata_quorum_tx).await
+        }
+        TopicCommand::BrokerHeartbeat { broker_id } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = ControllerCommands::BrokerHeartbeat {
+                broker_id,
+                reply_tx,
+            };
+            handle_controller_request(command, reply_rx, controller_tx).await
+        }
+        TopicCommand::ReassignPartitions {
+            reassignments,
+            throttle_rate,
+        } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = ControllerCommands::ReassignPartitions {
+                reassignments,
+                throttle_rate,
+                reply_tx,
+            };
+            handle_controller_request(command, reply_rx, controller_tx).await
+        }
+        TopicCommand::ControlledShutdown { broker_id } => {
+            // only sent to the broker which shuts down, the active controller finds no followers
+            // of its own to wait for when another broker's controller forwards the request
+            let lagging_partitions = membership::await_followers_caught_up(
+                broker_id,
+                &topic_manager_tx,
+                FOLLOWER_CATCH_UP_TIMEOUT,
+            )
+            .await;
+            if !lagging_partitions.is_empty() {
+                return BrokerResponse::ControlledShutdownCompleted {
+                    remaining_partitions: lagging_partitions,
+                };
+            }
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = ControllerCommands::ControlledShutdown {
+                broker_id,
+                reply_tx,
+            };
+            handle_controller_request(command, reply_rx, controller_tx).await
+        }
+        TopicCommand::DescribeCluster => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = ControllerCommands::DescribeCluster { reply_tx };
+            handle_controller_request(command, reply_rx, controller_tx).await
+        }
+        TopicCommand::DecommissionBroker { broker_id } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = ControllerCommands::DecommissionBroker {
+                broker_id,
+                reply_tx,
+            };
+            handle_controller_request(command, reply_rx, controller_tx).await
+        }
+        TopicCommand::DescribeReassignments => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = ControllerCommands::DescribeReassignments { reply_tx };
+            handle_controller_request(command, reply_rx, controller_tx).await
+        }
+        TopicCommand::ElectPreferredLeaders { topic_partitions } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = ControllerCommands::ElectPreferredLeaders {
+                topic_partitions,
+                reply_tx,
+            };
+            handle_controller_request(command, reply_rx, controller_tx).await
+        }
+        TopicCommand::DescribeTopic { topic_name } => {
+            handle_describe_topic_request(topic_name, topic_manager_tx).await
+        }
+        TopicCommand::OffsetForLeaderEpoch {
+            topic_partition,
+            leader_epoch,
+        } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            topic_manager_tx
+                .send(TopicManagerCommands::GetEpochEndOffset {
+                    topic_partition,
+                    leader_epoch,
+                    reply_tx,
+                })
+                .await
+                .unwrap();
+            reply_rx.await.unwrap()
+        }
+        TopicCommand::Metadata { topic_names } => {
+            handle_metadata_request(topic_names, metadata_quorum_tx, topic_manager_tx).await
+        }
+        TopicCommand::WriteToTopic { .. } => {
+            unreachable!("writes are handed to the partition writer by the connection")
+        }
+        TopicCommand::JoinGroup(request) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::JoinGroup { request, reply_tx };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+        TopicCommand::Heartbeat {
+            group_id,
+            member_id,
+        } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::Heartbeat {
+                group_id,
+                member_id,
+                reply_tx,
+            };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+        TopicCommand::LeaveGroup {
+            group_id,
+            member_id,
+        } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::LeaveGroup {
+                group_id,
+                member_id,
+                reply_tx,
+            };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+        TopicCommand::GetAssignment {
+            group_id,
+            member_id,
+        } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::GetAssignment {
+                group_id,
+                member_id,
+                reply_tx,
+            };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+        TopicCommand::CommitOffsets { group_id, offsets } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::CommitOffsets {
+                group_id,
+                offsets,
+                reply_tx,
+            };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+        TopicCommand::DescribeGroup { group_id } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::DescribeGroup { group_id, reply_tx };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+        TopicCommand::ResetOffsets {
+            group_id,
+            topic_name,
+            to,
+        } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::ResetOffsets {
+                group_id,
+                topic_name,
+                to,
+                reply_tx,
+            };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+        TopicCommand::Fetch(fetch_request) => {
+            let preferred_read_replica =
+                preferred_read_replica(&fetch_request, &metadata_quorum_tx, &topic_manager_tx)
+                    .await;
+            match preferred_read_replica {
+                Some(broker_id) => BrokerResponse::PreferredReadReplica {
+                    topic_partition: fetch_request.topic_partition,
+                    broker_id,
+                },
+                None => {
+                    let response =
+                        handle_fetch_request(fetch_request, topic_manager_tx, group_coordinator_tx)
+                            .await;
+                    record_bytes_out(&metrics, &response);
+                    response
+                }
+            }
+        }
+        TopicCommand::SessionFetch(request) => {
+            let response = handle_session_fetch_request(
+                request,
+                &fetch_sessions,
+                &metadata_quorum_tx,
+                &topic_manager_tx,
+                &group_coordinator_tx,
+            )
+            .await;
+            record_bytes_out(&metrics, &response);
+            response
+        }
+        TopicCommand::RevocationCompleted {
+            group_id,
+            member_id,
+        } => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = GroupCoordinatorCommands::RevocationCompleted {
+                group_id,
+                member_id,
+                reply_tx,
+            };
+            handle_group_request(command, reply_rx, group_coordinator_tx).await
+        }
+    }
+}
+
+/// Bytes of the records a fetch response holds, counted against the consumer byte rate quota.
+fn fetched_bytes(response: &BrokerResponse) -> u64 {
+    match response {
+        BrokerResponse::Records { batches, .. } => batches
+            .iter()
+            .map(|fetched_batch| fetched_batch.batch.records.len() as u64)
+            .sum(),
+        BrokerResponse::SessionRecords { responses, .. } => responses
+            .iter()
+            .map(|(_, response)| fetched_bytes(response))
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Counts the fetched bytes against the topics they were read from.
+fn record_bytes_out(metrics: &Metrics, response: &BrokerResponse) {
+    match response {
+        BrokerResponse::Records {
+            topic_partition, ..
+        } => {
+            if let Some(topic_metrics) = metrics.topic(&topic_partition.topic_name) {
+                topic_metrics
+                    .bytes_out
+                    .fetch_add(fetched_bytes(response), Ordering::Relaxed);
+            }
+        }
+        BrokerResponse::SessionRecords { responses, .. } => {
+            for (_, response) in responses {
+                record_bytes_out(metrics, response);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Hands the response to the writer of the connection once it is ready, `None` is not answered.
+/// A client exceeding its quotas gets the response only after the throttle time of the response.
+/// The request counts as in flight until then, so a throttled client cannot pile up requests.
+async fn respond(
+    responses_tx: mpsc::Sender<Response>,
+    correlation_id: u32,
+    _in_flight: OwnedSemaphorePermit,
+    response: impl Future<Output = Option<BrokerResponse>>,
+    throttle_time: impl FnOnce(&BrokerResponse) -> Duration,
+) {
+    if let Some(response) = response.await {
+        let throttle_time = throttle_time(&response);
+        if !throttle_time.is_zero() {
+            tokio::time::sleep(throttle_time).await;
+        }
+        // the writer is gone when the client closed the connection
+        let _ = responses_tx
+            .send(Response {
+                correlation_id,
+                throttle_time_ms: throttle_time.as_millis() as u32,
+                response,
+            })
+            .await;
+    }
+}
+
+/// Writes the responses of a connection in the order they are ready, which is not necessarily
+/// the order of their requests, and closes the connection once every request was answered.
+async fn write_responses(
+    mut write_half: WriteHalf<ClientStream>,
+    mut responses_rx: mpsc::Receiver<Response>,
+    mut response_bytes: PooledBuffer,
+) {
+    let mut response_codec = RequestCodec::default();
+    while let Some(response) = responses_rx.recv().await {
+        response_codec
+            .encode(response, &mut response_bytes)
+            .unwrap();
+        // responses which got ready meanwhile go out with the same write
+        while let Ok(response) = responses_rx.try_recv() {
+            response_codec
+                .encode(response, &mut response_bytes)
+                .unwrap();
+        }
+        if let Err(e) = write_half.write_all_buf(&mut *response_bytes).await {
+            tracing::info!("Could not write responses: {:?}", e);
+            return;
+        }
+    }
+    let _ = write_half.shutdown().await;
+}
+
+async fn handle_group_request(
+    command: GroupCoordinatorCommands,
+    reply_rx: oneshot::Receiver<BrokerResponse>,
+    group_coordinator_tx: mpsc::Sender<GroupCoordinatorCommands>,
+) -> BrokerResponse {
+    group_coordinator_tx.send(command).await.unwrap();
+    reply_rx.await.unwrap()
+}
+
+async fn handle_controller_request(
+    command: ControllerCommands,
+    reply_rx: oneshot::Receiver<BrokerResponse>,
+    controller_tx: mpsc::Sender<ControllerCommands>,
+) -> BrokerResponse {
+    controller_tx.send(command).await.unwrap();
+    reply_rx.await.unwrap()
+}
+
+async fn handle_quorum_request(
+    command: MetadataQuorumCommands,
+    reply_rx: oneshot::Receiver<BrokerResponse>,
+    metadata_quorum_tx: mpsc::Sender<MetadataQuorumCommands>,
+) -> BrokerResponse {
+    metadata_quorum_tx.send(command).await.unwrap();
+    reply_rx.await.unwrap()
+}
+
+/// Fetch of a proxy client reading a partition without a group, from `offset` or else from the
+/// first stored record. An offset outside of the stored records is an error rather than reset.
+fn proxy_fetch_request(
+    topic_partition: TopicPartition,
+    offset: Option<u64>,
+    max_records: Option<u32>,
+) -> FetchRequest {
+    FetchRequest {
+        topic_partition,
+        offset,
+        group_id: None,
+        member_id: None,
+        auto_offset_reset: match offset {
+            Some(_) => OffsetResetPolicy::None,
+            None => OffsetResetPolicy::Earliest,
+        },
+        max_records: max_records.unwrap_or(PROXY_DEFAULT_MAX_RECORDS),
+        replica_id: None,
+        leader_epoch: None,
+        rack_id: None,
+    }
+}
+
+/// In-sync follower in the rack of the consumer the fetch is redirected to, so consumers in
+/// another rack than the leader read rack-local. Fetches without a rack are served as usual.
+async fn preferred_read_replica(
+    fetch_request: &FetchRequest,
+    metadata_quorum_tx: &mpsc::Sender<MetadataQuorumCommands>,
+    topic_manager_tx: &mpsc::Sender<TopicManagerCommands>,
+) -> Option<u32> {
+    let rack_id = fetch_request.rack_id.clone()?;
+    if fetch_request.replica_id.is_some() {
+        return None;
+    }
+    let (reply_tx, reply_rx) = oneshot::channel();
+    metadata_quorum_tx
+        .send(MetadataQuorumCommands::GetBrokers { reply_tx })
+        .await
+        .unwrap();
+    let broker_racks = reply_rx
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|registration| (registration.broker_id, registration.rack))
+        .collect();
+    let (reply_tx, reply_rx) = oneshot::channel();
+    topic_manager_tx
+        .send(TopicManagerCommands::GetPreferredReadReplica {
+            topic_partition: fetch_request.topic_partition.clone(),
+            rack_id,
+            broker_racks,
+            reply_tx,
+        })
+        .await
+        .unwrap();
+    reply_rx.await.unwrap()
+}
+
+/// Fetches every partition of the session from the offset the session continues it from,
+/// answering only the partitions with new records or an error.
+async fn handle_session_fetch_request(
+    request: SessionFetchRequest,
+    fetch_sessions: &FetchSessions,
+    metadata_quorum_tx: &mpsc::Sender<MetadataQuorumCommands>,
+    topic_manager_tx: &mpsc::Sender<TopicManagerCommands>,
+    group_coordinator_tx: &mpsc::Sender<GroupCoordinatorCommands>,
+) -> BrokerResponse {
+    let (session_id, partitions) = match fetch_sessions.update(&request) {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    let mut responses = vec![];
+    for (topic_partition, offset) in partitions {
+        let fetch_request = FetchRequest {
+            topic_partition: topic_partition.clone(),
+            offset,
+            group_id: None,
+            member_id: None,
+            auto_offset_reset: request.auto_offset_reset,
+            max_records: request.max_records,
+            replica_id: None,
+            leader_epoch: None,
+            rack_id: request.rack_id.clone(),
+        };
+        let preferred_read_replica =
+            preferred_read_replica(&fetch_request, metadata_quorum_tx, topic_manager_tx).await;
+        if let Some(broker_id) = preferred_read_replica {
+            fetch_sessions.forget(session_id, &topic_partition);
+            let response = BrokerResponse::PreferredReadReplica {
+                topic_partition: topic_partition.clone(),
+                broker_id,
+            };
+            responses.push((topic_partition, response));
+            continue;
+        }
+        let response = handle_fetch_request(
+            fetch_request,
+            topic_manager_tx.clone(),
+            group_coordinator_tx.clone(),
+        )
+        .await;
+        if let BrokerResponse::Records {
+            base_offset,
+            batches,
+            ..
+        } = &response
+        {
+            let next_offset = next_fetch_offset(*base_offset, batches, request.max_records);
+            fetch_sessions.advance(session_id, &topic_partition, next_offset);
+            // partitions without new records are left out
+            if batches.is_empty() {
+                continue;
+            }
+        }
+        responses.push((topic_partition, response));
+    }
+    BrokerResponse::SessionRecords {
+        session_id,
+        responses,
+    }
+}
+
+/// Offset following the records a consumer takes from a fetch starting at `base_offset`.
+fn next_fetch_offset(base_offset: u64, batches: &[FetchedBatch], max_records: u32) -> u64 {
+    let fetched_end_offset = batches.last().map_or(base_offset, |fetched_batch| {
+        fetched_batch.base_offset + fetched_batch.batch.record_count as u64
+    });
+    fetched_end_offset.clamp(base_offset, base_offset + max_records as u64)
+}
+
+async fn handle_fetch_request(
+    fetch_request: FetchRequest,
+    topic_manager_tx: mpsc::Sender<TopicManagerCommands>,
+    group_coordinator_tx: mpsc::Sender<GroupCoordinatorCommands>,
+) -> BrokerResponse {
+    if let (Some(replica_id), Some(fetch_offset)) = (fetch_request.replica_id, fetch_request.offset)
+    {
+        topic_manager_tx
+            .send(TopicManagerCommands::RecordReplicaFetch {
+                topic_partition: fetch_request.topic_partition.clone(),
+                replica_id,
+                fetch_offset,
+            })
+            .await
+            .unwrap();
+    }
+    let (reply_tx, reply_rx) = oneshot::channel();
+    topic_manager_tx
+        .send(TopicManagerCommands::GetPartitionReadInfo {
+            topic_partition: fetch_request.topic_partition.clone(),
+            reply_tx,
+        })
+        .await
+        .unwrap();
+    match reply_rx.await.unwrap() {
+        Ok(read_info) => {
+            if let (Some(group_id), Some(member_id)) =
+                (&fetch_request.group_id, &fetch_request.member_id)
+            {
+                group_coordinator_tx
+                    .send(GroupCoordinatorCommands::RecordPoll {
+                        group_id: group_id.clone(),
+                        member_id: member_id.clone(),
+                    })
+                    .await
+                    .unwrap();
+            }
+            let committed_offset = match (&fetch_request.offset, &fetch_request.group_id) {
+                (None, Some(group_id)) => {
+                    let (reply_tx, reply_rx) = oneshot::channel();
+                    group_coordinator_tx
+                        .send(GroupCoordinatorCommands::GetCommittedOffset {
+                            group_id: group_id.clone(),
+                            topic_partition: fetch_request.topic_partition.clone(),
+                            reply_tx,
+                        })
+                        .await
+                        .unwrap();
+                    reply_rx.await.unwrap()
+                }
+                _ => None,
+            };
+            match read_info.fetch_offset(&fetch_request, committed_offset) {
+                Ok(base_offset) => {
+                    let mut batches = read_records(
+                        &read_info.segment_file_path,
+                        base_offset,
+                        fetch_request.max_records as usize,
+                    )
+                    .await;
+                    // followers copy the records the other in-sync replicas did not copy yet
+                    if fetch_request.replica_id.is_none() {
+                        batches = committed_batches(batches, read_info.high_watermark);
+                    }
+                    // followers copy scheduled records right away
+                    if read_info.scheduled_delivery && fetch_request.replica_id.is_none() {
+                        batches = deliverable_batches(batches, base_offset, now_millis());
+                    }
+                    BrokerResponse::Records {
+                        topic_partition: fetch_request.topic_partition,
+                        base_offset,
+                        batches,
+                        log_end_offset: read_info.log_end_offset,
+                        high_watermark: read_info.high_watermark,
+                        leader_epoch: read_info.leader_epoch,
+                    }
+                }
+                Err(error_response) => error_response,
+            }
+        }
+        Err(_) => BrokerResponse::UnknownTopicPartition {
+            topic_partition: fetch_request.topic_partition,
+        },
+    }
+}
+
+async fn handle_describe_topic_request(
+    topic_name: String,
+    topic_manager_tx: mpsc::Sender<TopicManagerCommands>,
+) -> BrokerResponse {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    topic_manager_tx
+        .send(TopicManagerCommands::GetTopicInfo {
+            topic_name: topic_name.clone(),
+            reply_tx,
+        })
+        .await
+        .unwrap();
+    match reply_rx.await.unwrap() {
+        Ok(topic) => BrokerResponse::TopicDescription { topic },
+        Err(_) => BrokerResponse::TopicNotFound { topic_name },
+    }
+}
+
+async fn handle_metadata_request(
+    topic_names: Option<Vec<String>>,
+    metadata_quorum_tx: mpsc::Sender<MetadataQuorumCommands>,
+    topic_manager_tx: mpsc::Sender<TopicManagerCommands>,
+) -> BrokerResponse {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    metadata_quorum_tx
+        .send(MetadataQuorumCommands::GetBrokers { reply_tx })
+        .await
+        .unwrap();
+    let brokers = reply_rx.await.unwrap();
+    let (reply_tx, reply_rx) = oneshot::channel();
+    metadata_quorum_tx
+        .send(MetadataQuorumCommands::GetLeaderId { reply_tx })
+        .await
+        .unwrap();
+    let controller_id = reply_rx.await.unwrap();
+    let (reply_tx, reply_rx) = oneshot::channel();
+    topic_manager_tx
+        .send(TopicManagerCommands::GetTopicMetadata {
+            topic_names,
+            reply_tx,
+        })
+        .await
+        .unwrap();
+    BrokerResponse::Metadata {
+        brokers,
+        controller_id,
+        topics: reply_rx.await.unwrap(),
+    }
+}
+
+/// Hands the batch to the partition writer and returns the answer, ready once the batch was
+/// handled as `acks` requires. `Acks::None` is not answered. Awaiting this before reading the
+/// next request of the connection appends pipelined batches of a partition in request order,
+/// while their answers are awaited concurrently. Writes are turned down while the log
+/// directory is short of space.
+async fn handle_write_to_topic_request(
+    topic_partition: TopicPartition,
+    acks: Acks,
+    leader_epoch: Option<u32>,
+    manager_channels: &ManagerChannels,
+    body: Bytes,
+) -> impl Future<Output = Option<BrokerResponse>> {
+    let deadline = Instant::now() + PRODUCE_TIMEOUT;
+    // oversized batches are turned down before they are decoded or queued
+    let partition_tx = if manager_channels.disk_usage.is_full() {
+        Err(ProduceError::StorageFull)
+    } else {
+        manager_channels
+            .partition_routes
+            .partition_tx(&topic_partition, leader_epoch, body.len())
+    };
+    let appending = match partition_tx {
+        Ok(partition_manager_tx) => {
+            decode_and_append(
+                &topic_partition,
+                &partition_manager_tx,
+                acks,
+                deadline,
+                &body,
+            )
+            .await
+        }
+        Err(error) => Err(BrokerResponse::ProduceFailed { error }),
+    };
+    async move {
+        if acks == Acks::None {
+            return None;
+        }
+        let base_offset_rx = match appending {
+            Ok(base_offset_rx) => base_offset_rx,
+            Err(response) => return Some(response),
+        };
+        let response = match tokio::time::timeout_at(deadline, base_offset_rx).await {
+            Ok(Ok(Ok(appended_batch))) => BrokerResponse::MessageBatchAppended(ProduceResponse {
+                topic_partition,
+                base_offset: appended_batch.base_offset,
+                log_append_time_millis: appended_batch.log_append_time,
+                acks,
+            }),
+            Ok(Ok(Err(error))) => BrokerResponse::ProduceFailed { error },
+            Ok(Err(_)) => BrokerResponse::ProduceFailed {
+                error: ProduceError::InvalidBatch(format!(
+                    "Could not append to {:?}",
+                    topic_partition
+                )),
+            },
+            Err(_) => BrokerResponse::ProduceFailed {
+                error: ProduceError::TimedOut,
+            },
+        };
+        Some(response)
+    }
+}
+
+/// Decodes the batch and hands it to the partition writer, the receiver of its base offset once
+/// it was queued.
+async fn decode_and_append(
+    topic_partition: &TopicPartition,
+    partition_manager_tx: &PartitionSender,
+    acks: Acks,
+    deadline: Instant,
+    body: &Bytes,
+) -> Result<oneshot::Receiver<Result<AppendedBatch, ProduceError>>, BrokerResponse> {
+    let decoded_batch = RecordBatchDecoder {}.decode(&mut BytesMut::from(&body[..]));
+    match decoded_batch {
+        // corrupted on the way, appending it would serve the corrupted records to consumers
+        Ok(Some(batch)) if batch.crc_matches() == Some(false) => {
+            tracing::error!("Batch for {:?} does not match its CRC", topic_partition);
+            Err(BrokerResponse::MessageBatchWriteFailure {
+                error: "Batch CRC does not match its records".to_string(),
+            })
+        }
+        Ok(Some(batch)) => tokio::time::timeout_at(
+            deadline,
+            append_to_partition(partition_manager_tx, batch, acks),
+        )
+        .await
+        .unwrap_or(Err(ProduceError::TimedOut))
+        .map_err(|error| BrokerResponse::ProduceFailed { error }),
+        Ok(None) => {
+            tracing::info!("Not enough data to decode a batch");
+            Err(BrokerResponse::MessageBatchWriteFailure {
+                error: "Not enough data to decode a batch".to_string(),
+            })
+        }
+        Err(e) => {
+            tracing::error!("Error decoding batch: {:?}", e);
+            Err(BrokerResponse::MessageBatchWriteFailure {
+                error: format!("Error decoding batch: {:?}", e),
+            })
+        }
+    }
+}
+
+/// Returns the receiver of where the partition writer appended the batch, sent once it handled the
+/// records as `acks` requires. Fails as the topic's overflow policy says when the partition's
+/// queue is full.
+async fn append_to_partition(
+    partition_manager_tx: &PartitionSender,
+    batch: RecordBatch,
+    acks: Acks,
+) -> Result<oneshot::Receiver<Result<AppendedBatch, ProduceError>>, ProduceError> {
+    let (base_offset_tx, base_offset_rx) = oneshot::channel();
+    partition_manager_tx
+        .send(PartitionAppend {
+            batch,
+            acks,
+            base_offset_tx: Some(base_offset_tx),
+        })
+        .await?;
+    Ok(base_offset_rx)
+}
+
+/// Creates the topic through the metadata log, so every broker creates it, and answers with the
+/// topic once this broker created it.
+async fn handle_create_topic_request(
+    topic: Topic,
+    metadata_quorum_tx: mpsc::Sender<MetadataQuorumCommands>,
+    topic_manager_tx: mpsc::Sender<TopicManagerCommands>,
+) -> BrokerResponse {
+    tracing::info!("Received a CreateTopic command: {:?}", topic);
+    let topic_name = topic.name.clone();
+    let (reply_tx, reply_rx) = oneshot::channel();
+    metadata_quorum_tx
+        .send(MetadataQuorumCommands::CreateTopic { topic, reply_tx })
+        .await
+        .unwrap();
+    match tokio::time::timeout(CREATE_TOPIC_TIMEOUT, reply_rx).await {
+        Ok(Ok(Ok(()))) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            topic_manager_tx
+                .send(TopicManagerCommands::GetTopicInfo {
+                    topic_name: topic_name.clone(),
+                    reply_tx,
+                })
+                .await
+                .unwrap();
+            match reply_rx.await.unwrap() {
+                Ok(topic) => BrokerResponse::TopicDescription { topic },
+                Err(_) => BrokerResponse::TopicNotCreated { topic_name },
+            }
+        }
+        result => {
+            tracing::error!("Could not create topic {}: {:?}", topic_name, result);
+            BrokerResponse::TopicNotCreated { topic_name }
+        }
+    }
+}
+
+/// Deletes the topic through the metadata log, so every broker deletes it, and answers once this
+/// broker deleted it.
+async fn handle_delete_topic_request(
+    topic_name: String,
+    metadata_quorum_tx: mpsc::Sender<MetadataQuorumCommands>,
+    topic_manager_tx: mpsc::Sender<TopicManagerCommands>,
+) -> BrokerResponse {
+    tracing::info!("Received a DeleteTopic command: {}", topic_name);
+    let (reply_tx, reply_rx) = oneshot::channel();
+    topic_manager_tx
+        .send(TopicManagerCommands::GetTopicInfo {
+            topic_name: topic_name.clone(),
+            reply_tx,
+        })
+        .await
+        .unwrap();
+    if reply_rx.await.unwrap().is_err() {
+        return BrokerResponse::TopicNotFound { topic_name };
+    }
+    let (reply_tx, reply_rx) = oneshot::channel();
+    metadata_quorum_tx
+        .send(MetadataQuorumCommands::Propose {
+            record: MetadataRecord::TopicDeleted {
+                topic_name: topic_name.clone(),
+            },
+            reply_tx,
+        })
+        .await
+        .unwrap();
+    match tokio::time::timeout(DELETE_TOPIC_TIMEOUT, reply_rx).await {
+        Ok(Ok(Ok(()))) => BrokerResponse::TopicDeleted { topic_name },
+        result => {
+            tracing::error!("Could not delete topic {}: {:?}", topic_name, result);
+            BrokerResponse::TopicNotDeleted { topic_name }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::Ipv4Addr;
+
+    use common::codecs::protocol::{Request, ResponseCodec};
+    use common::errors::WalrsError;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use test_log::test;
+    use tokio::io::{AsyncRead, AsyncWrite};
+
+    use crate::connections::ConnectionSettings;
+    use crate::quotas::QuotaSettings;
+
+    use super::*;
+
+    fn manager_channels() -> ManagerChannels {
+        ManagerChannels {
+            metadata_quorum_tx: mpsc::channel(1).0,
+            controller_tx: mpsc::channel(1).0,
+            topic_manager_tx: mpsc::channel(1).0,
+            group_coordinator_tx: mpsc::channel(1).0,
+            partition_routes: PartitionRoutes::new(0),
+            fetch_sessions: FetchSessions::new(1),
+            disk_usage: DiskUsage::default(),
+            replication_throttles: Arc::new(ReplicationThrottles::new(None, None, &Metrics::new())),
+        }
+    }
+
+    fn connection_permit() -> ConnectionPermit {
+        let tracker = ConnectionTracker::new(
+            ConnectionSettings::default(),
+            &Metrics::new(),
+            CancellationToken::new(),
+        );
+        Arc::new(tracker).open(Ipv4Addr::LOCALHOST.into()).unwrap()
+    }
+
+    fn connection_context(security: ListenerSecurity) -> ConnectionContext {
+        ConnectionContext {
+            security,
+            connection_buffers: BufferPool::new("connections", 1024, 1, &Metrics::new()),
+            clock: BrokerClock::new(),
+            producer_id_allocator: ProducerIdAllocator::new(0),
+            metrics: Metrics::new(),
+            client_quotas: Arc::new(ClientQuotas::new(QuotaSettings::default(), &Metrics::new())),
+        }
+    }
+
+    /// Sends a ping and reads the response, `None` when the broker closed the connection.
+    async fn ping(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) -> Option<BrokerResponse> {
+        let mut response_codec = ResponseCodec::default();
+        let mut buffer = BytesMut::new();
+        let request = Request::new(1, "test", TopicCommand::Ping, Bytes::new());
+        response_codec.encode(request, &mut buffer).unwrap();
+        stream.write_all(&buffer).await.ok()?;
+        buffer.clear();
+        loop {
+            if let Some(response) = response_codec.decode(&mut buffer).unwrap() {
+                return Some(response.response);
+            }
+            if stream.read_buf(&mut buffer).await.unwrap_or(0) == 0 {
+                return None;
+            }
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn test_tls_connection_should_require_client_certificates() {
+        let temp_dir = tempdir::TempDir::new("tls_").unwrap();
+        let path = |file: &str| temp_dir.path().join(file).to_str().unwrap().to_string();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        fs::write(path("ca.pem"), ca.pem()).unwrap();
+        for name in ["broker", "client"] {
+            let key = KeyPair::generate().unwrap();
+            let certificate = CertificateParams::new(vec!["localhost".to_string()])
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+            fs::write(path(&format!("{}.pem", name)), certificate.pem()).unwrap();
+            fs::write(path(&format!("{}.key", name)), key.serialize_pem()).unwrap();
+        }
+        let tls_acceptor = tls_acceptor(&TlsSettings {
+            certificate_path: path("broker.pem"),
+            private_key_path: path("broker.key"),
+            ca_path: Some(path("ca.pem")),
+            client_auth: true,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("tls://localhost:{}", listener.local_addr().unwrap().port());
+
+        let client_certificate = (path("client.pem"), path("client.key"));
+        let with_certificate = common::tls::client_config(
+            &path("ca.pem"),
+            Some((&client_certificate.0, &client_certificate.1)),
+        )
+        .unwrap();
+        let without_certificate = common::tls::client_config(&path("ca.pem"), None).unwrap();
+        for (client_config, answered) in [(with_certificate, true), (without_certificate, false)] {
+            let address = address.clone();
+            let client = tokio::spawn(async move {
+                let mut stream = common::tls::connect(&address, Some(&client_config))
+                    .await
+                    .unwrap();
+                ping(&mut stream).await
+            });
+            let (socket, _) = listener.accept().await.unwrap();
+            handle_client_connection(
+                socket,
+                connection_permit(),
+                connection_context(ListenerSecurity {
+                    tls_acceptor: Some(tls_acceptor.clone()),
+                    ..ListenerSecurity::default()
+                }),
+                manager_channels(),
+            )
+            .await;
+            let response = client.await.unwrap();
+            assert_eq!(
+                matches!(response, Some(BrokerResponse::Pong { .. })),
+                answered
+            );
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn test_connection_should_answer_pipelined_requests_as_they_complete() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (topic_manager_tx, mut topic_manager_rx) = mpsc::channel(10);
+        handle_client_connection(
+            socket,
+            connection_permit(),
+            connection_context(ListenerSecurity::default()),
+            ManagerChannels {
+                topic_manager_tx,
+                ..manager_channels()
+            },
+        )
+        .await;
+
+        let mut response_codec = ResponseCodec::default();
+        let mut requests = BytesMut::new();
+        let describe_topic = TopicCommand::DescribeTopic {
+            topic_name: "t1".to_string(),
+        };
+        for (correlation_id, command) in [(1, describe_topic), (2, TopicCommand::Ping)] {
+            response_codec
+                .encode(
+                    Request::new(correlation_id, "test", command, Bytes::new()),
+                    &mut requests,
+                )
+                .unwrap();
+        }
+        client.write_all(&requests).await.unwrap();
+
+        let mut received = BytesMut::new();
+        let mut read_response = async || loop {
+            if let Some(response) = response_codec.decode(&mut received).unwrap() {
+                break response;
+            }
+            assert!(client.read_buf(&mut received).await.unwrap() > 0);
+        };
+        // the ping is answered while the topic manager has not answered the describe request
+        let pong = read_response().await;
+        assert_eq!(pong.correlation_id, 2);
+        assert!(matches!(pong.response, BrokerResponse::Pong { .. }));
+
+        let Some(TopicManagerCommands::GetTopicInfo { reply_tx, .. }) =
+            topic_manager_rx.recv().await
+        else {
+            panic!("expected the describe request to reach the topic manager");
+        };
+        reply_tx
+            .send(Err(WalrsError::UnknownTopic("t1".to_string())))
+            .unwrap();
+        assert_eq!(
+            read_response().await,
+            Response {
+                correlation_id: 1,
+                throttle_time_ms: 0,
+                response: BrokerResponse::TopicNotFound {
+                    topic_name: "t1".to_string()
+                },
+            }
+        );
+    }
+}
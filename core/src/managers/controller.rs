@@ -0,0 +1,1417 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::models::{
+    BrokerResponse, ClusterHealth, LeaderAndIsr, MetadataRecord, PartitionReassignment,
+    ReassignmentStatus, TopicCommand, TopicPartition,
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::clock::Clock;
+use crate::cluster::{send_request, BrokerId, ClusterSettings};
+use crate::managers::metadata_quorum::MetadataQuorumCommands;
+use crate::managers::topics_manager::TopicManagerCommands;
+use crate::membership::BrokerLiveness;
+use crate::metrics::Metrics;
+use crate::models::PartitionState;
+
+pub enum ControllerCommands {
+    /// Answered with `BrokerResponse::NotQuorumLeader` when this broker is not the active
+    /// controller.
+    BrokerHeartbeat {
+        broker_id: BrokerId,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Answered once the reassignments are in the metadata log, the replicas are moved in the
+    /// background.
+    ReassignPartitions {
+        reassignments: Vec<PartitionReassignment>,
+        throttle_rate: Option<u64>,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    DescribeReassignments {
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Passed on to the active controller, which knows which preferred replicas are alive.
+    ElectPreferredLeaders {
+        topic_partitions: Option<Vec<TopicPartition>>,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Passed on to the active controller, answered once the leaders of the broker's partitions
+    /// are in the metadata log.
+    ControlledShutdown {
+        broker_id: BrokerId,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Passed on to the active controller, which watches the brokers and partitions.
+    DescribeCluster {
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Passed on to the active controller, answered once the reassignments off the broker, or
+    /// its unregistration, are in the metadata log.
+    DecommissionBroker {
+        broker_id: BrokerId,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+}
+
+/// Watches the brokers of the cluster and elects new leaders for the partitions of brokers which
+/// died. The leader of the metadata quorum is the active controller, every broker registers in
+/// the metadata log and sends heartbeats to it. Brokers without a heartbeat within the session
+/// timeout are dead: the controller removes them from the ISRs and elects new leaders for the
+/// partitions they led, and hands the leadership back to the preferred replicas once they are in
+/// sync again so leaders stay spread over the brokers. Partitions without a live replica in their
+/// ISR stay offline unless unclean leader election is enabled. Reassigned partitions get their new
+/// replicas once those caught up with the leader. Brokers which shut down hand the leadership of
+/// their partitions over before they stop, decommissioned brokers have their replicas moved to the
+/// other brokers before they leave the cluster. Leaders, ISRs and replicas are appended to the
+/// metadata log, so every broker starts leading or following the partitions once it applied them,
+/// including brokers which come back.
+pub struct Controller {
+    cluster_settings: ClusterSettings,
+    topic_manager_tx: Sender<TopicManagerCommands>,
+    metadata_quorum_tx: Sender<MetadataQuorumCommands>,
+    broker_liveness: BrokerLiveness,
+    /// Leader of the metadata quorum at the last check.
+    controller_id: Option<BrokerId>,
+    /// Partitions without a live leader and no live replica in their ISR to elect.
+    offline_partitions: HashSet<TopicPartition>,
+    /// Gauge of `offline_partitions` while this broker is the active controller.
+    offline_partitions_count: Arc<AtomicU64>,
+    /// Partitions with a live leader whose replicas are not all in sync, at the last check.
+    under_replicated_partitions: Vec<TopicPartition>,
+    /// Gauge of `under_replicated_partitions` while this broker is the active controller.
+    under_replicated_partitions_count: Arc<AtomicU64>,
+    /// Gauge of the dead brokers while this broker is the active controller.
+    unreachable_brokers_count: Arc<AtomicU64>,
+    isr_churn: IsrChurn,
+    /// Gauge of the ISR changes within the last minute while this broker is the active
+    /// controller.
+    isr_changes_last_minute: Arc<AtomicU64>,
+    /// Counter of the leaders elected outside of the ISR.
+    unclean_leader_elections: Arc<AtomicU64>,
+    /// Brokers which asked for a controlled shutdown, they are not elected until they stopped.
+    shutting_down_brokers: BTreeSet<BrokerId>,
+    /// Brokers being decommissioned, they get no replicas of other decommissioned brokers.
+    decommissioning_brokers: BTreeSet<BrokerId>,
+    /// Time the session timeouts of the brokers are measured with.
+    clock: Arc<dyn Clock>,
+    cancellation_token: CancellationToken,
+}
+
+impl Controller {
+    pub fn new(
+        cluster_settings: ClusterSettings,
+        topic_manager_tx: Sender<TopicManagerCommands>,
+        metadata_quorum_tx: Sender<MetadataQuorumCommands>,
+        metrics: &Metrics,
+        clock: Arc<dyn Clock>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        Controller {
+            broker_liveness: BrokerLiveness::new(cluster_settings.broker_session_timeout),
+            cluster_settings,
+            topic_manager_tx,
+            metadata_quorum_tx,
+            controller_id: None,
+            offline_partitions: HashSet::new(),
+            offline_partitions_count: metrics.register("offline_partitions_count"),
+            under_replicated_partitions: vec![],
+            under_replicated_partitions_count: metrics
+                .register("under_replicated_partitions_count"),
+            unreachable_brokers_count: metrics.register("unreachable_brokers_count"),
+            isr_churn: IsrChurn::default(),
+            isr_changes_last_minute: metrics.register("isr_changes_last_minute"),
+            unclean_leader_elections: metrics.register("unclean_leader_elections_total"),
+            shutting_down_brokers: BTreeSet::new(),
+            decommissioning_brokers: BTreeSet::new(),
+            clock,
+            cancellation_token,
+        }
+    }
+
+    pub async fn start_controller(mut self, mut commands_rx: Receiver<ControllerCommands>) {
+        tracing::info!("Controller started");
+        let check_interval =
+            (self.cluster_settings.broker_session_timeout / 3).max(Duration::from_millis(1));
+        let mut check_interval_timer = tokio::time::interval(check_interval);
+        let mut leader_imbalance_check_interval_timer = tokio::time::interval_at(
+            tokio::time::Instant::now() + self.cluster_settings.leader_imbalance_check_interval,
+            self.cluster_settings
+                .leader_imbalance_check_interval
+                .max(Duration::from_millis(1)),
+        );
+        let cancellation_token = self.cancellation_token.clone();
+        loop {
+            tokio::select! {
+                Some(command) = commands_rx.recv() => {
+                    match command {
+                        ControllerCommands::BrokerHeartbeat { broker_id, reply_tx } => {
+                            let response = if self.is_active() {
+                                self.broker_liveness.record_heartbeat(broker_id, self.clock.now());
+                                BrokerResponse::BrokerHeartbeatAcknowledged
+                            } else {
+                                BrokerResponse::NotQuorumLeader {
+                                    leader_id: self.controller_id,
+                                }
+                            };
+                            reply_tx.send(response).unwrap();
+                        }
+                        ControllerCommands::ReassignPartitions {
+                            reassignments,
+                            throttle_rate,
+                            reply_tx,
+                        } => {
+                            self.reassign_partitions(reassignments, throttle_rate, reply_tx)
+                                .await;
+                        }
+                        ControllerCommands::DescribeReassignments { reply_tx } => {
+                            let reassignments = self
+                                .partition_states()
+                                .await
+                                .into_iter()
+                                .filter(|partition_state| {
+                                    !partition_state.adding_replicas.is_empty()
+                                        || !partition_state.removing_replicas.is_empty()
+                                })
+                                .map(|partition_state| ReassignmentStatus {
+                                    topic_partition: partition_state.topic_partition,
+                                    replicas: partition_state.replicas,
+                                    adding_replicas: partition_state.adding_replicas,
+                                    removing_replicas: partition_state.removing_replicas,
+                                    isr: partition_state.leader_and_isr.isr,
+                                })
+                                .collect();
+                            reply_tx
+                                .send(BrokerResponse::Reassignments { reassignments })
+                                .unwrap();
+                        }
+                        ControllerCommands::ElectPreferredLeaders { topic_partitions, reply_tx } => {
+                            if self.is_active() {
+                                let response = self.elect_preferred_leaders(topic_partitions).await;
+                                reply_tx.send(response).unwrap();
+                            } else {
+                                let command = TopicCommand::ElectPreferredLeaders { topic_partitions };
+                                self.forward_to_active_controller(command, reply_tx);
+                            }
+                        }
+                        ControllerCommands::ControlledShutdown { broker_id, reply_tx } => {
+                            if self.is_active() {
+                                let response = self.controlled_shutdown(broker_id).await;
+                                // the broker may have given up waiting
+                                let _ = reply_tx.send(response);
+                            } else {
+                                let command = TopicCommand::ControlledShutdown { broker_id };
+                                self.forward_to_active_controller(command, reply_tx);
+                            }
+                        }
+                        ControllerCommands::DescribeCluster { reply_tx } => {
+                            if self.is_active() {
+                                reply_tx.send(self.describe_cluster()).unwrap();
+                            } else {
+                                self.forward_to_active_controller(TopicCommand::DescribeCluster, reply_tx);
+                            }
+                        }
+                        ControllerCommands::DecommissionBroker { broker_id, reply_tx } => {
+                            if self.is_active() {
+                                self.decommission_broker(broker_id, reply_tx).await;
+                            } else {
+                                let command = TopicCommand::DecommissionBroker { broker_id };
+                                self.forward_to_active_controller(command, reply_tx);
+                            }
+                        }
+                    }
+                }
+                _ = check_interval_timer.tick() => {
+                    self.check_brokers().await;
+                }
+                _ = leader_imbalance_check_interval_timer.tick() => {
+                    if self.is_active() && self.cluster_settings.auto_leader_rebalance_enable {
+                        self.rebalance_leaders().await;
+                    }
+                }
+                _ = cancellation_token.cancelled() => {
+                    tracing::info!("Cancellation token received for controller.");
+                    break;
+                }
+            }
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.controller_id == Some(self.cluster_settings.broker_id)
+    }
+
+    /// Marks brokers which did not send heartbeats within the session timeout as dead, updates
+    /// the leaders and ISRs of their partitions and completes reassignments whose new replicas
+    /// caught up, when this broker is the active controller.
+    async fn check_brokers(&mut self) {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.metadata_quorum_tx
+            .send(MetadataQuorumCommands::GetLeaderId { reply_tx })
+            .await
+            .unwrap();
+        let was_active = self.is_active();
+        self.controller_id = reply_rx.await.unwrap();
+        if !self.is_active() {
+            return;
+        }
+        if !was_active {
+            tracing::info!(
+                "Broker {} is the active controller",
+                self.cluster_settings.broker_id
+            );
+            // heartbeats went to the previous controller, every broker gets a session timeout
+            self.broker_liveness.reset();
+        }
+        let now = self.clock.now();
+        for broker_id in self.broker_ids().await {
+            if broker_id != self.cluster_settings.broker_id {
+                self.broker_liveness.watch(broker_id, now);
+            }
+        }
+        self.broker_liveness.check(now);
+        // brokers which shut down are elected again once they are back after they stopped
+        let broker_liveness = &self.broker_liveness;
+        let own_broker_id = self.cluster_settings.broker_id;
+        self.shutting_down_brokers.retain(|broker_id| {
+            *broker_id == own_broker_id || broker_liveness.is_alive(*broker_id)
+        });
+        let has_failures =
+            self.broker_liveness.has_dead_brokers() || !self.offline_partitions.is_empty();
+        let partition_states = self.partition_states().await;
+        self.update_health(&partition_states, now);
+        for partition_state in partition_states {
+            let leader_and_isr = &partition_state.leader_and_isr;
+            let is_reassigned = !partition_state.adding_replicas.is_empty()
+                || !partition_state.removing_replicas.is_empty();
+            if is_reassigned && self.is_alive(leader_and_isr.leader_id) {
+                let completed =
+                    complete_reassignment(&partition_state, |broker_id| self.is_alive(broker_id));
+                if let Some(completed) = completed {
+                    tracing::info!(
+                        "Reassignment of {:?} to {:?} completed",
+                        completed.topic_partition,
+                        completed.replicas
+                    );
+                    self.propose_replicas(completed).await;
+                    continue;
+                }
+            }
+            if !has_failures {
+                continue;
+            }
+            if self.is_alive(leader_and_isr.leader_id) {
+                self.offline_partitions
+                    .remove(&partition_state.topic_partition);
+                let isr: Vec<BrokerId> = leader_and_isr
+                    .isr
+                    .iter()
+                    .copied()
+                    .filter(|broker_id| self.is_alive(*broker_id))
+                    .collect();
+                if isr != leader_and_isr.isr {
+                    tracing::info!(
+                        "Removing dead brokers from the ISR of {:?}, shrinking it to {:?}",
+                        partition_state.topic_partition,
+                        isr
+                    );
+                    let leader_and_isr = LeaderAndIsr {
+                        isr,
+                        ..leader_and_isr.clone()
+                    };
+                    self.propose_leader_and_isr(partition_state.topic_partition, leader_and_isr)
+                        .await;
+                }
+                continue;
+            }
+            match elect_leader(&partition_state, |broker_id| self.is_electable(broker_id)) {
+                Some(leader_and_isr) => {
+                    tracing::info!(
+                        "Elected broker {} to lead {:?} in epoch {}",
+                        leader_and_isr.leader_id,
+                        partition_state.topic_partition,
+                        leader_and_isr.leader_epoch
+                    );
+                    self.offline_partitions
+                        .remove(&partition_state.topic_partition);
+                    self.propose_leader_and_isr(partition_state.topic_partition, leader_and_isr)
+                        .await;
+                }
+                None => self.handle_offline_partition(partition_state).await,
+            }
+        }
+        self.offline_partitions_count
+            .store(self.offline_partitions.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Updates the under-replicated partitions, the ISR churn and their gauges. Offline partitions
+    /// are left to the elections.
+    fn update_health(&mut self, partition_states: &[PartitionState], now: Instant) {
+        self.under_replicated_partitions = partition_states
+            .iter()
+            .filter(|partition_state| {
+                self.is_alive(partition_state.leader_and_isr.leader_id)
+                    && is_under_replicated(partition_state)
+            })
+            .map(|partition_state| partition_state.topic_partition.clone())
+            .collect();
+        self.under_replicated_partitions_count.store(
+            self.under_replicated_partitions.len() as u64,
+            Ordering::Relaxed,
+        );
+        self.unreachable_brokers_count.store(
+            self.broker_liveness.dead_brokers().len() as u64,
+            Ordering::Relaxed,
+        );
+        self.isr_churn.record(partition_states, now);
+        self.isr_changes_last_minute
+            .store(self.isr_churn.recent_changes(now) as u64, Ordering::Relaxed);
+    }
+
+    fn describe_cluster(&self) -> BrokerResponse {
+        let mut offline_partitions: Vec<TopicPartition> =
+            self.offline_partitions.iter().cloned().collect();
+        offline_partitions.sort();
+        BrokerResponse::ClusterHealth(ClusterHealth {
+            under_replicated_partitions: self.under_replicated_partitions.clone(),
+            offline_partitions,
+            unreachable_brokers: self.broker_liveness.dead_brokers(),
+            isr_changes_last_minute: self.isr_churn.recent_changes(self.clock.now()) as u32,
+        })
+    }
+
+    /// Elects a live replica outside of the ISR when unclean leader election is enabled, giving
+    /// up the records only the ISR has for availability, otherwise keeps the partition offline
+    /// until a replica of its ISR comes back.
+    async fn handle_offline_partition(&mut self, partition_state: PartitionState) {
+        let unclean_leader_and_isr = if self.cluster_settings.unclean_leader_election_enable {
+            elect_unclean_leader(&partition_state, |broker_id| self.is_alive(broker_id))
+        } else {
+            None
+        };
+        let Some(leader_and_isr) = unclean_leader_and_isr else {
+            if self
+                .offline_partitions
+                .insert(partition_state.topic_partition.clone())
+            {
+                tracing::error!(
+                    "{:?} is offline, no replica of its ISR {:?} is alive and unclean leader \
+                     election is {}",
+                    partition_state.topic_partition,
+                    partition_state.leader_and_isr.isr,
+                    if self.cluster_settings.unclean_leader_election_enable {
+                        "enabled but no other replica is alive either"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            return;
+        };
+        tracing::warn!(
+            "Elected out of sync broker {} to lead {:?} in epoch {}, records only its old ISR {:?} \
+             had are lost",
+            leader_and_isr.leader_id,
+            partition_state.topic_partition,
+            leader_and_isr.leader_epoch,
+            partition_state.leader_and_isr.isr
+        );
+        if self
+            .propose_leader_and_isr(partition_state.topic_partition.clone(), leader_and_isr)
+            .await
+        {
+            self.unclean_leader_elections
+                .fetch_add(1, Ordering::Relaxed);
+            self.offline_partitions
+                .remove(&partition_state.topic_partition);
+        }
+    }
+
+    /// Elects the preferred replicas of the partitions, or of every partition, which are alive
+    /// and in sync.
+    async fn elect_preferred_leaders(
+        &self,
+        topic_partitions: Option<Vec<TopicPartition>>,
+    ) -> BrokerResponse {
+        let mut partition_states: HashMap<TopicPartition, PartitionState> = self
+            .partition_states()
+            .await
+            .into_iter()
+            .map(|partition_state| (partition_state.topic_partition.clone(), partition_state))
+            .collect();
+        let topic_partitions = topic_partitions.unwrap_or_else(|| {
+            let mut topic_partitions: Vec<TopicPartition> =
+                partition_states.keys().cloned().collect();
+            topic_partitions.sort();
+            topic_partitions
+        });
+        let mut elected = vec![];
+        let mut not_elected = vec![];
+        for topic_partition in topic_partitions {
+            let Some(partition_state) = partition_states.remove(&topic_partition) else {
+                not_elected.push((topic_partition, "unknown partition".to_string()));
+                continue;
+            };
+            match elect_preferred_leader(&partition_state, |broker_id| self.is_electable(broker_id))
+            {
+                Ok(None) => {}
+                Ok(Some(leader_and_isr)) => {
+                    if self
+                        .propose_leader_and_isr(topic_partition.clone(), leader_and_isr)
+                        .await
+                    {
+                        elected.push(topic_partition);
+                    } else {
+                        not_elected.push((topic_partition, "metadata log unavailable".to_string()));
+                    }
+                }
+                Err(error) => not_elected.push((topic_partition, error)),
+            }
+        }
+        BrokerResponse::PreferredLeadersElected {
+            elected,
+            not_elected,
+        }
+    }
+
+    /// Moves the leadership of the partitions of a broker which shuts down to the first other live
+    /// replica of their ISR, in an ISR without the broker so writes do not wait for it. Answers
+    /// with the partitions no other replica of the ISR can lead yet. Partitions without other
+    /// replicas stay with the broker and are unavailable until it is back.
+    async fn controlled_shutdown(&mut self, broker_id: BrokerId) -> BrokerResponse {
+        if self.shutting_down_brokers.insert(broker_id) {
+            tracing::info!("Broker {} is shutting down", broker_id);
+        }
+        let mut remaining_partitions = vec![];
+        for partition_state in self.partition_states().await {
+            if partition_state.leader_and_isr.leader_id != broker_id
+                || partition_state.replicas.len() < 2
+            {
+                continue;
+            }
+            let topic_partition = partition_state.topic_partition.clone();
+            let moved =
+                match elect_leader(&partition_state, |replica_id| self.is_electable(replica_id)) {
+                    Some(leader_and_isr) => {
+                        tracing::info!(
+                            "Moving the leadership of {:?} to broker {} as broker {} shuts down",
+                            topic_partition,
+                            leader_and_isr.leader_id,
+                            broker_id
+                        );
+                        self.propose_leader_and_isr(topic_partition.clone(), leader_and_isr)
+                            .await
+                    }
+                    None => false,
+                };
+            if !moved {
+                remaining_partitions.push(topic_partition);
+            }
+        }
+        BrokerResponse::ControlledShutdownCompleted {
+            remaining_partitions,
+        }
+    }
+
+    /// Moves the replicas of a decommissioned broker to the live brokers which hold the fewest
+    /// replicas, and unregisters it once it holds none. Partitions being reassigned already are
+    /// moved by a later request, once their reassignment completed. The records are proposed in
+    /// the background like the ones of `reassign_partitions`.
+    async fn decommission_broker(
+        &mut self,
+        broker_id: BrokerId,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    ) {
+        let partition_states = self.partition_states().await;
+        let registered_broker_ids = self.registered_broker_ids().await;
+        if self.decommissioning_brokers.insert(broker_id) {
+            tracing::info!("Decommissioning broker {}", broker_id);
+        }
+        let broker_ids = self.broker_ids().await;
+        let target_broker_ids: BTreeSet<BrokerId> = broker_ids
+            .iter()
+            .copied()
+            .filter(|target_id| {
+                !self.decommissioning_brokers.contains(target_id) && self.is_alive(*target_id)
+            })
+            .collect();
+        let mut drain = drain_broker(&partition_states, broker_id, &target_broker_ids);
+        let mut records = vec![];
+        for reassignment in std::mem::take(&mut drain.reassignments) {
+            let partition_state = partition_states
+                .iter()
+                .find(|partition_state| {
+                    partition_state.topic_partition == reassignment.topic_partition
+                })
+                .cloned();
+            match start_reassignment(partition_state, &reassignment, &broker_ids) {
+                Ok(partition_state) => records.push(replicas_changed_record(partition_state, None)),
+                Err(error) => {
+                    drain
+                        .moving_partitions
+                        .retain(|topic_partition| *topic_partition != reassignment.topic_partition);
+                    drain
+                        .failed_partitions
+                        .push((reassignment.topic_partition, error));
+                }
+            }
+        }
+        let drained = drain.moving_partitions.is_empty() && drain.failed_partitions.is_empty();
+        let unregistered = drained && !registered_broker_ids.contains(&broker_id);
+        if drained && !unregistered {
+            records.push(MetadataRecord::BrokerUnregistered { broker_id });
+        }
+        if drained {
+            self.decommissioning_brokers.remove(&broker_id);
+        }
+        let metadata_quorum_tx = self.metadata_quorum_tx.clone();
+        let timeout = self.cluster_settings.broker_session_timeout;
+        tokio::spawn(async move {
+            let mut stored = true;
+            for record in records {
+                tracing::info!("Decommissioning broker {}: {:?}", broker_id, record);
+                if let Err(e) = propose(&metadata_quorum_tx, record, timeout).await {
+                    tracing::error!("Could not decommission broker {}: {:?}", broker_id, e);
+                    stored = false;
+                    break;
+                }
+            }
+            let _ = reply_tx.send(BrokerResponse::DecommissionProgress {
+                moving_partitions: drain.moving_partitions,
+                failed_partitions: drain.failed_partitions,
+                unregistered: unregistered || (drained && stored),
+            });
+        });
+    }
+
+    /// Hands the leadership back to the preferred replicas of brokers which lead too few of the
+    /// partitions they are preferred for.
+    async fn rebalance_leaders(&self) {
+        let partition_states = self.partition_states().await;
+        let imbalance_percentage = self.cluster_settings.leader_imbalance_per_broker_percentage;
+        for partition_state in imbalanced_partitions(&partition_states, imbalance_percentage) {
+            let Ok(Some(leader_and_isr)) =
+                elect_preferred_leader(partition_state, |broker_id| self.is_electable(broker_id))
+            else {
+                continue;
+            };
+            tracing::info!(
+                "Moving the leadership of {:?} back to its preferred replica {}",
+                partition_state.topic_partition,
+                leader_and_isr.leader_id
+            );
+            self.propose_leader_and_isr(partition_state.topic_partition.clone(), leader_and_isr)
+                .await;
+        }
+    }
+
+    /// Sends a request this broker cannot answer as it is not the active controller to the
+    /// active one, in the background.
+    fn forward_to_active_controller(
+        &self,
+        command: TopicCommand,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    ) {
+        let controller_id = self.controller_id;
+        let controller_address = controller_id
+            .and_then(|controller_id| self.cluster_settings.peer_address(controller_id))
+            .map(str::to_string);
+        tokio::spawn(async move {
+            let response = match controller_address {
+                Some(controller_address) => send_request(&controller_address, &command)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Could not reach controller {:?}: {:?}", controller_id, e);
+                        BrokerResponse::NotQuorumLeader {
+                            leader_id: controller_id,
+                        }
+                    }),
+                None => BrokerResponse::NotQuorumLeader {
+                    leader_id: controller_id,
+                },
+            };
+            let _ = reply_tx.send(response);
+        });
+    }
+
+    /// Starts the reassignments once every one of them is valid, the records are proposed in the
+    /// background so heartbeats are not held up by an unreachable quorum.
+    async fn reassign_partitions(
+        &self,
+        reassignments: Vec<PartitionReassignment>,
+        throttle_rate: Option<u64>,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    ) {
+        let mut partition_states: HashMap<TopicPartition, PartitionState> = self
+            .partition_states()
+            .await
+            .into_iter()
+            .map(|partition_state| (partition_state.topic_partition.clone(), partition_state))
+            .collect();
+        let broker_ids = self.broker_ids().await;
+        let mut records = vec![];
+        for reassignment in reassignments {
+            let partition_state = partition_states.remove(&reassignment.topic_partition);
+            match start_reassignment(partition_state, &reassignment, &broker_ids) {
+                Ok(partition_state) => {
+                    records.push(replicas_changed_record(partition_state, throttle_rate))
+                }
+                Err(error) => {
+                    reply_tx
+                        .send(BrokerResponse::ReassignmentFailed {
+                            topic_partition: reassignment.topic_partition,
+                            error,
+                        })
+                        .unwrap();
+                    return;
+                }
+            }
+        }
+        let metadata_quorum_tx = self.metadata_quorum_tx.clone();
+        let timeout = self.cluster_settings.broker_session_timeout;
+        tokio::spawn(async move {
+            let mut topic_partitions = vec![];
+            for record in records {
+                let MetadataRecord::ReplicasChanged {
+                    topic_partition, ..
+                } = &record
+                else {
+                    unreachable!("only replica changes are proposed");
+                };
+                let topic_partition = topic_partition.clone();
+                tracing::info!("Reassigning {:?}", record);
+                if let Err(e) = propose(&metadata_quorum_tx, record, timeout).await {
+                    let _ = reply_tx.send(BrokerResponse::ReassignmentFailed {
+                        topic_partition,
+                        error: e.to_string(),
+                    });
+                    return;
+                }
+                topic_partitions.push(topic_partition);
+            }
+            let _ = reply_tx.send(BrokerResponse::ReassignmentsStarted { topic_partitions });
+        });
+    }
+
+    /// Brokers registered in the metadata log.
+    async fn registered_broker_ids(&self) -> BTreeSet<BrokerId> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.metadata_quorum_tx
+            .send(MetadataQuorumCommands::GetBrokers { reply_tx })
+            .await
+            .unwrap();
+        reply_rx
+            .await
+            .unwrap()
+            .iter()
+            .map(|registration| registration.broker_id)
+            .collect()
+    }
+
+    /// Every broker of the cluster: the registered ones and the configured peers.
+    async fn broker_ids(&self) -> BTreeSet<BrokerId> {
+        self.registered_broker_ids()
+            .await
+            .into_iter()
+            .chain(self.cluster_settings.peers.keys().copied())
+            .chain([self.cluster_settings.broker_id])
+            .collect()
+    }
+
+    async fn partition_states(&self) -> Vec<PartitionState> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.topic_manager_tx
+            .send(TopicManagerCommands::GetPartitionStates { reply_tx })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap()
+    }
+
+    /// Appends the elected leader to the metadata log, returns whether it was stored. Gives up
+    /// after the session timeout when no majority of the brokers is reachable, the next check
+    /// elects again.
+    async fn propose_leader_and_isr(
+        &self,
+        topic_partition: TopicPartition,
+        leader_and_isr: LeaderAndIsr,
+    ) -> bool {
+        let record = MetadataRecord::LeaderAndIsrChanged {
+            topic_partition: topic_partition.clone(),
+            leader_and_isr,
+        };
+        let timeout = self.cluster_settings.broker_session_timeout;
+        match propose(&self.metadata_quorum_tx, record, timeout).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::error!(
+                    "Could not store the new leader of {:?}: {:?}",
+                    topic_partition,
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    async fn propose_replicas(&self, partition_state: PartitionState) {
+        let topic_partition = partition_state.topic_partition.clone();
+        let record = replicas_changed_record(partition_state, None);
+        let timeout = self.cluster_settings.broker_session_timeout;
+        if let Err(e) = propose(&self.metadata_quorum_tx, record, timeout).await {
+            tracing::error!(
+                "Could not store the new replicas of {:?}: {:?}",
+                topic_partition,
+                e
+            );
+        }
+    }
+
+    fn is_alive(&self, broker_id: BrokerId) -> bool {
+        broker_id == self.cluster_settings.broker_id || self.broker_liveness.is_alive(broker_id)
+    }
+
+    /// Whether partitions may be handed to the broker: it is alive and does not shut down.
+    fn is_electable(&self, broker_id: BrokerId) -> bool {
+        self.is_alive(broker_id) && !self.shutting_down_brokers.contains(&broker_id)
+    }
+}
+
+/// Appends a record to the metadata log, fails after `timeout` when no majority of the brokers
+/// is reachable.
+async fn propose(
+    metadata_quorum_tx: &Sender<MetadataQuorumCommands>,
+    record: MetadataRecord,
+    timeout: Duration,
+) -> io::Result<()> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    metadata_quorum_tx
+        .send(MetadataQuorumCommands::Propose { record, reply_tx })
+        .await
+        .unwrap();
+    match tokio::time::timeout(timeout, reply_rx).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(io::Error::other(e)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no majority of the brokers stored the record",
+        )),
+    }
+}
+
+/// `throttle_rate` limits the replication of the replicas a reassignment adds.
+fn replicas_changed_record(
+    partition_state: PartitionState,
+    throttle_rate: Option<u64>,
+) -> MetadataRecord {
+    MetadataRecord::ReplicasChanged {
+        topic_partition: partition_state.topic_partition,
+        replicas: partition_state.replicas,
+        adding_replicas: partition_state.adding_replicas,
+        removing_replicas: partition_state.removing_replicas,
+        throttle_rate,
+        leader_and_isr: partition_state.leader_and_isr,
+    }
+}
+
+/// Partition replicated to its old and new replicas until the new ones caught up, like Kafka's
+/// reassignments. Reassignments which add no replicas complete right away. The leader keeps
+/// leading in a new epoch, unless a completed reassignment removed it.
+fn start_reassignment(
+    partition_state: Option<PartitionState>,
+    reassignment: &PartitionReassignment,
+    broker_ids: &BTreeSet<BrokerId>,
+) -> Result<PartitionState, String> {
+    let Some(partition_state) = partition_state else {
+        return Err("unknown partition or reassigned twice in the request".to_string());
+    };
+    let target_replicas = &reassignment.replicas;
+    if target_replicas.is_empty() {
+        return Err("no replicas given".to_string());
+    }
+    if target_replicas.iter().collect::<BTreeSet<_>>().len() != target_replicas.len() {
+        return Err(format!("duplicate replicas in {:?}", target_replicas));
+    }
+    if let Some(broker_id) = target_replicas
+        .iter()
+        .find(|broker_id| !broker_ids.contains(broker_id))
+    {
+        return Err(format!("unknown broker {}", broker_id));
+    }
+    if !partition_state.adding_replicas.is_empty() || !partition_state.removing_replicas.is_empty()
+    {
+        return Err(format!(
+            "already being reassigned to {:?}",
+            partition_state
+                .replicas
+                .iter()
+                .filter(|broker_id| !partition_state.removing_replicas.contains(broker_id))
+                .collect::<Vec<_>>()
+        ));
+    }
+    let current_replicas = &partition_state.replicas;
+    let reassigning = PartitionState {
+        replicas: target_replicas
+            .iter()
+            .chain(
+                current_replicas
+                    .iter()
+                    .filter(|broker_id| !target_replicas.contains(broker_id)),
+            )
+            .copied()
+            .collect(),
+        adding_replicas: target_replicas
+            .iter()
+            .copied()
+            .filter(|broker_id| !current_replicas.contains(broker_id))
+            .collect(),
+        removing_replicas: current_replicas
+            .iter()
+            .copied()
+            .filter(|broker_id| !target_replicas.contains(broker_id))
+            .collect(),
+        ..partition_state
+    };
+    // the active controller elects another leader should the new one be dead
+    Ok(
+        complete_reassignment(&reassigning, |_| true).unwrap_or_else(|| PartitionState {
+            leader_and_isr: LeaderAndIsr {
+                leader_epoch: reassigning.leader_and_isr.leader_epoch + 1,
+                ..reassigning.leader_and_isr.clone()
+            },
+            ..reassigning
+        }),
+    )
+}
+
+/// Time ISR changes count towards the ISR churn.
+const ISR_CHURN_WINDOW: Duration = Duration::from_secs(60);
+
+/// ISR changes the active controller noticed between its checks.
+#[derive(Debug, Default)]
+struct IsrChurn {
+    /// ISR of every partition at the last check.
+    last_isrs: HashMap<TopicPartition, Vec<BrokerId>>,
+    /// Times of the changes within `ISR_CHURN_WINDOW`, oldest first.
+    changes: VecDeque<Instant>,
+}
+
+impl IsrChurn {
+    /// Counts the partitions whose ISR changed since the last check, partitions seen for the
+    /// first time did not change.
+    fn record(&mut self, partition_states: &[PartitionState], now: Instant) {
+        let last_isrs = std::mem::take(&mut self.last_isrs);
+        for partition_state in partition_states {
+            let topic_partition = &partition_state.topic_partition;
+            let isr = &partition_state.leader_and_isr.isr;
+            if last_isrs
+                .get(topic_partition)
+                .is_some_and(|last_isr| last_isr != isr)
+            {
+                self.changes.push_back(now);
+            }
+            self.last_isrs.insert(topic_partition.clone(), isr.clone());
+        }
+        while self
+            .changes
+            .front()
+            .is_some_and(|changed_at| now.duration_since(*changed_at) > ISR_CHURN_WINDOW)
+        {
+            self.changes.pop_front();
+        }
+    }
+
+    fn recent_changes(&self, now: Instant) -> usize {
+        self.changes
+            .iter()
+            .filter(|changed_at| now.duration_since(**changed_at) <= ISR_CHURN_WINDOW)
+            .count()
+    }
+}
+
+/// Whether replicas of the partition are out of its ISR, new replicas of a reassignment which
+/// are still catching up left aside.
+fn is_under_replicated(partition_state: &PartitionState) -> bool {
+    partition_state
+        .replicas
+        .iter()
+        .filter(|replica_id| !partition_state.adding_replicas.contains(replica_id))
+        .any(|replica_id| !partition_state.leader_and_isr.isr.contains(replica_id))
+}
+
+/// How the replicas of a broker which is decommissioned move off it.
+#[derive(Debug, PartialEq)]
+struct BrokerDrain {
+    /// Partitions getting another broker in place of the decommissioned one.
+    reassignments: Vec<PartitionReassignment>,
+    /// Partitions with a replica on the broker until their reassignment completed, including
+    /// the ones of `reassignments`.
+    moving_partitions: Vec<TopicPartition>,
+    failed_partitions: Vec<(TopicPartition, String)>,
+}
+
+/// Replaces broker `broker_id` in the replicas of its partitions, at the same position so the
+/// preferred leaders of the other partitions stay, by the target broker holding the fewest
+/// replicas which holds none of the partition yet, the lowest ID on a tie.
+fn drain_broker(
+    partition_states: &[PartitionState],
+    broker_id: BrokerId,
+    target_broker_ids: &BTreeSet<BrokerId>,
+) -> BrokerDrain {
+    let mut replica_counts: BTreeMap<BrokerId, usize> = target_broker_ids
+        .iter()
+        .map(|target_id| (*target_id, 0))
+        .collect();
+    for partition_state in partition_states {
+        for replica_id in &partition_state.replicas {
+            if let Some(replica_count) = replica_counts.get_mut(replica_id) {
+                *replica_count += 1;
+            }
+        }
+    }
+    let mut drain = BrokerDrain {
+        reassignments: vec![],
+        moving_partitions: vec![],
+        failed_partitions: vec![],
+    };
+    for partition_state in partition_states {
+        if !partition_state.replicas.contains(&broker_id) {
+            continue;
+        }
+        let topic_partition = partition_state.topic_partition.clone();
+        if !partition_state.adding_replicas.is_empty()
+            || !partition_state.removing_replicas.is_empty()
+        {
+            drain.moving_partitions.push(topic_partition);
+            continue;
+        }
+        let target_id = replica_counts
+            .iter()
+            .filter(|(target_id, _)| !partition_state.replicas.contains(target_id))
+            .min_by_key(|(target_id, replica_count)| (**replica_count, **target_id))
+            .map(|(target_id, _)| *target_id);
+        let Some(target_id) = target_id else {
+            drain.failed_partitions.push((
+                topic_partition,
+                "every live broker holds a replica already".to_string(),
+            ));
+            continue;
+        };
+        *replica_counts.get_mut(&target_id).unwrap() += 1;
+        let replicas = partition_state
+            .replicas
+            .iter()
+            .map(|replica_id| {
+                if *replica_id == broker_id {
+                    target_id
+                } else {
+                    *replica_id
+                }
+            })
+            .collect();
+        drain.reassignments.push(PartitionReassignment {
+            topic_partition: topic_partition.clone(),
+            replicas,
+        });
+        drain.moving_partitions.push(topic_partition);
+    }
+    drain
+}
+
+/// Partition with only its new replicas once every adding replica joined the ISR. A removed or
+/// dead leader is replaced by the first new replica in the ISR which is alive. `None` while new
+/// replicas are still catching up.
+fn complete_reassignment(
+    partition_state: &PartitionState,
+    is_alive: impl Fn(BrokerId) -> bool,
+) -> Option<PartitionState> {
+    let leader_and_isr = &partition_state.leader_and_isr;
+    if !partition_state
+        .adding_replicas
+        .iter()
+        .all(|broker_id| leader_and_isr.isr.contains(broker_id))
+    {
+        return None;
+    }
+    let replicas: Vec<BrokerId> = partition_state
+        .replicas
+        .iter()
+        .copied()
+        .filter(|broker_id| !partition_state.removing_replicas.contains(broker_id))
+        .collect();
+    let isr: Vec<BrokerId> = leader_and_isr
+        .isr
+        .iter()
+        .copied()
+        .filter(|broker_id| replicas.contains(broker_id))
+        .collect();
+    let leader_id =
+        if replicas.contains(&leader_and_isr.leader_id) && is_alive(leader_and_isr.leader_id) {
+            leader_and_isr.leader_id
+        } else {
+            replicas
+                .iter()
+                .copied()
+                .find(|broker_id| isr.contains(broker_id) && is_alive(*broker_id))?
+        };
+    Some(PartitionState {
+        topic_partition: partition_state.topic_partition.clone(),
+        replicas,
+        adding_replicas: vec![],
+        removing_replicas: vec![],
+        leader_and_isr: LeaderAndIsr {
+            leader_id,
+            leader_epoch: leader_and_isr.leader_epoch + 1,
+            isr,
+        },
+    })
+}
+
+/// Leader and ISR with the preferred replica, the first replica, leading the partition in a new
+/// epoch. `None` when it already leads, an error when it cannot lead as it is dead or not in sync.
+fn elect_preferred_leader(
+    partition_state: &PartitionState,
+    is_alive: impl Fn(BrokerId) -> bool,
+) -> Result<Option<LeaderAndIsr>, String> {
+    let leader_and_isr = &partition_state.leader_and_isr;
+    let preferred_leader_id = partition_state.replicas[0];
+    if leader_and_isr.leader_id == preferred_leader_id {
+        return Ok(None);
+    }
+    if !is_alive(preferred_leader_id) {
+        return Err(format!("preferred replica {} is dead", preferred_leader_id));
+    }
+    if !leader_and_isr.isr.contains(&preferred_leader_id) {
+        return Err(format!(
+            "preferred replica {} is not in the ISR {:?}",
+            preferred_leader_id, leader_and_isr.isr
+        ));
+    }
+    Ok(Some(LeaderAndIsr {
+        leader_id: preferred_leader_id,
+        leader_epoch: leader_and_isr.leader_epoch + 1,
+        isr: leader_and_isr.isr.clone(),
+    }))
+}
+
+/// Partitions not led by their preferred replica, of every preferred replica leading less than
+/// it should, like Kafka's auto leader rebalance: a broker is imbalanced once more than
+/// `imbalance_percentage` percent of the partitions it is the preferred replica of are led by
+/// other brokers. Partitions being reassigned are left alone.
+fn imbalanced_partitions(
+    partition_states: &[PartitionState],
+    imbalance_percentage: u32,
+) -> Vec<&PartitionState> {
+    let mut partitions_by_preferred_leader: BTreeMap<BrokerId, Vec<&PartitionState>> =
+        BTreeMap::new();
+    for partition_state in partition_states {
+        partitions_by_preferred_leader
+            .entry(partition_state.replicas[0])
+            .or_default()
+            .push(partition_state);
+    }
+    let mut imbalanced_partitions = vec![];
+    for (preferred_leader_id, partition_states) in partitions_by_preferred_leader {
+        let not_led: Vec<&PartitionState> = partition_states
+            .iter()
+            .copied()
+            .filter(|partition_state| {
+                partition_state.leader_and_isr.leader_id != preferred_leader_id
+                    && partition_state.adding_replicas.is_empty()
+                    && partition_state.removing_replicas.is_empty()
+            })
+            .collect();
+        if not_led.len() * 100 > partition_states.len() * imbalance_percentage as usize {
+            imbalanced_partitions.extend(not_led);
+        }
+    }
+    imbalanced_partitions
+}
+
+/// New leader of a partition whose leader died: the first live replica of the ISR, so the new
+/// leader has every record acknowledged by the ISR. The new ISR holds the live replicas of the
+/// old one. `None` when no replica of the ISR is alive.
+fn elect_leader(
+    partition_state: &PartitionState,
+    is_alive: impl Fn(BrokerId) -> bool,
+) -> Option<LeaderAndIsr> {
+    let isr: Vec<BrokerId> = partition_state
+        .leader_and_isr
+        .isr
+        .iter()
+        .copied()
+        .filter(|broker_id| is_alive(*broker_id))
+        .collect();
+    let leader_id = partition_state
+        .replicas
+        .iter()
+        .copied()
+        .find(|broker_id| isr.contains(broker_id))?;
+    Some(LeaderAndIsr {
+        leader_id,
+        leader_epoch: partition_state.leader_and_isr.leader_epoch + 1,
+        isr,
+    })
+}
+
+/// Leader of a partition without a live replica in its ISR: the first live replica, alone in the
+/// new ISR as the others may have records it misses. `None` when no replica is alive.
+fn elect_unclean_leader(
+    partition_state: &PartitionState,
+    is_alive: impl Fn(BrokerId) -> bool,
+) -> Option<LeaderAndIsr> {
+    let leader_id = partition_state
+        .replicas
+        .iter()
+        .copied()
+        .find(|broker_id| is_alive(*broker_id))?;
+    Some(LeaderAndIsr {
+        leader_id,
+        leader_epoch: partition_state.leader_and_isr.leader_epoch + 1,
+        isr: vec![leader_id],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elect_leader_should_pick_live_replicas_of_the_isr() {
+        let partition_state = PartitionState {
+            topic_partition: TopicPartition::new("t1".to_string(), 0),
+            replicas: vec![0, 1, 2],
+            adding_replicas: vec![],
+            removing_replicas: vec![],
+            leader_and_isr: LeaderAndIsr {
+                leader_id: 0,
+                leader_epoch: 3,
+                isr: vec![0, 2],
+            },
+        };
+
+        // broker 1 is alive but fell behind before broker 0 died
+        assert_eq!(
+            elect_leader(&partition_state, |broker_id| broker_id != 0),
+            Some(LeaderAndIsr {
+                leader_id: 2,
+                leader_epoch: 4,
+                isr: vec![2],
+            })
+        );
+        assert_eq!(
+            elect_leader(&partition_state, |broker_id| broker_id == 1),
+            None
+        );
+        // only broker 1 is left, it may lead once unclean leader election is enabled
+        assert_eq!(
+            elect_unclean_leader(&partition_state, |broker_id| broker_id == 1),
+            Some(LeaderAndIsr {
+                leader_id: 1,
+                leader_epoch: 4,
+                isr: vec![1],
+            })
+        );
+        assert_eq!(elect_unclean_leader(&partition_state, |_| false), None);
+    }
+
+    #[test]
+    fn test_reassignment_should_complete_once_new_replicas_are_in_sync() {
+        let partition_state = PartitionState {
+            topic_partition: TopicPartition::new("t1".to_string(), 0),
+            replicas: vec![0, 1],
+            adding_replicas: vec![],
+            removing_replicas: vec![],
+            leader_and_isr: LeaderAndIsr {
+                leader_id: 0,
+                leader_epoch: 3,
+                isr: vec![0, 1],
+            },
+        };
+        let reassignment = PartitionReassignment {
+            topic_partition: partition_state.topic_partition.clone(),
+            replicas: vec![2, 1],
+        };
+        let broker_ids = BTreeSet::from([0, 1, 2]);
+
+        let reassigning =
+            start_reassignment(Some(partition_state.clone()), &reassignment, &broker_ids).unwrap();
+        assert_eq!(reassigning.replicas, vec![2, 1, 0]);
+        assert_eq!(reassigning.adding_replicas, vec![2]);
+        assert_eq!(reassigning.removing_replicas, vec![0]);
+        assert_eq!(
+            reassigning.leader_and_isr,
+            LeaderAndIsr {
+                leader_id: 0,
+                leader_epoch: 4,
+                isr: vec![0, 1],
+            }
+        );
+        assert_eq!(complete_reassignment(&reassigning, |_| true), None);
+        assert!(start_reassignment(Some(reassigning.clone()), &reassignment, &broker_ids).is_err());
+
+        // broker 2 caught up, broker 0 is removed and hands the leadership to the first new
+        // replica in the ISR
+        let caught_up = PartitionState {
+            leader_and_isr: LeaderAndIsr {
+                isr: vec![0, 1, 2],
+                ..reassigning.leader_and_isr.clone()
+            },
+            ..reassigning
+        };
+        let completed = complete_reassignment(&caught_up, |_| true).unwrap();
+        assert_eq!(completed.replicas, vec![2, 1]);
+        assert!(completed.adding_replicas.is_empty() && completed.removing_replicas.is_empty());
+        assert_eq!(
+            completed.leader_and_isr,
+            LeaderAndIsr {
+                leader_id: 2,
+                leader_epoch: 5,
+                isr: vec![1, 2],
+            }
+        );
+
+        let unknown_broker = PartitionReassignment {
+            replicas: vec![1, 3],
+            ..reassignment
+        };
+        assert!(start_reassignment(Some(partition_state), &unknown_broker, &broker_ids).is_err());
+    }
+
+    #[test]
+    fn test_preferred_leaders_should_lead_again_once_in_sync() {
+        let partition_state = |partition_index, leader_id, isr: Vec<BrokerId>| PartitionState {
+            topic_partition: TopicPartition::new("t1".to_string(), partition_index),
+            replicas: vec![partition_index as BrokerId % 2, 2],
+            adding_replicas: vec![],
+            removing_replicas: vec![],
+            leader_and_isr: LeaderAndIsr {
+                leader_id,
+                leader_epoch: 1,
+                isr,
+            },
+        };
+        // broker 0 is preferred for partitions 0 and 2 but leads none of them, broker 1 leads one
+        // of its two partitions
+        let partition_states = vec![
+            partition_state(0, 2, vec![0, 2]),
+            partition_state(1, 1, vec![1, 2]),
+            partition_state(2, 2, vec![2]),
+            partition_state(3, 2, vec![1, 2]),
+        ];
+
+        let imbalanced: Vec<u8> = imbalanced_partitions(&partition_states, 50)
+            .iter()
+            .map(|partition_state| partition_state.topic_partition.partition_index)
+            .collect();
+        assert_eq!(imbalanced, vec![0, 2]);
+        assert_eq!(imbalanced_partitions(&partition_states, 10).len(), 3);
+
+        assert_eq!(
+            elect_preferred_leader(&partition_states[0], |_| true),
+            Ok(Some(LeaderAndIsr {
+                leader_id: 0,
+                leader_epoch: 2,
+                isr: vec![0, 2],
+            }))
+        );
+        assert_eq!(
+            elect_preferred_leader(&partition_states[1], |_| true),
+            Ok(None)
+        );
+        assert!(elect_preferred_leader(&partition_states[2], |_| true).is_err());
+        assert!(elect_preferred_leader(&partition_states[3], |broker_id| broker_id != 1).is_err());
+    }
+
+    #[test]
+    fn test_drain_broker_should_move_replicas_to_the_least_loaded_brokers() {
+        let partition_state =
+            |partition_index, replicas: Vec<BrokerId>, adding_replicas| PartitionState {
+                topic_partition: TopicPartition::new("t1".to_string(), partition_index),
+                leader_and_isr: LeaderAndIsr {
+                    leader_id: replicas[0],
+                    leader_epoch: 1,
+                    isr: replicas.clone(),
+                },
+                replicas,
+                adding_replicas,
+                removing_replicas: vec![],
+            };
+        let partition_states = vec![
+            partition_state(0, vec![0, 1], vec![]),
+            partition_state(1, vec![1, 0], vec![]),
+            partition_state(2, vec![0, 1, 2, 3], vec![]),
+            // moved once its reassignment completed
+            partition_state(3, vec![2, 0], vec![2]),
+        ];
+
+        // brokers 1, 2 and 3 hold 3, 2 and 1 replicas
+        let drain = drain_broker(&partition_states, 0, &BTreeSet::from([1, 2, 3]));
+        assert_eq!(
+            drain.reassignments,
+            vec![
+                PartitionReassignment {
+                    topic_partition: TopicPartition::new("t1".to_string(), 0),
+                    replicas: vec![3, 1],
+                },
+                PartitionReassignment {
+                    topic_partition: TopicPartition::new("t1".to_string(), 1),
+                    replicas: vec![1, 2],
+                },
+            ]
+        );
+        let moving: Vec<u8> = drain
+            .moving_partitions
+            .iter()
+            .map(|topic_partition| topic_partition.partition_index)
+            .collect();
+        assert_eq!(moving, vec![0, 1, 3]);
+        assert_eq!(drain.failed_partitions.len(), 1);
+        assert_eq!(drain.failed_partitions[0].0.partition_index, 2);
+
+        let drained = drain_broker(&partition_states, 4, &BTreeSet::from([0, 1, 2, 3]));
+        assert!(drained.moving_partitions.is_empty() && drained.failed_partitions.is_empty());
+    }
+
+    #[test]
+    fn test_health_should_count_under_replicated_partitions_and_isr_changes() {
+        let partition_state = |isr: Vec<BrokerId>, adding_replicas| PartitionState {
+            topic_partition: TopicPartition::new("t1".to_string(), 0),
+            replicas: vec![0, 1, 2],
+            adding_replicas,
+            removing_replicas: vec![],
+            leader_and_isr: LeaderAndIsr {
+                leader_id: 0,
+                leader_epoch: 1,
+                isr,
+            },
+        };
+        assert!(!is_under_replicated(&partition_state(
+            vec![0, 1, 2],
+            vec![]
+        )));
+        assert!(is_under_replicated(&partition_state(vec![0, 1], vec![])));
+        // broker 2 is still copying the partition it was reassigned
+        assert!(!is_under_replicated(&partition_state(vec![0, 1], vec![2])));
+
+        let start = Instant::now();
+        let mut isr_churn = IsrChurn::default();
+        isr_churn.record(&[partition_state(vec![0, 1, 2], vec![])], start);
+        assert_eq!(isr_churn.recent_changes(start), 0);
+        let shrunk = start + Duration::from_secs(10);
+        isr_churn.record(&[partition_state(vec![0, 1], vec![])], shrunk);
+        isr_churn.record(&[partition_state(vec![0, 1], vec![])], shrunk);
+        let expanded = start + Duration::from_secs(20);
+        isr_churn.record(&[partition_state(vec![0, 1, 2], vec![])], expanded);
+        assert_eq!(isr_churn.recent_changes(expanded), 2);
+        assert_eq!(
+            isr_churn.recent_changes(shrunk + ISR_CHURN_WINDOW + Duration::from_secs(1)),
+            1
+        );
+    }
+}
@@ -0,0 +1,1429 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::models::{
+    AssignmentStrategy, BrokerResponse, JoinGroupRequest, OffsetResetTarget, PartitionLag,
+    PartitionOffset, RebalanceProtocol, Subscription, TopicPartition,
+};
+use regex::Regex;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::assignors::{assignor_for, MemberSubscription};
+use crate::clock::Clock;
+use crate::managers::partition_manager::offset_for_timestamp;
+use crate::managers::topics_manager::{TopicEvent, TopicManagerCommands};
+
+/// How often members are checked for missed heartbeats and polls.
+const MEMBER_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+struct GroupMember {
+    subscription: Subscription,
+    /// Topics the subscription resolved to.
+    topics: Vec<String>,
+    group_instance_id: Option<String>,
+    /// Partitions the member is allowed to consume in the current generation.
+    assignment: Vec<TopicPartition>,
+    /// Partitions the last rebalance decided the member should end up with.
+    target_assignment: Vec<TopicPartition>,
+    /// Partitions taken away from the member. With the cooperative protocol they are not handed
+    /// to another member until this member confirms it stopped consuming them.
+    revoked_partitions: Vec<TopicPartition>,
+    session_timeout: Duration,
+    max_poll_interval: Duration,
+    last_heartbeat: Instant,
+    last_poll: Instant,
+}
+
+impl GroupMember {
+    fn new(
+        subscription: Subscription,
+        topics: Vec<String>,
+        group_instance_id: Option<String>,
+        now: Instant,
+    ) -> Self {
+        GroupMember {
+            subscription,
+            topics,
+            group_instance_id,
+            assignment: vec![],
+            target_assignment: vec![],
+            revoked_partitions: vec![],
+            session_timeout: Duration::from_millis(
+                JoinGroupRequest::DEFAULT_SESSION_TIMEOUT_MS as u64,
+            ),
+            max_poll_interval: Duration::from_millis(
+                JoinGroupRequest::DEFAULT_MAX_POLL_INTERVAL_MS as u64,
+            ),
+            last_heartbeat: now,
+            last_poll: now,
+        }
+    }
+
+    fn expiry_reason(&self, now: Instant) -> Option<&'static str> {
+        if now.duration_since(self.last_heartbeat) > self.session_timeout {
+            Some("missed its session timeout")
+        } else if now.duration_since(self.last_poll) > self.max_poll_interval {
+            Some("exceeded its max poll interval")
+        } else {
+            None
+        }
+    }
+}
+
+struct ConsumerGroup {
+    strategy: AssignmentStrategy,
+    protocol: RebalanceProtocol,
+    generation_id: u32,
+    members: BTreeMap<String, GroupMember>,
+    /// Member ids of static members keyed by their `group.instance.id`.
+    static_members: HashMap<String, String>,
+}
+
+impl ConsumerGroup {
+    fn new(strategy: AssignmentStrategy, protocol: RebalanceProtocol) -> Self {
+        ConsumerGroup {
+            strategy,
+            protocol,
+            generation_id: 0,
+            members: BTreeMap::new(),
+            static_members: HashMap::new(),
+        }
+    }
+
+    /// Hands partitions of the target assignment to their new owners once nobody else
+    /// consumes them or still has to confirm their revocation. Returns true if anything moved.
+    fn assign_released_partitions(&mut self) -> bool {
+        let mut partitions_in_use: HashSet<TopicPartition> = self
+            .members
+            .values()
+            .flat_map(|member| member.assignment.iter().chain(&member.revoked_partitions))
+            .cloned()
+            .collect();
+        let mut assignment_changed = false;
+        for member in self.members.values_mut() {
+            for partition in member.target_assignment.iter() {
+                if !partitions_in_use.contains(partition) {
+                    partitions_in_use.insert(partition.clone());
+                    member.assignment.push(partition.clone());
+                    assignment_changed = true;
+                }
+            }
+            member.assignment.sort();
+        }
+        assignment_changed
+    }
+
+    fn member_assignment(&self, member_id: &str) -> BrokerResponse {
+        let member = &self.members[member_id];
+        BrokerResponse::MemberAssignment {
+            generation_id: self.generation_id,
+            assignment: member.assignment.clone(),
+            revoked_partitions: member.revoked_partitions.clone(),
+        }
+    }
+}
+
+/// Keeps track of consumer groups and their members and decides, using the assignor
+/// chosen by the group, which member consumes which partition.
+/// Every join or leave bumps the generation of the group and recomputes the assignment.
+///
+/// With the eager protocol the new assignment takes effect immediately and members are expected
+/// to stop consuming everything they had before picking up their new partitions.
+/// With the cooperative protocol members keep the partitions which do not move; partitions which
+/// do move are only revoked in the first generation and handed to their new owner in a follow-up
+/// generation once the previous owner sent `RevocationCompleted`.
+///
+/// Members joining with a `group_instance_id` are static: when such a member restarts and joins
+/// again with the same instance id it gets its previous member id and assignment back without
+/// triggering a rebalance, as long as its subscription did not change.
+///
+/// Members have to send heartbeats within their session timeout and fetch within their max poll
+/// interval, otherwise they are evicted and their partitions are rebalanced to the other members.
+/// Joining counts as both.
+///
+/// Members subscribed with a pattern consume every topic matching it. Topics created later are
+/// matched against the patterns of all members and trigger a rebalance of the groups they join.
+pub struct GroupCoordinator {
+    groups: HashMap<String, ConsumerGroup>,
+    /// Committed offsets outlive the group membership, so they are kept apart from `groups`.
+    committed_offsets: HashMap<String, BTreeMap<TopicPartition, u64>>,
+    topic_manager_tx: Sender<TopicManagerCommands>,
+    /// Time the session timeouts and max poll intervals of the members are measured with.
+    clock: Arc<dyn Clock>,
+    cancellation_token: CancellationToken,
+    next_member_id: u64,
+}
+
+impl GroupCoordinator {
+    pub fn new(
+        topic_manager_tx: Sender<TopicManagerCommands>,
+        clock: Arc<dyn Clock>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        GroupCoordinator {
+            groups: HashMap::new(),
+            committed_offsets: HashMap::new(),
+            topic_manager_tx,
+            clock,
+            cancellation_token,
+            next_member_id: 0,
+        }
+    }
+
+    pub async fn start_group_coordinator(
+        &mut self,
+        mut parent_rx: Receiver<GroupCoordinatorCommands>,
+        mut topic_events_rx: broadcast::Receiver<TopicEvent>,
+    ) {
+        tracing::info!("Group coordinator started");
+        let mut next_member_expiry_check = self.clock.now();
+        loop {
+            tokio::select! {
+                Some(command) = parent_rx.recv() => {
+                    match command {
+                        GroupCoordinatorCommands::JoinGroup { request, reply_tx } => {
+                            let response = self.join_group(request).await;
+                            reply_tx.send(response).unwrap();
+                        }
+                        GroupCoordinatorCommands::Heartbeat { group_id, member_id, reply_tx } => {
+                            let response = self.heartbeat(group_id, member_id);
+                            reply_tx.send(response).unwrap();
+                        }
+                        GroupCoordinatorCommands::RecordPoll { group_id, member_id } => {
+                            if let Some(member) = self
+                                .groups
+                                .get_mut(&group_id)
+                                .and_then(|group| group.members.get_mut(&member_id))
+                            {
+                                member.last_poll = self.clock.now();
+                            }
+                        }
+                        GroupCoordinatorCommands::LeaveGroup { group_id, member_id, reply_tx } => {
+                            let response = self.leave_group(group_id, member_id).await;
+                            reply_tx.send(response).unwrap();
+                        }
+                        GroupCoordinatorCommands::GetAssignment { group_id, member_id, reply_tx } => {
+                            let response = self.get_assignment(group_id, member_id);
+                            reply_tx.send(response).unwrap();
+                        }
+                        GroupCoordinatorCommands::RevocationCompleted { group_id, member_id, reply_tx } => {
+                            let response = self.revocation_completed(group_id, member_id);
+                            reply_tx.send(response).unwrap();
+                        }
+                        GroupCoordinatorCommands::CommitOffsets { group_id, offsets, reply_tx } => {
+                            let response = self.commit_offsets(group_id, offsets);
+                            reply_tx.send(response).unwrap();
+                        }
+                        GroupCoordinatorCommands::GetCommittedOffset { group_id, topic_partition, reply_tx } => {
+                            let committed_offset = self
+                                .committed_offsets
+                                .get(&group_id)
+                                .and_then(|offsets| offsets.get(&topic_partition))
+                                .copied();
+                            reply_tx.send(committed_offset).unwrap();
+                        }
+                        GroupCoordinatorCommands::ResetOffsets { group_id, topic_name, to, reply_tx } => {
+                            let response = self.reset_offsets(group_id, topic_name, to).await;
+                            reply_tx.send(response).unwrap();
+                        }
+                        GroupCoordinatorCommands::DescribeGroup { group_id, reply_tx } => {
+                            let response = self.describe_group(group_id).await;
+                            reply_tx.send(response).unwrap();
+                        }
+                    }
+                }
+                _ = self.clock.sleep_until(next_member_expiry_check) => {
+                    self.evict_expired_members().await;
+                    next_member_expiry_check = self.clock.now() + MEMBER_EXPIRY_CHECK_INTERVAL;
+                }
+                Ok(TopicEvent::Created { topic_name }) = topic_events_rx.recv() => {
+                    self.topic_created(topic_name).await;
+                }
+                _ = self.cancellation_token.cancelled() => {
+                    tracing::info!("Cancellation token received for group coordinator.");
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn join_group(&mut self, request: JoinGroupRequest) -> BrokerResponse {
+        let JoinGroupRequest {
+            group_id,
+            member_id,
+            group_instance_id,
+            subscription,
+            strategy,
+            protocol,
+            session_timeout_ms,
+            max_poll_interval_ms,
+        } = request;
+        let topics = match self.resolve_subscription(&subscription).await {
+            Ok(topics) => topics,
+            Err(response) => return response,
+        };
+        let group = self
+            .groups
+            .entry(group_id.clone())
+            .or_insert_with(|| ConsumerGroup::new(strategy, protocol));
+        if group.strategy != strategy || group.protocol != protocol {
+            tracing::warn!(
+                "Member asked for {:?}/{:?} but group {} uses {:?}/{:?}",
+                strategy,
+                protocol,
+                group_id,
+                group.strategy,
+                group.protocol
+            );
+            return BrokerResponse::InconsistentGroupProtocol {
+                group_id,
+                strategy: group.strategy,
+                protocol: group.protocol,
+            };
+        }
+
+        let static_member_id = group_instance_id
+            .as_ref()
+            .and_then(|group_instance_id| group.static_members.get(group_instance_id))
+            .cloned();
+        let member_id = static_member_id.or(member_id).unwrap_or_else(|| {
+            self.next_member_id += 1;
+            format!("{}-{}", group_id, self.next_member_id)
+        });
+        let group = self.groups.get_mut(&group_id).unwrap();
+        let needs_rebalance = match group.members.get_mut(&member_id) {
+            Some(member) => {
+                let subscription_changed =
+                    member.subscription != subscription || member.topics != topics;
+                member.subscription = subscription;
+                member.topics = topics;
+                subscription_changed
+            }
+            None => {
+                if let Some(group_instance_id) = group_instance_id.as_ref() {
+                    group
+                        .static_members
+                        .insert(group_instance_id.clone(), member_id.clone());
+                }
+                group.members.insert(
+                    member_id.clone(),
+                    GroupMember::new(subscription, topics, group_instance_id, self.clock.now()),
+                );
+                true
+            }
+        };
+        let member = group.members.get_mut(&member_id).unwrap();
+        member.session_timeout = Duration::from_millis(session_timeout_ms as u64);
+        member.max_poll_interval = Duration::from_millis(max_poll_interval_ms as u64);
+        let now = self.clock.now();
+        member.last_heartbeat = now;
+        member.last_poll = now;
+        tracing::info!("Member {} joined group {}", member_id, group_id);
+
+        if needs_rebalance {
+            self.rebalance(&group_id).await;
+        }
+        let group = &self.groups[&group_id];
+        let member = &group.members[&member_id];
+        BrokerResponse::GroupJoined {
+            assignment: member.assignment.clone(),
+            revoked_partitions: member.revoked_partitions.clone(),
+            member_id,
+            generation_id: group.generation_id,
+        }
+    }
+
+    async fn resolve_subscription(
+        &self,
+        subscription: &Subscription,
+    ) -> Result<Vec<String>, BrokerResponse> {
+        let pattern = match subscription {
+            Subscription::Topics(topics) => return Ok(topics.clone()),
+            Subscription::Pattern(pattern) => pattern,
+        };
+        let regex =
+            compile_topic_pattern(pattern).map_err(|e| BrokerResponse::InvalidTopicPattern {
+                pattern: pattern.clone(),
+                error: e.to_string(),
+            })?;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.topic_manager_tx
+            .send(TopicManagerCommands::ListTopics { reply_tx })
+            .await
+            .unwrap();
+        let mut topics: Vec<String> = reply_rx
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|topic_name| regex.is_match(topic_name))
+            .collect();
+        topics.sort();
+        Ok(topics)
+    }
+
+    /// Adds a new topic to every member whose pattern matches it and rebalances their groups.
+    async fn topic_created(&mut self, topic_name: String) {
+        let mut groups_to_rebalance = vec![];
+        for (group_id, group) in self.groups.iter_mut() {
+            let mut group_changed = false;
+            for member in group.members.values_mut() {
+                let matches = match &member.subscription {
+                    Subscription::Pattern(pattern) => compile_topic_pattern(pattern)
+                        .is_ok_and(|regex| regex.is_match(&topic_name)),
+                    Subscription::Topics(_) => false,
+                };
+                if matches && !member.topics.contains(&topic_name) {
+                    member.topics.push(topic_name.clone());
+                    member.topics.sort();
+                    group_changed = true;
+                }
+            }
+            if group_changed {
+                groups_to_rebalance.push(group_id.clone());
+            }
+        }
+        for group_id in groups_to_rebalance {
+            tracing::info!(
+                "Topic {} matches the subscription of group {}",
+                topic_name,
+                group_id
+            );
+            self.rebalance(&group_id).await;
+        }
+    }
+
+    fn heartbeat(&mut self, group_id: String, member_id: String) -> BrokerResponse {
+        let group = self.groups.get_mut(&group_id);
+        match group.and_then(|group| {
+            let generation_id = group.generation_id;
+            group
+                .members
+                .get_mut(&member_id)
+                .map(|member| (member, generation_id))
+        }) {
+            Some((member, generation_id)) => {
+                member.last_heartbeat = self.clock.now();
+                BrokerResponse::HeartbeatAccepted { generation_id }
+            }
+            None => BrokerResponse::UnknownGroupMember {
+                group_id,
+                member_id,
+            },
+        }
+    }
+
+    async fn evict_expired_members(&mut self) {
+        let now = self.clock.now();
+        let mut groups_to_rebalance = vec![];
+        for (group_id, group) in self.groups.iter_mut() {
+            let expired_members: Vec<(String, &'static str)> = group
+                .members
+                .iter()
+                .filter_map(|(member_id, member)| {
+                    member
+                        .expiry_reason(now)
+                        .map(|reason| (member_id.clone(), reason))
+                })
+                .collect();
+            if expired_members.is_empty() {
+                continue;
+            }
+            for (member_id, reason) in expired_members {
+                tracing::warn!(
+                    "Evicting member {} of group {}, it {}",
+                    member_id,
+                    group_id,
+                    reason
+                );
+                let member = group.members.remove(&member_id).unwrap();
+                if let Some(group_instance_id) = member.group_instance_id {
+                    group.static_members.remove(&group_instance_id);
+                }
+            }
+            groups_to_rebalance.push(group_id.clone());
+        }
+        self.groups.retain(|_, group| !group.members.is_empty());
+        for group_id in groups_to_rebalance {
+            if self.groups.contains_key(&group_id) {
+                self.rebalance(&group_id).await;
+            }
+        }
+    }
+
+    async fn leave_group(&mut self, group_id: String, member_id: String) -> BrokerResponse {
+        let group = match self.groups.get_mut(&group_id) {
+            Some(group) => group,
+            None => {
+                return BrokerResponse::UnknownGroupMember {
+                    group_id,
+                    member_id,
+                }
+            }
+        };
+        match group.members.remove(&member_id) {
+            Some(member) => {
+                if let Some(group_instance_id) = member.group_instance_id {
+                    group.static_members.remove(&group_instance_id);
+                }
+            }
+            None => {
+                return BrokerResponse::UnknownGroupMember {
+                    group_id,
+                    member_id,
+                }
+            }
+        }
+        tracing::info!("Member {} left group {}", member_id, group_id);
+        if self.groups[&group_id].members.is_empty() {
+            self.groups.remove(&group_id);
+        } else {
+            self.rebalance(&group_id).await;
+        }
+        BrokerResponse::GroupLeft { member_id }
+    }
+
+    fn get_assignment(&self, group_id: String, member_id: String) -> BrokerResponse {
+        let group = self.groups.get(&group_id);
+        match group {
+            Some(group) if group.members.contains_key(&member_id) => {
+                group.member_assignment(&member_id)
+            }
+            _ => BrokerResponse::UnknownGroupMember {
+                group_id,
+                member_id,
+            },
+        }
+    }
+
+    fn revocation_completed(&mut self, group_id: String, member_id: String) -> BrokerResponse {
+        let group = match self.groups.get_mut(&group_id) {
+            Some(group) if group.members.contains_key(&member_id) => group,
+            _ => {
+                return BrokerResponse::UnknownGroupMember {
+                    group_id,
+                    member_id,
+                }
+            }
+        };
+        let member = group.members.get_mut(&member_id).unwrap();
+        tracing::info!(
+            "Member {} of group {} released {} partitions",
+            member_id,
+            group_id,
+            member.revoked_partitions.len()
+        );
+        member.revoked_partitions.clear();
+        if group.protocol == RebalanceProtocol::Cooperative && group.assign_released_partitions() {
+            group.generation_id += 1;
+            tracing::info!(
+                "Group {} moved to generation {} after revocation",
+                group_id,
+                group.generation_id
+            );
+        }
+        group.member_assignment(&member_id)
+    }
+
+    fn commit_offsets(
+        &mut self,
+        group_id: String,
+        offsets: Vec<PartitionOffset>,
+    ) -> BrokerResponse {
+        let committed_offsets = self.committed_offsets.entry(group_id).or_default();
+        for partition_offset in offsets {
+            committed_offsets.insert(partition_offset.topic_partition, partition_offset.offset);
+        }
+        BrokerResponse::OffsetsCommitted
+    }
+
+    async fn reset_offsets(
+        &mut self,
+        group_id: String,
+        topic_name: String,
+        to: OffsetResetTarget,
+    ) -> BrokerResponse {
+        if self.groups.contains_key(&group_id) {
+            tracing::warn!("Not resetting offsets of group {} with members", group_id);
+            return BrokerResponse::GroupNotEmpty { group_id };
+        }
+        let num_partitions = match self
+            .partitions_per_topic(vec![topic_name.clone()])
+            .await
+            .get(&topic_name)
+        {
+            Some(num_partitions) => *num_partitions,
+            None => return BrokerResponse::TopicNotFound { topic_name },
+        };
+
+        let mut offsets = vec![];
+        for partition_index in 0..num_partitions {
+            let topic_partition = TopicPartition::new(topic_name.clone(), partition_index);
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.topic_manager_tx
+                .send(TopicManagerCommands::GetPartitionReadInfo {
+                    topic_partition: topic_partition.clone(),
+                    reply_tx,
+                })
+                .await
+                .unwrap();
+            let read_info = match reply_rx.await.unwrap() {
+                Ok(read_info) => read_info,
+                Err(_) => return BrokerResponse::UnknownTopicPartition { topic_partition },
+            };
+            let offset = match to {
+                OffsetResetTarget::Earliest => read_info.log_start_offset,
+                OffsetResetTarget::Latest => read_info.log_end_offset,
+                OffsetResetTarget::Offset(offset) => {
+                    offset.clamp(read_info.log_start_offset, read_info.log_end_offset)
+                }
+                OffsetResetTarget::Timestamp(timestamp) => {
+                    offset_for_timestamp(&read_info.segment_file_path, timestamp)
+                        .await
+                        .unwrap_or(read_info.log_end_offset)
+                }
+            };
+            offsets.push(PartitionOffset {
+                topic_partition,
+                offset,
+            });
+        }
+
+        tracing::info!("Resetting offsets of group {} to {:?}", group_id, offsets);
+        self.commit_offsets(group_id.clone(), offsets.clone());
+        BrokerResponse::OffsetsReset { group_id, offsets }
+    }
+
+    /// Lag of every partition the group has committed offsets for or currently consumes.
+    async fn describe_group(&self, group_id: String) -> BrokerResponse {
+        let group = self.groups.get(&group_id);
+        let committed_offsets = self.committed_offsets.get(&group_id);
+        if group.is_none() && committed_offsets.is_none() {
+            return BrokerResponse::UnknownGroup { group_id };
+        }
+
+        let mut partition_owners: BTreeMap<TopicPartition, Option<String>> = BTreeMap::new();
+        for topic_partition in committed_offsets
+            .into_iter()
+            .flat_map(|offsets| offsets.keys())
+        {
+            partition_owners.insert(topic_partition.clone(), None);
+        }
+        for (member_id, member) in group.into_iter().flat_map(|group| group.members.iter()) {
+            for topic_partition in member.assignment.iter() {
+                partition_owners.insert(topic_partition.clone(), Some(member_id.clone()));
+            }
+        }
+
+        let mut log_end_offsets_per_topic: HashMap<String, Vec<u64>> = HashMap::new();
+        let mut partitions = vec![];
+        for (topic_partition, member_id) in partition_owners {
+            let topic_name = topic_partition.topic_name.clone();
+            if !log_end_offsets_per_topic.contains_key(&topic_name) {
+                let log_end_offsets = self.log_end_offsets(&topic_name).await;
+                log_end_offsets_per_topic.insert(topic_name.clone(), log_end_offsets);
+            }
+            let log_end_offset = log_end_offsets_per_topic[&topic_name]
+                .get(topic_partition.partition_index as usize)
+                .copied()
+                .unwrap_or(0);
+            let committed_offset = committed_offsets
+                .and_then(|offsets| offsets.get(&topic_partition))
+                .copied();
+            partitions.push(PartitionLag {
+                topic_partition,
+                member_id,
+                committed_offset,
+                log_end_offset,
+                lag: committed_offset.map(|offset| log_end_offset.saturating_sub(offset)),
+            });
+        }
+        BrokerResponse::GroupDescription {
+            group_id,
+            generation_id: group.map(|group| group.generation_id).unwrap_or(0),
+            partitions,
+        }
+    }
+
+    async fn log_end_offsets(&self, topic_name: &str) -> Vec<u64> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.topic_manager_tx
+            .send(TopicManagerCommands::GetLogEndOffsets {
+                topic_name: topic_name.to_string(),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap().unwrap_or_default()
+    }
+
+    async fn rebalance(&mut self, group_id: &str) {
+        let topic_names: Vec<String> = self.groups[group_id]
+            .members
+            .values()
+            .flat_map(|member| member.topics.clone())
+            .collect();
+        let partitions_per_topic = self.partitions_per_topic(topic_names).await;
+
+        let group = self.groups.get_mut(group_id).unwrap();
+        let subscriptions: Vec<MemberSubscription> = group
+            .members
+            .iter()
+            .map(|(member_id, member)| {
+                let mut subscription =
+                    MemberSubscription::new(member_id.clone(), member.topics.clone());
+                subscription.owned_partitions = member.assignment.clone();
+                subscription
+            })
+            .collect();
+        let assignor = assignor_for(group.strategy);
+        let mut assignment = assignor.assign(&subscriptions, &partitions_per_topic);
+        for (member_id, member) in group.members.iter_mut() {
+            member.target_assignment = assignment.remove(member_id).unwrap_or_default();
+            let target: HashSet<&TopicPartition> = member.target_assignment.iter().collect();
+            let (kept, revoked): (Vec<TopicPartition>, Vec<TopicPartition>) = member
+                .assignment
+                .drain(..)
+                .partition(|partition| target.contains(partition));
+            match group.protocol {
+                RebalanceProtocol::Eager => {
+                    member.assignment = member.target_assignment.clone();
+                    member.revoked_partitions = revoked;
+                }
+                RebalanceProtocol::Cooperative => {
+                    member.assignment = kept;
+                    // partitions still awaiting revocation which come back to the member are kept
+                    member
+                        .revoked_partitions
+                        .retain(|partition| !target.contains(partition));
+                    member.revoked_partitions.extend(revoked);
+                }
+            }
+        }
+        if group.protocol == RebalanceProtocol::Cooperative {
+            group.assign_released_partitions();
+        }
+        group.generation_id += 1;
+        tracing::info!(
+            "Group {} rebalanced with {} assignor, generation {}",
+            group_id,
+            assignor.name(),
+            group.generation_id
+        );
+    }
+
+    async fn partitions_per_topic(&self, mut topic_names: Vec<String>) -> HashMap<String, u8> {
+        topic_names.sort();
+        topic_names.dedup();
+        let mut partitions_per_topic = HashMap::new();
+        for topic_name in topic_names {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.topic_manager_tx
+                .send(TopicManagerCommands::GetTopicInfo {
+                    topic_name: topic_name.clone(),
+                    reply_tx,
+                })
+                .await
+                .unwrap();
+            match reply_rx.await.unwrap() {
+                Ok(topic) => {
+                    partitions_per_topic.insert(topic_name, topic.num_partitions.unwrap());
+                }
+                Err(e) => {
+                    tracing::warn!("Group subscribed to {}: {}", topic_name, e);
+                }
+            }
+        }
+        partitions_per_topic
+    }
+}
+
+pub enum GroupCoordinatorCommands {
+    JoinGroup {
+        request: JoinGroupRequest,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    Heartbeat {
+        group_id: String,
+        member_id: String,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Sent for every fetch of a group member, there is no reply.
+    RecordPoll { group_id: String, member_id: String },
+    LeaveGroup {
+        group_id: String,
+        member_id: String,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    GetAssignment {
+        group_id: String,
+        member_id: String,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    RevocationCompleted {
+        group_id: String,
+        member_id: String,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    CommitOffsets {
+        group_id: String,
+        offsets: Vec<PartitionOffset>,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    DescribeGroup {
+        group_id: String,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    ResetOffsets {
+        group_id: String,
+        topic_name: String,
+        to: OffsetResetTarget,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    GetCommittedOffset {
+        group_id: String,
+        topic_partition: TopicPartition,
+        reply_tx: oneshot::Sender<Option<u64>>,
+    },
+}
+
+/// Patterns have to match the whole topic name, like Kafka's pattern subscriptions.
+fn compile_topic_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{BrokerClock, MockClock};
+    use crate::cluster::ClusterSettings;
+    use crate::managers::topics_manager::TopicsManager;
+    use crate::metrics::Metrics;
+    use common::models::Topic;
+    use test_log::test;
+    use tokio::sync::mpsc;
+
+    async fn start_coordinator(
+        log_dir_path: String,
+        clock: Arc<dyn Clock>,
+        cancellation_token: CancellationToken,
+    ) -> (
+        Sender<GroupCoordinatorCommands>,
+        Sender<TopicManagerCommands>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let (topic_manager_tx, topic_manager_rx) = mpsc::channel(5);
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path,
+            1000,
+            ClusterSettings::default(),
+            &Metrics::new(),
+            tokio::runtime::Handle::current(),
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        );
+        let topic_events_rx = topics_manager.subscribe_topic_events();
+        tokio::spawn(async move {
+            topics_manager.start_topics_manager(topic_manager_rx).await;
+        });
+
+        let (coordinator_tx, coordinator_rx) = mpsc::channel(5);
+        let mut group_coordinator =
+            GroupCoordinator::new(topic_manager_tx.clone(), clock, cancellation_token);
+        let coordinator_handle = tokio::spawn(async move {
+            group_coordinator
+                .start_group_coordinator(coordinator_rx, topic_events_rx)
+                .await;
+        });
+        (coordinator_tx, topic_manager_tx, coordinator_handle)
+    }
+
+    async fn create_topic(
+        topic_manager_tx: &Sender<TopicManagerCommands>,
+        topic_name: &str,
+        num_partitions: u8,
+    ) {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        topic_manager_tx
+            .send(TopicManagerCommands::CreateTopic {
+                topic: Topic::new(
+                    topic_name.to_string(),
+                    Some(num_partitions),
+                    None,
+                    None,
+                    None,
+                    None,
+                ),
+                replicas: vec![vec![0]; num_partitions as usize],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap();
+    }
+
+    async fn start_coordinator_with_topic(
+        log_dir_path: String,
+        clock: Arc<dyn Clock>,
+        cancellation_token: CancellationToken,
+    ) -> (
+        Sender<GroupCoordinatorCommands>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let (coordinator_tx, topic_manager_tx, coordinator_handle) =
+            start_coordinator(log_dir_path, clock, cancellation_token).await;
+        create_topic(&topic_manager_tx, "test_topic", 4).await;
+        (coordinator_tx, coordinator_handle)
+    }
+
+    async fn join(
+        coordinator_tx: &Sender<GroupCoordinatorCommands>,
+        strategy: AssignmentStrategy,
+        protocol: RebalanceProtocol,
+    ) -> BrokerResponse {
+        join_static(coordinator_tx, None, strategy, protocol).await
+    }
+
+    async fn join_static(
+        coordinator_tx: &Sender<GroupCoordinatorCommands>,
+        group_instance_id: Option<String>,
+        strategy: AssignmentStrategy,
+        protocol: RebalanceProtocol,
+    ) -> BrokerResponse {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_tx
+            .send(GroupCoordinatorCommands::JoinGroup {
+                request: JoinGroupRequest {
+                    group_instance_id,
+                    strategy,
+                    protocol,
+                    ..JoinGroupRequest::new(
+                        "test_group".to_string(),
+                        Subscription::Topics(vec!["test_topic".to_string()]),
+                    )
+                },
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap()
+    }
+
+    async fn get_assignment(
+        coordinator_tx: &Sender<GroupCoordinatorCommands>,
+        member_id: String,
+    ) -> BrokerResponse {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_tx
+            .send(GroupCoordinatorCommands::GetAssignment {
+                group_id: "test_group".to_string(),
+                member_id,
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap()
+    }
+
+    fn joined_member_id(response: BrokerResponse) -> String {
+        match response {
+            BrokerResponse::GroupJoined { member_id, .. } => member_id,
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn test_group_coordinator_should_rebalance_when_members_join() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let (coordinator_tx, coordinator_handle) = start_coordinator_with_topic(
+            log_dir_path,
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        )
+        .await;
+
+        let first_join = join(
+            &coordinator_tx,
+            AssignmentStrategy::RoundRobin,
+            RebalanceProtocol::Eager,
+        )
+        .await;
+        let first_member_id = match first_join {
+            BrokerResponse::GroupJoined {
+                member_id,
+                generation_id,
+                assignment,
+                ..
+            } => {
+                assert_eq!(generation_id, 1);
+                assert_eq!(assignment.len(), 4);
+                member_id
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        let second_join = join(
+            &coordinator_tx,
+            AssignmentStrategy::RoundRobin,
+            RebalanceProtocol::Eager,
+        )
+        .await;
+        match second_join {
+            BrokerResponse::GroupJoined {
+                generation_id,
+                assignment,
+                ..
+            } => {
+                assert_eq!(generation_id, 2);
+                assert_eq!(assignment.len(), 2);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        match get_assignment(&coordinator_tx, first_member_id).await {
+            BrokerResponse::MemberAssignment {
+                generation_id,
+                assignment,
+                revoked_partitions,
+            } => {
+                assert_eq!(generation_id, 2);
+                assert_eq!(assignment.len(), 2);
+                assert_eq!(revoked_partitions.len(), 2);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        let response = join(
+            &coordinator_tx,
+            AssignmentStrategy::Range,
+            RebalanceProtocol::Eager,
+        )
+        .await;
+        assert_eq!(
+            response,
+            BrokerResponse::InconsistentGroupProtocol {
+                group_id: "test_group".to_string(),
+                strategy: AssignmentStrategy::RoundRobin,
+                protocol: RebalanceProtocol::Eager,
+            }
+        );
+
+        cancellation_token.cancel();
+        coordinator_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_cooperative_rebalance_should_wait_for_revocation_before_moving_partitions() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let (coordinator_tx, coordinator_handle) = start_coordinator_with_topic(
+            log_dir_path,
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        )
+        .await;
+
+        let first_member_id = joined_member_id(
+            join(
+                &coordinator_tx,
+                AssignmentStrategy::Sticky,
+                RebalanceProtocol::Cooperative,
+            )
+            .await,
+        );
+
+        let second_join = join(
+            &coordinator_tx,
+            AssignmentStrategy::Sticky,
+            RebalanceProtocol::Cooperative,
+        )
+        .await;
+        let second_member_id = match second_join {
+            BrokerResponse::GroupJoined {
+                member_id,
+                assignment,
+                ..
+            } => {
+                // the partitions moving to the new member are still owned by the first member
+                assert!(assignment.is_empty());
+                member_id
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        };
+
+        match get_assignment(&coordinator_tx, first_member_id.clone()).await {
+            BrokerResponse::MemberAssignment {
+                assignment,
+                revoked_partitions,
+                ..
+            } => {
+                assert_eq!(assignment.len(), 2);
+                assert_eq!(revoked_partitions.len(), 2);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_tx
+            .send(GroupCoordinatorCommands::RevocationCompleted {
+                group_id: "test_group".to_string(),
+                member_id: first_member_id,
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        match reply_rx.await.unwrap() {
+            BrokerResponse::MemberAssignment {
+                generation_id,
+                assignment,
+                revoked_partitions,
+            } => {
+                assert_eq!(generation_id, 3);
+                assert_eq!(assignment.len(), 2);
+                assert!(revoked_partitions.is_empty());
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        match get_assignment(&coordinator_tx, second_member_id).await {
+            BrokerResponse::MemberAssignment { assignment, .. } => {
+                assert_eq!(assignment.len(), 2);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        cancellation_token.cancel();
+        coordinator_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_static_member_should_rejoin_without_rebalance() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let (coordinator_tx, coordinator_handle) = start_coordinator_with_topic(
+            log_dir_path,
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        )
+        .await;
+
+        let static_join = join_static(
+            &coordinator_tx,
+            Some("consumer-host-1".to_string()),
+            AssignmentStrategy::Range,
+            RebalanceProtocol::Eager,
+        )
+        .await;
+        join(
+            &coordinator_tx,
+            AssignmentStrategy::Range,
+            RebalanceProtocol::Eager,
+        )
+        .await;
+        let static_member_id = joined_member_id(static_join);
+        let assignment_before_restart =
+            get_assignment(&coordinator_tx, static_member_id.clone()).await;
+
+        // the restarted consumer does not know its member id anymore, only its instance id
+        let rejoin = join_static(
+            &coordinator_tx,
+            Some("consumer-host-1".to_string()),
+            AssignmentStrategy::Range,
+            RebalanceProtocol::Eager,
+        )
+        .await;
+        match (rejoin, assignment_before_restart) {
+            (
+                BrokerResponse::GroupJoined {
+                    member_id,
+                    generation_id,
+                    assignment,
+                    ..
+                },
+                BrokerResponse::MemberAssignment {
+                    generation_id: generation_before_restart,
+                    assignment: assignment_before_restart,
+                    ..
+                },
+            ) => {
+                assert_eq!(member_id, static_member_id);
+                assert_eq!(generation_id, generation_before_restart);
+                assert_eq!(assignment, assignment_before_restart);
+            }
+            responses => panic!("Unexpected responses: {:?}", responses),
+        }
+
+        cancellation_token.cancel();
+        coordinator_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_describe_group_should_report_lag_per_partition() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let (coordinator_tx, coordinator_handle) = start_coordinator_with_topic(
+            log_dir_path,
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        )
+        .await;
+
+        let member_id = joined_member_id(
+            join(
+                &coordinator_tx,
+                AssignmentStrategy::Range,
+                RebalanceProtocol::Eager,
+            )
+            .await,
+        );
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_tx
+            .send(GroupCoordinatorCommands::CommitOffsets {
+                group_id: "test_group".to_string(),
+                offsets: vec![PartitionOffset {
+                    topic_partition: TopicPartition::new("test_topic".to_string(), 1),
+                    offset: 0,
+                }],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply_rx.await.unwrap(), BrokerResponse::OffsetsCommitted);
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_tx
+            .send(GroupCoordinatorCommands::DescribeGroup {
+                group_id: "test_group".to_string(),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        match reply_rx.await.unwrap() {
+            BrokerResponse::GroupDescription { partitions, .. } => {
+                assert_eq!(partitions.len(), 4);
+                assert_eq!(
+                    partitions[1],
+                    PartitionLag {
+                        topic_partition: TopicPartition::new("test_topic".to_string(), 1),
+                        member_id: Some(member_id),
+                        committed_offset: Some(0),
+                        log_end_offset: 0,
+                        lag: Some(0),
+                    }
+                );
+                assert_eq!(partitions[0].lag, None);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        cancellation_token.cancel();
+        coordinator_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_reset_offsets_should_refuse_groups_with_members() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let (coordinator_tx, coordinator_handle) = start_coordinator_with_topic(
+            log_dir_path,
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        )
+        .await;
+
+        let reset_offsets = |to: OffsetResetTarget| {
+            let coordinator_tx = coordinator_tx.clone();
+            async move {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                coordinator_tx
+                    .send(GroupCoordinatorCommands::ResetOffsets {
+                        group_id: "test_group".to_string(),
+                        topic_name: "test_topic".to_string(),
+                        to,
+                        reply_tx,
+                    })
+                    .await
+                    .unwrap();
+                reply_rx.await.unwrap()
+            }
+        };
+
+        let member_id = joined_member_id(
+            join(
+                &coordinator_tx,
+                AssignmentStrategy::Range,
+                RebalanceProtocol::Eager,
+            )
+            .await,
+        );
+        assert_eq!(
+            reset_offsets(OffsetResetTarget::Earliest).await,
+            BrokerResponse::GroupNotEmpty {
+                group_id: "test_group".to_string()
+            }
+        );
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_tx
+            .send(GroupCoordinatorCommands::LeaveGroup {
+                group_id: "test_group".to_string(),
+                member_id,
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap();
+
+        match reset_offsets(OffsetResetTarget::Offset(10)).await {
+            BrokerResponse::OffsetsReset { offsets, .. } => {
+                assert_eq!(offsets.len(), 4);
+                // nothing was written to the topic, so every offset is clamped to 0
+                assert!(offsets.iter().all(|offset| offset.offset == 0));
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        cancellation_token.cancel();
+        coordinator_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_pattern_subscription_should_pick_up_created_topics() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let (coordinator_tx, topic_manager_tx, coordinator_handle) = start_coordinator(
+            log_dir_path,
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        )
+        .await;
+        create_topic(&topic_manager_tx, "metrics.cpu", 1).await;
+        create_topic(&topic_manager_tx, "logs", 1).await;
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_tx
+            .send(GroupCoordinatorCommands::JoinGroup {
+                request: JoinGroupRequest::new(
+                    "test_group".to_string(),
+                    Subscription::Pattern(r"metrics\..*".to_string()),
+                ),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        let (member_id, assignment) = match reply_rx.await.unwrap() {
+            BrokerResponse::GroupJoined {
+                member_id,
+                assignment,
+                ..
+            } => (member_id, assignment),
+            response => panic!("Unexpected response: {:?}", response),
+        };
+        assert_eq!(
+            assignment,
+            vec![TopicPartition::new("metrics.cpu".to_string(), 0)]
+        );
+
+        create_topic(&topic_manager_tx, "metrics.memory", 1).await;
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        match get_assignment(&coordinator_tx, member_id).await {
+            BrokerResponse::MemberAssignment {
+                generation_id,
+                assignment,
+                ..
+            } => {
+                assert_eq!(generation_id, 2);
+                assert_eq!(
+                    assignment,
+                    vec![
+                        TopicPartition::new("metrics.cpu".to_string(), 0),
+                        TopicPartition::new("metrics.memory".to_string(), 0),
+                    ]
+                );
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        cancellation_token.cancel();
+        coordinator_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_members_without_heartbeats_should_be_evicted() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let clock = Arc::new(MockClock::new());
+        let (coordinator_tx, coordinator_handle) =
+            start_coordinator_with_topic(log_dir_path, clock.clone(), cancellation_token.clone())
+                .await;
+
+        let mut member_ids = vec![];
+        for session_timeout_ms in [10_000, 100] {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            coordinator_tx
+                .send(GroupCoordinatorCommands::JoinGroup {
+                    request: JoinGroupRequest {
+                        session_timeout_ms,
+                        ..JoinGroupRequest::new(
+                            "test_group".to_string(),
+                            Subscription::Topics(vec!["test_topic".to_string()]),
+                        )
+                    },
+                    reply_tx,
+                })
+                .await
+                .unwrap();
+            member_ids.push(joined_member_id(reply_rx.await.unwrap()));
+        }
+
+        clock.advance(Duration::from_millis(1000)).await;
+
+        let heartbeat = |member_id: String| {
+            let coordinator_tx = coordinator_tx.clone();
+            async move {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                coordinator_tx
+                    .send(GroupCoordinatorCommands::Heartbeat {
+                        group_id: "test_group".to_string(),
+                        member_id,
+                        reply_tx,
+                    })
+                    .await
+                    .unwrap();
+                reply_rx.await.unwrap()
+            }
+        };
+        assert_eq!(
+            heartbeat(member_ids[1].clone()).await,
+            BrokerResponse::UnknownGroupMember {
+                group_id: "test_group".to_string(),
+                member_id: member_ids[1].clone(),
+            }
+        );
+        assert_eq!(
+            heartbeat(member_ids[0].clone()).await,
+            BrokerResponse::HeartbeatAccepted { generation_id: 3 }
+        );
+        match get_assignment(&coordinator_tx, member_ids[0].clone()).await {
+            BrokerResponse::MemberAssignment { assignment, .. } => {
+                assert_eq!(assignment.len(), 4);
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+
+        cancellation_token.cancel();
+        coordinator_handle.await.unwrap();
+    }
+}
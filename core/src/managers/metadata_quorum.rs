@@ -0,0 +1,508 @@
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, RandomState};
+use std::io;
+use std::time::{Duration, Instant};
+
+use common::models::{
+    AppendEntriesRequest, BrokerRegistration, BrokerResponse, MetadataRecord, Topic, TopicCommand,
+    VoteRequest,
+};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::cluster::{send_request, BrokerId, ClusterSettings};
+use crate::managers::topics_manager::{OngoingReassignment, TopicEvent, TopicManagerCommands};
+use crate::raft::{RaftNode, Role};
+
+/// Directory of the metadata log within the log directory.
+const METADATA_DIR_NAME: &str = "__cluster_metadata";
+/// Time between two append entries requests of the quorum's leader, empty ones are heartbeats.
+const QUORUM_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+/// Followers which did not hear from a leader within this time and a random share of it again
+/// start an election, so brokers rarely start elections at the same time.
+const QUORUM_ELECTION_TIMEOUT: Duration = Duration::from_millis(1500);
+const QUORUM_TICK_INTERVAL: Duration = Duration::from_millis(50);
+/// Requests to other brokers of the quorum which are not answered within this time fail.
+const QUORUM_REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+const QUORUM_EVENTS_CHANNEL_SIZE: usize = 100;
+
+pub enum MetadataQuorumCommands {
+    /// Creates a topic with its partitions spread over the brokers and their racks, answered once
+    /// this broker created it.
+    CreateTopic {
+        topic: Topic,
+        reply_tx: oneshot::Sender<io::Result<()>>,
+    },
+    /// Appends a record to the metadata log, answered once this broker applied it.
+    Propose {
+        record: MetadataRecord,
+        reply_tx: oneshot::Sender<io::Result<()>>,
+    },
+    /// A record another broker proposed, answered with its index in the log.
+    ProposeForwarded {
+        record: MetadataRecord,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    RequestVote {
+        request: VoteRequest,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    AppendEntries {
+        request: AppendEntriesRequest,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Broker leading the metadata quorum, which is the active controller.
+    GetLeaderId {
+        reply_tx: oneshot::Sender<Option<BrokerId>>,
+    },
+    /// Brokers registered in the metadata log, ordered by their IDs.
+    GetBrokers {
+        reply_tx: oneshot::Sender<Vec<BrokerRegistration>>,
+    },
+}
+
+/// Answers of other brokers to requests sent in the background.
+enum QuorumEvent {
+    VoteResult {
+        from: BrokerId,
+        term: u64,
+        vote_granted: bool,
+    },
+    EntriesAppended {
+        from: BrokerId,
+        term: u64,
+        success: bool,
+        match_index: u64,
+    },
+    /// The quorum's leader appended a record this broker proposed at `index`.
+    Proposed {
+        index: u64,
+        reply_tx: oneshot::Sender<io::Result<()>>,
+    },
+}
+
+/// Cluster metadata replicated with Raft, every broker of the cluster is a voter. Topics and
+/// partition leaders change by appending records to the metadata log, which are applied to the
+/// topics manager of every broker once a majority of the brokers stored them. So all brokers end
+/// up with the same topics and leaders, and a restarted broker restores them from its log.
+pub struct MetadataQuorum {
+    raft_node: RaftNode,
+    cluster_settings: ClusterSettings,
+    topic_manager_tx: Sender<TopicManagerCommands>,
+    /// Proposals waiting until a leader of the quorum is known.
+    pending_proposals: Vec<(MetadataRecord, oneshot::Sender<io::Result<()>>)>,
+    /// Proposals waiting until this broker applied the log up to their index.
+    applied_waiters: BTreeMap<u64, Vec<oneshot::Sender<io::Result<()>>>>,
+    registered_brokers: BTreeMap<BrokerId, BrokerRegistration>,
+    election_deadline: Instant,
+    last_heartbeat: Instant,
+    cancellation_token: CancellationToken,
+}
+
+impl MetadataQuorum {
+    pub fn new(
+        cluster_settings: ClusterSettings,
+        topic_manager_tx: Sender<TopicManagerCommands>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        let raft_node = RaftNode::load(
+            cluster_settings.broker_id,
+            cluster_settings.peers.keys().copied().collect(),
+            &format!("{}/{}", cluster_settings.log_dir_path, METADATA_DIR_NAME),
+        );
+        MetadataQuorum {
+            raft_node,
+            cluster_settings,
+            topic_manager_tx,
+            pending_proposals: vec![],
+            applied_waiters: BTreeMap::new(),
+            registered_brokers: BTreeMap::new(),
+            election_deadline: Instant::now(),
+            last_heartbeat: Instant::now(),
+            cancellation_token,
+        }
+    }
+
+    pub async fn start_metadata_quorum(
+        mut self,
+        mut commands_rx: Receiver<MetadataQuorumCommands>,
+        mut topic_events_rx: broadcast::Receiver<TopicEvent>,
+    ) {
+        tracing::info!("Metadata quorum started");
+        let (events_tx, mut events_rx) = mpsc::channel(QUORUM_EVENTS_CHANNEL_SIZE);
+        // a broker without peers is the quorum on its own
+        if !self.raft_node.peer_ids().is_empty() {
+            self.reset_election_deadline();
+        }
+        let mut tick_interval = tokio::time::interval(QUORUM_TICK_INTERVAL);
+        let cancellation_token = self.cancellation_token.clone();
+        loop {
+            tokio::select! {
+                Some(command) = commands_rx.recv() => {
+                    self.handle_command(command, &events_tx);
+                }
+                Some(event) = events_rx.recv() => {
+                    self.handle_event(event);
+                }
+                topic_event = topic_events_rx.recv() => {
+                    // leaders report ISR changes through the log, so the controller elects from it
+                    if let Ok(TopicEvent::IsrChanged { topic_partition, leader_and_isr }) = topic_event {
+                        let record = MetadataRecord::LeaderAndIsrChanged {
+                            topic_partition,
+                            leader_and_isr,
+                        };
+                        self.propose(record, oneshot::channel().0, &events_tx);
+                    }
+                }
+                _ = tick_interval.tick() => {
+                    self.tick(&events_tx);
+                }
+                _ = cancellation_token.cancelled() => {
+                    tracing::info!("Cancellation token received for metadata quorum.");
+                    break;
+                }
+            }
+            self.propose_pending(&events_tx);
+            self.apply_committed().await;
+        }
+    }
+
+    fn handle_command(&mut self, command: MetadataQuorumCommands, events_tx: &Sender<QuorumEvent>) {
+        match command {
+            MetadataQuorumCommands::CreateTopic { topic, reply_tx } => {
+                let registered_brokers: Vec<BrokerRegistration> =
+                    self.registered_brokers.values().cloned().collect();
+                let replicas = (0..topic.num_partitions.unwrap_or(1))
+                    .map(|partition_index| {
+                        self.cluster_settings.replicas(
+                            &registered_brokers,
+                            partition_index,
+                            topic.replication_factor.unwrap_or(1),
+                        )
+                    })
+                    .collect();
+                let record = MetadataRecord::TopicCreated { topic, replicas };
+                self.propose(record, reply_tx, events_tx);
+            }
+            MetadataQuorumCommands::Propose { record, reply_tx } => {
+                self.propose(record, reply_tx, events_tx);
+            }
+            MetadataQuorumCommands::ProposeForwarded { record, reply_tx } => {
+                let response = match self.raft_node.propose(record) {
+                    Some(index) => {
+                        self.replicate(events_tx);
+                        BrokerResponse::MetadataProposed { index }
+                    }
+                    None => BrokerResponse::NotQuorumLeader {
+                        leader_id: self.raft_node.leader_id(),
+                    },
+                };
+                reply_tx.send(response).unwrap();
+            }
+            MetadataQuorumCommands::RequestVote { request, reply_tx } => {
+                let (term, vote_granted) = self.raft_node.handle_vote_request(&request);
+                if vote_granted {
+                    self.reset_election_deadline();
+                }
+                reply_tx
+                    .send(BrokerResponse::VoteResult { term, vote_granted })
+                    .unwrap();
+            }
+            MetadataQuorumCommands::AppendEntries { request, reply_tx } => {
+                let request_term = request.term;
+                let (term, success, match_index) = self.raft_node.handle_append_entries(request);
+                if term == request_term {
+                    self.reset_election_deadline();
+                }
+                reply_tx
+                    .send(BrokerResponse::EntriesAppended {
+                        term,
+                        success,
+                        match_index,
+                    })
+                    .unwrap();
+            }
+            MetadataQuorumCommands::GetLeaderId { reply_tx } => {
+                reply_tx.send(self.raft_node.leader_id()).unwrap();
+            }
+            MetadataQuorumCommands::GetBrokers { reply_tx } => {
+                reply_tx
+                    .send(self.registered_brokers.values().cloned().collect())
+                    .unwrap();
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: QuorumEvent) {
+        match event {
+            QuorumEvent::VoteResult {
+                from,
+                term,
+                vote_granted,
+            } => {
+                let was_leader = self.raft_node.role() == Role::Leader;
+                self.raft_node.handle_vote_result(from, term, vote_granted);
+                if !was_leader && self.raft_node.role() == Role::Leader {
+                    // replicate the new leader's first entry right away
+                    self.last_heartbeat = Instant::now() - QUORUM_HEARTBEAT_INTERVAL;
+                }
+            }
+            QuorumEvent::EntriesAppended {
+                from,
+                term,
+                success,
+                match_index,
+            } => {
+                self.raft_node
+                    .handle_entries_appended(from, term, success, match_index);
+            }
+            QuorumEvent::Proposed { index, reply_tx } => {
+                if self.raft_node.last_applied() >= index {
+                    let _ = reply_tx.send(Ok(()));
+                } else {
+                    self.applied_waiters
+                        .entry(index)
+                        .or_default()
+                        .push(reply_tx);
+                }
+            }
+        }
+    }
+
+    /// Appends the record when this broker leads the quorum, else forwards it to the leader.
+    fn propose(
+        &mut self,
+        record: MetadataRecord,
+        reply_tx: oneshot::Sender<io::Result<()>>,
+        events_tx: &Sender<QuorumEvent>,
+    ) {
+        if let Some(index) = self.raft_node.propose(record.clone()) {
+            self.applied_waiters
+                .entry(index)
+                .or_default()
+                .push(reply_tx);
+            self.replicate(events_tx);
+            return;
+        }
+        let Some(leader_id) = self.raft_node.leader_id() else {
+            self.pending_proposals.push((record, reply_tx));
+            return;
+        };
+        let Some(leader_address) = self.cluster_settings.peer_address(leader_id) else {
+            let _ = reply_tx.send(Err(io::Error::other(format!(
+                "No address of broker {} leading the metadata quorum",
+                leader_id
+            ))));
+            return;
+        };
+        let leader_address = leader_address.to_string();
+        let events_tx = events_tx.clone();
+        tokio::spawn(async move {
+            let command = TopicCommand::ProposeMetadata { record };
+            let response = tokio::time::timeout(
+                QUORUM_REQUEST_TIMEOUT,
+                send_request(&leader_address, &command),
+            )
+            .await;
+            match response {
+                Ok(Ok(BrokerResponse::MetadataProposed { index })) => {
+                    let _ = events_tx
+                        .send(QuorumEvent::Proposed { index, reply_tx })
+                        .await;
+                }
+                response => {
+                    let _ = reply_tx.send(Err(io::Error::other(format!(
+                        "{} did not take the proposal: {:?}",
+                        leader_address, response
+                    ))));
+                }
+            }
+        });
+    }
+
+    fn propose_pending(&mut self, events_tx: &Sender<QuorumEvent>) {
+        if self.raft_node.leader_id().is_none() {
+            return;
+        }
+        for (record, reply_tx) in std::mem::take(&mut self.pending_proposals) {
+            self.propose(record, reply_tx, events_tx);
+        }
+    }
+
+    fn tick(&mut self, events_tx: &Sender<QuorumEvent>) {
+        let now = Instant::now();
+        if self.raft_node.role() == Role::Leader {
+            if now.duration_since(self.last_heartbeat) >= QUORUM_HEARTBEAT_INTERVAL {
+                self.replicate(events_tx);
+            }
+            return;
+        }
+        if now < self.election_deadline {
+            return;
+        }
+        self.reset_election_deadline();
+        let vote_request = self.raft_node.start_election();
+        for peer_id in self.raft_node.peer_ids() {
+            let Some(peer_address) = self.cluster_settings.peer_address(*peer_id) else {
+                continue;
+            };
+            let from = *peer_id;
+            let peer_address = peer_address.to_string();
+            let command = TopicCommand::RequestVote(vote_request.clone());
+            let events_tx = events_tx.clone();
+            tokio::spawn(async move {
+                let response = tokio::time::timeout(
+                    QUORUM_REQUEST_TIMEOUT,
+                    send_request(&peer_address, &command),
+                )
+                .await;
+                if let Ok(Ok(BrokerResponse::VoteResult { term, vote_granted })) = response {
+                    let _ = events_tx
+                        .send(QuorumEvent::VoteResult {
+                            from,
+                            term,
+                            vote_granted,
+                        })
+                        .await;
+                }
+            });
+        }
+    }
+
+    /// Sends every follower the entries it misses, or a heartbeat.
+    fn replicate(&mut self, events_tx: &Sender<QuorumEvent>) {
+        self.last_heartbeat = Instant::now();
+        for peer_id in self.raft_node.peer_ids() {
+            let Some(peer_address) = self.cluster_settings.peer_address(*peer_id) else {
+                continue;
+            };
+            let from = *peer_id;
+            let peer_address = peer_address.to_string();
+            let command = TopicCommand::AppendEntries(self.raft_node.append_entries_request(from));
+            let events_tx = events_tx.clone();
+            tokio::spawn(async move {
+                let response = tokio::time::timeout(
+                    QUORUM_REQUEST_TIMEOUT,
+                    send_request(&peer_address, &command),
+                )
+                .await;
+                if let Ok(Ok(BrokerResponse::EntriesAppended {
+                    term,
+                    success,
+                    match_index,
+                })) = response
+                {
+                    let _ = events_tx
+                        .send(QuorumEvent::EntriesAppended {
+                            from,
+                            term,
+                            success,
+                            match_index,
+                        })
+                        .await;
+                }
+            });
+        }
+    }
+
+    /// Applies the committed records to the topics manager and the registered brokers, in log
+    /// order.
+    async fn apply_committed(&mut self) {
+        while let Some((index, record)) = self.raft_node.next_committed() {
+            tracing::debug!("Applying metadata record {}: {:?}", index, record);
+            match record {
+                MetadataRecord::Noop => {}
+                MetadataRecord::TopicCreated { topic, replicas } => {
+                    let (reply_tx, reply_rx) = oneshot::channel();
+                    self.topic_manager_tx
+                        .send(TopicManagerCommands::CreateTopic {
+                            topic,
+                            replicas,
+                            reply_tx,
+                        })
+                        .await
+                        .unwrap();
+                    reply_rx.await.unwrap();
+                }
+                MetadataRecord::LeaderAndIsrChanged {
+                    topic_partition,
+                    leader_and_isr,
+                } => {
+                    self.topic_manager_tx
+                        .send(TopicManagerCommands::UpdateLeaderAndIsr {
+                            topic_partition,
+                            leader_and_isr,
+                        })
+                        .await
+                        .unwrap();
+                }
+                MetadataRecord::ReplicasChanged {
+                    topic_partition,
+                    replicas,
+                    adding_replicas,
+                    removing_replicas,
+                    throttle_rate,
+                    leader_and_isr,
+                } => {
+                    self.topic_manager_tx
+                        .send(TopicManagerCommands::UpdateReplicas {
+                            topic_partition,
+                            replicas,
+                            reassignment: OngoingReassignment {
+                                adding_replicas,
+                                removing_replicas,
+                                throttle_rate,
+                            },
+                            leader_and_isr,
+                        })
+                        .await
+                        .unwrap();
+                }
+                MetadataRecord::TopicDeleted { topic_name } => {
+                    let (reply_tx, reply_rx) = oneshot::channel();
+                    self.topic_manager_tx
+                        .send(TopicManagerCommands::DeleteTopic {
+                            topic_name,
+                            reply_tx,
+                        })
+                        .await
+                        .unwrap();
+                    // a replayed deletion finds the topic deleted already
+                    let _ = reply_rx.await.unwrap();
+                }
+                MetadataRecord::BrokerRegistered(registration) => {
+                    tracing::info!(
+                        "Broker {} registered at {} in rack {:?}",
+                        registration.broker_id,
+                        registration.address,
+                        registration.rack
+                    );
+                    self.registered_brokers
+                        .insert(registration.broker_id, registration);
+                }
+                MetadataRecord::BrokerUnregistered { broker_id } => {
+                    tracing::info!("Broker {} was decommissioned", broker_id);
+                    self.registered_brokers.remove(&broker_id);
+                }
+            }
+        }
+        let still_waiting = self
+            .applied_waiters
+            .split_off(&(self.raft_node.last_applied() + 1));
+        for reply_tx in std::mem::replace(&mut self.applied_waiters, still_waiting)
+            .into_values()
+            .flatten()
+        {
+            let _ = reply_tx.send(Ok(()));
+        }
+    }
+
+    fn reset_election_deadline(&mut self) {
+        let jitter = RandomState::new().hash_one(Instant::now())
+            % QUORUM_ELECTION_TIMEOUT.as_millis() as u64;
+        self.election_deadline =
+            Instant::now() + QUORUM_ELECTION_TIMEOUT + Duration::from_millis(jitter);
+    }
+}
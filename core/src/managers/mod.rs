@@ -1,3 +1,7 @@
 pub mod consumer_manager;
+pub mod controller;
+pub mod group_coordinator;
+pub mod metadata_quorum;
 pub mod partition_manager;
+pub mod replica_fetcher;
 pub mod topics_manager;
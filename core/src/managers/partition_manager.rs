@@ -1,20 +1,65 @@
-use std::fs;
+use std::collections::HashMap;
+use std::io::IoSlice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fs, io};
 
 use bytes::BytesMut;
-use common::codecs::encoder::BatchEncoder;
-use common::models::{Batch, Message};
+use common::clock::now_millis;
+use common::errors::ProduceError;
+use common::models::{
+    Acks, Batch, CompressionCodec, FetchedBatch, Message, ProducerSequence, RecordBatch,
+    TimestampType, TopicPartition,
+};
 use tokio::io::AsyncWriteExt;
-use tokio::{fs::OpenOptions, sync::mpsc};
-use tokio_util::codec::Encoder;
+use tokio::{
+    fs::{File, OpenOptions},
+    sync::oneshot,
+};
+use tokio_util::codec::{Decoder, Encoder};
 use tokio_util::sync::CancellationToken;
 
-use crate::models::PartitionInfo;
+use crate::buffer_pool::BufferPool;
+use crate::clock::Clock;
+use crate::encryption::{SegmentDecoder, SegmentEncoder};
+use crate::metrics::{Metrics, PartitionMetrics, TopicMetrics};
+use crate::models::{AppendedBatch, PartitionInfo};
+use crate::partition_queue::{PartitionReceiver, PartitionWrite};
 
+/// What the partition writers of the broker share: how long records linger before they are
+/// written, the buffers batches are encoded into, the metrics and the clock.
+#[derive(Clone)]
+pub struct PartitionWriterContext {
+    pub linger: Duration,
+    pub segment_buffers: BufferPool,
+    pub metrics: Metrics,
+    pub clock: Arc<dyn Clock>,
+}
+
+/// Appends the messages received on `peers_rx` to the partition's segment file in batches.
+/// Uncompressed records are written together once `batch_size` of them arrived or `linger`
+/// after the first of them, whichever comes first, so a high rate of small appends costs a
+/// few writes. Compressed batches and batches of idempotent producers are appended once no more
+/// writes are waiting, a burst of them together with the pending records in one vectored write.
+/// Batches of topics using log append time are stamped when they are written, batches a leader
+/// stamped already keep its time.
+/// `log_end_offset` is kept at the number of records written to the segment, i.e.
+/// the offset the next written record will get, and is restored from the segment file on
+/// startup and after a follower truncated the segment.
 pub async fn start_partition_writer(
     partition_info: PartitionInfo,
-    mut peers_rx: mpsc::Receiver<Message>,
+    mut peers_rx: PartitionReceiver,
+    log_end_offset: Arc<AtomicU64>,
+    context: PartitionWriterContext,
     cancellation_token: CancellationToken,
 ) {
+    let PartitionWriterContext {
+        linger,
+        segment_buffers,
+        metrics,
+        clock,
+    } = context;
     tracing::info!(
         "Starting partition manager for {} : {}",
         partition_info.topic.name,
@@ -34,56 +79,138 @@ pub async fn start_partition_writer(
             );
         }
     }
-    let segment_file_path = format!("{}/{}", partition_info.partition_path, "segment_0.log");
+    let segment_file_path = partition_info.segment_file_path();
     tracing::info!("Segment file path: {}", segment_file_path);
+    let recovered_segment = recover_segment(&segment_file_path);
+    log_end_offset.store(recovered_segment.log_end_offset, Ordering::SeqCst);
+    tracing::info!(
+        "Log end offset restored to {}",
+        recovered_segment.log_end_offset
+    );
+    let mut producer_states = recovered_segment.producer_states;
     let mut file = OpenOptions::new()
         .append(true)
         .create(true)
-        .open(segment_file_path)
+        .open(&segment_file_path)
         .await
         .unwrap();
-    let mut current_batch = Batch { records: vec![] };
-    let mut batch_encoder = BatchEncoder {};
+    let topic_partition = TopicPartition::new(
+        partition_info.topic.name.clone(),
+        partition_info.partition_index,
+    );
+    let append_metrics = AppendMetrics::new(&metrics, &topic_partition, &segment_file_path);
+    let batch_size = partition_info.topic.batch_size.unwrap() as usize;
+    let timestamp_type = partition_info.topic.timestamp_type.unwrap_or_default();
+    let mut pending_write = PendingWrite::default();
     loop {
+        let linger_deadline = pending_write.deadline;
         tokio::select! {
-            Some(message) = peers_rx.recv() => {
-                tracing::info!("Received message: {:?}", message);
-                current_batch.records.push(message);
-                if current_batch.records.len() >= partition_info.topic.batch_size.unwrap() as usize {
-                    let mut encoded_batch = BytesMut::new();
-                    match batch_encoder.encode(current_batch.clone(), &mut encoded_batch) {
-                        Ok(_) => {
-                            file.write_all(&encoded_batch)
-                                .await
-                                .expect("Failed to write to segment file");
-                            file.flush().await.expect("Failed to flush segment file");
-                            tracing::info!("Wrote batch of {} messages to file", current_batch.records.len());
-                            current_batch = Batch { records: vec![] };
+            Some(write) = peers_rx.recv() => {
+                let append = match write {
+                    PartitionWrite::Append(append) => append,
+                    PartitionWrite::Truncate { offset, log_end_offset_tx } => {
+                        // the segment is not truncated when its pending records could not be written
+                        let result = match pending_write.write(&mut file, &log_end_offset, &mut producer_states, &segment_buffers, &append_metrics, timestamp_type).await {
+                            Ok(()) => truncate_segment(&segment_file_path, offset),
+                            Err(e) => Err(e),
+                        };
+                        if result.is_ok() {
+                            // producer states of the removed batches are forgotten
+                            let recovered_segment = recover_segment(&segment_file_path);
+                            log_end_offset.store(recovered_segment.log_end_offset, Ordering::SeqCst);
+                            producer_states = recovered_segment.producer_states;
+                            append_metrics.record_truncation(&segment_file_path);
                         }
-                        Err(e) => {
-                            tracing::error!("Failed to encode batch: {:?}", e);
+                        let _ = log_end_offset_tx.send(result);
+                        continue;
+                    }
+                };
+                let batch = append.batch;
+                tracing::info!("Received {} messages", batch.record_count);
+                if batch.producer.is_some()
+                    || batch.compression != CompressionCodec::None
+                    || batch.timestamp_type == TimestampType::LogAppendTime
+                {
+                    // batches of idempotent producers are stored on their own to keep their
+                    // sequence, compressed batches to be served without decompressing them and
+                    // stamped batches of the leader to keep its log append time
+                    let sequence_check = match batch.producer {
+                        Some(producer) => {
+                            let state = pending_write.producer_states.get(&producer.producer_id)
+                                .or_else(|| producer_states.get(&producer.producer_id));
+                            check_sequence(state, producer)
                         }
+                        None => Ok(None),
+                    };
+                    let result = match sequence_check {
+                        Ok(Some(appended_batch)) => {
+                            tracing::info!("Ignoring retried batch {:?}", batch.producer);
+                            Ok(appended_batch)
+                        }
+                        Ok(None) => {
+                            let base_offset = pending_write.next_offset(&log_end_offset);
+                            if let Some(producer) = batch.producer {
+                                pending_write.producer_states.insert(producer.producer_id, ProducerState::new(producer, batch.record_count, base_offset));
+                            }
+                            pending_write.add_batch(batch);
+                            // stamped once it is written
+                            Ok(AppendedBatch { base_offset, log_append_time: None })
+                        }
+                        Err(expected_sequence) => Err(ProduceError::OutOfOrderSequence {
+                            topic_partition: topic_partition.clone(),
+                            expected_sequence,
+                            sequence: batch.producer.map_or(0, |producer| producer.base_sequence),
+                        }),
+                    };
+                    match (append.base_offset_tx, result) {
+                        // acknowledged once the batch, or the retried one, was written
+                        (Some(base_offset_tx), Ok(appended_batch)) if appended_batch.base_offset >= log_end_offset.load(Ordering::SeqCst) => {
+                            pending_write.base_offset_txs.push((appended_batch.base_offset, base_offset_tx))
+                        }
+                        (Some(base_offset_tx), result) => {
+                            let _ = base_offset_tx.send(result);
+                        }
+                        (None, _) => {}
                     }
                 } else {
-                    tracing::info!("Batch size not reached yet. Current batch size: {}, batch size for topic: {}", current_batch.records.len(), partition_info.topic.batch_size.unwrap());
-                }
-            }
-            _ = cancellation_token.cancelled() => {
-                if !current_batch.records.is_empty() {
-                    let mut encoded_batch = BytesMut::new();
-                    match batch_encoder.encode(current_batch.clone(), &mut encoded_batch) {
-                        Ok(_) => {
-                            file.write_all(&encoded_batch)
-                                .await
-                                .expect("Failed to write to segment file");
-                            file.flush().await.expect("Failed to flush segment file");
-                            tracing::info!("Flushed last batch of {} messages to file", current_batch.records.len());
-                        }
+                    let records = match batch.records() {
+                        Ok(records) => records,
                         Err(e) => {
-                            tracing::error!("Failed to encode batch: {:?}", e);
+                            tracing::error!("Failed to decode records: {:?}", e);
+                            if let Some(base_offset_tx) = append.base_offset_tx {
+                                let _ = base_offset_tx.send(Err(ProduceError::InvalidBatch(e.to_string())));
+                            }
+                            continue;
+                        }
+                    };
+                    let base_offset = pending_write.next_offset(&log_end_offset);
+                    pending_write.add(records, clock.now() + linger);
+                    match append.base_offset_tx {
+                        // acknowledged once the records were written
+                        Some(base_offset_tx) if append.acks > Acks::None => {
+                            pending_write.base_offset_txs.push((base_offset, base_offset_tx))
                         }
+                        Some(base_offset_tx) => {
+                            let _ = base_offset_tx.send(Ok(AppendedBatch { base_offset, log_append_time: None }));
+                        }
+                        None => {}
                     }
                 }
+                // batches stored as received are written once no more writes are waiting, so
+                // a burst of them is appended with one write
+                let batch_full = pending_write.batch.records.len() >= batch_size || linger.is_zero();
+                if batch_full || (!pending_write.batches.is_empty() && peers_rx.is_empty()) {
+                    let _ = pending_write.write(&mut file, &log_end_offset, &mut producer_states, &segment_buffers, &append_metrics, timestamp_type).await;
+                }
+            }
+            _ = clock.sleep_until(linger_deadline.unwrap_or_else(|| clock.now())), if linger_deadline.is_some() => {
+                tracing::debug!("Writing {} lingering records", pending_write.batch.records.len());
+                let _ = pending_write.write(&mut file, &log_end_offset, &mut producer_states, &segment_buffers, &append_metrics, timestamp_type).await;
+            }
+            _ = cancellation_token.cancelled() => {
+                let _ = pending_write.write(&mut file, &log_end_offset, &mut producer_states, &segment_buffers, &append_metrics, timestamp_type).await;
+                #[cfg(feature = "fault-injection")]
+                crate::faults::fsync().await;
                 file.sync_all().await.expect("Failed to sync segment file");
                 tracing::info!("file synced and shutdown");
 
@@ -96,14 +223,516 @@ pub async fn start_partition_writer(
     }
 }
 
+/// Writes received but not appended to the segment file yet, with the appends acknowledged once
+/// they were written. Uncompressed records wait in `batch` for it to fill up, `batches` holds the
+/// batches to append before them in the order they were received.
+#[derive(Default)]
+struct PendingWrite {
+    batch: Batch,
+    batches: Vec<PendingBatch>,
+    /// Records of `batches`
+    batches_record_count: u64,
+    base_offset_txs: Vec<(u64, oneshot::Sender<Result<AppendedBatch, ProduceError>>)>,
+    /// States of the idempotent producers of `batches`, kept once the batches were written.
+    producer_states: HashMap<u64, ProducerState>,
+    /// `linger` after the first pending record arrived, `None` while no record is pending.
+    deadline: Option<Instant>,
+}
+
+enum PendingBatch {
+    /// Uncompressed records received before a batch stored as it was received.
+    Records(Batch),
+    /// Compressed batch or batch of an idempotent producer, stored as it was received.
+    Received(RecordBatch),
+}
+
+impl PendingWrite {
+    /// `deadline` is when the records are written at the latest, unless other records were
+    /// pending already.
+    fn add(&mut self, records: Vec<Message>, deadline: Instant) {
+        if self.deadline.is_none() && !records.is_empty() {
+            self.deadline = Some(deadline);
+        }
+        self.batch.records.extend(records);
+    }
+
+    /// Queues a batch to be stored as it is, behind the records received before it.
+    fn add_batch(&mut self, batch: RecordBatch) {
+        self.take_records();
+        self.batches_record_count += batch.record_count as u64;
+        self.batches.push(PendingBatch::Received(batch));
+    }
+
+    fn take_records(&mut self) {
+        if !self.batch.records.is_empty() {
+            self.batches_record_count += self.batch.records.len() as u64;
+            self.batches
+                .push(PendingBatch::Records(std::mem::take(&mut self.batch)));
+        }
+    }
+
+    /// Offset of the next record received.
+    fn next_offset(&self, log_end_offset: &AtomicU64) -> u64 {
+        log_end_offset.load(Ordering::SeqCst)
+            + self.batches_record_count
+            + self.batch.records.len() as u64
+    }
+
+    /// Appends the pending batches and records with one vectored write, keeps the states of
+    /// their producers and acknowledges their appends with the log append time of their batch. On failure the partly written batches are
+    /// cut off the segment and the appends fail with a retriable error, so producers resend them.
+    async fn write(
+        &mut self,
+        file: &mut File,
+        log_end_offset: &AtomicU64,
+        producer_states: &mut HashMap<u64, ProducerState>,
+        segment_buffers: &BufferPool,
+        append_metrics: &AppendMetrics,
+        timestamp_type: TimestampType,
+    ) -> io::Result<()> {
+        let mut pending_write = std::mem::take(self);
+        pending_write.take_records();
+        let pending_batches = std::mem::take(&mut pending_write.batches);
+        let start_offset = log_end_offset.load(Ordering::SeqCst);
+        let result = async {
+            let segment_len = file.metadata().await?.len();
+            let batches = stamped_batches(pending_batches, timestamp_type)?;
+            let mut end_offset = start_offset;
+            let log_append_times: Vec<(u64, Option<u128>)> = batches
+                .iter()
+                .map(|batch| {
+                    end_offset += batch.record_count as u64;
+                    (end_offset, batch_log_append_time(batch))
+                })
+                .collect();
+            #[cfg(feature = "fault-injection")]
+            crate::faults::segment_write()?;
+            let result = write_record_batches(
+                file,
+                batches,
+                log_end_offset,
+                segment_buffers,
+                append_metrics,
+            )
+            .await;
+            if result.is_err() {
+                // later appends follow the last complete batch instead of a torn one
+                if let Err(e) = file.set_len(segment_len).await {
+                    tracing::error!("Could not cut off the failed write: {:?}", e);
+                }
+            }
+            result.map(|_| log_append_times)
+        }
+        .await;
+        match result {
+            Ok(log_append_times) => {
+                let log_append_time = |offset: u64| {
+                    log_append_times
+                        .iter()
+                        .find(|(end_offset, _)| offset < *end_offset)
+                        .and_then(|(_, log_append_time)| *log_append_time)
+                };
+                for (producer_id, mut producer_state) in pending_write.producer_states {
+                    producer_state.log_append_time = log_append_time(producer_state.base_offset);
+                    producer_states.insert(producer_id, producer_state);
+                }
+                for (base_offset, base_offset_tx) in pending_write.base_offset_txs {
+                    // the appending request may have been dropped, its records are written anyway
+                    let _ = base_offset_tx.send(Ok(AppendedBatch {
+                        base_offset,
+                        log_append_time: log_append_time(base_offset),
+                    }));
+                }
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("Failed to write to segment file: {:?}", e);
+                let error = match e.kind() {
+                    io::ErrorKind::StorageFull => ProduceError::StorageFull,
+                    _ => ProduceError::BrokerUnavailable(format!(
+                        "Could not write to segment file: {}",
+                        e
+                    )),
+                };
+                for (_, base_offset_tx) in pending_write.base_offset_txs {
+                    let _ = base_offset_tx.send(Err(error.clone()));
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Encodes the pending records into batches and stamps the batches of topics using log append
+/// time which the leader did not stamp already.
+fn stamped_batches(
+    pending_batches: Vec<PendingBatch>,
+    timestamp_type: TimestampType,
+) -> io::Result<Vec<RecordBatch>> {
+    let log_append_time = now_millis();
+    let mut batches = Vec::with_capacity(pending_batches.len());
+    for pending_batch in pending_batches {
+        let mut record_batch = match pending_batch {
+            PendingBatch::Records(batch) => match RecordBatch::new(batch) {
+                Ok(record_batch) => record_batch,
+                Err(e) => {
+                    tracing::error!("Failed to encode batch: {:?}", e);
+                    return Err(e);
+                }
+            },
+            PendingBatch::Received(record_batch) => record_batch,
+        };
+        if timestamp_type == TimestampType::LogAppendTime
+            && record_batch.timestamp_type == TimestampType::CreateTime
+        {
+            record_batch.set_log_append_time(log_append_time);
+        }
+        batches.push(record_batch);
+    }
+    Ok(batches)
+}
+
+/// When the batch was stamped, `None` when it keeps the create times of its records.
+fn batch_log_append_time(batch: &RecordBatch) -> Option<u128> {
+    match batch.timestamp_type {
+        TimestampType::LogAppendTime => batch.max_timestamp,
+        TimestampType::CreateTime => None,
+    }
+}
+
+/// Reads the stored batches holding up to `max_records` records starting at `offset` from a
+/// segment file. Batches are returned as they are stored, the first one may start before `offset`.
+pub async fn read_records(
+    segment_file_path: &str,
+    offset: u64,
+    max_records: usize,
+) -> Vec<FetchedBatch> {
+    let segment = match tokio::fs::read(segment_file_path).await {
+        Ok(segment) => segment,
+        Err(e) => {
+            tracing::warn!("Could not read {} with error: {:?}", segment_file_path, e);
+            return vec![];
+        }
+    };
+    let mut src = BytesMut::from(segment.as_slice());
+    let mut batch_decoder = SegmentDecoder::new();
+    let mut batches = vec![];
+    let mut batch_offset = 0;
+    let end_offset = offset + max_records as u64;
+    while batch_offset < end_offset {
+        let batch = match batch_decoder.decode(&mut src) {
+            Ok(Some(batch)) => batch,
+            _ => break,
+        };
+        let batch_len = batch.record_count as u64;
+        if batch_offset + batch_len > offset {
+            batches.push(FetchedBatch {
+                base_offset: batch_offset,
+                batch,
+            });
+        }
+        batch_offset += batch_len;
+    }
+    batches
+}
+
+/// Offset of the first record with a timestamp at or after `timestamp`, `None` when there is none.
+/// Batches whose largest timestamp is earlier are skipped without decoding their records.
+pub async fn offset_for_timestamp(segment_file_path: &str, timestamp: u128) -> Option<u64> {
+    let segment = tokio::fs::read(segment_file_path).await.ok()?;
+    let mut src = BytesMut::from(segment.as_slice());
+    let mut batch_decoder = SegmentDecoder::new();
+    let mut offset = 0;
+    while let Ok(Some(batch)) = batch_decoder.decode(&mut src) {
+        if batch
+            .max_timestamp
+            .is_some_and(|max_timestamp| max_timestamp < timestamp)
+        {
+            offset += batch.record_count as u64;
+            continue;
+        }
+        for record in batch.records().ok()? {
+            if record
+                .timestamp
+                .is_some_and(|record_timestamp| record_timestamp >= timestamp)
+            {
+                return Some(offset);
+            }
+            offset += 1;
+        }
+    }
+    None
+}
+
+/// Appends the batches to the segment file as they were received, encoded (and encrypted when the
+/// broker has encryption keys) into pooled buffers and written with as few vectored writes as the
+/// file takes. Failed writes are returned, so the appends waiting for them fail instead of the
+/// writer.
+async fn write_record_batches(
+    file: &mut File,
+    batches: Vec<RecordBatch>,
+    log_end_offset: &AtomicU64,
+    segment_buffers: &BufferPool,
+    append_metrics: &AppendMetrics,
+) -> Result<(), std::io::Error> {
+    if batches.is_empty() {
+        return Ok(());
+    }
+    let batch_count = batches.len();
+    let mut record_count = 0;
+    let mut encoded_batches = Vec::with_capacity(batch_count);
+    let mut batch_encoder = SegmentEncoder::new();
+    for batch in batches {
+        record_count += batch.record_count as u64;
+        let mut encoded_batch = segment_buffers.acquire();
+        if let Err(e) = batch_encoder.encode(batch, &mut encoded_batch) {
+            tracing::error!("Failed to encode batch: {:?}", e);
+            return Err(e);
+        }
+        encoded_batches.push(encoded_batch);
+    }
+    let mut slices: Vec<IoSlice> = encoded_batches
+        .iter()
+        .map(|encoded_batch| IoSlice::new(encoded_batch))
+        .collect();
+    let mut remaining_slices = slices.as_mut_slice();
+    let write_started = Instant::now();
+    while !remaining_slices.is_empty() {
+        let written = file.write_vectored(remaining_slices).await?;
+        if written == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "Failed to write to segment file",
+            ));
+        }
+        IoSlice::advance_slices(&mut remaining_slices, written);
+    }
+    file.flush().await?;
+    log_end_offset.fetch_add(record_count, Ordering::SeqCst);
+    let written_bytes = encoded_batches
+        .iter()
+        .map(|encoded_batch| encoded_batch.len() as u64)
+        .sum();
+    append_metrics.record(record_count, written_bytes, write_started.elapsed());
+    tracing::info!(
+        "Wrote {} batches of {} messages to file",
+        batch_count,
+        record_count
+    );
+    Ok(())
+}
+
+/// Metrics of the partition updated by its writer, the topic and partition ones only kept at
+/// their granularity.
+struct AppendMetrics {
+    flushes: Arc<AtomicU64>,
+    flush_micros: Arc<AtomicU64>,
+    topic: Option<TopicMetrics>,
+    partition: Option<PartitionMetrics>,
+}
+
+impl AppendMetrics {
+    fn new(metrics: &Metrics, topic_partition: &TopicPartition, segment_file_path: &str) -> Self {
+        let partition = metrics.partition(topic_partition);
+        if let Some(partition) = &partition {
+            let size_bytes = fs::metadata(segment_file_path).map_or(0, |metadata| metadata.len());
+            partition.size_bytes.store(size_bytes, Ordering::Relaxed);
+            // a partition is written to a single segment file
+            partition.segments.store(1, Ordering::Relaxed);
+        }
+        AppendMetrics {
+            flushes: metrics.register("partition_flushes_total"),
+            flush_micros: metrics.register("partition_flush_micros_total"),
+            topic: metrics.topic(&topic_partition.topic_name),
+            partition,
+        }
+    }
+
+    /// Updates the partition's size once a follower truncated the segment file.
+    fn record_truncation(&self, segment_file_path: &str) {
+        if let Some(partition) = &self.partition {
+            let size_bytes = fs::metadata(segment_file_path).map_or(0, |metadata| metadata.len());
+            partition.size_bytes.store(size_bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// `flush_latency` is how long writing and flushing the batches to the segment file took.
+    fn record(&self, record_count: u64, written_bytes: u64, flush_latency: Duration) {
+        let flush_micros = flush_latency.as_micros() as u64;
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        self.flush_micros.fetch_add(flush_micros, Ordering::Relaxed);
+        if let Some(topic) = &self.topic {
+            topic.messages_in.fetch_add(record_count, Ordering::Relaxed);
+            topic.bytes_in.fetch_add(written_bytes, Ordering::Relaxed);
+        }
+        if let Some(partition) = &self.partition {
+            partition
+                .size_bytes
+                .fetch_add(written_bytes, Ordering::Relaxed);
+            partition
+                .last_append_millis
+                .store(now_millis() as u64, Ordering::Relaxed);
+            partition
+                .flush_latency_micros
+                .store(flush_micros, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Last batch an idempotent producer appended to the partition.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct ProducerState {
+    base_sequence: u32,
+    last_sequence: u32,
+    base_offset: u64,
+    /// When the batch was stamped, retries are answered with it.
+    log_append_time: Option<u128>,
+}
+
+impl ProducerState {
+    fn new(producer: ProducerSequence, record_count: u32, base_offset: u64) -> Self {
+        ProducerState {
+            base_sequence: producer.base_sequence,
+            last_sequence: producer.base_sequence + record_count.saturating_sub(1),
+            base_offset,
+            log_append_time: None,
+        }
+    }
+}
+
+/// `Ok(None)` when the batch continues the producer's sequence, `Ok(Some(appended_batch))` when
+/// it is a retry of the producer's last batch and `Err(expected_sequence)` when batches are
+/// missing or it is a retry of an older batch.
+fn check_sequence(
+    state: Option<&ProducerState>,
+    producer: ProducerSequence,
+) -> Result<Option<AppendedBatch>, u32> {
+    let expected_sequence = state.map_or(0, |state| state.last_sequence + 1);
+    match state {
+        Some(state) if state.base_sequence == producer.base_sequence => Ok(Some(AppendedBatch {
+            base_offset: state.base_offset,
+            log_append_time: state.log_append_time,
+        })),
+        _ if producer.base_sequence == expected_sequence => Ok(None),
+        _ => Err(expected_sequence),
+    }
+}
+
+/// What the partition writer restores from its segment file on startup.
+#[derive(Debug, Default)]
+struct RecoveredSegment {
+    log_end_offset: u64,
+    producer_states: HashMap<u64, ProducerState>,
+}
+
+/// Cuts the segment before the batch holding `offset` and returns the new log end offset, the
+/// base offset of that batch. Batches are only removed whole.
+fn truncate_segment(segment_file_path: &str, offset: u64) -> io::Result<u64> {
+    let segment = fs::read(segment_file_path)?;
+    let mut src = BytesMut::from(segment.as_slice());
+    let mut batch_decoder = SegmentDecoder::new();
+    let mut log_end_offset = 0;
+    let mut segment_length = 0;
+    while let Ok(Some(batch)) = batch_decoder.decode(&mut src) {
+        let batch_end_offset = log_end_offset + batch.record_count as u64;
+        if batch_end_offset > offset {
+            break;
+        }
+        log_end_offset = batch_end_offset;
+        segment_length = segment.len() - src.len();
+    }
+    tracing::info!(
+        "Truncating {} to offset {} at byte {}",
+        segment_file_path,
+        log_end_offset,
+        segment_length
+    );
+    let file = fs::OpenOptions::new().write(true).open(segment_file_path)?;
+    file.set_len(segment_length as u64)?;
+    file.sync_all()?;
+    Ok(log_end_offset)
+}
+
+fn recover_segment(segment_file_path: &str) -> RecoveredSegment {
+    let mut recovered_segment = RecoveredSegment::default();
+    let segment = match fs::read(segment_file_path) {
+        Ok(segment) => segment,
+        Err(_) => return recovered_segment,
+    };
+    let mut src = BytesMut::from(segment.as_slice());
+    let mut batch_decoder = SegmentDecoder::new();
+    while let Ok(Some(batch)) = batch_decoder.decode(&mut src) {
+        let record_count = batch.record_count;
+        if let Some(producer) = batch.producer {
+            recovered_segment.producer_states.insert(
+                producer.producer_id,
+                ProducerState {
+                    log_append_time: batch_log_append_time(&batch),
+                    ..ProducerState::new(producer, record_count, recovered_segment.log_end_offset)
+                },
+            );
+        }
+        recovered_segment.log_end_offset += record_count as u64;
+    }
+    recovered_segment
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
 
     use super::*;
-    use bytes::BytesMut;
-    use common::models::{Message, Topic};
+    use bytes::{Bytes, BytesMut};
+    use common::codecs::encoder::BatchEncoder;
+    use common::models::{Message, OverflowPolicy, Topic};
     use test_log::test;
+    use tokio::sync::oneshot;
+
+    use crate::clock::BrokerClock;
+    use crate::models::PartitionAppend;
+    use crate::partition_queue::{partition_queue, PartitionSender};
+
+    /// Longer than the tests, records are written once their batch is full.
+    const LINGER: Duration = Duration::from_secs(60);
+
+    fn record_batch(
+        records: Vec<Message>,
+        producer: Option<ProducerSequence>,
+        compression: CompressionCodec,
+    ) -> RecordBatch {
+        RecordBatch::new(Batch {
+            records,
+            producer,
+            compression,
+        })
+        .unwrap()
+    }
+
+    /// Answer of an append written at `base_offset` to a topic keeping create times.
+    fn appended(base_offset: u64) -> Result<AppendedBatch, ProduceError> {
+        Ok(AppendedBatch {
+            base_offset,
+            log_append_time: None,
+        })
+    }
+
+    fn writer_context(linger: Duration) -> PartitionWriterContext {
+        PartitionWriterContext {
+            linger,
+            segment_buffers: BufferPool::new("segments", 1024, 1, &Metrics::new()),
+            metrics: Metrics::new(),
+            clock: Arc::new(BrokerClock::new()),
+        }
+    }
+
+    fn peers_queue() -> (PartitionSender, PartitionReceiver) {
+        partition_queue(
+            TopicPartition::new("test_topic".to_string(), 0),
+            3,
+            OverflowPolicy::default(),
+            &Metrics::new(),
+        )
+    }
 
     #[test(tokio::test)]
     async fn test_partition_manager_should_write_message_batch_to_file() {
@@ -113,7 +742,7 @@ mod tests {
         fs::create_dir_all(log_dir_path.clone()).unwrap();
 
         let batch_size = 2;
-        let test_topic = Topic::new(topic_name.clone(), None, None, None, Some(batch_size));
+        let test_topic = Topic::new(topic_name.clone(), None, None, None, Some(batch_size), None);
 
         let partition_info = PartitionInfo::new(
             test_topic,
@@ -121,33 +750,66 @@ mod tests {
             log_dir_path.as_path().to_str().unwrap().to_string(),
         );
 
-        let (peers_tx, peers_rx) = mpsc::channel::<Message>(3);
+        let (peers_tx, peers_rx) = peers_queue();
         let cancellation_token = CancellationToken::new();
         let cancellation_token_clone = cancellation_token.clone();
 
+        let log_end_offset = Arc::new(AtomicU64::new(0));
+        let log_end_offset_clone = log_end_offset.clone();
+
         let partition_manager_handle = tokio::spawn(async move {
-            start_partition_writer(partition_info, peers_rx, cancellation_token_clone).await;
+            start_partition_writer(
+                partition_info,
+                peers_rx,
+                log_end_offset_clone,
+                writer_context(LINGER),
+                cancellation_token_clone,
+            )
+            .await;
         });
 
         let message_1 = Message {
-            payload: BytesMut::from("Message 1 without timestamp".as_bytes()).freeze(),
+            payload: Some(BytesMut::from("Message 1 without timestamp".as_bytes()).freeze()),
             key: None,
             timestamp: None,
+            headers: vec![],
         };
         let message_2 = Message {
-            payload: BytesMut::from("Message 2 with timestamp".as_bytes()).freeze(),
+            payload: Some(BytesMut::from("Message 2 with timestamp".as_bytes()).freeze()),
             key: None,
             timestamp: Some(1234567890),
+            headers: vec![],
         };
         let mut encoded_batch = BytesMut::new();
         let mut batch_encoder = BatchEncoder {};
         let batch = Batch {
             records: vec![message_1.clone(), message_2.clone()],
+            ..Batch::default()
         };
-        batch_encoder.encode(batch, &mut encoded_batch).unwrap();
+        batch_encoder
+            .encode(batch.clone(), &mut encoded_batch)
+            .unwrap();
 
-        peers_tx.send(message_1).await.unwrap();
-        peers_tx.send(message_2).await.unwrap();
+        peers_tx
+            .send(PartitionAppend {
+                batch: record_batch(vec![message_1], None, CompressionCodec::None),
+                acks: Acks::None,
+                base_offset_tx: None,
+            })
+            .await
+            .unwrap();
+        let (base_offset_tx, base_offset_rx) = oneshot::channel();
+        peers_tx
+            .send(PartitionAppend {
+                batch: record_batch(vec![message_2.clone()], None, CompressionCodec::None),
+                acks: Acks::Leader,
+                base_offset_tx: Some(base_offset_tx),
+            })
+            .await
+            .unwrap();
+        assert_eq!(base_offset_rx.await.unwrap(), appended(1));
+        // acknowledged records have been written
+        assert_eq!(log_end_offset.load(Ordering::SeqCst), 2);
 
         tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
         cancellation_token.cancel();
@@ -155,12 +817,437 @@ mod tests {
             Ok(_) => {
                 let segment_file_path =
                     format!("{}/0/{}", log_dir_path.to_str().unwrap(), "segment_0.log");
-                let file_contents = fs::read(segment_file_path).unwrap();
+                let file_contents = fs::read(segment_file_path.clone()).unwrap();
                 assert_eq!(&file_contents, &encoded_batch);
+                assert_eq!(log_end_offset.load(Ordering::SeqCst), 2);
+                assert_eq!(recover_segment(&segment_file_path).log_end_offset, 2);
+                let batches = read_records(&segment_file_path, 1, 10).await;
+                assert_eq!(
+                    batches,
+                    vec![FetchedBatch {
+                        base_offset: 0,
+                        batch: RecordBatch::new(batch).unwrap(),
+                    }]
+                );
+                assert_eq!(
+                    FetchedBatch::records(batches, 1, 10).unwrap(),
+                    vec![message_2]
+                );
+                assert_eq!(
+                    offset_for_timestamp(&segment_file_path, 1234567890).await,
+                    Some(1)
+                );
+                assert_eq!(
+                    offset_for_timestamp(&segment_file_path, 1234567891).await,
+                    None
+                );
             }
             Err(e) => {
                 panic!("Partition manager failed with error: {:?}", e);
             }
         }
     }
+
+    #[test(tokio::test)]
+    async fn test_partition_writer_should_deduplicate_retried_producer_batches() {
+        let temp_dir = tempdir::TempDir::new("log_dir_prefix").unwrap();
+        let test_topic = Topic::new("test_topic".to_string(), None, None, None, Some(10), None);
+        let partition_info =
+            PartitionInfo::new(test_topic, 0, temp_dir.path().to_str().unwrap().to_string());
+        let segment_file_path = partition_info.segment_file_path();
+        let (peers_tx, peers_rx) = peers_queue();
+        let cancellation_token = CancellationToken::new();
+        let log_end_offset = Arc::new(AtomicU64::new(0));
+        let partition_manager_handle = tokio::spawn(start_partition_writer(
+            partition_info,
+            peers_rx,
+            log_end_offset.clone(),
+            writer_context(LINGER),
+            cancellation_token.clone(),
+        ));
+
+        let message = |payload: &str| Message {
+            payload: Some(BytesMut::from(payload.as_bytes()).freeze()),
+            key: None,
+            timestamp: None,
+            headers: vec![],
+        };
+        let append = |records: Vec<Message>, base_sequence: u32| {
+            let peers_tx = peers_tx.clone();
+            async move {
+                let (base_offset_tx, base_offset_rx) = oneshot::channel();
+                peers_tx
+                    .send(PartitionAppend {
+                        batch: record_batch(
+                            records,
+                            Some(ProducerSequence {
+                                producer_id: 7,
+                                base_sequence,
+                            }),
+                            CompressionCodec::None,
+                        ),
+                        acks: Acks::Leader,
+                        base_offset_tx: Some(base_offset_tx),
+                    })
+                    .await
+                    .unwrap();
+                base_offset_rx.await.unwrap()
+            }
+        };
+
+        // buffered records are written before the producer's batch
+        peers_tx
+            .send(PartitionAppend {
+                batch: record_batch(vec![message("buffered")], None, CompressionCodec::None),
+                acks: Acks::None,
+                base_offset_tx: None,
+            })
+            .await
+            .unwrap();
+        let batch = vec![message("first"), message("second")];
+        assert_eq!(append(batch.clone(), 0).await, appended(1));
+        assert_eq!(append(batch, 0).await, appended(1));
+        assert_eq!(log_end_offset.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            append(vec![message("gap")], 5).await,
+            Err(ProduceError::OutOfOrderSequence {
+                topic_partition: TopicPartition::new("test_topic".to_string(), 0),
+                expected_sequence: 2,
+                sequence: 5,
+            })
+        );
+        assert_eq!(append(vec![message("third")], 2).await, appended(3));
+
+        cancellation_token.cancel();
+        partition_manager_handle.await.unwrap();
+        let recovered_segment = recover_segment(&segment_file_path);
+        assert_eq!(recovered_segment.log_end_offset, 4);
+        assert_eq!(
+            recovered_segment.producer_states[&7],
+            ProducerState {
+                base_sequence: 2,
+                last_sequence: 2,
+                base_offset: 3,
+                log_append_time: None,
+            }
+        );
+        let batches = read_records(&segment_file_path, 0, 10).await;
+        let producer_ids: Vec<Option<u64>> = batches
+            .iter()
+            .map(|fetched_batch| {
+                fetched_batch
+                    .batch
+                    .producer
+                    .map(|producer| producer.producer_id)
+            })
+            .collect();
+        assert_eq!(producer_ids, vec![None, Some(7), Some(7)]);
+    }
+
+    #[test(tokio::test)]
+    async fn test_failed_write_should_fail_its_appends_and_forget_their_producer_states() {
+        let temp_dir = tempdir::TempDir::new("log_dir_prefix").unwrap();
+        let segment_file_path = temp_dir.path().join("segment.log");
+        let segment_file_path = segment_file_path.to_str().unwrap();
+        fs::write(segment_file_path, b"").unwrap();
+        // writes to a file opened for reading fail
+        let mut file = File::open(segment_file_path).await.unwrap();
+        let log_end_offset = AtomicU64::new(0);
+        let mut producer_states = HashMap::new();
+        let segment_buffers = BufferPool::new("segments", 1024, 1, &Metrics::new());
+        let append_metrics = AppendMetrics::new(
+            &Metrics::new(),
+            &TopicPartition::new("test_topic".to_string(), 0),
+            segment_file_path,
+        );
+
+        let producer = ProducerSequence {
+            producer_id: 7,
+            base_sequence: 0,
+        };
+        let message = Message::new(Bytes::from_static(b"first"), None, None);
+        let mut pending_write = PendingWrite::default();
+        pending_write.add_batch(record_batch(
+            vec![message],
+            Some(producer),
+            CompressionCodec::None,
+        ));
+        pending_write
+            .producer_states
+            .insert(7, ProducerState::new(producer, 1, 0));
+        let (base_offset_tx, base_offset_rx) = oneshot::channel();
+        pending_write.base_offset_txs.push((0, base_offset_tx));
+        let result = pending_write
+            .write(
+                &mut file,
+                &log_end_offset,
+                &mut producer_states,
+                &segment_buffers,
+                &append_metrics,
+                TimestampType::CreateTime,
+            )
+            .await;
+
+        assert!(result.is_err());
+        let error = base_offset_rx.await.unwrap().unwrap_err();
+        assert!(error.is_retriable());
+        // the retry of the batch is written instead of being taken for a duplicate
+        assert!(producer_states.is_empty());
+        assert_eq!(log_end_offset.load(Ordering::SeqCst), 0);
+        assert_eq!(fs::metadata(segment_file_path).unwrap().len(), 0);
+    }
+
+    #[test(tokio::test)]
+    async fn test_partition_writer_should_store_compressed_batches_as_received() {
+        let temp_dir = tempdir::TempDir::new("log_dir_prefix").unwrap();
+        let test_topic = Topic::new("test_topic".to_string(), None, None, None, Some(10), None);
+        let partition_info =
+            PartitionInfo::new(test_topic, 0, temp_dir.path().to_str().unwrap().to_string());
+        let segment_file_path = partition_info.segment_file_path();
+        let (peers_tx, peers_rx) = peers_queue();
+        let cancellation_token = CancellationToken::new();
+        let log_end_offset = Arc::new(AtomicU64::new(0));
+        let partition_manager_handle = tokio::spawn(start_partition_writer(
+            partition_info,
+            peers_rx,
+            log_end_offset.clone(),
+            writer_context(LINGER),
+            cancellation_token.clone(),
+        ));
+
+        let records: Vec<Message> = (0..3)
+            .map(|index| Message {
+                payload: Some(format!("compressed {}", index).into_bytes().into()),
+                key: None,
+                timestamp: None,
+                headers: vec![],
+            })
+            .collect();
+        let compressed_batch = record_batch(records.clone(), None, CompressionCodec::Gzip);
+        let (base_offset_tx, base_offset_rx) = oneshot::channel();
+        peers_tx
+            .send(PartitionAppend {
+                batch: compressed_batch.clone(),
+                acks: Acks::Leader,
+                base_offset_tx: Some(base_offset_tx),
+            })
+            .await
+            .unwrap();
+        assert_eq!(base_offset_rx.await.unwrap(), appended(0));
+        assert_eq!(log_end_offset.load(Ordering::SeqCst), 3);
+
+        cancellation_token.cancel();
+        partition_manager_handle.await.unwrap();
+        assert_eq!(recover_segment(&segment_file_path).log_end_offset, 3);
+        let batches = read_records(&segment_file_path, 1, 1).await;
+        assert_eq!(
+            batches,
+            vec![FetchedBatch {
+                base_offset: 0,
+                batch: compressed_batch,
+            }]
+        );
+        assert_eq!(
+            FetchedBatch::records(batches, 1, 1).unwrap(),
+            vec![records[1].clone()]
+        );
+    }
+
+    #[test(tokio::test)]
+    async fn test_partition_writer_should_write_records_once_their_linger_passed() {
+        let temp_dir = tempdir::TempDir::new("log_dir_prefix").unwrap();
+        let test_topic = Topic::new("test_topic".to_string(), None, None, None, Some(10), None);
+        let partition_info =
+            PartitionInfo::new(test_topic, 0, temp_dir.path().to_str().unwrap().to_string());
+        let segment_file_path = partition_info.segment_file_path();
+        let (peers_tx, peers_rx) = peers_queue();
+        let cancellation_token = CancellationToken::new();
+        let log_end_offset = Arc::new(AtomicU64::new(0));
+        let linger = Duration::from_millis(200);
+        let partition_manager_handle = tokio::spawn(start_partition_writer(
+            partition_info,
+            peers_rx,
+            log_end_offset.clone(),
+            writer_context(linger),
+            cancellation_token.clone(),
+        ));
+
+        let mut base_offset_rxs = vec![];
+        for payload in ["first", "second"] {
+            let (base_offset_tx, base_offset_rx) = oneshot::channel();
+            peers_tx
+                .send(PartitionAppend {
+                    batch: record_batch(
+                        vec![Message::new(payload.into(), None, None)],
+                        None,
+                        CompressionCodec::None,
+                    ),
+                    acks: Acks::Leader,
+                    base_offset_tx: Some(base_offset_tx),
+                })
+                .await
+                .unwrap();
+            base_offset_rxs.push(base_offset_rx);
+        }
+        let sent_at = Instant::now();
+        // neither the batch size nor the linger was reached yet
+        assert_eq!(log_end_offset.load(Ordering::SeqCst), 0);
+        let mut base_offsets = vec![];
+        for base_offset_rx in base_offset_rxs {
+            base_offsets.push(base_offset_rx.await.unwrap());
+        }
+        assert_eq!(base_offsets, vec![appended(0), appended(1)]);
+        assert!(sent_at.elapsed() >= linger / 2);
+        assert_eq!(log_end_offset.load(Ordering::SeqCst), 2);
+
+        cancellation_token.cancel();
+        partition_manager_handle.await.unwrap();
+        // both records were written as one batch
+        assert_eq!(read_records(&segment_file_path, 0, 10).await.len(), 1);
+    }
+
+    #[test(tokio::test)]
+    async fn test_partition_writer_should_stamp_log_append_time() {
+        let temp_dir = tempdir::TempDir::new("log_dir_prefix").unwrap();
+        let test_topic = Topic {
+            timestamp_type: Some(TimestampType::LogAppendTime),
+            ..Topic::new("test_topic".to_string(), None, None, None, Some(1), None)
+        };
+        let partition_info =
+            PartitionInfo::new(test_topic, 0, temp_dir.path().to_str().unwrap().to_string());
+        let segment_file_path = partition_info.segment_file_path();
+        let (peers_tx, peers_rx) = peers_queue();
+        let cancellation_token = CancellationToken::new();
+        let partition_manager_handle = tokio::spawn(start_partition_writer(
+            partition_info,
+            peers_rx,
+            Arc::new(AtomicU64::new(0)),
+            writer_context(LINGER),
+            cancellation_token.clone(),
+        ));
+
+        let sent_at_millis = now_millis();
+        let mut appended_batches = vec![];
+        for compression in [CompressionCodec::None, CompressionCodec::Gzip] {
+            let (base_offset_tx, base_offset_rx) = oneshot::channel();
+            peers_tx
+                .send(PartitionAppend {
+                    batch: record_batch(
+                        vec![Message::new("record".into(), None, Some(1))],
+                        None,
+                        compression,
+                    ),
+                    acks: Acks::Leader,
+                    base_offset_tx: Some(base_offset_tx),
+                })
+                .await
+                .unwrap();
+            appended_batches.push(base_offset_rx.await.unwrap().unwrap());
+        }
+
+        let batches = read_records(&segment_file_path, 0, 10).await;
+        // producers are answered with the time consumers read
+        let stamps: Vec<Option<u128>> = batches
+            .iter()
+            .map(|fetched_batch| fetched_batch.batch.max_timestamp)
+            .collect();
+        let answered_stamps: Vec<Option<u128>> = appended_batches
+            .iter()
+            .map(|appended_batch| appended_batch.log_append_time)
+            .collect();
+        assert_eq!(answered_stamps, stamps);
+        let records = FetchedBatch::records(batches, 0, 10).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|record| record.timestamp >= Some(sent_at_millis)));
+        // the producers' create times are gone
+        assert_eq!(
+            offset_for_timestamp(&segment_file_path, sent_at_millis).await,
+            Some(0)
+        );
+
+        cancellation_token.cancel();
+        partition_manager_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_partition_writer_should_append_waiting_batches_together() {
+        let temp_dir = tempdir::TempDir::new("log_dir_prefix").unwrap();
+        let test_topic = Topic::new("test_topic".to_string(), None, None, None, Some(10), None);
+        let partition_info =
+            PartitionInfo::new(test_topic, 0, temp_dir.path().to_str().unwrap().to_string());
+        let segment_file_path = partition_info.segment_file_path();
+        let (peers_tx, peers_rx) = peers_queue();
+
+        // the writes wait for the writer, which takes them all before writing
+        let batches = [
+            record_batch(
+                vec![Message::new("record".into(), None, None)],
+                None,
+                CompressionCodec::None,
+            ),
+            record_batch(
+                vec![
+                    Message::new("first".into(), None, None),
+                    Message::new("second".into(), None, None),
+                ],
+                None,
+                CompressionCodec::Lz4,
+            ),
+            record_batch(
+                vec![
+                    Message::new("third".into(), None, None),
+                    Message::new("fourth".into(), None, None),
+                ],
+                None,
+                CompressionCodec::Zstd,
+            ),
+        ];
+        let mut base_offset_rxs = vec![];
+        for batch in batches {
+            let (base_offset_tx, base_offset_rx) = oneshot::channel();
+            peers_tx
+                .send(PartitionAppend {
+                    batch,
+                    acks: Acks::Leader,
+                    base_offset_tx: Some(base_offset_tx),
+                })
+                .await
+                .unwrap();
+            base_offset_rxs.push(base_offset_rx);
+        }
+        let cancellation_token = CancellationToken::new();
+        let log_end_offset = Arc::new(AtomicU64::new(0));
+        let partition_manager_handle = tokio::spawn(start_partition_writer(
+            partition_info,
+            peers_rx,
+            log_end_offset.clone(),
+            writer_context(LINGER),
+            cancellation_token.clone(),
+        ));
+
+        let mut base_offsets = vec![];
+        for base_offset_rx in base_offset_rxs {
+            base_offsets.push(base_offset_rx.await.unwrap());
+            // the first answer only comes once every batch was written
+            assert_eq!(log_end_offset.load(Ordering::SeqCst), 5);
+        }
+        assert_eq!(base_offsets, vec![appended(0), appended(1), appended(3)]);
+
+        cancellation_token.cancel();
+        partition_manager_handle.await.unwrap();
+        let batches = read_records(&segment_file_path, 0, 10).await;
+        assert_eq!(
+            batches
+                .iter()
+                .map(|fetched_batch| (fetched_batch.base_offset, fetched_batch.batch.compression))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, CompressionCodec::None),
+                (1, CompressionCodec::Lz4),
+                (3, CompressionCodec::Zstd),
+            ]
+        );
+    }
 }
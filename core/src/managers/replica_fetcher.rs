@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::models::{
+    Acks, BrokerResponse, FetchRequest, FetchedBatch, OffsetResetPolicy, RecordBatch,
+    TimestampType, TopicCommand, TopicPartition,
+};
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::cluster::{send_request, BrokerId};
+use crate::leader_epoch::LeaderEpochCache;
+use crate::models::PartitionAppend;
+use crate::partition_queue::PartitionSender;
+use crate::quotas::{Replication, ReplicationThrottles};
+
+/// Time a replica fetcher waits before fetching again when its leader had no new records.
+const REPLICA_FETCH_BACKOFF: Duration = Duration::from_millis(500);
+/// Records fetched per partition and request.
+const REPLICA_FETCH_MAX_RECORDS: u32 = 1000;
+
+pub enum ReplicaFetcherCommands {
+    /// Starts copying a partition the fetcher's leader leads, from the partition's local log
+    /// end offset on once it was truncated to the leader's log.
+    AddPartition {
+        topic_partition: TopicPartition,
+        partition_tx: PartitionSender,
+        log_end_offset: Arc<AtomicU64>,
+        leader_epochs: Arc<Mutex<LeaderEpochCache>>,
+        high_watermark: Arc<AtomicU64>,
+    },
+    /// Stops copying a partition whose leader changed or which moved to other replicas.
+    RemovePartition { topic_partition: TopicPartition },
+}
+
+/// Partition this broker follows. The fetch offset is the local log end offset, so fetching
+/// resumes where the local log ends after a restart.
+struct FollowerPartition {
+    partition_tx: PartitionSender,
+    log_end_offset: Arc<AtomicU64>,
+    leader_epochs: Arc<Mutex<LeaderEpochCache>>,
+    /// Leader's high watermark, at most the local log end offset.
+    high_watermark: Arc<AtomicU64>,
+    /// Leader epoch the leader answered the last fetch with, leaders of older epochs are not
+    /// copied from. `None` until the local log was truncated to the leader's in its epoch.
+    leader_epoch: Option<u32>,
+}
+
+/// Copies the partitions one leader leads to this broker. Followers pull: the fetcher keeps
+/// sending fetch requests for its partitions to the leader and appends the returned batches to
+/// the local logs as they are, so a follower's log holds the leader's batches at the same offsets.
+/// Before it copies a partition in a new leader epoch, the fetcher asks the leader where the
+/// follower's latest epoch ends in the leader's log and truncates the records after it, which
+/// an old leader did not replicate before it lost the leadership. A fetcher over the broker's
+/// follower throttle waits before it fetches again.
+pub struct ReplicaFetcher {
+    broker_id: BrokerId,
+    leader_address: String,
+    partitions: HashMap<TopicPartition, FollowerPartition>,
+    replication_throttles: Arc<ReplicationThrottles>,
+    cancellation_token: CancellationToken,
+}
+
+impl ReplicaFetcher {
+    pub fn new(
+        broker_id: BrokerId,
+        leader_address: String,
+        replication_throttles: Arc<ReplicationThrottles>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        ReplicaFetcher {
+            broker_id,
+            leader_address,
+            partitions: HashMap::new(),
+            replication_throttles,
+            cancellation_token,
+        }
+    }
+
+    pub async fn start_replica_fetcher(
+        mut self,
+        mut commands_rx: Receiver<ReplicaFetcherCommands>,
+    ) {
+        tracing::info!("Replica fetcher for leader {} started", self.leader_address);
+        let cancellation_token = self.cancellation_token.clone();
+        loop {
+            while let Ok(command) = commands_rx.try_recv() {
+                self.handle_command(command);
+            }
+            let fetched_records = tokio::select! {
+                fetched_records = self.fetch_partitions() => fetched_records,
+                _ = cancellation_token.cancelled() => break,
+            };
+            if fetched_records > 0 {
+                continue;
+            }
+            tokio::select! {
+                Some(command) = commands_rx.recv() => self.handle_command(command),
+                _ = tokio::time::sleep(REPLICA_FETCH_BACKOFF) => {}
+                _ = cancellation_token.cancelled() => break,
+            }
+        }
+        tracing::info!("Replica fetcher for leader {} stopped", self.leader_address);
+    }
+
+    fn handle_command(&mut self, command: ReplicaFetcherCommands) {
+        match command {
+            ReplicaFetcherCommands::AddPartition {
+                topic_partition,
+                partition_tx,
+                log_end_offset,
+                leader_epochs,
+                high_watermark,
+            } => {
+                tracing::info!(
+                    "Following {:?} led by {}",
+                    topic_partition,
+                    self.leader_address
+                );
+                self.partitions.insert(
+                    topic_partition,
+                    FollowerPartition {
+                        partition_tx,
+                        log_end_offset,
+                        leader_epochs,
+                        high_watermark,
+                        leader_epoch: None,
+                    },
+                );
+            }
+            ReplicaFetcherCommands::RemovePartition { topic_partition } => {
+                if self.partitions.remove(&topic_partition).is_some() {
+                    tracing::info!(
+                        "Stopped following {:?} led by {}",
+                        topic_partition,
+                        self.leader_address
+                    );
+                }
+            }
+        }
+    }
+
+    /// Fetches every partition once, returns the number of records appended.
+    async fn fetch_partitions(&mut self) -> usize {
+        let mut fetched_records = 0;
+        let mut leader_epochs = vec![];
+        let mut throttle_time = Duration::ZERO;
+        for (topic_partition, partition) in &self.partitions {
+            let fetch = match partition.leader_epoch {
+                Some(_) => self.fetch_partition(topic_partition, partition).await,
+                None => self
+                    .truncate_partition(topic_partition, partition)
+                    .await
+                    .map(|leader_epoch| (0, 0, Some(leader_epoch))),
+            };
+            match fetch {
+                Ok((record_count, fetched_bytes, leader_epoch)) => {
+                    fetched_records += record_count;
+                    leader_epochs.push((topic_partition.clone(), leader_epoch));
+                    throttle_time = throttle_time.max(self.replication_throttles.record(
+                        Replication::Follower,
+                        topic_partition,
+                        self.broker_id,
+                        fetched_bytes,
+                        Instant::now(),
+                    ));
+                }
+                Err(e) => tracing::warn!(
+                    "Could not fetch {:?} from {}: {:?}",
+                    topic_partition,
+                    self.leader_address,
+                    e
+                ),
+            }
+        }
+        for (topic_partition, leader_epoch) in leader_epochs {
+            if let Some(partition) = self.partitions.get_mut(&topic_partition) {
+                partition.leader_epoch = leader_epoch;
+            }
+        }
+        if !throttle_time.is_zero() {
+            tracing::debug!(
+                "Throttling replication from {} for {:?}",
+                self.leader_address,
+                throttle_time
+            );
+            tokio::time::sleep(throttle_time).await;
+        }
+        fetched_records
+    }
+
+    /// Truncates the local log where it diverges from the leader's and returns the leader's
+    /// epoch. The leader answers where the follower's latest epoch ends in its log. When the
+    /// leader's log has no records of that epoch, the follower's log diverges where its own
+    /// records of the leader's last older epoch end.
+    async fn truncate_partition(
+        &self,
+        topic_partition: &TopicPartition,
+        partition: &FollowerPartition,
+    ) -> io::Result<u32> {
+        let latest_epoch = partition
+            .leader_epochs
+            .lock()
+            .unwrap()
+            .latest_epoch()
+            .unwrap_or_default();
+        let request = TopicCommand::OffsetForLeaderEpoch {
+            topic_partition: topic_partition.clone(),
+            leader_epoch: latest_epoch,
+        };
+        let (end_offset, current_leader_epoch) =
+            match send_request(&self.leader_address, &request).await? {
+                BrokerResponse::EpochEndOffset {
+                    end_offset,
+                    current_leader_epoch,
+                    ..
+                } => (end_offset, current_leader_epoch),
+                response => {
+                    return Err(io::Error::other(format!(
+                        "Unexpected response {:?}",
+                        response
+                    )))
+                }
+            };
+        let log_end_offset = partition.log_end_offset.load(Ordering::SeqCst);
+        let truncation_offset = match end_offset {
+            Some(end_offset) if end_offset.leader_epoch < latest_epoch => partition
+                .leader_epochs
+                .lock()
+                .unwrap()
+                .end_offset_for(end_offset.leader_epoch, log_end_offset)
+                .map_or(0, |local_end_offset| local_end_offset.end_offset)
+                .min(end_offset.end_offset),
+            Some(end_offset) => end_offset.end_offset,
+            None => {
+                tracing::warn!(
+                    "Leader of {:?} knows no epoch at or before {}, keeping the local log",
+                    topic_partition,
+                    latest_epoch
+                );
+                log_end_offset
+            }
+        };
+        if truncation_offset < log_end_offset {
+            tracing::info!(
+                "Truncating {:?} from {} to offset {} of leader epoch {}",
+                topic_partition,
+                log_end_offset,
+                truncation_offset,
+                current_leader_epoch
+            );
+            let log_end_offset = partition.partition_tx.truncate(truncation_offset).await?;
+            partition
+                .leader_epochs
+                .lock()
+                .unwrap()
+                .truncate_from_end(log_end_offset);
+            partition
+                .high_watermark
+                .fetch_min(log_end_offset, Ordering::SeqCst);
+        }
+        let log_end_offset = partition.log_end_offset.load(Ordering::SeqCst);
+        partition
+            .leader_epochs
+            .lock()
+            .unwrap()
+            .assign(current_leader_epoch, log_end_offset);
+        Ok(current_leader_epoch)
+    }
+
+    /// Appends the records the leader has after the local log end offset, returns the number of
+    /// records appended, the bytes fetched and the leader's epoch, `None` once the leader moved
+    /// to a newer epoch the log must be truncated for.
+    async fn fetch_partition(
+        &self,
+        topic_partition: &TopicPartition,
+        partition: &FollowerPartition,
+    ) -> io::Result<(usize, u64, Option<u32>)> {
+        let fetch_offset = partition.log_end_offset.load(Ordering::SeqCst);
+        let request = TopicCommand::Fetch(FetchRequest {
+            topic_partition: topic_partition.clone(),
+            offset: Some(fetch_offset),
+            group_id: None,
+            member_id: None,
+            auto_offset_reset: OffsetResetPolicy::None,
+            max_records: REPLICA_FETCH_MAX_RECORDS,
+            replica_id: Some(self.broker_id),
+            leader_epoch: partition.leader_epoch,
+            rack_id: None,
+        });
+        #[cfg(feature = "fault-injection")]
+        crate::faults::replica_fetch().await?;
+        let (batches, high_watermark, leader_epoch) =
+            match send_request(&self.leader_address, &request).await? {
+                BrokerResponse::Records {
+                    batches,
+                    high_watermark,
+                    leader_epoch,
+                    ..
+                } => (batches, high_watermark, leader_epoch),
+                // the leader changed, truncate and fetch again in the new epoch
+                BrokerResponse::FencedLeaderEpoch {
+                    current_leader_epoch,
+                    ..
+                } if partition.leader_epoch < Some(current_leader_epoch) => {
+                    tracing::info!(
+                        "Leader of {:?} is in epoch {} now",
+                        topic_partition,
+                        current_leader_epoch
+                    );
+                    return Ok((0, 0, None));
+                }
+                BrokerResponse::FencedLeaderEpoch {
+                    current_leader_epoch,
+                    ..
+                } => {
+                    return Err(io::Error::other(format!(
+                        "{} is an old leader in epoch {}, not copying from it",
+                        self.leader_address, current_leader_epoch
+                    )))
+                }
+                response => {
+                    return Err(io::Error::other(format!(
+                        "Unexpected response {:?}",
+                        response
+                    )))
+                }
+            };
+        let fetched_bytes = batches
+            .iter()
+            .map(|fetched_batch| fetched_batch.batch.records.len() as u64)
+            .sum();
+        let mut appended_records = 0;
+        for fetched_batch in batches {
+            let Some(batch) = records_from(fetched_batch, fetch_offset + appended_records as u64)?
+            else {
+                continue;
+            };
+            let record_count = batch.record_count as usize;
+            let (base_offset_tx, base_offset_rx) = oneshot::channel();
+            partition
+                .partition_tx
+                .send(PartitionAppend {
+                    batch,
+                    acks: Acks::Leader,
+                    base_offset_tx: Some(base_offset_tx),
+                })
+                .await
+                .map_err(io::Error::other)?;
+            match base_offset_rx.await {
+                Ok(Ok(_)) => appended_records += record_count,
+                Ok(Err(error)) => return Err(io::Error::other(error)),
+                Err(_) => return Err(io::Error::other("Partition writer dropped the batch")),
+            }
+        }
+        let log_end_offset = partition.log_end_offset.load(Ordering::SeqCst);
+        partition
+            .high_watermark
+            .store(high_watermark.min(log_end_offset), Ordering::SeqCst);
+        Ok((appended_records, fetched_bytes, Some(leader_epoch)))
+    }
+}
+
+/// The records of a fetched batch at or after `offset`, `None` when the batch ends before it.
+/// Leaders return whole batches, so the first batch may start before the fetch offset.
+fn records_from(fetched_batch: FetchedBatch, offset: u64) -> io::Result<Option<RecordBatch>> {
+    let FetchedBatch { base_offset, batch } = fetched_batch;
+    if base_offset >= offset {
+        return Ok(Some(batch));
+    }
+    let skipped_records = (offset - base_offset) as usize;
+    if skipped_records >= batch.record_count as usize {
+        return Ok(None);
+    }
+    let timestamp_type = batch.timestamp_type;
+    let max_timestamp = batch.max_timestamp;
+    let mut batch = batch.into_batch()?;
+    batch.records.drain(..skipped_records);
+    if let Some(producer) = batch.producer.as_mut() {
+        producer.base_sequence += skipped_records as u32;
+    }
+    let mut record_batch = RecordBatch::new(batch)?;
+    // the remaining records keep the leader's log append time
+    if let (TimestampType::LogAppendTime, Some(log_append_time)) = (timestamp_type, max_timestamp) {
+        record_batch.set_log_append_time(log_append_time);
+    }
+    Ok(Some(record_batch))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use common::codecs::protocol::{RequestCodec, Response};
+    use common::models::{Batch, CompressionCodec, EpochEndOffset, Message, OverflowPolicy, Topic};
+    use test_log::test;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+    use crate::buffer_pool::BufferPool;
+    use crate::clock::BrokerClock;
+    use crate::managers::partition_manager::{
+        read_records, start_partition_writer, PartitionWriterContext,
+    };
+    use crate::metrics::Metrics;
+    use crate::models::PartitionInfo;
+    use crate::partition_queue::partition_queue;
+
+    fn messages(payloads: &[&'static str]) -> Vec<Message> {
+        payloads
+            .iter()
+            .map(|payload| Message::new(Bytes::from_static(payload.as_bytes()), None, Some(1)))
+            .collect()
+    }
+
+    #[test]
+    fn test_records_from_should_skip_records_before_offset() {
+        let batch = RecordBatch::new(Batch {
+            records: messages(&["a", "b", "c"]),
+            compression: CompressionCodec::Lz4,
+            ..Batch::default()
+        })
+        .unwrap();
+        let fetched_batch = |batch: &RecordBatch| FetchedBatch {
+            base_offset: 10,
+            batch: batch.clone(),
+        };
+
+        assert_eq!(
+            records_from(fetched_batch(&batch), 10).unwrap(),
+            Some(batch.clone())
+        );
+        assert_eq!(records_from(fetched_batch(&batch), 13).unwrap(), None);
+        let tail = records_from(fetched_batch(&batch), 12).unwrap().unwrap();
+        assert_eq!(tail.compression, CompressionCodec::Lz4);
+        assert_eq!(tail.records().unwrap(), messages(&["c"]));
+    }
+
+    #[test(tokio::test)]
+    async fn test_replica_fetcher_should_truncate_and_append_leader_batches() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let topic = Topic::new("t1".to_string(), Some(1), Some(2), Some(1), Some(10), None);
+        let partition_info = PartitionInfo::new(
+            topic,
+            0,
+            format!("{}/t1", temp_dir.path().to_str().unwrap()),
+        );
+        let segment_file_path = partition_info.segment_file_path();
+        let mut leader_epochs = LeaderEpochCache::load(0, &partition_info.partition_path);
+        let cancellation_token = CancellationToken::new();
+        let (partition_tx, partition_rx) = partition_queue(
+            TopicPartition::new("t1".to_string(), 0),
+            10,
+            OverflowPolicy::default(),
+            &Metrics::new(),
+        );
+        let log_end_offset = Arc::new(AtomicU64::new(0));
+        let writer_handle = tokio::spawn(start_partition_writer(
+            partition_info,
+            partition_rx,
+            log_end_offset.clone(),
+            PartitionWriterContext {
+                linger: Duration::ZERO,
+                segment_buffers: BufferPool::new("segments", 1024, 1, &Metrics::new()),
+                metrics: Metrics::new(),
+                clock: Arc::new(BrokerClock::new()),
+            },
+            cancellation_token.clone(),
+        ));
+        // the follower led the partition in epoch 3 and appended a record the new leader of
+        // epoch 4 did not copy
+        for records in [messages(&["a", "b"]), messages(&["x"])] {
+            let (base_offset_tx, base_offset_rx) = oneshot::channel();
+            partition_tx
+                .send(PartitionAppend {
+                    batch: RecordBatch::new(Batch {
+                        records,
+                        ..Batch::default()
+                    })
+                    .unwrap(),
+                    acks: Acks::Leader,
+                    base_offset_tx: Some(base_offset_tx),
+                })
+                .await
+                .unwrap();
+            base_offset_rx.await.unwrap().unwrap();
+        }
+        leader_epochs.assign(3, 2);
+
+        // a leader serving two batches, the follower must ask for the second one once it
+        // appended the first
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let leader_address = listener.local_addr().unwrap().to_string();
+        let leader_batches = [
+            FetchedBatch {
+                base_offset: 0,
+                batch: RecordBatch::new(Batch {
+                    records: messages(&["a", "b"]),
+                    ..Batch::default()
+                })
+                .unwrap(),
+            },
+            FetchedBatch {
+                base_offset: 2,
+                batch: RecordBatch::new(Batch {
+                    records: messages(&["c"]),
+                    compression: CompressionCodec::Zstd,
+                    ..Batch::default()
+                })
+                .unwrap(),
+            },
+        ];
+        let leader_handle = tokio::spawn(async move {
+            let mut fetch_offsets = vec![];
+            while fetch_offsets.len() < 2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request_codec = RequestCodec::default();
+                let mut received = BytesMut::new();
+                let request = loop {
+                    if let Some(request) = request_codec.decode(&mut received).unwrap() {
+                        break request;
+                    }
+                    socket.read_buf(&mut received).await.unwrap();
+                };
+                let response = match request.command {
+                    // the leader has no records of epoch 3, its epoch 0 ends at offset 2
+                    TopicCommand::OffsetForLeaderEpoch {
+                        topic_partition,
+                        leader_epoch,
+                    } => {
+                        assert_eq!(leader_epoch, 3);
+                        assert!(fetch_offsets.is_empty());
+                        BrokerResponse::EpochEndOffset {
+                            topic_partition,
+                            end_offset: Some(EpochEndOffset {
+                                leader_epoch: 0,
+                                end_offset: 2,
+                            }),
+                            current_leader_epoch: 4,
+                        }
+                    }
+                    TopicCommand::Fetch(fetch_request) => {
+                        assert_eq!(fetch_request.replica_id, Some(1));
+                        let offset = fetch_request.offset.unwrap();
+                        fetch_offsets.push((offset, fetch_request.leader_epoch));
+                        BrokerResponse::Records {
+                            topic_partition: fetch_request.topic_partition,
+                            base_offset: offset,
+                            batches: leader_batches
+                                .iter()
+                                .filter(|fetched_batch| fetched_batch.base_offset >= offset)
+                                .take(1)
+                                .cloned()
+                                .collect(),
+                            log_end_offset: 3,
+                            high_watermark: 3,
+                            leader_epoch: 4,
+                        }
+                    }
+                    command => panic!("Unexpected request {:?}", command),
+                };
+                let mut response_bytes = BytesMut::new();
+                request_codec
+                    .encode(
+                        Response {
+                            correlation_id: request.header.correlation_id,
+                            throttle_time_ms: 0,
+                            response,
+                        },
+                        &mut response_bytes,
+                    )
+                    .unwrap();
+                socket.write_all(&response_bytes).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+            fetch_offsets
+        });
+
+        let high_watermark = Arc::new(AtomicU64::new(0));
+        let (commands_tx, commands_rx) = mpsc::channel(10);
+        commands_tx
+            .send(ReplicaFetcherCommands::AddPartition {
+                topic_partition: TopicPartition::new("t1".to_string(), 0),
+                partition_tx,
+                log_end_offset: log_end_offset.clone(),
+                leader_epochs: Arc::new(Mutex::new(leader_epochs)),
+                high_watermark: high_watermark.clone(),
+            })
+            .await
+            .unwrap();
+        let fetcher = ReplicaFetcher::new(
+            1,
+            leader_address,
+            Arc::new(ReplicationThrottles::new(None, None, &Metrics::new())),
+            cancellation_token.clone(),
+        );
+        let fetcher_handle = tokio::spawn(fetcher.start_replica_fetcher(commands_rx));
+
+        // the follower fetches in the leader's epoch from where its log was truncated
+        assert_eq!(
+            leader_handle.await.unwrap(),
+            vec![(2, Some(4)), (3, Some(4))]
+        );
+        assert_eq!(log_end_offset.load(Ordering::SeqCst), 3);
+        assert_eq!(high_watermark.load(Ordering::SeqCst), 3);
+        cancellation_token.cancel();
+        fetcher_handle.await.unwrap();
+        writer_handle.await.unwrap();
+
+        let batches = read_records(&segment_file_path, 0, 10).await;
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1].base_offset, 2);
+        assert_eq!(batches[1].batch.compression, CompressionCodec::Zstd);
+        assert_eq!(
+            FetchedBatch::records(batches, 0, 10).unwrap(),
+            messages(&["a", "b", "c"])
+        );
+    }
+}
@@ -1,145 +1,1140 @@
-use std::collections::HashMap;
-use std::hash::{DefaultHasher, Hasher};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use common::models::{Message, Topic};
+use common::errors::WalrsError;
+use common::models::{
+    BrokerResponse, LeaderAndIsr, PartitionMetadata, Topic, TopicMetadata, TopicPartition,
+};
+use tokio::runtime::Handle;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::sync::oneshot;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
-use crate::managers::partition_manager::start_partition_writer;
-use crate::models::PartitionInfo;
+use crate::buffer_pool::BufferPool;
+use crate::clock::Clock;
+use crate::cluster::{BrokerId, ClusterSettings};
+use crate::high_watermark::HighWatermark;
+use crate::isr::PartitionIsr;
+use crate::leader_epoch::{LeaderEpochCache, PartitionLeader};
+use crate::managers::partition_manager::{start_partition_writer, PartitionWriterContext};
+use crate::managers::replica_fetcher::{ReplicaFetcher, ReplicaFetcherCommands};
+use crate::metrics::Metrics;
+use crate::models::{PartitionInfo, PartitionReadInfo, PartitionState};
+use crate::partition_queue::{partition_queue, PartitionSender};
+use crate::partition_routes::{PartitionRoute, PartitionRoutes};
+use crate::quotas::ReplicationThrottles;
 
-const PARTITION_MANAGER_CHANNEL_SIZE: usize = 1000;
+const TOPIC_EVENTS_CHANNEL_SIZE: usize = 100;
+const REPLICA_FETCHER_CHANNEL_SIZE: usize = 100;
+/// Initial size of the buffers batches are encoded into before they are written.
+const SEGMENT_BUFFER_SIZE: usize = 64 * 1024;
+/// Encoding buffers kept between writes, shared by the partition writers of the broker.
+const IDLE_SEGMENT_BUFFERS: usize = 32;
 
 pub struct TopicsManager {
     topics: HashMap<String, Topic>,
     cancellation_token: CancellationToken,
     log_dir_path: String,
-    partition_client_tx: HashMap<String, Sender<Message>>,
+    /// Queue size of the partitions of topics without their own `queue_size`
+    partition_channel_size: usize,
+    partition_client_tx: HashMap<String, PartitionSender>,
+    /// Snapshot of `partition_client_tx` and `partition_leaders` connection handlers route writes
+    /// with, published after every change to them
+    partition_routes: PartitionRoutes,
+    partition_log_end_offsets: HashMap<String, Arc<AtomicU64>>,
+    partition_leaders: HashMap<TopicPartition, PartitionLeader>,
+    /// Where the leader epochs start in every partition's log, shared with the replica fetchers
+    /// truncating the logs this broker follows
+    partition_leader_epochs: HashMap<TopicPartition, Arc<Mutex<LeaderEpochCache>>>,
+    /// High watermark of every partition, checkpointed every `high_watermark_checkpoint_interval`
+    partition_high_watermarks: HashMap<TopicPartition, HighWatermark>,
+    partition_replicas: HashMap<TopicPartition, Vec<BrokerId>>,
+    /// ISR of every replicated partition this broker leads
+    partition_isrs: HashMap<TopicPartition, PartitionIsr>,
+    /// Last ISR the leaders of the other partitions reported
+    known_isrs: HashMap<TopicPartition, Vec<BrokerId>>,
+    partition_reassignments: HashMap<TopicPartition, OngoingReassignment>,
+    /// Limits of the replication traffic, shared with the connections serving followers
+    replication_throttles: Arc<ReplicationThrottles>,
+    cluster_settings: ClusterSettings,
+    replica_fetchers_tx: HashMap<BrokerId, Sender<ReplicaFetcherCommands>>,
     partition_manager_task_tracker: TaskTracker,
+    /// Runtime the partition writers are spawned on
+    disk_io: Handle,
+    /// Partition writers of every topic, stopped on their own when the topic is deleted
+    topic_writers: HashMap<String, TopicWriters>,
+    topic_events_tx: broadcast::Sender<TopicEvent>,
+    metrics: Metrics,
+    /// Buffers the partition writers encode batches into
+    segment_buffers: BufferPool,
+    /// Time of the partition writers' linger and of the ISRs' replica lag
+    clock: Arc<dyn Clock>,
+}
+
+struct TopicWriters {
+    cancellation_token: CancellationToken,
+    task_tracker: TaskTracker,
 }
 
 impl TopicsManager {
-    pub fn new(log_dir_path: String, cancellation_token: CancellationToken) -> Self {
+    pub fn new(
+        log_dir_path: String,
+        partition_channel_size: usize,
+        cluster_settings: ClusterSettings,
+        metrics: &Metrics,
+        disk_io: Handle,
+        clock: Arc<dyn Clock>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
         TopicsManager {
             topics: HashMap::new(),
             cancellation_token,
             log_dir_path,
+            partition_channel_size,
             partition_client_tx: HashMap::new(),
+            partition_routes: PartitionRoutes::new(cluster_settings.broker_id),
+            partition_log_end_offsets: HashMap::new(),
+            partition_leaders: HashMap::new(),
+            partition_leader_epochs: HashMap::new(),
+            partition_high_watermarks: HashMap::new(),
+            partition_replicas: HashMap::new(),
+            partition_isrs: HashMap::new(),
+            known_isrs: HashMap::new(),
+            partition_reassignments: HashMap::new(),
+            replication_throttles: Arc::new(ReplicationThrottles::new(
+                cluster_settings.leader_replication_throttled_rate,
+                cluster_settings.follower_replication_throttled_rate,
+                metrics,
+            )),
+            cluster_settings,
+            replica_fetchers_tx: HashMap::new(),
             partition_manager_task_tracker: TaskTracker::new(),
+            disk_io,
+            topic_writers: HashMap::new(),
+            topic_events_tx: broadcast::channel(TOPIC_EVENTS_CHANNEL_SIZE).0,
+            metrics: metrics.clone(),
+            segment_buffers: BufferPool::new(
+                "segments",
+                SEGMENT_BUFFER_SIZE,
+                IDLE_SEGMENT_BUFFERS,
+                metrics,
+            ),
+            clock,
         }
     }
 
+    /// Receives an event for every change to the set of topics, e.g. for pattern subscriptions.
+    pub fn subscribe_topic_events(&self) -> broadcast::Receiver<TopicEvent> {
+        self.topic_events_tx.subscribe()
+    }
+
+    /// Routes connection handlers read to write to this broker's partitions.
+    pub fn partition_routes(&self) -> PartitionRoutes {
+        self.partition_routes.clone()
+    }
+
+    /// Tracks the partition writers of every topic.
+    pub fn partition_writer_tasks(&self) -> TaskTracker {
+        self.partition_manager_task_tracker.clone()
+    }
+
+    pub fn replication_throttles(&self) -> Arc<ReplicationThrottles> {
+        self.replication_throttles.clone()
+    }
+
     pub async fn start_topics_manager(&mut self, mut parent_rx: Receiver<TopicManagerCommands>) {
         tracing::info!("Topic Manager started");
+        // like Kafka, check twice within the lag time so lagging followers are removed in time
+        let mut isr_check_interval = tokio::time::interval(
+            (self.cluster_settings.replica_lag_time_max / 2).max(Duration::from_millis(1)),
+        );
+        let mut high_watermark_checkpoint_interval = tokio::time::interval(
+            self.cluster_settings
+                .high_watermark_checkpoint_interval
+                .max(Duration::from_millis(1)),
+        );
         loop {
             tokio::select! {
                     Some(command) = parent_rx.recv() => {
                         match command {
-                            TopicManagerCommands::CreateTopic { topic, reply_tx } => {
-                                self.create_topic(topic, reply_tx).await;
+                            TopicManagerCommands::CreateTopic { topic, replicas, reply_tx } => {
+                                self.create_topic(topic, replicas, reply_tx).await;
+                            }
+                            TopicManagerCommands::DeleteTopic { topic_name, reply_tx } => {
+                                self.delete_topic(topic_name, reply_tx).await;
                             }
-                            TopicManagerCommands::GetPartitionManagerTx {
+                            TopicManagerCommands::GetTopicInfo {
                                 topic_name,
-                                message_key,
                                 reply_tx,
                             } => {
-                                let partition_index = message_key
-                                    .as_ref()
-                                    .map(|key| {
-                                        let mut hasher = DefaultHasher::new();
-                                        hasher.write(key.as_bytes());
-                                        let topic = self.topics.get(topic_name.as_str()).unwrap();
-                                        (hasher.finish() % topic.num_partitions.unwrap() as u64) as u8
-                                    })
-                                    .unwrap_or(0);
-                                let partition_name = format!("{}-{}", topic_name, partition_index);
-                                if self.partition_client_tx.contains_key(&partition_name) {
-                                    let client_tx = self.partition_client_tx.get(&partition_name).unwrap();
-                                    reply_tx.send(Some(client_tx.to_owned())).unwrap();
-                                } else {
-                                    reply_tx.send(None).unwrap();
-                                }
+                                let topic = self
+                                    .topics
+                                    .get(&topic_name)
+                                    .cloned()
+                                    .ok_or(WalrsError::UnknownTopic(topic_name));
+                                reply(reply_tx, topic);
                             }
-                            TopicManagerCommands::GetTopicInfo {
+                            TopicManagerCommands::ListTopics { reply_tx } => {
+                                reply(reply_tx, self.topics.keys().cloned().collect());
+                            }
+                            TopicManagerCommands::GetLogEndOffsets {
                                 topic_name,
                                 reply_tx,
                             } => {
-                                if self.topics.contains_key(topic_name.as_str()) {
-                                    let topic = self.topics.get(&topic_name);
-                                    reply_tx.send(topic.cloned()).unwrap();
-                                } else {
-                                    reply_tx.send(None).unwrap();
-                                }
+                                reply(reply_tx, self.log_end_offsets(&topic_name));
+                            }
+                            TopicManagerCommands::GetPartitionReadInfo {
+                                topic_partition,
+                                reply_tx,
+                            } => {
+                                reply(reply_tx, self.partition_read_info(&topic_partition));
+                            }
+                            TopicManagerCommands::GetEpochEndOffset {
+                                topic_partition,
+                                leader_epoch,
+                                reply_tx,
+                            } => {
+                                reply(reply_tx, self.epoch_end_offset(topic_partition, leader_epoch));
+                            }
+                            TopicManagerCommands::GetPreferredReadReplica {
+                                topic_partition,
+                                rack_id,
+                                broker_racks,
+                                reply_tx,
+                            } => {
+                                let preferred_read_replica = self.preferred_read_replica(
+                                    &topic_partition,
+                                    &rack_id,
+                                    &broker_racks,
+                                );
+                                reply(reply_tx, preferred_read_replica);
+                            }
+                            TopicManagerCommands::RecordReplicaFetch {
+                                topic_partition,
+                                replica_id,
+                                fetch_offset,
+                            } => {
+                                self.record_replica_fetch(topic_partition, replica_id, fetch_offset);
+                            }
+                            TopicManagerCommands::GetPartitionStates { reply_tx } => {
+                                reply(reply_tx, self.partition_states());
+                            }
+                            TopicManagerCommands::GetLaggingPartitions { broker_id, reply_tx } => {
+                                reply(reply_tx, self.lagging_partitions(broker_id));
+                            }
+                            TopicManagerCommands::GetTopicMetadata { topic_names, reply_tx } => {
+                                reply(reply_tx, self.topic_metadata(topic_names));
+                            }
+                            TopicManagerCommands::UpdateLeaderAndIsr {
+                                topic_partition,
+                                leader_and_isr,
+                            } => {
+                                self.update_leader_and_isr(topic_partition, leader_and_isr).await;
+                                self.publish_partition_routes();
+                            }
+                            TopicManagerCommands::UpdateReplicas {
+                                topic_partition,
+                                replicas,
+                                reassignment,
+                                leader_and_isr,
+                            } => {
+                                self.update_replicas(topic_partition, replicas, reassignment, leader_and_isr)
+                                    .await;
+                                self.publish_partition_routes();
                             }
                         }
                     }
+                    _ = isr_check_interval.tick() => {
+                        self.shrink_isrs();
+                    }
+                    _ = high_watermark_checkpoint_interval.tick() => {
+                        self.checkpoint_high_watermarks();
+                    }
                     _ = self.cancellation_token.cancelled() => {
                         tracing::info!("Cancellation token received for topic manager.");
                         self.partition_manager_task_tracker.close();
                         self.partition_manager_task_tracker.wait().await;
+                        self.checkpoint_high_watermarks();
                         break;
                 }
             }
         }
     }
 
-    async fn create_topic(&mut self, topic: Topic, reply_tx: oneshot::Sender<Option<Topic>>) {
+    fn preferred_read_replica(
+        &self,
+        topic_partition: &TopicPartition,
+        rack_id: &str,
+        broker_racks: &BTreeMap<BrokerId, Option<String>>,
+    ) -> Option<BrokerId> {
+        let broker_id = self.cluster_settings.broker_id;
+        let leads_partition = self
+            .partition_leaders
+            .get(topic_partition)
+            .is_some_and(|partition_leader| partition_leader.leader_id == broker_id);
+        if !leads_partition || self.cluster_settings.rack.as_deref() == Some(rack_id) {
+            return None;
+        }
+        self.partition_isrs
+            .get(topic_partition)?
+            .isr()
+            .into_iter()
+            .find(|replica_id| {
+                *replica_id != broker_id
+                    && broker_racks
+                        .get(replica_id)
+                        .is_some_and(|rack| rack.as_deref() == Some(rack_id))
+            })
+    }
+
+    /// Where `leader_epoch` ends in the log of a partition this broker leads, for a follower to
+    /// find where its log diverges. Followers asking an old leader, or a leader which missed the
+    /// requested epoch, are fenced.
+    fn epoch_end_offset(
+        &self,
+        topic_partition: TopicPartition,
+        leader_epoch: u32,
+    ) -> BrokerResponse {
+        let partition_name = format!(
+            "{}-{}",
+            topic_partition.topic_name, topic_partition.partition_index
+        );
+        let (Some(partition_leader), Some(leader_epochs), Some(log_end_offset)) = (
+            self.partition_leaders.get(&topic_partition),
+            self.partition_leader_epochs.get(&topic_partition),
+            self.partition_log_end_offsets.get(&partition_name),
+        ) else {
+            return BrokerResponse::UnknownTopicPartition { topic_partition };
+        };
+        if partition_leader.leader_id != self.cluster_settings.broker_id
+            || leader_epoch > partition_leader.leader_epoch
+        {
+            return BrokerResponse::FencedLeaderEpoch {
+                topic_partition,
+                leader_epoch,
+                current_leader_epoch: partition_leader.leader_epoch,
+            };
+        }
+        let end_offset = leader_epochs
+            .lock()
+            .unwrap()
+            .end_offset_for(leader_epoch, log_end_offset.load(Ordering::SeqCst));
+        BrokerResponse::EpochEndOffset {
+            topic_partition,
+            end_offset,
+            current_leader_epoch: partition_leader.leader_epoch,
+        }
+    }
+
+    /// High watermark of the partition, at most its log end offset. Leaders move it up to where
+    /// every replica in the ISR copied the partition, which is the log end offset without
+    /// followers. Followers take it from their leader's fetch answers.
+    fn high_watermark(&self, topic_partition: &TopicPartition, log_end_offset: u64) -> u64 {
+        let Some(high_watermark) = self.partition_high_watermarks.get(topic_partition) else {
+            return log_end_offset;
+        };
+        let leads_partition =
+            self.partition_leaders
+                .get(topic_partition)
+                .is_some_and(|partition_leader| {
+                    partition_leader.leader_id == self.cluster_settings.broker_id
+                });
+        if leads_partition {
+            let isr_high_watermark = self
+                .partition_isrs
+                .get(topic_partition)
+                .map_or(log_end_offset, |partition_isr| {
+                    partition_isr.high_watermark(log_end_offset)
+                });
+            high_watermark.advance(isr_high_watermark);
+        }
+        high_watermark.offset().min(log_end_offset)
+    }
+
+    /// Advances the high watermarks of the partitions this broker leads and checkpoints the ones
+    /// which moved since the last checkpoint.
+    fn checkpoint_high_watermarks(&mut self) {
+        for topic_partition in self.partition_high_watermarks.keys() {
+            let partition_name = format!(
+                "{}-{}",
+                topic_partition.topic_name, topic_partition.partition_index
+            );
+            if let Some(log_end_offset) = self.partition_log_end_offsets.get(&partition_name) {
+                self.high_watermark(topic_partition, log_end_offset.load(Ordering::SeqCst));
+            }
+        }
+        for high_watermark in self.partition_high_watermarks.values_mut() {
+            high_watermark.checkpoint();
+        }
+    }
+
+    fn record_replica_fetch(
+        &mut self,
+        topic_partition: TopicPartition,
+        replica_id: BrokerId,
+        fetch_offset: u64,
+    ) {
+        let Some(partition_isr) = self.partition_isrs.get_mut(&topic_partition) else {
+            tracing::warn!(
+                "Broker {} fetched {:?} which is not replicated from this broker",
+                replica_id,
+                topic_partition
+            );
+            return;
+        };
+        let partition_name = format!(
+            "{}-{}",
+            topic_partition.topic_name, topic_partition.partition_index
+        );
+        let log_end_offset = self.partition_log_end_offsets[&partition_name].load(Ordering::SeqCst);
+        if partition_isr.record_fetch(replica_id, fetch_offset, log_end_offset, self.clock.now()) {
+            let isr = partition_isr.isr();
+            tracing::info!("ISR of {} expanded to {:?}", partition_name, isr);
+            self.broadcast_isr(topic_partition, isr);
+        }
+    }
+
+    /// Partitions broker `broker_id` leads whose followers in the ISR did not copy every record
+    /// yet. Only the leader learns how far its followers fetched, other brokers find none.
+    fn lagging_partitions(&self, broker_id: BrokerId) -> Vec<TopicPartition> {
+        if broker_id != self.cluster_settings.broker_id {
+            return vec![];
+        }
+        self.partition_isrs
+            .iter()
+            .filter(|(topic_partition, partition_isr)| {
+                let partition_name = format!(
+                    "{}-{}",
+                    topic_partition.topic_name, topic_partition.partition_index
+                );
+                let log_end_offset =
+                    self.partition_log_end_offsets[&partition_name].load(Ordering::SeqCst);
+                !partition_isr.followers_caught_up(log_end_offset)
+            })
+            .map(|(topic_partition, _)| topic_partition.clone())
+            .collect()
+    }
+
+    fn shrink_isrs(&mut self) {
+        let now = self.clock.now();
+        let mut shrunk_isrs = vec![];
+        for (topic_partition, partition_isr) in self.partition_isrs.iter_mut() {
+            if partition_isr.shrink(self.cluster_settings.replica_lag_time_max, now) {
+                tracing::warn!(
+                    "ISR of {:?} shrank to {:?}, followers did not catch up within {:?}",
+                    topic_partition,
+                    partition_isr.isr(),
+                    self.cluster_settings.replica_lag_time_max
+                );
+                shrunk_isrs.push((topic_partition.clone(), partition_isr.isr()));
+            }
+        }
+        for (topic_partition, isr) in shrunk_isrs {
+            self.broadcast_isr(topic_partition, isr);
+        }
+    }
+
+    /// Reports the ISR of a partition this broker leads, so the other brokers learn it through
+    /// the metadata log.
+    fn broadcast_isr(&self, topic_partition: TopicPartition, isr: Vec<BrokerId>) {
+        let partition_leader = self.partition_leaders[&topic_partition];
+        // nobody listening for topic events is not an error
+        let _ = self.topic_events_tx.send(TopicEvent::IsrChanged {
+            topic_partition,
+            leader_and_isr: LeaderAndIsr {
+                leader_id: partition_leader.leader_id,
+                leader_epoch: partition_leader.leader_epoch,
+                isr,
+            },
+        });
+    }
+
+    fn partition_states(&self) -> Vec<PartitionState> {
+        self.partition_leaders
+            .iter()
+            .map(|(topic_partition, partition_leader)| {
+                let replicas = self.partition_replicas[topic_partition].clone();
+                let isr = match self.partition_isrs.get(topic_partition) {
+                    Some(partition_isr) => partition_isr.isr(),
+                    None if partition_leader.leader_id == self.cluster_settings.broker_id => {
+                        vec![partition_leader.leader_id]
+                    }
+                    None => self
+                        .known_isrs
+                        .get(topic_partition)
+                        .cloned()
+                        .unwrap_or_else(|| replicas.clone()),
+                };
+                let reassignment = self
+                    .partition_reassignments
+                    .get(topic_partition)
+                    .cloned()
+                    .unwrap_or_default();
+                PartitionState {
+                    topic_partition: topic_partition.clone(),
+                    replicas,
+                    adding_replicas: reassignment.adding_replicas,
+                    removing_replicas: reassignment.removing_replicas,
+                    leader_and_isr: LeaderAndIsr {
+                        leader_id: partition_leader.leader_id,
+                        leader_epoch: partition_leader.leader_epoch,
+                        isr,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Applies a leader the controller elected, or the ISR the partition's leader reported.
+    /// Updates of older epochs are ignored. In a new epoch this broker starts leading the
+    /// partition or follows its new leader. A new leader starts its epoch at its log end offset,
+    /// followers truncate the records the new leader does not have before they copy its log.
+    async fn update_leader_and_isr(
+        &mut self,
+        topic_partition: TopicPartition,
+        leader_and_isr: LeaderAndIsr,
+    ) {
+        let broker_id = self.cluster_settings.broker_id;
+        let Some(current_leader) = self.partition_leaders.get(&topic_partition).copied() else {
+            tracing::warn!(
+                "Ignoring leader and ISR of unknown partition {:?}",
+                topic_partition
+            );
+            return;
+        };
+        if leader_and_isr.leader_epoch < current_leader.leader_epoch {
+            tracing::warn!(
+                "Ignoring leader and ISR of {:?} from old epoch {}, current epoch is {}",
+                topic_partition,
+                leader_and_isr.leader_epoch,
+                current_leader.leader_epoch
+            );
+            return;
+        }
+        if leader_and_isr.leader_epoch == current_leader.leader_epoch {
+            if current_leader.leader_id != broker_id {
+                self.known_isrs.insert(topic_partition, leader_and_isr.isr);
+            } else if let Some(partition_isr) = self.partition_isrs.get_mut(&topic_partition) {
+                if partition_isr.retain(&leader_and_isr.isr) {
+                    tracing::info!(
+                        "ISR of {:?} shrank to {:?}",
+                        topic_partition,
+                        partition_isr.isr()
+                    );
+                }
+            }
+            return;
+        }
+        let partition_path = self.partition_path(&topic_partition);
+        let partition_leader = PartitionLeader {
+            leader_id: leader_and_isr.leader_id,
+            leader_epoch: leader_and_isr.leader_epoch,
+        };
+        partition_leader.store(&partition_path);
+        self.partition_leaders
+            .insert(topic_partition.clone(), partition_leader);
+        self.partition_isrs.remove(&topic_partition);
+        let replicas = self.partition_replicas[&topic_partition].clone();
+        if current_leader.leader_id != broker_id && replicas.contains(&broker_id) {
+            self.unfollow_partition(current_leader.leader_id, topic_partition.clone())
+                .await;
+        }
+        if leader_and_isr.leader_id == broker_id {
+            let partition_name = format!(
+                "{}-{}",
+                topic_partition.topic_name, topic_partition.partition_index
+            );
+            let log_end_offset =
+                self.partition_log_end_offsets[&partition_name].load(Ordering::SeqCst);
+            self.partition_leader_epochs[&topic_partition]
+                .lock()
+                .unwrap()
+                .assign(leader_and_isr.leader_epoch, log_end_offset);
+            self.known_isrs.remove(&topic_partition);
+            self.track_isr(topic_partition, &leader_and_isr.isr);
+        } else {
+            self.known_isrs
+                .insert(topic_partition.clone(), leader_and_isr.isr);
+            if replicas.contains(&broker_id) {
+                let partition_name = format!(
+                    "{}-{}",
+                    topic_partition.topic_name, topic_partition.partition_index
+                );
+                let client_tx = self.partition_client_tx[&partition_name].clone();
+                let log_end_offset = self.partition_log_end_offsets[&partition_name].clone();
+                self.follow_partition(
+                    leader_and_isr.leader_id,
+                    topic_partition,
+                    client_tx,
+                    log_end_offset,
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Applies replicas a reassignment changed, with the leader and ISR of the new epoch. Unlike
+    /// leaders and ISRs, replicas are applied from records of older epochs as well: a restarted
+    /// broker replays the metadata log while it restored the leader of the latest epoch.
+    async fn update_replicas(
+        &mut self,
+        topic_partition: TopicPartition,
+        replicas: Vec<BrokerId>,
+        reassignment: OngoingReassignment,
+        leader_and_isr: LeaderAndIsr,
+    ) {
+        let broker_id = self.cluster_settings.broker_id;
+        let Some(current_leader) = self.partition_leaders.get(&topic_partition).copied() else {
+            tracing::warn!(
+                "Ignoring replicas of unknown partition {:?}",
+                topic_partition
+            );
+            return;
+        };
+        tracing::info!(
+            "Replicas of {:?} changed to {:?}, {:?}",
+            topic_partition,
+            replicas,
+            reassignment
+        );
+        let old_replicas = self
+            .partition_replicas
+            .insert(topic_partition.clone(), replicas.clone())
+            .unwrap_or_default();
+        self.replication_throttles.throttle_reassignment(
+            topic_partition.clone(),
+            reassignment.throttle_rate,
+            reassignment.adding_replicas.clone(),
+        );
+        if reassignment.adding_replicas.is_empty() && reassignment.removing_replicas.is_empty() {
+            self.partition_reassignments.remove(&topic_partition);
+        } else {
+            self.partition_reassignments
+                .insert(topic_partition.clone(), reassignment);
+        }
+        let was_replica = old_replicas.contains(&broker_id);
+        let is_replica = replicas.contains(&broker_id);
+        if was_replica && !is_replica && current_leader.leader_id != broker_id {
+            self.unfollow_partition(current_leader.leader_id, topic_partition.clone())
+                .await;
+        }
+        if leader_and_isr.leader_epoch > current_leader.leader_epoch {
+            self.update_leader_and_isr(topic_partition, leader_and_isr)
+                .await;
+            return;
+        }
+        // a replayed record, the restored leader stays
+        if current_leader.leader_id == broker_id {
+            let isr = self
+                .partition_isrs
+                .get(&topic_partition)
+                .map(PartitionIsr::isr)
+                .unwrap_or_else(|| vec![broker_id]);
+            self.track_isr(topic_partition, &isr);
+        } else if is_replica && !was_replica {
+            let partition_name = format!(
+                "{}-{}",
+                topic_partition.topic_name, topic_partition.partition_index
+            );
+            let client_tx = self.partition_client_tx[&partition_name].clone();
+            let log_end_offset = self.partition_log_end_offsets[&partition_name].clone();
+            self.follow_partition(
+                current_leader.leader_id,
+                topic_partition,
+                client_tx,
+                log_end_offset,
+            )
+            .await;
+        }
+    }
+
+    /// Tracks the ISR of a partition this broker leads, starting with `isr`. Partitions without
+    /// followers have no ISR to track.
+    fn track_isr(&mut self, topic_partition: TopicPartition, isr: &[BrokerId]) {
+        let broker_id = self.cluster_settings.broker_id;
+        let follower_ids: Vec<BrokerId> = self.partition_replicas[&topic_partition]
+            .iter()
+            .copied()
+            .filter(|replica_id| *replica_id != broker_id)
+            .collect();
+        if follower_ids.is_empty() {
+            self.partition_isrs.remove(&topic_partition);
+            return;
+        }
+        let partition_isr = PartitionIsr::new(
+            broker_id,
+            &follower_ids,
+            isr,
+            &self.partition_path(&topic_partition),
+            self.clock.now(),
+        );
+        self.partition_isrs.insert(topic_partition, partition_isr);
+    }
+
+    fn partition_path(&self, topic_partition: &TopicPartition) -> String {
+        format!(
+            "{}/{}/{}",
+            self.log_dir_path, topic_partition.topic_name, topic_partition.partition_index
+        )
+    }
+
+    fn publish_partition_routes(&self) {
+        let routes = self
+            .partition_leaders
+            .iter()
+            .filter_map(|(topic_partition, partition_leader)| {
+                let partition_name = format!(
+                    "{}-{}",
+                    topic_partition.topic_name, topic_partition.partition_index
+                );
+                let partition_tx = self.partition_client_tx.get(&partition_name)?.clone();
+                let max_message_bytes = self
+                    .topics
+                    .get(&topic_partition.topic_name)?
+                    .max_message_bytes
+                    .map_or(
+                        self.cluster_settings.message_max_bytes,
+                        |max_message_bytes| max_message_bytes as usize,
+                    );
+                let route = PartitionRoute {
+                    partition_tx,
+                    leader: *partition_leader,
+                    max_message_bytes,
+                };
+                Some((topic_partition.clone(), route))
+            })
+            .collect();
+        self.partition_routes.publish(routes);
+    }
+
+    async fn unfollow_partition(&mut self, leader_id: BrokerId, topic_partition: TopicPartition) {
+        if let Some(replica_fetcher_tx) = self.replica_fetchers_tx.get(&leader_id) {
+            let command = ReplicaFetcherCommands::RemovePartition { topic_partition };
+            if replica_fetcher_tx.send(command).await.is_err() {
+                tracing::error!("Replica fetcher of broker {} stopped", leader_id);
+            }
+        }
+    }
+
+    /// Makes the replica fetcher of `leader_id` copy the partition, the fetcher is started with
+    /// the first partition this broker follows on the leader.
+    async fn follow_partition(
+        &mut self,
+        leader_id: BrokerId,
+        topic_partition: TopicPartition,
+        partition_tx: PartitionSender,
+        log_end_offset: Arc<AtomicU64>,
+    ) {
+        let Some(leader_address) = self.cluster_settings.peer_address(leader_id) else {
+            tracing::error!(
+                "No address of broker {} leading {:?}",
+                leader_id,
+                topic_partition
+            );
+            return;
+        };
+        let leader_address = leader_address.to_string();
+        let replica_fetcher_tx = self
+            .replica_fetchers_tx
+            .entry(leader_id)
+            .or_insert_with(|| {
+                let (replica_fetcher_tx, replica_fetcher_rx) =
+                    mpsc::channel(REPLICA_FETCHER_CHANNEL_SIZE);
+                let replica_fetcher = ReplicaFetcher::new(
+                    self.cluster_settings.broker_id,
+                    leader_address,
+                    self.replication_throttles.clone(),
+                    self.cancellation_token.clone(),
+                );
+                self.partition_manager_task_tracker
+                    .spawn(replica_fetcher.start_replica_fetcher(replica_fetcher_rx));
+                replica_fetcher_tx
+            });
+        let leader_epochs = self.partition_leader_epochs[&topic_partition].clone();
+        let high_watermark = self.partition_high_watermarks[&topic_partition].shared_offset();
+        let command = ReplicaFetcherCommands::AddPartition {
+            topic_partition,
+            partition_tx,
+            log_end_offset,
+            leader_epochs,
+            high_watermark,
+        };
+        if replica_fetcher_tx.send(command).await.is_err() {
+            tracing::error!("Replica fetcher of broker {} stopped", leader_id);
+        }
+    }
+
+    fn topic_metadata(&self, topic_names: Option<Vec<String>>) -> Vec<TopicMetadata> {
+        let topic_names = topic_names.unwrap_or_else(|| self.topics.keys().cloned().collect());
+        let mut partition_states = self.partition_states();
+        partition_states.sort_by(|a, b| a.topic_partition.cmp(&b.topic_partition));
+        topic_names
+            .into_iter()
+            .filter_map(|topic_name| self.topics.get(&topic_name))
+            .map(|topic| TopicMetadata {
+                topic: topic.clone(),
+                partitions: partition_states
+                    .iter()
+                    .filter(|state| state.topic_partition.topic_name == topic.name)
+                    .map(|state| PartitionMetadata {
+                        partition_index: state.topic_partition.partition_index,
+                        leader_id: state.leader_and_isr.leader_id,
+                        leader_epoch: state.leader_and_isr.leader_epoch,
+                        replicas: state.replicas.clone(),
+                        isr: state.leader_and_isr.isr.clone(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Log end offset of every partition of the topic, indexed by partition.
+    fn log_end_offsets(&self, topic_name: &str) -> Result<Vec<u64>, WalrsError> {
+        let topic = self
+            .topics
+            .get(topic_name)
+            .ok_or_else(|| WalrsError::UnknownTopic(topic_name.to_string()))?;
+        let log_end_offsets = (0..topic.num_partitions.unwrap())
+            .map(|partition_index| {
+                let partition_name = format!("{}-{}", topic_name, partition_index);
+                self.partition_log_end_offsets[&partition_name].load(Ordering::SeqCst)
+            })
+            .collect();
+        Ok(log_end_offsets)
+    }
+
+    fn partition_read_info(
+        &self,
+        topic_partition: &TopicPartition,
+    ) -> Result<PartitionReadInfo, WalrsError> {
+        let unknown_partition = || WalrsError::UnknownPartition(topic_partition.clone());
+        let topic = self
+            .topics
+            .get(&topic_partition.topic_name)
+            .ok_or_else(unknown_partition)?;
+        let partition_name = format!(
+            "{}-{}",
+            topic_partition.topic_name, topic_partition.partition_index
+        );
+        let log_end_offset = self
+            .partition_log_end_offsets
+            .get(&partition_name)
+            .ok_or_else(unknown_partition)?;
+        let partition_leader = self
+            .partition_leaders
+            .get(topic_partition)
+            .ok_or_else(unknown_partition)?;
+        let partition_info = PartitionInfo::new(
+            topic.clone(),
+            topic_partition.partition_index,
+            format!("{}/{}", self.log_dir_path, topic_partition.topic_name),
+        );
+        let log_end_offset = log_end_offset.load(Ordering::SeqCst);
+        Ok(PartitionReadInfo {
+            segment_file_path: partition_info.segment_file_path(),
+            log_start_offset: 0,
+            log_end_offset,
+            high_watermark: self.high_watermark(topic_partition, log_end_offset),
+            leader_epoch: partition_leader.leader_epoch,
+            scheduled_delivery: topic.scheduled_delivery.unwrap_or_default(),
+        })
+    }
+
+    /// Creates the topic's partitions, `replicas` holds the brokers of every partition.
+    async fn create_topic(
+        &mut self,
+        topic: Topic,
+        replicas: Vec<Vec<BrokerId>>,
+        reply_tx: oneshot::Sender<Option<Topic>>,
+    ) {
         let topic_name = topic.name.clone();
 
         if self.topics.contains_key(topic_name.as_str()) {
             tracing::warn!("{} Topic already exists", topic_name);
-            let topic = self.topics.get(topic_name.as_str()).unwrap().to_owned();
-            reply_tx.send(Some(topic)).unwrap();
+            let topic = self.topics[&topic_name].clone();
+            reply(reply_tx, Some(topic));
         } else {
+            let topic_writers = TopicWriters {
+                cancellation_token: self.cancellation_token.child_token(),
+                task_tracker: TaskTracker::new(),
+            };
+            let queue_size = topic
+                .queue_size
+                .map_or(self.partition_channel_size, |queue_size| {
+                    queue_size as usize
+                });
             for partition_index in 0..topic.num_partitions.unwrap() {
                 let partition_name = format!("{}-{}", topic_name, partition_index);
-                let (client_tx, client_rx) =
-                    mpsc::channel::<Message>(PARTITION_MANAGER_CHANNEL_SIZE);
+                let (client_tx, client_rx) = partition_queue(
+                    TopicPartition::new(topic_name.clone(), partition_index),
+                    queue_size,
+                    topic.overflow_policy.unwrap_or_default(),
+                    &self.metrics,
+                );
                 self.partition_client_tx
-                    .insert(partition_name.clone(), client_tx);
+                    .insert(partition_name.clone(), client_tx.clone());
+                let log_end_offset = Arc::new(AtomicU64::new(0));
+                self.partition_log_end_offsets
+                    .insert(partition_name.clone(), log_end_offset.clone());
+                let topic_log_dir_path = format!("{}/{}", self.log_dir_path, topic_name);
                 let partition =
-                    PartitionInfo::new(topic.clone(), partition_index, self.log_dir_path.clone());
-                let cancellation_token_for_partition = self.cancellation_token.clone();
-                self.partition_manager_task_tracker.spawn(async move {
-                    start_partition_writer(partition, client_rx, cancellation_token_for_partition)
+                    PartitionInfo::new(topic.clone(), partition_index, topic_log_dir_path);
+                let partition_path = partition.partition_path.clone();
+                let cancellation_token_for_partition = topic_writers.cancellation_token.clone();
+                let writer_context = PartitionWriterContext {
+                    linger: self.cluster_settings.partition_linger,
+                    segment_buffers: self.segment_buffers.clone(),
+                    metrics: self.metrics.clone(),
+                    clock: self.clock.clone(),
+                };
+                self.partition_manager_task_tracker.spawn_on(
+                    topic_writers.task_tracker.track_future(async move {
+                        start_partition_writer(
+                            partition,
+                            client_rx,
+                            log_end_offset,
+                            writer_context,
+                            cancellation_token_for_partition,
+                        )
                         .await;
-                });
+                    }),
+                    &self.disk_io,
+                );
+                let replicas = replicas[partition_index as usize].clone();
+                let topic_partition = TopicPartition::new(topic_name.clone(), partition_index);
+                // the leader may have been elected after the partition was created
+                let partition_leader = PartitionLeader::load(&replicas, &partition_path);
+                let leader_id = partition_leader.leader_id;
+                self.partition_leaders
+                    .insert(topic_partition.clone(), partition_leader);
+                self.partition_leader_epochs.insert(
+                    topic_partition.clone(),
+                    Arc::new(Mutex::new(LeaderEpochCache::load(
+                        partition_leader.leader_epoch,
+                        &partition_path,
+                    ))),
+                );
+                self.partition_high_watermarks.insert(
+                    topic_partition.clone(),
+                    HighWatermark::load(&partition_path),
+                );
+                self.partition_replicas
+                    .insert(topic_partition.clone(), replicas.clone());
+                if leader_id == self.cluster_settings.broker_id && replicas.len() > 1 {
+                    let follower_ids: Vec<BrokerId> = replicas
+                        .iter()
+                        .copied()
+                        .filter(|replica_id| *replica_id != leader_id)
+                        .collect();
+                    let partition_isr = PartitionIsr::load(
+                        leader_id,
+                        &follower_ids,
+                        &partition_path,
+                        self.clock.now(),
+                    );
+                    self.partition_isrs
+                        .insert(topic_partition.clone(), partition_isr);
+                }
+                if leader_id != self.cluster_settings.broker_id
+                    && replicas.contains(&self.cluster_settings.broker_id)
+                {
+                    let log_end_offset = self.partition_log_end_offsets[&partition_name].clone();
+                    self.follow_partition(leader_id, topic_partition, client_tx, log_end_offset)
+                        .await;
+                }
             }
             self.topics.insert(topic_name.clone(), topic.clone());
+            self.topic_writers.insert(topic_name.clone(), topic_writers);
+            self.publish_partition_routes();
             tracing::info!("{} Topic created", topic_name);
-            reply_tx.send(Some(topic)).unwrap();
+            // nobody listening for topic events is not an error
+            let _ = self.topic_events_tx.send(TopicEvent::Created {
+                topic_name: topic_name.clone(),
+            });
+            reply(reply_tx, Some(topic));
         }
     }
+
+    /// Stops the topic's partition writers and replica fetches and removes its directory,
+    /// answered with the deleted topic.
+    async fn delete_topic(
+        &mut self,
+        topic_name: String,
+        reply_tx: oneshot::Sender<Result<Topic, WalrsError>>,
+    ) {
+        let Some(topic) = self.topics.remove(&topic_name) else {
+            tracing::warn!("Ignoring deletion of unknown topic {}", topic_name);
+            reply(reply_tx, Err(WalrsError::UnknownTopic(topic_name)));
+            return;
+        };
+        let broker_id = self.cluster_settings.broker_id;
+        for partition_index in 0..topic.num_partitions.unwrap() {
+            let topic_partition = TopicPartition::new(topic_name.clone(), partition_index);
+            let partition_name = format!("{}-{}", topic_name, partition_index);
+            self.partition_client_tx.remove(&partition_name);
+            self.partition_log_end_offsets.remove(&partition_name);
+            self.partition_isrs.remove(&topic_partition);
+            self.known_isrs.remove(&topic_partition);
+            self.partition_reassignments.remove(&topic_partition);
+            self.partition_leader_epochs.remove(&topic_partition);
+            self.partition_high_watermarks.remove(&topic_partition);
+            let replicas = self
+                .partition_replicas
+                .remove(&topic_partition)
+                .unwrap_or_default();
+            if let Some(partition_leader) = self.partition_leaders.remove(&topic_partition) {
+                if partition_leader.leader_id != broker_id && replicas.contains(&broker_id) {
+                    self.unfollow_partition(partition_leader.leader_id, topic_partition)
+                        .await;
+                }
+            }
+        }
+        self.publish_partition_routes();
+        // writers write their pending batch before they stop, so they are awaited before their
+        // files are removed
+        if let Some(topic_writers) = self.topic_writers.remove(&topic_name) {
+            topic_writers.cancellation_token.cancel();
+            topic_writers.task_tracker.close();
+            topic_writers.task_tracker.wait().await;
+        }
+        let topic_log_dir_path = format!("{}/{}", self.log_dir_path, topic_name);
+        if let Err(e) = fs::remove_dir_all(&topic_log_dir_path) {
+            tracing::error!("Could not remove {}: {}", topic_log_dir_path, e);
+        }
+        tracing::info!("{} Topic deleted", topic_name);
+        reply(reply_tx, Ok(topic));
+    }
+}
+
+/// Answers a command. The requester may have stopped waiting for the answer, e.g. as its
+/// connection closed, which is no reason to stop the manager.
+fn reply<T>(reply_tx: oneshot::Sender<T>, answer: T) {
+    if reply_tx.send(answer).is_err() {
+        tracing::debug!("Requester stopped waiting for the answer");
+    }
+}
+
+/// Replicas an ongoing reassignment adds to and removes from a partition.
+#[derive(Debug, Clone, Default)]
+pub struct OngoingReassignment {
+    pub adding_replicas: Vec<BrokerId>,
+    pub removing_replicas: Vec<BrokerId>,
+    /// Bytes per second the adding replicas copy the partition with, unlimited without a value.
+    pub throttle_rate: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TopicEvent {
+    Created {
+        topic_name: String,
+    },
+    IsrChanged {
+        topic_partition: TopicPartition,
+        leader_and_isr: LeaderAndIsr,
+    },
 }
 
 pub enum TopicManagerCommands {
+    /// `replicas` holds the brokers of every partition, indexed by partition.
     CreateTopic {
         topic: Topic,
+        replicas: Vec<Vec<BrokerId>>,
         reply_tx: oneshot::Sender<Option<Topic>>,
     },
+    /// Answered with the deleted topic, `WalrsError::UnknownTopic` for unknown topics.
+    DeleteTopic {
+        topic_name: String,
+        reply_tx: oneshot::Sender<Result<Topic, WalrsError>>,
+    },
     GetTopicInfo {
         topic_name: String,
-        reply_tx: oneshot::Sender<Option<Topic>>,
+        reply_tx: oneshot::Sender<Result<Topic, WalrsError>>,
+    },
+    ListTopics {
+        reply_tx: oneshot::Sender<Vec<String>>,
     },
-    GetPartitionManagerTx {
+    GetLogEndOffsets {
         topic_name: String,
-        message_key: Option<String>,
-        reply_tx: oneshot::Sender<Option<Sender<Message>>>,
+        reply_tx: oneshot::Sender<Result<Vec<u64>, WalrsError>>,
+    },
+    GetPartitionReadInfo {
+        topic_partition: TopicPartition,
+        reply_tx: oneshot::Sender<Result<PartitionReadInfo, WalrsError>>,
+    },
+    /// In-sync follower in `rack_id` a consumer of the partition this broker leads reads from
+    /// instead, `None` when the leader is in that rack or no follower of the ISR is.
+    /// `broker_racks` are the racks of the registered brokers.
+    GetPreferredReadReplica {
+        topic_partition: TopicPartition,
+        rack_id: String,
+        broker_racks: BTreeMap<BrokerId, Option<String>>,
+        reply_tx: oneshot::Sender<Option<BrokerId>>,
+    },
+    /// Answered with `BrokerResponse::EpochEndOffset` for the end of `leader_epoch` in the log of
+    /// a partition this broker leads.
+    GetEpochEndOffset {
+        topic_partition: TopicPartition,
+        leader_epoch: u32,
+        reply_tx: oneshot::Sender<BrokerResponse>,
+    },
+    /// Sent for every fetch of a follower, the partition's leader tracks its ISR from them.
+    RecordReplicaFetch {
+        topic_partition: TopicPartition,
+        replica_id: BrokerId,
+        fetch_offset: u64,
+    },
+    GetPartitionStates {
+        reply_tx: oneshot::Sender<Vec<PartitionState>>,
+    },
+    /// Partitions broker `broker_id` leads whose followers did not catch up yet, e.g. before it
+    /// hands their leadership to a follower.
+    GetLaggingPartitions {
+        broker_id: BrokerId,
+        reply_tx: oneshot::Sender<Vec<TopicPartition>>,
+    },
+    /// Topics with their partitions, every topic with `None`. Unknown topics are left out.
+    GetTopicMetadata {
+        topic_names: Option<Vec<String>>,
+        reply_tx: oneshot::Sender<Vec<TopicMetadata>>,
+    },
+    /// Sent for leaders the controller elected and ISR changes leaders reported, once they are
+    /// in the metadata log.
+    UpdateLeaderAndIsr {
+        topic_partition: TopicPartition,
+        leader_and_isr: LeaderAndIsr,
+    },
+    /// Sent for replicas changed by a reassignment, once they are in the metadata log.
+    UpdateReplicas {
+        topic_partition: TopicPartition,
+        replicas: Vec<BrokerId>,
+        reassignment: OngoingReassignment,
+        leader_and_isr: LeaderAndIsr,
     },
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::fs;
 
     use super::*;
-    use bytes::BytesMut;
-    use common::{codecs::decoder::BatchDecoder, models::Message};
+    use bytes::{Bytes, BytesMut};
+    use common::{
+        codecs::decoder::BatchDecoder,
+        errors::ProduceError,
+        models::{Acks, Batch, Message, OrderingMode, RecordBatch},
+    };
     use test_log::test;
     use tokio_util::codec::Decoder;
 
+    use crate::clock::{BrokerClock, MockClock};
+    use crate::models::{AppendedBatch, PartitionAppend};
+
     #[test(tokio::test)]
     async fn test_topics_manager_should_return_partition_manager() {
         let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
@@ -147,8 +1142,17 @@ mod tests {
         let (parent_tx, parent_rx) = mpsc::channel(5);
         let cancellation_token = CancellationToken::new();
 
-        let mut topics_manager =
-            TopicsManager::new(log_dir_path.clone(), cancellation_token.clone());
+        let clock = Arc::new(MockClock::new());
+
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path.clone(),
+            1000,
+            ClusterSettings::default(),
+            &Metrics::new(),
+            Handle::current(),
+            clock.clone(),
+            cancellation_token.clone(),
+        );
 
         let topic_name = "test_topic".to_string();
 
@@ -158,8 +1162,15 @@ mod tests {
             replication_factor: Some(1),
             retention_period: Some(1),
             batch_size: Some(2),
+            ordering_mode: Some(OrderingMode::Strict),
+            queue_size: None,
+            overflow_policy: None,
+            max_message_bytes: None,
+            timestamp_type: None,
+            scheduled_delivery: None,
         };
 
+        let partition_routes = topics_manager.partition_routes();
         let topic_manager_handle = tokio::spawn(async move {
             topics_manager.start_topics_manager(parent_rx).await;
         });
@@ -168,7 +1179,8 @@ mod tests {
         parent_tx
             .send(TopicManagerCommands::CreateTopic {
                 topic: topic.clone(),
-                reply_tx: reply_tx,
+                replicas: vec![vec![0]],
+                reply_tx,
             })
             .await
             .unwrap();
@@ -177,43 +1189,97 @@ mod tests {
         assert_eq!(topic_received, topic);
         assert_eq!(topic.name, topic_name.clone());
 
-        let (reply_tx, reply_rx) = oneshot::channel();
-        let get_partition_manager_command = TopicManagerCommands::GetPartitionManagerTx {
-            topic_name: topic_name.clone(),
-            message_key: None,
-            reply_tx: reply_tx,
-        };
-
-        parent_tx.send(get_partition_manager_command).await.unwrap();
-        let partition_manager_tx = reply_rx.await.unwrap().unwrap();
+        let partition_manager_tx = partition_routes
+            .partition_tx(&TopicPartition::new(topic_name.clone(), 0), None, 0)
+            .unwrap();
 
         let message_1 = Message {
-            payload: BytesMut::from("Message 1 without timestamp".as_bytes()).freeze(),
-            key: Some("dummy_key".to_string()),
+            payload: Some(BytesMut::from("Message 1 without timestamp".as_bytes()).freeze()),
+            key: Some(Bytes::from_static(b"dummy_key")),
             timestamp: None,
+            headers: vec![],
         };
 
         let message_2 = Message {
-            payload: BytesMut::from("Message 2 with timestamp".as_bytes()).freeze(),
+            payload: Some(BytesMut::from("Message 2 with timestamp".as_bytes()).freeze()),
             key: None,
             timestamp: Some(1234567890),
+            headers: vec![],
         };
 
         let message_3 = Message {
-            payload: BytesMut::from("Message 3 with timestamp".as_bytes()).freeze(),
-            key: Some("dummy_key_2".to_string()),
+            payload: Some(BytesMut::from("Message 3 with timestamp".as_bytes()).freeze()),
+            key: Some(Bytes::from_static(b"dummy_key_2")),
             timestamp: Some(1334567899),
+            headers: vec![],
         };
-        partition_manager_tx.send(message_1.clone()).await.unwrap();
-        partition_manager_tx.send(message_2.clone()).await.unwrap();
-        partition_manager_tx.send(message_3.clone()).await.unwrap();
+        /// Sends the message without waiting for the partition writer to write it.
+        async fn append(
+            partition_manager_tx: &PartitionSender,
+            message: Message,
+        ) -> oneshot::Receiver<Result<AppendedBatch, ProduceError>> {
+            let (base_offset_tx, base_offset_rx) = oneshot::channel();
+            partition_manager_tx
+                .send(PartitionAppend {
+                    batch: RecordBatch::new(Batch {
+                        records: vec![message],
+                        ..Batch::default()
+                    })
+                    .unwrap(),
+                    acks: Acks::Leader,
+                    base_offset_tx: Some(base_offset_tx),
+                })
+                .await
+                .unwrap();
+            base_offset_rx
+        }
+        let base_offset_1 = append(&partition_manager_tx, message_1.clone()).await;
+        let base_offset_2 = append(&partition_manager_tx, message_2.clone()).await;
+        // the first two messages filled a batch
+        assert_eq!(
+            base_offset_1
+                .await
+                .unwrap()
+                .map(|appended_batch| appended_batch.base_offset),
+            Ok(0)
+        );
+        assert_eq!(
+            base_offset_2
+                .await
+                .unwrap()
+                .map(|appended_batch| appended_batch.base_offset),
+            Ok(1)
+        );
+
+        // the third is written on its own once its linger passed
+        let base_offset_3 = append(&partition_manager_tx, message_3.clone()).await;
+        clock.timer_started().await;
+        clock
+            .advance(ClusterSettings::default().partition_linger)
+            .await;
+        assert_eq!(
+            base_offset_3
+                .await
+                .unwrap()
+                .map(|appended_batch| appended_batch.base_offset),
+            Ok(2)
+        );
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::GetLogEndOffsets {
+                topic_name: topic_name.clone(),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply_rx.await.unwrap(), Ok(vec![3]));
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
         cancellation_token.cancel();
 
         topic_manager_handle.await.unwrap();
 
-        let segment_file_path = format!("{}/0/{}", log_dir_path, "segment_0.log");
+        let segment_file_path = format!("{}/{}/0/{}", log_dir_path, topic_name, "segment_0.log");
         tracing::info!("in test - Segment file path: {}", segment_file_path);
         let file_contents = fs::read(segment_file_path).unwrap();
         tracing::info!("in test - File contents: {:?}", file_contents);
@@ -231,4 +1297,466 @@ mod tests {
         assert_eq!(decoded_batches[0].records[1], message_2);
         assert_eq!(decoded_batches[1].records[0], message_3);
     }
+
+    #[test(tokio::test)]
+    async fn test_topics_manager_should_reject_writes_to_partitions_led_by_other_brokers() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let (parent_tx, parent_rx) = mpsc::channel(5);
+        let cancellation_token = CancellationToken::new();
+        let cluster_settings = ClusterSettings {
+            broker_id: 1,
+            peers: BTreeMap::from([(0, "127.0.0.1:1".to_string())]),
+            ..ClusterSettings::default()
+        };
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path,
+            1000,
+            cluster_settings,
+            &Metrics::new(),
+            Handle::current(),
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        );
+        let partition_routes = topics_manager.partition_routes();
+        let topic_manager_handle = tokio::spawn(async move {
+            topics_manager.start_topics_manager(parent_rx).await;
+        });
+
+        let topic = Topic::new("t1".to_string(), Some(2), Some(1), Some(1), Some(10), None);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::CreateTopic {
+                topic,
+                replicas: vec![vec![0], vec![1]],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap().unwrap();
+
+        let results: Vec<_> = (0..2)
+            .map(|partition_index| {
+                partition_routes.partition_tx(
+                    &TopicPartition::new("t1".to_string(), partition_index),
+                    None,
+                    0,
+                )
+            })
+            .collect();
+        // broker 0 leads partition 0, this broker leads partition 1
+        assert!(matches!(
+            &results[0],
+            Err(ProduceError::NotLeader(topic_partition))
+                if *topic_partition == TopicPartition::new("t1".to_string(), 0)
+        ));
+        assert!(results[1].is_ok());
+        // the topic has no limit of its own, so the broker's applies
+        assert_eq!(
+            partition_routes
+                .partition_tx(
+                    &TopicPartition::new("t1".to_string(), 1),
+                    None,
+                    1024 * 1024 + 1,
+                )
+                .err(),
+            Some(ProduceError::MessageTooLarge {
+                size: 1024 * 1024 + 1,
+                max_size: 1024 * 1024,
+            })
+        );
+
+        cancellation_token.cancel();
+        topic_manager_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_topics_manager_should_apply_elected_leaders() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let (parent_tx, parent_rx) = mpsc::channel(5);
+        let cancellation_token = CancellationToken::new();
+        let cluster_settings = ClusterSettings {
+            broker_id: 0,
+            peers: BTreeMap::from([(1, "127.0.0.1:1".to_string())]),
+            ..ClusterSettings::default()
+        };
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path,
+            1000,
+            cluster_settings,
+            &Metrics::new(),
+            Handle::current(),
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        );
+        let partition_routes = topics_manager.partition_routes();
+        let topic_manager_handle = tokio::spawn(async move {
+            topics_manager.start_topics_manager(parent_rx).await;
+        });
+
+        let topic = Topic::new("t1".to_string(), Some(1), Some(2), Some(1), Some(10), None);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::CreateTopic {
+                topic,
+                replicas: vec![vec![0, 1]],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap().unwrap();
+
+        let topic_partition = TopicPartition::new("t1".to_string(), 0);
+        let leader_epochs = [
+            // broker 1 was elected while this broker was considered dead
+            (1, 1, Some(ProduceError::NotLeader(topic_partition.clone()))),
+            // an update of an older epoch does not make this broker the leader again
+            (0, 0, Some(ProduceError::NotLeader(topic_partition.clone()))),
+            (0, 2, None),
+        ];
+        for (leader_id, leader_epoch, expected_error) in leader_epochs {
+            parent_tx
+                .send(TopicManagerCommands::UpdateLeaderAndIsr {
+                    topic_partition: topic_partition.clone(),
+                    leader_and_isr: LeaderAndIsr {
+                        leader_id,
+                        leader_epoch,
+                        isr: vec![leader_id],
+                    },
+                })
+                .await
+                .unwrap();
+            // the update is applied once a later command was answered
+            let (reply_tx, reply_rx) = oneshot::channel();
+            parent_tx
+                .send(TopicManagerCommands::GetPartitionStates { reply_tx })
+                .await
+                .unwrap();
+            reply_rx.await.unwrap();
+            assert_eq!(
+                partition_routes
+                    .partition_tx(&topic_partition, None, 0)
+                    .err(),
+                expected_error
+            );
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::GetPartitionStates { reply_tx })
+            .await
+            .unwrap();
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            vec![PartitionState {
+                topic_partition,
+                replicas: vec![0, 1],
+                adding_replicas: vec![],
+                removing_replicas: vec![],
+                leader_and_isr: LeaderAndIsr {
+                    leader_id: 0,
+                    leader_epoch: 2,
+                    isr: vec![0],
+                },
+            }]
+        );
+
+        cancellation_token.cancel();
+        topic_manager_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_topics_manager_should_prefer_in_sync_followers_in_the_consumer_rack() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let (parent_tx, parent_rx) = mpsc::channel(5);
+        let cancellation_token = CancellationToken::new();
+        let cluster_settings = ClusterSettings {
+            broker_id: 0,
+            rack: Some("a".to_string()),
+            peers: BTreeMap::from([(1, "127.0.0.1:1".to_string())]),
+            ..ClusterSettings::default()
+        };
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path,
+            1000,
+            cluster_settings,
+            &Metrics::new(),
+            Handle::current(),
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        );
+        let topic_manager_handle = tokio::spawn(async move {
+            topics_manager.start_topics_manager(parent_rx).await;
+        });
+
+        let topic = Topic::new("t1".to_string(), Some(1), Some(2), Some(1), Some(10), None);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::CreateTopic {
+                topic,
+                replicas: vec![vec![0, 1]],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap().unwrap();
+        let topic_partition = TopicPartition::new("t1".to_string(), 0);
+        parent_tx
+            .send(TopicManagerCommands::UpdateLeaderAndIsr {
+                topic_partition: topic_partition.clone(),
+                leader_and_isr: LeaderAndIsr {
+                    leader_id: 0,
+                    leader_epoch: 1,
+                    isr: vec![0, 1],
+                },
+            })
+            .await
+            .unwrap();
+
+        let broker_racks = BTreeMap::from([(0, Some("a".to_string())), (1, Some("b".to_string()))]);
+        for (rack_id, expected_replica) in [("b", Some(1)), ("a", None), ("c", None)] {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            parent_tx
+                .send(TopicManagerCommands::GetPreferredReadReplica {
+                    topic_partition: topic_partition.clone(),
+                    rack_id: rack_id.to_string(),
+                    broker_racks: broker_racks.clone(),
+                    reply_tx,
+                })
+                .await
+                .unwrap();
+            assert_eq!(reply_rx.await.unwrap(), expected_replica);
+        }
+
+        cancellation_token.cancel();
+        topic_manager_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_topics_manager_should_describe_partitions_of_topics() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let (parent_tx, parent_rx) = mpsc::channel(5);
+        let cancellation_token = CancellationToken::new();
+        let cluster_settings = ClusterSettings {
+            broker_id: 0,
+            peers: BTreeMap::from([(1, "127.0.0.1:1".to_string())]),
+            ..ClusterSettings::default()
+        };
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path,
+            1000,
+            cluster_settings,
+            &Metrics::new(),
+            Handle::current(),
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        );
+        let topic_manager_handle = tokio::spawn(async move {
+            topics_manager.start_topics_manager(parent_rx).await;
+        });
+
+        let topic = Topic::new("t1".to_string(), Some(2), Some(2), Some(1), Some(10), None);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::CreateTopic {
+                topic: topic.clone(),
+                replicas: vec![vec![1, 0], vec![0, 1]],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap().unwrap();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::GetTopicMetadata {
+                topic_names: Some(vec!["t1".to_string(), "unknown".to_string()]),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            vec![TopicMetadata {
+                topic,
+                partitions: vec![
+                    PartitionMetadata {
+                        partition_index: 0,
+                        leader_id: 1,
+                        leader_epoch: 0,
+                        replicas: vec![1, 0],
+                        isr: vec![1, 0],
+                    },
+                    PartitionMetadata {
+                        partition_index: 1,
+                        leader_id: 0,
+                        leader_epoch: 0,
+                        replicas: vec![0, 1],
+                        isr: vec![0, 1],
+                    },
+                ],
+            }]
+        );
+
+        cancellation_token.cancel();
+        topic_manager_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_topics_manager_should_apply_reassigned_replicas() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let (parent_tx, parent_rx) = mpsc::channel(5);
+        let cancellation_token = CancellationToken::new();
+        let cluster_settings = ClusterSettings {
+            broker_id: 0,
+            peers: BTreeMap::from([
+                (1, "127.0.0.1:1".to_string()),
+                (2, "127.0.0.1:2".to_string()),
+            ]),
+            ..ClusterSettings::default()
+        };
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path,
+            1000,
+            cluster_settings,
+            &Metrics::new(),
+            Handle::current(),
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        );
+        let topic_manager_handle = tokio::spawn(async move {
+            topics_manager.start_topics_manager(parent_rx).await;
+        });
+
+        let topic = Topic::new("t1".to_string(), Some(1), Some(2), Some(1), Some(10), None);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::CreateTopic {
+                topic,
+                replicas: vec![vec![0, 1]],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap().unwrap();
+
+        let topic_partition = TopicPartition::new("t1".to_string(), 0);
+        let reassigning = PartitionState {
+            topic_partition: topic_partition.clone(),
+            replicas: vec![0, 2, 1],
+            adding_replicas: vec![2],
+            removing_replicas: vec![1],
+            leader_and_isr: LeaderAndIsr {
+                leader_id: 0,
+                leader_epoch: 1,
+                isr: vec![0, 1],
+            },
+        };
+        // the second record replays the start of the reassignment, its replicas apply again
+        for partition_state in [reassigning.clone(), reassigning.clone()] {
+            parent_tx
+                .send(TopicManagerCommands::UpdateReplicas {
+                    topic_partition: topic_partition.clone(),
+                    replicas: partition_state.replicas,
+                    reassignment: OngoingReassignment {
+                        adding_replicas: partition_state.adding_replicas,
+                        removing_replicas: partition_state.removing_replicas,
+                        throttle_rate: None,
+                    },
+                    leader_and_isr: partition_state.leader_and_isr,
+                })
+                .await
+                .unwrap();
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::GetPartitionStates { reply_tx })
+            .await
+            .unwrap();
+        assert_eq!(reply_rx.await.unwrap(), vec![reassigning]);
+
+        cancellation_token.cancel();
+        topic_manager_handle.await.unwrap();
+    }
+
+    #[test(tokio::test)]
+    async fn test_topics_manager_should_stop_partitions_and_remove_files_of_deleted_topics() {
+        let temp_dir = tempdir::TempDir::new("log_dir_").unwrap();
+        let log_dir_path = temp_dir.path().to_str().unwrap().to_string();
+        let (parent_tx, parent_rx) = mpsc::channel(5);
+        let cancellation_token = CancellationToken::new();
+        let mut topics_manager = TopicsManager::new(
+            log_dir_path.clone(),
+            1000,
+            ClusterSettings::default(),
+            &Metrics::new(),
+            Handle::current(),
+            Arc::new(BrokerClock::new()),
+            cancellation_token.clone(),
+        );
+        let partition_routes = topics_manager.partition_routes();
+        let topic_manager_handle = tokio::spawn(async move {
+            topics_manager.start_topics_manager(parent_rx).await;
+        });
+
+        let topic = Topic::new("t1".to_string(), Some(2), Some(1), Some(1), Some(10), None);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::CreateTopic {
+                topic: topic.clone(),
+                replicas: vec![vec![0], vec![0]],
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        reply_rx.await.unwrap().unwrap();
+        let partition_manager_tx = partition_routes
+            .partition_tx(&TopicPartition::new("t1".to_string(), 0), None, 0)
+            .unwrap();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::DeleteTopic {
+                topic_name: "t1".to_string(),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply_rx.await.unwrap(), Ok(topic));
+
+        assert!(partition_manager_tx.is_closed());
+        assert!(!std::path::Path::new(&format!("{}/t1", log_dir_path)).exists());
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::GetTopicInfo {
+                topic_name: "t1".to_string(),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err(WalrsError::UnknownTopic("t1".to_string()))
+        );
+        let (reply_tx, reply_rx) = oneshot::channel();
+        parent_tx
+            .send(TopicManagerCommands::DeleteTopic {
+                topic_name: "t1".to_string(),
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err(WalrsError::UnknownTopic("t1".to_string()))
+        );
+
+        cancellation_token.cancel();
+        topic_manager_handle.await.unwrap();
+    }
 }
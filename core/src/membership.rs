@@ -0,0 +1,282 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use common::models::{
+    BrokerRegistration, BrokerResponse, MetadataRecord, TopicCommand, TopicPartition,
+};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+use crate::cluster::{send_request, BrokerId, ClusterSettings};
+use crate::managers::controller::ControllerCommands;
+use crate::managers::metadata_quorum::MetadataQuorumCommands;
+use crate::managers::topics_manager::TopicManagerCommands;
+
+/// Time a broker waits for the controller to answer a heartbeat or take its registration.
+const MEMBERSHIP_REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time a broker which shuts down waits before it asks the controller again to move the
+/// partitions it still leads, e.g. as their followers were not in sync yet.
+const CONTROLLED_SHUTDOWN_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// Time between two checks whether the followers of a broker which shuts down caught up.
+const FOLLOWER_CATCH_UP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Which brokers the active controller considers alive, from their heartbeats. Brokers are
+/// alive while their last heartbeat is at most the session timeout old, brokers the controller
+/// did not hear from yet get the session timeout from when it started watching them.
+#[derive(Debug)]
+pub struct BrokerLiveness {
+    session_timeout: Duration,
+    last_heartbeats: HashMap<BrokerId, Instant>,
+    dead_brokers: BTreeSet<BrokerId>,
+}
+
+impl BrokerLiveness {
+    pub fn new(session_timeout: Duration) -> Self {
+        BrokerLiveness {
+            session_timeout,
+            last_heartbeats: HashMap::new(),
+            dead_brokers: BTreeSet::new(),
+        }
+    }
+
+    /// Forgets every heartbeat, e.g. when this broker became the active controller and did not
+    /// get the heartbeats sent to the previous one.
+    pub fn reset(&mut self) {
+        self.last_heartbeats.clear();
+        self.dead_brokers.clear();
+    }
+
+    pub fn watch(&mut self, broker_id: BrokerId, now: Instant) {
+        self.last_heartbeats.entry(broker_id).or_insert(now);
+    }
+
+    pub fn record_heartbeat(&mut self, broker_id: BrokerId, now: Instant) {
+        self.last_heartbeats.insert(broker_id, now);
+    }
+
+    /// Marks brokers without a heartbeat within the session timeout as dead and brokers which
+    /// sent one again as alive, returns whether any broker changed.
+    pub fn check(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        for (broker_id, last_heartbeat) in &self.last_heartbeats {
+            let alive = now.duration_since(*last_heartbeat) <= self.session_timeout;
+            if alive && self.dead_brokers.remove(broker_id) {
+                tracing::info!("Broker {} is back", broker_id);
+                changed = true;
+            } else if !alive && self.dead_brokers.insert(*broker_id) {
+                tracing::warn!(
+                    "Broker {} did not send a heartbeat within {:?}",
+                    broker_id,
+                    self.session_timeout
+                );
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn is_alive(&self, broker_id: BrokerId) -> bool {
+        self.last_heartbeats.contains_key(&broker_id) && !self.dead_brokers.contains(&broker_id)
+    }
+
+    pub fn has_dead_brokers(&self) -> bool {
+        !self.dead_brokers.is_empty()
+    }
+
+    pub fn dead_brokers(&self) -> Vec<BrokerId> {
+        self.dead_brokers.iter().copied().collect()
+    }
+}
+
+/// Registers the broker in the metadata log and keeps sending heartbeats to the controller,
+/// whichever broker leads the metadata quorum. Heartbeats are sent a few times within the
+/// session timeout, like Kafka's `broker.heartbeat.interval.ms`.
+pub async fn start_broker_heartbeats(
+    cluster_settings: ClusterSettings,
+    metadata_quorum_tx: Sender<MetadataQuorumCommands>,
+    controller_tx: Sender<ControllerCommands>,
+    cancellation_token: CancellationToken,
+) {
+    let registration = BrokerRegistration {
+        broker_id: cluster_settings.broker_id,
+        address: cluster_settings.advertised_address.clone(),
+        rack: cluster_settings.rack.clone(),
+    };
+    let heartbeat_interval =
+        (cluster_settings.broker_session_timeout / 3).max(Duration::from_millis(1));
+    let mut heartbeat_interval_timer = tokio::time::interval(heartbeat_interval);
+    let mut registered = false;
+    loop {
+        tokio::select! {
+            _ = heartbeat_interval_timer.tick() => {}
+            _ = cancellation_token.cancelled() => break,
+        }
+        if !registered {
+            registered = register(&registration, &metadata_quorum_tx).await;
+            continue;
+        }
+        let (reply_tx, reply_rx) = oneshot::channel();
+        metadata_quorum_tx
+            .send(MetadataQuorumCommands::GetLeaderId { reply_tx })
+            .await
+            .unwrap();
+        let Some(controller_id) = reply_rx.await.unwrap() else {
+            continue;
+        };
+        let response = if controller_id == cluster_settings.broker_id {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            controller_tx
+                .send(ControllerCommands::BrokerHeartbeat {
+                    broker_id: cluster_settings.broker_id,
+                    reply_tx,
+                })
+                .await
+                .unwrap();
+            Ok(Ok(reply_rx.await.unwrap()))
+        } else {
+            let Some(controller_address) = cluster_settings.peer_address(controller_id) else {
+                continue;
+            };
+            let command = TopicCommand::BrokerHeartbeat {
+                broker_id: cluster_settings.broker_id,
+            };
+            tokio::time::timeout(
+                MEMBERSHIP_REQUEST_TIMEOUT,
+                send_request(controller_address, &command),
+            )
+            .await
+        };
+        if !matches!(
+            response,
+            Ok(Ok(BrokerResponse::BrokerHeartbeatAcknowledged))
+        ) {
+            tracing::warn!(
+                "Controller {} did not acknowledge the heartbeat: {:?}",
+                controller_id,
+                response
+            );
+        }
+    }
+}
+
+/// Waits until the followers in the ISR of every partition broker `broker_id` leads copied all
+/// of its records, so moving the leaderships loses none of them, e.g. records produced with
+/// `acks=1`. Returns the partitions whose followers did not catch up within `timeout`, none on
+/// other brokers than the leader.
+pub async fn await_followers_caught_up(
+    broker_id: BrokerId,
+    topic_manager_tx: &Sender<TopicManagerCommands>,
+    timeout: Duration,
+) -> Vec<TopicPartition> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        topic_manager_tx
+            .send(TopicManagerCommands::GetLaggingPartitions {
+                broker_id,
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        let lagging_partitions = reply_rx.await.unwrap();
+        if lagging_partitions.is_empty()
+            || tokio::time::Instant::now() + FOLLOWER_CATCH_UP_CHECK_INTERVAL >= deadline
+        {
+            return lagging_partitions;
+        }
+        tokio::time::sleep(FOLLOWER_CATCH_UP_CHECK_INTERVAL).await;
+    }
+}
+
+/// Asks the controller, through the broker's own controller which passes it on to the active
+/// one, to move the leadership of the broker's partitions to other replicas before it shuts
+/// down. Asks again while partitions remain, until `timeout` has passed, and returns whether
+/// every partition was moved.
+pub async fn request_controlled_shutdown(
+    broker_id: BrokerId,
+    controller_tx: &Sender<ControllerCommands>,
+    timeout: Duration,
+) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        controller_tx
+            .send(ControllerCommands::ControlledShutdown {
+                broker_id,
+                reply_tx,
+            })
+            .await
+            .unwrap();
+        match tokio::time::timeout_at(deadline, reply_rx).await {
+            Ok(Ok(BrokerResponse::ControlledShutdownCompleted {
+                remaining_partitions,
+            })) if remaining_partitions.is_empty() => return true,
+            Ok(Ok(BrokerResponse::ControlledShutdownCompleted {
+                remaining_partitions,
+            })) => tracing::warn!(
+                "No other replica can lead {:?} yet, asking the controller again",
+                remaining_partitions
+            ),
+            Ok(response) => tracing::warn!(
+                "Controller did not move the partitions of the broker: {:?}",
+                response
+            ),
+            Err(_) => return false,
+        }
+        if tokio::time::Instant::now() + CONTROLLED_SHUTDOWN_RETRY_BACKOFF >= deadline {
+            return false;
+        }
+        tokio::time::sleep(CONTROLLED_SHUTDOWN_RETRY_BACKOFF).await;
+    }
+}
+
+async fn register(
+    registration: &BrokerRegistration,
+    metadata_quorum_tx: &Sender<MetadataQuorumCommands>,
+) -> bool {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    metadata_quorum_tx
+        .send(MetadataQuorumCommands::Propose {
+            record: MetadataRecord::BrokerRegistered(registration.clone()),
+            reply_tx,
+        })
+        .await
+        .unwrap();
+    match tokio::time::timeout(MEMBERSHIP_REQUEST_TIMEOUT, reply_rx).await {
+        Ok(Ok(Ok(()))) => true,
+        result => {
+            tracing::warn!("Could not register with the controller: {:?}", result);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broker_liveness_should_mark_brokers_without_heartbeats_dead() {
+        let start = Instant::now();
+        let mut broker_liveness = BrokerLiveness::new(Duration::from_secs(9));
+        broker_liveness.watch(1, start);
+        broker_liveness.watch(2, start);
+        assert!(!broker_liveness.is_alive(3));
+
+        broker_liveness.record_heartbeat(1, start + Duration::from_secs(6));
+        assert!(!broker_liveness.check(start + Duration::from_secs(9)));
+        assert!(broker_liveness.check(start + Duration::from_secs(10)));
+        assert!(broker_liveness.is_alive(1));
+        assert!(!broker_liveness.is_alive(2));
+        assert!(broker_liveness.has_dead_brokers());
+
+        // watching does not overwrite the last heartbeat of a dead broker
+        broker_liveness.watch(2, start + Duration::from_secs(11));
+        assert!(!broker_liveness.check(start + Duration::from_secs(11)));
+        broker_liveness.record_heartbeat(2, start + Duration::from_secs(12));
+        assert!(broker_liveness.check(start + Duration::from_secs(12)));
+        assert!(broker_liveness.is_alive(2));
+        assert!(!broker_liveness.has_dead_brokers());
+    }
+}
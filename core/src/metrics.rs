@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::models::TopicPartition;
+
+/// Counters and gauges of the broker by name, read by the `DescribeMetrics` admin command.
+/// Components register their metrics once and keep the returned handle, updating it does not
+/// lock the registry.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    metrics: Arc<Mutex<BTreeMap<String, Arc<AtomicU64>>>>,
+    granularity: MetricsGranularity,
+}
+
+/// Labels the broker keeps metrics by. Every topic and partition adds metrics, so big clusters
+/// opt in to finer ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MetricsGranularity {
+    /// Metrics of the broker as a whole.
+    #[default]
+    Broker,
+    /// Adds the records and bytes in and out of every topic.
+    Topic,
+    /// Adds the size, segments, last append and flush latency of every partition on top of the
+    /// topic metrics.
+    Partition,
+}
+
+impl fmt::Display for MetricsGranularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MetricsGranularity::Broker => "broker",
+            MetricsGranularity::Topic => "topic",
+            MetricsGranularity::Partition => "partition",
+        })
+    }
+}
+
+/// Parses `broker`, `topic` or `partition`.
+impl FromStr for MetricsGranularity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "broker" => Ok(MetricsGranularity::Broker),
+            "topic" => Ok(MetricsGranularity::Topic),
+            "partition" => Ok(MetricsGranularity::Partition),
+            _ => Err(format!(
+                "Unknown metrics granularity {}, expected broker, topic or partition",
+                value
+            )),
+        }
+    }
+}
+
+/// Totals of a topic, its rates are the change between two `DescribeMetrics`.
+#[derive(Debug, Clone)]
+pub struct TopicMetrics {
+    pub messages_in: Arc<AtomicU64>,
+    pub bytes_in: Arc<AtomicU64>,
+    pub bytes_out: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionMetrics {
+    pub size_bytes: Arc<AtomicU64>,
+    pub segments: Arc<AtomicU64>,
+    /// Milliseconds since the epoch of the last write to the partition.
+    pub last_append_millis: Arc<AtomicU64>,
+    /// Microseconds the last write to the segment file took until it was flushed.
+    pub flush_latency_micros: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn with_granularity(granularity: MetricsGranularity) -> Self {
+        Metrics {
+            granularity,
+            ..Metrics::default()
+        }
+    }
+
+    /// Handle of the metric with this name, starting at 0 when it was not registered before.
+    pub fn register(&self, name: &str) -> Arc<AtomicU64> {
+        self.metrics
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Metrics of the topic, `None` unless the broker keeps metrics by topic.
+    pub fn topic(&self, topic_name: &str) -> Option<TopicMetrics> {
+        if self.granularity < MetricsGranularity::Topic {
+            return None;
+        }
+        let metric = |name: &str| self.register(&format!("{}{{topic=\"{}\"}}", name, topic_name));
+        Some(TopicMetrics {
+            messages_in: metric("messages_in_total"),
+            bytes_in: metric("bytes_in_total"),
+            bytes_out: metric("bytes_out_total"),
+        })
+    }
+
+    /// Metrics of the partition, `None` unless the broker keeps metrics by partition.
+    pub fn partition(&self, topic_partition: &TopicPartition) -> Option<PartitionMetrics> {
+        if self.granularity < MetricsGranularity::Partition {
+            return None;
+        }
+        let metric = |name: &str| {
+            self.register(&format!(
+                "{}{{topic=\"{}\",partition=\"{}\"}}",
+                name, topic_partition.topic_name, topic_partition.partition_index
+            ))
+        };
+        Some(PartitionMetrics {
+            size_bytes: metric("partition_size_bytes"),
+            segments: metric("partition_segments"),
+            last_append_millis: metric("partition_last_append_millis"),
+            flush_latency_micros: metric("partition_flush_latency_micros"),
+        })
+    }
+
+    /// Current value of every metric, sorted by name.
+    pub fn snapshot(&self) -> Vec<(String, u64)> {
+        self.metrics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_should_share_registered_values() {
+        let metrics = Metrics::new();
+        let elections = metrics.register("unclean_leader_elections_total");
+        metrics
+            .register("offline_partitions_count")
+            .store(2, Ordering::Relaxed);
+        elections.fetch_add(1, Ordering::Relaxed);
+        metrics
+            .register("unclean_leader_elections_total")
+            .fetch_add(1, Ordering::Relaxed);
+
+        assert_eq!(
+            metrics.snapshot(),
+            vec![
+                ("offline_partitions_count".to_string(), 2),
+                ("unclean_leader_elections_total".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metrics_should_only_label_by_the_configured_granularity() {
+        let topic_partition = TopicPartition::new("orders".to_string(), 1);
+        let metrics = Metrics::new();
+        assert!(metrics.topic("orders").is_none());
+        assert!(metrics.partition(&topic_partition).is_none());
+        assert!(metrics.snapshot().is_empty());
+
+        let metrics = Metrics::with_granularity("topic".parse().unwrap());
+        assert!(metrics.partition(&topic_partition).is_none());
+        let topic_metrics = metrics.topic("orders").unwrap();
+        topic_metrics.messages_in.fetch_add(3, Ordering::Relaxed);
+        metrics
+            .topic("orders")
+            .unwrap()
+            .messages_in
+            .fetch_add(2, Ordering::Relaxed);
+        assert_eq!(
+            metrics.snapshot(),
+            vec![
+                ("bytes_in_total{topic=\"orders\"}".to_string(), 0),
+                ("bytes_out_total{topic=\"orders\"}".to_string(), 0),
+                ("messages_in_total{topic=\"orders\"}".to_string(), 5),
+            ]
+        );
+
+        let metrics = Metrics::with_granularity(MetricsGranularity::Partition);
+        assert!(metrics.topic("orders").is_some());
+        metrics
+            .partition(&topic_partition)
+            .unwrap()
+            .segments
+            .store(1, Ordering::Relaxed);
+        assert!(metrics.snapshot().contains(&(
+            "partition_segments{topic=\"orders\",partition=\"1\"}".to_string(),
+            1
+        )));
+    }
+}
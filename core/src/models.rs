@@ -1,5 +1,14 @@
-use common::models::Topic;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use common::errors::ProduceError;
+use common::models::{
+    Acks, BrokerResponse, FetchRequest, LeaderAndIsr, RecordBatch, Topic, TopicPartition,
+};
 use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::cluster::BrokerId;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct PartitionInfo {
@@ -17,4 +26,212 @@ impl PartitionInfo {
             partition_path,
         }
     }
+
+    pub fn segment_file_path(&self) -> String {
+        format!("{}/{}", self.partition_path, "segment_0.log")
+    }
+}
+
+/// Where a partition writer appended a batch.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AppendedBatch {
+    /// Offset of the first record of the batch.
+    pub base_offset: u64,
+    /// When the writer stamped the batch, `None` unless its topic uses log append time.
+    pub log_append_time: Option<u128>,
+}
+
+/// A batch sent to a partition writer, batches are appended in the order they were received.
+/// Uncompressed records wait in the writer until its batch is full or their linger passed,
+/// records with `Acks::None` are answered right away. Compressed batches and batches of
+/// idempotent producers are stored as they were received. `base_offset_tx` gets where the
+/// records were appended once they were handled according to `acks`, or the error when the
+/// records of an idempotent producer are out of sequence, or when the write was shed from a
+/// full partition queue.
+#[derive(Debug)]
+pub struct PartitionAppend {
+    pub batch: RecordBatch,
+    pub acks: Acks,
+    pub base_offset_tx: Option<oneshot::Sender<Result<AppendedBatch, ProduceError>>>,
+}
+
+/// Hands out the IDs of idempotent producers. IDs start at the broker's start time in
+/// milliseconds so a producer of an earlier run of the broker does not share its ID with a new one.
+#[derive(Debug, Clone)]
+pub struct ProducerIdAllocator {
+    next_producer_id: Arc<AtomicU64>,
+}
+
+impl ProducerIdAllocator {
+    pub fn new(start_millis: u128) -> Self {
+        ProducerIdAllocator {
+            next_producer_id: Arc::new(AtomicU64::new(start_millis as u64)),
+        }
+    }
+
+    pub fn allocate(&self) -> u64 {
+        self.next_producer_id.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// Replicas, leader and ISR of a partition as this broker knows them, the controller elects new
+/// leaders from them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PartitionState {
+    pub topic_partition: TopicPartition,
+    /// Brokers holding a copy of the partition, the first one was its leader when it was created
+    /// or reassigned. While the partition is reassigned it holds the old and the new replicas.
+    pub replicas: Vec<BrokerId>,
+    /// Replicas of an ongoing reassignment which are still copying the partition.
+    pub adding_replicas: Vec<BrokerId>,
+    /// Replicas of an ongoing reassignment which are removed once it completes.
+    pub removing_replicas: Vec<BrokerId>,
+    pub leader_and_isr: LeaderAndIsr,
+}
+
+/// What a fetch needs to know about a partition to read from it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PartitionReadInfo {
+    pub segment_file_path: String,
+    /// Offset of the oldest record still stored, records are never deleted yet so this is 0.
+    pub log_start_offset: u64,
+    pub log_end_offset: u64,
+    /// Offset every in-sync replica copied the partition up to, consumers read the records
+    /// before it.
+    pub high_watermark: u64,
+    pub leader_epoch: u32,
+    /// Whether consumers read records only once their `deliver-at` time came.
+    pub scheduled_delivery: bool,
+}
+
+impl PartitionReadInfo {
+    /// Offset the fetch starts at: the requested offset, else the group's committed offset,
+    /// else the one picked by the reset policy. The reset policy also replaces offsets outside of
+    /// the stored records; without a policy the fetch is answered with an error response, as are
+    /// fetches with another leader epoch than the partition's.
+    pub fn fetch_offset(
+        &self,
+        request: &FetchRequest,
+        committed_offset: Option<u64>,
+    ) -> Result<u64, BrokerResponse> {
+        if let Some(leader_epoch) = request
+            .leader_epoch
+            .filter(|leader_epoch| *leader_epoch != self.leader_epoch)
+        {
+            return Err(BrokerResponse::FencedLeaderEpoch {
+                topic_partition: request.topic_partition.clone(),
+                leader_epoch,
+                current_leader_epoch: self.leader_epoch,
+            });
+        }
+        // consumers start after the last record every in-sync replica copied
+        let latest_offset = match request.replica_id {
+            Some(_) => self.log_end_offset,
+            None => self.high_watermark,
+        };
+        let reset_offset = request
+            .auto_offset_reset
+            .reset_offset(self.log_start_offset, latest_offset);
+        match request.offset.or(committed_offset) {
+            Some(offset) if (self.log_start_offset..=self.log_end_offset).contains(&offset) => {
+                Ok(offset)
+            }
+            Some(offset) => reset_offset.ok_or_else(|| BrokerResponse::OffsetOutOfRange {
+                topic_partition: request.topic_partition.clone(),
+                offset,
+                log_start_offset: self.log_start_offset,
+                log_end_offset: self.log_end_offset,
+            }),
+            None => reset_offset.ok_or_else(|| BrokerResponse::NoCommittedOffset {
+                group_id: request.group_id.clone(),
+                topic_partition: request.topic_partition.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::models::{OffsetResetPolicy, TopicPartition};
+
+    use super::*;
+
+    fn fetch_request(offset: Option<u64>, auto_offset_reset: OffsetResetPolicy) -> FetchRequest {
+        FetchRequest {
+            topic_partition: TopicPartition::new("test_topic".to_string(), 0),
+            offset,
+            group_id: Some("test_group".to_string()),
+            member_id: None,
+            auto_offset_reset,
+            max_records: 10,
+            replica_id: None,
+            leader_epoch: None,
+            rack_id: None,
+        }
+    }
+
+    #[test]
+    fn test_fetch_offset_should_apply_reset_policy() {
+        let read_info = PartitionReadInfo {
+            segment_file_path: "unused".to_string(),
+            log_start_offset: 5,
+            log_end_offset: 20,
+            high_watermark: 18,
+            leader_epoch: 3,
+            scheduled_delivery: false,
+        };
+
+        let mut latest = fetch_request(None, OffsetResetPolicy::Latest);
+        assert_eq!(read_info.fetch_offset(&latest, None), Ok(18));
+        latest.replica_id = Some(1);
+        assert_eq!(read_info.fetch_offset(&latest, None), Ok(20));
+        assert_eq!(read_info.fetch_offset(&latest, Some(7)), Ok(7));
+
+        let earliest = fetch_request(Some(2), OffsetResetPolicy::Earliest);
+        assert_eq!(read_info.fetch_offset(&earliest, Some(7)), Ok(5));
+
+        let none = fetch_request(Some(21), OffsetResetPolicy::None);
+        assert_eq!(
+            read_info.fetch_offset(&none, None),
+            Err(BrokerResponse::OffsetOutOfRange {
+                topic_partition: none.topic_partition.clone(),
+                offset: 21,
+                log_start_offset: 5,
+                log_end_offset: 20,
+            })
+        );
+        let none = fetch_request(None, OffsetResetPolicy::None);
+        assert_eq!(
+            read_info.fetch_offset(&none, None),
+            Err(BrokerResponse::NoCommittedOffset {
+                group_id: Some("test_group".to_string()),
+                topic_partition: none.topic_partition.clone(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_fetch_offset_should_reject_other_leader_epochs() {
+        let read_info = PartitionReadInfo {
+            segment_file_path: "unused".to_string(),
+            log_start_offset: 0,
+            log_end_offset: 20,
+            high_watermark: 18,
+            leader_epoch: 3,
+            scheduled_delivery: false,
+        };
+        let mut request = fetch_request(Some(10), OffsetResetPolicy::None);
+        request.leader_epoch = Some(3);
+        assert_eq!(read_info.fetch_offset(&request, None), Ok(10));
+
+        request.leader_epoch = Some(2);
+        assert_eq!(
+            read_info.fetch_offset(&request, None),
+            Err(BrokerResponse::FencedLeaderEpoch {
+                topic_partition: request.topic_partition.clone(),
+                leader_epoch: 2,
+                current_leader_epoch: 3,
+            })
+        );
+    }
 }
@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::errors::ProduceError;
+use common::models::{OverflowPolicy, TopicPartition};
+use tokio::sync::{oneshot, Notify};
+use tokio::time::Instant;
+
+use crate::metrics::Metrics;
+use crate::models::PartitionAppend;
+
+/// Producers are asked to wait this long before retrying a write rejected by a full queue.
+const QUEUE_FULL_THROTTLE_TIME_MS: u64 = 100;
+
+/// Bounded queue of the writes waiting for a partition writer. Unlike a channel, a sender finding
+/// it full applies the topic's `OverflowPolicy` instead of waiting for room indefinitely.
+/// The number of waiting writes is published as `partition_queue_depth{topic="..",partition=".."}`.
+pub fn partition_queue(
+    topic_partition: TopicPartition,
+    capacity: usize,
+    overflow_policy: OverflowPolicy,
+    metrics: &Metrics,
+) -> (PartitionSender, PartitionReceiver) {
+    let queue = Arc::new(PartitionQueue {
+        state: Mutex::new(QueueState::default()),
+        capacity: capacity.max(1),
+        overflow_policy,
+        queued: Notify::new(),
+        dequeued: Notify::new(),
+        depth: metrics.register(&format!(
+            "partition_queue_depth{{topic=\"{}\",partition=\"{}\"}}",
+            topic_partition.topic_name, topic_partition.partition_index
+        )),
+        rejected_writes: metrics.register("rejected_partition_writes_total"),
+        shed_writes: metrics.register("shed_partition_writes_total"),
+        topic_partition,
+    });
+    (
+        PartitionSender {
+            queue: queue.clone(),
+        },
+        PartitionReceiver { queue },
+    )
+}
+
+struct PartitionQueue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    overflow_policy: OverflowPolicy,
+    /// Wakes the writer once a write was queued.
+    queued: Notify,
+    /// Wakes a blocked sender once the writer took a write, or every sender once it stopped.
+    dequeued: Notify,
+    depth: Arc<AtomicU64>,
+    rejected_writes: Arc<AtomicU64>,
+    shed_writes: Arc<AtomicU64>,
+    topic_partition: TopicPartition,
+}
+
+#[derive(Default)]
+struct QueueState {
+    appends: VecDeque<PartitionAppend>,
+    /// Truncations of followers, taken before the appends.
+    truncations: VecDeque<(u64, oneshot::Sender<io::Result<u64>>)>,
+    closed: bool,
+}
+
+/// What the writer of a partition takes from its queue.
+pub enum PartitionWrite {
+    Append(PartitionAppend),
+    /// Removes the records from `offset` on, answered with the new log end offset.
+    Truncate {
+        offset: u64,
+        log_end_offset_tx: oneshot::Sender<io::Result<u64>>,
+    },
+}
+
+impl PartitionQueue {
+    fn throttled(&self, counter: &AtomicU64) -> ProduceError {
+        counter.fetch_add(1, Ordering::Relaxed);
+        ProduceError::Throttled {
+            throttle_time_ms: QUEUE_FULL_THROTTLE_TIME_MS,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PartitionSender {
+    queue: Arc<PartitionQueue>,
+}
+
+impl PartitionSender {
+    /// Queues `append` for the writer. Fails with `ProduceError::Throttled` when the queue stays
+    /// full, and with `ProduceError::UnknownTopic` once the writer stopped, e.g. as the topic was
+    /// deleted.
+    pub async fn send(&self, append: PartitionAppend) -> Result<(), ProduceError> {
+        let queue = &self.queue;
+        let deadline = match queue.overflow_policy {
+            OverflowPolicy::Block { timeout_ms } => {
+                Some(Instant::now() + Duration::from_millis(timeout_ms as u64))
+            }
+            OverflowPolicy::Reject | OverflowPolicy::ShedOldest => None,
+        };
+        let mut append = Some(append);
+        loop {
+            let dequeued = queue.dequeued.notified();
+            tokio::pin!(dequeued);
+            // registered before checking for room, so a write taken meanwhile still wakes it
+            dequeued.as_mut().enable();
+            let queued = {
+                let mut state = queue.state.lock().unwrap();
+                if state.closed {
+                    return Err(ProduceError::UnknownTopic(
+                        queue.topic_partition.topic_name.clone(),
+                    ));
+                }
+                let full = state.appends.len() >= queue.capacity;
+                match queue.overflow_policy {
+                    OverflowPolicy::Block { .. } if full => None,
+                    OverflowPolicy::Reject if full => {
+                        return Err(queue.throttled(&queue.rejected_writes))
+                    }
+                    _ => {
+                        let shed_append = if full {
+                            state.appends.pop_front()
+                        } else {
+                            None
+                        };
+                        state.appends.push_back(append.take().unwrap());
+                        queue
+                            .depth
+                            .store(state.appends.len() as u64, Ordering::Relaxed);
+                        Some(shed_append)
+                    }
+                }
+            };
+            match queued {
+                Some(shed_append) => {
+                    queue.queued.notify_one();
+                    if let Some(base_offset_tx) =
+                        shed_append.and_then(|append| append.base_offset_tx)
+                    {
+                        let _ = base_offset_tx.send(Err(queue.throttled(&queue.shed_writes)));
+                    }
+                    return Ok(());
+                }
+                None => {
+                    let deadline = deadline.expect("blocking sends have a deadline");
+                    if tokio::time::timeout_at(deadline, dequeued).await.is_err() {
+                        return Err(queue.throttled(&queue.rejected_writes));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Makes the writer remove the records from `offset` on once it wrote the pending ones and
+    /// returns the new log end offset, which may be before `offset` as whole batches are removed.
+    /// Followers truncate their log to the leader's with it.
+    pub async fn truncate(&self, offset: u64) -> io::Result<u64> {
+        let (log_end_offset_tx, log_end_offset_rx) = oneshot::channel();
+        {
+            let mut state = self.queue.state.lock().unwrap();
+            if state.closed {
+                return Err(io::Error::other("Partition writer stopped"));
+            }
+            state.truncations.push_back((offset, log_end_offset_tx));
+        }
+        self.queue.queued.notify_one();
+        log_end_offset_rx
+            .await
+            .map_err(|_| io::Error::other("Partition writer stopped"))?
+    }
+
+    /// Whether the writer stopped, so every further send fails.
+    #[cfg(test)]
+    pub fn is_closed(&self) -> bool {
+        self.queue.state.lock().unwrap().closed
+    }
+}
+
+pub struct PartitionReceiver {
+    queue: Arc<PartitionQueue>,
+}
+
+impl PartitionReceiver {
+    /// A waiting truncation or else the oldest waiting write, `None` once the queue was closed.
+    pub async fn recv(&mut self) -> Option<PartitionWrite> {
+        let queue = &self.queue;
+        loop {
+            // only the writer waits for `queued`, a notification without it waiting is kept
+            let queued = queue.queued.notified();
+            {
+                let mut state = queue.state.lock().unwrap();
+                if let Some((offset, log_end_offset_tx)) = state.truncations.pop_front() {
+                    return Some(PartitionWrite::Truncate {
+                        offset,
+                        log_end_offset_tx,
+                    });
+                }
+                if let Some(append) = state.appends.pop_front() {
+                    queue
+                        .depth
+                        .store(state.appends.len() as u64, Ordering::Relaxed);
+                    queue.dequeued.notify_one();
+                    return Some(PartitionWrite::Append(append));
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            queued.await;
+        }
+    }
+
+    /// Whether no write is waiting to be taken.
+    pub fn is_empty(&self) -> bool {
+        self.queue.state.lock().unwrap().appends.is_empty()
+    }
+
+    /// Fails further sends and drops the waiting writes, which tells their senders they were
+    /// not written.
+    pub fn close(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.closed = true;
+        state.appends.clear();
+        state.truncations.clear();
+        self.queue.depth.store(0, Ordering::Relaxed);
+        self.queue.dequeued.notify_waiters();
+    }
+}
+
+impl Drop for PartitionReceiver {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use common::models::{Acks, Batch, RecordBatch};
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::models::AppendedBatch;
+
+    fn append() -> (
+        PartitionAppend,
+        oneshot::Receiver<Result<AppendedBatch, ProduceError>>,
+    ) {
+        let (base_offset_tx, base_offset_rx) = oneshot::channel();
+        let append = PartitionAppend {
+            batch: RecordBatch::new(Batch::default()).unwrap(),
+            acks: Acks::Leader,
+            base_offset_tx: Some(base_offset_tx),
+        };
+        (append, base_offset_rx)
+    }
+
+    #[tokio::test]
+    async fn test_partition_queue_should_apply_overflow_policy_when_full() {
+        let metrics = Metrics::new();
+        let topic_partition = TopicPartition::new("t1".to_string(), 0);
+
+        let (sender, mut receiver) =
+            partition_queue(topic_partition.clone(), 1, OverflowPolicy::Reject, &metrics);
+        sender.send(append().0).await.unwrap();
+        assert_eq!(
+            sender.send(append().0).await,
+            Err(ProduceError::Throttled {
+                throttle_time_ms: QUEUE_FULL_THROTTLE_TIME_MS
+            })
+        );
+        assert!(receiver.recv().await.is_some());
+        sender.send(append().0).await.unwrap();
+
+        let (sender, mut receiver) = partition_queue(
+            topic_partition.clone(),
+            1,
+            OverflowPolicy::ShedOldest,
+            &metrics,
+        );
+        let (oldest, oldest_rx) = append();
+        sender.send(oldest).await.unwrap();
+        sender.send(append().0).await.unwrap();
+        assert!(matches!(
+            oldest_rx.await,
+            Ok(Err(ProduceError::Throttled { .. }))
+        ));
+        assert!(receiver.recv().await.is_some());
+
+        let (sender, mut receiver) = partition_queue(
+            topic_partition,
+            1,
+            OverflowPolicy::Block { timeout_ms: 50 },
+            &metrics,
+        );
+        sender.send(append().0).await.unwrap();
+        assert!(sender.send(append().0).await.is_err());
+        let blocked_sender = sender.clone();
+        let blocked_send = tokio::spawn(async move { blocked_sender.send(append().0).await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // taking a write makes room for the blocked one
+        assert!(receiver.recv().await.is_some());
+        assert_eq!(blocked_send.await.unwrap(), Ok(()));
+        receiver.close();
+        assert!(matches!(
+            sender.send(append().0).await,
+            Err(ProduceError::UnknownTopic(_))
+        ));
+
+        let snapshot: BTreeMap<String, u64> = metrics.snapshot().into_iter().collect();
+        assert_eq!(
+            snapshot[r#"partition_queue_depth{topic="t1",partition="0"}"#],
+            0
+        );
+        assert_eq!(snapshot["rejected_partition_writes_total"], 2);
+        assert_eq!(snapshot["shed_partition_writes_total"], 1);
+    }
+}
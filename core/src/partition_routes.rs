@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use common::errors::ProduceError;
+use common::models::TopicPartition;
+
+use crate::cluster::BrokerId;
+use crate::leader_epoch::PartitionLeader;
+use crate::partition_queue::PartitionSender;
+
+/// Writers of the broker's partitions with their leaders, which connection handlers read to
+/// route writes without waiting for the topics manager. The topics manager publishes a new
+/// snapshot whenever it creates or deletes a partition or a leader changes; readers never lock,
+/// they keep using the snapshot they loaded.
+#[derive(Clone)]
+pub struct PartitionRoutes {
+    broker_id: BrokerId,
+    routes: Arc<ArcSwap<HashMap<TopicPartition, PartitionRoute>>>,
+}
+
+#[derive(Clone)]
+pub struct PartitionRoute {
+    pub partition_tx: PartitionSender,
+    pub leader: PartitionLeader,
+    /// Largest batch the partition's topic accepts.
+    pub max_message_bytes: usize,
+}
+
+impl PartitionRoutes {
+    pub fn new(broker_id: BrokerId) -> Self {
+        PartitionRoutes {
+            broker_id,
+            routes: Arc::new(ArcSwap::from_pointee(HashMap::new())),
+        }
+    }
+
+    /// Writer of the partition for a batch of `batch_size` bytes, fails with
+    /// `ProduceError::NotLeader` on brokers which do not lead it, with
+    /// `ProduceError::FencedLeaderEpoch` for writes of another leader epoch and with
+    /// `ProduceError::MessageTooLarge` for batches larger than the topic accepts.
+    pub fn partition_tx(
+        &self,
+        topic_partition: &TopicPartition,
+        leader_epoch: Option<u32>,
+        batch_size: usize,
+    ) -> Result<PartitionSender, ProduceError> {
+        let routes = self.routes.load();
+        let Some(route) = routes.get(topic_partition) else {
+            return Err(ProduceError::UnknownTopic(
+                topic_partition.topic_name.clone(),
+            ));
+        };
+        if route.leader.leader_id != self.broker_id {
+            return Err(ProduceError::NotLeader(topic_partition.clone()));
+        }
+        if route.leader.is_fenced(leader_epoch) {
+            return Err(ProduceError::FencedLeaderEpoch {
+                topic_partition: topic_partition.clone(),
+                leader_epoch: leader_epoch.unwrap_or_default(),
+                current_leader_epoch: route.leader.leader_epoch,
+            });
+        }
+        if batch_size > route.max_message_bytes {
+            return Err(ProduceError::MessageTooLarge {
+                size: batch_size,
+                max_size: route.max_message_bytes,
+            });
+        }
+        Ok(route.partition_tx.clone())
+    }
+
+    /// Replaces the routes, writes routed from now on see the new ones.
+    pub fn publish(&self, routes: HashMap<TopicPartition, PartitionRoute>) {
+        self.routes.store(Arc::new(routes));
+    }
+}
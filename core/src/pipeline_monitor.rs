@@ -0,0 +1,130 @@
+//! Depth of the managers' queues and the tasks of the broker's task trackers, sampled into
+//! gauges to show where backpressure builds up between the actors of the broker.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::metrics::Metrics;
+
+/// Time between two samples of the queues and task trackers.
+const PIPELINE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+type Sampler = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// Gauges sampled together until the broker shuts down.
+pub struct PipelineMonitor {
+    metrics: Metrics,
+    samplers: Vec<(Arc<AtomicU64>, Sampler)>,
+}
+
+impl PipelineMonitor {
+    pub fn new(metrics: &Metrics) -> Self {
+        PipelineMonitor {
+            metrics: metrics.clone(),
+            samplers: vec![],
+        }
+    }
+
+    /// Samples the commands waiting in the queue of the manager into `manager_queue_depth`. The
+    /// monitor does not keep the queue open, the manager still stops once its senders are gone.
+    pub fn watch_queue<T: Send + 'static>(&mut self, manager: &str, tx: &mpsc::Sender<T>) {
+        let tx = tx.downgrade();
+        let depth = self
+            .metrics
+            .register(&format!("manager_queue_depth{{manager=\"{}\"}}", manager));
+        self.samplers.push((
+            depth,
+            Box::new(move || {
+                tx.upgrade()
+                    .map_or(0, |tx| (tx.max_capacity() - tx.capacity()) as u64)
+            }),
+        ));
+    }
+
+    /// Samples the running tasks of the tracker into `tasks_count`.
+    pub fn watch_tasks(&mut self, tracker_name: &str, tracker: &TaskTracker) {
+        let tracker = tracker.clone();
+        let count = self
+            .metrics
+            .register(&format!("tasks_count{{tracker=\"{}\"}}", tracker_name));
+        self.samplers
+            .push((count, Box::new(move || tracker.len() as u64)));
+    }
+
+    pub fn start(self, cancellation_token: CancellationToken) {
+        tokio::spawn(async move {
+            let mut sample_interval = tokio::time::interval(PIPELINE_SAMPLE_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = sample_interval.tick() => {}
+                    _ = cancellation_token.cancelled() => break,
+                }
+                self.sample();
+            }
+        });
+    }
+
+    fn sample(&self) {
+        for (gauge, sampler) in &self.samplers {
+            gauge.store(sampler(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pipeline_monitor_should_sample_queue_depths_and_tasks() {
+        let metrics = Metrics::new();
+        let mut pipeline_monitor = PipelineMonitor::new(&metrics);
+        let (tx, mut rx) = mpsc::channel::<u32>(4);
+        let tracker = TaskTracker::new();
+        pipeline_monitor.watch_queue("topics_manager", &tx);
+        pipeline_monitor.watch_tasks("partition_writers", &tracker);
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        tracker.spawn(async move {
+            let _ = release_rx.await;
+        });
+
+        pipeline_monitor.sample();
+        assert_eq!(
+            metrics.snapshot(),
+            vec![
+                (
+                    "manager_queue_depth{manager=\"topics_manager\"}".to_string(),
+                    2
+                ),
+                ("tasks_count{tracker=\"partition_writers\"}".to_string(), 1),
+            ]
+        );
+
+        rx.recv().await.unwrap();
+        drop(release_tx);
+        tracker.close();
+        tracker.wait().await;
+        // the monitor does not keep the queue open
+        drop(tx);
+        pipeline_monitor.sample();
+        assert_eq!(
+            metrics.snapshot(),
+            vec![
+                (
+                    "manager_queue_depth{manager=\"topics_manager\"}".to_string(),
+                    0
+                ),
+                ("tasks_count{tracker=\"partition_writers\"}".to_string(), 0),
+            ]
+        );
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.recv().await, None);
+    }
+}
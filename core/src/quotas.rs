@@ -0,0 +1,354 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::models::TopicPartition;
+use tokio::time::Instant;
+
+use crate::cluster::BrokerId;
+use crate::config::{ConfigError, ConfigSource};
+use crate::metrics::Metrics;
+
+/// Rates are measured over the samples of this window, like Kafka's
+/// `quota.window.num * quota.window.size.seconds`. Responses are delayed by at most this long.
+const QUOTA_WINDOW: Duration = Duration::from_secs(10);
+const SAMPLE_DURATION: Duration = Duration::from_secs(1);
+
+/// Rates every client ID may use, unlimited without a value.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct QuotaSettings {
+    /// `WALRS_QUOTA_PRODUCER_BYTE_RATE`, bytes per second of the batches a client writes
+    pub producer_byte_rate: Option<u64>,
+    /// `WALRS_QUOTA_CONSUMER_BYTE_RATE`, bytes per second of the records a client fetches
+    pub consumer_byte_rate: Option<u64>,
+    /// `WALRS_QUOTA_REQUEST_RATE`, requests per second of a client
+    pub request_rate: Option<u64>,
+}
+
+impl QuotaSettings {
+    pub fn from_config(config: &ConfigSource) -> Result<Self, ConfigError> {
+        Ok(QuotaSettings {
+            producer_byte_rate: config.parse("WALRS_QUOTA_PRODUCER_BYTE_RATE")?,
+            consumer_byte_rate: config.parse("WALRS_QUOTA_CONSUMER_BYTE_RATE")?,
+            request_rate: config.parse("WALRS_QUOTA_REQUEST_RATE")?,
+        })
+    }
+}
+
+/// What a client uses of its quotas.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Quota {
+    ProducedBytes,
+    FetchedBytes,
+    Requests,
+}
+
+/// Rates of every client ID, shared by the connections of the broker. A client exceeding one of
+/// its quotas is throttled for as long as it takes its rate to fall back to the quota.
+pub struct ClientQuotas {
+    settings: QuotaSettings,
+    clients: Mutex<HashMap<String, ClientRates>>,
+    throttled_responses: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct ClientRates {
+    produced_bytes: Rate,
+    fetched_bytes: Rate,
+    requests: Rate,
+}
+
+impl ClientQuotas {
+    pub fn new(settings: QuotaSettings, metrics: &Metrics) -> Self {
+        ClientQuotas {
+            settings,
+            clients: Mutex::new(HashMap::new()),
+            throttled_responses: metrics.register("throttled_responses_total"),
+        }
+    }
+
+    /// Records that the client used `amount` of the quota and returns how long its response is
+    /// delayed, zero while the client stays within the quota.
+    pub fn record(&self, client_id: &str, quota: Quota, amount: u64, now: Instant) -> Duration {
+        let limit = match quota {
+            Quota::ProducedBytes => self.settings.producer_byte_rate,
+            Quota::FetchedBytes => self.settings.consumer_byte_rate,
+            Quota::Requests => self.settings.request_rate,
+        };
+        let Some(limit) = limit else {
+            return Duration::ZERO;
+        };
+        let mut clients = self.clients.lock().unwrap();
+        if !clients.contains_key(client_id) {
+            // clients which have gone quiet are forgotten once new ones show up
+            clients.retain(|_, rates| !rates.is_idle(now));
+        }
+        let rates = clients.entry(client_id.to_string()).or_default();
+        let rate = match quota {
+            Quota::ProducedBytes => &mut rates.produced_bytes,
+            Quota::FetchedBytes => &mut rates.fetched_bytes,
+            Quota::Requests => &mut rates.requests,
+        };
+        let throttle_time = rate.record(amount, now).throttle_time(limit);
+        if !throttle_time.is_zero() {
+            self.throttled_responses.fetch_add(1, Ordering::Relaxed);
+        }
+        throttle_time
+    }
+}
+
+/// Which side of replication the traffic is on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Replication {
+    /// Records a leader serves to its followers.
+    Leader,
+    /// Records a follower copies from its leaders.
+    Follower,
+}
+
+/// Rates of the replication traffic of the broker, so moving or rebuilding replicas does not
+/// take the bandwidth of the clients. The broker's limits apply to all of its replication, the
+/// limit of a reassignment to the replicas it adds on top.
+pub struct ReplicationThrottles {
+    leader_rate: Option<u64>,
+    follower_rate: Option<u64>,
+    rates: Mutex<ReplicationRates>,
+    /// Limit and adding replicas of the partitions of throttled reassignments.
+    reassignments: Mutex<HashMap<TopicPartition, (u64, Vec<BrokerId>)>>,
+    throttled_fetches: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct ReplicationRates {
+    leader: Rate,
+    follower: Rate,
+    reassignment_leader: Rate,
+    reassignment_follower: Rate,
+}
+
+impl ReplicationThrottles {
+    pub fn new(leader_rate: Option<u64>, follower_rate: Option<u64>, metrics: &Metrics) -> Self {
+        ReplicationThrottles {
+            leader_rate,
+            follower_rate,
+            rates: Mutex::new(ReplicationRates::default()),
+            reassignments: Mutex::new(HashMap::new()),
+            throttled_fetches: metrics.register("throttled_replica_fetches_total"),
+        }
+    }
+
+    /// Limits the replicas the reassignment of the partition adds to `rate` bytes per second,
+    /// `None` once the reassignment completed or when it is not throttled.
+    pub fn throttle_reassignment(
+        &self,
+        topic_partition: TopicPartition,
+        rate: Option<u64>,
+        adding_replicas: Vec<BrokerId>,
+    ) {
+        let mut reassignments = self.reassignments.lock().unwrap();
+        match rate {
+            Some(rate) if !adding_replicas.is_empty() => {
+                reassignments.insert(topic_partition, (rate, adding_replicas));
+            }
+            _ => {
+                reassignments.remove(&topic_partition);
+            }
+        }
+    }
+
+    /// Records `bytes` of the partition replicated to or by `replica_id` and returns how long the
+    /// next fetch of the replica waits, zero while the replication stays within its limits.
+    pub fn record(
+        &self,
+        replication: Replication,
+        topic_partition: &TopicPartition,
+        replica_id: BrokerId,
+        bytes: u64,
+        now: Instant,
+    ) -> Duration {
+        let reassignment_rate = self
+            .reassignments
+            .lock()
+            .unwrap()
+            .get(topic_partition)
+            .filter(|(_, adding_replicas)| adding_replicas.contains(&replica_id))
+            .map(|(rate, _)| *rate);
+        let mut rates = self.rates.lock().unwrap();
+        let rates = &mut *rates;
+        let (broker_limit, rate, reassignment) = match replication {
+            Replication::Leader => (
+                self.leader_rate,
+                &mut rates.leader,
+                &mut rates.reassignment_leader,
+            ),
+            Replication::Follower => (
+                self.follower_rate,
+                &mut rates.follower,
+                &mut rates.reassignment_follower,
+            ),
+        };
+        let measured = rate.record(bytes, now);
+        let mut throttle_time =
+            broker_limit.map_or(Duration::ZERO, |limit| measured.throttle_time(limit));
+        if let Some(limit) = reassignment_rate {
+            throttle_time = throttle_time.max(reassignment.record(bytes, now).throttle_time(limit));
+        }
+        if !throttle_time.is_zero() {
+            self.throttled_fetches.fetch_add(1, Ordering::Relaxed);
+        }
+        throttle_time
+    }
+}
+
+impl ClientRates {
+    fn is_idle(&self, now: Instant) -> bool {
+        [&self.produced_bytes, &self.fetched_bytes, &self.requests]
+            .iter()
+            .all(|rate| rate.is_idle(now))
+    }
+}
+
+/// Amounts of the last `QUOTA_WINDOW`, in samples of `SAMPLE_DURATION`.
+#[derive(Default)]
+struct Rate {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+/// Total of a `Rate` and the time it was measured over.
+struct Measured {
+    total: u64,
+    elapsed: Duration,
+}
+
+impl Rate {
+    fn record(&mut self, amount: u64, now: Instant) -> Measured {
+        while self
+            .samples
+            .front()
+            .is_some_and(|(start, _)| now.duration_since(*start) >= QUOTA_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        match self.samples.back_mut() {
+            Some((start, sample)) if now.duration_since(*start) < SAMPLE_DURATION => {
+                *sample += amount
+            }
+            _ => self.samples.push_back((now, amount)),
+        }
+        let oldest_start = self.samples.front().map_or(now, |(start, _)| *start);
+        Measured {
+            total: self.samples.iter().map(|(_, sample)| sample).sum(),
+            elapsed: now.duration_since(oldest_start).max(SAMPLE_DURATION),
+        }
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.samples
+            .back()
+            .is_none_or(|(start, _)| now.duration_since(*start) >= QUOTA_WINDOW)
+    }
+}
+
+impl Measured {
+    /// How long the client has to wait until the rate over the measured time falls back to
+    /// `limit` per second, like Kafka's `(rate - limit) / limit * window`.
+    fn throttle_time(&self, limit: u64) -> Duration {
+        let allowed_time = Duration::from_secs_f64(self.total as f64 / limit.max(1) as f64);
+        allowed_time.saturating_sub(self.elapsed).min(QUOTA_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_quotas_should_throttle_clients_above_their_rate() {
+        let metrics = Metrics::new();
+        let quotas = ClientQuotas::new(
+            QuotaSettings {
+                producer_byte_rate: Some(1000),
+                ..QuotaSettings::default()
+            },
+            &metrics,
+        );
+        let start = Instant::now();
+
+        assert_eq!(
+            quotas.record("p1", Quota::ProducedBytes, 1000, start),
+            Duration::ZERO
+        );
+        // 3000 bytes within the first second take 3 seconds at 1000 bytes per second
+        assert_eq!(
+            quotas.record("p1", Quota::ProducedBytes, 2000, start),
+            Duration::from_secs(2)
+        );
+        // other clients and quotas without a limit are not throttled
+        assert_eq!(
+            quotas.record("p2", Quota::ProducedBytes, 1000, start),
+            Duration::ZERO
+        );
+        assert_eq!(
+            quotas.record("p1", Quota::Requests, 1_000_000, start),
+            Duration::ZERO
+        );
+        // the rate fell back to the quota once the client waited
+        assert_eq!(
+            quotas.record(
+                "p1",
+                Quota::ProducedBytes,
+                0,
+                start + Duration::from_secs(3)
+            ),
+            Duration::ZERO
+        );
+        assert_eq!(
+            quotas.record("p1", Quota::ProducedBytes, 50_000, start + QUOTA_WINDOW * 2),
+            QUOTA_WINDOW
+        );
+        assert_eq!(
+            metrics.snapshot(),
+            vec![("throttled_responses_total".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_replication_throttles_should_limit_brokers_and_reassignments() {
+        let metrics = Metrics::new();
+        let throttles = ReplicationThrottles::new(Some(1000), None, &metrics);
+        let moved = TopicPartition::new("orders".to_string(), 0);
+        let other = TopicPartition::new("orders".to_string(), 1);
+        let start = Instant::now();
+
+        // the broker's limit applies to every partition of its side of replication
+        assert_eq!(
+            throttles.record(Replication::Leader, &other, 1, 3000, start),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            throttles.record(Replication::Follower, &other, 0, 3000, start),
+            Duration::ZERO
+        );
+
+        // a reassignment limits the replicas it adds
+        throttles.throttle_reassignment(moved.clone(), Some(100), vec![2]);
+        assert_eq!(
+            throttles.record(Replication::Follower, &moved, 2, 300, start),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            throttles.record(Replication::Follower, &moved, 1, 300, start),
+            Duration::ZERO
+        );
+        throttles.throttle_reassignment(moved.clone(), None, vec![]);
+        assert_eq!(
+            throttles.record(Replication::Follower, &moved, 2, 300, start),
+            Duration::ZERO
+        );
+        assert_eq!(
+            metrics.snapshot(),
+            vec![("throttled_replica_fetches_total".to_string(), 2)]
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+
+use common::models::{AppendEntriesRequest, MetadataEntry, MetadataRecord, VoteRequest};
+use serde::{Deserialize, Serialize};
+
+use crate::cluster::BrokerId;
+
+const RAFT_STATE_FILE_NAME: &str = "raft_state";
+/// Entries sent to a follower per append entries request.
+const MAX_ENTRIES_PER_REQUEST: usize = 100;
+
+/// What a broker must not forget across restarts to keep Raft's guarantees.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistentState {
+    current_term: u64,
+    voted_for: Option<BrokerId>,
+    /// Entry at index `i` is stored at `entries[i - 1]`, Raft's indexes start at 1.
+    entries: Vec<MetadataEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Raft state of one broker of the metadata quorum, every broker of the cluster votes. The node
+/// only decides, sending requests and timing elections is up to its caller.
+#[derive(Debug)]
+pub struct RaftNode {
+    broker_id: BrokerId,
+    peer_ids: Vec<BrokerId>,
+    state: PersistentState,
+    state_path: String,
+    role: Role,
+    leader_id: Option<BrokerId>,
+    commit_index: u64,
+    last_applied: u64,
+    votes: BTreeSet<BrokerId>,
+    next_index: HashMap<BrokerId, u64>,
+    match_index: HashMap<BrokerId, u64>,
+}
+
+impl RaftNode {
+    /// Restores the term, vote and log stored in `dir_path`. Committed entries are applied again
+    /// from the start once the node learns the commit index.
+    pub fn load(broker_id: BrokerId, peer_ids: Vec<BrokerId>, dir_path: &str) -> Self {
+        let state_path = format!("{}/{}", dir_path, RAFT_STATE_FILE_NAME);
+        let state = fs::read(&state_path)
+            .ok()
+            .and_then(|state| bincode::deserialize(&state).ok())
+            .unwrap_or_default();
+        if let Err(e) = fs::create_dir_all(dir_path) {
+            tracing::error!("Could not create {}: {:?}", dir_path, e);
+        }
+        RaftNode {
+            broker_id,
+            peer_ids,
+            state,
+            state_path,
+            role: Role::Follower,
+            leader_id: None,
+            commit_index: 0,
+            last_applied: 0,
+            votes: BTreeSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn leader_id(&self) -> Option<BrokerId> {
+        self.leader_id
+    }
+
+    pub fn peer_ids(&self) -> &[BrokerId] {
+        &self.peer_ids
+    }
+
+    fn last_log_index(&self) -> u64 {
+        self.state.entries.len() as u64
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        match index {
+            0 => 0,
+            index => self
+                .state
+                .entries
+                .get(index as usize - 1)
+                .map_or(0, |entry| entry.term),
+        }
+    }
+
+    fn is_majority(&self, count: usize) -> bool {
+        count * 2 > self.peer_ids.len() + 1
+    }
+
+    /// Votes for itself in a new term, returns the request to send to the peers.
+    pub fn start_election(&mut self) -> VoteRequest {
+        self.state.current_term += 1;
+        self.state.voted_for = Some(self.broker_id);
+        self.persist();
+        self.role = Role::Candidate;
+        self.leader_id = None;
+        self.votes = BTreeSet::from([self.broker_id]);
+        tracing::info!(
+            "Broker {} wants to lead the metadata quorum in term {}",
+            self.broker_id,
+            self.state.current_term
+        );
+        if self.is_majority(self.votes.len()) {
+            self.become_leader();
+        }
+        VoteRequest {
+            term: self.state.current_term,
+            candidate_id: self.broker_id,
+            last_log_index: self.last_log_index(),
+            last_log_term: self.term_at(self.last_log_index()),
+        }
+    }
+
+    /// Returns the node's term and whether it voted for the candidate. Nodes vote once per
+    /// term, for candidates whose log is at least as up to date as their own.
+    pub fn handle_vote_request(&mut self, request: &VoteRequest) -> (u64, bool) {
+        if request.term > self.state.current_term {
+            self.step_down(request.term);
+        }
+        let last_log_term = self.term_at(self.last_log_index());
+        let log_up_to_date = request.last_log_term > last_log_term
+            || (request.last_log_term == last_log_term
+                && request.last_log_index >= self.last_log_index());
+        let vote_granted = request.term == self.state.current_term
+            && self
+                .state
+                .voted_for
+                .is_none_or(|voted_for| voted_for == request.candidate_id)
+            && log_up_to_date;
+        if vote_granted {
+            self.state.voted_for = Some(request.candidate_id);
+            self.persist();
+        }
+        (self.state.current_term, vote_granted)
+    }
+
+    pub fn handle_vote_result(&mut self, from: BrokerId, term: u64, vote_granted: bool) {
+        if term > self.state.current_term {
+            self.step_down(term);
+            return;
+        }
+        if self.role != Role::Candidate || term != self.state.current_term || !vote_granted {
+            return;
+        }
+        self.votes.insert(from);
+        if self.is_majority(self.votes.len()) {
+            self.become_leader();
+        }
+    }
+
+    fn become_leader(&mut self) {
+        tracing::info!(
+            "Broker {} leads the metadata quorum in term {}",
+            self.broker_id,
+            self.state.current_term
+        );
+        self.role = Role::Leader;
+        self.leader_id = Some(self.broker_id);
+        for peer_id in &self.peer_ids {
+            self.next_index.insert(*peer_id, self.last_log_index() + 1);
+            self.match_index.insert(*peer_id, 0);
+        }
+        self.append(MetadataRecord::Noop);
+    }
+
+    /// Returns the node's term, whether it took the entries and its match index.
+    pub fn handle_append_entries(&mut self, request: AppendEntriesRequest) -> (u64, bool, u64) {
+        if request.term < self.state.current_term {
+            return (self.state.current_term, false, self.last_log_index());
+        }
+        if request.term > self.state.current_term || self.role != Role::Follower {
+            self.step_down(request.term);
+        }
+        self.leader_id = Some(request.leader_id);
+        if request.prev_log_index > self.last_log_index()
+            || self.term_at(request.prev_log_index) != request.prev_log_term
+        {
+            let match_index = self
+                .last_log_index()
+                .min(request.prev_log_index.saturating_sub(1));
+            return (self.state.current_term, false, match_index);
+        }
+        let mut index = request.prev_log_index;
+        let mut log_changed = false;
+        for entry in request.entries {
+            index += 1;
+            if index <= self.last_log_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+                // entries of a leader which lost its leadership before committing them
+                self.state.entries.truncate(index as usize - 1);
+            }
+            self.state.entries.push(entry);
+            log_changed = true;
+        }
+        if log_changed {
+            self.persist();
+        }
+        if request.leader_commit > self.commit_index {
+            self.commit_index = request.leader_commit.min(index);
+        }
+        (self.state.current_term, true, index)
+    }
+
+    /// Request bringing the peer's log up to date with the leader's.
+    pub fn append_entries_request(&self, peer_id: BrokerId) -> AppendEntriesRequest {
+        let next_index = self.next_index.get(&peer_id).copied().unwrap_or(1).max(1);
+        let prev_log_index = next_index - 1;
+        AppendEntriesRequest {
+            term: self.state.current_term,
+            leader_id: self.broker_id,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index),
+            entries: self
+                .state
+                .entries
+                .iter()
+                .skip(prev_log_index as usize)
+                .take(MAX_ENTRIES_PER_REQUEST)
+                .cloned()
+                .collect(),
+            leader_commit: self.commit_index,
+        }
+    }
+
+    pub fn handle_entries_appended(
+        &mut self,
+        from: BrokerId,
+        term: u64,
+        success: bool,
+        match_index: u64,
+    ) {
+        if term > self.state.current_term {
+            self.step_down(term);
+            return;
+        }
+        if self.role != Role::Leader || term != self.state.current_term {
+            return;
+        }
+        if success {
+            let peer_match_index = self.match_index.entry(from).or_default();
+            *peer_match_index = (*peer_match_index).max(match_index);
+            self.next_index.insert(from, *peer_match_index + 1);
+            self.advance_commit_index();
+        } else {
+            // the follower's log differs, retry from the last entry it may have in common
+            let next_index = self.next_index.entry(from).or_insert(1);
+            *next_index = (*next_index - 1).min(match_index + 1).max(1);
+        }
+    }
+
+    /// Appends the record to the leader's log, returns its index. Only leaders take proposals.
+    pub fn propose(&mut self, record: MetadataRecord) -> Option<u64> {
+        if self.role != Role::Leader {
+            return None;
+        }
+        Some(self.append(record))
+    }
+
+    fn append(&mut self, record: MetadataRecord) -> u64 {
+        self.state.entries.push(MetadataEntry {
+            term: self.state.current_term,
+            record,
+        });
+        self.persist();
+        self.advance_commit_index();
+        self.last_log_index()
+    }
+
+    /// Commits the newest entry of the current term a majority has.
+    fn advance_commit_index(&mut self) {
+        for index in (self.commit_index + 1..=self.last_log_index()).rev() {
+            if self.term_at(index) != self.state.current_term {
+                break;
+            }
+            let replicas = 1 + self
+                .match_index
+                .values()
+                .filter(|match_index| **match_index >= index)
+                .count();
+            if self.is_majority(replicas) {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+
+    /// Next committed record to apply, with its index.
+    pub fn next_committed(&mut self) -> Option<(u64, MetadataRecord)> {
+        if self.last_applied >= self.commit_index {
+            return None;
+        }
+        self.last_applied += 1;
+        let entry = &self.state.entries[self.last_applied as usize - 1];
+        Some((self.last_applied, entry.record.clone()))
+    }
+
+    pub fn last_applied(&self) -> u64 {
+        self.last_applied
+    }
+
+    fn step_down(&mut self, term: u64) {
+        if term > self.state.current_term {
+            self.state.current_term = term;
+            self.state.voted_for = None;
+            self.persist();
+        }
+        if self.role == Role::Leader {
+            tracing::info!(
+                "Broker {} stopped leading the metadata quorum in term {}",
+                self.broker_id,
+                self.state.current_term
+            );
+        }
+        self.role = Role::Follower;
+        self.leader_id = None;
+        self.votes.clear();
+    }
+
+    /// Term, vote and log must be on disk before the node answers a request or counts itself
+    /// as having an entry.
+    fn persist(&self) {
+        let result = bincode::serialize(&self.state)
+            .map_err(std::io::Error::other)
+            .and_then(|state| fs::write(&self.state_path, state));
+        if let Err(e) = result {
+            tracing::error!("Could not store Raft state in {}: {:?}", self.state_path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::models::{LeaderAndIsr, TopicPartition};
+
+    use super::*;
+
+    fn record(leader_id: BrokerId) -> MetadataRecord {
+        MetadataRecord::LeaderAndIsrChanged {
+            topic_partition: TopicPartition::new("t1".to_string(), 0),
+            leader_and_isr: LeaderAndIsr {
+                leader_id,
+                leader_epoch: 1,
+                isr: vec![leader_id],
+            },
+        }
+    }
+
+    fn replicate(leader: &mut RaftNode, follower: &mut RaftNode) {
+        let request = leader.append_entries_request(follower.broker_id);
+        let (term, success, match_index) = follower.handle_append_entries(request);
+        leader.handle_entries_appended(follower.broker_id, term, success, match_index);
+    }
+
+    fn committed(node: &mut RaftNode) -> Vec<MetadataRecord> {
+        std::iter::from_fn(|| node.next_committed().map(|(_, record)| record)).collect()
+    }
+
+    #[test]
+    fn test_raft_should_commit_records_a_majority_has() {
+        let temp_dir = tempdir::TempDir::new("raft_").unwrap();
+        let dir_path = |broker_id| format!("{}/{}", temp_dir.path().display(), broker_id);
+        let mut nodes: Vec<RaftNode> = (0..3)
+            .map(|broker_id| {
+                let peer_ids = (0..3).filter(|peer_id| *peer_id != broker_id).collect();
+                RaftNode::load(broker_id, peer_ids, &dir_path(broker_id))
+            })
+            .collect();
+
+        let vote_request = nodes[0].start_election();
+        let (term, vote_granted) = nodes[1].handle_vote_request(&vote_request);
+        nodes[0].handle_vote_result(1, term, vote_granted);
+        assert_eq!(nodes[0].role(), Role::Leader);
+        // broker 1 voted in this term already
+        assert_eq!(
+            nodes[1].handle_vote_request(&VoteRequest {
+                candidate_id: 2,
+                ..vote_request.clone()
+            }),
+            (1, false)
+        );
+
+        assert_eq!(nodes[0].propose(record(0)), Some(2));
+        assert_eq!(nodes[1].propose(record(1)), None);
+        assert!(committed(&mut nodes[0]).is_empty());
+
+        let (leader, followers) = nodes.split_at_mut(1);
+        replicate(&mut leader[0], &mut followers[0]);
+        assert_eq!(
+            committed(&mut leader[0]),
+            vec![MetadataRecord::Noop, record(0)]
+        );
+        // followers learn the commit index with the next request
+        replicate(&mut leader[0], &mut followers[0]);
+        assert_eq!(
+            committed(&mut followers[0]),
+            vec![MetadataRecord::Noop, record(0)]
+        );
+
+        // broker 2 was not reachable, it catches up from the restored log of broker 0
+        let mut restarted_leader = RaftNode::load(0, vec![1, 2], &dir_path(0));
+        assert_eq!(restarted_leader.start_election().last_log_index, 2);
+        restarted_leader.handle_vote_result(2, 2, true);
+        for _ in 0..3 {
+            replicate(&mut restarted_leader, &mut followers[1]);
+        }
+        assert_eq!(
+            committed(&mut followers[1]),
+            vec![MetadataRecord::Noop, record(0), MetadataRecord::Noop]
+        );
+    }
+
+    #[test]
+    fn test_raft_should_replace_uncommitted_entries_of_old_leaders() {
+        let temp_dir = tempdir::TempDir::new("raft_").unwrap();
+        let dir_path = |broker_id| format!("{}/{}", temp_dir.path().display(), broker_id);
+        let mut old_leader = RaftNode::load(0, vec![1, 2], &dir_path(0));
+        let mut new_leader = RaftNode::load(1, vec![0, 2], &dir_path(1));
+        let mut follower = RaftNode::load(2, vec![0, 1], &dir_path(2));
+
+        // broker 0 appends an entry nobody else got before it is cut off
+        let vote_request = old_leader.start_election();
+        let (term, vote_granted) = follower.handle_vote_request(&vote_request);
+        old_leader.handle_vote_result(2, term, vote_granted);
+        old_leader.propose(record(0));
+
+        // broker 2 voted for broker 0 in term 1, broker 1 wins term 2
+        let vote_request = new_leader.start_election();
+        assert_eq!(follower.handle_vote_request(&vote_request), (1, false));
+        let vote_request = new_leader.start_election();
+        let (term, vote_granted) = follower.handle_vote_request(&vote_request);
+        new_leader.handle_vote_result(2, term, vote_granted);
+        assert_eq!(new_leader.role(), Role::Leader);
+        new_leader.propose(record(1));
+        replicate(&mut new_leader, &mut follower);
+
+        // the old leader's entries are replaced by the new leader's
+        for _ in 0..3 {
+            replicate(&mut new_leader, &mut old_leader);
+        }
+        assert_eq!(old_leader.role(), Role::Follower);
+        assert_eq!(old_leader.leader_id(), Some(1));
+        assert_eq!(
+            committed(&mut old_leader),
+            vec![MetadataRecord::Noop, record(1)]
+        );
+    }
+}
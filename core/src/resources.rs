@@ -0,0 +1,206 @@
+use std::{fs, thread};
+
+use crate::config::{ConfigError, ConfigSource};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const MEMINFO_PATH: &str = "/proc/meminfo";
+const MIB: u64 = 1024 * 1024;
+
+/// Memory and CPUs available to the broker, which are the container limits when the broker runs
+/// in a cgroup with limits and the host's resources otherwise.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ResourceLimits {
+    pub memory_bytes: u64,
+    pub cpus: usize,
+}
+
+impl ResourceLimits {
+    pub fn detect() -> Self {
+        let host_cpus = thread::available_parallelism()
+            .map(|cpus| cpus.get())
+            .unwrap_or(1);
+        let host_memory_bytes = fs::read_to_string(MEMINFO_PATH)
+            .ok()
+            .and_then(|meminfo| parse_meminfo_total(&meminfo))
+            .unwrap_or(1024 * MIB);
+        let (cgroup_memory_bytes, cgroup_cpus) = read_cgroup_limits(CGROUP_ROOT);
+        ResourceLimits {
+            memory_bytes: cgroup_memory_bytes
+                .map_or(host_memory_bytes, |memory| memory.min(host_memory_bytes)),
+            cpus: cgroup_cpus.map_or(host_cpus, |cpus| cpus.min(host_cpus)),
+        }
+    }
+}
+
+/// Sizes of the broker's worker pool and buffers. Each one is derived from the resource limits
+/// unless it is set in the config file or with its environment variable.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ResourceSettings {
+    /// `WALRS_WORKER_THREADS`, threads of the runtime serving connections and the managers
+    pub worker_threads: usize,
+    /// `WALRS_DISK_IO_THREADS`, threads of the separate runtime the partition writers run on, so
+    /// writers waiting on a slow disk do not hold up connections
+    pub disk_io_threads: usize,
+    /// `WALRS_PARTITION_CHANNEL_SIZE`, messages buffered per partition before writers apply
+    /// back pressure to producers
+    pub partition_channel_size: usize,
+    /// `WALRS_MANAGER_CHANNEL_SIZE`, commands buffered by each manager, e.g. the controller,
+    /// before the connections passing requests to it wait
+    pub manager_channel_size: usize,
+    /// `WALRS_READ_BUFFER_SIZE`, initial size in bytes of the buffer requests are read into
+    pub read_buffer_size: usize,
+}
+
+impl ResourceSettings {
+    pub fn from_config(config: &ConfigSource, limits: ResourceLimits) -> Result<Self, ConfigError> {
+        let derived = ResourceSettings::from_limits(limits);
+        Ok(ResourceSettings {
+            worker_threads: config
+                .parse("WALRS_WORKER_THREADS")?
+                .unwrap_or(derived.worker_threads),
+            disk_io_threads: config
+                .parse("WALRS_DISK_IO_THREADS")?
+                .unwrap_or(derived.disk_io_threads),
+            partition_channel_size: config
+                .parse("WALRS_PARTITION_CHANNEL_SIZE")?
+                .unwrap_or(derived.partition_channel_size),
+            manager_channel_size: config
+                .parse("WALRS_MANAGER_CHANNEL_SIZE")?
+                .unwrap_or(derived.manager_channel_size),
+            read_buffer_size: config
+                .parse("WALRS_READ_BUFFER_SIZE")?
+                .unwrap_or(derived.read_buffer_size),
+        })
+    }
+
+    /// One worker per CPU, one disk IO thread per four CPUs, one buffered message per MiB of memory and 1 KiB of read buffer per
+    /// 256 MiB of memory, so a 512 MiB container buffers 512 messages per partition while a large
+    /// host buffers up to 10000.
+    pub fn from_limits(limits: ResourceLimits) -> Self {
+        let memory_mib = (limits.memory_bytes / MIB) as usize;
+        ResourceSettings {
+            worker_threads: limits.cpus.max(1),
+            disk_io_threads: limits.cpus.div_ceil(4).max(1),
+            partition_channel_size: memory_mib.clamp(100, 10_000),
+            manager_channel_size: 10,
+            read_buffer_size: (memory_mib / 256 * 1024).clamp(1024, 64 * 1024),
+        }
+    }
+}
+
+/// Memory limit in bytes and CPU limit rounded up to whole CPUs, from cgroup v2 or else v1.
+/// Either is `None` when the cgroup does not limit it.
+fn read_cgroup_limits(cgroup_root: &str) -> (Option<u64>, Option<usize>) {
+    let read = |file: &str| fs::read_to_string(format!("{}/{}", cgroup_root, file)).ok();
+
+    if let Some(memory_max) = read("memory.max") {
+        let cpus = read("cpu.max").and_then(|cpu_max| {
+            let (quota, period) = cpu_max.trim().split_once(' ')?;
+            cpus_from_quota(quota.parse().ok()?, period.parse().ok()?)
+        });
+        return (parse_memory_limit(&memory_max), cpus);
+    }
+
+    let memory = read("memory/memory.limit_in_bytes").and_then(|limit| parse_memory_limit(&limit));
+    let quota = read("cpu/cpu.cfs_quota_us").and_then(|quota| quota.trim().parse::<i64>().ok());
+    let period = read("cpu/cpu.cfs_period_us").and_then(|period| period.trim().parse().ok());
+    let cpus = match (quota, period) {
+        // a quota of -1 means unlimited
+        (Some(quota), Some(period)) if quota > 0 => cpus_from_quota(quota as u64, period),
+        _ => None,
+    };
+    (memory, cpus)
+}
+
+/// cgroup v2 writes `max` for no limit and v1 a huge number close to `i64::MAX`.
+fn parse_memory_limit(limit: &str) -> Option<u64> {
+    limit
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|limit| *limit < i64::MAX as u64 / 2)
+}
+
+fn cpus_from_quota(quota_us: u64, period_us: u64) -> Option<usize> {
+    if period_us == 0 {
+        return None;
+    }
+    Some(quota_us.div_ceil(period_us).max(1) as usize)
+}
+
+fn parse_meminfo_total(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_cgroup_limits_should_read_v2_and_v1_limits() {
+        let cgroup_v2 = tempdir::TempDir::new("cgroup_v2_").unwrap();
+        fs::write(cgroup_v2.path().join("memory.max"), "536870912\n").unwrap();
+        fs::write(cgroup_v2.path().join("cpu.max"), "150000 100000\n").unwrap();
+        assert_eq!(
+            read_cgroup_limits(cgroup_v2.path().to_str().unwrap()),
+            (Some(512 * MIB), Some(2))
+        );
+
+        fs::write(cgroup_v2.path().join("memory.max"), "max\n").unwrap();
+        fs::write(cgroup_v2.path().join("cpu.max"), "max 100000\n").unwrap();
+        assert_eq!(
+            read_cgroup_limits(cgroup_v2.path().to_str().unwrap()),
+            (None, None)
+        );
+
+        let cgroup_v1 = tempdir::TempDir::new("cgroup_v1_").unwrap();
+        fs::create_dir_all(cgroup_v1.path().join("memory")).unwrap();
+        fs::create_dir_all(cgroup_v1.path().join("cpu")).unwrap();
+        fs::write(
+            cgroup_v1.path().join("memory/memory.limit_in_bytes"),
+            "9223372036854771712\n",
+        )
+        .unwrap();
+        fs::write(cgroup_v1.path().join("cpu/cpu.cfs_quota_us"), "400000\n").unwrap();
+        fs::write(cgroup_v1.path().join("cpu/cpu.cfs_period_us"), "100000\n").unwrap();
+        assert_eq!(
+            read_cgroup_limits(cgroup_v1.path().to_str().unwrap()),
+            (None, Some(4))
+        );
+    }
+
+    #[test]
+    fn test_resource_settings_should_scale_with_limits() {
+        let small_container = ResourceSettings::from_limits(ResourceLimits {
+            memory_bytes: 512 * MIB,
+            cpus: 1,
+        });
+        assert_eq!(
+            small_container,
+            ResourceSettings {
+                worker_threads: 1,
+                disk_io_threads: 1,
+                partition_channel_size: 512,
+                manager_channel_size: 10,
+                read_buffer_size: 2048,
+            }
+        );
+
+        let large_host = ResourceSettings::from_limits(ResourceLimits {
+            memory_bytes: 64 * 1024 * MIB,
+            cpus: 32,
+        });
+        assert_eq!(large_host.worker_threads, 32);
+        assert_eq!(large_host.disk_io_threads, 8);
+        assert_eq!(large_host.partition_channel_size, 10_000);
+        assert_eq!(large_host.read_buffer_size, 64 * 1024);
+    }
+
+    #[test]
+    fn test_parse_meminfo_total_should_return_bytes() {
+        let meminfo = "MemTotal:       16318480 kB\nMemFree:         1234567 kB\n";
+        assert_eq!(parse_meminfo_total(meminfo), Some(16318480 * 1024));
+    }
+}
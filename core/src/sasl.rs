@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use common::models::{BrokerResponse, TopicCommand};
+use common::sasl::{parse_plain_auth_bytes, SaslMechanism, ScramCredential, ScramServer};
+
+use crate::metrics::Metrics;
+
+/// Where the broker looks up the users clients authenticate as. Only SCRAM credentials are
+/// needed, PLAIN passwords are checked against them too.
+pub trait CredentialStore: Send + Sync {
+    fn scram_credential(&self, username: &str) -> Option<ScramCredential>;
+}
+
+/// Credentials of the users in `WALRS_SASL_USERS`, the passwords are not kept.
+pub struct StaticCredentialStore {
+    credentials: HashMap<String, ScramCredential>,
+}
+
+impl StaticCredentialStore {
+    pub fn new(users: &BTreeMap<String, String>) -> Self {
+        StaticCredentialStore {
+            credentials: users
+                .iter()
+                .map(|(username, password)| (username.clone(), ScramCredential::generate(password)))
+                .collect(),
+        }
+    }
+}
+
+impl CredentialStore for StaticCredentialStore {
+    fn scram_credential(&self, username: &str) -> Option<ScramCredential> {
+        self.credentials.get(username).cloned()
+    }
+}
+
+/// Authenticates the connections of a broker with SASL, every connection has a `SaslSession`.
+pub struct SaslAuthenticator {
+    enabled_mechanisms: Vec<SaslMechanism>,
+    credential_store: Arc<dyn CredentialStore>,
+    failed_authentications: Arc<AtomicU64>,
+}
+
+impl SaslAuthenticator {
+    pub fn new(
+        enabled_mechanisms: Vec<SaslMechanism>,
+        credential_store: Arc<dyn CredentialStore>,
+        metrics: &Metrics,
+    ) -> Self {
+        SaslAuthenticator {
+            enabled_mechanisms,
+            credential_store,
+            failed_authentications: metrics.register("failed_authentications_total"),
+        }
+    }
+
+    pub fn session(self: &Arc<Self>) -> SaslSession {
+        SaslSession {
+            authenticator: self.clone(),
+            state: SessionState::AwaitingHandshake,
+        }
+    }
+}
+
+/// What the connection does with a request, see `SaslSession::handle`.
+#[derive(Debug, PartialEq)]
+pub enum SaslStep {
+    /// The connection is authenticated, the request is handled as usual.
+    Pass,
+    /// The request was part of the authentication, this is its answer.
+    Answer(BrokerResponse),
+    /// The request is answered with this and the connection is closed.
+    Reject(BrokerResponse),
+}
+
+enum SessionState {
+    AwaitingHandshake,
+    AwaitingAuthenticate(SaslMechanism),
+    AwaitingClientFinal {
+        username: String,
+        server: ScramServer,
+    },
+    Authenticated {
+        username: String,
+    },
+}
+
+/// Authentication of one connection. Until it completed only `ApiVersions` and the SASL requests
+/// are handled, every other request is rejected.
+pub struct SaslSession {
+    authenticator: Arc<SaslAuthenticator>,
+    state: SessionState,
+}
+
+impl SaslSession {
+    pub fn handle(&mut self, command: &TopicCommand) -> SaslStep {
+        let state = std::mem::replace(&mut self.state, SessionState::AwaitingHandshake);
+        match (command, state) {
+            (TopicCommand::ApiVersions, state) => {
+                self.state = state;
+                SaslStep::Pass
+            }
+            (
+                TopicCommand::SaslHandshake { .. } | TopicCommand::SaslAuthenticate { .. },
+                state @ SessionState::Authenticated { .. },
+            ) => {
+                self.state = state;
+                self.reject("connection is already authenticated".to_string())
+            }
+            (_, state @ SessionState::Authenticated { .. }) => {
+                self.state = state;
+                SaslStep::Pass
+            }
+            (TopicCommand::SaslHandshake { mechanism }, SessionState::AwaitingHandshake) => {
+                let enabled_mechanisms = &self.authenticator.enabled_mechanisms;
+                if let Some(mechanism) = enabled_mechanisms
+                    .iter()
+                    .find(|enabled_mechanism| enabled_mechanism.name() == mechanism)
+                {
+                    self.state = SessionState::AwaitingAuthenticate(*mechanism);
+                }
+                SaslStep::Answer(BrokerResponse::SaslHandshake {
+                    enabled_mechanisms: enabled_mechanisms
+                        .iter()
+                        .map(|mechanism| mechanism.name().to_string())
+                        .collect(),
+                })
+            }
+            (
+                TopicCommand::SaslAuthenticate { auth_bytes },
+                SessionState::AwaitingAuthenticate(SaslMechanism::Plain),
+            ) => match parse_plain_auth_bytes(auth_bytes) {
+                Ok((username, password)) => {
+                    let credential = self
+                        .authenticator
+                        .credential_store
+                        .scram_credential(&username);
+                    if credential.is_some_and(|credential| credential.verify_password(&password)) {
+                        self.authenticated(&username, SaslMechanism::Plain, vec![])
+                    } else {
+                        self.reject(format!("invalid credentials of {}", username))
+                    }
+                }
+                Err(e) => self.reject(e),
+            },
+            (
+                TopicCommand::SaslAuthenticate { auth_bytes },
+                SessionState::AwaitingAuthenticate(SaslMechanism::ScramSha256),
+            ) => {
+                let started = ScramServer::username(auth_bytes).and_then(|username| {
+                    let credential = self
+                        .authenticator
+                        .credential_store
+                        .scram_credential(&username)
+                        .ok_or_else(|| format!("invalid credentials of {}", username))?;
+                    Ok((username, ScramServer::start(auth_bytes, credential)?))
+                });
+                match started {
+                    Ok((username, (server, server_first))) => {
+                        self.state = SessionState::AwaitingClientFinal { username, server };
+                        SaslStep::Answer(BrokerResponse::SaslAuthenticated {
+                            auth_bytes: server_first,
+                        })
+                    }
+                    Err(e) => self.reject(e),
+                }
+            }
+            (
+                TopicCommand::SaslAuthenticate { auth_bytes },
+                SessionState::AwaitingClientFinal { username, server },
+            ) => match server.finish(auth_bytes) {
+                Ok(server_final) => {
+                    self.authenticated(&username, SaslMechanism::ScramSha256, server_final)
+                }
+                Err(e) => self.reject(format!("{} of {}", e, username)),
+            },
+            _ => self.reject("authentication required".to_string()),
+        }
+    }
+
+    /// The user the connection authenticated as, `None` until it did.
+    pub fn principal(&self) -> Option<&str> {
+        match &self.state {
+            SessionState::Authenticated { username } => Some(username),
+            _ => None,
+        }
+    }
+
+    fn authenticated(
+        &mut self,
+        username: &str,
+        mechanism: SaslMechanism,
+        auth_bytes: Vec<u8>,
+    ) -> SaslStep {
+        tracing::info!("Authenticated {} with {}", username, mechanism);
+        self.state = SessionState::Authenticated {
+            username: username.to_string(),
+        };
+        SaslStep::Answer(BrokerResponse::SaslAuthenticated { auth_bytes })
+    }
+
+    fn reject(&mut self, error: String) -> SaslStep {
+        tracing::warn!("Authentication failed: {}", error);
+        self.authenticator
+            .failed_authentications
+            .fetch_add(1, Ordering::Relaxed);
+        SaslStep::Reject(BrokerResponse::SaslAuthenticationFailed { error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::sasl::{SaslClient, SaslCredentials};
+
+    use super::*;
+
+    fn authenticate(session: &mut SaslSession, credentials: SaslCredentials) -> SaslStep {
+        let mechanism = credentials.mechanism.name().to_string();
+        assert!(matches!(
+            session.handle(&TopicCommand::SaslHandshake { mechanism }),
+            SaslStep::Answer(BrokerResponse::SaslHandshake { .. })
+        ));
+        let mut client = SaslClient::new(credentials);
+        let mut auth_bytes = client.initial_response();
+        loop {
+            match session.handle(&TopicCommand::SaslAuthenticate { auth_bytes }) {
+                SaslStep::Answer(BrokerResponse::SaslAuthenticated {
+                    auth_bytes: challenge,
+                }) => match client.respond(&challenge).unwrap() {
+                    Some(response) => auth_bytes = response,
+                    None => return SaslStep::Pass,
+                },
+                step => return step,
+            }
+        }
+    }
+
+    #[test]
+    fn test_sasl_session_should_reject_requests_until_authenticated() {
+        let metrics = Metrics::new();
+        let users = BTreeMap::from([("alice".to_string(), "secret".to_string())]);
+        let authenticator = Arc::new(SaslAuthenticator::new(
+            vec![SaslMechanism::Plain, SaslMechanism::ScramSha256],
+            Arc::new(StaticCredentialStore::new(&users)),
+            &metrics,
+        ));
+        let credentials = |mechanism, password: &str| SaslCredentials {
+            mechanism,
+            username: "alice".to_string(),
+            password: password.to_string(),
+        };
+
+        let mut session = authenticator.session();
+        assert_eq!(session.handle(&TopicCommand::ApiVersions), SaslStep::Pass);
+        assert!(matches!(
+            session.handle(&TopicCommand::DescribeMetrics),
+            SaslStep::Reject(BrokerResponse::SaslAuthenticationFailed { .. })
+        ));
+        for mechanism in [SaslMechanism::Plain, SaslMechanism::ScramSha256] {
+            let mut session = authenticator.session();
+            assert!(matches!(
+                authenticate(&mut session, credentials(mechanism, "guess")),
+                SaslStep::Reject(BrokerResponse::SaslAuthenticationFailed { .. })
+            ));
+            let mut session = authenticator.session();
+            assert_eq!(session.principal(), None);
+            assert_eq!(
+                authenticate(&mut session, credentials(mechanism, "secret")),
+                SaslStep::Pass
+            );
+            assert_eq!(session.principal(), Some("alice"));
+            assert_eq!(
+                session.handle(&TopicCommand::DescribeMetrics),
+                SaslStep::Pass
+            );
+        }
+        assert_eq!(
+            metrics.snapshot(),
+            vec![("failed_authentications_total".to_string(), 3)]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use common::models::{Batch, FetchedBatch, Message, RecordBatch, DELIVER_AT_HEADER};
+
+/// Fetched batches cut before the first record from `offset` on whose `deliver-at` header is
+/// later than `now`, so consumers of topics with scheduled delivery read a record once its time
+/// came. Later records wait for it too, which keeps the partition read in offset order. A batch
+/// holding records on both sides of the cut is served as a new batch of the records before it.
+/// Nothing is served while no record from `offset` on is due.
+pub fn deliverable_batches(
+    batches: Vec<FetchedBatch>,
+    offset: u64,
+    now: u128,
+) -> Vec<FetchedBatch> {
+    let mut deliverable = Vec::with_capacity(batches.len());
+    let mut delivers_records = false;
+    for fetched_batch in batches {
+        let records = match fetched_batch.batch.records() {
+            Ok(records) => records,
+            Err(e) => {
+                // the consumer reports the undecodable batch
+                tracing::warn!("Could not decode a fetched batch: {:?}", e);
+                delivers_records = true;
+                deliverable.push(fetched_batch);
+                continue;
+            }
+        };
+        // records before the fetch offset were delivered already
+        let skipped = offset.saturating_sub(fetched_batch.base_offset) as usize;
+        let held = records
+            .iter()
+            .skip(skipped)
+            .position(|record| deliver_at(record).is_some_and(|deliver_at| deliver_at > now));
+        let Some(held) = held else {
+            delivers_records |= records.len() > skipped;
+            deliverable.push(fetched_batch);
+            continue;
+        };
+        if held > 0 {
+            delivers_records = true;
+            let batch = Batch {
+                records: records[..skipped + held].to_vec(),
+                producer: None,
+                compression: fetched_batch.batch.compression,
+            };
+            match RecordBatch::new(batch) {
+                Ok(batch) => deliverable.push(FetchedBatch {
+                    base_offset: fetched_batch.base_offset,
+                    batch,
+                }),
+                Err(e) => tracing::warn!("Could not encode the deliverable records: {:?}", e),
+            }
+        }
+        break;
+    }
+    if !delivers_records {
+        deliverable.clear();
+    }
+    deliverable
+}
+
+/// Milliseconds since the epoch of the record's `deliver-at` header, `None` for records without a
+/// valid one, which are delivered right away.
+fn deliver_at(record: &Message) -> Option<u128> {
+    record
+        .headers
+        .iter()
+        .find(|(name, _)| name == DELIVER_AT_HEADER)
+        .and_then(|(_, value)| std::str::from_utf8(value).ok()?.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn record(deliver_at: Option<&'static str>) -> Message {
+        let mut message = Message::new(Bytes::from_static(b"v"), None, None);
+        if let Some(deliver_at) = deliver_at {
+            message.headers = vec![(DELIVER_AT_HEADER.to_string(), Bytes::from(deliver_at))];
+        }
+        message
+    }
+
+    fn fetched_batch(base_offset: u64, records: Vec<Message>) -> FetchedBatch {
+        FetchedBatch {
+            base_offset,
+            batch: RecordBatch::new(Batch {
+                records,
+                ..Batch::default()
+            })
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_deliverable_batches_should_hold_records_scheduled_after_now() {
+        let batches = vec![
+            fetched_batch(0, vec![record(None), record(Some("1000"))]),
+            fetched_batch(2, vec![record(None), record(Some("3000")), record(None)]),
+            fetched_batch(5, vec![record(None)]),
+        ];
+
+        let deliverable = deliverable_batches(batches.clone(), 1, 2000);
+        assert_eq!(deliverable.len(), 2);
+        assert_eq!(deliverable[0], batches[0]);
+        assert_eq!(deliverable[1].base_offset, 2);
+        assert_eq!(deliverable[1].batch.record_count, 1);
+
+        // the scheduled record is the first one fetched
+        assert!(deliverable_batches(batches.clone(), 3, 2000).is_empty());
+        assert_eq!(deliverable_batches(batches.clone(), 3, 3000), batches);
+    }
+}
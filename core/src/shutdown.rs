@@ -0,0 +1,172 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::cluster::ClusterSettings;
+use crate::connections::ConnectionTracker;
+use crate::managers::controller::ControllerCommands;
+use crate::managers::topics_manager::TopicManagerCommands;
+use crate::membership;
+
+/// What a broker stops when it shuts down, in the order in which it stops them. Clients move to
+/// the new leaders before the broker is gone and no request it acknowledged is lost.
+pub struct BrokerShutdown {
+    pub cluster_settings: ClusterSettings,
+    pub controller_tx: Sender<ControllerCommands>,
+    /// Asked whether the followers copied every record before the leaderships move.
+    pub topic_manager_tx: Sender<TopicManagerCommands>,
+    /// Cancelled once the partitions were moved: the listeners stop accepting connections and
+    /// the connections stop reading requests, they close once the requests in flight are
+    /// answered.
+    pub listener_token: CancellationToken,
+    pub connection_tracker: Arc<ConnectionTracker>,
+    /// The HTTP proxy and the gRPC server, which answer their requests in flight before they
+    /// stop.
+    pub proxy_tasks: TaskTracker,
+    /// Parent of `listener_token`, cancelled once the connections closed: the managers stop and
+    /// the partition writers write their pending batch and fsync their segment.
+    pub cancellation_token: CancellationToken,
+    /// Ends once the partition writers finished.
+    pub topics_manager_task: JoinHandle<()>,
+}
+
+/// Completes once the broker received SIGTERM.
+pub async fn sigterm() {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    sigterm.recv().await;
+    tracing::info!("Received SIGTERM, shutting down gracefully");
+}
+
+impl BrokerShutdown {
+    /// Waits for `signal` and shuts the broker down.
+    pub async fn after(self, signal: impl Future<Output = ()>) {
+        signal.await;
+        self.shut_down().await;
+    }
+
+    async fn shut_down(self) {
+        let timeout = self.cluster_settings.shutdown_timeout;
+        // the broker keeps answering requests meanwhile, so clients learn the new leaders from it
+        if self.cluster_settings.controlled_shutdown_enable {
+            let broker_id = self.cluster_settings.broker_id;
+            let lagging_partitions =
+                membership::await_followers_caught_up(broker_id, &self.topic_manager_tx, timeout)
+                    .await;
+            if !lagging_partitions.is_empty() {
+                tracing::warn!(
+                    "Followers of {:?} did not catch up within {:?}, moving them anyway",
+                    lagging_partitions,
+                    timeout
+                );
+            }
+            if membership::request_controlled_shutdown(broker_id, &self.controller_tx, timeout)
+                .await
+            {
+                tracing::info!("Other brokers lead the partitions of broker {}", broker_id);
+            } else {
+                tracing::warn!(
+                    "Shutting down while leading partitions, they get new leaders once the \
+                     controller noticed the broker is gone"
+                );
+            }
+        }
+
+        self.listener_token.cancel();
+        self.proxy_tasks.close();
+        let drained = tokio::time::timeout(timeout, async {
+            self.connection_tracker.closed().await;
+            self.proxy_tasks.wait().await;
+        })
+        .await;
+        match drained {
+            Ok(()) => tracing::info!("Answered the requests in flight and closed the connections"),
+            Err(_) => tracing::warn!(
+                "Connections did not answer their requests in flight within {:?}, closing them",
+                timeout
+            ),
+        }
+
+        self.cancellation_token.cancel();
+        if let Err(e) = self.topics_manager_task.await {
+            tracing::error!("Topic manager failed while shutting down: {:?}", e);
+        }
+        tracing::info!("Partition writers flushed and synced their segments, broker stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use common::models::BrokerResponse;
+    use tokio::sync::mpsc;
+
+    use crate::connections::ConnectionSettings;
+    use crate::metrics::Metrics;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_should_move_partitions_then_drain_connections_then_stop_managers() {
+        let cancellation_token = CancellationToken::new();
+        let listener_token = cancellation_token.child_token();
+        let connection_tracker = Arc::new(ConnectionTracker::new(
+            ConnectionSettings::default(),
+            &Metrics::new(),
+            listener_token.clone(),
+        ));
+        let connection = connection_tracker.open(Ipv4Addr::LOCALHOST.into()).unwrap();
+        let (controller_tx, mut controller_rx) = mpsc::channel(1);
+        let (topic_manager_tx, mut topic_manager_rx) = mpsc::channel(1);
+        let manager_token = cancellation_token.clone();
+        let shutdown = tokio::spawn(
+            BrokerShutdown {
+                cluster_settings: ClusterSettings::default(),
+                controller_tx,
+                topic_manager_tx,
+                listener_token: listener_token.clone(),
+                connection_tracker,
+                proxy_tasks: TaskTracker::new(),
+                cancellation_token: cancellation_token.clone(),
+                topics_manager_task: tokio::spawn(async move { manager_token.cancelled().await }),
+            }
+            .shut_down(),
+        );
+
+        let Some(TopicManagerCommands::GetLaggingPartitions {
+            broker_id: 0,
+            reply_tx,
+        }) = topic_manager_rx.recv().await
+        else {
+            panic!("expected the broker to wait for its followers first");
+        };
+        assert!(controller_rx.try_recv().is_err());
+        reply_tx.send(vec![]).unwrap();
+
+        let Some(ControllerCommands::ControlledShutdown {
+            broker_id: 0,
+            reply_tx,
+        }) = controller_rx.recv().await
+        else {
+            panic!("expected the broker to ask the controller to move its partitions");
+        };
+        assert!(!listener_token.is_cancelled());
+        reply_tx
+            .send(BrokerResponse::ControlledShutdownCompleted {
+                remaining_partitions: vec![],
+            })
+            .unwrap();
+
+        // the connection answers its requests in flight while the managers keep running
+        connection.shutdown_token().cancelled().await;
+        assert!(!cancellation_token.is_cancelled());
+        drop(connection);
+        shutdown.await.unwrap();
+        assert!(cancellation_token.is_cancelled());
+    }
+}
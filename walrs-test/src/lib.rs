@@ -0,0 +1,167 @@
+//! Brokers started within the test process, so applications using walrs can be tested against a
+//! real broker without Docker.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tempdir::TempDir;
+use tokio::sync::oneshot;
+use walrs_broker::{start_broker, BrokerConfig, ResourceLimits};
+
+/// Resources the settings of embedded brokers are derived from, a small container's rather than
+/// the host's.
+const EMBEDDED_LIMITS: ResourceLimits = ResourceLimits {
+    memory_bytes: 512 * 1024 * 1024,
+    cpus: 2,
+};
+/// Embedded brokers have no partitions to move to other brokers, so their shutdown only waits
+/// for the requests in flight.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A single broker listening on a random port of localhost, with its log in a temporary
+/// directory. It runs on its own runtime, so tests of any runtime flavor, or none, can use it.
+/// The broker shuts down and its log is removed once it is dropped.
+pub struct EmbeddedBroker {
+    address: SocketAddr,
+    log_dir: TempDir,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    broker_thread: Option<JoinHandle<()>>,
+}
+
+impl EmbeddedBroker {
+    /// Starts a broker with the default settings. It accepts connections once this returns,
+    /// clients retry until its metadata quorum elected it, which takes a couple of seconds.
+    pub fn start() -> io::Result<Self> {
+        EmbeddedBroker::start_with(BrokerConfig::defaults(EMBEDDED_LIMITS))
+    }
+
+    /// Starts a broker with `config`, of which the listen address and log directory are
+    /// replaced.
+    pub fn start_with(mut config: BrokerConfig) -> io::Result<Self> {
+        let log_dir = TempDir::new("walrs-embedded-broker")?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        config.cluster.listen_address = address.to_string();
+        config.cluster.advertised_address = address.to_string();
+        config.cluster.log_dir_path = log_dir.path().to_string_lossy().into_owned();
+        config.cluster.shutdown_timeout = SHUTDOWN_TIMEOUT;
+
+        let disk_io_runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(config.resources.disk_io_threads)
+            .thread_name("walrs-embedded-disk-io")
+            .enable_all()
+            .build()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(config.resources.worker_threads)
+            .thread_name("walrs-embedded-broker")
+            .enable_all()
+            .build()?;
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let broker_thread = thread::Builder::new()
+            .name("walrs-embedded-broker".to_string())
+            .spawn(move || {
+                runtime.block_on(async {
+                    let listener = tokio::net::TcpListener::from_std(listener)
+                        .expect("Could not register the listener with the runtime");
+                    let shutdown = async move {
+                        // a dropped sender shuts the broker down as well
+                        let _ = shutdown_rx.await;
+                    };
+                    start_broker(config, listener, shutdown, disk_io_runtime.handle().clone())
+                        .await;
+                });
+            })?;
+        Ok(EmbeddedBroker {
+            address,
+            log_dir,
+            shutdown_tx: Some(shutdown_tx),
+            broker_thread: Some(broker_thread),
+        })
+    }
+
+    /// Address clients bootstrap from, e.g. `127.0.0.1:41234`.
+    pub fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    /// Directory of the broker's log, e.g. to read its segments.
+    pub fn log_dir(&self) -> &Path {
+        self.log_dir.path()
+    }
+
+    /// Shuts the broker down gracefully, blocking until the requests in flight are answered and
+    /// the partition writers synced their segments, and removes its log.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+        if let Some(broker_thread) = self.broker_thread.take() {
+            if broker_thread.join().is_err() {
+                tracing::error!("Embedded broker at {} panicked", self.address);
+            }
+        }
+    }
+}
+
+impl Drop for EmbeddedBroker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use bytes::{Bytes, BytesMut};
+    use common::codecs::protocol::{Request, ResponseCodec};
+    use common::models::{BrokerResponse, TopicCommand};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+
+    fn ping(address: &str) -> io::Result<BrokerResponse> {
+        let mut stream = TcpStream::connect(address)?;
+        let mut encoded_request = BytesMut::new();
+        let request = Request::new(1, "walrs-test", TopicCommand::Ping, Bytes::new());
+        ResponseCodec::default().encode(request, &mut encoded_request)?;
+        stream.write_all(&encoded_request)?;
+        let mut response_codec = ResponseCodec::default();
+        let mut received = BytesMut::new();
+        let mut read_buffer = [0; 1024];
+        loop {
+            if let Some(response) = response_codec.decode(&mut received)? {
+                return Ok(response.response);
+            }
+            let read = stream.read(&mut read_buffer)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            received.extend_from_slice(&read_buffer[..read]);
+        }
+    }
+
+    #[test]
+    fn test_embedded_broker_should_answer_requests_and_clean_up_on_shutdown() {
+        let broker = EmbeddedBroker::start().unwrap();
+        let address = broker.address();
+        let log_dir = broker.log_dir().to_path_buf();
+
+        let response = ping(&address).unwrap();
+        assert!(matches!(response, BrokerResponse::Pong { .. }));
+        assert!(log_dir.exists());
+
+        broker.shutdown();
+        assert!(TcpStream::connect(&address).is_err());
+        assert!(!log_dir.exists());
+    }
+}